@@ -0,0 +1,93 @@
+use ferroterm::command_parser::CommandParser;
+use ferroterm::config::{ConfigManager, KeymapConfig};
+use ferroterm::input::{InputProcessor, Key, ShellMode};
+use ferroterm::tty::{PtyConfig, TtyEngine};
+use parking_lot::RwLock;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn main() {
+    println!("Running keypress fast path benchmark...\n");
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let engine = TtyEngine::new();
+    let pty_id = runtime
+        .block_on(engine.create_pty(PtyConfig::default()))
+        .unwrap();
+
+    let mut input = InputProcessor::new(
+        Arc::new(RwLock::new(KeymapConfig::default())),
+        Arc::new(RwLock::new(CommandParser::new("p".to_string()))),
+        Arc::new(ConfigManager::new().unwrap()),
+    );
+    input.set_shell_mode(ShellMode::Emacs);
+    let modifiers = HashSet::new();
+    let keys: Vec<Key> = "echo fast path"
+        .chars()
+        .map(Key::Char)
+        .chain([Key::Backspace, Key::Tab])
+        .collect();
+
+    // Warm the binding cache and the outgoing queue
+    for &key in &keys {
+        let bytes = input.fast_path(key, &modifiers).unwrap();
+        engine.queue_write(pty_id, bytes.as_bytes()).unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    const ITERATIONS: usize = 100;
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        for &key in &keys {
+            let bytes = input.fast_path(key, &modifiers).unwrap();
+            engine.queue_write(pty_id, bytes.as_bytes()).unwrap();
+        }
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    let presses = ITERATIONS * keys.len();
+
+    println!(
+        "Keypress to PTY queue: {:?} per key ({} keys)",
+        elapsed / presses as u32,
+        presses
+    );
+    println!("Allocations on the keypress path: {}", allocations);
+
+    runtime.block_on(engine.destroy_pty(pty_id)).unwrap();
+
+    println!("\n=== Performance Requirements Check ===");
+    if allocations == 0 {
+        println!("✓ Keypress path allocates nothing");
+    } else {
+        println!("✗ Keypress path allocated {} times", allocations);
+        std::process::exit(1);
+    }
+}
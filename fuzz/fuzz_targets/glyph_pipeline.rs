@@ -0,0 +1,47 @@
+#![no_main]
+
+use ferroterm::glyph_guard::{
+    AtlasAllocator, GlyphLimits, GlyphPipeline, GlyphRasterizer, RasterMetrics, text_width,
+};
+use libfuzzer_sys::fuzz_target;
+
+/// Stand-in for swash: metrics are derived from the cluster bytes so the
+/// fuzzer can reach huge, zero and non-finite values
+struct StubRasterizer;
+
+impl GlyphRasterizer for StubRasterizer {
+    fn measure(&mut self, cluster: &str) -> RasterMetrics {
+        let seed = cluster
+            .bytes()
+            .fold(0x811C_9DC5u32, |h, b| (h ^ b as u32).wrapping_mul(0x0100_0193));
+        RasterMetrics {
+            width: seed % 4096,
+            height: seed.rotate_left(11) % 4096,
+            advance: f32::from_bits(seed),
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let text = String::from_utf8_lossy(data);
+    let limits = GlyphLimits::default();
+    let mut pipeline = GlyphPipeline::new(
+        StubRasterizer,
+        limits.clone(),
+        8.0,
+        16.0,
+        AtlasAllocator::new(128, 2),
+    );
+
+    let placed = pipeline.process(&text);
+    pipeline.check_invariants().unwrap();
+
+    let columns: usize = placed.iter().map(|g| g.columns as usize).sum();
+    assert_eq!(columns, text_width(&text, &limits));
+    for glyph in &placed {
+        assert!(glyph.advance.is_finite() && glyph.advance > 0.0);
+        if let Some(slot) = glyph.slot {
+            assert!(slot.width as usize * slot.height as usize <= limits.max_glyph_bytes);
+        }
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use ferroterm::terminal::TerminalState;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut terminal = TerminalState::new(80, 24);
+    terminal.feed_bytes(data);
+
+    assert!(terminal.cursor_x <= terminal.width);
+    assert!(terminal.cursor_y < terminal.height);
+    assert_eq!(terminal.cells.len(), 80 * 24);
+});
@@ -0,0 +1,775 @@
+//! Behavioral contract for `ModelAdapter` implementations.
+//!
+//! `run_adapter_conformance` is the bar every adapter must clear before it is
+//! registered with `ModelHost`, built-in or third-party: idempotent
+//! load/unload, `max_tokens` and `stop_sequences` honored, deadlines kept,
+//! well-formed streams, order-preserving batches and `NotLoaded` errors while
+//! unloaded. Failures are collected into a report instead of panicking, so
+//! one run shows every violation.
+
+use crate::model_host::{FinishReason, InferenceRequest, ModelAdapter, ModelHostError};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+
+#[derive(Debug, Clone)]
+pub struct ConformanceOptions {
+    pub prompt: String,
+    /// Token budget for probe requests; small enough that every adapter's
+    /// output is truncated by it
+    pub max_tokens: u32,
+    pub batch_size: usize,
+    /// Deadline given to the timeout probe
+    pub timeout_probe_ms: u64,
+    /// How far past a deadline an adapter may return
+    pub timeout_grace: Duration,
+    /// Upper bound for draining a stream
+    pub stream_timeout: Duration,
+}
+
+impl Default for ConformanceOptions {
+    fn default() -> Self {
+        Self {
+            prompt: "conformance probe".to_string(),
+            max_tokens: 12,
+            batch_size: 3,
+            timeout_probe_ms: 1,
+            timeout_grace: Duration::from_millis(25),
+            stream_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckOutcome {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConformanceReport {
+    pub adapter: String,
+    pub checks: Vec<CheckResult>,
+}
+
+impl ConformanceReport {
+    pub fn passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.outcome, CheckOutcome::Failed(_)))
+    }
+
+    pub fn outcome(&self, name: &str) -> Option<&CheckOutcome> {
+        self.checks
+            .iter()
+            .find(|check| check.name == name)
+            .map(|check| &check.outcome)
+    }
+
+    fn record(&mut self, name: &'static str, result: Result<(), String>) {
+        let outcome = match result {
+            Ok(()) => CheckOutcome::Passed,
+            Err(reason) => CheckOutcome::Failed(reason),
+        };
+        self.checks.push(CheckResult { name, outcome });
+    }
+
+    fn skip(&mut self, name: &'static str, reason: &str) {
+        self.checks.push(CheckResult {
+            name,
+            outcome: CheckOutcome::Skipped(reason.to_string()),
+        });
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "conformance: {}", self.adapter)?;
+        for check in &self.checks {
+            match &check.outcome {
+                CheckOutcome::Passed => writeln!(f, "  PASS {}", check.name)?,
+                CheckOutcome::Failed(reason) => writeln!(f, "  FAIL {}: {}", check.name, reason)?,
+                CheckOutcome::Skipped(reason) => writeln!(f, "  SKIP {}: {}", check.name, reason)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Exercise the whole `ModelAdapter` trait against `adapter`, which must
+/// start unloaded. The adapter is left unloaded.
+pub async fn run_adapter_conformance(
+    mut adapter: Box<dyn ModelAdapter>,
+    opts: &ConformanceOptions,
+) -> ConformanceReport {
+    let model_name = adapter.get_model_info().name;
+    let mut report = ConformanceReport {
+        adapter: model_name.clone(),
+        checks: Vec::new(),
+    };
+    let probe = |prompt: &str| {
+        let mut request = InferenceRequest::new(model_name.clone(), prompt);
+        request.parameters.max_tokens = opts.max_tokens;
+        request
+    };
+
+    report.record(
+        "unloaded_errors",
+        check_unloaded(adapter.as_ref(), probe(&opts.prompt)).await,
+    );
+    let health_before = adapter.health_check().await;
+
+    let loaded = adapter.load().await;
+    let reloaded = adapter.load().await;
+    report.record(
+        "load_idempotent",
+        match (loaded, reloaded) {
+            (Err(e), _) => Err(format!("load failed: {}", e)),
+            (Ok(()), Err(e)) => Err(format!("second load failed: {}", e)),
+            (Ok(()), Ok(())) if !adapter.is_loaded() => {
+                Err("is_loaded() is false after load".to_string())
+            }
+            _ => Ok(()),
+        },
+    );
+
+    const LOADED_CHECKS: [&str; 5] = [
+        "infer_max_tokens",
+        "infer_stop_sequences",
+        "infer_timeout",
+        "stream_ordering",
+        "batch_order",
+    ];
+    if adapter.is_loaded() {
+        let health_loaded = adapter.health_check().await;
+        report.record(
+            "infer_max_tokens",
+            check_max_tokens(adapter.as_ref(), probe(&opts.prompt)).await,
+        );
+        match check_stop_sequences(adapter.as_ref(), probe(&opts.prompt)).await {
+            Ok(Some(reason)) => report.skip("infer_stop_sequences", &reason),
+            Ok(None) => report.record("infer_stop_sequences", Ok(())),
+            Err(reason) => report.record("infer_stop_sequences", Err(reason)),
+        }
+        report.record(
+            "infer_timeout",
+            check_timeout(adapter.as_ref(), probe(&opts.prompt), opts).await,
+        );
+        report.record(
+            "stream_ordering",
+            check_stream(adapter.as_ref(), probe(&opts.prompt), opts).await,
+        );
+        let batch = (0..opts.batch_size)
+            .map(|i| probe(&format!("{} [#{}]", opts.prompt, i)))
+            .collect();
+        match check_batch(adapter.as_ref(), batch).await {
+            Ok(Some(reason)) => report.skip("batch_order", &reason),
+            Ok(None) => report.record("batch_order", Ok(())),
+            Err(reason) => report.record("batch_order", Err(reason)),
+        }
+
+        let unloaded = adapter.unload().await;
+        let unloaded_again = adapter.unload().await;
+        report.record(
+            "unload_idempotent",
+            match (unloaded, unloaded_again) {
+                (Err(e), _) => Err(format!("unload failed: {}", e)),
+                (Ok(()), Err(e)) => Err(format!("second unload failed: {}", e)),
+                (Ok(()), Ok(())) if adapter.is_loaded() => {
+                    Err("is_loaded() is true after unload".to_string())
+                }
+                _ => Ok(()),
+            },
+        );
+
+        let health_after = adapter.health_check().await;
+        report.record(
+            "health_check",
+            match (health_before, health_loaded, health_after) {
+                (Ok(()), _, _) => Err("healthy before load".to_string()),
+                (_, Err(e), _) => Err(format!("unhealthy while loaded: {}", e)),
+                (_, _, Ok(())) => Err("healthy after unload".to_string()),
+                _ => Ok(()),
+            },
+        );
+    } else {
+        for name in LOADED_CHECKS {
+            report.skip(name, "adapter did not load");
+        }
+        report.skip("unload_idempotent", "adapter did not load");
+        report.skip("health_check", "adapter did not load");
+    }
+
+    report
+}
+
+fn expect_not_loaded<T>(call: &str, result: Result<T, ModelHostError>) -> Result<(), String> {
+    match result {
+        Err(ModelHostError::NotLoaded { .. }) => Ok(()),
+        Err(e) => Err(format!("{} returned {:?} instead of NotLoaded", call, e)),
+        Ok(_) => Err(format!("{} succeeded while unloaded", call)),
+    }
+}
+
+async fn check_unloaded(
+    adapter: &dyn ModelAdapter,
+    request: InferenceRequest,
+) -> Result<(), String> {
+    if adapter.is_loaded() {
+        return Err("is_loaded() is true before load".to_string());
+    }
+    expect_not_loaded("infer", adapter.infer(request.clone()).await)?;
+    expect_not_loaded("infer_stream", adapter.infer_stream(request.clone()).await)?;
+    expect_not_loaded("batch_infer", adapter.batch_infer(vec![request]).await)?;
+    expect_not_loaded("health_check", adapter.health_check().await)
+}
+
+async fn check_max_tokens(
+    adapter: &dyn ModelAdapter,
+    mut request: InferenceRequest,
+) -> Result<(), String> {
+    request.parameters.max_tokens = 2;
+    let response = adapter.infer(request).await.map_err(|e| e.to_string())?;
+    let words = response.text.split_whitespace().count();
+    if response.tokens_generated > 2 || words > 2 {
+        return Err(format!(
+            "max_tokens 2 produced {} tokens ({} words)",
+            response.tokens_generated, words
+        ));
+    }
+    Ok(())
+}
+
+/// Stops on a word taken from the adapter's own unconstrained output.
+/// `Ok(Some(_))` means the output was too short to probe.
+async fn check_stop_sequences(
+    adapter: &dyn ModelAdapter,
+    mut request: InferenceRequest,
+) -> Result<Option<String>, String> {
+    let baseline = adapter
+        .infer(request.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    let Some(stop) = baseline.text.split_whitespace().nth(2) else {
+        return Ok(Some("output too short to pick a stop sequence".to_string()));
+    };
+
+    request.parameters.stop_sequences = vec![stop.to_string()];
+    let response = adapter.infer(request).await.map_err(|e| e.to_string())?;
+    if response.text.contains(stop) {
+        return Err(format!("output still contains stop sequence {:?}", stop));
+    }
+    if response.finish_reason != FinishReason::Stop {
+        return Err(format!(
+            "finish_reason {:?} after stop sequence",
+            response.finish_reason
+        ));
+    }
+    Ok(None)
+}
+
+async fn check_timeout(
+    adapter: &dyn ModelAdapter,
+    mut request: InferenceRequest,
+    opts: &ConformanceOptions,
+) -> Result<(), String> {
+    request.timeout_ms = Some(opts.timeout_probe_ms);
+    let deadline = Duration::from_millis(opts.timeout_probe_ms) + opts.timeout_grace;
+    let started = Instant::now();
+    let result = adapter.infer(request).await;
+    let elapsed = started.elapsed();
+
+    match result {
+        Err(ModelHostError::Timeout { .. }) | Ok(_) if elapsed <= deadline => Ok(()),
+        Err(ModelHostError::Timeout { .. }) | Ok(_) => Err(format!(
+            "returned after {:?} with a {}ms timeout",
+            elapsed, opts.timeout_probe_ms
+        )),
+        Err(e) => Err(format!("expected Timeout, got {:?}", e)),
+    }
+}
+
+async fn check_stream(
+    adapter: &dyn ModelAdapter,
+    request: InferenceRequest,
+    opts: &ConformanceOptions,
+) -> Result<(), String> {
+    let max_tokens = request.parameters.max_tokens as usize;
+    let stream = adapter
+        .infer_stream(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    let tokens: Vec<_> = tokio::time::timeout(opts.stream_timeout, stream.collect())
+        .await
+        .map_err(|_| format!("stream did not finish within {:?}", opts.stream_timeout))?;
+
+    let mut last_index = None;
+    let mut finals = 0;
+    for (position, token) in tokens.iter().enumerate() {
+        let token = token.as_ref().map_err(|e| format!("stream error: {}", e))?;
+        if last_index.is_some_and(|last| token.token_index <= last) {
+            return Err(format!(
+                "token_index {} after {:?}",
+                token.token_index, last_index
+            ));
+        }
+        last_index = Some(token.token_index);
+        if token.is_final {
+            finals += 1;
+            if position != tokens.len() - 1 {
+                return Err("tokens arrived after is_final".to_string());
+            }
+        }
+    }
+    if finals != 1 {
+        return Err(format!("{} final tokens, expected exactly one", finals));
+    }
+    if tokens.len() > max_tokens.max(1) {
+        return Err(format!(
+            "{} tokens streamed with max_tokens {}",
+            tokens.len(),
+            max_tokens
+        ));
+    }
+    Ok(())
+}
+
+/// Each request carries a `[#i]` marker; `Ok(Some(_))` means the adapter
+/// doesn't echo prompts, so only the count can be checked.
+async fn check_batch(
+    adapter: &dyn ModelAdapter,
+    requests: Vec<InferenceRequest>,
+) -> Result<Option<String>, String> {
+    let empty = adapter
+        .batch_infer(Vec::new())
+        .await
+        .map_err(|e| e.to_string())?;
+    if !empty.is_empty() {
+        return Err(format!("empty batch returned {} responses", empty.len()));
+    }
+
+    let count = requests.len();
+    let responses = adapter
+        .batch_infer(requests)
+        .await
+        .map_err(|e| e.to_string())?;
+    if responses.len() != count {
+        return Err(format!(
+            "{} requests returned {} responses",
+            count,
+            responses.len()
+        ));
+    }
+
+    let markers: Vec<bool> = responses
+        .iter()
+        .enumerate()
+        .map(|(i, response)| response.text.contains(&format!("[#{}]", i)))
+        .collect();
+    if markers.iter().all(|&echoed| echoed) {
+        return Ok(None);
+    }
+    let echoes_any = responses
+        .iter()
+        .any(|response| response.text.contains("[#"));
+    if !echoes_any {
+        return Ok(Some(
+            "responses don't echo prompts; only count verified".to_string(),
+        ));
+    }
+    let position = markers
+        .iter()
+        .position(|&echoed| !echoed)
+        .unwrap_or_default();
+    Err(format!(
+        "response {} does not answer request {}",
+        position, position
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_host::{
+        AnthropicAdapter, GeminiAdapter, InferenceParameters, LocalGGUFAdapter, MLCAdapter,
+        ModelConfig, ModelType, OllamaAdapter, RemoteAPIAdapter, SecureApiKey, VLLMAdapter,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn config(name: &str, model_type: ModelType) -> ModelConfig {
+        ModelConfig {
+            name: name.to_string(),
+            model_type,
+            model_path: None,
+            api_endpoint: None,
+            api_key_env: None,
+            context_window: 4096,
+            vram_required_mb: 1024,
+            default_parameters: InferenceParameters::default(),
+            fallback_models: vec![],
+            warm_pool_size: 1,
+            max_concurrent: 2,
+            retry: Default::default(),
+            cost_per_1k_prompt_tokens: None,
+            cost_per_1k_completion_tokens: None,
+            daily_budget: None,
+            idle_unload_secs: 0,
+            keep_warm: false,
+        }
+    }
+
+    fn assert_conforms(report: &ConformanceReport) {
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.checks.len(), 9);
+    }
+
+    type Responder = fn(&str, serde_json::Value) -> (&'static str, String);
+
+    /// Minimal HTTP server answering each request with `respond(lowercased
+    /// request head, JSON body)`
+    async fn spawn_mock_server(respond: Responder) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    let body_start = loop {
+                        let Ok(n) = socket.read(&mut chunk).await else {
+                            return;
+                        };
+                        if n == 0 {
+                            return;
+                        }
+                        buf.extend_from_slice(&chunk[..n]);
+                        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                            break end + 4;
+                        }
+                    };
+                    let head = String::from_utf8_lossy(&buf[..body_start]).to_lowercase();
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length:"))
+                        .and_then(|value| value.trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                    while buf.len() < body_start + length {
+                        match socket.read(&mut chunk).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                        }
+                    }
+
+                    let request = serde_json::from_slice::<serde_json::Value>(&buf[body_start..])
+                        .unwrap_or_default();
+                    let (content_type, body) = respond(&head, request);
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        content_type,
+                        body.len(),
+                        body
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        addr
+    }
+
+    fn mock_completion(request: &serde_json::Value) -> String {
+        let prompt = request["prompt"]
+            .as_str()
+            .or_else(|| request.pointer("/messages")?.as_array()?.last()?["content"].as_str())
+            .or_else(|| request.pointer("/contents")?.as_array()?.last()?.pointer("/parts/0/text")?.as_str())
+            .unwrap_or_default();
+        format!("mock completion for {} with a few more words than asked", prompt)
+    }
+
+    /// Completion endpoint: echoes the prompt as JSON, or as server-sent
+    /// events when asked to stream
+    async fn spawn_mock_api() -> String {
+        let addr = spawn_mock_server(|_, request| {
+            let text = mock_completion(&request);
+            if request["stream"] == true {
+                let mut events: String = text
+                    .split_inclusive(' ')
+                    .map(|word| format!("data: {}\n\n", serde_json::json!({ "text": word })))
+                    .collect();
+                events.push_str("data: [DONE]\n\n");
+                ("text/event-stream", events)
+            } else {
+                ("application/json", serde_json::json!({ "text": text }).to_string())
+            }
+        })
+        .await;
+        format!("http://{}/v1/completions", addr)
+    }
+
+    /// Ollama's native API with `ollama-probe` pulled; generation reports
+    /// a fixed eval_count so token counts can be told from word counts
+    async fn spawn_mock_ollama() -> String {
+        let addr = spawn_mock_server(|head, request| {
+            if head.starts_with("get /api/tags") {
+                let tags = serde_json::json!({ "models": [{ "name": "ollama-probe:latest" }] });
+                return ("application/json", tags.to_string());
+            }
+            let text = mock_completion(&request);
+            let done = serde_json::json!({
+                "response": "",
+                "done": true,
+                "done_reason": "stop",
+                "prompt_eval_count": 7,
+                "eval_count": 42,
+            });
+            if request["stream"] == true {
+                let mut lines: String = text
+                    .split_inclusive(' ')
+                    .map(|word| format!("{}\n", serde_json::json!({ "response": word, "done": false })))
+                    .collect();
+                lines.push_str(&format!("{}\n", done));
+                ("application/x-ndjson", lines)
+            } else {
+                let mut response = done;
+                response["response"] = text.into();
+                ("application/json", response.to_string())
+            }
+        })
+        .await;
+        format!("http://{}", addr)
+    }
+
+    /// Anthropic's Messages API; requests without an API key get an error
+    /// body instead of content
+    async fn spawn_mock_anthropic() -> String {
+        let addr = spawn_mock_server(|head, request| {
+            if !head.contains("x-api-key:") || !head.contains("anthropic-version:") {
+                let error = serde_json::json!({
+                    "type": "error",
+                    "error": { "type": "authentication_error" },
+                });
+                return ("application/json", error.to_string());
+            }
+            if head.starts_with("get ") {
+                return ("application/json", serde_json::json!({ "data": [] }).to_string());
+            }
+            let text = mock_completion(&request);
+            if request["stream"] == true {
+                let event = |kind: &str, mut data: serde_json::Value| {
+                    data["type"] = kind.into();
+                    format!("event: {}\ndata: {}\n\n", kind, data)
+                };
+                let mut events = event("message_start", serde_json::json!({ "message": { "content": [] } }));
+                for word in text.split_inclusive(' ') {
+                    let delta = serde_json::json!({ "index": 0, "delta": { "type": "text_delta", "text": word } });
+                    events.push_str(&event("content_block_delta", delta));
+                }
+                let stop = serde_json::json!({ "delta": { "stop_reason": "end_turn" } });
+                events.push_str(&event("message_delta", stop));
+                events.push_str(&event("message_stop", serde_json::json!({})));
+                ("text/event-stream", events)
+            } else {
+                let response = serde_json::json!({
+                    "content": [{ "type": "text", "text": text }],
+                    "stop_reason": "end_turn",
+                    "usage": { "input_tokens": 7, "output_tokens": 42 },
+                });
+                ("application/json", response.to_string())
+            }
+        })
+        .await;
+        format!("http://{}", addr)
+    }
+
+    /// Gemini's generateContent API, keyed by query parameter. Prompts
+    /// mentioning "unsafe" come back blocked.
+    async fn spawn_mock_gemini() -> String {
+        let addr = spawn_mock_server(|head, request| {
+            if !head.contains("key=test-key") {
+                let error = serde_json::json!({ "error": { "status": "INVALID_ARGUMENT" } });
+                return ("application/json", error.to_string());
+            }
+            if head.starts_with("get ") {
+                return ("application/json", serde_json::json!({ "name": "models/gemini-probe" }).to_string());
+            }
+            let text = mock_completion(&request);
+            let candidate = |text: &str, finish: Option<&str>| {
+                let mut candidate = serde_json::json!({ "content": { "role": "model", "parts": [{ "text": text }] } });
+                if let Some(finish) = finish {
+                    candidate["finishReason"] = finish.into();
+                }
+                serde_json::json!({ "candidates": [candidate] })
+            };
+            if head.contains(":streamgeneratecontent") && head.contains("alt=sse") {
+                let events: String = text
+                    .split_inclusive(' ')
+                    .map(|word| format!("data: {}\r\n\r\n", candidate(word, None)))
+                    .chain(std::iter::once(format!("data: {}\r\n\r\n", candidate("", Some("STOP")))))
+                    .collect();
+                ("text/event-stream", events)
+            } else if text.contains("unsafe") {
+                ("application/json", candidate("", Some("SAFETY")).to_string())
+            } else {
+                let mut response = candidate(&text, Some("STOP"));
+                response["usageMetadata"] = serde_json::json!({ "promptTokenCount": 7, "candidatesTokenCount": 42 });
+                ("application/json", response.to_string())
+            }
+        })
+        .await;
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
+    async fn test_gguf_adapter_conformance() {
+        let dir = tempfile::tempdir().unwrap();
+        let model_path = dir.path().join("probe.gguf");
+        std::fs::write(&model_path, b"GGUF").unwrap();
+        let adapter = LocalGGUFAdapter::new(ModelConfig {
+            model_path: Some(model_path),
+            ..config("gguf-probe", ModelType::LocalGGUF)
+        });
+
+        let report =
+            run_adapter_conformance(Box::new(adapter), &ConformanceOptions::default()).await;
+        assert_conforms(&report);
+    }
+
+    #[tokio::test]
+    async fn test_mlc_adapter_conformance() {
+        let adapter = MLCAdapter::new(config("mlc-probe", ModelType::MLC));
+        let report =
+            run_adapter_conformance(Box::new(adapter), &ConformanceOptions::default()).await;
+        assert_conforms(&report);
+    }
+
+    #[tokio::test]
+    async fn test_vllm_adapter_conformance() {
+        let adapter = VLLMAdapter::new(config("vllm-probe", ModelType::VLLM));
+        let report =
+            run_adapter_conformance(Box::new(adapter), &ConformanceOptions::default()).await;
+        assert_conforms(&report);
+    }
+
+    #[tokio::test]
+    async fn test_remote_api_adapter_conformance() {
+        let adapter = RemoteAPIAdapter::new(ModelConfig {
+            api_endpoint: Some(spawn_mock_api().await),
+            vram_required_mb: 0,
+            ..config("remote-probe", ModelType::RemoteAPI)
+        })
+        .unwrap();
+        let report =
+            run_adapter_conformance(Box::new(adapter), &ConformanceOptions::default()).await;
+        assert_conforms(&report);
+    }
+
+    #[tokio::test]
+    async fn test_ollama_adapter_conformance() {
+        let adapter = OllamaAdapter::new(ModelConfig {
+            api_endpoint: Some(spawn_mock_ollama().await),
+            ..config("ollama-probe", ModelType::Ollama)
+        });
+        let report =
+            run_adapter_conformance(Box::new(adapter), &ConformanceOptions::default()).await;
+        assert_conforms(&report);
+    }
+
+    #[tokio::test]
+    async fn test_ollama_token_counts_and_missing_model() {
+        let endpoint = spawn_mock_ollama().await;
+        let mut adapter = OllamaAdapter::new(ModelConfig {
+            api_endpoint: Some(endpoint.clone()),
+            ..config("ollama-probe", ModelType::Ollama)
+        });
+        adapter.load().await.unwrap();
+        let mut request = InferenceRequest::new("ollama-probe", "count");
+        request.parameters.max_tokens = 100;
+        let response = adapter.infer(request).await.unwrap();
+        assert_eq!(response.tokens_generated, 42);
+        assert_eq!(response.total_tokens, 49);
+
+        let mut missing = OllamaAdapter::new(ModelConfig {
+            api_endpoint: Some(endpoint),
+            ..config("not-pulled", ModelType::Ollama)
+        });
+        assert!(missing.load().await.is_err());
+        assert!(!missing.is_loaded());
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_adapter_conformance() {
+        let adapter = AnthropicAdapter::new(ModelConfig {
+            api_endpoint: Some(spawn_mock_anthropic().await),
+            api_key_env: None,
+            ..config("claude-probe", ModelType::Anthropic)
+        })
+        .unwrap()
+        .with_api_key(SecureApiKey::new("test-key".to_string()));
+        let report =
+            run_adapter_conformance(Box::new(adapter), &ConformanceOptions::default()).await;
+        assert_conforms(&report);
+    }
+
+    #[tokio::test]
+    async fn test_gemini_adapter_conformance() {
+        let gemini = |endpoint: &str| {
+            GeminiAdapter::new(ModelConfig {
+                api_endpoint: Some(endpoint.to_string()),
+                api_key_env: None,
+                ..config("gemini-probe", ModelType::Gemini)
+            })
+            .unwrap()
+            .with_api_key(SecureApiKey::new("test-key".to_string()))
+        };
+        let endpoint = spawn_mock_gemini().await;
+        let report =
+            run_adapter_conformance(Box::new(gemini(&endpoint)), &ConformanceOptions::default()).await;
+        assert_conforms(&report);
+
+        let mut adapter = gemini(&endpoint);
+        adapter.load().await.unwrap();
+        let mut request = InferenceRequest::new("gemini-probe", "count");
+        request.parameters.max_tokens = 100;
+        let response = adapter.infer(request).await.unwrap();
+        assert_eq!((response.tokens_generated, response.total_tokens), (42, 49));
+        let blocked = adapter.infer(InferenceRequest::new("gemini-probe", "unsafe")).await.unwrap();
+        assert_eq!(blocked.finish_reason, FinishReason::Error("response blocked: SAFETY".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_missing_model_skips_loaded_checks() {
+        let adapter = LocalGGUFAdapter::new(ModelConfig {
+            model_path: Some("/nonexistent/probe.gguf".into()),
+            ..config("missing-probe", ModelType::LocalGGUF)
+        });
+        let report =
+            run_adapter_conformance(Box::new(adapter), &ConformanceOptions::default()).await;
+
+        assert!(!report.passed());
+        assert_eq!(
+            report.outcome("unloaded_errors"),
+            Some(&CheckOutcome::Passed)
+        );
+        assert!(matches!(
+            report.outcome("load_idempotent"),
+            Some(CheckOutcome::Failed(_))
+        ));
+        assert!(matches!(
+            report.outcome("stream_ordering"),
+            Some(CheckOutcome::Skipped(_))
+        ));
+        assert!(report.to_string().contains("FAIL load_idempotent"));
+    }
+}
@@ -0,0 +1,362 @@
+use crate::config::UiConfig;
+use crate::theme::Theme;
+
+/// Value of `ui.theme` that follows the OS appearance
+pub const AUTO_THEME: &str = "auto";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Appearance {
+    Light,
+    #[default]
+    Dark,
+}
+
+impl Appearance {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Appearance::Light => "light",
+            Appearance::Dark => "dark",
+        }
+    }
+
+    /// Appearance of a theme, judged by its background
+    pub fn of_theme(theme: &str) -> Self {
+        let [r, g, b, _] = theme_background(theme);
+        if 0.2126 * r + 0.7152 * g + 0.0722 * b > 0.5 {
+            Appearance::Light
+        } else {
+            Appearance::Dark
+        }
+    }
+}
+
+impl From<winit::window::Theme> for Appearance {
+    fn from(theme: winit::window::Theme) -> Self {
+        match theme {
+            winit::window::Theme::Light => Appearance::Light,
+            winit::window::Theme::Dark => Appearance::Dark,
+        }
+    }
+}
+
+/// Default background for a built-in theme, used for the window clear
+/// color and reported to applications through OSC 11
+pub fn theme_background(theme: &str) -> [f32; 4] {
+    Theme::named(theme).background
+}
+
+/// Reply to an OSC 11 background query, in xterm's `rgb:` form
+pub fn background_report(background: [f32; 4]) -> String {
+    let channel = |v: f32| (v.clamp(0.0, 1.0) * 65535.0).round() as u16;
+    format!(
+        "\x1b]11;rgb:{:04x}/{:04x}/{:04x}\x1b\\",
+        channel(background[0]),
+        channel(background[1]),
+        channel(background[2])
+    )
+}
+
+/// Color scheme report (`CSI ? 997 ; 1|2 n`), sent for `CSI ? 996 n` and on
+/// every change while mode 2031 is set
+pub fn color_scheme_report(appearance: Appearance) -> &'static str {
+    match appearance {
+        Appearance::Dark => "\x1b[?997;1n",
+        Appearance::Light => "\x1b[?997;2n",
+    }
+}
+
+/// Source of the OS light/dark setting
+pub trait AppearanceProvider: Send {
+    /// `None` when the platform has no preference or cannot be queried
+    fn current(&self) -> Option<Appearance>;
+}
+
+/// Reads `NSApp.effectiveAppearance`
+#[cfg(target_os = "macos")]
+pub struct MacAppearance;
+
+#[cfg(target_os = "macos")]
+impl AppearanceProvider for MacAppearance {
+    fn current(&self) -> Option<Appearance> {
+        use objc::runtime::{Class, Object};
+        use objc::{msg_send, sel, sel_impl};
+        use std::ffi::CStr;
+
+        unsafe {
+            let app: *mut Object = msg_send![Class::get("NSApplication")?, sharedApplication];
+            let appearance: *mut Object = msg_send![app, effectiveAppearance];
+            if appearance.is_null() {
+                return None;
+            }
+            let name: *mut Object = msg_send![appearance, name];
+            let utf8: *const std::os::raw::c_char = msg_send![name, UTF8String];
+            if utf8.is_null() {
+                return None;
+            }
+            let name = CStr::from_ptr(utf8).to_string_lossy();
+            Some(if name.contains("Dark") {
+                Appearance::Dark
+            } else {
+                Appearance::Light
+            })
+        }
+    }
+}
+
+/// Reads `org.freedesktop.appearance color-scheme` from the settings portal
+#[cfg(target_os = "linux")]
+pub struct PortalAppearance;
+
+#[cfg(target_os = "linux")]
+impl AppearanceProvider for PortalAppearance {
+    fn current(&self) -> Option<Appearance> {
+        let output = std::process::Command::new("busctl")
+            .args([
+                "--user",
+                "call",
+                "org.freedesktop.portal.Desktop",
+                "/org/freedesktop/portal/desktop",
+                "org.freedesktop.portal.Settings",
+                "Read",
+                "ss",
+                "org.freedesktop.appearance",
+                "color-scheme",
+            ])
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        parse_portal_color_scheme(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// busctl prints the variant as e.g. `v v u 1`: 1 prefers dark, 2 prefers
+/// light, 0 has no preference
+pub fn parse_portal_color_scheme(output: &str) -> Option<Appearance> {
+    match output.split_whitespace().last()? {
+        "1" => Some(Appearance::Dark),
+        "2" => Some(Appearance::Light),
+        _ => None,
+    }
+}
+
+/// Provider for the current platform
+pub fn system_provider() -> Option<Box<dyn AppearanceProvider>> {
+    #[cfg(target_os = "macos")]
+    return Some(Box::new(MacAppearance));
+    #[cfg(target_os = "linux")]
+    return Some(Box::new(PortalAppearance));
+    #[allow(unreachable_code)]
+    None
+}
+
+/// Polls a provider and reports only changes
+pub struct AppearanceWatcher {
+    provider: Box<dyn AppearanceProvider>,
+    last: Option<Appearance>,
+}
+
+impl AppearanceWatcher {
+    pub fn new(provider: Box<dyn AppearanceProvider>) -> Self {
+        let last = provider.current();
+        Self { provider, last }
+    }
+
+    pub fn current(&self) -> Option<Appearance> {
+        self.last
+    }
+
+    pub fn poll(&mut self) -> Option<Appearance> {
+        let now = self.provider.current();
+        if now.is_some() && now != self.last {
+            self.last = now;
+            return now;
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThemeSelection {
+    /// Follow the OS between `theme_light` and `theme_dark`
+    Auto,
+    /// Chosen with `:theme` or a fixed `ui.theme`
+    Manual(String),
+}
+
+/// Decides the active theme from the config, the OS appearance and any
+/// manual `:theme` choice. Every method that can change the theme returns
+/// the new theme name when it did.
+#[derive(Debug, Clone)]
+pub struct ThemeController {
+    light: String,
+    dark: String,
+    selection: ThemeSelection,
+    os_appearance: Appearance,
+}
+
+impl ThemeController {
+    pub fn new(ui: &UiConfig, os_appearance: Option<Appearance>) -> Self {
+        let selection = if ui.theme == AUTO_THEME {
+            ThemeSelection::Auto
+        } else {
+            ThemeSelection::Manual(ui.theme.clone())
+        };
+        Self {
+            light: ui.theme_light.clone(),
+            dark: ui.theme_dark.clone(),
+            selection,
+            os_appearance: os_appearance.unwrap_or_default(),
+        }
+    }
+
+    pub fn active(&self) -> &str {
+        match &self.selection {
+            ThemeSelection::Auto => match self.os_appearance {
+                Appearance::Light => &self.light,
+                Appearance::Dark => &self.dark,
+            },
+            ThemeSelection::Manual(theme) => theme,
+        }
+    }
+
+    pub fn selection(&self) -> &ThemeSelection {
+        &self.selection
+    }
+
+    /// Appearance of the active theme, which is what applications are told
+    pub fn appearance(&self) -> Appearance {
+        Appearance::of_theme(self.active())
+    }
+
+    pub fn os_appearance(&self) -> Appearance {
+        self.os_appearance
+    }
+
+    pub fn set_os_appearance(&mut self, appearance: Appearance) -> Option<String> {
+        self.update(|controller| controller.os_appearance = appearance)
+    }
+
+    /// `:theme <name>`; `auto` hands control back to the OS appearance
+    pub fn select(&mut self, theme: &str) -> Option<String> {
+        let selection = if theme == AUTO_THEME {
+            ThemeSelection::Auto
+        } else {
+            ThemeSelection::Manual(theme.to_string())
+        };
+        self.update(|controller| controller.selection = selection)
+    }
+
+    fn update(&mut self, change: impl FnOnce(&mut Self)) -> Option<String> {
+        let before = self.active().to_string();
+        change(self);
+        let after = self.active();
+        (after != before).then(|| after.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::TerminalState;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct StubProvider(Arc<Mutex<Option<Appearance>>>);
+
+    impl StubProvider {
+        fn set(&self, appearance: Appearance) {
+            *self.0.lock().unwrap() = Some(appearance);
+        }
+    }
+
+    impl AppearanceProvider for StubProvider {
+        fn current(&self) -> Option<Appearance> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn auto_ui() -> UiConfig {
+        UiConfig {
+            theme: AUTO_THEME.to_string(),
+            theme_light: "light".to_string(),
+            theme_dark: "dark".to_string(),
+            ..UiConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_auto_theme_follows_provider() {
+        let stub = StubProvider::default();
+        stub.set(Appearance::Light);
+        let mut watcher = AppearanceWatcher::new(Box::new(stub.clone()));
+        let mut themes = ThemeController::new(&auto_ui(), watcher.current());
+        assert_eq!(themes.active(), "light");
+
+        assert_eq!(watcher.poll(), None);
+        stub.set(Appearance::Dark);
+        let changed = watcher.poll().unwrap();
+        assert_eq!(themes.set_os_appearance(changed), Some("dark".to_string()));
+        assert_eq!(themes.set_os_appearance(Appearance::Dark), None);
+        assert_eq!(themes.appearance(), Appearance::Dark);
+    }
+
+    #[test]
+    fn test_manual_theme_overrides_auto() {
+        let mut themes = ThemeController::new(&auto_ui(), Some(Appearance::Dark));
+        assert_eq!(themes.select("light"), Some("light".to_string()));
+        assert_eq!(themes.set_os_appearance(Appearance::Light), None);
+        assert_eq!(themes.set_os_appearance(Appearance::Dark), None);
+        assert_eq!(themes.active(), "light");
+
+        assert_eq!(themes.select(AUTO_THEME), Some("dark".to_string()));
+        assert_eq!(themes.selection(), &ThemeSelection::Auto);
+        assert_eq!(
+            themes.set_os_appearance(Appearance::Light),
+            Some("light".to_string())
+        );
+
+        // A fixed ui.theme never follows the OS
+        let fixed = UiConfig {
+            theme: "dark".to_string(),
+            ..auto_ui()
+        };
+        let mut themes = ThemeController::new(&fixed, Some(Appearance::Light));
+        assert_eq!(themes.active(), "dark");
+        assert_eq!(themes.set_os_appearance(Appearance::Dark), None);
+    }
+
+    #[test]
+    fn test_osc11_and_mode_2031_follow_theme() {
+        let mut themes = ThemeController::new(&auto_ui(), Some(Appearance::Dark));
+        let mut terminal = TerminalState::new(20, 5);
+        terminal.set_theme(themes.appearance(), theme_background(themes.active()));
+
+        terminal.feed_bytes(b"\x1b]11;?\x07\x1b[?2031h");
+        assert_eq!(
+            terminal.take_replies(),
+            b"\x1b]11;rgb:0000/0000/0000\x1b\\".to_vec()
+        );
+
+        let theme = themes.set_os_appearance(Appearance::Light).unwrap();
+        terminal.set_theme(themes.appearance(), theme_background(&theme));
+        assert_eq!(terminal.take_replies(), b"\x1b[?997;2n".to_vec());
+
+        terminal.feed_bytes(b"\x1b]11;?\x1b\\\x1b[?996n");
+        let replies = String::from_utf8(terminal.take_replies()).unwrap();
+        assert_eq!(replies, "\x1b]11;rgb:fae0/fae0/fae0\x1b\\\x1b[?997;2n");
+        assert_eq!(terminal.get_cell(0, 0).unwrap().character, ' ');
+
+        // Unsubscribed applications get no unsolicited reports
+        terminal.feed_bytes(b"\x1b[?2031l");
+        terminal.set_theme(Appearance::Dark, theme_background("dark"));
+        assert!(terminal.take_replies().is_empty());
+
+        assert_eq!(
+            parse_portal_color_scheme("v v u 1\n"),
+            Some(Appearance::Dark)
+        );
+        assert_eq!(parse_portal_color_scheme("v v u 0"), None);
+    }
+}
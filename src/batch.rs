@@ -0,0 +1,393 @@
+//! `batch <file>`: a file of prompts answered through
+//! `ModelHost::batch_infer`, shown in the pane under a header each or
+//! written to a JSONL file
+
+use crate::config::Config;
+use crate::messages;
+use crate::model_host::{
+    BatchInferenceRequest, BatchProgress, FinishReason, InferencePriority, InferenceRequest, InferenceResponse,
+    ModelHost, ModelHostError,
+};
+use crate::title;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+#[derive(Error, Debug)]
+pub enum BatchError {
+    #[error("Failed to read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Failed to write {path}: {source}")]
+    Write {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Line {line}: {message}")]
+    Parse { line: usize, message: String },
+    #[error("No prompts in {0}")]
+    Empty(String),
+}
+
+/// Longest prompt shown in a result header
+const HEADER_PROMPT_CHARS: usize = 60;
+
+/// One line of a JSONL prompt file. `model` and `parameters` default to
+/// the agent's settings; `parameters` may set only some fields.
+#[derive(Debug, Deserialize)]
+struct PromptLine {
+    prompt: String,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    parameters: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The requests in a prompt file: JSONL when its first non-blank line
+/// starts with `{`, otherwise a prompt per non-blank line
+pub fn parse_prompts(text: &str, config: &Config) -> Result<Vec<InferenceRequest>, BatchError> {
+    let jsonl = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .is_some_and(|line| line.starts_with('{'));
+    let mut requests = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let request = if jsonl {
+            parse_line(line, config).map_err(|message| BatchError::Parse { line: index + 1, message })?
+        } else {
+            default_request(line.to_string(), None, config)
+        };
+        requests.push(request);
+    }
+    Ok(requests)
+}
+
+/// A prompt with the agent's model and settings. Batches run at low
+/// priority so prompts typed meanwhile are answered first.
+fn default_request(prompt: String, model: Option<String>, config: &Config) -> InferenceRequest {
+    let agent = &config.agent;
+    let model = model.unwrap_or_else(|| config.models.default_model(agent).to_string());
+    let mut request = InferenceRequest::new(model, prompt);
+    request.parameters.temperature = agent.temperature;
+    request.parameters.max_tokens = agent.max_tokens;
+    request.priority = InferencePriority::Low;
+    request.timeout_ms = (agent.timeout_ms > 0).then_some(agent.timeout_ms);
+    request
+}
+
+fn parse_line(line: &str, config: &Config) -> Result<InferenceRequest, String> {
+    let entry: PromptLine = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let mut request = default_request(entry.prompt, entry.model, config);
+    let mut parameters = serde_json::to_value(&request.parameters).map_err(|e| e.to_string())?;
+    if let Some(fields) = parameters.as_object_mut() {
+        fields.extend(entry.parameters);
+    }
+    request.parameters = serde_json::from_value(parameters).map_err(|e| e.to_string())?;
+    Ok(request)
+}
+
+/// The prompts in `path` as one batch, `agent.batch_max_parallel` at a time
+pub fn read_batch(path: &Path, config: &Config) -> Result<BatchInferenceRequest, BatchError> {
+    let text = fs::read_to_string(path).map_err(|source| BatchError::Read {
+        path: path.display().to_string(),
+        source,
+    })?;
+    let requests = parse_prompts(&text, config)?;
+    if requests.is_empty() {
+        return Err(BatchError::Empty(path.display().to_string()));
+    }
+    Ok(BatchInferenceRequest {
+        requests,
+        batch_id: uuid::Uuid::new_v4().simple().to_string(),
+        max_parallel: Some(config.agent.batch_max_parallel.max(1) as usize),
+    })
+}
+
+/// The outcome of one prompt, and a line of a `--out` file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchRecord {
+    pub prompt: String,
+    /// The model asked
+    pub model: String,
+    /// The model that answered, when a fallback took over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<u32>,
+    /// `stop`, `length` or `error`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BatchRecord {
+    pub fn new(request: &InferenceRequest, result: &Result<InferenceResponse, ModelHostError>) -> Self {
+        let record = Self {
+            prompt: request.prompt.clone(),
+            model: request.model_name.clone(),
+            fallback_model: None,
+            text: None,
+            tokens: None,
+            finish_reason: None,
+            error: None,
+        };
+        match result {
+            Ok(response) => Self {
+                fallback_model: response.is_fallback.then(|| response.model_used.clone()),
+                text: Some(response.text.clone()),
+                tokens: Some(response.tokens_generated),
+                finish_reason: Some(
+                    match response.finish_reason {
+                        FinishReason::Stop => "stop",
+                        FinishReason::Length => "length",
+                        FinishReason::Error(_) => "error",
+                    }
+                    .to_string(),
+                ),
+                ..record
+            },
+            Err(e) => Self {
+                error: Some(e.to_string()),
+                ..record
+            },
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+/// Each answer under a header with its number, model and prompt, as
+/// markdown for the pane's inline region; failed prompts show their error
+/// instead. Control characters are dropped so a model cannot send escape
+/// sequences to the terminal.
+pub fn format_inline(records: &[BatchRecord]) -> String {
+    let messages = messages::current();
+    records
+        .iter()
+        .enumerate()
+        .map(|(index, record)| {
+            let model = record.fallback_model.as_deref().unwrap_or(&record.model);
+            let prompt = title::sanitize(&record.prompt, HEADER_PROMPT_CHARS);
+            let body = match &record.error {
+                Some(error) => messages.error_marker(error),
+                None => record.text.as_deref().unwrap_or_default().trim_end().to_string(),
+            };
+            format!(
+                "{}\n\n{}\n",
+                messages.batch_header(index + 1, records.len(), model, &prompt),
+                strip_controls(&body)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `text` without C0 and C1 controls other than line breaks and tabs
+fn strip_controls(text: &str) -> String {
+    text.chars().filter(|&c| matches!(c, '\n' | '\t') || !c.is_control()).collect()
+}
+
+/// One JSON object per line, in prompt order
+pub fn write_jsonl(path: &Path, records: &[BatchRecord]) -> Result<(), BatchError> {
+    let write = || {
+        let mut file = std::io::BufWriter::new(fs::File::create(path)?);
+        for record in records {
+            serde_json::to_writer(&mut file, record)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()
+    };
+    write().map_err(|source| BatchError::Write {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+/// Answer the prompts in `file` on `host`, sending counts to `progress`
+/// as each finishes. One prompt failing leaves the rest running; its
+/// record carries the error. With `out` the records are also written
+/// there as JSONL.
+pub async fn run_file(
+    host: &ModelHost,
+    config: &Config,
+    file: &Path,
+    out: Option<&Path>,
+    progress: mpsc::UnboundedSender<BatchProgress>,
+) -> Result<Vec<BatchRecord>, BatchError> {
+    let batch = read_batch(file, config)?;
+    let requests = batch.requests.clone();
+    let results = host.batch_infer_with_progress(batch, progress).await;
+    let records: Vec<_> = requests
+        .iter()
+        .zip(&results)
+        .map(|(request, result)| BatchRecord::new(request, result))
+        .collect();
+    if let Some(out) = out {
+        write_jsonl(out, &records)?;
+    }
+    Ok(records)
+}
+
+/// A `batch` command answering on its own task
+pub struct RunningBatch {
+    pub pane: u64,
+    out: Option<PathBuf>,
+    progress: mpsc::UnboundedReceiver<BatchProgress>,
+    task: tokio::task::JoinHandle<Result<Vec<BatchRecord>, BatchError>>,
+}
+
+impl RunningBatch {
+    pub fn start(host: Arc<ModelHost>, config: Config, pane: u64, file: PathBuf, out: Option<PathBuf>) -> Self {
+        let (progress_tx, progress) = mpsc::unbounded_channel();
+        let task = {
+            let out = out.clone();
+            tokio::spawn(async move { run_file(&host, &config, &file, out.as_deref(), progress_tx).await })
+        };
+        Self { pane, out, progress, task }
+    }
+
+    /// The latest counts, when any arrived since the last call
+    pub fn progress(&mut self) -> Option<BatchProgress> {
+        std::iter::from_fn(|| self.progress.try_recv().ok()).last()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// The final counts, then the answers or where they were written
+    pub async fn finish(self) -> String {
+        let messages = messages::current();
+        let records = match self.task.await {
+            Ok(Ok(records)) => records,
+            Ok(Err(e)) => return format!("{}\n", messages.command_error(&e.to_string())),
+            Err(e) => return format!("{}\n", messages.command_error(&e.to_string())),
+        };
+        let failed = records.iter().filter(|record| record.failed()).count();
+        let counts = messages.batch_progress(records.len(), records.len(), failed);
+        match &self.out {
+            Some(out) => format!("{} · {}\n", counts, messages.batch_saved(&out.display().to_string())),
+            None => format!("{}\n\n{}", counts, format_inline(&records)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vram_probe::VramBudget;
+
+    #[test]
+    fn test_parse_plain_and_jsonl_prompts() {
+        let config = Config::default();
+        let requests = parse_prompts("first question\n\n  second question  \n", &config).unwrap();
+        let prompts: Vec<_> = requests.iter().map(|request| request.prompt.as_str()).collect();
+        assert_eq!(prompts, ["first question", "second question"]);
+        assert_eq!(requests[0].model_name, config.models.default_model(&config.agent));
+        assert_eq!(requests[0].parameters.max_tokens, config.agent.max_tokens);
+        assert_eq!(requests[0].priority, InferencePriority::Low);
+
+        let jsonl = r#"{"prompt": "hi", "model": "coder", "parameters": {"temperature": 0.1}}
+{"prompt": "there"}
+"#;
+        let requests = parse_prompts(jsonl, &config).unwrap();
+        assert_eq!(requests[0].model_name, "coder");
+        assert_eq!(requests[0].parameters.temperature, 0.1);
+        assert_eq!(requests[0].parameters.max_tokens, config.agent.max_tokens);
+        assert_eq!(requests[1].parameters.temperature, config.agent.temperature);
+
+        assert!(matches!(
+            parse_prompts("{\"prompt\": \"ok\"}\n{\"model\": \"x\"}\n", &config),
+            Err(BatchError::Parse { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse_prompts("{\"prompt\": \"ok\", \"parameters\": {\"max_tokens\": \"many\"}}", &config),
+            Err(BatchError::Parse { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_format_inline_and_records() {
+        let ok = BatchRecord::new(
+            &InferenceRequest::new("local", "say hi"),
+            &Ok(InferenceResponse {
+                text: "hi\n".to_string(),
+                tokens_generated: 1,
+                total_tokens: 3,
+                finish_reason: FinishReason::Stop,
+                timing: crate::model_host::InferenceTiming {
+                    prompt_eval_time: Default::default(),
+                    eval_time: Default::default(),
+                    total_time: Default::default(),
+                    load_time: Default::default(),
+                },
+                model_used: "local".to_string(),
+                is_fallback: false,
+                context_dropped: 0,
+            }),
+        );
+        let failed = BatchRecord::new(
+            &InferenceRequest::new("gone", "say bye"),
+            &Err(ModelHostError::ModelNotFound { name: "gone".to_string() }),
+        );
+        assert!(!ok.failed() && failed.failed());
+        assert_eq!(
+            format_inline(&[ok.clone(), failed.clone()]),
+            "── 1/2 · local · say hi\n\nhi\n\n── 2/2 · gone · say bye\n\n[ERROR: Model not found: gone]\n"
+        );
+        let mut hostile = ok.clone();
+        hostile.text = Some("\x1b]0;pwned\x07red \x1b[31mtext\u{9b}2J\tdone\n".to_string());
+        assert_eq!(format_inline(&[hostile]), "── 1/1 · local · say hi\n\n]0;pwnedred [31mtext2J\tdone\n");
+
+        let line = serde_json::to_string(&ok).unwrap();
+        assert_eq!(
+            line,
+            r#"{"prompt":"say hi","model":"local","text":"hi\n","tokens":1,"finish_reason":"stop"}"#
+        );
+        assert_eq!(serde_json::from_str::<BatchRecord>(&line).unwrap(), ok);
+    }
+
+    #[tokio::test]
+    async fn test_run_file_keeps_going_past_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("prompts.jsonl");
+        let out = dir.path().join("results.jsonl");
+        fs::write(&file, "{\"prompt\": \"a\", \"model\": \"missing\"}\n{\"prompt\": \"b\", \"model\": \"missing\"}\n").unwrap();
+        let host = ModelHost::new(1, 1, VramBudget::Fixed(0));
+
+        let (progress_tx, mut progress) = mpsc::unbounded_channel();
+        let records = run_file(&host, &Config::default(), &file, Some(&out), progress_tx).await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(BatchRecord::failed));
+        let counts: Vec<_> = std::iter::from_fn(|| progress.try_recv().ok()).collect();
+        assert_eq!(counts.last(), Some(&BatchProgress { completed: 2, failed: 2, total: 2 }));
+
+        let written: Vec<BatchRecord> = fs::read_to_string(&out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(written, records);
+
+        assert!(matches!(
+            run_file(&host, &Config::default(), &dir.path().join("none"), None, mpsc::unbounded_channel().0).await,
+            Err(BatchError::Read { .. })
+        ));
+    }
+}
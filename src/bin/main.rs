@@ -1,72 +1,290 @@
 #![allow(unexpected_cfgs, dead_code)]
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 use ferroterm::{
-    config::ConfigManager,
-    input::{Key, KeyEvent},
-    simple_renderer::SimpleRenderer,
-    terminal::TerminalState,
-    tty::{PtyConfig, TtyEngine},
+    agent_prompt::{self, ActivePrompt, InterruptRoute, InterruptRouter, PromptEvent, PromptReply},
+    buffer_search::{SearchBar, SearchOutcome},
+    appearance::{self, Appearance, AppearanceWatcher, ThemeController},
+    batch::RunningBatch,
+    bitmap_font::BitmapFont,
+    calc::{CalcBlock, CalcKeyOutcome},
+    clipboard::Clipboard,
+    column_guides::{self, GuideOverrides, GuideStyle},
+    command_history::{self, CommandHistory, CommandTracker, HistoryOverlay, OverlayOutcome},
+    config::{Config, ConfigManager, ShellConfig, UiConfig, UserCommand},
+    ghost_text::{self, CompletionModel, CompletionReply, GhostText, GhostTextConfig, HostCompletion},
+    hyperlinks,
+    command_parser::CommandParser,
+    command_registry::{CachedCompletions, CompletionSource, PathCompleter},
+    idle_lock::{IdleLock, IdleLockConfig},
+    markdown_stream::MarkdownStream,
+    messages::{self, Messages},
+    mouse_wheel::{self, WheelAccumulator},
+    model_host::{FinishReason, HotSwapProgress, ModelHost, HOT_SWAP_TARGET},
+    model_registry,
+    command_parser::{self, AgentCommand, Command, SessionAction},
+    input::{InputAction, InputProcessor, Key, KeyEvent, Modifier, TerminalContext},
+    multiplexer::{self, Multiplexer, MultiplexerConfig, PaneDirection, Session, SplitDirection},
+    output_scheduler::{OutputBacklog, OutputScheduler, OutputSchedulerConfig, SliceReport},
+    pane_border::READ_ONLY_MARKER,
+    paste_guard::{self, PasteGuardConfig, PasteReview, PasteVerdict, ReviewOutcome},
+    read_only::{InputSource, ReadOnlyMode, ReadOnlyPanes},
+    render_budget::DegradationConfig,
+    render_caps,
+    response_browser::{BrowserOutcome, ResponseBrowser, ResponseHistory},
+    response_log::{self, ResponseLog, ResponseLogConfig},
+    scaffold::{self, ScaffoldPlan},
+    scrollback::{Scrollback, ScrollbackConfig},
+    simple_renderer::{PaneView, SimpleRenderer},
+    startup::{CellFont, StagedStartup, StartupStage},
+    stats_overlay::{self, StatsOverlay, StatsReport},
+    syntax_highlight::SyntaxHighlighter,
+    system_font::SystemFont,
+    terminal::{Selection, ShellEvent, TerminalCell, TerminalState},
+    terminal_parser::{CursorShape, CursorStyle},
+    title::{self, TitleFields},
+    trace::{self, RecordingSink, Trace, TraceHeader, TraceRecorder},
+    tty::{OnShellExit, PtyConfig, PtyEvent, TtyEngine, TtyError},
+    usage::{self, UsageTracker},
+    vram_probe::VramBudget,
+    watchdog::{Component, Heartbeat, LogRing, RecoveryAction, Stall, Watchdog, WatchdogConfig},
+    window_manager::{CloseDecision, SessionLayout, WindowGeometry, WindowRecord, WindowRegistry},
 };
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use parking_lot::RwLock;
 use tracing::{debug, error, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
 
 #[cfg(target_os = "macos")]
 use objc::{msg_send, sel, sel_impl};
 #[cfg(target_os = "macos")]
 use objc::runtime::Object;
 use winit::{
-    event::{ElementState, KeyEvent as WinitKeyEvent, Modifiers, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    keyboard::{Key as WinitKey, NamedKey},
-    window::{Window, WindowBuilder},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, Ime, KeyEvent as WinitKeyEvent, Modifiers, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
+    keyboard::{Key as WinitKey, ModifiersState, NamedKey},
+    window::{UserAttentionType, Window, WindowBuilder, WindowId},
 };
 
 #[derive(Parser)]
 #[command(name = "ferroterm")]
 #[command(about = "A modern terminal emulator")]
 struct Args {
+    #[command(subcommand)]
+    action: Option<Action>,
+
     /// Command to run on startup
     #[arg(short, long)]
     command: Option<String>,
 
+    /// Record the first window's output, input and resizes to a trace file
+    #[arg(long, value_name = "FILE")]
+    record_trace: Option<PathBuf>,
+
     /// Arguments for the command
     #[arg(last = true)]
     command_args: Vec<String>,
 }
 
+#[derive(Subcommand)]
+enum Action {
+    /// Replay a recorded trace without a PTY and compare its snapshots
+    ReplayTrace {
+        file: PathBuf,
+        /// Snapshot after every event rather than only at checkpoints
+        #[arg(long)]
+        every_event: bool,
+        /// Print the grid after each snapshot
+        #[arg(long)]
+        print: bool,
+    },
+}
+
+/// The window being recorded with `--record-trace`
+struct TraceSession {
+    window: WindowId,
+    pty_id: u64,
+    /// `None` once a write has failed
+    recorder: Option<TraceRecorder>,
+}
+
+
+
+/// A tab's PTY output waiting to be parsed, drained a slice per frame
+struct TabOutput {
+    backlog: Arc<OutputBacklog>,
+    scheduler: OutputScheduler,
+}
+
+// Per-window resources owned by the frontend
+struct WindowContext {
+    window: Arc<Window>,
+    renderer: Option<SimpleRenderer>,
+    modifiers: Modifiers,
+    /// The title shows a notice until then
+    notice_until: Option<Instant>,
+    /// Catch-up indicator currently in the title
+    indicator: Option<String>,
+    /// Title last set from the focused tab, notices and indicator aside
+    title: String,
+    /// IME composition in progress; it reaches the PTY only when committed
+    preedit: Option<String>,
+    /// Last mouse position, in physical pixels
+    pointer: PhysicalPosition<f64>,
+    selection: Option<Selection>,
+    /// The left button is down and drags extend the selection
+    selecting: bool,
+    wheel: WheelAccumulator,
+}
+
+/// How long a read-only notice replaces the window title
+const NOTICE_DURATION: Duration = Duration::from_secs(2);
+
+/// How long a pane's foreground process name is trusted before asking again
+const FOREGROUND_REFRESH: Duration = Duration::from_millis(500);
+
+/// How often a blanked terminal looks at output for wake-on-output and bells
+const BLANKED_POLL: Duration = Duration::from_millis(250);
 
+/// Longest gap between frames before the watchdog steps in
+const RENDER_STALL: Duration = Duration::from_secs(2);
+
+/// Longest gap between PTY reader iterations, idle ones included
+const READER_STALL: Duration = Duration::from_secs(5);
+
+/// Started instead of a configured shell that cannot be run
+const FALLBACK_SHELL: &str = "/bin/sh";
+
+/// Agent responses kept in memory for `copy` and the history browser
+const RESPONSE_HISTORY: usize = 100;
+
+/// How long in-flight model requests get to finish on exit
+const MODEL_DRAIN: Duration = Duration::from_secs(3);
+
+/// Lists behind Tab completion on the prefix line
+#[derive(Debug, Default)]
+struct CompletionLists {
+    models: CachedCompletions,
+    sessions: CachedCompletions,
+    paths: PathCompleter,
+    /// A prefix line was showing at the last key
+    open: bool,
+}
 
 // Application state
 struct FerrotermApp {
-    window: Option<Arc<Window>>,
+    windows: WindowRegistry<WindowId, WindowContext>,
     tty_engine: Arc<TtyEngine>,
     config_manager: Arc<ConfigManager>,
-    terminal_state: Arc<RwLock<TerminalState>>,
-    renderer: Option<SimpleRenderer>,
-    main_pty_id: Option<u64>,
+    /// Configs written to the config file since startup
+    config_changes: tokio::sync::watch::Receiver<Config>,
+    /// Family, size and line height the windows are laid out for
+    font: (String, Vec<String>, u32, f32),
+    /// Scale factor the font is rasterized for
+    font_scale: f64,
+    /// Font size Ctrl+0 goes back to
+    base_font_size: u32,
     is_initialized: bool,
     startup_time: Instant,
     frame_count: u64,
     last_fps_time: Instant,
-    modifiers: Modifiers,
     startup_command: Option<String>,
+    startup: StagedStartup,
+    themes: ThemeController,
+    appearance_rx: crossbeam_channel::Receiver<Appearance>,
+    input: InputProcessor,
+    read_only: ReadOnlyPanes,
+    /// Foreground process per PTY and when it was looked up
+    foreground: HashMap<u64, (Instant, Option<String>)>,
+    /// Shell directories as the OS reports them, for panes without OSC 7
+    shell_cwds: HashMap<u64, (Instant, Option<String>)>,
+    /// `ui.title_format`
+    title_format: String,
+    idle: IdleLock,
+    /// Where to record a trace, until the first window opens
+    trace_path: Option<PathBuf>,
+    trace: Option<TraceSession>,
+    paste_guard: PasteGuardConfig,
+    /// A held paste and the window and pane it is for
+    paste_review: Option<(WindowId, u64, PasteReview)>,
+    /// Saves and restores `:session`s of a window's tabs
+    multiplexer: Multiplexer,
+    /// A `:scaffold` plan waiting for `y`, and the pane it was asked in
+    scaffold_plan: Option<(u64, ScaffoldPlan)>,
+    /// The `:calc` result just shown and its pane, until the next key
+    calc_block: Option<(u64, CalcBlock)>,
+    /// Latest `:calc` result, for `insert_calc_result`
+    calc_result: Option<String>,
+    /// `:set guide` columns per PTY
+    guides: GuideOverrides,
+    command_history: CommandHistory,
+    /// Commands waiting for their `D` mark, per PTY
+    command_trackers: HashMap<u64, CommandTracker>,
+    /// The open find-and-run overlay and the window and pane it is for
+    history_overlay: Option<(WindowId, u64, HistoryOverlay)>,
+    watchdog: Arc<parking_lot::Mutex<Watchdog>>,
+    render_heartbeat: Heartbeat,
+    /// Stalls waiting for recovery, once the watchdog task has started
+    stalls: Option<crossbeam_channel::Receiver<Stall>>,
+    readers: HashMap<u64, tokio::task::JoinHandle<()>>,
+    /// Output of every tab, background tabs included, by PTY id
+    tab_outputs: HashMap<u64, TabOutput>,
+    /// Why a tab or pane runs `FALLBACK_SHELL`, shown in it once it starts
+    spawn_errors: HashMap<u64, String>,
+    /// `[commands]` entries and key bindings that could not be loaded,
+    /// shown in the next pane to start
+    command_errors: Vec<String>,
+    /// What Tab completes on the prefix line, refreshed in the background
+    /// each time a line is opened
+    completions: CompletionLists,
+    /// Prompt suggestions per PTY
+    ghost_text: HashMap<u64, GhostText>,
+    /// Models registered from `[[models.list]]`, once started
+    model_host: Option<Arc<ModelHost>>,
+    /// `None` unless `ui.ghost_text` is on and the fast model loaded
+    completion_model: Option<Arc<dyn CompletionModel>>,
+    completion_tx: crossbeam_channel::Sender<CompletionReply>,
+    completion_rx: crossbeam_channel::Receiver<CompletionReply>,
+    /// Prefix-line prompts being answered, by PTY
+    prompts: HashMap<u64, ActivePrompt>,
+    /// Steps of `model <name>` swaps still running, with the PTY that asked
+    model_swaps: Vec<(u64, tokio::sync::mpsc::UnboundedReceiver<HotSwapProgress>)>,
+    /// `batch <file>` commands still answering
+    batches: Vec<RunningBatch>,
+    interrupts: InterruptRouter,
+    prompt_tx: crossbeam_channel::Sender<PromptReply>,
+    prompt_rx: crossbeam_channel::Receiver<PromptReply>,
+    /// Where answers are kept once done; `None` with history off
+    response_log: Option<ResponseLog>,
+    /// Answers restored from disk and given since, newest last
+    responses: ResponseHistory,
+    /// The open response browser and the window it is in
+    response_browser: Option<(WindowId, ResponseBrowser)>,
+    /// The open scrollback search and the window and pane it searches
+    search: Option<(WindowId, u64, SearchBar)>,
+    /// F12 or `:stats`: debug numbers over every window
+    stats_overlay: StatsOverlay,
+    clipboard: Clipboard,
+    pty_events: tokio::sync::broadcast::Receiver<PtyEvent>,
+    on_shell_exit: OnShellExit,
 }
 
 impl FerrotermApp {
-    fn new(startup_command: Option<String>) -> Result<Self, Box<dyn std::error::Error>> {
+    fn new(startup_command: Option<String>, log: LogRing) -> Result<Self, Box<dyn std::error::Error>> {
         let startup_time = Instant::now();
         info!("Starting Ferroterm terminal emulator...");
 
         // 1. Initialize configuration system from platform config directory
         info!("Loading configuration from platform config directory...");
         let config_manager = match ConfigManager::new() {
-            Ok(manager) => {
+            Ok(mut manager) => {
                 let config = manager.get_config();
                 if let Ok(config_path) = ConfigManager::get_config_path() {
                     info!("✓ Configuration loaded from: {}", config_path.display());
@@ -76,6 +294,9 @@ impl FerrotermApp {
                 info!("  Font: {} {}px", config.ui.font_family, config.ui.font_size);
                 info!("  Theme: {}", config.ui.theme);
                 info!("  Window: {}x{} characters", config.ui.window_width, config.ui.window_height);
+                if let Err(e) = manager.start_watching() {
+                    warn!("Config changes need a restart; watching failed: {}", e);
+                }
                 Arc::new(manager)
             }
             Err(e) => {
@@ -88,249 +309,3102 @@ impl FerrotermApp {
             }
         };
 
+        // User-facing strings, before anything shows one
+        let config = config_manager.get_config();
+        let config_changes = config_manager.subscribe();
+        if !config.ui.messages_file.is_empty() {
+            let path = ConfigManager::get_config_path()
+                .ok()
+                .and_then(|config_path| config_path.parent().map(|dir| dir.join(&config.ui.messages_file)))
+                .unwrap_or_else(|| PathBuf::from(&config.ui.messages_file));
+            match Messages::load(&path) {
+                Ok(catalog) => messages::install(catalog),
+                Err(e) => warn!("Failed to load messages from {}: {}", path.display(), e),
+            }
+        }
+
         // 2. Initialize TTY Engine
         info!("Initializing TTY Engine...");
         let tty_engine = Arc::new(TtyEngine::new());
+        let pty_events = tty_engine.subscribe_events();
+
+        // 3. Window registry; each window gets its own terminal state when opened
+        let windows = WindowRegistry::new(config.ui.font_size as f32, config.ui.line_height);
+
+        // 4. Staged startup: draw with the built-in font until the real one
+        // has loaded in the background
+        let mut startup = StagedStartup::new(startup_time, BitmapFont::default());
+        let (family, fallbacks) = (config.ui.font_family.clone(), config.ui.font_fallback.clone());
+        let (size_px, line_height) = (config.ui.font_size as f32, config.ui.line_height);
+        startup.load_font(move || {
+            SystemFont::load(&family, &fallbacks, size_px, line_height).map(|font| Arc::new(font) as Arc<dyn CellFont>)
+        });
+
+        // 5. Follow the OS appearance for `theme = "auto"`
+        let (appearance_tx, appearance_rx) = crossbeam_channel::unbounded();
+        let watcher = appearance::system_provider().map(AppearanceWatcher::new);
+        let themes = ThemeController::new(&config.ui, watcher.as_ref().and_then(|w| w.current()));
+        info!("  Appearance: {} (theme {})", themes.os_appearance().as_str(), themes.active());
+        if let Some(mut watcher) = watcher {
+            std::thread::spawn(move || loop {
+                std::thread::sleep(Duration::from_secs(2));
+                if let Some(appearance) = watcher.poll()
+                    && appearance_tx.send(appearance).is_err()
+                {
+                    break;
+                }
+            });
+        }
+
+        // 6. Input processing; echoable keys are written straight from the
+        // event handler through its fast path
+        let mut command_parser = CommandParser::new(config.keymap.prefix.clone());
+        command_parser.auto_calc = config.agent.auto_calc;
+        let mut command_errors: Vec<String> = command_parser
+            .set_user_commands(&config.commands)
+            .iter()
+            .map(|error| {
+                warn!("{}", error);
+                error.to_string()
+            })
+            .collect();
+        let mut input = InputProcessor::new(
+            Arc::new(RwLock::new(config.keymap.clone())),
+            Arc::new(RwLock::new(command_parser)),
+            config_manager.clone(),
+        );
+        if let Err(e) = pollster::block_on(input.load_keybindings_from_config()) {
+            warn!("Using the default keybindings: {}", e);
+            command_errors.push(e.to_string());
+        }
+        warn_binding_conflicts(&input);
+        let completions = CompletionLists::default();
+        input.set_completer(CompletionSource::Models, Arc::new(completions.models.clone()));
+        input.set_completer(CompletionSource::Sessions, Arc::new(completions.sessions.clone()));
+        input.set_completer(CompletionSource::Paths, Arc::new(completions.paths.clone()));
+
+        // 7. Shell commands recorded from every tab, shared with other instances
+        let command_history = match command_history::log_config(&config.ui, None) {
+            Ok(Some(log_config)) => CommandHistory::open(log_config).unwrap_or_else(|e| {
+                warn!("Command history unavailable: {}", e);
+                CommandHistory::in_memory()
+            }),
+            Ok(None) => CommandHistory::in_memory(),
+            Err(e) => {
+                warn!("Command history unavailable: {}", e);
+                CommandHistory::in_memory()
+            }
+        };
 
-        // 3. Initialize terminal state (default size, will be resized)
-        info!("Initializing terminal state...");
-        let terminal_state = Arc::new(RwLock::new(TerminalState::new(80, 24)));
+        // 8. Watchdog over the render loop and PTY readers
+        let mut watchdog = Watchdog::new(
+            WatchdogConfig {
+                recover: config.ui.watchdog_recovery,
+                ..WatchdogConfig::default()
+            },
+            log,
+        );
+        let render_heartbeat = watchdog.register(Component::RenderLoop, RENDER_STALL);
+        let (completion_tx, completion_rx) = crossbeam_channel::unbounded();
+        let (prompt_tx, prompt_rx) = crossbeam_channel::unbounded();
+        let response_log: Option<ResponseLog> = if config.agent.history_enabled {
+            ResponseLogConfig::from_config(&config.agent, None)
+                .and_then(ResponseLog::open)
+                .inspect_err(|e| warn!("Response history unavailable: {}", e))
+                .ok()
+        } else {
+            None
+        };
+        // The last answers come back so `copy` and the history browser
+        // work across restarts
+        let mut responses = ResponseHistory::new(RESPONSE_HISTORY);
+        let restore = (config.agent.history_restore as usize).min(RESPONSE_HISTORY);
+        match response_log.as_ref().map(|log| log.load_recent(restore)) {
+            Some(Ok(report)) => {
+                if report.damaged_segments > 0 {
+                    warn!(
+                        "Response history: {} segment(s) ended in a damaged record",
+                        report.damaged_segments
+                    );
+                }
+                report.records.into_iter().for_each(|record| responses.add(record));
+            }
+            Some(Err(e)) => warn!("Failed to restore response history: {}", e),
+            None => {}
+        }
 
         Ok(Self {
-            window: None,
+            windows,
+            multiplexer: Multiplexer::new(tty_engine.clone()),
             tty_engine,
             config_manager,
-            terminal_state,
-            renderer: None,
-            main_pty_id: None,
+            config_changes,
+            font: (
+                config.ui.font_family.clone(),
+                config.ui.font_fallback.clone(),
+                config.ui.font_size,
+                config.ui.line_height,
+            ),
+            font_scale: 1.0,
+            base_font_size: config.ui.font_size,
             is_initialized: false,
             startup_time,
             frame_count: 0,
             last_fps_time: startup_time,
-            modifiers: Modifiers::default(),
             startup_command,
+            startup,
+            themes,
+            appearance_rx,
+            input,
+            read_only: ReadOnlyPanes::from_config(&config.ui),
+            foreground: HashMap::new(),
+            shell_cwds: HashMap::new(),
+            title_format: config.ui.title_format.clone(),
+            idle: IdleLock::new(IdleLockConfig::from_config(&config.ui), startup_time),
+            trace_path: None,
+            trace: None,
+            paste_guard: PasteGuardConfig::from_config(&config.ui),
+            paste_review: None,
+            scaffold_plan: None,
+            calc_block: None,
+            calc_result: None,
+            guides: GuideOverrides::default(),
+            command_history,
+            command_trackers: HashMap::new(),
+            history_overlay: None,
+            watchdog: Arc::new(parking_lot::Mutex::new(watchdog)),
+            render_heartbeat,
+            stalls: None,
+            readers: HashMap::new(),
+            tab_outputs: HashMap::new(),
+            spawn_errors: HashMap::new(),
+            command_errors,
+            completions,
+            ghost_text: HashMap::new(),
+            model_host: None,
+            completion_model: None,
+            completion_tx,
+            completion_rx,
+            prompts: HashMap::new(),
+            model_swaps: Vec::new(),
+            batches: Vec::new(),
+            interrupts: InterruptRouter::default(),
+            prompt_tx,
+            prompt_rx,
+            response_log,
+            responses,
+            response_browser: None,
+            search: None,
+            stats_overlay: StatsOverlay::default(),
+            clipboard: Clipboard::new(config.ui.clipboard_max_kb, config.ui.clipboard_osc52),
+            pty_events,
+            on_shell_exit: config.ui.on_shell_exit.parse().unwrap_or_default(),
         })
     }
 
-    async fn initialize_graphics(&mut self, window: Arc<Window>) -> Result<(), Box<dyn std::error::Error>> {
-        info!("Initializing graphics subsystem...");
-        
+    /// Register the configured models, then load the fast model behind
+    /// prompt suggestions when they are on
+    async fn start_models(&mut self) {
+        let config = self.config_manager.get_config();
+        let mut host = ModelHost::new(1, 1, VramBudget::Detect);
+        if let Some(path) = UsageTracker::default_path() {
+            host = host.with_usage_file(path);
+        }
+        let host = Arc::new(host);
+        model_registry::register_models(&host, &config).await;
+        host.set_current_model(config.models.default_model(&config.agent)).await;
+        host.start_hot_swaps();
+        host.start_warm_pool();
+        self.model_host = Some(host.clone());
+
+        if !config.ui.ghost_text {
+            return;
+        }
+        match HostCompletion::load(host, &config, GhostTextConfig::default().budget).await {
+            Ok(model) => self.completion_model = Some(Arc::new(model)),
+            Err(e) => warn!("Prompt suggestions unavailable: {}", e),
+        }
+    }
+
+    /// Ask for, show and drop suggestions at each window's prompt
+    fn update_ghost_text(&mut self, now: Instant) {
+        let Some(model) = self.completion_model.clone() else {
+            return;
+        };
+        for reply in self.completion_rx.try_iter() {
+            let Some(ghost) = self.ghost_text.get_mut(&reply.pane) else {
+                continue;
+            };
+            match reply.text {
+                Ok(text) => {
+                    ghost.deliver(reply.id, &text, now);
+                }
+                Err(e) => {
+                    debug!("Suggestion failed: {}", e);
+                    ghost.failed(reply.id);
+                }
+            }
+        }
+
+        let overlay_open = self.paste_review.is_some()
+            || self.history_overlay.is_some()
+            || self.response_browser.is_some()
+            || self.search.is_some()
+            || self.input.is_prefix_mode();
+        for (_, managed) in self.windows.iter_mut() {
+            let Some(pty_id) = managed.active_pty() else {
+                continue;
+            };
+            let ghost = self
+                .ghost_text
+                .entry(pty_id)
+                .or_insert_with(|| GhostText::new(GhostTextConfig::default(), now));
+            let blocked = overlay_open || self.read_only.is_read_only(pty_id);
+            let terminal = managed.terminal.read();
+            if let Some(job) = ghost.update(terminal.prompt_input().as_deref(), blocked, now) {
+                let context = ghost_text::context_lines(&terminal, ghost.config().context_lines);
+                let budget = ghost.config().budget;
+                ghost_text::spawn_completion(model.clone(), pty_id, job, context, budget, self.completion_tx.clone());
+            }
+            drop(terminal);
+            if let Some(renderer) = managed.resources.renderer.as_mut() {
+                renderer.set_ghost_text(ghost.suggestion().map(str::to_string));
+            }
+        }
+    }
+
+    /// Ask the default model, or the line's `--model`, streaming the answer
+    /// into the pane below the line
+    fn ask(&mut self, id: WindowId, pty_id: u64, command: AgentCommand) {
+        let Some(host) = self.model_host.clone() else {
+            self.show_notice(id, &messages::current().command_unavailable(&command.prompt));
+            return;
+        };
+        let config = self.config_manager.get_config();
+        let context = match self.windows.get(&id) {
+            Some(managed) if config.agent.context_capture && !command.no_context => {
+                let lines = managed.terminal.write().snapshot_lines(config.agent.context_lines as usize);
+                agent_prompt::capture_context(lines)
+            }
+            _ => Vec::new(),
+        };
+        let request = agent_prompt::build_request(&config, &command, context);
+        info!(
+            "Asking {} from pane {} with {} lines of context",
+            request.model_name,
+            pty_id,
+            request.context.as_ref().map_or(0, Vec::len)
+        );
+        self.snap_to_bottom(id);
+        let view = self.markdown_view(pty_id);
+        let prompt = ActivePrompt::start(host, pty_id, request, self.prompt_tx.clone()).with_view(view);
+        // A second question in the same pane replaces the first
+        if let Some(mut previous) = self.prompts.insert(pty_id, prompt) {
+            previous.interrupt();
+            self.finish_prompt(previous, "");
+        }
+    }
+
+    /// Show the answer so far in the inline region of each pane that got
+    /// tokens, under the line that asked
+    fn update_prompts(&mut self) {
+        let replies: Vec<_> = self.prompt_rx.try_iter().collect();
+        let mut changed = HashSet::new();
+        for reply in replies {
+            let Some(prompt) = self.prompts.get_mut(&reply.pane) else {
+                continue;
+            };
+            if !prompt.apply(&reply.event) {
+                changed.insert(reply.pane);
+                continue;
+            }
+            let trailer = match &reply.event {
+                PromptEvent::Failed(e) => messages::current().error_marker(e),
+                _ => String::new(),
+            };
+            if let Some(prompt) = self.prompts.remove(&reply.pane) {
+                self.finish_prompt(prompt, &trailer);
+            }
+        }
+        for pty_id in changed {
+            if let Some(prompt) = self.prompts.get(&pty_id)
+                && let Some(grid) = self.pane_grid(pty_id)
+            {
+                grid.write().set_inline_region(prompt.rows());
+            }
+        }
+        self.update_prompt_status(Instant::now());
+    }
+
+    /// A markdown view sized and styled for a pane's inline region
+    fn markdown_view(&self, pty_id: u64) -> MarkdownStream {
+        let width = self.pane_grid(pty_id).map_or(80, |grid| grid.read().width);
+        let capabilities = self
+            .windows
+            .iter()
+            .find(|(_, managed)| managed.grid(pty_id).is_some())
+            .and_then(|(_, managed)| managed.resources.renderer.as_ref())
+            .map(SimpleRenderer::capabilities)
+            .unwrap_or_default();
+        let ui = self.config_manager.get_config().ui;
+        MarkdownStream::new(width, self.code_highlighter())
+            .with_capabilities(capabilities)
+            .with_theme(self.config_manager.theme(self.themes.active()))
+            .with_degradation(DegradationConfig {
+                frame_budget: Duration::from_millis(ui.answer_budget_ms as u64),
+                consecutive_cycles: ui.answer_degrade_after as u32,
+            })
+    }
+
+    /// The grid of a pane in any window
+    fn pane_grid(&self, pty_id: u64) -> Option<Arc<RwLock<TerminalState>>> {
+        self.windows.iter().find_map(|(_, managed)| managed.grid(pty_id).cloned())
+    }
+
+    /// Refresh the status bar of windows whose active pane is being
+    /// answered, a few times a second at most, and clear the rest
+    fn update_prompt_status(&mut self, now: Instant) {
+        let show = !self.prompts.is_empty() && self.config_manager.get_config().agent.show_status_line;
+        for (_, managed) in self.windows.iter_mut() {
+            let prompt = managed
+                .active_pty()
+                .and_then(|pty_id| self.prompts.get_mut(&pty_id))
+                .filter(|_| show);
+            let status = match prompt {
+                Some(prompt) => {
+                    if !prompt.status_due(now) {
+                        continue;
+                    }
+                    Some(messages::current().generation_status(
+                        prompt.model(),
+                        prompt.elapsed().as_secs_f32(),
+                        prompt.tokens(),
+                        prompt.tokens_per_second(),
+                    ))
+                }
+                None => None,
+            };
+            if let Some(renderer) = managed.resources.renderer.as_mut() {
+                renderer.set_status_line(status);
+            }
+        }
+    }
+
+    /// Move the answer from the inline region into the pane's output, with
+    /// `trailer` and a dimmed summary line under it, and keep the answer
+    fn finish_prompt(&mut self, prompt: ActivePrompt, trailer: &str) {
+        let mut rows = prompt.rows();
+        if !trailer.is_empty() {
+            rows.push(text_row(trailer.trim(), false));
+        }
+        if self.config_manager.get_config().agent.show_status_line {
+            let messages = messages::current();
+            let reason = match prompt.finish_reason() {
+                Some(FinishReason::Stop) => "stop",
+                Some(FinishReason::Length) => "length",
+                Some(FinishReason::Error(_)) => "error",
+                None => "interrupted",
+            };
+            let mut summary = messages.generation_summary(
+                reason,
+                prompt.elapsed().as_secs_f32(),
+                prompt.tokens(),
+                prompt.tokens_per_second(),
+            );
+            if prompt.used_fallback() {
+                summary = format!("{} · {}", summary, messages.generation_fallback(prompt.model()));
+            }
+            rows.push(text_row(&summary, true));
+        }
+        if let Some(grid) = self.pane_grid(prompt.pane) {
+            let mut grid = grid.write();
+            grid.set_inline_region(rows);
+            grid.commit_inline_region();
+        }
+        if prompt.content().is_empty() {
+            return;
+        }
+        let record = prompt.to_record();
+        if let Some(log) = self.response_log.as_mut()
+            && let Err(e) = log.append(&record)
+        {
+            warn!("Failed to save response: {}", e);
+        }
+        self.responses.add(record);
+    }
+
+    /// Type the shown suggestion for the user; false when there is none
+    fn accept_ghost_text(&mut self, pty_id: u64) -> bool {
+        let Some(bytes) = self.ghost_text.get_mut(&pty_id).and_then(GhostText::accept) else {
+            return false;
+        };
+        self.send_input(pty_id, InputSource::Keyboard, &bytes);
+        true
+    }
+
+    /// Start checking heartbeats, once the first frames are due
+    fn start_watchdog(&mut self) {
+        let (stall_tx, stall_rx) = crossbeam_channel::unbounded();
+        self.render_heartbeat.beat();
+        Watchdog::spawn(self.watchdog.clone(), stall_tx);
+        self.stalls = Some(stall_rx);
+    }
+
+    /// Read a PTY's output into its tab's backlog, replacing any reader
+    /// already running for it
+    fn start_reader(&mut self, pty_id: u64) {
+        let Some(tab_output) = self.tab_outputs.get(&pty_id) else {
+            return;
+        };
+        let output = tab_output.backlog.clone();
+        let max_backlog = tab_output.scheduler.config().max_backlog;
+        let heartbeat = self.watchdog.lock().register(Component::PtyReader(pty_id), READER_STALL);
+        let task = spawn_pty_reader(self.tty_engine.clone(), pty_id, output, max_backlog, heartbeat);
+        if let Some(stalled) = self.readers.insert(pty_id, task) {
+            stalled.abort();
+        }
+    }
+
+    fn stop_reader(&mut self, pty_id: u64) {
+        self.watchdog.lock().unregister(&Component::PtyReader(pty_id));
+        if let Some(reader) = self.readers.remove(&pty_id) {
+            reader.abort();
+        }
+    }
+
+    /// Carry out the watchdog's recoveries and show each as an error banner
+    fn handle_stalls(&mut self) {
+        let Some(stalls) = self.stalls.as_ref() else {
+            return;
+        };
+        let stalls: Vec<Stall> = stalls.try_iter().collect();
+        for stall in stalls {
+            match &stall.action {
+                Some(RecoveryAction::Redraw) => {
+                    for (_, managed) in self.windows.iter_mut() {
+                        if let Some(renderer) = managed.resources.renderer.as_mut() {
+                            renderer.reconfigure();
+                        }
+                        managed.resources.window.request_redraw();
+                    }
+                }
+                Some(RecoveryAction::RestartReader(pty_id)) => {
+                    if self.tab_outputs.contains_key(pty_id) {
+                        self.start_reader(*pty_id);
+                    } else {
+                        self.stop_reader(*pty_id);
+                    }
+                }
+                // No model host runs in this process
+                Some(RecoveryAction::RestartWorker(_) | RecoveryAction::LogOnly) | None => {}
+            }
+            if let Some(id) = self.windows.focused() {
+                self.show_notice(id, &stall.banner());
+            }
+        }
+    }
+
+    /// Open a new OS window with its own surface, renderer, terminal grid and
+    /// shell. `record` restores a saved position and size.
+    fn open_window(
+        &mut self,
+        target: &EventLoopWindowTarget<()>,
+        record: Option<&WindowRecord>,
+    ) -> Result<WindowId, Box<dyn std::error::Error>> {
         let config = self.config_manager.get_config();
+
+        let mut builder = WindowBuilder::new()
+            .with_title(format!("Ferroterm v{}", env!("CARGO_PKG_VERSION")))
+            .with_min_inner_size(winit::dpi::LogicalSize::new(400, 200));
+
+        match record {
+            Some(record) => {
+                builder = builder.with_inner_size(winit::dpi::PhysicalSize::new(
+                    record.pixel_width,
+                    record.pixel_height,
+                ));
+                if let Some((x, y)) = record.position {
+                    builder = builder.with_position(winit::dpi::PhysicalPosition::new(x, y));
+                }
+            }
+            None => {
+                // Calculate window size from terminal dimensions
+                let metrics = self.windows.metrics(1.0);
+                let window_width = (config.ui.window_width as f32 * metrics.width) as u32;
+                let window_height = (config.ui.window_height as f32 * metrics.height) as u32;
+                builder = builder.with_inner_size(winit::dpi::LogicalSize::new(window_width, window_height));
+            }
+        }
+
+        let window = Arc::new(builder.build(target)?);
+        self.startup.mark(StartupStage::WindowCreated);
+        let id = window.id();
         let window_size = window.inner_size();
-        
-        // Calculate terminal grid dimensions from font size
-        let font_size = config.ui.font_size as f32;
-        let estimated_char_width = font_size * 0.6; // Rough monospace character width
-        let estimated_char_height = font_size * 1.2 * config.ui.line_height;
-        
-        let term_cols = (window_size.width as f32 / estimated_char_width) as u32;
-        let term_rows = (window_size.height as f32 / estimated_char_height) as u32;
-        
-        info!("Terminal grid: {}x{} ({}x{} pixels)", term_cols, term_rows, window_size.width, window_size.height);
 
-        // Update terminal state size
-        {
-            let mut terminal = self.terminal_state.write();
-            terminal.resize(term_cols, term_rows);
+        let context = WindowContext {
+            window: window.clone(),
+            renderer: None,
+            modifiers: Modifiers::default(),
+            notice_until: None,
+            indicator: None,
+            title: String::new(),
+            preedit: None,
+            pointer: PhysicalPosition::new(0.0, 0.0),
+            selection: None,
+            selecting: false,
+            wheel: WheelAccumulator::default(),
+        };
+        window.set_ime_allowed(true);
+        let managed = self.windows.insert(
+            id,
+            context,
+            (window_size.width, window_size.height),
+            window.scale_factor(),
+        );
+        if let Ok(position) = window.outer_position() {
+            managed.position = Some((position.x, position.y));
+        }
+        let terminal = managed.terminal.clone();
+        let geometry = managed.geometry;
+        let managed_pty = managed.active_pty();
+
+        info!(
+            "Window {:?} grid: {}x{} ({}x{} pixels, scale {})",
+            id, geometry.cols, geometry.rows, window_size.width, window_size.height, geometry.scale_factor
+        );
+        self.follow_scale_factor(geometry.scale_factor);
+
+        match pollster::block_on(SimpleRenderer::new(window.clone(), terminal.clone())) {
+            Ok(mut renderer) => {
+                let ui = UiConfig {
+                    theme: self.themes.active().to_string(),
+                    ..config.ui.clone()
+                };
+                renderer.set_colors(config.theme(&ui.theme));
+                let style = GuideStyle::from_config(&ui);
+                let columns = managed_pty.map(|pty_id| self.guides.columns(pty_id, &style).to_vec());
+                renderer.set_guides(style, columns);
+                renderer.set_color_policy(&ui.theme, render_caps::forced_color_depth(&ui.color_depth));
+                renderer.set_cursor_style(cursor_style(&ui));
+                renderer.set_ligatures(ui.ligatures);
+                if self.startup.has_real_font() {
+                    renderer.set_font(self.startup.font());
+                }
+                if let Some(managed) = self.windows.get_mut(&id) {
+                    managed.resources.renderer = Some(renderer);
+                }
+            }
+            Err(e) => warn!("Failed to initialize renderer for window {:?}: {}", id, e),
+        }
+
+        // Create this window's first tab
+        let pty_id = self.open_tab(id)?;
+        if let Some(record) = record
+            && let Some(managed) = self.windows.get(&id)
+        {
+            self.read_only.restore(record, &managed.tabs);
+        }
+        self.startup.mark(StartupStage::PtySpawned);
+        if let Some(path) = self.trace_path.take() {
+            self.start_trace(&path, id, pty_id, geometry.cols, geometry.rows);
+        }
+
+        Ok(id)
+    }
+
+    /// Add a tab with a fresh shell to a window and switch to it
+    fn open_tab(&mut self, id: WindowId) -> Result<u64, Box<dyn std::error::Error>> {
+        let geometry = self.windows.get(&id).ok_or("window closed")?.geometry;
+        let cwd = self.start_dir(id);
+        let pty_id = self.spawn_shell(&geometry, cwd)?;
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return Ok(pty_id);
+        };
+        let grid = managed.open_tab(pty_id);
+        self.start_pane(pty_id, &grid);
+        // The tab bar may have appeared and taken a row
+        self.resize_window_pty(id);
+        self.show_active_tab(id);
+        Ok(pty_id)
+    }
+
+    /// Split the focused pane and start a shell in the new half
+    fn split_pane(&mut self, id: WindowId, direction: SplitDirection) -> Result<(), Box<dyn std::error::Error>> {
+        let geometry = self.windows.get(&id).ok_or("window closed")?.geometry;
+        let cwd = self.start_dir(id);
+        let pty_id = self.spawn_shell(&geometry, cwd)?;
+        let Some(grid) = self
+            .windows
+            .get_mut(&id)
+            .and_then(|managed| managed.split_pane(pty_id, direction))
+        else {
+            info!("Pane is too small to split");
+            self.release_tab(pty_id);
+            return Ok(());
+        };
+        self.start_pane(pty_id, &grid);
+        self.resize_window_pty(id);
+        self.show_active_tab(id);
+        Ok(())
+    }
+
+    /// Set up a new tab's or pane's grid and start reading its PTY
+    fn start_pane(&mut self, pty_id: u64, grid: &Arc<RwLock<TerminalState>>) {
+        let config = self.config_manager.get_config();
+        {
+            let mut terminal = grid.write();
+            terminal.scrollback = Scrollback::new(ScrollbackConfig::from_config(&config.ui));
+            terminal.set_auto_fold_lines(config.ui.auto_fold_lines);
+            let theme = config.theme(self.themes.active());
+            terminal.set_palette(theme);
+            terminal.set_theme(self.themes.appearance(), theme.background);
+        }
+        self.tab_outputs.insert(
+            pty_id,
+            TabOutput {
+                backlog: Arc::new(OutputBacklog::new()),
+                scheduler: OutputScheduler::new(OutputSchedulerConfig::from_config(&config.ui)),
+            },
+        );
+        self.start_reader(pty_id);
+        self.report_spawn_error(pty_id);
+        self.report_command_errors(pty_id);
+    }
+
+    /// Say in the pane why it runs the fallback shell
+    fn report_spawn_error(&mut self, pty_id: u64) {
+        if let Some(message) = self.spawn_errors.remove(&pty_id) {
+            self.print_local(pty_id, &message);
+        }
+    }
+
+    /// Say in the pane which `[commands]` entries were left out
+    fn report_command_errors(&mut self, pty_id: u64) {
+        if !self.command_errors.is_empty() {
+            let text = std::mem::take(&mut self.command_errors).join("\n");
+            self.print_local(pty_id, &text);
+        }
+    }
+
+    /// Move the focus to the nearest pane in `direction`; keys go to its
+    /// PTY from then on
+    fn focus_pane(&mut self, id: WindowId, direction: PaneDirection) {
+        if self.windows.get_mut(&id).is_some_and(|managed| managed.focus_pane(direction)) {
+            self.show_active_tab(id);
+        }
+    }
+
+    /// Point the renderer at the active tab's grid and redraw the tab bar
+    fn show_active_tab(&mut self, id: WindowId) {
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return;
+        };
+        managed.resources.selection = None;
+        managed.resources.selecting = false;
+        let terminal = managed.terminal.clone();
+        if let Some(renderer) = managed.resources.renderer.as_mut() {
+            renderer.set_selection(None);
+            renderer.set_terminal(terminal);
+        }
+        self.update_panes(id);
+        self.update_guides(id);
+        self.update_tab_bar(id);
+        self.refresh_title(id);
+    }
+
+    /// Column guides for the window's active pane, with its `:set guide`
+    /// columns in place of the configured ones
+    fn update_guides(&mut self, id: WindowId) {
+        let ui = UiConfig {
+            theme: self.themes.active().to_string(),
+            ..self.config_manager.get_config().ui
+        };
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return;
+        };
+        let style = GuideStyle::from_config(&ui);
+        let columns = managed.active_pty().map(|pty_id| self.guides.columns(pty_id, &style).to_vec());
+        if let Some(renderer) = managed.resources.renderer.as_mut() {
+            renderer.set_guides(style, columns);
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    /// `:set guide <columns|off|default>` for one pane
+    fn set_guide(&mut self, id: WindowId, pty_id: u64, value: &str) {
+        match column_guides::parse_guide_setting(value) {
+            Ok(setting) => {
+                self.guides.set(pty_id, setting);
+                self.update_guides(id);
+            }
+            Err(e) => self.show_notice(id, &messages::current().command_error(&e.to_string())),
+        }
+    }
+
+    /// Hand the renderer the active tab's panes and where they sit
+    fn update_panes(&mut self, id: WindowId) {
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return;
+        };
+        let focused = managed.active_pty();
+        let panes = managed
+            .pane_layout()
+            .map(|layout| {
+                layout
+                    .ordered_panes()
+                    .iter()
+                    .filter_map(|pane| {
+                        Some(PaneView {
+                            terminal: managed.grid(pane.pty_id)?.clone(),
+                            column: pane.layout.x,
+                            row: pane.layout.y,
+                            focused: Some(pane.pty_id) == focused,
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let area = managed.grid_size();
+        if let Some(renderer) = managed.resources.renderer.as_mut() {
+            renderer.set_panes(panes, area);
+        }
+        managed.resources.window.request_redraw();
+    }
+
+    fn select_tab(&mut self, id: WindowId, action: &InputAction) {
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return;
+        };
+        let switched = match action {
+            InputAction::NextTab => managed.cycle_tab(true),
+            InputAction::PrevTab => managed.cycle_tab(false),
+            InputAction::SwitchTab(index) => *index != managed.active_tab && managed.select_tab(*index),
+            _ => false,
+        };
+        if switched {
+            self.show_active_tab(id);
+        }
+    }
+
+    /// Close one tab, or the whole window with its last tab. A tab with a
+    /// running job needs a second request, as a window does.
+    fn close_tab(&mut self, id: WindowId, pty_id: u64, target: &EventLoopWindowTarget<()>) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+        if managed.tabs.len() <= 1 {
+            self.close_window(id, target);
+            return;
+        }
+        let panes = managed.tab_panes(pty_id);
+        let has_running_jobs = panes
+            .iter()
+            .any(|pane| self.tty_engine.has_foreground_job(*pane).unwrap_or(false));
+        match self.windows.request_close(&id, has_running_jobs) {
+            Ok(CloseDecision::ConfirmationRequired) => {
+                warn!("Tab {} has running processes; close again to confirm", pty_id);
+                let prompt = messages::current().close_confirmation();
+                self.show_notice(id, &prompt);
+            }
+            Ok(CloseDecision::Close { .. }) => {
+                if let Some(managed) = self.windows.get_mut(&id) {
+                    managed.close_tab(pty_id);
+                }
+                for pane in panes {
+                    self.release_tab(pane);
+                }
+                self.resize_window_pty(id);
+                self.show_active_tab(id);
+            }
+            Err(e) => error!("Failed to close tab {}: {}", pty_id, e),
+        }
+    }
+
+    /// Close the pane whose shell ended; the last pane of a tab closes
+    /// the tab
+    fn close_pane(&mut self, id: WindowId, pty_id: u64, target: &EventLoopWindowTarget<()>) {
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return;
+        };
+        let Some(tab) = managed.tab_index(pty_id).map(|index| managed.tabs[index]) else {
+            return;
+        };
+        if managed.tab_panes(tab).len() <= 1 {
+            self.close_tab(id, pty_id, target);
+            return;
+        }
+        managed.close_pane(pty_id);
+        self.release_tab(pty_id);
+        self.resize_window_pty(id);
+        self.show_active_tab(id);
+    }
+
+    /// Stop reading a closed tab's PTY and end its shell
+    fn release_tab(&mut self, pty_id: u64) {
+        self.read_only.forget(pty_id);
+        self.guides.remove_pane(pty_id);
+        self.foreground.remove(&pty_id);
+        self.shell_cwds.remove(&pty_id);
+        self.ghost_text.remove(&pty_id);
+        self.command_trackers.remove(&pty_id);
+        self.tab_outputs.remove(&pty_id);
+        self.stop_reader(pty_id);
+        let tty_engine = self.tty_engine.clone();
+        tokio::spawn(async move {
+            if let Err(e) = tty_engine.destroy_pty(pty_id).await {
+                error!("Failed to destroy PTY: {}", e);
+            }
+        });
+    }
+
+    /// Tab titles for the tab bar, which only shows with two or more tabs
+    fn update_tab_bar(&mut self, id: WindowId) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+        let tabs = managed.tabs.clone();
+        let active = managed.active_tab;
+        let tab_bar = if tabs.len() > 1 {
+            let titles = tabs.iter().map(|&pty_id| self.tab_title(id, pty_id)).collect();
+            Some((titles, active))
+        } else {
+            None
+        };
+        if let Some(managed) = self.windows.get_mut(&id)
+            && let Some(renderer) = managed.resources.renderer.as_mut()
+        {
+            renderer.set_tab_bar(tab_bar);
+        }
+    }
+
+    /// `ui.title_format` for one tab or pane
+    fn tab_title(&mut self, id: WindowId, pty_id: u64) -> String {
+        let (set_title, reported_cwd) = self
+            .windows
+            .get(&id)
+            .and_then(|managed| managed.grid(pty_id))
+            .map(|grid| {
+                let terminal = grid.read();
+                (terminal.title.clone(), terminal.working_directory.clone())
+            })
+            .unwrap_or_default();
+        let process = self.foreground_process(pty_id);
+        let cwd = reported_cwd.or_else(|| self.shell_cwd(pty_id));
+        let fields = TitleFields {
+            title: set_title.or_else(|| process.clone()).unwrap_or_else(|| "shell".to_string()),
+            cwd: cwd.map(|cwd| title::abbreviate_home(&cwd, dirs::home_dir().as_deref())),
+            process,
+        };
+        title::render(&self.title_format, &fields)
+    }
+
+    /// The shell's directory as the OS sees it, asked at most every
+    /// `FOREGROUND_REFRESH`
+    fn shell_cwd(&mut self, pty_id: u64) -> Option<String> {
+        let now = Instant::now();
+        match self.shell_cwds.get(&pty_id) {
+            Some((checked, cwd)) if now.duration_since(*checked) < FOREGROUND_REFRESH => cwd.clone(),
+            _ => {
+                let cwd = self.tty_engine.shell_cwd(pty_id).unwrap_or(None);
+                self.shell_cwds.insert(pty_id, (now, cwd.clone()));
+                cwd
+            }
+        }
+    }
+
+    /// Name of the process in the foreground of a PTY, looked up at most
+    /// every `FOREGROUND_REFRESH`
+    fn foreground_process(&mut self, pty_id: u64) -> Option<String> {
+        let now = Instant::now();
+        match self.foreground.get(&pty_id) {
+            Some((checked, process)) if now.duration_since(*checked) < FOREGROUND_REFRESH => process.clone(),
+            _ => {
+                let process = self.tty_engine.foreground_process(pty_id).unwrap_or(None);
+                self.foreground.insert(pty_id, (now, process.clone()));
+                process
+            }
+        }
+    }
+
+    fn start_trace(&mut self, path: &std::path::Path, id: WindowId, pty_id: u64, cols: u32, rows: u32) {
+        let config = self.config_manager.get_config();
+        let metrics = self.windows.metrics(1.0);
+        let header = TraceHeader {
+            font_family: config.ui.font_family.clone(),
+            font_size: config.ui.font_size,
+            cell_width: metrics.width.ceil() as u32,
+            cell_height: metrics.height.ceil() as u32,
+            ..TraceHeader::new(cols, rows)
+        };
+        match TraceRecorder::create(path, &header) {
+            Ok(recorder) => {
+                info!("Recording trace to {}", path.display());
+                self.trace = Some(TraceSession {
+                    window: id,
+                    pty_id,
+                    recorder: Some(recorder),
+                });
+            }
+            Err(e) => error!("Failed to start trace {}: {}", path.display(), e),
+        }
+    }
+
+    /// Bytes about to be written to a PTY, if it is being traced
+    fn trace_input(&mut self, pty_id: u64, data: &[u8]) {
+        if let Some(session) = self.trace.as_mut()
+            && session.pty_id == pty_id
+            && let Some(recorder) = session.recorder.as_mut()
+            && let Err(e) = recorder.input(data)
+        {
+            error!("Trace recording stopped: {}", e);
+            session.recorder = None;
+        }
+    }
+
+    fn handle_window_event(
+        &mut self,
+        id: WindowId,
+        event: WindowEvent,
+        target: &EventLoopWindowTarget<()>,
+    ) {
+        if !self.windows.contains(&id) {
+            return;
+        }
+
+        match event {
+            WindowEvent::CloseRequested => {
+                info!("Close requested for window {:?}", id);
+                self.close_window(id, target);
+            }
+            WindowEvent::Resized(new_size) => {
+                debug!("Window {:?} resized to {:?}", id, new_size);
+                self.handle_window_resize(id, new_size);
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                debug!("Window {:?} scale factor changed to {}", id, scale_factor);
+                self.follow_scale_factor(scale_factor);
+                if self.windows.set_scale_factor(&id, scale_factor).is_some() {
+                    self.resize_window_pty(id);
+                    self.update_panes(id);
+                }
+            }
+            WindowEvent::Moved(position) => {
+                self.windows.set_position(&id, position.x, position.y);
+            }
+            WindowEvent::Focused(focused) => {
+                if focused {
+                    self.windows.set_focused(id);
+                }
+                if let Some(managed) = self.windows.get_mut(&id)
+                    && let Some(renderer) = managed.resources.renderer.as_mut()
+                {
+                    renderer.set_window_focused(focused);
+                    managed.resources.window.request_redraw();
+                }
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let Some(action) = self.handle_key_input(id, event) {
+                    self.handle_window_action(id, action, target);
+                }
+            }
+            WindowEvent::Ime(ime) => self.handle_ime(id, ime),
+            WindowEvent::CursorMoved { position, .. } => self.handle_pointer_moved(id, position),
+            WindowEvent::MouseInput { state, button: MouseButton::Left, .. } => {
+                self.handle_left_button(id, state);
+            }
+            WindowEvent::MouseWheel { delta, .. } => self.handle_wheel(id, delta),
+            WindowEvent::ModifiersChanged(modifiers) => {
+                if let Some(managed) = self.windows.get_mut(&id) {
+                    managed.resources.modifiers = modifiers;
+                }
+            }
+            WindowEvent::ThemeChanged(theme) => {
+                self.set_os_appearance(theme.into());
+            }
+            WindowEvent::RedrawRequested => {
+                while let Ok(appearance) = self.appearance_rx.try_recv() {
+                    self.set_os_appearance(appearance);
+                }
+                if let Some(managed) = self.windows.get(&id)
+                    && managed.resources.notice_until.is_some_and(|until| Instant::now() >= until)
+                {
+                    self.refresh_title(id);
+                }
+                if self.process_output(id).is_none_or(|report| report.render) {
+                    self.render_frame(id);
+                }
+
+                // Request next frame for continuous rendering; a blank
+                // screen only redraws when the lock notice changes
+                if !self.idle.is_blanked()
+                    && let Some(managed) = self.windows.get(&id)
+                {
+                    managed.resources.window.request_redraw();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Apply a config file edit: keybindings, fonts and the grids they size
+    fn apply_config_changes(&mut self) {
+        if !self.config_changes.has_changed().unwrap_or(false) {
+            return;
+        }
+        let config = self.config_changes.borrow_and_update().clone();
+        info!("Applying reloaded configuration");
+
+        match pollster::block_on(self.input.load_keybindings_from_config()) {
+            Ok(()) => warn_binding_conflicts(&self.input),
+            Err(e) => {
+                warn!("Keeping the previous keybindings: {}", e);
+                self.command_errors.push(e.to_string());
+            }
+        }
+        for error in self.input.load_user_commands(&config.commands) {
+            warn!("{}", error);
+            self.command_errors.push(error.to_string());
+        }
+        if let Some(pty_id) = self.windows.focused().and_then(|id| self.windows.get(&id)?.active_pty()) {
+            self.report_command_errors(pty_id);
+        }
+        self.paste_guard = PasteGuardConfig::from_config(&config.ui);
+        self.on_shell_exit = config.ui.on_shell_exit.parse().unwrap_or_default();
+        self.title_format = config.ui.title_format.clone();
+        for (_, managed) in self.windows.iter_mut() {
+            if let Some(renderer) = managed.resources.renderer.as_mut() {
+                renderer.set_cursor_style(cursor_style(&config.ui));
+                renderer.set_ligatures(config.ui.ligatures);
+            }
+            for pty_id in managed.ptys() {
+                if let Some(grid) = managed.grid(pty_id) {
+                    grid.write().set_auto_fold_lines(config.ui.auto_fold_lines);
+                }
+            }
+        }
+        // Edited [theme] colors repaint like a theme switch
+        let theme = self.themes.active().to_string();
+        self.apply_theme(&theme);
+
+        let font = (
+            config.ui.font_family.clone(),
+            config.ui.font_fallback.clone(),
+            config.ui.font_size,
+            config.ui.line_height,
+        );
+        if font == self.font {
+            return;
+        }
+        self.font = font;
+        self.windows.set_font_metrics(config.ui.font_size as f32, config.ui.line_height);
+        self.load_font(&config.ui);
+        self.refit_windows();
+    }
+
+    /// Load the configured font in the background, rasterized for the
+    /// display scale so glyphs stay sharp on HiDPI screens
+    fn load_font(&mut self, ui: &UiConfig) {
+        let (family, fallbacks) = (ui.font_family.clone(), ui.font_fallback.clone());
+        let (size_px, line_height) = (ui.font_size as f32 * self.font_scale as f32, ui.line_height);
+        self.startup.load_font(move || {
+            SystemFont::load(&family, &fallbacks, size_px, line_height).map(|font| Arc::new(font) as Arc<dyn CellFont>)
+        });
+    }
+
+    /// Rasterize the font for the scale factor of the window last opened
+    /// or moved. Windows on other displays scale its glyphs to their cells.
+    fn follow_scale_factor(&mut self, scale_factor: f64) {
+        if scale_factor != self.font_scale {
+            self.font_scale = scale_factor;
+            let ui = self.config_manager.get_config().ui;
+            self.load_font(&ui);
+        }
+    }
+
+    /// Resize every window's surface, panes and PTYs to the current cell size
+    fn refit_windows(&mut self) {
+        for id in self.windows.ids().to_vec() {
+            if let Some(managed) = self.windows.get_mut(&id)
+                && let Some(ref mut renderer) = managed.resources.renderer
+            {
+                renderer.resize(managed.resources.window.inner_size());
+            }
+            self.resize_window_pty(id);
+            self.update_panes(id);
+        }
+    }
+
+    fn set_os_appearance(&mut self, appearance: Appearance) {
+        if let Some(theme) = self.themes.set_os_appearance(appearance) {
+            info!("Appearance changed to {}, switching to theme {}", appearance.as_str(), theme);
+            self.apply_theme(&theme);
+        }
+    }
+
+    /// `:theme <name>`; a manual choice holds until `:theme auto`
+    fn select_theme(&mut self, name: &str) {
+        if let Some(theme) = self.themes.select(name) {
+            self.apply_theme(&theme);
+        }
+    }
+
+    /// Repaint every window with `theme` and tell each shell's applications
+    fn apply_theme(&mut self, theme: &str) {
+        let ui = UiConfig {
+            theme: theme.to_string(),
+            ..self.config_manager.get_config().ui
+        };
+        let colors = self.config_manager.theme(theme);
+        let appearance = self.themes.appearance();
+        let mut replies = Vec::new();
+
+        for (_, managed) in self.windows.iter_mut() {
+            let active = managed.active_pty();
+            if let Some(renderer) = managed.resources.renderer.as_mut() {
+                renderer.set_colors(colors);
+                let style = GuideStyle::from_config(&ui);
+                let columns = active.map(|pty_id| self.guides.columns(pty_id, &style).to_vec());
+                renderer.set_guides(style, columns);
+                renderer.set_color_policy(theme, render_caps::forced_color_depth(&ui.color_depth));
+            }
+            for pty_id in managed.ptys() {
+                let Some(grid) = managed.grid(pty_id) else {
+                    continue;
+                };
+                let mut terminal = grid.write();
+                terminal.set_palette(colors);
+                terminal.set_theme(appearance, colors.background);
+                ferroterm::startup::mark_all_dirty(&mut terminal);
+                let reply = terminal.take_replies();
+                if !reply.is_empty() {
+                    replies.push((pty_id, reply));
+                }
+            }
+        }
+        for (pty_id, reply) in replies {
+            self.send_to_pty(pty_id, &reply);
+        }
+    }
+
+    /// Where a new tab or pane in window `id` starts: with
+    /// `shell.inherit_cwd`, the focused pane's directory as the shell last
+    /// reported it (OSC 7) or as the OS sees it; otherwise `shell.cwd`
+    fn start_dir(&self, id: WindowId) -> Option<PathBuf> {
+        let shell = self.config_manager.get_config().shell;
+        let inherited = shell
+            .inherit_cwd
+            .then(|| {
+                let managed = self.windows.get(&id)?;
+                let pty_id = managed.active_pty()?;
+                let reported = managed.grid(pty_id).and_then(|grid| grid.read().working_directory.clone());
+                reported.or_else(|| self.tty_engine.shell_cwd(pty_id).ok().flatten())
+            })
+            .flatten();
+        inherited.map(PathBuf::from).or_else(|| shell.start_dir())
+    }
+
+    /// Start the configured shell in a PTY sized to `geometry`. A shell
+    /// that cannot be run is replaced by `FALLBACK_SHELL`, and the pane
+    /// says why once it starts.
+    fn spawn_shell(&mut self, geometry: &WindowGeometry, cwd: Option<PathBuf>) -> Result<u64, Box<dyn std::error::Error>> {
+        let pty_config = pty_config(&self.config_manager.get_config().shell, geometry, cwd);
+        match pollster::block_on(self.tty_engine.create_pty(pty_config.clone())) {
+            Ok(pty_id) => Ok(pty_id),
+            Err(e @ TtyError::ShellNotFound { .. }) => {
+                error!("Failed to start shell: {}", e);
+                let fallback = PtyConfig {
+                    shell: FALLBACK_SHELL.to_string(),
+                    args: Vec::new(),
+                    login_shell: false,
+                    ..pty_config
+                };
+                let pty_id = pollster::block_on(self.tty_engine.create_pty(fallback))?;
+                self.spawn_errors
+                    .insert(pty_id, messages::current().shell_failed(&e.to_string(), FALLBACK_SHELL));
+                Ok(pty_id)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Close, restart or hold tabs whose shell has exited, per
+    /// `ui.on_shell_exit`
+    fn handle_pty_events(&mut self, target: &EventLoopWindowTarget<()>) {
+        loop {
+            let (pty_id, code) = match self.pty_events.try_recv() {
+                Ok(PtyEvent::Exited { pty_id, code }) => (pty_id, code),
+                Err(tokio::sync::broadcast::error::TryRecvError::Lagged(missed)) => {
+                    warn!("Missed {} PTY events", missed);
+                    continue;
+                }
+                Err(_) => return,
+            };
+            let Some(id) = self
+                .windows
+                .iter()
+                .find(|(_, managed)| managed.tab_index(pty_id).is_some())
+                .map(|(id, _)| *id)
+            else {
+                continue;
+            };
+            info!("Shell in PTY {} exited with {:?}", pty_id, code);
+
+            match self.on_shell_exit {
+                OnShellExit::Close => self.close_pane(id, pty_id, target),
+                OnShellExit::Restart => {
+                    let Some(geometry) = self.windows.get(&id).map(|managed| managed.geometry) else {
+                        continue;
+                    };
+                    // Output still waiting to be parsed is shown before the new prompt
+                    let tab_output = self.tab_outputs.remove(&pty_id);
+                    self.release_tab(pty_id);
+                    let cwd = self.config_manager.get_config().shell.start_dir();
+                    let new_id = match self.spawn_shell(&geometry, cwd) {
+                        Ok(new_id) => new_id,
+                        Err(e) => {
+                            error!("Failed to restart shell: {}", e);
+                            continue;
+                        }
+                    };
+                    if let Some(managed) = self.windows.get_mut(&id) {
+                        managed.replace_tab(pty_id, new_id);
+                    }
+                    if let Some(tab_output) = tab_output {
+                        self.tab_outputs.insert(new_id, tab_output);
+                    }
+                    self.start_reader(new_id);
+                    self.report_spawn_error(new_id);
+                    self.resize_window_pty(id);
+                    self.refresh_title(id);
+                }
+                OnShellExit::Hold => {
+                    // The tab stays until closed, which releases the PTY
+                    self.stop_reader(pty_id);
+                    let notice = match code {
+                        Some(code) => messages::current().process_exited(code),
+                        None => messages::current().process_killed(),
+                    };
+                    if let Some(tab_output) = self.tab_outputs.get(&pty_id) {
+                        // Behind any output still waiting to be parsed
+                        tab_output.backlog.push(format!("\r\n{}\r\n", notice).as_bytes());
+                    }
+                    if let Some(managed) = self.windows.get(&id) {
+                        managed.resources.window.request_redraw();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Close one window, tearing down only its resources. Closing the last
+    /// window shuts the application down.
+    fn close_window(&mut self, id: WindowId, target: &EventLoopWindowTarget<()>) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+
+        let has_running_jobs = managed
+            .ptys()
+            .iter()
+            .any(|pty_id| self.tty_engine.has_foreground_job(*pty_id).unwrap_or(false));
+
+        // Record the layout before the last window disappears
+        let mut layout = self.windows.session_layout();
+        self.read_only.record(&mut layout);
+
+        match self.windows.request_close(&id, has_running_jobs) {
+            Ok(CloseDecision::ConfirmationRequired) => {
+                warn!("Window {:?} has running processes; close again to confirm", id);
+                if let Some(managed) = self.windows.get(&id) {
+                    let prompt = messages::current().close_confirmation();
+                    managed.resources.window.set_title(&format!("Ferroterm — {}", prompt));
+                }
+            }
+            Ok(CloseDecision::Close { last_window }) => {
+                if let Some(managed) = self.windows.remove(&id) {
+                    for pty_id in managed.ptys() {
+                        self.release_tab(pty_id);
+                    }
+                }
+
+                if last_window {
+                    if let Some(path) = SessionLayout::default_path()
+                        && let Err(e) = layout.save(&path)
+                    {
+                        warn!("Failed to save session layout: {}", e);
+                    }
+                    info!("Last window closed, shutting down");
+                    target.exit();
+                }
+            }
+            Err(e) => error!("Failed to close window {:?}: {}", id, e),
+        }
+    }
+
+    fn handle_window_resize(&mut self, id: WindowId, new_size: winit::dpi::PhysicalSize<u32>) {
+        if let Some(managed) = self.windows.get_mut(&id)
+            && let Some(ref mut renderer) = managed.resources.renderer
+        {
+            renderer.resize(new_size);
+        }
+
+        if self.windows.resize(&id, new_size.width, new_size.height).is_some()
+            && let Some((cols, rows)) = self.windows.get(&id).map(|managed| managed.grid_size())
+        {
+            debug!("Resizing terminal: {}x{}", cols, rows);
+            if let Some(session) = self.trace.as_mut().filter(|session| session.window == id)
+                && let Some(recorder) = session.recorder.as_mut()
+                && let Err(e) = recorder.resize(cols, rows)
+            {
+                error!("Trace recording stopped: {}", e);
+                session.recorder = None;
+            }
+            self.resize_window_pty(id);
+            self.update_panes(id);
+        }
+    }
+
+    /// Match every tab's and pane's PTY to the size of its grid
+    fn resize_window_pty(&self, id: WindowId) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+        for (pty_id, (cols, rows)) in managed.pty_sizes() {
+            if let Err(e) = self.tty_engine.resize_pty(pty_id, rows as u16, cols as u16) {
+                error!("Failed to resize PTY: {}", e);
+            }
+        }
+    }
+
+    /// Route a key press to the window's active PTY. Application-level actions
+    /// (such as opening a window) are returned to the caller.
+    fn handle_key_input(&mut self, id: WindowId, key_event: WinitKeyEvent) -> Option<InputAction> {
+        if !self.is_initialized {
+            return None;
+        }
+        let modifiers = self.windows.get(&id)?.resources.modifiers.state();
+
+        // The key that wakes a blanked terminal, and the unlock word, never
+        // reach the PTY
+        if key_event.state == ElementState::Pressed {
+            if self.idle.is_blanked() {
+                let key = self.convert_key_event(key_event, modifiers).map(|event| event.key);
+                self.idle.key(key, Instant::now());
+                self.update_blank();
+                return None;
+            }
+            self.idle.note_input(Instant::now());
+            if let Some(renderer) = self.windows.get_mut(&id)?.resources.renderer.as_mut() {
+                renderer.restart_blink();
+            }
+        }
+
+        // A held paste takes every key until it is pasted, edited or dropped
+        if self.paste_review.is_some() {
+            if let Some(event) = self.convert_key_event(key_event, modifiers) {
+                self.review_paste_key(&event.key);
+            }
+            return None;
+        }
+
+        // A scaffold plan takes the next key: y writes it, anything else drops it
+        if self.scaffold_plan.is_some() {
+            if let Some(event) = self.convert_key_event(key_event, modifiers) {
+                self.scaffold_key(&event.key);
+            }
+            return None;
+        }
+
+        // The key after a calc result may copy or type it; any other key
+        // dismisses the result and carries on as usual
+        if key_event.state == ElementState::Pressed
+            && let Some((calc_pty, block)) = self.calc_block.take()
+            && let Some(event) = self.convert_key_event(key_event.clone(), modifiers)
+            && event.modifiers.is_empty()
+        {
+            match block.key(&event.key) {
+                CalcKeyOutcome::Copy(text) => {
+                    self.copy_text(id, &text);
+                    return None;
+                }
+                CalcKeyOutcome::Insert(text) => {
+                    self.snap_to_bottom(id);
+                    self.send_input(calc_pty, InputSource::Keyboard, text.as_bytes());
+                    return None;
+                }
+                CalcKeyOutcome::Dismiss => return None,
+                CalcKeyOutcome::Ignored => {}
+            }
+        }
+
+        if self.history_overlay.is_some() {
+            if let Some(event) = self.convert_key_event(key_event, modifiers) {
+                self.history_overlay_key(&event);
+            }
+            return None;
+        }
+
+        if self.response_browser.is_some() {
+            if let Some(event) = self.convert_key_event(key_event, modifiers) {
+                self.response_browser_key(&event);
+            }
+            return None;
+        }
+
+        if self.search.is_some() {
+            if let Some(event) = self.convert_key_event(key_event, modifiers) {
+                self.search_key(&event);
+            }
+            return None;
+        }
+
+        // Keys belong to the input method while it is composing
+        if self.windows.get(&id)?.resources.preedit.is_some() {
+            return None;
+        }
+
+        // Check for About panel shortcut (Cmd+A on macOS)
+        #[cfg(target_os = "macos")]
+        {
+            if key_event.state == ElementState::Pressed
+                && modifiers.super_key()
+                && key_event.logical_key == WinitKey::Character("a".into()) {
+                show_about_panel();
+                return None;
+            }
+        }
+
+        if key_event.state == ElementState::Pressed
+            && modifiers.control_key()
+            && modifiers.shift_key()
+            && let WinitKey::Character(ref s) = key_event.logical_key
+            && s.eq_ignore_ascii_case("r")
+        {
+            let managed = self.windows.get(&id)?;
+            let pty_id = managed.active_pty()?;
+            let pane_index = managed.active_tab + 1;
+            self.read_only.toggle_pressed(pty_id, pane_index, key_event.repeat, Instant::now());
+            self.show_read_only_notices();
+            return None;
+        }
+
+        // ^C jumps ahead of queued input so a flood can be stopped at once,
+        // unless it may finish a key sequence
+        if key_event.state == ElementState::Pressed
+            && self.input.sequence_deadline().is_none()
+            && modifiers.control_key()
+            && !modifiers.shift_key()
+            && let WinitKey::Character(ref s) = key_event.logical_key
+            && s.eq_ignore_ascii_case("c")
+        {
+            let pty_id = self.windows.get(&id)?.active_pty()?;
+            self.snap_to_bottom(id);
+            self.interrupt(pty_id);
+            return None;
+        }
+
+        // Convert winit key event to our internal format
+        let our_key_event = self.convert_key_event(key_event, modifiers)?;
+
+        if let Some(pty_id) = self.windows.get(&id).and_then(|w| w.active_pty()) {
+            // Right or End takes a suggestion; otherwise they reach the shell
+            if matches!(our_key_event.key, Key::Right | Key::End)
+                && modifiers.is_empty()
+                && self.accept_ghost_text(pty_id)
+            {
+                return None;
+            }
+            self.update_terminal_context(id, pty_id);
+            // Dead keys and compose sequences can produce several characters
+            let composed = our_key_event.text.as_ref().is_some_and(|text| text.chars().count() > 1);
+            if !composed
+                && let Some(bytes) = self.input.fast_path(our_key_event.key, &our_key_event.modifiers)
+            {
+                self.snap_to_bottom(id);
+                self.send_input(pty_id, InputSource::Keyboard, bytes.as_bytes());
+                return None;
+            }
+            // Everything else goes through bindings and prefix mode
+            if let Err(e) = pollster::block_on(self.input.process_key_event(our_key_event)) {
+                warn!("Key not processed: {}", e);
+            }
+            self.show_command_line(id);
+            return self.drain_input_actions(id, pty_id);
+        }
+        None
+    }
+
+    /// Carry out what the input processor queued; the last action that
+    /// needs the event loop is handed back
+    fn drain_input_actions(&mut self, id: WindowId, pty_id: u64) -> Option<InputAction> {
+        let mut forwarded = None;
+        while let Some(action) = self.input.try_receive_action() {
+            forwarded = self.handle_input_action(id, pty_id, action).or(forwarded);
+        }
+        forwarded
+    }
+
+    /// Actions from keys that open or close windows and tabs
+    fn handle_window_action(&mut self, id: WindowId, action: InputAction, target: &EventLoopWindowTarget<()>) {
+        match action {
+            InputAction::NewWindow => {
+                if let Err(e) = self.open_window(target, None) {
+                    error!("Failed to open new window: {}", e);
+                }
+            }
+            InputAction::CloseWindow => self.close_window(id, target),
+            InputAction::CloseTab => {
+                if let Some(pty_id) = self.windows.get(&id).and_then(|managed| managed.active_pty()) {
+                    self.close_tab(id, pty_id, target);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Once a held key sequence times out, its keys take effect in the
+    /// focused pane as if no sequence had started with them
+    fn flush_key_sequence(&mut self, now: Instant, target: &EventLoopWindowTarget<()>) {
+        let Some(id) = self.windows.focused() else {
+            return;
+        };
+        let Some(pty_id) = self.windows.get(&id).and_then(|managed| managed.active_pty()) else {
+            return;
+        };
+        match pollster::block_on(self.input.flush_expired_sequence(now)) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => warn!("Key sequence not processed: {}", e),
+        }
+        self.show_command_line(id);
+        if let Some(action) = self.drain_input_actions(id, pty_id) {
+            self.handle_window_action(id, action, target);
+        }
+    }
+
+    /// Draw the prefix command line, with its cursor, over the bottom row
+    /// while it is being typed, and Tab's candidates under it
+    fn show_command_line(&mut self, id: WindowId) {
+        let line = self.input.command_line();
+        if line.is_some() && !self.completions.open {
+            self.refresh_completions(id);
+        }
+        self.completions.open = line.is_some();
+
+        let width = self.windows.get(&id).map_or(0, |managed| managed.terminal.read().width);
+        let rows = line
+            .map(|line| std::iter::once(line.row(&self.input.prefix(), width)).chain(line.candidate_row(width)).collect())
+            .unwrap_or_default();
+        if let Some(managed) = self.windows.get_mut(&id)
+            && let Some(renderer) = managed.resources.renderer.as_mut()
+        {
+            renderer.set_command_line(rows);
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    /// Reload the model and session names off the event loop, and point
+    /// path completion at the active pane's directory
+    fn refresh_completions(&mut self, id: WindowId) {
+        let cwd = self
+            .windows
+            .get(&id)
+            .and_then(|managed| managed.active_pty())
+            .and_then(|pty_id| self.shell_cwd(pty_id));
+        self.completions.paths.set_base(cwd.map(PathBuf::from));
+
+        let models = self.completions.models.clone();
+        let sessions = self.completions.sessions.clone();
+        let host = self.model_host.clone();
+        std::thread::spawn(move || {
+            if let Some(host) = host {
+                let mut names: Vec<String> =
+                    pollster::block_on(host.model_states()).into_iter().map(|state| state.name).collect();
+                names.sort();
+                models.set(names);
+            }
+            match multiplexer::saved_session_names(&MultiplexerConfig::default().session_directory) {
+                Ok(names) => sessions.set(names),
+                Err(e) => debug!("Session names unavailable: {}", e),
+            }
+        });
+    }
+
+    /// Carry out an action from the input processor. Actions that need the
+    /// event loop are handed back.
+    fn handle_input_action(&mut self, id: WindowId, pty_id: u64, action: InputAction) -> Option<InputAction> {
+        if let Some(bytes) = control_bytes(&action) {
+            self.snap_to_bottom(id);
+            self.send_input(pty_id, InputSource::Keyboard, bytes);
+            return None;
+        }
+        match action {
+            InputAction::SendToTerminal(text) => {
+                self.snap_to_bottom(id);
+                self.send_input(pty_id, InputSource::Keyboard, text.as_bytes());
+            }
+            InputAction::Interrupt => {
+                self.snap_to_bottom(id);
+                self.interrupt(pty_id);
+            }
+            InputAction::ExecuteParsedCommand(parsed) => match parsed.command {
+                // Prefixed by escape, or not a command after all: the shell runs it
+                Command::Terminal(line) => {
+                    self.snap_to_bottom(id);
+                    self.send_input(pty_id, InputSource::Keyboard, format!("{}\r", line).as_bytes());
+                }
+                Command::SetConfig { path, value, save } => self.set_config(id, pty_id, &path, &value, save),
+                // `guide` is the only pane option the parser accepts
+                Command::Set(_, value) => self.set_guide(id, pty_id, &value),
+                Command::ShowConfig { path, diff } => {
+                    let text = self.config_manager.show_config(path.as_deref(), diff);
+                    self.print_local(pty_id, &text);
+                }
+                Command::Help(command) => {
+                    let width = self.pane_grid(pty_id).map_or(80, |grid| grid.read().width) as usize;
+                    let text = self.input.command_help(command.as_deref(), width);
+                    self.print_local(pty_id, text.trim_end());
+                }
+                Command::Keys { conflicts: false } => {
+                    let width = self.pane_grid(pty_id).map_or(80, |grid| grid.read().width) as usize;
+                    let text = self.input.format_keybindings(width);
+                    self.print_local(pty_id, text.trim_end());
+                }
+                Command::Keys { conflicts: true } => {
+                    let text = self.input.format_binding_conflicts();
+                    self.print_local(pty_id, &text);
+                }
+                Command::Custom(name, args) => self.run_user_command(id, pty_id, &name, &args),
+                Command::Usage => self.show_usage(pty_id),
+                Command::Models => self.show_models(pty_id),
+                Command::Model { name, force } => self.switch_model(id, pty_id, &name, force),
+                Command::Batch { file, out } => self.run_batch(id, pty_id, file, out),
+                Command::Stats { json: false } => self.toggle_stats_overlay(),
+                Command::Stats { json: true } => {
+                    if let Some(report) = self.stats_report(id) {
+                        self.print_local(pty_id, &report.to_json().to_string());
+                    }
+                }
+                Command::Agent(command) => self.ask(id, pty_id, command),
+                Command::Ask(prompt) => self.ask(id, pty_id, AgentCommand::plain(prompt)),
+                Command::Calc(expression) => self.show_calc(pty_id, &expression),
+                Command::CopyResponse { block } => self.copy_response(id, pty_id, block),
+                Command::OpenLink(n) => self.open_link(id, pty_id, n),
+                Command::Search(pattern) => self.open_search(id, pty_id, pattern),
+                Command::InsertCode(block) => {
+                    if let Some(code) = self.response_code(pty_id, block) {
+                        self.snap_to_bottom(id);
+                        self.paste(id, &code);
+                    }
+                }
+                Command::Scaffold(dir) => self.plan_scaffold(pty_id, dir),
+                Command::HistoryPrune { max_mb } => self.prune_history(pty_id, max_mb),
+                Command::Session(action) => self.run_session(id, pty_id, action),
+                Command::ReadOnly(mode) => self.set_read_only(mode),
+                Command::Fold { all } => self.set_folded(id, pty_id, true, all),
+                Command::Unfold { all } => self.set_folded(id, pty_id, false, all),
+                Command::Theme(name) => self.select_theme(&name),
+                // Opening a window needs the event loop
+                Command::NewWindow => return Some(InputAction::NewWindow),
+                command => {
+                    info!("Parsed command {:?}", command);
+                    self.show_notice(id, &messages::current().command_unavailable(parsed.raw_input.trim()));
+                }
+            },
+            InputAction::CommandError(error) => {
+                self.show_notice(id, &messages::current().command_error(&error));
+            }
+            InputAction::Copy => self.copy_selection(id),
+            InputAction::Paste => match read_clipboard() {
+                Some(text) if !text.is_empty() => self.paste(id, &text),
+                _ => warn!("Clipboard is empty or unavailable"),
+            },
+            InputAction::CommandHistory => self.open_history_overlay(id),
+            InputAction::ResponseHistory => self.open_response_browser(id, pty_id),
+            InputAction::SearchScrollback => self.open_search(id, pty_id, None),
+            InputAction::IncreaseFontSize => self.change_font_size(id, Some(1)),
+            InputAction::DecreaseFontSize => self.change_font_size(id, Some(-1)),
+            InputAction::ResetFontSize => self.change_font_size(id, None),
+            InputAction::ToggleGhostText => self.toggle_ghost_text(id, pty_id),
+            InputAction::ToggleStatsOverlay => self.toggle_stats_overlay(),
+            InputAction::ToggleFold => {
+                if let Some(grid) = self.pane_grid(pty_id)
+                    && grid.write().toggle_fold().is_some()
+                    && let Some(managed) = self.windows.get(&id)
+                {
+                    managed.resources.window.request_redraw();
+                }
+            }
+            InputAction::InsertCalcResult => match self.calc_result.clone() {
+                Some(text) => {
+                    self.snap_to_bottom(id);
+                    self.send_input(pty_id, InputSource::Keyboard, text.as_bytes());
+                }
+                None => self.show_notice(id, &messages::current().no_calc_result()),
+            },
+            InputAction::ScrollPageUp
+            | InputAction::ScrollPageDown
+            | InputAction::ScrollToTop
+            | InputAction::ScrollToBottom => self.scroll_view(id, &action),
+            InputAction::NewTab => {
+                if let Err(e) = self.open_tab(id) {
+                    error!("Failed to open new tab: {}", e);
+                    self.show_notice(id, &messages::current().command_error(&e.to_string()));
+                }
+            }
+            InputAction::NextTab | InputAction::PrevTab | InputAction::SwitchTab(_) => self.select_tab(id, &action),
+            InputAction::SplitRight | InputAction::SplitDown => {
+                let direction = match action {
+                    InputAction::SplitRight => SplitDirection::Vertical,
+                    _ => SplitDirection::Horizontal,
+                };
+                if let Err(e) = self.split_pane(id, direction) {
+                    error!("Failed to split pane: {}", e);
+                    self.show_notice(id, &messages::current().command_error(&e.to_string()));
+                }
+            }
+            InputAction::FocusPaneLeft => self.focus_pane(id, PaneDirection::Left),
+            InputAction::FocusPaneRight => self.focus_pane(id, PaneDirection::Right),
+            InputAction::FocusPaneUp => self.focus_pane(id, PaneDirection::Up),
+            InputAction::FocusPaneDown => self.focus_pane(id, PaneDirection::Down),
+            InputAction::NewWindow | InputAction::CloseWindow | InputAction::CloseTab => return Some(action),
+            other => debug!("No handler for {:?} in this window", other),
+        }
+        None
+    }
+
+    /// Cell under a point in the window, clamped to the grid
+    fn cell_at(&self, id: WindowId, position: PhysicalPosition<f64>) -> Option<(u32, u32)> {
+        let managed = self.windows.get(&id)?;
+        let (cell_width, cell_height) = managed.resources.renderer.as_ref()?.cell_size();
+        let terminal = managed.terminal.read();
+        let (left, top) = managed.pane_origin();
+        let column = ((position.x.max(0.0) / cell_width as f64) as u32).saturating_sub(left);
+        let row = ((position.y.max(0.0) / cell_height as f64) as u32).saturating_sub(managed.tab_bar_rows() + top);
+        Some((
+            column.min(terminal.width.saturating_sub(1)),
+            row.min(terminal.height.saturating_sub(1)),
+        ))
+    }
+
+    /// Pressing starts a selection at the cell under the pointer; releasing
+    /// keeps it for copying. Ctrl+click on a link opens it instead.
+    fn handle_left_button(&mut self, id: WindowId, state: ElementState) {
+        let Some(position) = self.windows.get(&id).map(|managed| managed.resources.pointer) else {
+            return;
+        };
+        let cell = self.cell_at(id, position);
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return;
+        };
+        let link = cell
+            .filter(|_| state == ElementState::Pressed && managed.resources.modifiers.state().control_key())
+            .and_then(|(x, y)| managed.terminal.read().link_at(x, y));
+        if let Some(url) = link {
+            self.launch_link(id, &url);
+            return;
+        }
+        match state {
+            ElementState::Pressed => {
+                managed.resources.selecting = true;
+                managed.resources.selection = None;
+                if let Some((x, y)) = cell {
+                    managed.resources.selection = Some(Selection::new(x, y));
+                }
+            }
+            ElementState::Released => {
+                managed.resources.selecting = false;
+                // A click without a drag selects nothing
+                if managed.resources.selection.is_some_and(|selection| selection.anchor == selection.head) {
+                    managed.resources.selection = None;
+                }
+            }
+        }
+        self.show_selection(id);
+    }
+
+    fn handle_pointer_moved(&mut self, id: WindowId, position: PhysicalPosition<f64>) {
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return;
+        };
+        managed.resources.pointer = position;
+        if !managed.resources.selecting {
+            return;
+        }
+        let Some(head) = self.cell_at(id, position) else {
+            return;
+        };
+        if let Some(managed) = self.windows.get_mut(&id)
+            && let Some(selection) = managed.resources.selection.as_mut()
+            && selection.head != head
+        {
+            selection.head = head;
+            self.show_selection(id);
+        }
+    }
+
+    fn show_selection(&mut self, id: WindowId) {
+        if let Some(managed) = self.windows.get_mut(&id) {
+            let selection = managed.resources.selection;
+            if let Some(renderer) = managed.resources.renderer.as_mut() {
+                renderer.set_selection(selection);
+            }
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    fn copy_selection(&mut self, id: WindowId) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+        let Some(selection) = managed.resources.selection else {
+            return;
+        };
+        let text = managed.terminal.read().extract_text(&selection);
+        self.copy_text(id, &text);
+    }
+
+    /// Put `text` on the clipboard and say in the title what was copied, or
+    /// why nothing was
+    fn copy_text(&mut self, id: WindowId, text: &str) {
+        let messages = messages::current();
+        let notice = match self.clipboard.copy(text) {
+            Ok(copied) if copied.truncated => messages.copied_truncated(copied.chars),
+            Ok(copied) => messages.copied(copied.chars),
+            Err(e) => {
+                warn!("Failed to copy {} bytes: {}", text.len(), e);
+                messages.copy_failed(&e.to_string())
+            }
+        };
+        self.show_notice(id, &notice);
+    }
+
+    /// Copy the latest answer, or its `block`th code block, to the clipboard
+    fn copy_response(&mut self, id: WindowId, pty_id: u64, block: Option<usize>) {
+        let text = match block {
+            Some(block) => self.response_code(pty_id, block),
+            None => {
+                let text = self.responses.latest().map(|record| record.content.clone());
+                if text.is_none() {
+                    self.print_local(pty_id, &messages::current().no_response());
+                }
+                text
+            }
+        };
+        let Some(text) = text else {
+            return;
+        };
+        self.copy_text(id, &text);
+    }
+
+    /// List the links on screen, or open the `n`th of them
+    fn open_link(&mut self, id: WindowId, pty_id: u64, n: Option<usize>) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+        let links = managed.terminal.read().screen_links();
+        let messages = messages::current();
+        match n {
+            None if links.is_empty() => self.print_local(pty_id, &messages.no_links()),
+            None => self.print_local(pty_id, &hyperlinks::format_list(&links)),
+            Some(n) => match links.get(n - 1) {
+                Some(link) => self.launch_link(id, &link.url),
+                None => self.print_local(pty_id, &messages.no_such_link(n)),
+            },
+        }
+    }
+
+    fn launch_link(&mut self, id: WindowId, url: &str) {
+        match hyperlinks::open_url(url) {
+            Ok(()) => info!("Opened {}", url),
+            Err(e) => {
+                warn!("Failed to open {}: {}", url, e);
+                self.show_notice(id, &messages::current().link_failed(&e.to_string()));
+            }
+        }
+    }
+
+    /// The latest answer's `block`th code block, counting from 1, or a note
+    /// in the pane saying why there is none
+    fn response_code(&mut self, pty_id: u64, block: usize) -> Option<String> {
+        let Some(response) = self.responses.latest() else {
+            self.print_local(pty_id, &messages::current().no_response());
+            return None;
+        };
+        let code = agent_prompt::code_blocks(&response.content).into_iter().nth(block.saturating_sub(1));
+        if code.is_none() {
+            self.print_local(pty_id, &messages::current().no_code_block());
+        }
+        code
+    }
+
+    /// Evaluate `:calc`, or a prefix line that is an expression, and show
+    /// the result under the line
+    fn show_calc(&mut self, pty_id: u64, expression: &str) {
+        let block = CalcBlock::new(expression);
+        self.print_local(pty_id, &block.lines().join("\n"));
+        if let Some(result) = block.result_text() {
+            self.calc_result = Some(result);
+            self.calc_block = Some((pty_id, block));
+        }
+    }
+
+    /// `:history prune [MB]`: apply retention now, `max_mb` tightening the
+    /// configured cap
+    fn prune_history(&mut self, pty_id: u64, max_mb: Option<u32>) {
+        let messages = messages::current();
+        let text = match self.response_log.as_ref() {
+            None => messages.history_off(),
+            Some(log) => {
+                let cap = max_mb.map_or(log.config().max_total_bytes, |mb| u64::from(mb) * 1024 * 1024);
+                match log.prune_to(cap, log.config().max_age) {
+                    Ok(report) => response_log::format_prune(&report),
+                    Err(e) => messages.history_prune_failed(&e.to_string()),
+                }
+            }
+        };
+        self.print_local(pty_id, &text);
+    }
+
+    /// `:session save|restore|list|delete`. A session is the window's
+    /// tabs with their splits and directories; restoring adds its tabs to
+    /// the window.
+    fn run_session(&mut self, id: WindowId, pty_id: u64, action: SessionAction) {
+        let messages = messages::current();
+        let result = match action {
+            SessionAction::Save(name) => self.save_session(id, &name).map(|()| messages.session_saved(&name)),
+            SessionAction::Restore(name) => self.restore_session(id, &name).map(|tabs| messages.session_restored(&name, tabs)),
+            SessionAction::Delete(name) => self.multiplexer.delete_session(&name).map(|()| messages.session_deleted(&name)),
+            SessionAction::List => self.multiplexer.list_sessions().map(|names| {
+                if names.is_empty() { messages.no_sessions() } else { names.join("\n") }
+            }),
+        };
+        let text = result.unwrap_or_else(|e| messages.session_failed(&e.to_string()));
+        // A restore switches tabs; the result shows in the one now active
+        let pty_id = self.windows.get(&id).and_then(|managed| managed.active_pty()).unwrap_or(pty_id);
+        self.print_local(pty_id, &text);
+    }
+
+    fn save_session(&mut self, id: WindowId, name: &str) -> Result<(), multiplexer::MultiplexerError> {
+        let Some(managed) = self.windows.get(&id) else {
+            return Ok(());
+        };
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+        let mut session = Session::new(name.to_string(), cwd);
+        for (index, &tab) in managed.tabs.iter().enumerate() {
+            let mut window = managed.tab_layout(tab);
+            window.id = index as u64 + 1;
+            window.name = format!("Tab {}", index + 1);
+            session.add_window(window);
+        }
+        session.active_window_id = Some(managed.active_tab as u64 + 1);
+        pollster::block_on(self.multiplexer.add_session(session));
+        pollster::block_on(self.multiplexer.save_session(name))
+    }
+
+    /// Open the saved session's tabs in window `id`; returns how many
+    fn restore_session(&mut self, id: WindowId, name: &str) -> Result<usize, multiplexer::MultiplexerError> {
+        pollster::block_on(self.multiplexer.restore_session(name))?;
+        let Some(session) = pollster::block_on(self.multiplexer.get_session(name)) else {
+            return Ok(0);
+        };
+        let mut windows: Vec<_> = session.windows.into_values().collect();
+        windows.sort_by_key(|window| window.id);
+        let count = windows.len();
+        for window in windows {
+            let Some(managed) = self.windows.get_mut(&id) else {
+                break;
+            };
+            for (pty_id, grid) in managed.open_layout_tab(window) {
+                self.start_pane(pty_id, &grid);
+            }
+        }
+        self.resize_window_pty(id);
+        self.show_active_tab(id);
+        Ok(count)
+    }
+
+    /// List the files the latest answer would write under `dir` and hold
+    /// them until the next key confirms
+    fn plan_scaffold(&mut self, pty_id: u64, dir: PathBuf) {
+        let Some(response) = self.responses.latest() else {
+            self.print_local(pty_id, &messages::current().no_response());
+            return;
+        };
+        let plan = ScaffoldPlan::new(&response.content, dir);
+        if plan.files.is_empty() {
+            self.print_local(pty_id, &messages::current().scaffold_empty());
+            return;
+        }
+        self.print_local(pty_id, &plan.summary());
+        self.scaffold_plan = Some((pty_id, plan));
+    }
+
+    fn scaffold_key(&mut self, key: &Key) {
+        let Some((pty_id, plan)) = self.scaffold_plan.take() else {
+            return;
+        };
+        let text = match key {
+            Key::Char('y' | 'Y') => scaffold::format_results(&plan.write()),
+            _ => messages::current().scaffold_cancelled(),
+        };
+        self.print_local(pty_id, &text);
+    }
+
+    /// `:fold` and `:unfold` in the pane, `all` for every command in it
+    fn set_folded(&mut self, id: WindowId, pty_id: u64, folded: bool, all: bool) {
+        let Some(grid) = self.pane_grid(pty_id) else {
+            return;
+        };
+        if !grid.write().set_folded(folded, all) {
+            self.show_notice(id, &messages::current().no_command_output());
+        } else if let Some(managed) = self.windows.get(&id) {
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    /// Move the window's view through the scrollback
+    fn scroll_view(&mut self, id: WindowId, action: &InputAction) {
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return;
+        };
+        // Selections are in screen cells, which move under a scrolled view
+        managed.resources.selection = None;
+        if let Some(renderer) = managed.resources.renderer.as_mut() {
+            renderer.set_selection(None);
+        }
+        {
+            let mut terminal = managed.terminal.write();
+            let page = terminal.height as isize;
+            match action {
+                InputAction::ScrollPageUp => terminal.scroll_viewport(page),
+                InputAction::ScrollPageDown => terminal.scroll_viewport(-page),
+                InputAction::ScrollToTop => terminal.scroll_viewport_to_top(),
+                _ => terminal.scroll_viewport_to_bottom(),
+            }
+        }
+        managed.resources.window.request_redraw();
+    }
+
+    /// The wheel scrolls an open response browser, else the app in the
+    /// pane when it takes mouse input, else the scrollback. Alt+wheel always
+    /// scrolls the scrollback.
+    fn handle_wheel(&mut self, id: WindowId, delta: MouseScrollDelta) {
+        let multiplier = self.config_manager.get_config().ui.scroll_multiplier;
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return;
+        };
+        let Some(cell_height) = managed.resources.renderer.as_ref().map(|renderer| renderer.cell_size().1) else {
+            return;
+        };
+        let lines = managed.resources.wheel.lines(delta, cell_height, multiplier);
+        if lines == 0 {
+            return;
+        }
+
+        if self.response_browser.as_ref().is_some_and(|(browser_id, _)| *browser_id == id) {
+            if let Some((_, browser)) = self.response_browser.as_mut() {
+                browser.scroll_by(lines);
+            }
+            self.show_response_browser();
+            return;
+        }
+
+        let modifiers = managed.resources.modifiers.state();
+        let (reporting, sgr) = {
+            let terminal = managed.terminal.read();
+            (terminal.mouse_reporting, terminal.sgr_mouse)
+        };
+        if let Some(pty_id) = managed.active_pty()
+            && reporting
+            && !modifiers.alt_key()
+            && !self.read_only.is_read_only(pty_id)
+        {
+            let pointer = managed.resources.pointer;
+            let Some(cell) = self.cell_at(id, pointer) else {
+                return;
+            };
+            let report = mouse_wheel::wheel_report(lines > 0, cell, sgr, modifiers.shift_key(), modifiers.control_key());
+            self.send_input(pty_id, InputSource::Mouse, &report.repeat(lines.unsigned_abs()));
+            return;
+        }
+
+        // Selections are in screen cells, which move under a scrolled view
+        managed.resources.selection = None;
+        if let Some(renderer) = managed.resources.renderer.as_mut() {
+            renderer.set_selection(None);
+        }
+        managed.terminal.write().scroll_viewport(lines);
+        managed.resources.window.request_redraw();
+    }
+
+    /// Typing into the shell brings a view scrolled into history back to
+    /// the screen
+    fn snap_to_bottom(&self, id: WindowId) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+        let mut terminal = managed.terminal.write();
+        if terminal.viewport_offset() > 0 {
+            terminal.scroll_viewport_to_bottom();
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    /// Composed text goes to the PTY once committed; until then it is
+    /// drawn at the cursor
+    fn handle_ime(&mut self, id: WindowId, ime: Ime) {
+        match ime {
+            Ime::Enabled => self.place_ime_cursor(id),
+            Ime::Preedit(text, _) => {
+                self.place_ime_cursor(id);
+                self.set_preedit(id, (!text.is_empty()).then_some(text));
+            }
+            Ime::Commit(text) => {
+                self.set_preedit(id, None);
+                if self.idle.is_blanked()
+                    || self.paste_review.is_some()
+                    || self.history_overlay.is_some()
+                    || self.response_browser.is_some()
+                    || self.search.is_some()
+                {
+                    debug!("Dropped IME commit of {} bytes", text.len());
+                    return;
+                }
+                self.idle.note_input(Instant::now());
+                if let Some(pty_id) = self.windows.get(&id).and_then(|managed| managed.active_pty()) {
+                    self.snap_to_bottom(id);
+                    self.send_input(pty_id, InputSource::Keyboard, text.as_bytes());
+                }
+            }
+            Ime::Disabled => self.set_preedit(id, None),
+        }
+    }
+
+    fn set_preedit(&mut self, id: WindowId, preedit: Option<String>) {
+        if let Some(managed) = self.windows.get_mut(&id) {
+            if let Some(renderer) = managed.resources.renderer.as_mut() {
+                renderer.set_preedit(preedit.clone());
+            }
+            managed.resources.preedit = preedit;
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    /// Candidate windows open next to the cursor cell
+    fn place_ime_cursor(&self, id: WindowId) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+        let Some(renderer) = managed.resources.renderer.as_ref() else {
+            return;
+        };
+        let (cell_width, cell_height) = renderer.cell_size();
+        let (x, y) = {
+            let terminal = managed.terminal.read();
+            let (left, top) = managed.pane_origin();
+            (terminal.cursor_x + left, terminal.cursor_y + managed.tab_bar_rows() + top)
+        };
+        managed.resources.window.set_ime_cursor_area(
+            PhysicalPosition::new(x as f32 * cell_width, y as f32 * cell_height),
+            PhysicalSize::new(cell_width, cell_height),
+        );
+    }
+
+    /// Stop the pane's prompt if one is being answered, else send ^C ahead
+    /// of anything queued for the pane; a quick second Ctrl+C does both
+    fn interrupt(&mut self, pty_id: u64) {
+        let generating = self.prompts.contains_key(&pty_id);
+        let route = self.interrupts.route(pty_id, generating, Instant::now());
+        if matches!(route, InterruptRoute::Agent | InterruptRoute::Both)
+            && let Some(mut prompt) = self.prompts.remove(&pty_id)
+        {
+            prompt.interrupt();
+            let trailer = format!(" {}", messages::current().interrupted());
+            self.finish_prompt(prompt, &trailer);
+        }
+        if route == InterruptRoute::Agent {
+            return;
+        }
+        if self.read_only.is_read_only(pty_id) {
+            self.send_input(pty_id, InputSource::Keyboard, b"\x03");
+        } else {
+            self.trace_input(pty_id, b"\x03");
+            if let Err(e) = self.tty_engine.queue_priority(pty_id, b"\x03") {
+                error!("Failed to send interrupt: {}", e);
+            }
+        }
+    }
+
+    fn toggle_ghost_text(&mut self, id: WindowId, pty_id: u64) {
+        let ghost = self
+            .ghost_text
+            .entry(pty_id)
+            .or_insert_with(|| GhostText::new(GhostTextConfig::default(), Instant::now()));
+        ghost.set_disabled(!ghost.is_disabled());
+        let notice = if ghost.is_disabled() {
+            messages::current().ghost_text_off()
+        } else {
+            messages::current().ghost_text_on()
+        };
+        self.show_notice(id, &notice);
+    }
+
+
+    fn convert_key_event(&self, winit_event: WinitKeyEvent, state: ModifiersState) -> Option<KeyEvent> {
+        if winit_event.state != ElementState::Pressed {
+            return None; // Only handle key press events
+        }
+
+        let key = match winit_event.logical_key {
+            WinitKey::Character(ref s) => {
+                if let Some(c) = s.chars().next() {
+                    // Chords are bound lowercase with an explicit shift,
+                    // e.g. ctrl+shift+t
+                    if state.control_key() && state.shift_key() {
+                        Key::Char(c.to_ascii_lowercase())
+                    } else {
+                        Key::Char(c)
+                    }
+                } else {
+                    return None;
+                }
+            }
+            WinitKey::Named(NamedKey::Space) => Key::Char(' '),
+            WinitKey::Named(NamedKey::Enter) => Key::Enter,
+            WinitKey::Named(NamedKey::Tab) => Key::Tab,
+            WinitKey::Named(NamedKey::Backspace) => Key::Backspace,
+            WinitKey::Named(NamedKey::Delete) => Key::Delete,
+            WinitKey::Named(NamedKey::Escape) => Key::Escape,
+            WinitKey::Named(NamedKey::ArrowUp) => Key::Up,
+            WinitKey::Named(NamedKey::ArrowDown) => Key::Down,
+            WinitKey::Named(NamedKey::ArrowLeft) => Key::Left,
+            WinitKey::Named(NamedKey::ArrowRight) => Key::Right,
+            WinitKey::Named(NamedKey::Home) => Key::Home,
+            WinitKey::Named(NamedKey::End) => Key::End,
+            WinitKey::Named(NamedKey::PageUp) => Key::PageUp,
+            WinitKey::Named(NamedKey::PageDown) => Key::PageDown,
+            WinitKey::Named(NamedKey::F1) => Key::F1,
+            WinitKey::Named(NamedKey::F2) => Key::F2,
+            WinitKey::Named(NamedKey::F3) => Key::F3,
+            WinitKey::Named(NamedKey::F4) => Key::F4,
+            WinitKey::Named(NamedKey::F5) => Key::F5,
+            WinitKey::Named(NamedKey::F6) => Key::F6,
+            WinitKey::Named(NamedKey::F7) => Key::F7,
+            WinitKey::Named(NamedKey::F8) => Key::F8,
+            WinitKey::Named(NamedKey::F9) => Key::F9,
+            WinitKey::Named(NamedKey::F10) => Key::F10,
+            WinitKey::Named(NamedKey::F11) => Key::F11,
+            WinitKey::Named(NamedKey::F12) => Key::F12,
+            WinitKey::Named(NamedKey::Insert) => Key::Insert,
+            _ => return None, // Ignore other keys
+        };
+
+        let mut modifiers = HashSet::new();
+        for (held, modifier) in [
+            (state.control_key(), Modifier::Ctrl),
+            (state.alt_key(), Modifier::Alt),
+            (state.shift_key(), Modifier::Shift),
+            (state.super_key(), Modifier::Super),
+        ] {
+            if held {
+                modifiers.insert(modifier);
+            }
+        }
+        // A typed character already carries Shift; chords match bindings,
+        // which are written in lower case
+        let key = match key {
+            Key::Char(c) if modifiers.len() == 1 && modifiers.contains(&Modifier::Shift) => {
+                modifiers.clear();
+                Key::Char(c)
+            }
+            Key::Char(c) if !modifiers.is_empty() => Key::Char(c.to_ascii_lowercase()),
+            key => key,
+        };
+
+        Some(KeyEvent {
+            key,
+            modifiers,
+            text: winit_event.text.as_ref().map(|text| text.to_string()),
+            repeat: winit_event.repeat,
+            timestamp: Instant::now(),
+            key_code: None,
+        })
+    }
+
+    /// User input for a pane; swallowed while the pane is read-only
+    fn send_input(&mut self, pty_id: u64, source: InputSource, data: &[u8]) {
+        if !self.read_only.is_read_only(pty_id) {
+            self.trace_input(pty_id, data);
+        }
+        self.read_only.send(&*self.tty_engine, pty_id, source, data);
+        self.show_read_only_notices();
+    }
+
+    /// Paste into the window's active pane, holding anything with line
+    /// breaks, control characters or risky commands for confirmation
+    fn paste(&mut self, id: WindowId, text: &str) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+        let Some(pty_id) = managed.active_pty() else {
+            return;
+        };
+        let (bracketed, width, height) = {
+            let terminal = managed.terminal.read();
+            (terminal.bracketed_paste, terminal.width, terminal.height)
+        };
+        match paste_guard::classify(text, &self.paste_guard, bracketed) {
+            PasteVerdict::Send(bytes) => self.send_input(pty_id, InputSource::Paste, &bytes),
+            PasteVerdict::Confirm(review) => {
+                if let Some(managed) = self.windows.get_mut(&id)
+                    && let Some(renderer) = managed.resources.renderer.as_mut()
+                {
+                    renderer.set_overlay(Some(review.overlay_rows(width, height)));
+                    managed.resources.window.request_redraw();
+                }
+                self.paste_review = Some((id, pty_id, review));
+            }
+        }
+    }
+
+    fn review_paste_key(&mut self, key: &Key) {
+        let Some((id, pty_id, review)) = self.paste_review.as_ref() else {
+            return;
+        };
+        let (id, pty_id) = (*id, *pty_id);
+        match review.key(key) {
+            ReviewOutcome::Pending => return,
+            ReviewOutcome::Paste(bytes) => self.send_input(pty_id, InputSource::Paste, &bytes),
+            // TODO: Open the held text in the composer once there is one
+            ReviewOutcome::Edit(_) => self.show_notice(id, &messages::current().paste_edit_unavailable()),
+            ReviewOutcome::Cancel => {}
+        }
+        self.paste_review = None;
+        if let Some(managed) = self.windows.get_mut(&id)
+            && let Some(renderer) = managed.resources.renderer.as_mut()
+        {
+            renderer.set_overlay(None);
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    fn open_history_overlay(&mut self, id: WindowId) {
+        let Some(pty_id) = self.windows.get(&id).and_then(|managed| managed.active_pty()) else {
+            return;
+        };
+        let cwd = self.tty_engine.shell_cwd(pty_id).ok().flatten();
+        let overlay = HistoryOverlay::new(&self.command_history, cwd, pty_id);
+        self.history_overlay = Some((id, pty_id, overlay));
+        self.show_history_overlay();
+    }
+
+    fn show_history_overlay(&mut self) {
+        let Some((id, _, overlay)) = self.history_overlay.as_ref() else {
+            return;
+        };
+        if let Some(managed) = self.windows.get_mut(id)
+            && let Some(renderer) = managed.resources.renderer.as_mut()
+        {
+            let (width, height) = {
+                let terminal = managed.terminal.read();
+                (terminal.width, terminal.height)
+            };
+            renderer.set_overlay(Some(overlay.overlay_rows(width, height)));
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    fn history_overlay_key(&mut self, event: &KeyEvent) {
+        let Some((id, pty_id, overlay)) = self.history_overlay.as_mut() else {
+            return;
+        };
+        let (id, pty_id) = (*id, *pty_id);
+        match overlay.key(&self.command_history, event) {
+            OverlayOutcome::Pending => {
+                self.show_history_overlay();
+                return;
+            }
+            OverlayOutcome::Insert(bytes) | OverlayOutcome::Run(bytes) => {
+                self.send_input(pty_id, InputSource::Keyboard, &bytes)
+            }
+            OverlayOutcome::Cancel => {}
+        }
+        self.history_overlay = None;
+        if let Some(managed) = self.windows.get_mut(&id)
+            && let Some(renderer) = managed.resources.renderer.as_mut()
+        {
+            renderer.set_overlay(None);
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    /// `ui.code_theme`, or `None` when code is left plain
+    fn code_highlighter(&self) -> Option<SyntaxHighlighter> {
+        let theme = self.config_manager.get_config().ui.code_theme;
+        if theme.is_empty() {
+            return None;
+        }
+        SyntaxHighlighter::new(&theme)
+            .inspect_err(|e| warn!("{}; code blocks stay plain", e))
+            .ok()
+    }
+
+    fn open_response_browser(&mut self, id: WindowId, pty_id: u64) {
+        match ResponseBrowser::new(&self.responses) {
+            Some(mut browser) => {
+                if let Some(highlighter) = self.code_highlighter() {
+                    browser = browser.with_highlighting(highlighter);
+                }
+                if let Some(renderer) = self.windows.get(&id).and_then(|managed| managed.resources.renderer.as_ref()) {
+                    browser = browser.with_capabilities(renderer.capabilities());
+                }
+                self.response_browser = Some((id, browser));
+                self.show_response_browser();
+            }
+            None => self.print_local(pty_id, &messages::current().no_response()),
+        }
+    }
+
+    fn show_response_browser(&mut self) {
+        let Some((id, browser)) = self.response_browser.as_mut() else {
+            return;
+        };
+        if let Some(managed) = self.windows.get_mut(id)
+            && let Some(renderer) = managed.resources.renderer.as_mut()
+        {
+            let (width, height) = {
+                let terminal = managed.terminal.read();
+                (terminal.width, terminal.height)
+            };
+            renderer.set_overlay(Some(browser.overlay_rows(width, height)));
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    fn response_browser_key(&mut self, event: &KeyEvent) {
+        let Some((id, browser)) = self.response_browser.as_mut() else {
+            return;
+        };
+        let id = *id;
+        match browser.key(event) {
+            BrowserOutcome::Pending => {
+                self.show_response_browser();
+                return;
+            }
+            BrowserOutcome::Copy(text) => self.copy_text(id, &text),
+            BrowserOutcome::Close => {}
+        }
+        self.response_browser = None;
+        if let Some(managed) = self.windows.get_mut(&id)
+            && let Some(renderer) = managed.resources.renderer.as_mut()
+        {
+            renderer.set_overlay(None);
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    /// Open the search bar over `pty_id`, searching at once when a pattern
+    /// was given with the command
+    fn open_search(&mut self, id: WindowId, pty_id: u64, pattern: Option<String>) {
+        let search_now = pattern.is_some();
+        self.search = Some((id, pty_id, SearchBar::new(pattern)));
+        if search_now {
+            self.run_search();
+        } else {
+            self.show_search();
+        }
+    }
+
+    fn show_search(&mut self) {
+        let Some((id, _, bar)) = self.search.as_ref() else {
+            return;
+        };
+        if let Some(managed) = self.windows.get_mut(id)
+            && let Some(renderer) = managed.resources.renderer.as_mut()
+        {
+            let width = managed.terminal.read().width;
+            renderer.set_search(bar.matches().to_vec(), bar.current());
+            renderer.set_search_bar(Some(bar.row(width)));
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    /// Search the pane's history and screen, and bring the first match
+    /// into view
+    fn run_search(&mut self) {
+        let Some((_, pty_id, bar)) = self.search.as_ref() else {
+            return;
+        };
+        let Some(grid) = self.pane_grid(*pty_id) else {
+            return;
+        };
+        let results = grid.write().search(bar.query());
+        if let Some((_, _, bar)) = self.search.as_mut() {
+            bar.set_results(results);
+        }
+        self.reveal_match();
+    }
+
+    fn reveal_match(&mut self) {
+        if let Some((_, pty_id, bar)) = self.search.as_ref()
+            && let Some(found) = bar.current()
+            && let Some(grid) = self.pane_grid(*pty_id)
+        {
+            grid.write().reveal_line(found.line);
+        }
+        self.show_search();
+    }
+
+    fn search_key(&mut self, event: &KeyEvent) {
+        let Some((id, _, bar)) = self.search.as_mut() else {
+            return;
+        };
+        let id = *id;
+        match bar.key(event) {
+            SearchOutcome::Pending => self.show_search(),
+            SearchOutcome::Search => self.run_search(),
+            SearchOutcome::Next => {
+                bar.next_match();
+                self.reveal_match();
+            }
+            SearchOutcome::Previous => {
+                bar.prev_match();
+                self.reveal_match();
+            }
+            SearchOutcome::Close => {
+                self.search = None;
+                if let Some(managed) = self.windows.get_mut(&id)
+                    && let Some(renderer) = managed.resources.renderer.as_mut()
+                {
+                    renderer.set_search(Vec::new(), None);
+                    renderer.set_search_bar(None);
+                    managed.resources.window.request_redraw();
+                }
+            }
+        }
+    }
+
+    /// Append `message` to the window title for a moment
+    fn show_notice(&mut self, id: WindowId, message: &str) {
+        self.refresh_title(id);
+        if let Some(managed) = self.windows.get_mut(&id) {
+            let window = &managed.resources.window;
+            window.set_title(&format!("{} — {}", window.title(), message));
+            managed.resources.notice_until = Some(Instant::now() + NOTICE_DURATION);
         }
+    }
 
-        // Initialize renderer
-        info!("Initializing renderer...");
-        let renderer = SimpleRenderer::new(window.clone(), self.terminal_state.clone()).await?;
-        self.renderer = Some(renderer);
+    /// `set <path> <value> [--save]`. Windows pick the change up from the
+    /// config subscription; a bad path or value is printed in the pane.
+    fn set_config(&mut self, id: WindowId, pty_id: u64, path: &str, value: &str, save: bool) {
+        let result = if save {
+            self.config_manager.save_setting(path, value)
+        } else {
+            self.config_manager.set_override(path, value)
+        };
+        match result {
+            Ok(()) if save => self.show_notice(id, &messages::current().setting_saved(path, value)),
+            Ok(()) => self.show_notice(id, &messages::current().setting_changed(path, value)),
+            Err(e) => self.print_local(pty_id, &messages::current().command_error(&e.to_string())),
+        }
+    }
 
-        // Create main PTY session
-        info!("Creating main PTY session...");
-        let mut pty_config = PtyConfig::default();
-        pty_config.rows = term_rows as u16;
-        pty_config.cols = term_cols as u16;
-        
-        if let Ok(shell) = std::env::var("SHELL") {
-            pty_config.shell = shell;
+    /// Ctrl+Plus/Minus step the font size by a point and Ctrl+0 goes back
+    /// to the size at startup. Every grid is resized when the config
+    /// change comes through; `ui.save_font_size` also writes it to the file.
+    fn change_font_size(&mut self, id: WindowId, step: Option<i32>) {
+        let ui = self.config_manager.get_config().ui;
+        let size = match step {
+            Some(step) => (ui.font_size as i32 + step).clamp(6, 72) as u32,
+            None => self.base_font_size,
+        };
+        if size == ui.font_size {
+            return;
         }
-        
-        self.main_pty_id = Some(self.tty_engine.create_pty(pty_config).await?);
-        
-        // Store window reference
-        self.window = Some(window);
-        self.is_initialized = true;
+        let value = size.to_string();
+        let result = if ui.save_font_size {
+            self.config_manager.save_setting("ui.font_size", &value)
+        } else {
+            self.config_manager.set_override("ui.font_size", &value)
+        };
+        match result {
+            Ok(()) => self.show_notice(id, &messages::current().setting_changed("ui.font_size", &value)),
+            Err(e) => warn!("Font size unchanged: {}", e),
+        }
+    }
 
-        let elapsed = self.startup_time.elapsed();
-        info!("Ferroterm initialized successfully in {:?}", elapsed);
+    /// Show text in a pane on lines of its own, without sending it to the shell
+    fn print_local(&mut self, pty_id: u64, text: &str) {
+        self.write_local(pty_id, &format!("\n{}\n", text));
+    }
 
-        // Check if we met the startup time target
-        if elapsed <= Duration::from_millis(100) {
-            info!("✓ Met startup time target (≤100ms)");
-        } else {
-            warn!("⚠ Startup time exceeded target: {:?} > 100ms", elapsed);
+    /// Write text into a pane as if the shell had printed it
+    fn write_local(&mut self, pty_id: u64, text: &str) {
+        if let Some(tab_output) = self.tab_outputs.get(&pty_id) {
+            tab_output.backlog.push(text.replace('\n', "\r\n").as_bytes());
         }
+    }
 
-        // Execute startup command if provided
-        if let Some(ref cmd) = self.startup_command {
-            if let Some(pty_id) = self.main_pty_id {
-                info!("Executing startup command: {}", cmd);
-                let cmd_with_newline = format!("{}\n", cmd);
-                if let Err(e) = self.tty_engine.write_to_pty(pty_id, cmd_with_newline.as_bytes()).await {
-                    warn!("Failed to execute startup command: {}", e);
+    /// A `[commands]` entry: shell commands are typed into the pane or run
+    /// beside it, prompts are filled in and asked
+    fn run_user_command(&mut self, id: WindowId, pty_id: u64, name: &str, args: &[String]) {
+        let Some(command) = self.config_manager.get_config().commands.get(name).cloned() else {
+            self.show_notice(id, &messages::current().command_unavailable(name));
+            return;
+        };
+        match command {
+            UserCommand::Shell { cmd, detached, .. } => {
+                let line = std::iter::once(cmd.as_str())
+                    .chain(args.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if detached {
+                    self.run_detached(pty_id, line);
+                } else {
+                    self.snap_to_bottom(id);
+                    self.send_input(pty_id, InputSource::Keyboard, format!("{}\r", line).as_bytes());
                 }
             }
+            UserCommand::Prompt { template, .. } => {
+                let last_output = self.tty_engine.last_command_output(pty_id).unwrap_or(None).unwrap_or_default();
+                let selection = self
+                    .windows
+                    .get(&id)
+                    .and_then(|managed| {
+                        let selection = managed.resources.selection?;
+                        Some(managed.terminal.read().extract_text(&selection))
+                    })
+                    .unwrap_or_default();
+                let cwd = self.shell_cwd(pty_id).unwrap_or_default();
+                let prompt = command_parser::expand_template(
+                    &template,
+                    &[
+                        ("last_output", &last_output),
+                        ("selection", &selection),
+                        ("cwd", &cwd),
+                        ("args", &args.join(" ")),
+                    ],
+                );
+                self.ask(id, pty_id, AgentCommand::plain(prompt));
+            }
         }
+    }
 
-        Ok(())
+    /// Run `line` with `FALLBACK_SHELL -c` in the pane's directory, away
+    /// from the event loop, and print what it wrote in the pane once done
+    fn run_detached(&mut self, pty_id: u64, line: String) {
+        let Some(backlog) = self.tab_outputs.get(&pty_id).map(|output| output.backlog.clone()) else {
+            return;
+        };
+        let cwd = self.shell_cwd(pty_id);
+        std::thread::spawn(move || {
+            let mut process = std::process::Command::new(FALLBACK_SHELL);
+            process.arg("-c").arg(&line).stdin(std::process::Stdio::null());
+            if let Some(cwd) = cwd {
+                process.current_dir(cwd);
+            }
+            let text = match process.output() {
+                Ok(output) => {
+                    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+                    text.push_str(&String::from_utf8_lossy(&output.stderr));
+                    if let Some(code) = output.status.code().filter(|&code| code != 0) {
+                        text = format!("{}\n{}", text.trim_end(), messages::current().process_exited(code));
+                    }
+                    text
+                }
+                Err(e) => messages::current().command_error(&e.to_string()),
+            };
+            backlog.push(format!("\n{}\n", text.trim_end()).replace('\n', "\r\n").as_bytes());
+        });
     }
 
-    fn handle_window_resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if let Some(pty_id) = self.main_pty_id {
-            let config = self.config_manager.get_config();
-            let font_size = config.ui.font_size as f32;
-            let estimated_char_width = font_size * 0.6;
-            let estimated_char_height = font_size * 1.2 * config.ui.line_height;
-            
-            let term_cols = (new_size.width as f32 / estimated_char_width) as u32;
-            let term_rows = (new_size.height as f32 / estimated_char_height) as u32;
-            
-            debug!("Resizing terminal: {}x{}", term_cols, term_rows);
-            
-            // Resize terminal state
-            {
-                let mut terminal = self.terminal_state.write();
-                terminal.resize(term_cols, term_rows);
+    /// Usage of the model host, or as saved by earlier runs
+    fn show_usage(&mut self, pty_id: u64) {
+        let models = match &self.model_host {
+            Some(host) => host.get_usage_all(),
+            None => UsageTracker::default_path()
+                .and_then(|path| UsageTracker::read(&path).ok())
+                .unwrap_or_default(),
+        };
+        self.print_local(pty_id, usage::format_table(&models).trim_end());
+    }
+
+    /// Numbers for the stats overlay and `:stats --json`, with the
+    /// window's own renderer figures and its active tab's output backlog
+    fn stats_report(&self, id: WindowId) -> Option<StatsReport> {
+        let managed = self.windows.get(&id)?;
+        let render = managed.resources.renderer.as_ref()?.render_stats();
+        let output = managed
+            .active_pty()
+            .and_then(|pty_id| self.tab_outputs.get(&pty_id))
+            .map(|tab_output| tab_output.scheduler.metrics())
+            .unwrap_or_default();
+        let models = match &self.model_host {
+            Some(host) => {
+                let names: Vec<String> =
+                    pollster::block_on(host.model_states()).into_iter().map(|state| state.name).collect();
+                stats_overlay::model_stats(&names, &pollster::block_on(host.get_stats()))
             }
-            
-            // Resize renderer
-            if let Some(ref mut renderer) = self.renderer {
-                renderer.resize(new_size);
+            None => Vec::new(),
+        };
+        Some(StatsReport::new(render, output, &self.input.get_input_stats(), models))
+    }
+
+    fn toggle_stats_overlay(&mut self) {
+        if !self.stats_overlay.toggle() {
+            for (_, managed) in self.windows.iter_mut() {
+                if let Some(renderer) = managed.resources.renderer.as_mut() {
+                    renderer.set_stats_overlay(None);
+                }
             }
-            
-            // Resize PTY
-            if let Err(e) = self.tty_engine.resize_pty(pty_id, term_rows as u16, term_cols as u16) {
-                error!("Failed to resize PTY: {}", e);
+        }
+    }
+
+    /// Refresh the overlay text in every window, at most twice a second
+    fn update_stats_overlay(&mut self, now: Instant) {
+        if !self.stats_overlay.due(now) {
+            return;
+        }
+        for id in self.windows.ids().to_vec() {
+            let lines = self.stats_report(id).map(|report| report.lines());
+            if let Some(renderer) = self.windows.get_mut(&id).and_then(|managed| managed.resources.renderer.as_mut()) {
+                renderer.set_stats_overlay(lines);
             }
         }
     }
 
-    fn handle_key_input(&mut self, key_event: WinitKeyEvent, _modifiers: Modifiers) {
-        if !self.is_initialized {
+    /// `model <name>`: check the swap fits, then queue it; its steps are
+    /// written to the pane as they happen. Without a name, show the model
+    /// prompts go to.
+    fn switch_model(&mut self, id: WindowId, pty_id: u64, name: &str, force: bool) {
+        if name.is_empty() {
+            let config = self.config_manager.get_config();
+            let text = messages::current().model_current(config.models.default_model(&config.agent));
+            self.print_local(pty_id, &text);
+            return;
+        }
+        let Some(host) = self.model_host.clone() else {
+            self.show_notice(id, &messages::current().command_unavailable(&format!("model {}", name)));
             return;
+        };
+        match pollster::block_on(host.request_hot_swap(name.to_string(), force)) {
+            Ok(progress) => {
+                self.write_local(pty_id, "\n");
+                self.model_swaps.push((pty_id, progress));
+            }
+            Err(e) => self.print_local(pty_id, &messages::current().command_error(&e.to_string())),
         }
+    }
 
-        // Check for About panel shortcut (Cmd+A on macOS)
-        #[cfg(target_os = "macos")]
-        {
-            if key_event.state == ElementState::Pressed
-                && key_event.logical_key == WinitKey::Character("a".into()) {
-                // TODO: Check for Cmd modifier
-                show_about_panel();
-                return;
+    /// Write the steps of running model swaps on one line per swap; a
+    /// finished one becomes the default model for later prompts
+    fn update_model_swaps(&mut self) {
+        let mut steps = Vec::new();
+        self.model_swaps.retain_mut(|(pty_id, progress)| loop {
+            match progress.try_recv() {
+                Ok(step) => {
+                    let done = matches!(step, HotSwapProgress::Ready(_) | HotSwapProgress::Failed(_));
+                    steps.push((*pty_id, step));
+                    if done {
+                        return false;
+                    }
+                }
+                Err(tokio::sync::mpsc::error::TryRecvError::Empty) => return true,
+                Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => return false,
             }
+        });
+        let messages = messages::current();
+        for (pty_id, step) in steps {
+            let text = match step {
+                HotSwapProgress::Unloading(model) => format!("{} ", messages.model_unloading(&model)),
+                HotSwapProgress::Loading(model) => format!("{} ", messages.model_loading(&model)),
+                HotSwapProgress::WarmingUp => format!("{} ", messages.model_warming_up()),
+                HotSwapProgress::Ready(summary) => {
+                    let target = summary.is_slow().then(|| HOT_SWAP_TARGET.as_secs_f32());
+                    if let Err(e) = self.config_manager.set_override("models.default_model", &summary.model) {
+                        warn!("Prompts stay on the previous model: {}", e);
+                    }
+                    format!("{}\n", messages.model_ready(summary.elapsed.as_secs_f32(), target))
+                }
+                HotSwapProgress::Failed(error) => format!("{}\n", messages.model_swap_failed(&error)),
+            };
+            self.write_local(pty_id, &text);
         }
+    }
 
-        // Convert winit key event to our internal format
-        let our_key_event = match self.convert_key_event(key_event) {
-            Some(event) => event,
-            None => return, // Ignore unsupported keys
+    /// `batch <file> [--out <file>]`, with paths from the pane's directory.
+    /// Progress is kept in the inline region until the answers replace it.
+    fn run_batch(&mut self, id: WindowId, pty_id: u64, file: PathBuf, out: Option<PathBuf>) {
+        let Some(host) = self.model_host.clone() else {
+            self.show_notice(id, &messages::current().command_unavailable("batch"));
+            return;
         };
+        let cwd = self.shell_cwd(pty_id).map(PathBuf::from).unwrap_or_default();
+        let config = self.config_manager.get_config();
+        self.batches.push(RunningBatch::start(host, config, pty_id, cwd.join(file), out.map(|out| cwd.join(out))));
+    }
 
-        // Process input through input processor
-        if let Some(pty_id) = self.main_pty_id {
-            // For now, convert key to simple string and send to PTY
-            // TODO: Implement proper input processing with the InputProcessor
-            let key_str = self.key_event_to_string(our_key_event);
-            if !key_str.is_empty() {
-                self.send_to_pty(pty_id, key_str.as_bytes());
+    /// Show each running batch's progress in its pane's inline region, and
+    /// commit what finished ones produced. A pane answering a prompt keeps
+    /// the region until that answer is done.
+    fn update_batches(&mut self) {
+        let messages = messages::current();
+        let mut updates = Vec::new();
+        let mut finished = Vec::new();
+        for mut batch in std::mem::take(&mut self.batches) {
+            if self.prompts.contains_key(&batch.pane) {
+                self.batches.push(batch);
+                continue;
+            }
+            if let Some(progress) = batch.progress() {
+                let text = messages.batch_progress(progress.completed, progress.total, progress.failed);
+                updates.push((batch.pane, text));
+            }
+            if batch.is_finished() {
+                finished.push(batch);
+            } else {
+                self.batches.push(batch);
+            }
+        }
+        for (pty_id, text) in updates {
+            if let Some(grid) = self.pane_grid(pty_id) {
+                grid.write().set_inline_region(vec![text_row(&text, true)]);
+            }
+        }
+        for batch in finished {
+            let pty_id = batch.pane;
+            let mut view = self.markdown_view(pty_id);
+            view.push(&pollster::block_on(batch.finish()));
+            if let Some(grid) = self.pane_grid(pty_id) {
+                let mut grid = grid.write();
+                grid.set_inline_region(view.rows().cloned().collect());
+                grid.commit_inline_region();
             }
         }
     }
 
-    fn convert_key_event(&self, winit_event: WinitKeyEvent) -> Option<KeyEvent> {
-        if winit_event.state != ElementState::Pressed {
-            return None; // Only handle key press events
-        }
+    /// Registered models and whether each is loaded
+    fn show_models(&mut self, pty_id: u64) {
+        let states = match &self.model_host {
+            Some(host) => pollster::block_on(host.model_states()),
+            None => Vec::new(),
+        };
+        let config = self.config_manager.get_config();
+        let default_model = config.models.default_model(&config.agent);
+        self.print_local(pty_id, model_registry::format_table(&states, default_model).trim_end());
+    }
 
-        let key = match winit_event.logical_key {
-            WinitKey::Character(ref s) => {
-                if let Some(c) = s.chars().next() {
-                    Key::Char(c)
-                } else {
-                    return None;
+    /// `:readonly on|off|toggle` for the focused window's active pane
+    fn set_read_only(&mut self, mode: ReadOnlyMode) {
+        let Some(managed) = self.windows.focused().and_then(|id| self.windows.get(&id)) else {
+            return;
+        };
+        let Some(pty_id) = managed.active_pty() else {
+            return;
+        };
+        let pane_index = managed.active_tab + 1;
+        self.read_only.set(pty_id, mode, pane_index);
+        self.show_read_only_notices();
+    }
+
+    /// Flash read-only notices in the title of the window showing the pane
+    fn show_read_only_notices(&mut self) {
+        for notice in self.read_only.take_notices() {
+            let Some(id) = self
+                .windows
+                .iter()
+                .find(|(_, w)| w.tab_index(notice.pty_id).is_some())
+                .map(|(id, _)| *id)
+            else {
+                continue;
+            };
+            self.refresh_title(id);
+            if let Some(managed) = self.windows.get_mut(&id) {
+                let window = &managed.resources.window;
+                window.set_title(&format!("{} — {}", window.title(), notice.message));
+                if notice.bell {
+                    window.request_user_attention(Some(UserAttentionType::Informational));
                 }
+                managed.resources.notice_until = Some(Instant::now() + NOTICE_DURATION);
             }
-            WinitKey::Named(NamedKey::Enter) => Key::Enter,
-            WinitKey::Named(NamedKey::Tab) => Key::Tab,
-            WinitKey::Named(NamedKey::Backspace) => Key::Backspace,
-            WinitKey::Named(NamedKey::Delete) => Key::Delete,
-            WinitKey::Named(NamedKey::Escape) => Key::Escape,
-            WinitKey::Named(NamedKey::ArrowUp) => Key::Up,
-            WinitKey::Named(NamedKey::ArrowDown) => Key::Down,
-            WinitKey::Named(NamedKey::ArrowLeft) => Key::Left,
-            WinitKey::Named(NamedKey::ArrowRight) => Key::Right,
-            WinitKey::Named(NamedKey::Home) => Key::Home,
-            WinitKey::Named(NamedKey::End) => Key::End,
-            WinitKey::Named(NamedKey::PageUp) => Key::PageUp,
-            WinitKey::Named(NamedKey::PageDown) => Key::PageDown,
-            WinitKey::Named(NamedKey::F1) => Key::F1,
-            WinitKey::Named(NamedKey::F2) => Key::F2,
-            WinitKey::Named(NamedKey::F3) => Key::F3,
-            WinitKey::Named(NamedKey::F4) => Key::F4,
-            WinitKey::Named(NamedKey::F5) => Key::F5,
-            WinitKey::Named(NamedKey::F6) => Key::F6,
-            WinitKey::Named(NamedKey::F7) => Key::F7,
-            WinitKey::Named(NamedKey::F8) => Key::F8,
-            WinitKey::Named(NamedKey::F9) => Key::F9,
-            WinitKey::Named(NamedKey::F10) => Key::F10,
-            WinitKey::Named(NamedKey::F11) => Key::F11,
-            WinitKey::Named(NamedKey::F12) => Key::F12,
-            WinitKey::Named(NamedKey::Insert) => Key::Insert,
-            _ => return None, // Ignore other keys
-        };
+        }
+    }
 
-        let modifiers = HashSet::new(); // TODO: Extract modifiers from winit event
+    /// Window title, with the lock marker while the active pane is read-only
+    fn refresh_title(&mut self, id: WindowId) {
+        let Some(title) = self.window_title(id) else {
+            return;
+        };
+        let Some(managed) = self.windows.get_mut(&id) else {
+            return;
+        };
+        managed.resources.window.set_title(&title);
+        managed.resources.title = title;
+        managed.resources.notice_until = None;
+    }
 
-        Some(KeyEvent {
-            key,
-            modifiers,
-            text: None,
-            repeat: winit_event.repeat,
-            timestamp: Instant::now(),
-            key_code: None,
+    /// "{tab title} — Ferroterm" for the focused tab
+    fn window_title(&mut self, id: WindowId) -> Option<String> {
+        let active = self.windows.get(&id)?.active_pty();
+        let title = match active {
+            Some(pty_id) => format!("{} — Ferroterm", self.tab_title(id, pty_id)),
+            None => "Ferroterm".to_string(),
+        };
+        Some(match active {
+            Some(pty_id) if self.read_only.is_read_only(pty_id) => format!("{} {}", READ_ONLY_MARKER, title),
+            _ => title,
         })
     }
 
-    fn key_event_to_string(&self, key_event: KeyEvent) -> String {
-        match key_event.key {
-            Key::Char(c) => c.to_string(),
-            Key::Enter => "\r".to_string(),
-            Key::Tab => "\t".to_string(),
-            Key::Backspace => "\x08".to_string(),
-            Key::Delete => "\x7f".to_string(),
-            Key::Escape => "\x1b".to_string(),
-            Key::Up => "\x1b[A".to_string(),
-            Key::Down => "\x1b[B".to_string(),
-            Key::Right => "\x1b[C".to_string(),
-            Key::Left => "\x1b[D".to_string(),
-            Key::Home => "\x1b[H".to_string(),
-            Key::End => "\x1b[F".to_string(),
-            Key::PageUp => "\x1b[5~".to_string(),
-            Key::PageDown => "\x1b[6~".to_string(),
-            _ => String::new(), // Ignore other keys for now
+    /// Retitle the window when its focused tab's title or directory has
+    /// changed, unless a notice or the catch-up indicator is showing
+    fn follow_title(&mut self, id: WindowId) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+        if managed.resources.notice_until.is_some() || managed.resources.indicator.is_some() {
+            return;
+        }
+        let current = managed.resources.title.clone();
+        if self.window_title(id).is_some_and(|title| title != current) {
+            self.refresh_title(id);
+        }
+    }
+
+    fn send_to_pty(&mut self, pty_id: u64, data: &[u8]) {
+        self.trace_input(pty_id, data);
+        if let Err(e) = self.tty_engine.queue_write(pty_id, data) {
+            error!("Failed to write to PTY: {}", e);
         }
     }
 
-    fn send_to_pty(&self, pty_id: u64, data: &[u8]) {
-        let tty_engine = self.tty_engine.clone();
-        let data = data.to_vec();
-        tokio::spawn(async move {
-            if let Err(e) = tty_engine.write_to_pty(pty_id, &data).await {
-                error!("Failed to write to PTY: {}", e);
-            }
+    /// Tells the input processor what the pane is running so the prefix
+    /// character can pass through to full-screen programs
+    fn update_terminal_context(&mut self, id: WindowId, pty_id: u64) {
+        let Some(managed) = self.windows.get(&id) else {
+            return;
+        };
+        let (alternate_screen, at_input_start) = {
+            let terminal = managed.terminal.read();
+            (terminal.alternate_screen, terminal.at_input_start())
+        };
+        let foreground_process = self.foreground_process(pty_id);
+        self.input.set_terminal_context(TerminalContext {
+            alternate_screen,
+            foreground_process,
+            at_input_start,
         });
     }
 
-    fn render_frame(&mut self) {
-        if let Some(ref mut renderer) = self.renderer {
-            if let Err(e) = renderer.render() {
-                error!("Render error: {}", e);
+    /// Parse one time slice of pending output for each of the window's tabs
+    /// and panes; background tabs keep draining so their programs never block on a
+    /// full pipe. The report is the active tab's. While a flood is being
+    /// caught up the title shows the throughput instead of each frame.
+    fn process_output(&mut self, id: WindowId) -> Option<SliceReport> {
+        let managed = self.windows.get(&id)?;
+        let active = managed.active_pty();
+        let tabs: Vec<(u64, Arc<RwLock<TerminalState>>)> = managed
+            .ptys()
+            .into_iter()
+            .filter_map(|pty_id| managed.grid(pty_id).map(|grid| (pty_id, grid.clone())))
+            .collect();
+
+        let mut active_report = None;
+        let mut woke = false;
+        for (pty_id, grid) in tabs {
+            let Some(tab_output) = self.tab_outputs.get_mut(&pty_id) else {
+                continue;
+            };
+            let (report, replies, bell, shell_events) = {
+                let mut terminal = grid.write();
+                let report = match self.trace.as_mut().filter(|session| session.pty_id == pty_id) {
+                    Some(session) => tab_output.scheduler.run_slice(
+                        &tab_output.backlog,
+                        &mut RecordingSink {
+                            terminal: &mut terminal,
+                            recorder: &mut session.recorder,
+                        },
+                    ),
+                    None => tab_output.scheduler.run_slice(&tab_output.backlog, &mut *terminal),
+                };
+                (report, terminal.take_replies(), terminal.take_bell(), terminal.take_shell_events())
+            };
+
+            if !replies.is_empty() {
+                self.send_to_pty(pty_id, &replies);
+            }
+            if !shell_events.is_empty() {
+                self.record_commands(pty_id, shell_events);
+            }
+            let now = Instant::now();
+            woke |= (report.processed > 0 && self.idle.note_output(now)) | (bell && self.idle.note_bell(now));
+            if Some(pty_id) == active {
+                active_report = Some(report);
+            }
+        }
+        if woke {
+            self.update_blank();
+        }
+
+        let indicator = active
+            .and_then(|pty_id| self.tab_outputs.get(&pty_id))
+            .and_then(|tab_output| tab_output.scheduler.indicator());
+        let managed = self.windows.get_mut(&id)?;
+        if indicator != managed.resources.indicator {
+            managed.resources.indicator = indicator.clone();
+            match indicator {
+                Some(text) => managed
+                    .resources
+                    .window
+                    .set_title(&format!("Ferroterm v{} {}", env!("CARGO_PKG_VERSION"), text)),
+                None => self.refresh_title(id),
+            }
+        }
+        active_report
+    }
+
+    fn record_commands(&mut self, pty_id: u64, events: Vec<ShellEvent>) {
+        let tracker = self.command_trackers.entry(pty_id).or_default();
+        for event in events {
+            let now = response_log::unix_millis(SystemTime::now());
+            let tty_engine = &self.tty_engine;
+            let Some(record) = tracker.observe(event, pty_id, now, || tty_engine.shell_cwd(pty_id).ok().flatten())
+            else {
+                continue;
+            };
+            if let Err(e) = self.command_history.record(record) {
+                warn!("Failed to store command: {}", e);
+            }
+        }
+    }
+
+    /// Show or hide the idle lock screen in every window
+    fn update_blank(&mut self) {
+        let blank = self.idle.notice().map(|notice| (self.idle.style(), notice));
+        for (_, managed) in self.windows.iter_mut() {
+            if let Some(renderer) = managed.resources.renderer.as_mut() {
+                renderer.set_blank(blank.clone());
             }
+            managed.resources.window.request_redraw();
+        }
+    }
+
+    fn render_frame(&mut self, id: WindowId) {
+        self.render_heartbeat.beat();
+        // Titles follow the foreground process and OSC title changes
+        self.update_tab_bar(id);
+        self.follow_title(id);
+        if let Some(managed) = self.windows.get_mut(&id)
+            && let Some(ref mut renderer) = managed.resources.renderer
+            && let Err(e) = renderer.render()
+        {
+            error!("Render error: {}", e);
+        }
+
+        if self.startup.report().time_to_first_frame().is_none() {
+            self.startup.frame_presented();
+            info!("Startup: {}", self.startup.report().summary());
         }
         
         self.frame_count += 1;
@@ -338,7 +3412,7 @@ impl FerrotermApp {
         // Calculate FPS every second
         let now = Instant::now();
         if now.duration_since(self.last_fps_time) >= Duration::from_secs(1) {
-            let fps = self.frame_count;
+            let fps = self.frame_count / self.windows.len().max(1) as u64;
             if fps < 60 {
                 debug!("FPS: {}", fps);
             }
@@ -351,11 +3425,30 @@ impl FerrotermApp {
 
     async fn shutdown(&mut self) {
         info!("Shutting down Ferroterm...");
+
+        if let Some(TraceSession { window, recorder: Some(mut recorder), .. }) = self.trace.take()
+            && let Some(managed) = self.windows.get(&window)
+            && let Err(e) = recorder.checkpoint("exit", &managed.terminal.read())
+        {
+            error!("Failed to finish trace: {}", e);
+        }
+
+        self.completion_model = None;
+        self.prompts.clear();
+        if let Some(host) = self.model_host.take() {
+            host.shutdown(MODEL_DRAIN).await;
+        }
         
-        // Close PTY session
-        if let Some(pty_id) = self.main_pty_id {
-            if let Err(e) = self.tty_engine.destroy_pty(pty_id).await {
-                error!("Failed to destroy PTY: {}", e);
+        // Close every window's PTY sessions
+        let ids: Vec<WindowId> = self.windows.ids().to_vec();
+        for id in ids {
+            if let Some(managed) = self.windows.remove(&id) {
+                for pty_id in managed.ptys() {
+                    self.stop_reader(pty_id);
+                    if let Err(e) = self.tty_engine.destroy_pty(pty_id).await {
+                        error!("Failed to destroy PTY: {}", e);
+                    }
+                }
             }
         }
         
@@ -364,6 +3457,144 @@ impl FerrotermApp {
     }
 }
 
+/// Clipboard text from the platform's command-line tools
+/// Shell line-editing actions are left to the shell: send the keys it
+/// expects for them
+fn control_bytes(action: &InputAction) -> Option<&'static [u8]> {
+    Some(match action {
+        InputAction::Eof => b"\x04",
+        InputAction::Suspend => b"\x1a",
+        InputAction::Clear => b"\x0c",
+        InputAction::ClearLine | InputAction::DeleteToStart => b"\x15",
+        InputAction::LineStart => b"\x01",
+        InputAction::LineEnd => b"\x05",
+        InputAction::DeleteToEnd => b"\x0b",
+        InputAction::DeleteWord => b"\x17",
+        InputAction::WordBack => b"\x1bb",
+        InputAction::WordForward => b"\x1bf",
+        InputAction::HistoryPrev => b"\x10",
+        InputAction::HistoryNext => b"\x0e",
+        InputAction::HistorySearch => b"\x12",
+        _ => return None,
+    })
+}
+
+/// The cursor the user configured, for panes whose app has not set one
+fn cursor_style(ui: &UiConfig) -> CursorStyle {
+    CursorStyle {
+        shape: CursorShape::from_name(&ui.cursor_style),
+        blink: ui.cursor_blink,
+    }
+}
+
+/// Log the config key bindings that shadow others; `keys --conflicts`
+/// lists them in a pane
+fn warn_binding_conflicts(input: &InputProcessor) {
+    for conflict in input.binding_conflicts() {
+        warn!("Key binding conflict: {}", conflict);
+    }
+}
+
+/// One row of plain text, dimmed for notes such as the answer summary
+fn text_row(text: &str, dim: bool) -> Vec<TerminalCell> {
+    text.chars()
+        .map(|character| TerminalCell { character, dim, ..TerminalCell::default() })
+        .collect()
+}
+
+fn read_clipboard() -> Option<String> {
+    let candidates: &[&[&str]] = if cfg!(target_os = "macos") {
+        &[&["pbpaste"]]
+    } else {
+        &[&["wl-paste", "--no-newline"], &["xclip", "-o", "-selection", "clipboard"], &["xsel", "-ob"]]
+    };
+    candidates.iter().find_map(|command| {
+        let output = std::process::Command::new(command[0]).args(&command[1..]).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    })
+}
+
+/// `ferroterm replay-trace`: run a trace through a headless terminal and
+/// report the first checkpoint that no longer matches
+fn replay_trace(path: &std::path::Path, every_event: bool, print: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let trace = Trace::load(path)?;
+    let report = trace::replay(&trace, every_event);
+    if print {
+        for snapshot in &report.snapshots {
+            println!("--- event {} ({})", snapshot.event, snapshot.label);
+            for row in &snapshot.snapshot.rows {
+                println!("{}", row);
+            }
+        }
+    }
+    println!("{}", report.summary());
+    match report.divergence {
+        Some(_) => Err("replayed grid diverged from the recording".into()),
+        None => Ok(()),
+    }
+}
+
+/// The PTY for a shell as `[shell]` describes it
+fn pty_config(shell: &ShellConfig, geometry: &WindowGeometry, cwd: Option<PathBuf>) -> PtyConfig {
+    let mut pty_config = PtyConfig {
+        args: shell.args.clone(),
+        env: shell.env.clone().into_iter().collect(),
+        cwd,
+        login_shell: shell.login,
+        rows: geometry.rows as u16,
+        cols: geometry.cols as u16,
+        ..PtyConfig::default()
+    };
+    if !shell.program.is_empty() {
+        pty_config.shell = shell.program.clone();
+    }
+    pty_config
+}
+
+// Continuously feed a PTY's output into the terminal state of its window
+fn spawn_pty_reader(
+    tty_engine: Arc<TtyEngine>,
+    pty_id: u64,
+    output: Arc<OutputBacklog>,
+    max_backlog: usize,
+    heartbeat: Heartbeat,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        info!("Starting continuous PTY output reader for PTY {}", pty_id);
+        let mut batches = match tty_engine.subscribe_output(pty_id) {
+            Ok(batches) => batches,
+            Err(e) => {
+                error!("PTY subscribe error: {}", e);
+                heartbeat.retire();
+                return;
+            }
+        };
+        loop {
+            heartbeat.beat();
+            // Leave the rest of a flood with the PTY reader, which stops
+            // reading once its channel is full
+            if output.len() >= max_backlog {
+                tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+                continue;
+            }
+            match tokio::time::timeout(tokio::time::Duration::from_millis(100), batches.recv()).await {
+                Ok(Some(batch)) => {
+                    // Parsed on the UI thread a slice per frame
+                    output.push(&batch);
+                    heartbeat.set_state(format!("read {} bytes, backlog {} bytes", batch.len(), output.len()));
+                    debug!("PTY output: {} bytes", batch.len());
+                }
+                Ok(None) => {
+                    // The shell exited and all its output has been delivered
+                    heartbeat.retire();
+                    break;
+                }
+                Err(_) => {}
+            }
+        }
+    })
+}
+
 #[cfg(target_os = "macos")]
 fn show_about_panel() {
     unsafe {
@@ -386,6 +3617,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parse command line arguments
     let args = Args::parse();
 
+    if let Some(Action::ReplayTrace { file, every_event, print }) = args.action {
+        return replay_trace(&file, every_event, print);
+    }
+
     // Construct startup command if provided
     let startup_command = if let Some(cmd) = args.command {
         if args.command_args.is_empty() {
@@ -402,106 +3637,52 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         None
     };
 
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+    // Initialize logging; recent lines are also kept for watchdog reports
+    let log_ring = LogRing::default();
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(tracing_subscriber::EnvFilter::from_default_env()))
+        .with(log_ring.clone().with_filter(LevelFilter::INFO))
         .init();
 
     info!("Ferroterm v{} starting...", env!("CARGO_PKG_VERSION"));
 
     // Create application
-    let mut app = FerrotermApp::new(startup_command)?;
-
-    // Create window before event loop
-    let config = app.config_manager.get_config();
-
-    // Calculate window size from terminal dimensions
-    let font_size = config.ui.font_size as f32;
-    let estimated_char_width = font_size * 0.6;
-    let estimated_char_height = font_size * 1.2 * config.ui.line_height;
-
-    let window_width = (config.ui.window_width as f32 * estimated_char_width) as u32;
-    let window_height = (config.ui.window_height as f32 * estimated_char_height) as u32;
-
-    let window_attributes = WindowBuilder::new()
-        .with_title(format!("Ferroterm v{}", env!("CARGO_PKG_VERSION")))
-        .with_inner_size(winit::dpi::LogicalSize::new(window_width, window_height))
-        .with_min_inner_size(winit::dpi::LogicalSize::new(400, 200));
+    let mut app = FerrotermApp::new(startup_command, log_ring)?;
+    app.trace_path = args.record_trace;
 
     // Create event loop
     let event_loop = EventLoop::new()?;
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let window = Arc::new(window_attributes.build(&event_loop)?);
-
-    // Store window reference in app
-    app.window = Some(window.clone());
-
-    // Initialize graphics and PTY before starting event loop
-    info!("Initializing graphics and creating main PTY session...");
-    
-    // Calculate terminal dimensions
-    let window_size = window.inner_size();
-    let term_cols = (window_size.width as f32 / estimated_char_width) as u32;
-    let term_rows = (window_size.height as f32 / estimated_char_height) as u32;
-    
-    info!("Terminal grid: {}x{} ({}x{} pixels)", term_cols, term_rows, window_size.width, window_size.height);
-
-    // Create main PTY session
-    let mut pty_config = PtyConfig::default();
-    pty_config.rows = term_rows as u16;
-    pty_config.cols = term_cols as u16;
-    
-    if let Ok(shell) = std::env::var("SHELL") {
-        pty_config.shell = shell;
-    }
-    
-    app.main_pty_id = Some(app.tty_engine.create_pty(pty_config).await?);
+    // Restore the previous window layout, or open a single default window
+    let layout = SessionLayout::default_path()
+        .and_then(|path| SessionLayout::load(&path).ok())
+        .unwrap_or_default();
+
+    info!("Initializing graphics and creating PTY sessions...");
+    if layout.windows.is_empty() {
+        app.open_window(&event_loop, None)?;
+    } else {
+        for record in &layout.windows {
+            app.open_window(&event_loop, Some(record))?;
+        }
+    }
     app.is_initialized = true;
-    
-    // Start continuous PTY output reader
-    if let Some(pty_id) = app.main_pty_id {
-        let tty_engine_clone = app.tty_engine.clone();
-        let terminal_state_clone = app.terminal_state.clone();
-        tokio::spawn(async move {
-            info!("Starting continuous PTY output reader for PTY {}", pty_id);
-            loop {
-                let mut buffer = [0u8; 4096];
-                match tty_engine_clone.read_from_pty(pty_id, &mut buffer).await {
-                    Ok(bytes_read) if bytes_read > 0 => {
-                        let output = &buffer[..bytes_read];
-                        
-                        // Feed data to terminal state for parsing and rendering
-                        {
-                            let mut terminal = terminal_state_clone.write();
-                            terminal.feed_bytes(output);
-                        }
-                        
-                        // Also print to console for debugging (remove this later)
-                        let output_str = String::from_utf8_lossy(output);
-                        debug!("PTY output: {} bytes: {}", bytes_read, output_str.trim());
-                    }
-                    Ok(_) => {
-                        // No data available, wait longer to reduce CPU usage
-                        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
-                    }
-                    Err(e) => {
-                        // Only log non-timeout errors to reduce noise
-                        if !e.to_string().contains("Timeout") {
-                            error!("PTY read error: {}", e);
-                            break;
-                        }
-                        // For timeout errors, just wait a bit shorter
-                        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
-                    }
-                }
+    app.start_models().await;
+    app.start_watchdog();
+
+    // Execute startup command in the first window
+    if let Some(cmd) = app.startup_command.clone() {
+        let first_pty = app.windows.ids().first().and_then(|id| app.windows.get(id)).and_then(|w| w.active_pty());
+        if let Some(pty_id) = first_pty {
+            info!("Executing startup command: {}", cmd);
+            let cmd_with_newline = format!("{}\n", cmd);
+            if let Err(e) = app.tty_engine.write_to_pty(pty_id, cmd_with_newline.as_bytes()).await {
+                warn!("Failed to execute startup command: {}", e);
             }
-        });
-        
-        // Give the shell a moment to start and output its prompt
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
     }
-    
+
     let elapsed = app.startup_time.elapsed();
     info!("Ferroterm initialized successfully in {:?}", elapsed);
 
@@ -510,46 +3691,72 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     event_loop.run(move |event, event_loop| {
         match event {
             winit::event::Event::Resumed => {
-                // Window is already created
-            }
-            winit::event::Event::WindowEvent { event, .. } => {
-                match event {
-                    WindowEvent::CloseRequested => {
-                        info!("Close requested");
-                        // TODO: Handle shutdown properly in event loop context
-                        info!("Shutdown skipped for now (async issues in event loop context)");
-                        event_loop.exit();
-                    }
-                    WindowEvent::Resized(new_size) => {
-                        debug!("Window resized to {:?}", new_size);
-                        app.handle_window_resize(new_size);
-                    }
-                    WindowEvent::KeyboardInput { event, .. } => {
-                        app.handle_key_input(event, app.modifiers);
-                    }
-                    WindowEvent::ModifiersChanged(modifiers) => {
-                        app.modifiers = modifiers;
-                    }
-                    WindowEvent::RedrawRequested => {
-                        app.render_frame();
-
-                        // Request next frame for continuous rendering
-                        if let Some(window) = &app.window {
-                            window.request_redraw();
-                        }
-                    }
-                    _ => {}
-                }
+                // Windows are already created
+            }
+            winit::event::Event::WindowEvent { window_id, event } => {
+                app.handle_window_event(window_id, event, event_loop);
             }
             winit::event::Event::DeviceEvent { .. } => {
                 // Handle device events if needed
             }
-            winit::event::Event::AboutToWait => {
-                // Handle periodic tasks
-                if app.is_initialized {
-                    if let Some(window) = &app.window {
-                        window.request_redraw();
+            winit::event::Event::AboutToWait if app.is_initialized => {
+                // Swap in the real font once it has loaded
+                match app.startup.poll_font() {
+                    Some(Ok(font)) => {
+                        for (_, managed) in app.windows.iter_mut() {
+                            if let Some(renderer) = managed.resources.renderer.as_mut() {
+                                renderer.set_font(font.clone());
+                            }
+                        }
+                        // Lay the grids out by the font's own metrics
+                        let (width, height) = font.cell_size();
+                        let scale = app.font_scale as f32;
+                        app.windows.set_cell_size(width as f32 / scale, height as f32 / scale);
+                        app.refit_windows();
+                        info!("Startup: {}", app.startup.report().summary());
+                    }
+                    Some(Err(e)) => warn!("Keeping built-in font: {}", e),
+                    None => {}
+                }
+
+                app.handle_stalls();
+                app.apply_config_changes();
+                app.handle_pty_events(event_loop);
+                // Minimized windows get no frames; that is not a stall
+                if app.windows.iter().all(|(_, managed)| managed.resources.window.is_minimized() == Some(true)) {
+                    app.render_heartbeat.beat();
+                }
+
+                let now = Instant::now();
+                if app.idle.poll(now) {
+                    app.update_blank();
+                }
+                if app.idle.is_blanked() {
+                    app.render_heartbeat.beat();
+                    // Nothing to draw; only output and bells can wake it
+                    for id in app.windows.ids().to_vec() {
+                        app.process_output(id);
                     }
+                    event_loop.set_control_flow(ControlFlow::WaitUntil(now + BLANKED_POLL));
+                    return;
+                }
+                app.flush_key_sequence(now, event_loop);
+                app.update_ghost_text(now);
+                app.update_prompts();
+                app.update_model_swaps();
+                app.update_batches();
+                app.update_stats_overlay(now);
+                // The idle deadline and a held key sequence's timeout are the
+                // only timers; nothing wakes the loop early just to check them
+                let deadline = app.idle.deadline().into_iter().chain(app.input.sequence_deadline()).min();
+                event_loop.set_control_flow(match deadline {
+                    Some(deadline) => ControlFlow::WaitUntil(deadline),
+                    None => ControlFlow::Poll,
+                });
+
+                // Handle periodic tasks
+                for (_, managed) in app.windows.iter() {
+                    managed.resources.window.request_redraw();
                 }
             }
             _ => {}
@@ -557,4 +3764,4 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     })?;
 
     Ok(())
-}
\ No newline at end of file
+}
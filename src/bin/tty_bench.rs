@@ -50,17 +50,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Read throughput test
     sleep(Duration::from_millis(100)).await; // Let data accumulate
 
+    let mut output = engine.subscribe_output(pty_id)?;
     let mut total_read = 0;
     let start = Instant::now();
-    for _ in 0..100 {
-        let mut buffer = [0u8; 1024];
-        match engine.read_from_pty(pty_id, &mut buffer).await {
-            Ok(bytes_read) => total_read += bytes_read,
-            Err(_) => break,
-        }
-        if total_read == 0 {
-            break;
-        }
+    while let Ok(Some(batch)) = tokio::time::timeout(Duration::from_millis(100), output.recv()).await {
+        total_read += batch.len();
     }
     let read_duration = start.elapsed();
 
@@ -72,6 +66,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     engine.destroy_pty(pty_id).await?;
 
+    // Flood throughput: 100MB through cat, as in `cat bigfile`
+    let flood = 100 * 1024 * 1024;
+    let flood_config = PtyConfig {
+        shell: "/bin/sh".to_string(),
+        args: vec!["-c".to_string(), format!("head -c {} /dev/zero | cat", flood)],
+        ..config.clone()
+    };
+    let start = Instant::now();
+    let pty_id = engine.create_pty(flood_config).await?;
+    let mut output = engine.subscribe_output(pty_id)?;
+    let mut flooded = 0;
+    let mut batches = 0;
+    while let Some(batch) = output.recv().await {
+        flooded += batch.len();
+        batches += 1;
+    }
+    let flood_duration = start.elapsed();
+    println!(
+        "Flood: {} bytes in {} batches, {:?} ({:.1} MB/s)",
+        flooded,
+        batches,
+        flood_duration,
+        flooded as f64 / flood_duration.as_secs_f64() / (1024.0 * 1024.0)
+    );
+    let _ = engine.destroy_pty(pty_id).await;
+
     // Test 3: Concurrent PTY Management
     println!("\n=== Concurrent PTY Management ===");
     let engine = Arc::new(TtyEngine::new());
@@ -0,0 +1,352 @@
+use crate::input::{Key, KeyEvent, Modifier};
+use crate::paste_guard::{self, SpanStyle};
+use crate::terminal::TerminalCell;
+use regex::{Regex, RegexBuilder};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SearchError {
+    #[error("invalid pattern: {0}")]
+    Pattern(String),
+}
+
+/// A match in the history and screen read as one buffer: scrollback lines
+/// by index, then screen rows after them. Columns are cells, end exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchLocation {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub pattern: String,
+    pub case_sensitive: bool,
+    /// Treat the pattern as a regular expression rather than literal text
+    pub regex: bool,
+}
+
+impl SearchQuery {
+    pub fn compile(&self) -> Result<Regex, SearchError> {
+        let pattern = if self.regex {
+            self.pattern.clone()
+        } else {
+            regex::escape(&self.pattern)
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .build()
+            .map_err(|e| {
+                // The full message repeats the pattern with a caret under it
+                let message = e.to_string();
+                let reason = message.lines().last().unwrap_or_default();
+                SearchError::Pattern(reason.trim_start_matches("error: ").to_string())
+            })
+    }
+}
+
+/// Matches of `regex` in a row of cells. The spacer after a wide glyph is
+/// left out of the text, so matches cover whole glyphs.
+pub fn find_in_cells(
+    regex: &Regex,
+    cells: &[TerminalCell],
+    line: usize,
+    matches: &mut Vec<MatchLocation>,
+) {
+    let mut text = String::with_capacity(cells.len());
+    // Cell column where each character of `text` starts, plus the end
+    let mut columns = Vec::with_capacity(cells.len() + 1);
+    let mut column = 0;
+    while column < cells.len() {
+        let cell = &cells[column];
+        columns.push(column);
+        text.push(if cell.character == '\0' {
+            ' '
+        } else {
+            cell.character
+        });
+        column += if cell.wide { 2 } else { 1 };
+    }
+    columns.push(column.min(cells.len()));
+
+    let mut byte = 0;
+    let mut index = 0;
+    for found in regex.find_iter(&text) {
+        if found.is_empty() {
+            continue;
+        }
+        index += text[byte..found.start()].chars().count();
+        let start = columns[index];
+        index += found.as_str().chars().count();
+        byte = found.end();
+        matches.push(MatchLocation {
+            line,
+            start,
+            end: columns[index],
+        });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchOutcome {
+    Pending,
+    /// Run the query and jump to the first match
+    Search,
+    Next,
+    Previous,
+    Close,
+}
+
+/// One-line search input drawn at the bottom of the pane. After Enter the
+/// bar keeps its matches and `n`/`N` step through them.
+#[derive(Debug, Clone, Default)]
+pub struct SearchBar {
+    query: SearchQuery,
+    editing: bool,
+    matches: Vec<MatchLocation>,
+    current: Option<usize>,
+    error: Option<String>,
+    searched: bool,
+}
+
+impl SearchBar {
+    pub fn new(pattern: Option<String>) -> Self {
+        Self {
+            query: SearchQuery {
+                pattern: pattern.unwrap_or_default(),
+                ..SearchQuery::default()
+            },
+            editing: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn query(&self) -> &SearchQuery {
+        &self.query
+    }
+
+    pub fn matches(&self) -> &[MatchLocation] {
+        &self.matches
+    }
+
+    pub fn current(&self) -> Option<MatchLocation> {
+        self.current.map(|index| self.matches[index])
+    }
+
+    pub fn key(&mut self, event: &KeyEvent) -> SearchOutcome {
+        let alt = event.modifiers.contains(&Modifier::Alt);
+        let ctrl = event.modifiers.contains(&Modifier::Ctrl);
+        if event.key == Key::Escape {
+            return SearchOutcome::Close;
+        }
+        if !self.editing {
+            return match event.key {
+                Key::Char('n') if !ctrl && !alt => SearchOutcome::Next,
+                Key::Char('N') if !ctrl && !alt => SearchOutcome::Previous,
+                Key::Enter | Key::KpEnter | Key::Down => SearchOutcome::Next,
+                Key::Up => SearchOutcome::Previous,
+                Key::Char('/') | Key::Backspace => {
+                    self.editing = true;
+                    SearchOutcome::Pending
+                }
+                _ => SearchOutcome::Pending,
+            };
+        }
+        match event.key {
+            Key::Enter | Key::KpEnter if !self.query.pattern.is_empty() => {
+                self.editing = false;
+                return SearchOutcome::Search;
+            }
+            Key::Backspace => {
+                self.query.pattern.pop();
+            }
+            Key::Char('c') if alt => self.query.case_sensitive = !self.query.case_sensitive,
+            Key::Char('r') if alt => self.query.regex = !self.query.regex,
+            Key::Char(c) if !ctrl && !alt => self.query.pattern.push(c),
+            Key::Space => self.query.pattern.push(' '),
+            _ => {}
+        }
+        SearchOutcome::Pending
+    }
+
+    /// Keep what a search found; the first match becomes current
+    pub fn set_results(&mut self, results: Result<Vec<MatchLocation>, SearchError>) {
+        self.searched = true;
+        match results {
+            Ok(matches) => {
+                self.current = (!matches.is_empty()).then_some(0);
+                self.matches = matches;
+                self.error = None;
+            }
+            Err(e) => {
+                self.matches.clear();
+                self.current = None;
+                self.error = Some(e.to_string());
+                // A bad pattern goes straight back to editing
+                self.editing = true;
+            }
+        }
+    }
+
+    /// Step to the following match, wrapping after the last
+    pub fn next_match(&mut self) -> Option<MatchLocation> {
+        let len = self.matches.len();
+        self.current = self.current.map(|index| (index + 1) % len);
+        self.current()
+    }
+
+    pub fn prev_match(&mut self) -> Option<MatchLocation> {
+        let len = self.matches.len();
+        self.current = self.current.map(|index| (index + len - 1) % len);
+        self.current()
+    }
+
+    pub fn row(&self, width: u32) -> Vec<TerminalCell> {
+        let flags = format!(
+            " [{}] [{}] ",
+            if self.query.case_sensitive {
+                "Aa"
+            } else {
+                "aa"
+            },
+            if self.query.regex { ".*" } else { "ab" },
+        );
+        let status = match (&self.error, self.current) {
+            (Some(error), _) => (SpanStyle::Risky, format!(" {} ", error)),
+            (None, Some(index)) => (
+                SpanStyle::Plain,
+                format!(
+                    "{}/{} · n/N next/previous · Esc close",
+                    index + 1,
+                    self.matches.len()
+                ),
+            ),
+            (None, None) if self.searched && !self.editing => {
+                (SpanStyle::Plain, "no matches · Esc close".to_string())
+            }
+            (None, None) => (
+                SpanStyle::Plain,
+                "Enter search · Alt+C case · Alt+R regex · Esc close".to_string(),
+            ),
+        };
+        paste_guard::cells(
+            &[
+                (SpanStyle::Plain, format!(" /{}", self.query.pattern)),
+                (SpanStyle::Control, flags),
+                status,
+            ],
+            width,
+            false,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::time::Instant;
+
+    fn cells(text: &str) -> Vec<TerminalCell> {
+        let mut row = Vec::new();
+        for character in text.chars() {
+            let wide = crate::glyph_guard::char_width(character) == 2;
+            row.push(TerminalCell {
+                character,
+                wide,
+                ..TerminalCell::default()
+            });
+            if wide {
+                row.push(TerminalCell::default());
+            }
+        }
+        row
+    }
+
+    fn key(key: Key) -> KeyEvent {
+        KeyEvent {
+            key,
+            modifiers: HashSet::new(),
+            text: None,
+            repeat: false,
+            timestamp: Instant::now(),
+            key_code: None,
+        }
+    }
+
+    #[test]
+    fn test_find_literal_regex_and_case() {
+        let row = cells("Error: 日本 error 42.0");
+        let find = |query: SearchQuery| {
+            let mut matches = Vec::new();
+            find_in_cells(&query.compile().unwrap(), &row, 7, &mut matches);
+            matches.iter().map(|m| (m.start, m.end)).collect::<Vec<_>>()
+        };
+        let literal = |pattern: &str| SearchQuery {
+            pattern: pattern.to_string(),
+            ..SearchQuery::default()
+        };
+
+        assert_eq!(find(literal("error")), vec![(0, 5), (12, 17)]);
+        assert_eq!(
+            find(SearchQuery {
+                case_sensitive: true,
+                ..literal("error")
+            }),
+            vec![(12, 17)]
+        );
+        // Wide glyphs take two cells; the literal dot is not a wildcard
+        assert_eq!(find(literal("日本")), vec![(7, 11)]);
+        assert_eq!(find(literal("2.0")), vec![(19, 22)]);
+        assert_eq!(
+            find(SearchQuery {
+                regex: true,
+                ..literal(r"\d+")
+            }),
+            vec![(18, 20), (21, 22)]
+        );
+
+        let error = SearchQuery {
+            regex: true,
+            ..literal("(unclosed")
+        }
+        .compile()
+        .unwrap_err();
+        assert!(error.to_string().starts_with("invalid pattern"));
+    }
+
+    #[test]
+    fn test_bar_cycles_matches_and_reports_errors() {
+        let mut bar = SearchBar::new(None);
+        for c in "ab".chars() {
+            assert_eq!(bar.key(&key(Key::Char(c))), SearchOutcome::Pending);
+        }
+        assert_eq!(bar.query().pattern, "ab");
+        assert_eq!(bar.key(&key(Key::Enter)), SearchOutcome::Search);
+
+        let at = |line| MatchLocation {
+            line,
+            start: 0,
+            end: 2,
+        };
+        bar.set_results(Ok(vec![at(1), at(5), at(9)]));
+        assert_eq!(bar.current(), Some(at(1)));
+        assert_eq!(bar.key(&key(Key::Char('n'))), SearchOutcome::Next);
+        assert_eq!(bar.next_match(), Some(at(5)));
+        assert_eq!(bar.key(&key(Key::Char('N'))), SearchOutcome::Previous);
+        assert_eq!(bar.prev_match(), Some(at(1)));
+        assert_eq!(bar.prev_match(), Some(at(9)));
+        let row: String = bar.row(60).iter().map(|c| c.character).collect();
+        assert!(row.contains("3/3"));
+
+        bar.set_results(Err(SearchError::Pattern("unclosed group".to_string())));
+        assert_eq!(bar.current(), None);
+        let row: String = bar.row(60).iter().map(|c| c.character).collect();
+        assert!(row.contains("invalid pattern: unclosed group"));
+        // Back to editing, so typing changes the pattern again
+        bar.key(&key(Key::Char('c')));
+        assert_eq!(bar.query().pattern, "abc");
+        assert_eq!(bar.key(&key(Key::Escape)), SearchOutcome::Close);
+    }
+}
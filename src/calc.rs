@@ -0,0 +1,1006 @@
+use crate::input::Key;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// A calc failure with the character column it applies to
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum CalcError {
+    #[error("{} at column {}", .message, .position + 1)]
+    Parse { position: usize, message: String },
+    #[error("{} at column {}", .message, .position + 1)]
+    Eval { position: usize, message: String },
+}
+
+impl CalcError {
+    pub fn position(&self) -> usize {
+        match self {
+            Self::Parse { position, .. } | Self::Eval { position, .. } => *position,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Parse { message, .. } | Self::Eval { message, .. } => message,
+        }
+    }
+
+    /// The expression with a caret under the failing column
+    pub fn caret(&self, expression: &str) -> String {
+        format!(
+            "{}\n{}^ {}",
+            expression,
+            " ".repeat(self.position()),
+            self.message()
+        )
+    }
+}
+
+fn parse_error<T>(position: usize, message: impl Into<String>) -> Result<T, CalcError> {
+    Err(CalcError::Parse {
+        position,
+        message: message.into(),
+    })
+}
+
+fn eval_error<T>(position: usize, message: impl Into<String>) -> Result<T, CalcError> {
+    Err(CalcError::Eval {
+        position,
+        message: message.into(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteUnit {
+    pub name: &'static str,
+    pub factor: f64,
+}
+
+const BYTE_UNITS: &[(&str, f64)] = &[
+    ("B", 1.0),
+    ("KB", 1e3),
+    ("MB", 1e6),
+    ("GB", 1e9),
+    ("TB", 1e12),
+    ("PB", 1e15),
+    ("KiB", 1024.0),
+    ("MiB", 1048576.0),
+    ("GiB", 1073741824.0),
+    ("TiB", 1099511627776.0),
+    ("PiB", 1125899906842624.0),
+];
+
+fn byte_unit(name: &str) -> Option<ByteUnit> {
+    let name = if name == "kB" { "KB" } else { name };
+    BYTE_UNITS
+        .iter()
+        .find(|(unit, _)| *unit == name)
+        .map(|&(name, factor)| ByteUnit { name, factor })
+}
+
+const FUNCTIONS: &[&str] = &["to_hex", "to_bin", "to_oct", "iso", "unix", "now"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i128),
+    Float(f64),
+    Bytes {
+        bytes: f64,
+        unit: ByteUnit,
+    },
+    /// Unix seconds, shown as ISO 8601 UTC
+    Time(i64),
+    Text(String),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Int(_) => "an integer",
+            Self::Float(_) => "a number",
+            Self::Bytes { .. } => "a byte size",
+            Self::Time(_) => "a timestamp",
+            Self::Text(_) => "text",
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(n) => Some(*n as f64),
+            Self::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(n) => write!(f, "{}", n),
+            Self::Float(x) => f.write_str(&format_float(*x)),
+            Self::Bytes { bytes, unit } => {
+                write!(f, "{} {}", format_float(bytes / unit.factor), unit.name)
+            }
+            Self::Time(secs) => f.write_str(&format_iso(*secs)),
+            Self::Text(text) => f.write_str(text),
+        }
+    }
+}
+
+fn format_float(x: f64) -> String {
+    if !x.is_finite() {
+        return x.to_string();
+    }
+    if x != 0.0 && !(1e-6..1e15).contains(&x.abs()) {
+        return format!("{:e}", x);
+    }
+    let fixed = format!("{:.10}", x);
+    let trimmed = fixed.trim_end_matches('0').trim_end_matches('.');
+    if trimmed == "-0" {
+        "0".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Int(i128),
+    Float(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    tok: Tok,
+    pos: usize,
+}
+
+const OPERATORS: &[&str] = &[
+    "**", "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, CalcError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let tok = if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit()))
+        {
+            let (tok, end) = lex_number(&chars, i)?;
+            i = end;
+            tok
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            Tok::Ident(chars[start..i].iter().collect())
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                i += 1;
+            }
+            if i == chars.len() {
+                return parse_error(start, "unterminated string");
+            }
+            i += 1;
+            Tok::Str(chars[start + 1..i - 1].iter().collect())
+        } else if c == '(' {
+            i += 1;
+            Tok::LParen
+        } else if c == ')' {
+            i += 1;
+            Tok::RParen
+        } else if c == ',' {
+            i += 1;
+            Tok::Comma
+        } else if let Some(op) = OPERATORS.iter().find(|op| {
+            op.chars()
+                .enumerate()
+                .all(|(k, oc)| chars.get(i + k) == Some(&oc))
+        }) {
+            i += op.len();
+            Tok::Op(op)
+        } else {
+            return parse_error(start, format!("unexpected '{}'", c));
+        };
+        tokens.push(Token { tok, pos: start });
+    }
+    tokens.push(Token {
+        tok: Tok::End,
+        pos: chars.len(),
+    });
+    Ok(tokens)
+}
+
+fn lex_number(chars: &[char], start: usize) -> Result<(Tok, usize), CalcError> {
+    let radix = match (chars[start], chars.get(start + 1)) {
+        ('0', Some('x')) => Some(16),
+        ('0', Some('o')) => Some(8),
+        ('0', Some('b')) => Some(2),
+        _ => None,
+    };
+    if let Some(radix) = radix {
+        let mut i = start + 2;
+        let mut digits = String::new();
+        while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+            if chars[i] != '_' {
+                if !chars[i].is_digit(radix) {
+                    return parse_error(
+                        i,
+                        format!("invalid digit '{}' for base {}", chars[i], radix),
+                    );
+                }
+                digits.push(chars[i]);
+            }
+            i += 1;
+        }
+        if digits.is_empty() {
+            return parse_error(start, "missing digits after base prefix");
+        }
+        return match i128::from_str_radix(&digits, radix) {
+            Ok(n) => Ok((Tok::Int(n), i)),
+            Err(_) => parse_error(start, "integer literal too large"),
+        };
+    }
+
+    let mut i = start;
+    let mut text = String::new();
+    let mut float = false;
+    while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '_') {
+        if chars[i] != '_' {
+            text.push(chars[i]);
+        }
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit()) {
+        float = true;
+        text.push('.');
+        i += 1;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            text.push(chars[i]);
+            i += 1;
+        }
+    }
+    // An exponent only when digits follow, so `2EiB` stays a unit
+    if matches!(chars.get(i), Some('e') | Some('E')) {
+        let sign = matches!(chars.get(i + 1), Some('+') | Some('-'));
+        let digit_at = if sign { i + 2 } else { i + 1 };
+        if chars.get(digit_at).is_some_and(|d| d.is_ascii_digit()) {
+            float = true;
+            text.extend(&chars[i..digit_at]);
+            i = digit_at;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                text.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    if float {
+        match text.parse() {
+            Ok(x) => Ok((Tok::Float(x), i)),
+            Err(_) => parse_error(start, "invalid number"),
+        }
+    } else {
+        match text.parse() {
+            Ok(n) => Ok((Tok::Int(n), i)),
+            Err(_) => parse_error(start, "integer literal too large"),
+        }
+    }
+}
+
+/// What `in`/`as` converts to
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Unit(ByteUnit),
+    Radix(u32),
+    Decimal,
+    Unix,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    kind: ExprKind,
+    pos: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ExprKind {
+    Value(Value),
+    Str(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    WithUnit(Box<Expr>, ByteUnit),
+    Call(String, Vec<Expr>),
+    Convert(Box<Expr>, Target),
+}
+
+const UNARY_BP: u8 = 13;
+
+fn binding_power(op: &str) -> (u8, u8) {
+    match op {
+        "|" => (1, 2),
+        "^" => (3, 4),
+        "&" => (5, 6),
+        "<<" | ">>" => (7, 8),
+        "+" | "-" => (9, 10),
+        "*" | "/" | "%" => (11, 12),
+        // Right-associative, and tighter than unary minus: -2**2 is -4
+        "**" => (15, 14),
+        _ => (0, 0),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.index]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.index].clone();
+        if token.tok != Tok::End {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, tok: Tok, what: &str) -> Result<(), CalcError> {
+        let token = self.next();
+        if token.tok == tok {
+            Ok(())
+        } else {
+            parse_error(token.pos, format!("expected {}", what))
+        }
+    }
+
+    fn top(&mut self) -> Result<Expr, CalcError> {
+        let mut expr = self.expr(0)?;
+        while let Tok::Ident(word) = &self.peek().tok
+            && (word == "in" || word == "as")
+        {
+            let pos = self.next().pos;
+            let token = self.next();
+            let target = match &token.tok {
+                Tok::Ident(name) => match name.as_str() {
+                    "hex" => Target::Radix(16),
+                    "oct" => Target::Radix(8),
+                    "bin" => Target::Radix(2),
+                    "dec" => Target::Decimal,
+                    "unix" => Target::Unix,
+                    _ => match byte_unit(name) {
+                        Some(unit) => Target::Unit(unit),
+                        None => return parse_error(token.pos, format!("unknown unit '{}'", name)),
+                    },
+                },
+                _ => return parse_error(token.pos, "expected a unit or base"),
+            };
+            expr = Expr {
+                kind: ExprKind::Convert(Box::new(expr), target),
+                pos,
+            };
+        }
+        let token = self.peek();
+        if token.tok != Tok::End {
+            return parse_error(token.pos, "expected an operator");
+        }
+        Ok(expr)
+    }
+
+    fn expr(&mut self, min_bp: u8) -> Result<Expr, CalcError> {
+        let mut lhs = self.prefix()?;
+        loop {
+            let token = self.peek().clone();
+            let Tok::Op(op) = token.tok else { break };
+            let (lbp, rbp) = binding_power(op);
+            if lbp == 0 {
+                return parse_error(token.pos, format!("'{}' is not a binary operator", op));
+            }
+            if lbp < min_bp {
+                break;
+            }
+            self.next();
+            let rhs = self.expr(rbp)?;
+            lhs = Expr {
+                kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+                pos: token.pos,
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn prefix(&mut self) -> Result<Expr, CalcError> {
+        if let Tok::Op(op @ ("-" | "+" | "~")) = self.peek().tok {
+            let pos = self.next().pos;
+            let operand = self.expr(UNARY_BP)?;
+            return Ok(Expr {
+                kind: ExprKind::Unary(op, Box::new(operand)),
+                pos,
+            });
+        }
+        let primary = self.primary()?;
+        if let Tok::Ident(name) = &self.peek().tok
+            && let Some(unit) = byte_unit(name)
+        {
+            let pos = self.next().pos;
+            return Ok(Expr {
+                kind: ExprKind::WithUnit(Box::new(primary), unit),
+                pos,
+            });
+        }
+        Ok(primary)
+    }
+
+    fn primary(&mut self) -> Result<Expr, CalcError> {
+        let token = self.next();
+        let kind = match token.tok {
+            Tok::Int(n) => ExprKind::Value(Value::Int(n)),
+            Tok::Float(x) => ExprKind::Value(Value::Float(x)),
+            Tok::Str(s) => ExprKind::Str(s),
+            Tok::LParen => {
+                let inner = self.expr(0)?;
+                self.expect(Tok::RParen, "')'")?;
+                return Ok(inner);
+            }
+            Tok::Ident(name) => {
+                if !FUNCTIONS.contains(&name.as_str()) {
+                    return parse_error(token.pos, format!("unknown name '{}'", name));
+                }
+                self.expect(Tok::LParen, "'(' after function name")?;
+                let mut args = Vec::new();
+                if self.peek().tok == Tok::RParen {
+                    self.next();
+                } else {
+                    loop {
+                        args.push(self.expr(0)?);
+                        let next = self.next();
+                        match next.tok {
+                            Tok::Comma => continue,
+                            Tok::RParen => break,
+                            _ => return parse_error(next.pos, "expected ',' or ')'"),
+                        }
+                    }
+                }
+                ExprKind::Call(name, args)
+            }
+            _ => return parse_error(token.pos, "expected a value"),
+        };
+        Ok(Expr {
+            kind,
+            pos: token.pos,
+        })
+    }
+}
+
+/// Parses without evaluating
+pub fn parse(input: &str) -> Result<Expr, CalcError> {
+    let tokens = tokenize(input)?;
+    if tokens.len() == 1 {
+        return parse_error(0, "expected a value");
+    }
+    Parser { tokens, index: 0 }.top()
+}
+
+/// Whether a prefix line should be evaluated locally instead of prompting
+/// a model. A bare number stays a prompt.
+pub fn is_expression(input: &str) -> bool {
+    matches!(parse(input), Ok(expr) if !matches!(expr.kind, ExprKind::Value(_)))
+}
+
+pub fn evaluate(input: &str) -> Result<Value, CalcError> {
+    eval(&parse(input)?)
+}
+
+fn eval(expr: &Expr) -> Result<Value, CalcError> {
+    let pos = expr.pos;
+    match &expr.kind {
+        ExprKind::Value(value) => Ok(value.clone()),
+        ExprKind::Str(_) => eval_error(pos, "text is only allowed as a function argument"),
+        ExprKind::Unary(op, operand) => unary(op, eval(operand)?, pos),
+        ExprKind::Binary(op, lhs, rhs) => binary(op, eval(lhs)?, eval(rhs)?, pos),
+        ExprKind::WithUnit(inner, unit) => match eval(inner)?.as_f64() {
+            Some(n) => Ok(Value::Bytes {
+                bytes: n * unit.factor,
+                unit: *unit,
+            }),
+            None => eval_error(pos, format!("{} needs a number", unit.name)),
+        },
+        ExprKind::Call(name, args) => call(name, args, pos),
+        ExprKind::Convert(inner, target) => convert(eval(inner)?, target, pos),
+    }
+}
+
+fn unary(op: &str, value: Value, pos: usize) -> Result<Value, CalcError> {
+    match (op, value) {
+        ("+", v @ (Value::Int(_) | Value::Float(_) | Value::Bytes { .. })) => Ok(v),
+        ("-", Value::Int(n)) => n.checked_neg().map(Value::Int).ok_or_else(|| overflow(pos)),
+        ("-", Value::Float(x)) => Ok(Value::Float(-x)),
+        ("-", Value::Bytes { bytes, unit }) => Ok(Value::Bytes {
+            bytes: -bytes,
+            unit,
+        }),
+        ("~", Value::Int(n)) => Ok(Value::Int(!n)),
+        (op, v) => eval_error(pos, format!("cannot apply '{}' to {}", op, v.kind())),
+    }
+}
+
+fn overflow(pos: usize) -> CalcError {
+    CalcError::Eval {
+        position: pos,
+        message: "integer overflow".to_string(),
+    }
+}
+
+fn binary(op: &str, lhs: Value, rhs: Value, pos: usize) -> Result<Value, CalcError> {
+    use Value::*;
+    let mismatch = |lhs: &Value, rhs: &Value| {
+        eval_error(
+            pos,
+            format!("cannot apply '{}' to {} and {}", op, lhs.kind(), rhs.kind()),
+        )
+    };
+    match (&lhs, &rhs) {
+        (Int(a), Int(b)) => int_binary(op, *a, *b, pos),
+        (Int(_) | Float(_), Int(_) | Float(_)) => {
+            let (a, b) = (lhs.as_f64().unwrap_or(0.0), rhs.as_f64().unwrap_or(0.0));
+            float_binary(op, a, b, pos)
+        }
+        (Bytes { bytes: a, unit: ua }, Bytes { bytes: b, unit: ub }) => match op {
+            "+" | "-" => Ok(Bytes {
+                bytes: if op == "+" { a + b } else { a - b },
+                unit: if ua.factor >= ub.factor { *ua } else { *ub },
+            }),
+            "/" if *b == 0.0 => eval_error(pos, "division by zero"),
+            "/" => Ok(Float(a / b)),
+            _ => mismatch(&lhs, &rhs),
+        },
+        (Bytes { bytes, unit }, Int(_) | Float(_)) => {
+            let n = rhs.as_f64().unwrap_or(0.0);
+            match op {
+                "*" => Ok(Bytes {
+                    bytes: bytes * n,
+                    unit: *unit,
+                }),
+                "/" if n == 0.0 => eval_error(pos, "division by zero"),
+                "/" => Ok(Bytes {
+                    bytes: bytes / n,
+                    unit: *unit,
+                }),
+                _ => mismatch(&lhs, &rhs),
+            }
+        }
+        (Int(_) | Float(_), Bytes { bytes, unit }) if op == "*" => Ok(Bytes {
+            bytes: lhs.as_f64().unwrap_or(0.0) * bytes,
+            unit: *unit,
+        }),
+        (Time(t), Int(n)) if op == "+" || op == "-" => {
+            let n = i64::try_from(*n).map_err(|_| overflow(pos))?;
+            let shifted = if op == "+" {
+                t.checked_add(n)
+            } else {
+                t.checked_sub(n)
+            };
+            shifted.map(Time).ok_or_else(|| overflow(pos))
+        }
+        (Int(n), Time(t)) if op == "+" => {
+            let n = i64::try_from(*n).map_err(|_| overflow(pos))?;
+            t.checked_add(n).map(Time).ok_or_else(|| overflow(pos))
+        }
+        (Time(a), Time(b)) if op == "-" => Ok(Int(*a as i128 - *b as i128)),
+        _ => mismatch(&lhs, &rhs),
+    }
+}
+
+fn int_binary(op: &str, a: i128, b: i128, pos: usize) -> Result<Value, CalcError> {
+    let result = match op {
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "/" | "%" if b == 0 => return eval_error(pos, "division by zero"),
+        "/" if a % b != 0 => return Ok(Value::Float(a as f64 / b as f64)),
+        "/" => a.checked_div(b),
+        "%" => a.checked_rem(b),
+        "**" if b < 0 => return Ok(Value::Float((a as f64).powf(b as f64))),
+        "**" => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+        "&" => Some(a & b),
+        "|" => Some(a | b),
+        "^" => Some(a ^ b),
+        "<<" | ">>" if !(0..128).contains(&b) => {
+            return eval_error(pos, "shift amount must be between 0 and 127");
+        }
+        "<<" => a.checked_shl(b as u32),
+        ">>" => a.checked_shr(b as u32),
+        _ => None,
+    };
+    result.map(Value::Int).ok_or_else(|| overflow(pos))
+}
+
+fn float_binary(op: &str, a: f64, b: f64, pos: usize) -> Result<Value, CalcError> {
+    match op {
+        "+" => Ok(Value::Float(a + b)),
+        "-" => Ok(Value::Float(a - b)),
+        "*" => Ok(Value::Float(a * b)),
+        "/" | "%" if b == 0.0 => eval_error(pos, "division by zero"),
+        "/" => Ok(Value::Float(a / b)),
+        "%" => Ok(Value::Float(a % b)),
+        "**" => Ok(Value::Float(a.powf(b))),
+        _ => eval_error(pos, format!("'{}' needs integers", op)),
+    }
+}
+
+fn call(name: &str, args: &[Expr], pos: usize) -> Result<Value, CalcError> {
+    let arity = if name == "now" { 0 } else { 1 };
+    if args.len() != arity {
+        return eval_error(
+            pos,
+            format!(
+                "{}() takes {} argument{}",
+                name,
+                arity,
+                if arity == 1 { "" } else { "s" }
+            ),
+        );
+    }
+    match name {
+        "now" => {
+            let secs = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            Ok(Value::Time(secs))
+        }
+        "to_hex" => convert(eval(&args[0])?, &Target::Radix(16), args[0].pos),
+        "to_bin" => convert(eval(&args[0])?, &Target::Radix(2), args[0].pos),
+        "to_oct" => convert(eval(&args[0])?, &Target::Radix(8), args[0].pos),
+        "iso" => match eval(&args[0])? {
+            Value::Int(n) => i64::try_from(n).map(Value::Time).map_err(|_| overflow(pos)),
+            Value::Float(x) => Ok(Value::Time(x.floor() as i64)),
+            Value::Time(t) => Ok(Value::Time(t)),
+            other => eval_error(
+                args[0].pos,
+                format!("iso() needs unix seconds, not {}", other.kind()),
+            ),
+        },
+        "unix" => match &args[0].kind {
+            // Point errors inside the string, past the opening quote
+            ExprKind::Str(text) => parse_iso(text)
+                .map(|secs| Value::Int(secs as i128))
+                .map_err(|(offset, message)| CalcError::Eval {
+                    position: args[0].pos + 1 + offset,
+                    message,
+                }),
+            _ => convert(eval(&args[0])?, &Target::Unix, args[0].pos),
+        },
+        _ => eval_error(pos, format!("unknown function '{}'", name)),
+    }
+}
+
+fn convert(value: Value, target: &Target, pos: usize) -> Result<Value, CalcError> {
+    match (target, value) {
+        (Target::Unit(unit), Value::Bytes { bytes, .. }) => Ok(Value::Bytes { bytes, unit: *unit }),
+        (Target::Unit(unit), v @ (Value::Int(_) | Value::Float(_))) => Ok(Value::Bytes {
+            bytes: v.as_f64().unwrap_or(0.0),
+            unit: *unit,
+        }),
+        (Target::Radix(radix), Value::Int(n)) => {
+            let (prefix, digits) = match radix {
+                16 => ("0x", format!("{:x}", n.unsigned_abs())),
+                8 => ("0o", format!("{:o}", n.unsigned_abs())),
+                _ => ("0b", format!("{:b}", n.unsigned_abs())),
+            };
+            let sign = if n < 0 { "-" } else { "" };
+            Ok(Value::Text(format!("{}{}{}", sign, prefix, digits)))
+        }
+        (Target::Decimal, v @ (Value::Int(_) | Value::Float(_))) => Ok(v),
+        (Target::Decimal, Value::Bytes { bytes, .. }) => Ok(Value::Float(bytes)),
+        (Target::Unix, Value::Time(t)) => Ok(Value::Int(t as i128)),
+        (_, v) => eval_error(pos, format!("cannot convert {}", v.kind())),
+    }
+}
+
+// Proleptic Gregorian calendar conversions (H. Hinnant's algorithms)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+pub fn format_iso(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let rem = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Parses `YYYY-MM-DD[(T| )HH:MM[:SS[.frac]]][Z|±HH:MM]` to unix seconds.
+/// Errors carry the offset into `text`.
+pub fn parse_iso(text: &str) -> Result<i64, (usize, String)> {
+    let bytes = text.as_bytes();
+    let mut at = 0;
+    let field = |at: &mut usize, len: usize, what: &str| -> Result<i64, (usize, String)> {
+        let digits = bytes
+            .get(*at..*at + len)
+            .filter(|d| d.iter().all(u8::is_ascii_digit));
+        match digits {
+            Some(d) => {
+                *at += len;
+                Ok(std::str::from_utf8(d).unwrap_or("0").parse().unwrap_or(0))
+            }
+            None => Err((*at, format!("expected {}", what))),
+        }
+    };
+    let expect = |at: &mut usize, sep: u8| -> Result<(), (usize, String)> {
+        if bytes.get(*at) == Some(&sep) {
+            *at += 1;
+            Ok(())
+        } else {
+            Err((*at, format!("expected '{}'", sep as char)))
+        }
+    };
+
+    let year = field(&mut at, 4, "a 4-digit year")?;
+    expect(&mut at, b'-')?;
+    let month = field(&mut at, 2, "a 2-digit month")?;
+    expect(&mut at, b'-')?;
+    let day = field(&mut at, 2, "a 2-digit day")?;
+    if !(1..=12).contains(&month) {
+        return Err((5, "month out of range".to_string()));
+    }
+    if !(1..=31).contains(&day) {
+        return Err((8, "day out of range".to_string()));
+    }
+    let mut secs = days_from_civil(year, month, day) * 86400;
+
+    if matches!(bytes.get(at), Some(b'T') | Some(b' ')) {
+        at += 1;
+        let hour = field(&mut at, 2, "a 2-digit hour")?;
+        expect(&mut at, b':')?;
+        let minute = field(&mut at, 2, "2-digit minutes")?;
+        let second = if bytes.get(at) == Some(&b':') {
+            at += 1;
+            field(&mut at, 2, "2-digit seconds")?
+        } else {
+            0
+        };
+        if hour > 23 || minute > 59 || second > 60 {
+            return Err((11, "time out of range".to_string()));
+        }
+        // Fractional seconds are dropped
+        if bytes.get(at) == Some(&b'.') {
+            at += 1;
+            while bytes.get(at).is_some_and(u8::is_ascii_digit) {
+                at += 1;
+            }
+        }
+        secs += hour * 3600 + minute * 60 + second;
+    }
+
+    match bytes.get(at) {
+        None => {}
+        Some(b'Z') => at += 1,
+        Some(&sign @ (b'+' | b'-')) => {
+            at += 1;
+            let hours = field(&mut at, 2, "a 2-digit offset hour")?;
+            if bytes.get(at) == Some(&b':') {
+                at += 1;
+            }
+            let minutes = field(&mut at, 2, "2-digit offset minutes")?;
+            let offset = hours * 3600 + minutes * 60;
+            secs += if sign == b'+' { -offset } else { offset };
+        }
+        Some(_) => return Err((at, "expected 'Z' or a UTC offset".to_string())),
+    }
+    if at != bytes.len() {
+        return Err((at, "unexpected text after timestamp".to_string()));
+    }
+    Ok(secs)
+}
+
+/// What a key does to an inline calc block
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcKeyOutcome {
+    Copy(String),
+    Insert(String),
+    Dismiss,
+    Ignored,
+}
+
+/// A `:calc` result shown inline under the prompt, like an `:ask` answer.
+/// `y` copies the result, Enter or Tab types it at the shell prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalcBlock {
+    pub expression: String,
+    pub outcome: Result<Value, CalcError>,
+}
+
+impl CalcBlock {
+    pub fn new(expression: &str) -> Self {
+        let expression = expression.trim().to_string();
+        let outcome = evaluate(&expression);
+        Self {
+            expression,
+            outcome,
+        }
+    }
+
+    pub fn result_text(&self) -> Option<String> {
+        self.outcome.as_ref().ok().map(|value| value.to_string())
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        match &self.outcome {
+            Ok(value) => vec![self.expression.clone(), format!("= {}", value)],
+            Err(error) => error
+                .caret(&self.expression)
+                .lines()
+                .map(String::from)
+                .collect(),
+        }
+    }
+
+    pub fn key(&self, key: &Key) -> CalcKeyOutcome {
+        match (key, self.result_text()) {
+            (Key::Char('y'), Some(text)) => CalcKeyOutcome::Copy(text),
+            (Key::Enter | Key::Tab, Some(text)) => CalcKeyOutcome::Insert(text),
+            (Key::Escape, _) => CalcKeyOutcome::Dismiss,
+            _ => CalcKeyOutcome::Ignored,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_str(input: &str) -> String {
+        evaluate(input)
+            .unwrap_or_else(|e| panic!("{}: {}", input, e))
+            .to_string()
+    }
+
+    #[test]
+    fn test_expression_corpus() {
+        let corpus = [
+            // Precedence and associativity
+            ("1 + 2 * 3", "7"),
+            ("(1 + 2) * 3", "9"),
+            ("2 ** 3 ** 2", "512"),
+            ("-2 ** 2", "-4"),
+            ("1 | 2 ^ 3 & 6", "1"),
+            ("1 << 4 + 1", "32"),
+            ("7 / 2", "3.5"),
+            ("8 / 2", "4"),
+            ("-7 % 3", "-1"),
+            ("0.1 + 0.2", "0.3"),
+            ("~0", "-1"),
+            // Bases
+            ("0xff", "255"),
+            ("0b1010 + 0o17", "25"),
+            ("0xdead_beef & 0xffff", "48879"),
+            ("to_hex(255)", "0xff"),
+            ("to_bin(10)", "0b1010"),
+            ("to_hex(-16)", "-0x10"),
+            ("4096 in hex", "0x1000"),
+            // Byte sizes
+            ("1 GiB + 512 MiB", "1.5 GiB"),
+            ("1.5GB in MB", "1500 MB"),
+            ("1 GiB in MB", "1073.741824 MB"),
+            ("10 MiB / 4", "2.5 MiB"),
+            ("1 GiB / 1 MiB", "1024"),
+            ("3 * 512 KiB", "1536 KiB"),
+            // Timestamps
+            ("iso(0)", "1970-01-01T00:00:00Z"),
+            ("iso(1700000000)", "2023-11-14T22:13:20Z"),
+            ("unix(\"2023-11-14T22:13:20Z\")", "1700000000"),
+            ("unix('2024-02-29')", "1709164800"),
+            ("unix('2024-01-01T02:00:00+02:00')", "1704067200"),
+            ("iso(1700000000) + 86400", "2023-11-15T22:13:20Z"),
+            ("iso(86400) - iso(0)", "86400"),
+            ("iso(-1)", "1969-12-31T23:59:59Z"),
+        ];
+        for (input, expected) in corpus {
+            assert_eq!(eval_str(input), expected, "{}", input);
+        }
+        assert!(is_expression("0xff * 2"));
+        assert!(!is_expression("42"));
+        assert!(!is_expression("how big is 1 GiB"));
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let cases = [
+            ("1 + * 2", 4, "expected a value"),
+            ("(1 + 2", 6, "expected ')'"),
+            ("2 $ 3", 2, "unexpected '$'"),
+            ("0x", 0, "missing digits after base prefix"),
+            ("0b102", 4, "invalid digit '2' for base 2"),
+            ("1 + foo", 4, "unknown name 'foo'"),
+            ("1 2", 2, "expected an operator"),
+            ("4 in parsecs", 5, "unknown unit 'parsecs'"),
+            ("1.5 & 1", 4, "'&' needs integers"),
+            ("10 / (5 - 5)", 3, "division by zero"),
+            (
+                "1 GiB * 1 GiB",
+                6,
+                "cannot apply '*' to a byte size and a byte size",
+            ),
+            ("unix('2023-13-01')", 11, "month out of range"),
+            ("unix('2023-11-14X')", 16, "expected 'Z' or a UTC offset"),
+            ("2 ** 200", 2, "integer overflow"),
+        ];
+        for (input, position, message) in cases {
+            let error = evaluate(input).unwrap_err();
+            assert_eq!(
+                (error.position(), error.message()),
+                (position, message),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            evaluate("1 + * 2").unwrap_err().caret("1 + * 2"),
+            "1 + * 2\n    ^ expected a value"
+        );
+    }
+
+    #[test]
+    fn test_calc_block_keys() {
+        let block = CalcBlock::new(" 0x10 * 2 ");
+        assert_eq!(block.lines(), vec!["0x10 * 2", "= 32"]);
+        assert_eq!(
+            block.key(&Key::Char('y')),
+            CalcKeyOutcome::Copy("32".to_string())
+        );
+        assert_eq!(
+            block.key(&Key::Enter),
+            CalcKeyOutcome::Insert("32".to_string())
+        );
+        assert_eq!(block.key(&Key::Char('q')), CalcKeyOutcome::Ignored);
+
+        let failed = CalcBlock::new("1 +");
+        assert_eq!(failed.lines(), vec!["1 +", "   ^ expected a value"]);
+        assert_eq!(failed.key(&Key::Enter), CalcKeyOutcome::Ignored);
+        assert_eq!(failed.key(&Key::Escape), CalcKeyOutcome::Dismiss);
+    }
+}
@@ -0,0 +1,149 @@
+use base64::{Engine as _, engine::general_purpose};
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Error, Debug)]
+pub enum ClipboardError {
+    #[error("no clipboard tool worked (tried {0})")]
+    NoTool(String),
+    #[error("no clipboard tool worked and stdout is not a terminal for OSC 52")]
+    NoTerminal,
+    #[error("OSC 52 write failed: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Where a copy ended up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The desktop clipboard, through the platform's copy tool
+    System,
+    /// An OSC 52 sequence to the terminal Ferroterm itself runs in
+    Osc52,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Copied {
+    pub backend: Backend,
+    pub chars: usize,
+    /// The text was cut at the size limit
+    pub truncated: bool,
+}
+
+/// Copies text to the desktop clipboard, falling back to OSC 52 when there
+/// is no display server, e.g. when running over SSH
+#[derive(Debug, Clone)]
+pub struct Clipboard {
+    /// Bytes kept of a copy; 0 means no limit
+    max_bytes: usize,
+    osc52: bool,
+}
+
+impl Clipboard {
+    pub fn new(max_kb: u32, osc52: bool) -> Self {
+        Self {
+            max_bytes: max_kb as usize * 1024,
+            osc52,
+        }
+    }
+
+    pub fn copy(&self, text: &str) -> Result<Copied, ClipboardError> {
+        let (text, truncated) = truncate(text, self.max_bytes);
+        if truncated {
+            warn!(
+                "Clipboard copy cut to {} of its bytes (limit {})",
+                text.len(),
+                self.max_bytes
+            );
+        }
+        let backend = match copy_system(text) {
+            Ok(()) => Backend::System,
+            Err(e) if self.osc52 => {
+                warn!("{}; falling back to OSC 52", e);
+                let mut stdout = std::io::stdout();
+                if !stdout.is_terminal() {
+                    return Err(ClipboardError::NoTerminal);
+                }
+                stdout.write_all(osc52_sequence(text).as_bytes())?;
+                stdout.flush()?;
+                Backend::Osc52
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Copied {
+            backend,
+            chars: text.chars().count(),
+            truncated,
+        })
+    }
+}
+
+/// The longest prefix of `text` within `max_bytes`, ending on a character
+/// boundary, and whether anything was cut. A limit of 0 keeps everything.
+pub fn truncate(text: &str, max_bytes: usize) -> (&str, bool) {
+    if max_bytes == 0 || text.len() <= max_bytes {
+        return (text, false);
+    }
+    let end = (0..=max_bytes)
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or(0);
+    (&text[..end], true)
+}
+
+/// Sets the clipboard of the terminal that reads it
+pub fn osc52_sequence(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", general_purpose::STANDARD.encode(text))
+}
+
+fn copy_system(text: &str) -> Result<(), ClipboardError> {
+    let candidates: &[&[&str]] = if cfg!(target_os = "macos") {
+        &[&["pbcopy"]]
+    } else {
+        &[
+            &["wl-copy"],
+            &["xclip", "-i", "-selection", "clipboard"],
+            &["xsel", "-ib"],
+        ]
+    };
+    let copied = candidates.iter().any(|command| {
+        let spawned = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .spawn();
+        let Ok(mut child) = spawned else {
+            return false;
+        };
+        let written = child
+            .stdin
+            .take()
+            .is_some_and(|mut stdin| stdin.write_all(text.as_bytes()).is_ok());
+        child.wait().is_ok_and(|status| status.success()) && written
+    });
+    if copied {
+        Ok(())
+    } else {
+        let tried: Vec<_> = candidates.iter().map(|command| command[0]).collect();
+        Err(ClipboardError::NoTool(tried.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_on_char_boundary() {
+        assert_eq!(truncate("hello", 0), ("hello", false));
+        assert_eq!(truncate("hello", 5), ("hello", false));
+        assert_eq!(truncate("hello", 3), ("hel", true));
+        // "é" is two bytes; cutting inside it drops the whole character
+        assert_eq!(truncate("aé", 2), ("a", true));
+    }
+
+    #[test]
+    fn test_osc52_sequence() {
+        assert_eq!(osc52_sequence("hi"), "\x1b]52;c;aGk=\x07");
+    }
+}
@@ -0,0 +1,247 @@
+use crate::config::UiConfig;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ColumnGuideError {
+    #[error("Invalid guide column: {0}")]
+    InvalidColumn(String),
+}
+
+/// Appearance and placement of column guides, taken from `[ui]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuideStyle {
+    pub columns: Vec<u32>,
+    pub margin_shading: bool,
+    pub line_color: [f32; 4],
+    pub margin_color: [f32; 4],
+    /// Guide line width as a fraction of the cell width
+    pub line_width: f32,
+}
+
+impl GuideStyle {
+    pub fn from_config(ui: &UiConfig) -> Self {
+        let (line_color, margin_color) = match ui.theme.as_str() {
+            "light" => ([0.0, 0.0, 0.0, 0.12], [0.0, 0.0, 0.0, 0.04]),
+            _ => ([1.0, 1.0, 1.0, 0.12], [1.0, 1.0, 1.0, 0.04]),
+        };
+        Self {
+            columns: normalize(ui.column_guides.clone()),
+            margin_shading: ui.margin_shading,
+            line_color,
+            margin_color,
+            line_width: 0.1,
+        }
+    }
+}
+
+fn normalize(mut columns: Vec<u32>) -> Vec<u32> {
+    columns.retain(|&c| c > 0);
+    columns.sort_unstable();
+    columns.dedup();
+    columns
+}
+
+/// Parse the value of `:set guide ...`: a column list ("100", "80,100"),
+/// "off" to hide guides in this pane, or "default" to drop the override.
+/// Returns `None` for "default".
+pub fn parse_guide_setting(value: &str) -> Result<Option<Vec<u32>>, ColumnGuideError> {
+    match value.trim() {
+        "default" => Ok(None),
+        "off" | "none" => Ok(Some(Vec::new())),
+        list => list
+            .split([',', ' '])
+            .filter(|s| !s.is_empty())
+            .map(|s| match s.parse::<u32>() {
+                Ok(col) if col > 0 => Ok(col),
+                _ => Err(ColumnGuideError::InvalidColumn(s.to_string())),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|cols| Some(normalize(cols))),
+    }
+}
+
+/// Per-pane guide columns that take precedence over the global config
+#[derive(Debug, Clone, Default)]
+pub struct GuideOverrides {
+    panes: HashMap<u64, Vec<u32>>,
+}
+
+impl GuideOverrides {
+    pub fn set(&mut self, pane_id: u64, setting: Option<Vec<u32>>) {
+        match setting {
+            Some(columns) => {
+                self.panes.insert(pane_id, normalize(columns));
+            }
+            None => {
+                self.panes.remove(&pane_id);
+            }
+        }
+    }
+
+    pub fn remove_pane(&mut self, pane_id: u64) {
+        self.panes.remove(&pane_id);
+    }
+
+    pub fn columns<'a>(&'a self, pane_id: u64, style: &'a GuideStyle) -> &'a [u32] {
+        self.panes.get(&pane_id).unwrap_or(&style.columns)
+    }
+}
+
+/// Pixel geometry of a pane's content area (pane rectangle plus padding)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContentArea {
+    pub x: f32,
+    pub y: f32,
+    pub cell_width: f32,
+    pub cell_height: f32,
+    pub cols: u32,
+    pub rows: u32,
+}
+
+impl ContentArea {
+    pub fn width(&self) -> f32 {
+        self.cols as f32 * self.cell_width
+    }
+
+    pub fn height(&self) -> f32 {
+        self.rows as f32 * self.cell_height
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuideQuadKind {
+    Margin,
+    Line,
+}
+
+/// Overlay quad in window pixels. Drawn after cell backgrounds and before
+/// glyphs; never part of grid content.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuideQuad {
+    pub kind: GuideQuadKind,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub color: [f32; 4],
+}
+
+/// Quads for one pane: at most one margin tint plus one line per visible
+/// guide, independent of the number of rows. Margin quads come first so
+/// lines draw on top of them.
+pub fn guide_quads(area: &ContentArea, columns: &[u32], style: &GuideStyle) -> Vec<GuideQuad> {
+    let mut quads = Vec::with_capacity(columns.len() + 1);
+    let visible: Vec<u32> = columns
+        .iter()
+        .copied()
+        .filter(|&c| c > 0 && c < area.cols)
+        .collect();
+
+    if style.margin_shading
+        && let Some(&last) = visible.last()
+    {
+        let x = area.x + last as f32 * area.cell_width;
+        quads.push(GuideQuad {
+            kind: GuideQuadKind::Margin,
+            x,
+            y: area.y,
+            width: area.x + area.width() - x,
+            height: area.height(),
+            color: style.margin_color,
+        });
+    }
+
+    let line_width = (area.cell_width * style.line_width).max(1.0);
+    for col in visible {
+        quads.push(GuideQuad {
+            kind: GuideQuadKind::Line,
+            x: area.x + col as f32 * area.cell_width - line_width / 2.0,
+            y: area.y,
+            width: line_width,
+            height: area.height(),
+            color: style.line_color,
+        });
+    }
+    quads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(columns: Vec<u32>, margin_shading: bool) -> GuideStyle {
+        GuideStyle::from_config(&UiConfig {
+            column_guides: columns,
+            margin_shading,
+            theme: "dark".to_string(),
+            ..UiConfig::default()
+        })
+    }
+
+    fn area(x: f32, cell_width: f32) -> ContentArea {
+        ContentArea {
+            x,
+            y: 10.0,
+            cell_width,
+            cell_height: cell_width * 2.0,
+            cols: 120,
+            rows: 40,
+        }
+    }
+
+    #[test]
+    fn test_quad_geometry_tracks_font_size_and_offset() {
+        let style = style(vec![100, 80], false);
+        for (x, cell_width) in [(0.0, 8.0), (4.0, 10.0), (512.0, 7.5)] {
+            let area = area(x, cell_width);
+            let quads = guide_quads(&area, &style.columns, &style);
+            assert_eq!(quads.len(), 2);
+            let line_width = (cell_width * 0.1).max(1.0);
+            assert_eq!(quads[0].x, x + 80.0 * cell_width - line_width / 2.0);
+            assert_eq!(quads[1].x, x + 100.0 * cell_width - line_width / 2.0);
+            assert!(quads[0].width < cell_width);
+            assert_eq!(quads[0].height, 40.0 * cell_width * 2.0);
+            assert_eq!(quads[0].y, 10.0);
+        }
+
+        // Guides beyond the pane width are not drawn
+        let narrow = ContentArea {
+            cols: 90,
+            ..area(0.0, 8.0)
+        };
+        assert_eq!(guide_quads(&narrow, &style.columns, &style).len(), 1);
+    }
+
+    #[test]
+    fn test_margin_shading() {
+        let style = style(vec![80, 100], true);
+        let area = area(4.0, 8.0);
+        let quads = guide_quads(&area, &style.columns, &style);
+        assert_eq!(quads.len(), 3);
+        assert_eq!(quads[0].kind, GuideQuadKind::Margin);
+        assert_eq!(quads[0].x, 4.0 + 800.0);
+        assert_eq!(quads[0].width, 20.0 * 8.0);
+    }
+
+    #[test]
+    fn test_pane_override_precedence() {
+        let style = style(vec![80], false);
+        let mut overrides = GuideOverrides::default();
+        assert_eq!(overrides.columns(1, &style), &[80]);
+
+        overrides.set(1, parse_guide_setting("100").unwrap());
+        assert_eq!(overrides.columns(1, &style), &[100]);
+        assert_eq!(overrides.columns(2, &style), &[80]);
+
+        overrides.set(1, parse_guide_setting("off").unwrap());
+        assert!(overrides.columns(1, &style).is_empty());
+
+        overrides.set(1, parse_guide_setting("default").unwrap());
+        assert_eq!(overrides.columns(1, &style), &[80]);
+
+        assert_eq!(parse_guide_setting("100, 80").unwrap(), Some(vec![80, 100]));
+        assert!(parse_guide_setting("0").is_err());
+        assert!(parse_guide_setting("wide").is_err());
+    }
+}
@@ -0,0 +1,574 @@
+use crate::config::UiConfig;
+use crate::input::{Key, KeyEvent, Modifier};
+use crate::paste_guard::{self, SpanStyle};
+use crate::response_log::{LogRecord, ResponseLog, ResponseLogConfig, ResponseLogError};
+use crate::terminal::{ShellEvent, TerminalCell};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Entries reloaded from disk on startup
+const RESTORE_LIMIT: usize = 5000;
+
+/// A command seen through shell integration marks, as stored on disk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandRecord {
+    pub id: String,
+    pub command: String,
+    pub cwd: Option<String>,
+    /// PTY of the tab it ran in
+    pub tab: u64,
+    /// Unix milliseconds
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub exit: Option<i32>,
+}
+
+impl CommandRecord {
+    pub fn duration_ms(&self) -> u64 {
+        self.finished_at.saturating_sub(self.started_at)
+    }
+}
+
+impl LogRecord for CommandRecord {
+    fn order_key(&self) -> (u64, &str) {
+        (self.finished_at, &self.id)
+    }
+}
+
+/// `~/.local/share/ferroterm/commands/<profile>`, capped by
+/// `ui.command_history_max_mb`; `None` when recording is off
+pub fn log_config(
+    ui: &UiConfig,
+    profile: Option<&str>,
+) -> Result<Option<ResponseLogConfig>, ResponseLogError> {
+    if ui.command_history_max_mb == 0 {
+        return Ok(None);
+    }
+    let dir = dirs::data_dir()
+        .ok_or(ResponseLogError::NoDataDir)?
+        .join("ferroterm")
+        .join("commands")
+        .join(profile.unwrap_or("default"));
+    let max_total_bytes = ui.command_history_max_mb as u64 * 1024 * 1024;
+    Ok(Some(ResponseLogConfig {
+        dir,
+        segment_bytes: ResponseLogConfig::DEFAULT_SEGMENT_BYTES.min(max_total_bytes),
+        max_total_bytes,
+        max_age: None,
+        restore: RESTORE_LIMIT,
+    }))
+}
+
+/// Pairs a pane's `C` and `D` marks into records
+#[derive(Debug, Default)]
+pub struct CommandTracker {
+    running: Option<(String, Option<String>, u64)>,
+}
+
+impl CommandTracker {
+    /// `cwd` is only read for a starting command
+    pub fn observe(
+        &mut self,
+        event: ShellEvent,
+        tab: u64,
+        now_ms: u64,
+        cwd: impl FnOnce() -> Option<String>,
+    ) -> Option<CommandRecord> {
+        match event {
+            ShellEvent::CommandStarted(command) => {
+                self.running = (!command.is_empty()).then(|| (command, cwd(), now_ms));
+                None
+            }
+            ShellEvent::CommandFinished(exit) => {
+                let (command, cwd, started_at) = self.running.take()?;
+                Some(CommandRecord {
+                    id: uuid::Uuid::new_v4().simple().to_string(),
+                    command,
+                    cwd,
+                    tab,
+                    started_at,
+                    finished_at: now_ms.max(started_at),
+                    exit,
+                })
+            }
+        }
+    }
+}
+
+/// The latest run of a command and how often it ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub record: CommandRecord,
+    pub runs: u32,
+}
+
+impl HistoryEntry {
+    /// `exit 1 · 2.3s · 4 runs`
+    pub fn result_line(&self) -> String {
+        let exit = match self.record.exit {
+            Some(code) => format!("exit {}", code),
+            None => "exit ?".to_string(),
+        };
+        let duration = self.record.duration_ms();
+        let duration = if duration < 1000 {
+            format!("{}ms", duration)
+        } else {
+            format!("{:.1}s", duration as f64 / 1000.0)
+        };
+        let runs = if self.runs == 1 {
+            "1 run".to_string()
+        } else {
+            format!("{} runs", self.runs)
+        };
+        format!("{} · {} · {}", exit, duration, runs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryFilter {
+    All,
+    /// Commands run in this directory
+    Directory(String),
+    /// Non-zero exit status
+    Failed,
+    Tab(u64),
+}
+
+impl HistoryFilter {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Directory(_) => "this directory",
+            Self::Failed => "failed",
+            Self::Tab(_) => "this tab",
+        }
+    }
+
+    fn matches(&self, record: &CommandRecord) -> bool {
+        match self {
+            Self::All => true,
+            Self::Directory(dir) => record.cwd.as_deref() == Some(dir.as_str()),
+            Self::Failed => record.exit.is_some_and(|code| code != 0),
+            Self::Tab(tab) => record.tab == *tab,
+        }
+    }
+}
+
+/// Commands from every tab, deduplicated by text. Writes go through a
+/// [`ResponseLog`], so several instances can share one profile's store.
+pub struct CommandHistory {
+    log: Option<ResponseLog<CommandRecord>>,
+    entries: Vec<HistoryEntry>,
+    index: HashMap<String, usize>,
+}
+
+impl CommandHistory {
+    pub fn in_memory() -> Self {
+        Self {
+            log: None,
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    pub fn open(config: ResponseLogConfig) -> Result<Self, ResponseLogError> {
+        let restore = config.restore;
+        let log = ResponseLog::open(config)?;
+        let report = log.load_recent(restore)?;
+        let mut history = Self::in_memory();
+        for record in report.records {
+            history.insert(record);
+        }
+        history.log = Some(log);
+        Ok(history)
+    }
+
+    /// Store a finished command. It is kept in memory even if the write
+    /// fails.
+    pub fn record(&mut self, record: CommandRecord) -> Result<(), ResponseLogError> {
+        let written = match self.log.as_mut() {
+            Some(log) => log.append(&record),
+            None => Ok(()),
+        };
+        self.insert(record);
+        written
+    }
+
+    fn insert(&mut self, record: CommandRecord) {
+        match self.index.get(&record.command) {
+            Some(&slot) => {
+                let entry = &mut self.entries[slot];
+                entry.runs += 1;
+                if record.order_key() > entry.record.order_key() {
+                    entry.record = record;
+                }
+            }
+            None => {
+                self.index
+                    .insert(record.command.clone(), self.entries.len());
+                self.entries.push(HistoryEntry { record, runs: 1 });
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Best fuzzy matches first, most recent first among equals
+    pub fn search(&self, query: &str, filter: &HistoryFilter) -> Vec<&HistoryEntry> {
+        let mut matches: Vec<(i64, &HistoryEntry)> = self
+            .entries
+            .iter()
+            .filter(|entry| filter.matches(&entry.record))
+            .filter_map(|entry| Some((fuzzy_score(query, &entry.record.command)?, entry)))
+            .collect();
+        matches.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .cmp(a_score)
+                .then_with(|| b.record.order_key().cmp(&a.record.order_key()))
+        });
+        matches.into_iter().map(|(_, entry)| entry).collect()
+    }
+}
+
+/// Case-insensitive subsequence match; runs of consecutive characters and
+/// word starts score higher. `None` when `query` is not a subsequence.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut chars = text.chars().enumerate().peekable();
+    let mut previous: Option<(usize, char)> = None;
+    let mut last_match: Option<usize> = None;
+    for wanted in query.chars().flat_map(char::to_lowercase) {
+        loop {
+            let (index, ch) = chars.next()?;
+            let word_start = previous.is_none_or(|(_, before)| !before.is_alphanumeric());
+            previous = Some((index, ch));
+            if ch.to_lowercase().eq(std::iter::once(wanted)) {
+                score += 1;
+                if last_match.is_some_and(|last| last + 1 == index) {
+                    score += 4;
+                }
+                if word_start {
+                    score += 2;
+                }
+                last_match = Some(index);
+                break;
+            }
+        }
+    }
+    Some(score)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverlayOutcome {
+    Pending,
+    /// Type the command at the prompt without running it
+    Insert(Vec<u8>),
+    /// Type the command and press Enter
+    Run(Vec<u8>),
+    Cancel,
+}
+
+/// Find-and-run overlay over a [`CommandHistory`]
+#[derive(Debug, Clone)]
+pub struct HistoryOverlay {
+    query: String,
+    filters: Vec<HistoryFilter>,
+    filter: usize,
+    selected: usize,
+    matches: Vec<HistoryEntry>,
+}
+
+impl HistoryOverlay {
+    /// The directory filter is offered only when the pane's directory is
+    /// known
+    pub fn new(history: &CommandHistory, cwd: Option<String>, tab: u64) -> Self {
+        let mut filters = vec![HistoryFilter::All];
+        filters.extend(cwd.map(HistoryFilter::Directory));
+        filters.extend([HistoryFilter::Failed, HistoryFilter::Tab(tab)]);
+        let mut overlay = Self {
+            query: String::new(),
+            filters,
+            filter: 0,
+            selected: 0,
+            matches: Vec::new(),
+        };
+        overlay.refresh(history);
+        overlay
+    }
+
+    pub fn filter(&self) -> &HistoryFilter {
+        &self.filters[self.filter]
+    }
+
+    pub fn matches(&self) -> &[HistoryEntry] {
+        &self.matches
+    }
+
+    pub fn selected(&self) -> Option<&HistoryEntry> {
+        self.matches.get(self.selected)
+    }
+
+    fn refresh(&mut self, history: &CommandHistory) {
+        self.matches = history
+            .search(&self.query, &self.filters[self.filter])
+            .into_iter()
+            .cloned()
+            .collect();
+        self.selected = self.selected.min(self.matches.len().saturating_sub(1));
+    }
+
+    /// Enter inserts the selection, Alt or Ctrl+Enter runs it, Tab cycles
+    /// filters, Esc closes; typing narrows the search
+    pub fn key(&mut self, history: &CommandHistory, event: &KeyEvent) -> OverlayOutcome {
+        let run =
+            event.modifiers.contains(&Modifier::Alt) || event.modifiers.contains(&Modifier::Ctrl);
+        match event.key {
+            Key::Enter | Key::KpEnter => match self.selected() {
+                Some(entry) => {
+                    let mut bytes = entry.record.command.clone().into_bytes();
+                    if run {
+                        bytes.push(b'\r');
+                        OverlayOutcome::Run(bytes)
+                    } else {
+                        OverlayOutcome::Insert(bytes)
+                    }
+                }
+                None => OverlayOutcome::Pending,
+            },
+            Key::Escape => OverlayOutcome::Cancel,
+            Key::Tab => {
+                self.filter = (self.filter + 1) % self.filters.len();
+                self.selected = 0;
+                self.refresh(history);
+                OverlayOutcome::Pending
+            }
+            Key::Up => {
+                self.selected = self.selected.saturating_sub(1);
+                OverlayOutcome::Pending
+            }
+            Key::Down => {
+                self.selected = (self.selected + 1).min(self.matches.len().saturating_sub(1));
+                OverlayOutcome::Pending
+            }
+            Key::Backspace => {
+                self.query.pop();
+                self.selected = 0;
+                self.refresh(history);
+                OverlayOutcome::Pending
+            }
+            Key::Char(ch) if !event.modifiers.contains(&Modifier::Ctrl) => {
+                self.query.push(ch);
+                self.selected = 0;
+                self.refresh(history);
+                OverlayOutcome::Pending
+            }
+            Key::Space => {
+                self.query.push(' ');
+                self.selected = 0;
+                self.refresh(history);
+                OverlayOutcome::Pending
+            }
+            _ => OverlayOutcome::Pending,
+        }
+    }
+
+    /// A query line, the matches around the selection with their result
+    /// lines, and the key help
+    pub fn overlay_rows(&self, width: u32, height: u32) -> Vec<Vec<TerminalCell>> {
+        let header = format!("History [{}] › {}", self.filter().label(), self.query);
+        let help = "Enter insert · Alt+Enter run · Tab filter · Esc close";
+        let room = (height as usize).saturating_sub(2);
+        let first = self.selected.saturating_sub(room.saturating_sub(1));
+        let mut rows = vec![paste_guard::cells(
+            &[(SpanStyle::Plain, header)],
+            width,
+            true,
+        )];
+        for (index, entry) in self.matches.iter().enumerate().skip(first).take(room) {
+            let marker = if index == self.selected { "› " } else { "  " };
+            let result = entry.result_line();
+            let text_width = (width as usize).saturating_sub(result.chars().count() + 3);
+            let command: String = entry.record.command.chars().take(text_width).collect();
+            let padding = text_width.saturating_sub(command.chars().count()) + 1;
+            let failed = entry.record.exit.is_some_and(|code| code != 0);
+            let spans = [
+                (
+                    SpanStyle::Plain,
+                    format!("{}{}{}", marker, command, " ".repeat(padding)),
+                ),
+                (
+                    if failed {
+                        SpanStyle::Risky
+                    } else {
+                        SpanStyle::Control
+                    },
+                    result,
+                ),
+            ];
+            rows.push(paste_guard::cells(&spans, width, index == self.selected));
+        }
+        rows.push(paste_guard::cells(
+            &[(SpanStyle::Plain, help.to_string())],
+            width,
+            true,
+        ));
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::TerminalState;
+    use std::collections::HashSet;
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    fn key(key: Key, modifiers: &[Modifier]) -> KeyEvent {
+        KeyEvent {
+            key,
+            modifiers: modifiers.iter().copied().collect::<HashSet<_>>(),
+            text: None,
+            repeat: false,
+            timestamp: Instant::now(),
+            key_code: None,
+        }
+    }
+
+    fn record(command: &str, cwd: &str, tab: u64, finished_at: u64, exit: i32) -> CommandRecord {
+        CommandRecord {
+            id: format!("{}-{}", command, finished_at),
+            command: command.to_string(),
+            cwd: Some(cwd.to_string()),
+            tab,
+            started_at: finished_at - 1500,
+            finished_at,
+            exit: Some(exit),
+        }
+    }
+
+    #[test]
+    fn test_marks_become_records() {
+        let mut terminal = TerminalState::new(40, 5);
+        let mut tracker = CommandTracker::default();
+        let mut records = Vec::new();
+        let mut now = 1_000;
+        for chunk in [
+            "\x1b]133;A\x07$ \x1b]133;B\x07cargo test\r\n\x1b]133;C\x07",
+            "ok\r\n\x1b]133;D;101\x07",
+            "\x1b]133;A\x07$ \x1b]133;B\x07\r\n\x1b]133;C\x07\x1b]133;D;0\x07",
+        ] {
+            terminal.feed_bytes(chunk.as_bytes());
+            for event in terminal.take_shell_events() {
+                now += 2_500;
+                records.extend(tracker.observe(event, 7, now, || Some("/src".to_string())));
+            }
+        }
+
+        // An empty line is not recorded
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.command, "cargo test");
+        assert_eq!(record.cwd.as_deref(), Some("/src"));
+        assert_eq!(
+            (record.tab, record.exit, record.duration_ms()),
+            (7, Some(101), 2_500)
+        );
+    }
+
+    #[test]
+    fn test_filters_dedup_and_reload() {
+        let dir = TempDir::new().unwrap();
+        let config = ResponseLogConfig {
+            dir: dir.path().to_path_buf(),
+            segment_bytes: 1 << 20,
+            max_total_bytes: 1 << 30,
+            max_age: None,
+            restore: 100,
+        };
+        let mut history = CommandHistory::open(config.clone()).unwrap();
+        history.record(record("make", "/a", 1, 10_000, 0)).unwrap();
+        history
+            .record(record("cargo build", "/b", 2, 20_000, 101))
+            .unwrap();
+        history.record(record("make", "/b", 2, 30_000, 2)).unwrap();
+        history.record(record("ls", "/a", 1, 40_000, 0)).unwrap();
+
+        let commands = |history: &CommandHistory, filter: HistoryFilter| -> Vec<String> {
+            history
+                .search("", &filter)
+                .iter()
+                .map(|entry| entry.record.command.clone())
+                .collect()
+        };
+        assert_eq!(
+            commands(&history, HistoryFilter::All),
+            ["ls", "make", "cargo build"]
+        );
+        assert_eq!(
+            commands(&history, HistoryFilter::Directory("/a".into())),
+            ["ls"]
+        );
+        assert_eq!(
+            commands(&history, HistoryFilter::Failed),
+            ["make", "cargo build"]
+        );
+        assert_eq!(
+            commands(&history, HistoryFilter::Tab(2)),
+            ["make", "cargo build"]
+        );
+
+        // The latest instance is kept, with a count of every run
+        let make = history.search("mk", &HistoryFilter::All)[0].clone();
+        assert_eq!((make.record.cwd.as_deref(), make.runs), (Some("/b"), 2));
+        assert_eq!(make.result_line(), "exit 2 · 1.5s · 2 runs");
+
+        // Another instance sees the same store
+        let reopened = CommandHistory::open(config).unwrap();
+        assert_eq!(reopened.len(), 3);
+        assert_eq!(reopened.search("make", &HistoryFilter::All)[0], &make);
+    }
+
+    #[test]
+    fn test_overlay_inserts_or_runs_selection() {
+        let mut history = CommandHistory::in_memory();
+        history
+            .record(record("git status", "/r", 1, 10_000, 0))
+            .unwrap();
+        history
+            .record(record("git push", "/r", 2, 20_000, 1))
+            .unwrap();
+
+        let mut overlay = HistoryOverlay::new(&history, Some("/r".to_string()), 1);
+        for ch in "gst".chars() {
+            overlay.key(&history, &key(Key::Char(ch), &[]));
+        }
+        assert_eq!(
+            overlay.key(&history, &key(Key::Enter, &[])),
+            OverlayOutcome::Insert(b"git status".to_vec())
+        );
+
+        overlay.key(&history, &key(Key::Backspace, &[]));
+        overlay.key(&history, &key(Key::Backspace, &[]));
+        assert_eq!(overlay.matches().len(), 2);
+        // all → this directory → failed
+        overlay.key(&history, &key(Key::Tab, &[]));
+        overlay.key(&history, &key(Key::Tab, &[]));
+        assert_eq!(overlay.filter(), &HistoryFilter::Failed);
+        assert_eq!(
+            overlay.key(&history, &key(Key::Enter, &[Modifier::Alt])),
+            OverlayOutcome::Run(b"git push\r".to_vec())
+        );
+        assert_eq!(overlay.overlay_rows(60, 4).len(), 3);
+        assert_eq!(
+            overlay.key(&history, &key(Key::Escape, &[])),
+            OverlayOutcome::Cancel
+        );
+    }
+}
@@ -1,6 +1,13 @@
-use std::collections::HashMap;
+use crate::calc;
+use crate::command_registry::{
+    ArgSpec, CommandHandler, CommandRegistry, CommandSpec, Completer, CompletionSource,
+};
+use crate::config::UserCommand;
+use crate::read_only::ReadOnlyMode;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::path::PathBuf;
+use std::sync::Arc;
 use thiserror::Error;
 use serde::{Deserialize, Serialize};
 
@@ -10,6 +17,8 @@ pub enum CommandParseError {
     Syntax(String),
     #[error("Unknown command: {0}")]
     UnknownCommand(String),
+    #[error("Unknown command: {name} (did you mean '{suggestion}'?)")]
+    DidYouMean { name: String, suggestion: String },
     #[error("Missing required argument: {0}")]
     MissingArgument(String),
     #[error("Invalid argument value: {0}")]
@@ -20,18 +29,69 @@ pub enum CommandParseError {
     Model(String),
     #[error("Quote parsing error: {0}")]
     Quote(String),
+    #[error("Custom command '{0}' is not loaded: a built-in command has that name")]
+    ReservedName(String),
 }
 
 #[derive(Debug, Clone)]
 pub enum Command {
     // Traditional commands
-    Help,
+    /// Overview, or the help for one command
+    Help(Option<String>),
+    /// Key bindings, grouped by the context they apply in, or with
+    /// `--conflicts` the bindings that shadow each other
+    Keys { conflicts: bool },
     Run(String),
     Ask(String),
+    /// Evaluate arithmetic, base, byte-size or timestamp expressions locally
+    Calc(String),
     Config(String, String),
-    Model(String),
+    /// Switch to a registered model, `force` evicting others to make room;
+    /// an empty name shows the one in use
+    Model { name: String, force: bool },
+    /// Answer every prompt in a file, inline or as JSONL in `out`
+    Batch { file: PathBuf, out: Option<PathBuf> },
     Clear,
     Exit,
+    NewWindow,
+    /// Fold command output: the region at the cursor, or every region
+    Fold { all: bool },
+    Unfold { all: bool },
+    /// Per-pane display setting, e.g. `:set guide 100`
+    Set(String, String),
+    /// Change a config setting for this session, or in the config file
+    /// too with `--save`, e.g. `:set ui.font_size 16`
+    SetConfig { path: String, value: String, save: bool },
+    /// Switch theme; `auto` follows the OS appearance again
+    Theme(String),
+    /// Block or allow input to the current pane
+    ReadOnly(ReadOnlyMode),
+    /// Write the code blocks of the latest response into a directory
+    Scaffold(PathBuf),
+    /// Put the latest response, or its nth code block counting from 1,
+    /// on the clipboard
+    CopyResponse { block: Option<usize> },
+    /// Paste the nth code block of the latest response into the pane
+    InsertCode(usize),
+    /// List the links on screen, or open the nth counting from 1
+    OpenLink(Option<usize>),
+    /// Search the scrollback, opening the search bar with the pattern if
+    /// one follows the `/`
+    Search(Option<String>),
+    /// Effective config with the source of each value
+    ShowConfig { path: Option<String>, diff: bool },
+    /// Apply response history retention now, optionally to a smaller cap
+    HistoryPrune { max_mb: Option<u32> },
+    /// Toggle the debug overlay, or with `--json` print its numbers
+    Stats { json: bool },
+    /// Requests, tokens and estimated cost per model
+    Usage,
+    /// Registered models and their load state
+    Models,
+    /// Save, restore, list or delete a multiplexer session
+    Session(SessionAction),
+    /// A `[commands]` entry, or a command registered with
+    /// `CommandHandler::Custom`, with its arguments
     Custom(String, Vec<String>),
     // AI Agent command
     Agent(AgentCommand),
@@ -39,6 +99,14 @@ pub enum Command {
     Terminal(String),
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionAction {
+    Save(String),
+    Restore(String),
+    List,
+    Delete(String),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentCommand {
     pub prompt: String,
@@ -47,6 +115,40 @@ pub struct AgentCommand {
     pub max_tokens: Option<u32>,
     pub context: AgentContext,
     pub is_continuation: bool,
+    /// `:noctx` was given: send no scrollback with this request
+    #[serde(default)]
+    pub no_context: bool,
+}
+
+impl AgentCommand {
+    /// A prompt with no overrides or collected context, as `:ask` gives.
+    /// A leading `:noctx` is honoured as on a prefix line.
+    pub fn plain(prompt: String) -> Self {
+        let (no_context, prompt) = match strip_noctx(&prompt) {
+            Some(rest) => (true, rest.to_string()),
+            None => (false, prompt),
+        };
+        Self {
+            prompt,
+            model_override: None,
+            temperature: None,
+            max_tokens: None,
+            context: AgentContext {
+                scrollback_lines: Vec::new(),
+                environment_vars: HashMap::new(),
+                current_directory: env::current_dir().unwrap_or_default(),
+                shell_state: None,
+            },
+            is_continuation: false,
+            no_context,
+        }
+    }
+}
+
+/// The text after a leading `:noctx` flag, if there is one
+fn strip_noctx(text: &str) -> Option<&str> {
+    let rest = text.trim_start().strip_prefix(":noctx")?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| rest.trim_start())
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -85,45 +187,36 @@ pub enum ParseState {
 pub struct CommandParser {
     prefix: String,
     escape_sequence: String,
-    commands: HashMap<String, CommandDefinition>,
+    registry: CommandRegistry,
+    /// Names registered from `[commands]`, replaced on each load
+    user_commands: HashSet<String>,
     state: ParseState,
     context_lines: u32,
     pub include_env: bool,
+    /// Prefix lines that parse as an expression become `Command::Calc`
+    pub auto_calc: bool,
     current_buffer: String,
     quote_char: Option<char>,
     continuation_buffer: String,
     pub scrollback: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
-pub struct CommandDefinition {
-    pub name: String,
-    pub description: String,
-    pub syntax: String,
-    pub examples: Vec<String>,
-    pub handler: CommandHandler,
-}
-
-#[derive(Clone, Debug)]
-pub enum CommandHandler {
-    BuiltIn(fn(&[String]) -> Result<Command, CommandParseError>),
-    Custom(String),
-}
-
 impl CommandParser {
     pub fn new(prefix: String) -> Self {
-        let mut commands = HashMap::new();
+        let mut registry = CommandRegistry::new();
 
         // Register built-in commands
-        Self::register_builtin_commands(&mut commands);
+        Self::register_builtin_commands(&mut registry);
 
         Self {
             prefix: prefix.clone(),
             escape_sequence: format!("\\{}", prefix),
-            commands,
+            registry,
+            user_commands: HashSet::new(),
             state: ParseState::LineStart,
             context_lines: 100,
             include_env: true,
+            auto_calc: true,
             current_buffer: String::new(),
             quote_char: None,
             continuation_buffer: String::new(),
@@ -138,95 +231,256 @@ impl CommandParser {
         parser
     }
 
-    fn register_builtin_commands(commands: &mut HashMap<String, CommandDefinition>) {
-        // Help command
-        commands.insert(
-            "help".to_string(),
-            CommandDefinition {
-                name: "help".to_string(),
-                description: "Show help information".to_string(),
-                syntax: "help [command]".to_string(),
-                examples: vec!["help".to_string(), "help run".to_string()],
-                handler: CommandHandler::BuiltIn(Self::handle_help),
-            },
+    fn register_builtin_commands(registry: &mut CommandRegistry) {
+        registry.register(
+            CommandSpec::new("help", "Show help information", CommandHandler::BuiltIn(Self::handle_help))
+                .arg(ArgSpec::optional("command").command_name())
+                .example("help")
+                .example("help run"),
+        );
+        registry.register(
+            CommandSpec::new("keys", "List the key bindings by context", CommandHandler::BuiltIn(Self::handle_keys))
+                .arg(ArgSpec::optional("--conflicts").choices(&["--conflicts"]))
+                .example("keys")
+                .example("keys --conflicts"),
+        );
+        registry.register(
+            CommandSpec::new("run", "Execute a shell command", CommandHandler::BuiltIn(Self::handle_run))
+                .arg(ArgSpec::required("command").variadic())
+                .example("run ls -la")
+                .example("run echo hello"),
         );
 
-        // Run command
-        commands.insert(
-            "run".to_string(),
-            CommandDefinition {
-                name: "run".to_string(),
-                description: "Execute a shell command".to_string(),
-                syntax: "run <command>".to_string(),
-                examples: vec!["run ls -la".to_string(), "run echo hello".to_string()],
-                handler: CommandHandler::BuiltIn(Self::handle_run),
-            },
+        // AI query
+        registry.register(
+            CommandSpec::new("ask", "Ask AI assistant a question", CommandHandler::BuiltIn(Self::handle_ask))
+                .arg(ArgSpec::required("question").variadic())
+                .example("ask how to list files")
+                .example("ask what is the current directory"),
+        );
+        registry.register(
+            CommandSpec::new(
+                "calc",
+                "Evaluate an expression without a model",
+                CommandHandler::BuiltIn(Self::handle_calc),
+            )
+            .arg(ArgSpec::required("expression").variadic())
+            .example(":calc 0xff * 2")
+            .example(":calc 1 GiB in MB")
+            .example(":calc iso(1700000000)"),
+        );
+        registry.register(
+            CommandSpec::new(
+                "config",
+                "Get or set configuration values",
+                CommandHandler::BuiltIn(Self::handle_config),
+            )
+            .arg(ArgSpec::required("key"))
+            .arg(ArgSpec::optional("value").variadic())
+            .example("config font_size")
+            .example("config font_size 14"),
+        );
+        registry.register(
+            CommandSpec::new(
+                "model",
+                "Switch AI model or show current model",
+                CommandHandler::BuiltIn(Self::handle_model),
+            )
+            .arg(ArgSpec::optional("model_name").complete_from(CompletionSource::Models))
+            .arg(ArgSpec::optional("--force").choices(&["--force"]))
+            .example("model")
+            .example("model mistral-7b-instruct")
+            .example("model qwen-14b --force"),
+        );
+        registry.register(
+            CommandSpec::new(
+                "batch",
+                "Answer each prompt in a file, or each JSONL {prompt, model, parameters} line",
+                CommandHandler::BuiltIn(Self::handle_batch),
+            )
+            .arg(ArgSpec::required("file").complete_from(CompletionSource::Paths))
+            .arg(ArgSpec::optional("--out").choices(&["--out"]))
+            .arg(ArgSpec::optional("results").complete_from(CompletionSource::Paths))
+            .example(":batch prompts.txt")
+            .example(":batch prompts.jsonl --out results.jsonl"),
+        );
+        registry.register(
+            CommandSpec::new("clear", "Clear the terminal screen", CommandHandler::BuiltIn(Self::handle_clear))
+                .example("clear"),
+        );
+        registry.register(
+            CommandSpec::new("exit", "Exit the terminal", CommandHandler::BuiltIn(Self::handle_exit))
+                .example("exit"),
+        );
+        registry.register(
+            CommandSpec::new(
+                "new-window",
+                "Open a new terminal window",
+                CommandHandler::BuiltIn(Self::handle_new_window),
+            )
+            .example(":new-window"),
         );
 
-        // Ask command (AI query)
-        commands.insert(
-            "ask".to_string(),
-            CommandDefinition {
-                name: "ask".to_string(),
-                description: "Ask AI assistant a question".to_string(),
-                syntax: "ask <question>".to_string(),
-                examples: vec![
-                    "ask how to list files".to_string(),
-                    "ask what is the current directory".to_string(),
-                ],
-                handler: CommandHandler::BuiltIn(Self::handle_ask),
-            },
+        // Output folding commands
+        registry.register(
+            CommandSpec::new(
+                "fold",
+                "Collapse command output to a summary line",
+                CommandHandler::BuiltIn(Self::handle_fold),
+            )
+            .arg(ArgSpec::optional("all").choices(&["all"]))
+            .example(":fold")
+            .example(":fold all"),
+        );
+        registry.register(
+            CommandSpec::new("unfold", "Expand folded command output", CommandHandler::BuiltIn(Self::handle_unfold))
+                .arg(ArgSpec::optional("all").choices(&["all"]))
+                .example(":unfold")
+                .example(":unfold all"),
         );
 
-        // Config command
-        commands.insert(
-            "config".to_string(),
-            CommandDefinition {
-                name: "config".to_string(),
-                description: "Get or set configuration values".to_string(),
-                syntax: "config <key> [value]".to_string(),
-                examples: vec![
-                    "config font_size".to_string(),
-                    "config font_size 14".to_string(),
-                ],
-                handler: CommandHandler::BuiltIn(Self::handle_config),
-            },
+        // Per-pane settings
+        registry.register(
+            CommandSpec::new(
+                "set",
+                "Change a setting for the current pane, or a config setting by its path",
+                CommandHandler::BuiltIn(Self::handle_set),
+            )
+            .arg(ArgSpec::required("option"))
+            .arg(ArgSpec::required("value").variadic())
+            .example(":set guide 100")
+            .example(":set guide off")
+            .example(":set ui.font_size 16")
+            .example(":set --save keymap.prefix ,"),
+        );
+        registry.register(
+            CommandSpec::new("theme", "Switch the color theme", CommandHandler::BuiltIn(Self::handle_theme))
+                .arg(ArgSpec::required("name").choices(&["auto", "light", "dark"]))
+                .example(":theme light")
+                .example(":theme auto"),
+        );
+        registry.register(
+            CommandSpec::new(
+                "readonly",
+                "Stop keyboard and paste input from reaching the current pane",
+                CommandHandler::BuiltIn(Self::handle_readonly),
+            )
+            .arg(ArgSpec::optional("mode").choices(&["on", "off", "toggle"]))
+            .example(":readonly on")
+            .example(":readonly"),
         );
 
-        // Model command
-        commands.insert(
-            "model".to_string(),
-            CommandDefinition {
-                name: "model".to_string(),
-                description: "Switch AI model or show current model".to_string(),
-                syntax: "model [model_name]".to_string(),
-                examples: vec!["model".to_string(), "model mistral-7b-instruct".to_string()],
-                handler: CommandHandler::BuiltIn(Self::handle_model),
-            },
+        registry.register(
+            CommandSpec::new(
+                "show",
+                "Show effective settings and where each value comes from",
+                CommandHandler::BuiltIn(Self::handle_show),
+            )
+            .arg(ArgSpec::required("what").choices(&["config"]))
+            .arg(ArgSpec::optional("path"))
+            .arg(ArgSpec::optional("--diff").choices(&["--diff"]))
+            .example(":show config")
+            .example(":show config ui.font")
+            .example(":show ui.line_height")
+            .example(":show config --diff"),
         );
 
-        // Clear command
-        commands.insert(
-            "clear".to_string(),
-            CommandDefinition {
-                name: "clear".to_string(),
-                description: "Clear the terminal screen".to_string(),
-                syntax: "clear".to_string(),
-                examples: vec!["clear".to_string()],
-                handler: CommandHandler::BuiltIn(Self::handle_clear),
-            },
+        // Response export
+        registry.register(
+            CommandSpec::new(
+                "scaffold",
+                "Write the files in the latest response into a directory",
+                CommandHandler::BuiltIn(Self::handle_scaffold),
+            )
+            .arg(ArgSpec::required("dir").complete_from(CompletionSource::Paths))
+            .example(":scaffold ./my-project"),
+        );
+        registry.register(
+            CommandSpec::new(
+                "copy",
+                "Copy the latest response, or one of its code blocks, to the clipboard",
+                CommandHandler::BuiltIn(Self::handle_copy),
+            )
+            .arg(ArgSpec::optional("what").choices(&["code"]))
+            .arg(ArgSpec::optional("n"))
+            .example(":copy")
+            .example(":copy code")
+            .example(":copy code 2"),
+        );
+        registry.register(
+            CommandSpec::new(
+                "insert",
+                "Paste a code block from the latest response into the pane",
+                CommandHandler::BuiltIn(Self::handle_insert),
+            )
+            .arg(ArgSpec::required("what").choices(&["code"]))
+            .arg(ArgSpec::optional("n"))
+            .example(":insert code")
+            .example(":insert code 2"),
+        );
+        registry.register(
+            CommandSpec::new(
+                "open",
+                "List the links on screen, or open one in the browser",
+                CommandHandler::BuiltIn(Self::handle_open),
+            )
+            .arg(ArgSpec::optional("n"))
+            .example(":open")
+            .example(":open 2"),
         );
 
-        // Exit command
-        commands.insert(
-            "exit".to_string(),
-            CommandDefinition {
-                name: "exit".to_string(),
-                description: "Exit the terminal".to_string(),
-                syntax: "exit".to_string(),
-                examples: vec!["exit".to_string()],
-                handler: CommandHandler::BuiltIn(Self::handle_exit),
-            },
+        registry.register(
+            CommandSpec::new(
+                "history",
+                "Manage responses kept across restarts",
+                CommandHandler::BuiltIn(Self::handle_history),
+            )
+            .arg(ArgSpec::required("action").choices(&["prune"]))
+            .arg(ArgSpec::optional("max_mb"))
+            .example(":history prune")
+            .example(":history prune 16"),
+        );
+
+        registry.register(
+            CommandSpec::new(
+                "stats",
+                "Toggle the overlay with frame rate, GPU, input and model stats",
+                CommandHandler::BuiltIn(Self::handle_stats),
+            )
+            .arg(ArgSpec::optional("--json").choices(&["--json"]))
+            .example(":stats")
+            .example(":stats --json"),
+        );
+
+        registry.register(
+            CommandSpec::new(
+                "models",
+                "List the models registered from the config and whether each is loaded",
+                CommandHandler::BuiltIn(Self::handle_models),
+            )
+            .example(":models"),
+        );
+
+        registry.register(
+            CommandSpec::new(
+                "usage",
+                "Show requests, tokens and estimated cost per model",
+                CommandHandler::BuiltIn(Self::handle_usage),
+            )
+            .example(":usage"),
+        );
+
+        registry.register(
+            CommandSpec::new(
+                "session",
+                "Save or restore the panes, directories and layout of a session",
+                CommandHandler::BuiltIn(Self::handle_session),
+            )
+            .arg(ArgSpec::required("action").choices(&["save", "restore", "list", "delete"]))
+            .arg(ArgSpec::optional("name").complete_from(CompletionSource::Sessions))
+            .example(":session save work")
+            .example(":session restore work")
+            .example(":session list"),
         );
     }
 
@@ -290,9 +544,47 @@ impl CommandParser {
                 .ok_or_else(|| CommandParseError::Syntax("Invalid prefix".to_string()))?
         };
 
+        // `/pattern` searches the scrollback instead of asking
+        if let Some(pattern) = remaining.strip_prefix('/') {
+            let pattern = pattern.trim();
+            return Ok(ParsedCommand {
+                command: Command::Search((!pattern.is_empty()).then(|| pattern.to_string())),
+                raw_input: input.to_string(),
+            });
+        }
+
+        // Settings, usage, models, stats and response actions are handled
+        // here rather than asked about
+        if Self::is_setting_command(remaining)
+            || Self::is_response_command(remaining)
+            || Self::is_model_command(remaining)
+            || Self::is_batch_command(remaining)
+            || self.is_help_command(remaining)
+            || self.is_user_command(remaining)
+            || matches!(remaining.trim(), "usage" | "models" | "stats" | "stats --json")
+        {
+            return Ok(ParsedCommand {
+                command: self.parse_builtin(remaining)?,
+                raw_input: input.to_string(),
+            });
+        }
+
         // Parse command line using state machine
         let mut parser = AgentCommandParser::new(remaining.trim());
         let (model_override, temperature, max_tokens, prompt) = parser.parse()?;
+        let no_context = parser.no_context;
+
+        if self.auto_calc
+            && model_override.is_none()
+            && temperature.is_none()
+            && max_tokens.is_none()
+            && calc::is_expression(&prompt)
+        {
+            return Ok(ParsedCommand {
+                command: Command::Calc(prompt),
+                raw_input: input.to_string(),
+            });
+        }
 
         // Collect context
         let context = self.collect_context()?;
@@ -304,6 +596,7 @@ impl CommandParser {
             max_tokens,
             context,
             is_continuation: !self.continuation_buffer.is_empty(),
+            no_context,
         };
 
         Ok(ParsedCommand {
@@ -369,6 +662,67 @@ impl CommandParser {
         }
     }
 
+    /// `set <path> ...` or `show <path>` with a dotted config path; other
+    /// lines starting with those words go to the agent
+    fn is_setting_command(line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        let verb = words.next();
+        let target = words.find(|word| !word.starts_with("--"));
+        matches!(
+            (verb, target),
+            (Some("set" | "show"), Some(target)) if target.contains('.')
+        ) || matches!((verb, target), (Some("show"), Some("config")))
+    }
+
+    /// `copy`, `copy code [n]`, `insert code [n]` or `open [n]`, exactly;
+    /// anything longer is a question about copying or opening
+    fn is_response_command(line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["copy"] | ["open"] => true,
+            ["open", n] => n.parse::<usize>().is_ok(),
+            ["copy" | "insert", "code"] => true,
+            ["copy" | "insert", "code", n] => n.parse::<usize>().is_ok(),
+            _ => false,
+        }
+    }
+
+    /// `model`, or `model <name>` with or without `--force`; a longer line
+    /// starting with `model` is a question
+    fn is_model_command(line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["model"] | ["model", _] => true,
+            ["model", first, second] => *first == "--force" || *second == "--force",
+            _ => false,
+        }
+    }
+
+    /// `batch <file>`, optionally with `--out <file>`; other lines starting
+    /// with `batch` are questions
+    fn is_batch_command(line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        matches!(words.as_slice(), ["batch", _] | ["batch", _, "--out", _])
+    }
+
+    /// `help`, `help <command>` for a registered command, or `keys` with
+    /// or without `--conflicts`; anything longer is a question for the agent
+    fn is_help_command(&self, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["help"] | ["keys"] | ["keys", "--conflicts"] => true,
+            ["help", name] => self.registry.get(name).is_some(),
+            _ => false,
+        }
+    }
+
+    /// A line starting with the name of a `[commands]` entry
+    fn is_user_command(&self, line: &str) -> bool {
+        line.split_whitespace()
+            .next()
+            .is_some_and(|name| self.user_commands.contains(name))
+    }
+
     /// Get accumulated continuation buffer
     pub fn get_continuation(&mut self) -> String {
         std::mem::take(&mut self.continuation_buffer)
@@ -382,49 +736,80 @@ impl CommandParser {
         self.quote_char = None;
     }
 
-    pub fn get_command_help(&self, command_name: Option<&str>) -> String {
-        match command_name {
-            Some(name) => {
-                if let Some(definition) = self.commands.get(name) {
-                    format!(
-                        "Command: {}\nDescription: {}\nSyntax: {}\nExamples:\n{}",
-                        definition.name,
-                        definition.description,
-                        definition.syntax,
-                        definition.examples.join("\n")
-                    )
-                } else {
-                    format!("Unknown command: {}", name)
-                }
-            }
-            None => {
-                let mut help = "Available commands:\n".to_string();
-                for (name, definition) in &self.commands {
-                    help.push_str(&format!("  {:<12} - {}\n", name, definition.description));
-                }
-                help.push_str(&format!(
-                    "\nType 'help <command>' for detailed help on a specific command."
-                ));
-                help
-            }
+    /// `help` text for the current prefix, wrapped to `width` columns
+    pub fn get_command_help(&self, command_name: Option<&str>, width: usize) -> String {
+        self.registry.help(command_name, &self.prefix, width)
+    }
+
+    /// Resolve a `:name args...` style line against the registered commands.
+    /// Commands with async handlers must go through `registry().dispatch`.
+    pub fn parse_builtin(&self, input: &str) -> Result<Command, CommandParseError> {
+        let (spec, args) = self.registry.resolve(input)?;
+        match &spec.handler {
+            CommandHandler::BuiltIn(handler) => handler(&args),
+            CommandHandler::Custom(action) => Ok(Command::Custom(action.clone(), args)),
+            CommandHandler::Async(_) => Ok(Command::Custom(spec.name.clone(), args)),
         }
     }
 
-    pub fn register_command(&mut self, definition: CommandDefinition) {
-        self.commands.insert(definition.name.clone(), definition);
+    pub fn registry(&self) -> &CommandRegistry {
+        &self.registry
+    }
+
+    /// Candidates for the last word of a prefix line, typed without the
+    /// prefix
+    pub fn complete(&self, line: &str) -> Vec<String> {
+        self.registry.complete(line)
+    }
+
+    pub fn set_completer(&mut self, source: CompletionSource, completer: Arc<dyn Completer>) {
+        self.registry.set_completer(source, completer);
+    }
+
+    pub fn register_command(&mut self, spec: CommandSpec) {
+        self.registry.register(spec);
     }
 
     pub fn unregister_command(&mut self, name: &str) -> bool {
-        self.commands.remove(name).is_some()
+        self.registry.unregister(name)
     }
 
     pub fn list_commands(&self) -> Vec<&str> {
-        self.commands.keys().map(|s| s.as_str()).collect()
+        self.registry.names()
+    }
+
+    /// Register the `[commands]` entries in place of those loaded before.
+    /// One named like a built-in command is left out and reported.
+    pub fn set_user_commands(&mut self, commands: &BTreeMap<String, UserCommand>) -> Vec<CommandParseError> {
+        for name in std::mem::take(&mut self.user_commands) {
+            self.registry.unregister(&name);
+        }
+        let mut errors = Vec::new();
+        for (name, command) in commands {
+            if self.registry.get(name).is_some() {
+                errors.push(CommandParseError::ReservedName(name.clone()));
+                continue;
+            }
+            self.registry.register(
+                CommandSpec::new(name, &command.description(), CommandHandler::Custom(name.clone()))
+                    .arg(ArgSpec::optional("args").variadic()),
+            );
+            self.user_commands.insert(name.clone());
+        }
+        errors
     }
 
     // Built-in command handlers
-    fn handle_help(_args: &[String]) -> Result<Command, CommandParseError> {
-        Ok(Command::Help)
+    fn handle_help(args: &[String]) -> Result<Command, CommandParseError> {
+        Ok(Command::Help(args.first().cloned()))
+    }
+
+    fn handle_keys(args: &[String]) -> Result<Command, CommandParseError> {
+        match args.first().map(String::as_str) {
+            None => Ok(Command::Keys { conflicts: false }),
+            Some("--conflicts") => Ok(Command::Keys { conflicts: true }),
+            Some(other) => Err(CommandParseError::InvalidArgument(other.to_string())),
+        }
     }
 
     fn handle_run(args: &[String]) -> Result<Command, CommandParseError> {
@@ -441,6 +826,13 @@ impl CommandParser {
         Ok(Command::Ask(args.join(" ")))
     }
 
+    fn handle_calc(args: &[String]) -> Result<Command, CommandParseError> {
+        if args.is_empty() {
+            return Err(CommandParseError::MissingArgument("expression".to_string()));
+        }
+        Ok(Command::Calc(args.join(" ")))
+    }
+
     fn handle_config(args: &[String]) -> Result<Command, CommandParseError> {
         match args.len() {
             0 => Err(CommandParseError::MissingArgument("key".to_string())),
@@ -450,13 +842,26 @@ impl CommandParser {
     }
 
     fn handle_model(args: &[String]) -> Result<Command, CommandParseError> {
-        if args.is_empty() {
-            Ok(Command::Model(String::new()))
-        } else {
-            Ok(Command::Model(args[0].clone()))
+        let (force, names): (Vec<&String>, Vec<&String>) = args.iter().partition(|arg| *arg == "--force");
+        match names.as_slice() {
+            [] if force.is_empty() => Ok(Command::Model { name: String::new(), force: false }),
+            [] => Err(CommandParseError::MissingArgument("model name".to_string())),
+            [name] => Ok(Command::Model { name: name.to_string(), force: !force.is_empty() }),
+            [_, extra, ..] => Err(CommandParseError::InvalidArgument(extra.to_string())),
         }
     }
 
+    fn handle_batch(args: &[String]) -> Result<Command, CommandParseError> {
+        let (file, out) = match args {
+            [] => return Err(CommandParseError::MissingArgument("file".to_string())),
+            [file] => (file, None),
+            [file, flag, out] if flag == "--out" => (file, Some(PathBuf::from(out))),
+            [_, flag] if flag == "--out" => return Err(CommandParseError::MissingArgument("results file".to_string())),
+            [_, extra, ..] => return Err(CommandParseError::InvalidArgument(extra.to_string())),
+        };
+        Ok(Command::Batch { file: PathBuf::from(file), out })
+    }
+
     fn handle_clear(_args: &[String]) -> Result<Command, CommandParseError> {
         Ok(Command::Clear)
     }
@@ -465,6 +870,175 @@ impl CommandParser {
         Ok(Command::Exit)
     }
 
+    fn handle_new_window(_args: &[String]) -> Result<Command, CommandParseError> {
+        Ok(Command::NewWindow)
+    }
+
+    fn fold_scope(args: &[String]) -> Result<bool, CommandParseError> {
+        match args.first().map(|s| s.as_str()) {
+            None => Ok(false),
+            Some("all") => Ok(true),
+            Some(other) => Err(CommandParseError::InvalidArgument(other.to_string())),
+        }
+    }
+
+    fn handle_fold(args: &[String]) -> Result<Command, CommandParseError> {
+        Ok(Command::Fold { all: Self::fold_scope(args)? })
+    }
+
+    fn handle_unfold(args: &[String]) -> Result<Command, CommandParseError> {
+        Ok(Command::Unfold { all: Self::fold_scope(args)? })
+    }
+
+    /// A dotted option is a config path; `--save` may come anywhere
+    fn handle_set(args: &[String]) -> Result<Command, CommandParseError> {
+        let save = args.iter().any(|arg| arg == "--save");
+        let args: Vec<String> = args.iter().filter(|arg| *arg != "--save").cloned().collect();
+        match args.as_slice() {
+            [] => Err(CommandParseError::MissingArgument("option".to_string())),
+            [_] => Err(CommandParseError::MissingArgument("value".to_string())),
+            [option, value @ ..] if option.contains('.') => Ok(Command::SetConfig {
+                path: option.clone(),
+                value: value.join(" "),
+                save,
+            }),
+            [option, value @ ..] if option == "guide" && !save => {
+                Ok(Command::Set(option.clone(), value.join(" ")))
+            }
+            [option, ..] => Err(CommandParseError::InvalidArgument(option.clone())),
+        }
+    }
+
+    fn handle_theme(args: &[String]) -> Result<Command, CommandParseError> {
+        match args.first() {
+            Some(name) => Ok(Command::Theme(name.clone())),
+            None => Err(CommandParseError::MissingArgument("name".to_string())),
+        }
+    }
+
+    /// No argument toggles
+    fn handle_readonly(args: &[String]) -> Result<Command, CommandParseError> {
+        match args.first() {
+            None => Ok(Command::ReadOnly(ReadOnlyMode::Toggle)),
+            Some(mode) => mode
+                .parse()
+                .map(Command::ReadOnly)
+                .map_err(|_| CommandParseError::InvalidArgument(mode.clone())),
+        }
+    }
+
+    /// `--diff` may come before or after the path; `show ui.font_size` is
+    /// short for `show config ui.font_size`
+    fn handle_show(args: &[String]) -> Result<Command, CommandParseError> {
+        let rest = match args.first().map(String::as_str) {
+            Some("config") => &args[1..],
+            Some(path) if path.contains('.') => args,
+            Some(other) => return Err(CommandParseError::InvalidArgument(other.to_string())),
+            None => return Err(CommandParseError::MissingArgument("what".to_string())),
+        };
+        let mut path = None;
+        let mut diff = false;
+        for arg in rest {
+            match arg.as_str() {
+                "--diff" => diff = true,
+                _ if path.is_none() => path = Some(arg.clone()),
+                _ => return Err(CommandParseError::InvalidArgument(arg.clone())),
+            }
+        }
+        Ok(Command::ShowConfig { path, diff })
+    }
+
+    fn handle_scaffold(args: &[String]) -> Result<Command, CommandParseError> {
+        match args.first() {
+            Some(dir) => Ok(Command::Scaffold(PathBuf::from(dir))),
+            None => Err(CommandParseError::MissingArgument("dir".to_string())),
+        }
+    }
+
+    fn handle_history(args: &[String]) -> Result<Command, CommandParseError> {
+        match args.first().map(String::as_str) {
+            Some("prune") => {}
+            Some(other) => return Err(CommandParseError::InvalidArgument(other.to_string())),
+            None => return Err(CommandParseError::MissingArgument("action".to_string())),
+        }
+        let max_mb = match args.get(1) {
+            Some(mb) => Some(
+                mb.parse()
+                    .map_err(|_| CommandParseError::InvalidArgument(mb.clone()))?,
+            ),
+            None => None,
+        };
+        Ok(Command::HistoryPrune { max_mb })
+    }
+
+    fn handle_stats(args: &[String]) -> Result<Command, CommandParseError> {
+        match args.first().map(String::as_str) {
+            None => Ok(Command::Stats { json: false }),
+            Some("--json") => Ok(Command::Stats { json: true }),
+            Some(other) => Err(CommandParseError::InvalidArgument(other.to_string())),
+        }
+    }
+
+    /// Code block number from `code [n]`, 1 when left out
+    fn code_block(args: &[String]) -> Result<usize, CommandParseError> {
+        match args.first().map(String::as_str) {
+            Some("code") => {}
+            Some(other) => return Err(CommandParseError::InvalidArgument(other.to_string())),
+            None => return Err(CommandParseError::MissingArgument("what".to_string())),
+        }
+        match args.get(1) {
+            Some(n) => match n.parse() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(CommandParseError::InvalidArgument(n.clone())),
+            },
+            None => Ok(1),
+        }
+    }
+
+    fn handle_copy(args: &[String]) -> Result<Command, CommandParseError> {
+        let block = if args.is_empty() { None } else { Some(Self::code_block(args)?) };
+        Ok(Command::CopyResponse { block })
+    }
+
+    fn handle_insert(args: &[String]) -> Result<Command, CommandParseError> {
+        Ok(Command::InsertCode(Self::code_block(args)?))
+    }
+
+    fn handle_open(args: &[String]) -> Result<Command, CommandParseError> {
+        match args.first() {
+            Some(n) => match n.parse() {
+                Ok(n) if n > 0 => Ok(Command::OpenLink(Some(n))),
+                _ => Err(CommandParseError::InvalidArgument(n.clone())),
+            },
+            None => Ok(Command::OpenLink(None)),
+        }
+    }
+
+    fn handle_usage(_args: &[String]) -> Result<Command, CommandParseError> {
+        Ok(Command::Usage)
+    }
+
+    fn handle_models(_args: &[String]) -> Result<Command, CommandParseError> {
+        Ok(Command::Models)
+    }
+
+    fn handle_session(args: &[String]) -> Result<Command, CommandParseError> {
+        let name = || {
+            args.get(1)
+                .cloned()
+                .ok_or_else(|| CommandParseError::MissingArgument("name".to_string()))
+        };
+        let action = match args.first().map(String::as_str) {
+            Some("save") => SessionAction::Save(name()?),
+            Some("restore") => SessionAction::Restore(name()?),
+            Some("delete") => SessionAction::Delete(name()?),
+            Some("list") => SessionAction::List,
+            Some(other) => return Err(CommandParseError::InvalidArgument(other.to_string())),
+            None => return Err(CommandParseError::MissingArgument("action".to_string())),
+        };
+        Ok(Command::Session(action))
+    }
+
     pub fn update_prefix(&mut self, new_prefix: String) {
         self.prefix = new_prefix.clone();
         self.escape_sequence = format!("\\{}", new_prefix);
@@ -475,10 +1049,40 @@ impl CommandParser {
     }
 }
 
+/// `template` with each `{name}` in `vars` replaced by its value. Values
+/// are inserted as they are, so a `{cwd}` in the output isn't expanded
+/// again; unknown names are left alone.
+pub fn expand_template(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        text.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let value = rest.find('}').and_then(|close| {
+            let name = &rest[1..close];
+            vars.iter().find(|(var, _)| *var == name).map(|(_, value)| (close, *value))
+        });
+        match value {
+            Some((close, value)) => {
+                text.push_str(value);
+                rest = &rest[close + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
 /// Specialized parser for agent command syntax
 struct AgentCommandParser {
     input: String,
     pos: usize,
+    /// Set by a `:noctx` among the options
+    no_context: bool,
 }
 
 impl AgentCommandParser {
@@ -486,6 +1090,7 @@ impl AgentCommandParser {
         Self {
             input: input.to_string(),
             pos: 0,
+            no_context: false,
         }
     }
 
@@ -502,7 +1107,10 @@ impl AgentCommandParser {
                 break;
             }
 
-            if self.peek() == Some('-') && self.peek_next() == Some('-') {
+            if let Some(rest) = strip_noctx(&self.input[self.pos..]) {
+                self.no_context = true;
+                self.pos = self.input.len() - rest.len();
+            } else if self.peek() == Some('-') && self.peek_next() == Some('-') {
                 // Parse argument
                 let (arg_name, arg_value) = self.parse_argument()?;
                 match arg_name.as_str() {
@@ -681,6 +1289,7 @@ impl AgentCommandParser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
 
     #[test]
     fn test_o1_prefix_detection() {
@@ -984,4 +1593,214 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_auto_calc() {
+        let mut parser = CommandParser::new("f".to_string());
+        let parsed = parser.parse("f 0xff * 2").unwrap();
+        assert!(matches!(parsed.command, Command::Calc(e) if e == "0xff * 2"));
+
+        // Prose, and explicit model options, still go to the agent
+        assert!(!matches!(parser.parse("f what is 2 + 2").unwrap().command, Command::Calc(_)));
+        assert!(!matches!(parser.parse("f --model gpt-4 1 + 1").unwrap().command, Command::Calc(_)));
+
+        parser.auto_calc = false;
+        assert!(!matches!(parser.parse("f 1 + 1").unwrap().command, Command::Calc(_)));
+    }
+
+    #[test]
+    fn test_response_commands() {
+        let mut parser = CommandParser::new("f".to_string());
+        assert!(matches!(parser.parse("f copy").unwrap().command, Command::CopyResponse { block: None }));
+        assert!(matches!(parser.parse("f copy code").unwrap().command, Command::CopyResponse { block: Some(1) }));
+        assert!(matches!(parser.parse("f insert code 3").unwrap().command, Command::InsertCode(3)));
+        assert!(parser.parse("f insert code 0").is_err());
+        // A question about copying still goes to the agent
+        assert!(matches!(parser.parse("f copy a file over ssh").unwrap().command, Command::Agent(_)));
+        assert!(matches!(parser.parse_builtin(":copy code 2"), Ok(Command::CopyResponse { block: Some(2) })));
+        assert!(matches!(parser.parse("f open").unwrap().command, Command::OpenLink(None)));
+        assert!(matches!(parser.parse("f open 2").unwrap().command, Command::OpenLink(Some(2))));
+        assert!(matches!(parser.parse("f/").unwrap().command, Command::Search(None)));
+        assert!(
+            matches!(parser.parse("f/ disk full ").unwrap().command, Command::Search(Some(p)) if p == "disk full")
+        );
+        assert!(matches!(parser.parse("f open the pod bay doors").unwrap().command, Command::Agent(_)));
+    }
+
+    #[test]
+    fn test_batch_command() {
+        let mut parser = CommandParser::new("f".to_string());
+        assert!(matches!(
+            parser.parse("f batch prompts.txt").unwrap().command,
+            Command::Batch { file, out: None } if file.as_path() == Path::new("prompts.txt")
+        ));
+        assert!(matches!(
+            parser.parse("f batch prompts.jsonl --out results.jsonl").unwrap().command,
+            Command::Batch { out: Some(out), .. } if out.as_path() == Path::new("results.jsonl")
+        ));
+        assert!(matches!(parser.parse_builtin(":batch a.txt --out"), Err(CommandParseError::MissingArgument(_))));
+        assert!(matches!(parser.parse_builtin(":batch a.txt b.txt"), Err(CommandParseError::InvalidArgument(_))));
+        assert!(matches!(parser.parse("f batch jobs versus streaming jobs").unwrap().command, Command::Agent(_)));
+    }
+
+    #[test]
+    fn test_noctx_flag() {
+        let mut parser = CommandParser::new("f".to_string());
+        let Command::Agent(cmd) = parser.parse("f --model gpt-4 :noctx why did that fail").unwrap().command else {
+            panic!("Expected Agent command");
+        };
+        assert!(cmd.no_context);
+        assert_eq!(cmd.prompt, "why did that fail");
+        assert_eq!(cmd.model_override.as_deref(), Some("gpt-4"));
+
+        let Command::Agent(cmd) = parser.parse("f :noctxish is a word").unwrap().command else {
+            panic!("Expected Agent command");
+        };
+        assert!(!cmd.no_context);
+
+        let cmd = AgentCommand::plain(":noctx explain".to_string());
+        assert!(cmd.no_context);
+        assert_eq!(cmd.prompt, "explain");
+    }
+
+    #[test]
+    fn test_parse_builtin() {
+        let mut parser = CommandParser::new("p".to_string());
+
+        assert!(matches!(parser.parse_builtin(":new-window"), Ok(Command::NewWindow)));
+        assert!(matches!(
+            parser.parse_builtin("model llama"),
+            Ok(Command::Model { name, force: false }) if name == "llama"
+        ));
+        assert!(matches!(
+            parser.parse_builtin("model --force llama"),
+            Ok(Command::Model { name, force: true }) if name == "llama"
+        ));
+        assert!(matches!(parser.parse_builtin("model"), Ok(Command::Model { name, .. }) if name.is_empty()));
+        assert!(parser.parse_builtin("model --force").is_err());
+        assert!(matches!(
+            parser.parse("p model qwen-14b --force").map(|p| p.command),
+            Ok(Command::Model { name, force: true }) if name == "qwen-14b"
+        ));
+        assert!(matches!(parser.parse("p model this data as a graph").map(|p| p.command), Ok(Command::Agent(_))));
+        assert!(matches!(parser.parse_builtin(":fold all"), Ok(Command::Fold { all: true })));
+        assert!(matches!(parser.parse_builtin(":unfold"), Ok(Command::Unfold { all: false })));
+        assert!(matches!(
+            parser.parse_builtin(":set guide 80 100"),
+            Ok(Command::Set(option, value)) if option == "guide" && value == "80 100"
+        ));
+        assert!(matches!(
+            parser.parse_builtin(":fold some"),
+            Err(CommandParseError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            parser.parse_builtin(":no-such-command"),
+            Err(CommandParseError::UnknownCommand(_))
+        ));
+        assert!(matches!(parser.parse_builtin(":theme auto"), Ok(Command::Theme(t)) if t == "auto"));
+        assert!(matches!(parser.parse_builtin(":readonly"), Ok(Command::ReadOnly(ReadOnlyMode::Toggle))));
+        assert!(matches!(parser.parse_builtin(":readonly on"), Ok(Command::ReadOnly(ReadOnlyMode::On))));
+        assert!(parser.parse_builtin(":readonly maybe").is_err());
+        assert!(matches!(parser.parse_builtin(":scaffold out/app"), Ok(Command::Scaffold(d)) if d.as_path() == Path::new("out/app")));
+        assert!(parser.parse_builtin(":scaffold").is_err());
+        assert!(matches!(
+            parser.parse_builtin(":show config --diff ui.font"),
+            Ok(Command::ShowConfig { path: Some(p), diff: true }) if p == "ui.font"
+        ));
+        assert!(matches!(parser.parse_builtin(":show config"), Ok(Command::ShowConfig { path: None, diff: false })));
+        assert!(parser.parse_builtin(":show keys").is_err());
+        assert!(matches!(
+            parser.parse_builtin(":show ui.font_size"),
+            Ok(Command::ShowConfig { path: Some(p), diff: false }) if p == "ui.font_size"
+        ));
+        assert!(matches!(
+            parser.parse_builtin(":set ui.font_size 16 --save"),
+            Ok(Command::SetConfig { path, value, save: true }) if path == "ui.font_size" && value == "16"
+        ));
+        assert!(matches!(parser.parse_builtin(":set guide 80"), Ok(Command::Set(o, v)) if o == "guide" && v == "80"));
+        assert!(parser.parse_builtin(":set font 16").is_err());
+
+        // After the prefix, settings commands are run rather than asked about
+        let mut parser = parser;
+        assert!(matches!(
+            parser.parse("pset ui.line_height 1.3").map(|p| p.command),
+            Ok(Command::SetConfig { path, .. }) if path == "ui.line_height"
+        ));
+        assert!(matches!(parser.parse("pset up a venv").map(|p| p.command), Ok(Command::Agent(_))));
+        assert!(matches!(parser.parse_builtin(":history prune"), Ok(Command::HistoryPrune { max_mb: None })));
+        assert!(matches!(parser.parse_builtin(":history prune 16"), Ok(Command::HistoryPrune { max_mb: Some(16) })));
+        assert!(parser.parse_builtin(":history prune lots").is_err());
+        assert!(matches!(parser.parse_builtin(":stats"), Ok(Command::Stats { json: false })));
+        assert!(matches!(parser.parse_builtin(":stats --json"), Ok(Command::Stats { json: true })));
+        assert!(matches!(parser.parse("p stats --json").map(|p| p.command), Ok(Command::Stats { json: true })));
+        assert!(matches!(parser.parse_builtin(":usage"), Ok(Command::Usage)));
+        assert!(matches!(parser.parse("p usage").map(|p| p.command), Ok(Command::Usage)));
+        assert!(matches!(parser.parse("p models").map(|p| p.command), Ok(Command::Models)));
+        assert!(matches!(parser.parse_builtin(":calc 0x10 + 1"), Ok(Command::Calc(e)) if e == "0x10 + 1"));
+        assert!(parser.parse_builtin(":calc").is_err());
+        assert!(matches!(
+            parser.parse_builtin(":session save work"),
+            Ok(Command::Session(SessionAction::Save(n))) if n == "work"
+        ));
+        assert!(matches!(parser.parse_builtin(":session list"), Ok(Command::Session(SessionAction::List))));
+        assert!(parser.parse_builtin(":session restore").is_err());
+        assert!(matches!(parser.parse_builtin(":help fold"), Ok(Command::Help(Some(c))) if c == "fold"));
+        assert!(parser.get_command_help(Some("set"), 80).contains("Usage: p set <option> <value...>"));
+
+        // After the prefix, help for a command is shown; other lines
+        // starting with `help` are questions
+        assert!(matches!(parser.parse("p help").map(|p| p.command), Ok(Command::Help(None))));
+        assert!(matches!(parser.parse("p help fold").map(|p| p.command), Ok(Command::Help(Some(c))) if c == "fold"));
+        assert!(matches!(parser.parse("p keys").map(|p| p.command), Ok(Command::Keys { conflicts: false })));
+        assert!(matches!(
+            parser.parse("p keys --conflicts").map(|p| p.command),
+            Ok(Command::Keys { conflicts: true })
+        ));
+        assert!(matches!(parser.parse("p help me write a loop").map(|p| p.command), Ok(Command::Agent(_))));
+        let overview = parser.get_command_help(None, 80);
+        assert!(parser.list_commands().iter().all(|name| overview.contains(&format!("  {} ", name))));
+        assert!(overview.contains("'p help <command>'"));
+    }
+
+    #[test]
+    fn test_user_commands() {
+        let mut parser = CommandParser::new("p".to_string());
+        let mut commands = BTreeMap::new();
+        commands.insert(
+            "gs".to_string(),
+            UserCommand::Shell { cmd: "git status".to_string(), detached: false, description: String::new() },
+        );
+        commands.insert(
+            "fold".to_string(),
+            UserCommand::Prompt { template: "{last_output}".to_string(), description: String::new() },
+        );
+        let errors = parser.set_user_commands(&commands);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Custom command 'fold' is not loaded: a built-in command has that name"
+        );
+
+        assert!(matches!(
+            parser.parse("p gs -s").map(|p| p.command),
+            Ok(Command::Custom(name, args)) if name == "gs" && args == ["-s"]
+        ));
+        assert!(parser.get_command_help(None, 80).contains("  gs "));
+        assert!(parser.get_command_help(Some("gs"), 80).contains("Run `git status`"));
+        assert!(matches!(parser.parse_builtin(":fold"), Ok(Command::Fold { all: false })));
+
+        // A reload replaces the earlier set
+        parser.set_user_commands(&BTreeMap::new());
+        assert!(matches!(parser.parse("p gs").map(|p| p.command), Ok(Command::Agent(_))));
+        assert!(parser.registry().get("gs").is_none());
+        assert!(parser.registry().get("fold").is_some());
+    }
+
+    #[test]
+    fn test_expand_template() {
+        let vars = [("last_output", "a {cwd} b"), ("cwd", "/tmp")];
+        assert_eq!(expand_template("Review:\n{last_output} in {cwd}", &vars), "Review:\na {cwd} b in /tmp");
+        assert_eq!(expand_template("{unknown} {cwd", &vars), "{unknown} {cwd");
+        assert_eq!(expand_template("{{cwd}}", &vars), "{/tmp}");
+    }
 }
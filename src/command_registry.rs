@@ -0,0 +1,752 @@
+use crate::command_parser::{Command, CommandParseError, ParsedCommand};
+use crate::config::ConfigManager;
+use crate::model_host::ModelHost;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use unicode_width::UnicodeWidthStr;
+
+/// One positional argument in a command's grammar
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArgSpec {
+    pub name: String,
+    pub required: bool,
+    /// Takes the rest of the line
+    pub variadic: bool,
+    /// Fixed values offered for completion
+    pub choices: Vec<String>,
+    /// Completes with registered command names
+    pub command_name: bool,
+    /// Completes with values known only at runtime
+    pub source: Option<CompletionSource>,
+}
+
+impl ArgSpec {
+    pub fn required(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            required: true,
+            variadic: false,
+            choices: Vec::new(),
+            command_name: false,
+            source: None,
+        }
+    }
+
+    pub fn optional(name: &str) -> Self {
+        Self {
+            required: false,
+            ..Self::required(name)
+        }
+    }
+
+    pub fn variadic(mut self) -> Self {
+        self.variadic = true;
+        self
+    }
+
+    pub fn choices(mut self, choices: &[&str]) -> Self {
+        self.choices = choices.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    pub fn command_name(mut self) -> Self {
+        self.command_name = true;
+        self
+    }
+
+    pub fn complete_from(mut self, source: CompletionSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    fn usage(&self) -> String {
+        let dots = if self.variadic { "..." } else { "" };
+        if self.required {
+            format!("<{}{}>", self.name, dots)
+        } else {
+            format!("[{}{}]", self.name, dots)
+        }
+    }
+}
+
+/// Where an argument's candidates come from when they change at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompletionSource {
+    Models,
+    Sessions,
+    Paths,
+}
+
+/// Candidates for a `CompletionSource`. Called on the input path, so it
+/// must answer at once: from a list kept up to date elsewhere, or a single
+/// directory read for paths.
+pub trait Completer: fmt::Debug + Send + Sync {
+    fn complete(&self, partial: &str) -> Vec<String>;
+}
+
+/// Names refreshed in the background, e.g. the registered models
+#[derive(Debug, Clone, Default)]
+pub struct CachedCompletions {
+    names: Arc<RwLock<Vec<String>>>,
+}
+
+impl CachedCompletions {
+    pub fn set(&self, names: Vec<String>) {
+        *self.names.write() = names;
+    }
+}
+
+impl Completer for CachedCompletions {
+    fn complete(&self, _partial: &str) -> Vec<String> {
+        self.names.read().clone()
+    }
+}
+
+/// Entries of the directory a partial path names, relative paths taken
+/// from the base directory; directories end in `/`
+#[derive(Debug, Clone, Default)]
+pub struct PathCompleter {
+    base: Arc<RwLock<Option<PathBuf>>>,
+}
+
+/// Entries listed from one directory, so a huge one cannot stall typing
+const MAX_PATH_CANDIDATES: usize = 256;
+
+impl PathCompleter {
+    /// The active pane's directory, or `None` for the process's own
+    pub fn set_base(&self, base: Option<PathBuf>) {
+        *self.base.write() = base;
+    }
+}
+
+impl Completer for PathCompleter {
+    fn complete(&self, partial: &str) -> Vec<String> {
+        let (dir, _) = partial.rsplit_once('/').unwrap_or(("", partial));
+        let shown = if partial.contains('/') { format!("{}/", dir) } else { String::new() };
+        let dir = match (dir, partial.starts_with('/')) {
+            ("", true) => PathBuf::from("/"),
+            (dir, _) => {
+                let dir = Path::new(if dir.is_empty() { "." } else { dir });
+                match self.base.read().as_ref() {
+                    Some(base) => base.join(dir),
+                    None => dir.to_path_buf(),
+                }
+            }
+        };
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .take(MAX_PATH_CANDIDATES)
+            .map(|entry| {
+                let slash = if entry.path().is_dir() { "/" } else { "" };
+                format!("{}{}{}", shown, entry.file_name().to_string_lossy(), slash)
+            })
+            .collect()
+    }
+}
+
+/// Completion candidates for the argument being typed, given the complete
+/// arguments before it
+pub type CompletionProvider = Arc<dyn Fn(&[String], &str) -> Vec<String> + Send + Sync>;
+
+/// Runs a command directly against the handles in `CommandContext`
+pub type AsyncCommandHandler = Arc<
+    dyn for<'a> Fn(
+            ParsedCommand,
+            &'a mut CommandContext,
+        ) -> BoxFuture<'a, Result<(), CommandParseError>>
+        + Send
+        + Sync,
+>;
+
+#[derive(Clone)]
+pub enum CommandHandler {
+    /// Turns arguments into a `Command` for the caller to act on
+    BuiltIn(fn(&[String]) -> Result<Command, CommandParseError>),
+    Async(AsyncCommandHandler),
+    /// Forwarded as `Command::Custom(action, args)`
+    Custom(String),
+}
+
+impl fmt::Debug for CommandHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandHandler::BuiltIn(_) => f.write_str("BuiltIn"),
+            CommandHandler::Async(_) => f.write_str("Async"),
+            CommandHandler::Custom(action) => write!(f, "Custom({:?})", action),
+        }
+    }
+}
+
+/// A command contributed to the registry by a module
+#[derive(Clone)]
+pub struct CommandSpec {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub description: String,
+    pub args: Vec<ArgSpec>,
+    pub examples: Vec<String>,
+    pub completion: Option<CompletionProvider>,
+    pub handler: CommandHandler,
+}
+
+impl fmt::Debug for CommandSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandSpec")
+            .field("name", &self.name)
+            .field("aliases", &self.aliases)
+            .field("args", &self.args)
+            .field("handler", &self.handler)
+            .finish()
+    }
+}
+
+impl CommandSpec {
+    pub fn new(name: &str, description: &str, handler: CommandHandler) -> Self {
+        Self {
+            name: name.to_string(),
+            aliases: Vec::new(),
+            description: description.to_string(),
+            args: Vec::new(),
+            examples: Vec::new(),
+            completion: None,
+            handler,
+        }
+    }
+
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.aliases.push(alias.to_string());
+        self
+    }
+
+    pub fn arg(mut self, arg: ArgSpec) -> Self {
+        self.args.push(arg);
+        self
+    }
+
+    pub fn example(mut self, example: &str) -> Self {
+        self.examples.push(example.to_string());
+        self
+    }
+
+    pub fn completion(mut self, provider: CompletionProvider) -> Self {
+        self.completion = Some(provider);
+        self
+    }
+
+    /// Usage line generated from the argument spec, e.g. `config <key> [value...]`
+    pub fn syntax(&self) -> String {
+        std::iter::once(self.name.clone())
+            .chain(self.args.iter().map(ArgSpec::usage))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn arg_at(&self, index: usize) -> Option<&ArgSpec> {
+        self.args
+            .get(index)
+            .or_else(|| self.args.last().filter(|arg| arg.variadic))
+    }
+}
+
+/// The active pane, as far as a command needs it
+pub trait PaneHandle: Send + Sync {
+    fn pane_id(&self) -> u64;
+    /// Write text into the pane's input, as if typed
+    fn send_input(&self, text: &str);
+}
+
+#[async_trait]
+pub trait ModelCatalog: Send + Sync {
+    async fn model_names(&self) -> Vec<String>;
+    async fn current_model(&self) -> Option<String>;
+}
+
+#[async_trait]
+impl ModelCatalog for ModelHost {
+    async fn model_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .list_models()
+            .await
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        names.sort();
+        names
+    }
+
+    async fn current_model(&self) -> Option<String> {
+        self.get_current_model().await
+    }
+}
+
+/// Read-only configuration lookup by dotted key, e.g. `ui.font_size`
+pub trait ConfigView: Send + Sync {
+    fn value(&self, key: &str) -> Option<String>;
+}
+
+impl ConfigView for ConfigManager {
+    fn value(&self, key: &str) -> Option<String> {
+        let mut value = serde_json::to_value(self.get_config()).ok()?;
+        for part in key.split('.') {
+            value = value.get(part)?.clone();
+        }
+        Some(match value {
+            serde_json::Value::String(s) => s,
+            other => other.to_string(),
+        })
+    }
+}
+
+/// Handles available to command handlers. Every handle is optional so
+/// handlers can be tested against just the fakes they need.
+#[derive(Default)]
+pub struct CommandContext {
+    pub pane: Option<Arc<dyn PaneHandle>>,
+    pub models: Option<Arc<dyn ModelCatalog>>,
+    pub config: Option<Arc<dyn ConfigView>>,
+    notices: Vec<String>,
+}
+
+impl CommandContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a short message for the renderer to show
+    pub fn notify(&mut self, message: impl Into<String>) {
+        self.notices.push(message.into());
+    }
+
+    pub fn take_notices(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notices)
+    }
+}
+
+/// Commands contributed by modules at startup, looked up by name or alias
+#[derive(Debug, Clone, Default)]
+pub struct CommandRegistry {
+    specs: HashMap<String, CommandSpec>,
+    aliases: HashMap<String, String>,
+    completers: HashMap<CompletionSource, Arc<dyn Completer>>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a command, replacing any earlier one with the same name
+    pub fn register(&mut self, spec: CommandSpec) {
+        self.unregister(&spec.name);
+        for alias in &spec.aliases {
+            self.aliases.insert(alias.clone(), spec.name.clone());
+        }
+        self.specs.insert(spec.name.clone(), spec);
+    }
+
+    /// Answer completions for arguments marked with `source`
+    pub fn set_completer(&mut self, source: CompletionSource, completer: Arc<dyn Completer>) {
+        self.completers.insert(source, completer);
+    }
+
+    pub fn unregister(&mut self, name: &str) -> bool {
+        let removed = self.specs.remove(name);
+        if let Some(spec) = &removed {
+            for alias in &spec.aliases {
+                self.aliases.remove(alias);
+            }
+        }
+        removed.is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CommandSpec> {
+        self.specs
+            .get(name)
+            .or_else(|| self.aliases.get(name).and_then(|n| self.specs.get(n)))
+    }
+
+    /// Registered command names, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.specs.keys().map(|s| s.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Closest registered name or alias to a mistyped command
+    pub fn suggest(&self, name: &str) -> Option<&str> {
+        let limit = (name.chars().count() / 3).max(2);
+        self.specs
+            .keys()
+            .chain(self.aliases.keys())
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= limit)
+            .min()
+            .map(|(_, candidate)| candidate.as_str())
+    }
+
+    pub fn unknown(&self, name: &str) -> CommandParseError {
+        match self.suggest(name) {
+            Some(suggestion) => CommandParseError::DidYouMean {
+                name: name.to_string(),
+                suggestion: suggestion.to_string(),
+            },
+            None => CommandParseError::UnknownCommand(name.to_string()),
+        }
+    }
+
+    /// `help` text generated from the specs, wrapped to `width` columns.
+    /// Without a command, a table of every command and how to type one
+    /// after `prefix`.
+    pub fn help(&self, command: Option<&str>, prefix: &str, width: usize) -> String {
+        let Some(name) = command else {
+            let rows: Vec<(String, String)> = self
+                .names()
+                .into_iter()
+                .map(|name| (name.to_string(), self.specs[name].description.clone()))
+                .collect();
+            return format!(
+                "Commands, typed after the prefix '{prefix}':\n{}\n{}",
+                format_columns(&rows, width),
+                fill(&format!("'{prefix} help <command>' shows its usage and examples; '{prefix} keys' lists the key bindings"), width),
+            );
+        };
+        let Some(spec) = self.get(name) else {
+            return self.unknown(name).to_string();
+        };
+        let mut help = format!("Usage: {} {}\n{}", prefix, spec.syntax(), fill(&spec.description, width));
+        if !spec.aliases.is_empty() {
+            help.push_str(&format!("\nAliases: {}", spec.aliases.join(", ")));
+        }
+        let (flags, args): (Vec<&ArgSpec>, Vec<&ArgSpec>) = spec.args.iter().partition(|arg| arg.name.starts_with("--"));
+        if !flags.is_empty() {
+            help.push_str(&format!(
+                "\nFlags: {}",
+                flags.iter().map(|flag| flag.name.as_str()).collect::<Vec<_>>().join(", ")
+            ));
+        }
+        let choices: Vec<(String, String)> = args
+            .iter()
+            .filter(|arg| !arg.choices.is_empty())
+            .map(|arg| (arg.name.clone(), format!("one of {}", arg.choices.join(", "))))
+            .collect();
+        if !choices.is_empty() {
+            help.push_str(&format!("\nArguments:\n{}", format_columns(&choices, width)));
+        }
+        if !spec.examples.is_empty() {
+            help.push_str("\nExamples:");
+            for example in &spec.examples {
+                help.push_str(&format!("\n  {}", example));
+            }
+        }
+        help
+    }
+
+    /// Candidates for the last word of `line` (without the leading `:`)
+    pub fn complete(&self, line: &str) -> Vec<String> {
+        let line = line.trim_start().trim_start_matches(':');
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let partial = if line.is_empty() || line.ends_with(char::is_whitespace) {
+            ""
+        } else {
+            words.pop().unwrap_or("")
+        };
+
+        let candidates: Vec<String> = match words.split_first() {
+            None => self.names().into_iter().map(str::to_string).collect(),
+            Some((name, args)) => {
+                let Some(spec) = self.get(name) else {
+                    return Vec::new();
+                };
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                match (&spec.completion, spec.arg_at(args.len())) {
+                    (Some(provider), _) => provider(&args, partial),
+                    (None, Some(arg)) if arg.command_name => {
+                        self.names().into_iter().map(str::to_string).collect()
+                    }
+                    (None, Some(ArgSpec { source: Some(source), .. })) => self
+                        .completers
+                        .get(source)
+                        .map(|completer| completer.complete(partial))
+                        .unwrap_or_default(),
+                    (None, Some(arg)) => arg.choices.clone(),
+                    (None, None) => Vec::new(),
+                }
+            }
+        };
+
+        let mut matches: Vec<String> = candidates
+            .into_iter()
+            .filter(|c| c.starts_with(partial))
+            .collect();
+        matches.sort();
+        matches.dedup();
+        matches
+    }
+
+    /// Split a `:name args...` line and check it against the grammar
+    pub fn resolve(&self, input: &str) -> Result<(&CommandSpec, Vec<String>), CommandParseError> {
+        let line = input.trim().trim_start_matches(':');
+        let mut parts = line.split_whitespace().map(|s| s.to_string());
+        let name = parts
+            .next()
+            .ok_or_else(|| CommandParseError::Syntax("empty command".to_string()))?;
+        let args: Vec<String> = parts.collect();
+        let spec = self.get(&name).ok_or_else(|| self.unknown(&name))?;
+
+        if let Some(missing) = spec.args.iter().skip(args.len()).find(|arg| arg.required) {
+            return Err(CommandParseError::MissingArgument(missing.name.clone()));
+        }
+        Ok((spec, args))
+    }
+
+    /// Parse and run a command line. Built-in commands come back as a
+    /// `Command` for the caller; async handlers run here and return `None`.
+    pub async fn dispatch(
+        &self,
+        input: &str,
+        context: &mut CommandContext,
+    ) -> Result<Option<Command>, CommandParseError> {
+        let (spec, args) = self.resolve(input)?;
+        match &spec.handler {
+            CommandHandler::BuiltIn(handler) => handler(&args).map(Some),
+            CommandHandler::Custom(action) => Ok(Some(Command::Custom(action.clone(), args))),
+            CommandHandler::Async(handler) => {
+                let parsed = ParsedCommand {
+                    command: Command::Custom(spec.name.clone(), args),
+                    raw_input: input.to_string(),
+                };
+                handler(parsed, context).await.map(|()| None)
+            }
+        }
+    }
+}
+
+/// Two columns, the first as wide as its longest entry, with the second
+/// wrapped to `width` and its continuation lines lined up under it
+pub fn format_columns(rows: &[(String, String)], width: usize) -> String {
+    let first = rows.iter().map(|(name, _)| name.width()).max().unwrap_or(0);
+    let indent = 2 + first + 2;
+    let wrap_at = width.saturating_sub(indent).max(20);
+    let mut table = String::new();
+    for (name, text) in rows {
+        let mut lines = textwrap::wrap(text, wrap_at).into_iter();
+        let pad = first - name.width();
+        table.push_str(&format!("  {}{}  {}\n", name, " ".repeat(pad), lines.next().unwrap_or_default()));
+        for line in lines {
+            table.push_str(&format!("{}{}\n", " ".repeat(indent), line));
+        }
+    }
+    table
+}
+
+fn fill(text: &str, width: usize) -> String {
+    textwrap::fill(text, width.max(20))
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_parser::CommandParser;
+    use parking_lot::Mutex;
+
+    #[derive(Default)]
+    struct FakePane {
+        typed: Mutex<Vec<String>>,
+    }
+
+    impl PaneHandle for FakePane {
+        fn pane_id(&self) -> u64 {
+            7
+        }
+
+        fn send_input(&self, text: &str) {
+            self.typed.lock().push(text.to_string());
+        }
+    }
+
+    fn greet_spec() -> CommandSpec {
+        let handler: AsyncCommandHandler = Arc::new(|parsed, context| {
+            Box::pin(async move {
+                let Command::Custom(_, args) = parsed.command else {
+                    unreachable!()
+                };
+                let pane = context
+                    .pane
+                    .clone()
+                    .ok_or_else(|| CommandParseError::Context("no active pane".to_string()))?;
+                pane.send_input(&format!("hello {}", args.join(" ")));
+                context.notify(format!("greeted in pane {}", pane.pane_id()));
+                Ok(())
+            })
+        });
+        CommandSpec::new(
+            "greet",
+            "Say hello in the active pane",
+            CommandHandler::Async(handler),
+        )
+        .alias("hi")
+        .arg(ArgSpec::required("name"))
+        .arg(ArgSpec::optional("style").choices(&["loud", "quiet", "plain"]))
+        .example(":greet world loud")
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_registered_command() {
+        let mut parser = CommandParser::new("p".to_string());
+        parser.register_command(greet_spec());
+
+        let pane = Arc::new(FakePane::default());
+        let mut context = CommandContext {
+            pane: Some(pane.clone()),
+            ..CommandContext::new()
+        };
+        let outcome = parser
+            .registry()
+            .dispatch(":hi world", &mut context)
+            .await
+            .unwrap();
+        assert!(outcome.is_none());
+        assert_eq!(*pane.typed.lock(), vec!["hello world".to_string()]);
+        assert_eq!(
+            context.take_notices(),
+            vec!["greeted in pane 7".to_string()]
+        );
+
+        assert!(matches!(
+            parser.registry().dispatch(":greet", &mut context).await,
+            Err(CommandParseError::MissingArgument(arg)) if arg == "name"
+        ));
+
+        // Built-ins still come back as commands
+        let outcome = parser
+            .registry()
+            .dispatch(":fold all", &mut context)
+            .await
+            .unwrap();
+        assert!(matches!(outcome, Some(Command::Fold { all: true })));
+    }
+
+    #[test]
+    fn test_completion_and_help_from_spec() {
+        let mut registry = CommandRegistry::new();
+        registry.register(greet_spec());
+        registry.register(
+            CommandSpec::new(
+                "help",
+                "Show help information",
+                CommandHandler::Custom("help".to_string()),
+            )
+            .arg(ArgSpec::optional("command").command_name()),
+        );
+        registry.register(
+            CommandSpec::new(
+                "theme",
+                "Switch theme",
+                CommandHandler::Custom("theme".to_string()),
+            )
+            .arg(ArgSpec::required("name"))
+            .completion(Arc::new(|_, _| {
+                vec!["dark".to_string(), "light".to_string()]
+            })),
+        );
+
+        assert_eq!(registry.complete(":gr"), vec!["greet"]);
+        assert_eq!(
+            registry.complete(":greet world "),
+            vec!["loud", "plain", "quiet"]
+        );
+        assert_eq!(registry.complete(":hi world q"), vec!["quiet"]);
+        assert_eq!(registry.complete(":help t"), vec!["theme"]);
+        assert_eq!(registry.complete(":theme l"), vec!["light"]);
+
+        let help = registry.help(Some("hi"), "p", 80);
+        assert!(help.contains("Usage: p greet <name> [style]"));
+        assert!(help.contains("Aliases: hi"));
+        assert!(help.contains("  style  one of loud, quiet, plain"));
+        assert!(help.contains("  :greet world loud"));
+        let overview = registry.help(None, "p", 80);
+        assert!(overview.starts_with("Commands, typed after the prefix 'p':"));
+        assert!(overview.find("greet").unwrap() < overview.find("theme").unwrap());
+        assert!(overview.contains("  greet  Say hello in the active pane\n"));
+    }
+
+    #[test]
+    fn test_completion_sources() {
+        let mut registry = CommandRegistry::new();
+        registry.register(
+            CommandSpec::new("model", "Switch model", CommandHandler::Custom("model".to_string()))
+                .arg(ArgSpec::optional("name").complete_from(CompletionSource::Models)),
+        );
+        registry.register(
+            CommandSpec::new("scaffold", "Write files", CommandHandler::Custom("scaffold".to_string()))
+                .arg(ArgSpec::required("dir").complete_from(CompletionSource::Paths)),
+        );
+        assert!(registry.complete(":model ").is_empty());
+
+        let models = CachedCompletions::default();
+        registry.set_completer(CompletionSource::Models, Arc::new(models.clone()));
+        models.set(vec!["llama".to_string(), "qwen".to_string(), "qwen-coder".to_string()]);
+        assert_eq!(registry.complete(":model q"), vec!["qwen", "qwen-coder"]);
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        std::fs::write(dir.path().join("setup.sh"), "").unwrap();
+        let paths = PathCompleter::default();
+        paths.set_base(Some(dir.path().to_path_buf()));
+        registry.set_completer(CompletionSource::Paths, Arc::new(paths));
+        assert_eq!(registry.complete(":scaffold s"), vec!["setup.sh", "src/"]);
+        assert_eq!(registry.complete(":scaffold src/"), vec!["src/main.rs"]);
+        assert!(registry.complete(":scaffold missing/").is_empty());
+    }
+
+    #[test]
+    fn test_columns_wrap_to_width() {
+        let rows = vec![
+            ("fold".to_string(), "Collapse command output to a summary line".to_string()),
+            ("annotations".to_string(), "List lines".to_string()),
+        ];
+        assert_eq!(
+            format_columns(&rows, 40),
+            "  fold         Collapse command output\n               to a summary line\n  annotations  List lines\n"
+        );
+    }
+
+    #[test]
+    fn test_typo_suggestions() {
+        let parser = CommandParser::new("p".to_string());
+        assert_eq!(parser.registry().suggest("fodl"), Some("fold"));
+        assert_eq!(parser.registry().suggest("scafold"), Some("scaffold"));
+        assert_eq!(parser.registry().suggest("zzzzzz"), None);
+
+        let err = parser.parse_builtin(":unfodl").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown command: unfodl (did you mean 'unfold'?)"
+        );
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
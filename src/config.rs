@@ -1,10 +1,13 @@
+use crate::config_provenance::{self, ConfigEntry, ConfigSource, Provenance};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::theme::Theme;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 use thiserror::Error;
-use toml_edit::{DocumentMut, Table};
+use tokio::sync::watch;
+use toml_edit::{DocumentMut, Table, TableLike};
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
@@ -23,48 +26,188 @@ pub enum ConfigError {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct UiConfig {
     pub font_size: u32,
+    /// Write font size changes made with Ctrl+Plus/Minus/0 to the file
+    pub save_font_size: bool,
     pub font_family: String,
+    /// Families tried, in order, for characters `font_family` lacks
+    pub font_fallback: Vec<String>,
+    /// Join `->`, `!=` and the like when the font has ligatures
+    pub ligatures: bool,
     pub theme: String,
+    pub theme_light: String,
+    pub theme_dark: String,
     pub cursor_style: String,
+    /// Blink the cursor unless an application asks for a steady one
+    pub cursor_blink: bool,
     pub line_height: f32,
     pub padding: u32,
     pub window_width: u32,
     pub window_height: u32,
+    pub auto_fold_lines: u32,
+    pub column_guides: Vec<u32>,
+    pub margin_shading: bool,
+    pub scrollback_live_lines: u32,
+    pub scrollback_lines: u32,
+    pub readonly_unlock: String,
+    pub readonly_bell: bool,
+    pub output_slice_ms: u32,
+    /// Markdown time per chunk of a streaming answer before the chunk
+    /// counts as over budget
+    pub answer_budget_ms: u8,
+    /// Chunks over budget in a row before the answer's rendering is
+    /// simplified a step
+    pub answer_degrade_after: u8,
+    pub lock_after_secs: u32,
+    pub lock_count_output: bool,
+    pub lock_style: String,
+    pub lock_unlock_word: String,
+    pub lock_wake_on_output: bool,
+    pub lock_wake_on_bell: bool,
+    /// Hold multi-line, control-character and risky pastes for confirmation
+    pub paste_warn: bool,
+    pub paste_newline_threshold: u32,
+    /// Regexes flagged in pasted text
+    pub paste_risky_patterns: Vec<String>,
+    /// No line-break warning when the application uses bracketed paste
+    pub paste_trust_bracketed: bool,
+    /// Remove control characters other than tab and line breaks from pastes
+    pub paste_strip_control: bool,
+    /// Size cap for recorded shell commands; 0 stops recording
+    pub command_history_max_mb: u32,
+    /// TOML file overriding user-facing strings; relative to the config
+    /// directory unless absolute
+    pub messages_file: String,
+    /// "auto", "truecolor", "256" or "16"
+    pub color_depth: String,
+    /// Restart stalled subsystems rather than only reporting them
+    pub watchdog_recovery: bool,
+    /// Suggest the rest of the command line as dim text at the prompt
+    pub ghost_text: bool,
+    /// When the shell exits: "close", "restart" or "hold"
+    pub on_shell_exit: String,
+    /// Copies beyond this many KiB are cut short; 0 = no limit
+    pub clipboard_max_kb: u32,
+    /// Copy through OSC 52 when there is no desktop clipboard
+    pub clipboard_osc52: bool,
+    /// syntect theme for code blocks in answers; empty leaves them plain
+    pub code_theme: String,
+    /// Lines scrolled per wheel notch, or per line height of trackpad travel
+    pub scroll_multiplier: f32,
+    /// Window and tab titles, from `{title}`, `{cwd}` and `{process}`
+    pub title_format: String,
 }
 
 impl Default for UiConfig {
     fn default() -> Self {
         Self {
             font_size: 14,
+            save_font_size: false,
             font_family: "SF Mono".to_string(),
+            font_fallback: Vec::new(),
+            ligatures: false,
             theme: "system".to_string(),
+            theme_light: "light".to_string(),
+            theme_dark: "dark".to_string(),
             cursor_style: "block".to_string(),
+            cursor_blink: false,
             line_height: 1.2,
             padding: 4,
             window_width: 90,
             window_height: 25,
+            auto_fold_lines: 0,
+            column_guides: Vec::new(),
+            margin_shading: false,
+            scrollback_live_lines: 2000,
+            scrollback_lines: 100_000,
+            readonly_unlock: "index".to_string(),
+            readonly_bell: true,
+            output_slice_ms: 2,
+            answer_budget_ms: 12,
+            answer_degrade_after: 5,
+            lock_after_secs: 0,
+            lock_count_output: false,
+            lock_style: "background".to_string(),
+            lock_unlock_word: String::new(),
+            lock_wake_on_output: false,
+            lock_wake_on_bell: false,
+            paste_warn: true,
+            paste_newline_threshold: 1,
+            paste_risky_patterns: crate::paste_guard::DEFAULT_RISKY_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            paste_trust_bracketed: false,
+            paste_strip_control: true,
+            command_history_max_mb: 16,
+            messages_file: String::new(),
+            color_depth: "auto".to_string(),
+            watchdog_recovery: true,
+            ghost_text: false,
+            on_shell_exit: "close".to_string(),
+            clipboard_max_kb: 1024,
+            clipboard_osc52: true,
+            code_theme: "base16-ocean.dark".to_string(),
+            scroll_multiplier: 3.0,
+            title_format: "{title} ({cwd})".to_string(),
         }
     }
 }
 
+/// Contexts a `[[keymap.context_bindings]]` entry can name
+pub const KEYMAP_CONTEXTS: [&str; 5] = ["global", "shell", "emacs", "vi", "agent"];
+
+/// One `[[keymap.context_bindings]]` entry
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ContextBinding {
+    pub key: String,
+    pub action: String,
+    /// One of `KEYMAP_CONTEXTS`
+    pub context: String,
+    /// The higher one wins when two bindings claim a key in one context
+    pub priority: u8,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct KeymapConfig {
-    pub bindings: HashMap<String, String>,
+    /// Key to action, in the global context
+    pub bindings: BTreeMap<String, String>,
+    /// Bindings with a context and priority of their own
+    pub context_bindings: Vec<ContextBinding>,
     pub prefix: String,
     pub escape_sequence: String,
+    /// Modifier that must be held with the prefix character ("alt", "ctrl",
+    /// ...). Empty means the bare character at line start.
+    pub prefix_modifier: String,
+    pub prefix_in_alternate_screen: bool,
+    /// Foreground programs during which prefix detection is off
+    pub prefix_suppressed_processes: Vec<String>,
+    /// How long a key sequence such as "ctrl+k ctrl+c" waits for its next
+    /// key before the keys held so far are handled on their own
+    pub sequence_timeout_ms: u32,
 }
 
 impl Default for KeymapConfig {
     fn default() -> Self {
-        let mut bindings = HashMap::new();
+        let mut bindings = BTreeMap::new();
         bindings.insert("ctrl+c".to_string(), "interrupt".to_string());
         bindings.insert("ctrl+d".to_string(), "eof".to_string());
         bindings.insert("ctrl+l".to_string(), "clear".to_string());
 
         Self {
             bindings,
+            context_bindings: Vec::new(),
             prefix: "p".to_string(),
             escape_sequence: "\\p".to_string(),
+            prefix_modifier: String::new(),
+            prefix_in_alternate_screen: false,
+            prefix_suppressed_processes: [
+                "vim", "nvim", "vi", "emacs", "nano", "less", "man", "htop", "top", "python",
+                "python3", "node", "irb", "ghci", "psql", "mysql", "sqlite3",
+            ]
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+            sequence_timeout_ms: 1000,
         }
     }
 }
@@ -73,9 +216,31 @@ impl Default for KeymapConfig {
 pub struct AgentConfig {
     pub default_model: String,
     pub context_lines: u32,
+    /// Send the pane's recent lines with agent requests; off keeps all
+    /// terminal output on this machine
+    pub context_capture: bool,
     pub timeout_ms: u64,
     pub max_tokens: u32,
     pub temperature: f32,
+    /// Persist completed responses across restarts
+    pub history_enabled: bool,
+    /// Total size of the history directory before the oldest segments go
+    pub history_max_mb: u32,
+    /// Segments older than this are pruned; 0 keeps them until the size cap
+    pub history_max_age_days: u32,
+    /// Responses loaded back into the history on startup
+    pub history_restore: u32,
+    /// Evaluate prefix lines that parse as arithmetic locally instead of
+    /// sending them to a model
+    pub auto_calc: bool,
+    /// Model for latency-sensitive work such as prompt suggestions; empty
+    /// uses `default_model`
+    pub fast_model: String,
+    /// Show model, elapsed time and tokens per second while an answer
+    /// streams, and a summary line when it ends
+    pub show_status_line: bool,
+    /// Prompts of a `batch` file answered at the same time
+    pub batch_max_parallel: u32,
 }
 
 impl Default for AgentConfig {
@@ -83,27 +248,93 @@ impl Default for AgentConfig {
         Self {
             default_model: "mistral-7b-instruct".to_string(),
             context_lines: 100,
+            context_capture: true,
             timeout_ms: 30000,
             max_tokens: 2048,
             temperature: 0.7,
+            history_enabled: true,
+            history_max_mb: 64,
+            history_max_age_days: 0,
+            history_restore: 100,
+            auto_calc: true,
+            fast_model: String::new(),
+            show_status_line: true,
+            batch_max_parallel: 4,
         }
     }
 }
 
+/// One `[[models.list]]` entry, registered with the model host at startup
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct ModelConfig {
     pub name: String,
+    /// "gguf", "mlc", "vllm", "ollama", "openai", "anthropic", "gemini" or
+    /// "remote"; empty picks one from `path` and `api_endpoint`
+    pub model_type: String,
     pub path: Option<String>,
     pub api_endpoint: Option<String>,
     pub api_key: Option<String>,
+    /// Environment variable holding the API key
+    pub api_key_env: Option<String>,
     pub quantization: String,
     pub context_window: u32,
+    pub vram_required_mb: u64,
+    /// Tried in order when this model fails
+    pub fallback_models: Vec<String>,
+    pub warm_pool_size: usize,
+    pub max_concurrent: usize,
+    /// Load at startup rather than on first use
+    pub preload: bool,
+    #[serde(default)]
+    pub cost_per_1k_prompt_tokens: Option<f64>,
+    #[serde(default)]
+    pub cost_per_1k_completion_tokens: Option<f64>,
+    /// Spend per UTC day after which requests to this model fail
+    #[serde(default)]
+    pub daily_budget: Option<f64>,
+    /// Unload a local model unused this long; it reloads on its next
+    /// request (0 = never)
+    #[serde(default)]
+    pub idle_unload_secs: u64,
+    /// Reload once VRAM frees up after a hot-swap unloads it
+    #[serde(default)]
+    pub keep_warm: bool,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            model_type: String::new(),
+            path: None,
+            api_endpoint: None,
+            api_key: None,
+            api_key_env: None,
+            quantization: "q4_0".to_string(),
+            context_window: 4096,
+            vram_required_mb: 0,
+            fallback_models: Vec::new(),
+            warm_pool_size: 1,
+            max_concurrent: 1,
+            preload: false,
+            cost_per_1k_prompt_tokens: None,
+            cost_per_1k_completion_tokens: None,
+            daily_budget: None,
+            idle_unload_secs: 0,
+            keep_warm: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ModelsConfig {
+    /// Read from `[[models.list]]`, or the older `[[models.models]]`
+    #[serde(rename = "list", alias = "models")]
     pub models: Vec<ModelConfig>,
     pub cache_dir: String,
+    /// Model for agent requests; empty uses `agent.default_model`
+    pub default_model: String,
 }
 
 impl Default for ModelsConfig {
@@ -112,12 +343,105 @@ impl Default for ModelsConfig {
             models: vec![ModelConfig {
                 name: "mistral-7b-instruct".to_string(),
                 path: Some("~/.cache/ferroterm/models/mistral-7b-instruct.gguf".to_string()),
-                api_endpoint: None,
-                api_key: None,
-                quantization: "q4_0".to_string(),
-                context_window: 4096,
+                ..Default::default()
             }],
             cache_dir: "~/.cache/ferroterm/models".to_string(),
+            default_model: String::new(),
+        }
+    }
+}
+
+impl ModelsConfig {
+    /// `default_model`, falling back to the agent's
+    pub fn default_model<'a>(&'a self, agent: &'a AgentConfig) -> &'a str {
+        match self.default_model.as_str() {
+            "" => &agent.default_model,
+            name => name,
+        }
+    }
+}
+
+/// How tabs and panes start their shell
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ShellConfig {
+    /// Path or name in `PATH`; empty uses `$SHELL`
+    pub program: String,
+    pub args: Vec<String>,
+    /// Start as a login shell, reading the profile files
+    pub login: bool,
+    /// Directory new shells start in; empty is the directory ferroterm was
+    /// started from. `~` is the home directory.
+    pub cwd: String,
+    /// New tabs and panes start in the focused pane's directory
+    pub inherit_cwd: bool,
+    /// Added to the environment, overriding `TERM=xterm-256color` and
+    /// `COLORTERM=truecolor`
+    pub env: BTreeMap<String, String>,
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        Self {
+            program: String::new(),
+            args: Vec::new(),
+            login: false,
+            cwd: String::new(),
+            inherit_cwd: true,
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+impl ShellConfig {
+    /// `cwd` with `~` expanded, `None` when unset
+    pub fn start_dir(&self) -> Option<PathBuf> {
+        match self.cwd.as_str() {
+            "" => None,
+            "~" => dirs::home_dir(),
+            cwd => match cwd.strip_prefix("~/") {
+                Some(rest) => dirs::home_dir().map(|home| home.join(rest)),
+                None => Some(PathBuf::from(cwd)),
+            },
+        }
+    }
+}
+
+/// A prefix command of the user's own, from `[commands]`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum UserCommand {
+    /// Typed into the active pane with any arguments appended, or with
+    /// `detached` run on its own and its output printed in the pane
+    Shell {
+        cmd: String,
+        #[serde(default)]
+        detached: bool,
+        #[serde(default)]
+        description: String,
+    },
+    /// Asked of the agent once `{last_output}`, `{selection}`, `{cwd}` and
+    /// `{args}` are filled in
+    Prompt {
+        template: String,
+        #[serde(default)]
+        description: String,
+    },
+}
+
+impl UserCommand {
+    /// What `help` says about it: the description, else the command or
+    /// the first line of the template
+    pub fn description(&self) -> String {
+        match self {
+            UserCommand::Shell { description, .. } | UserCommand::Prompt { description, .. }
+                if !description.is_empty() =>
+            {
+                description.clone()
+            }
+            UserCommand::Shell { cmd, .. } => format!("Run `{}`", cmd),
+            UserCommand::Prompt { template, .. } => {
+                format!("Ask: {}", template.lines().next().unwrap_or_default())
+            }
         }
     }
 }
@@ -147,7 +471,12 @@ pub struct Config {
     pub keymap: KeymapConfig,
     pub agent: AgentConfig,
     pub models: ModelsConfig,
+    pub shell: ShellConfig,
     pub telemetry: TelemetryConfig,
+    /// `[commands]` by name, typed after the prefix like built-in commands
+    pub commands: BTreeMap<String, UserCommand>,
+    /// `[theme]` colors as `#rrggbb`, replacing the built-in theme's
+    pub theme: BTreeMap<String, String>,
     pub includes: Vec<PathBuf>,
     #[serde(skip)]
     pub version: u32,
@@ -160,39 +489,65 @@ impl Default for Config {
             keymap: KeymapConfig::default(),
             agent: AgentConfig::default(),
             models: ModelsConfig::default(),
+            shell: ShellConfig::default(),
             telemetry: TelemetryConfig::default(),
+            commands: BTreeMap::new(),
+            theme: BTreeMap::new(),
             includes: vec![],
             version: 1,
         }
     }
 }
 
+impl Config {
+    /// The built-in theme `name` with the `[theme]` colors applied
+    pub fn theme(&self, name: &str) -> Theme {
+        Theme::named(name)
+            .with_overrides(&self.theme)
+            .unwrap_or_else(|_| Theme::named(name))
+    }
+}
+
+/// Layers chosen during the session, applied over the config file on every
+/// load
+#[derive(Debug, Clone, Default)]
+pub struct SessionLayers {
+    /// Table under `[profiles.<name>]` in the config file
+    pub profile: Option<String>,
+    /// Dotted path and value, in the order they were set
+    pub overrides: Vec<(String, serde_json::Value)>,
+}
+
+/// Prefix of environment variables that override a setting, e.g.
+/// `FERROTERM_UI_FONT_SIZE=16`
+pub const ENV_PREFIX: &str = "FERROTERM_";
+
 pub struct ConfigManager {
     config: Arc<RwLock<Config>>,
+    provenance: Arc<RwLock<Provenance>>,
+    session: Arc<RwLock<SessionLayers>>,
     config_path: PathBuf,
     watcher: Option<notify::RecommendedWatcher>,
+    /// Every config that replaces the current one
+    changes: Arc<watch::Sender<Config>>,
 }
 
 impl ConfigManager {
     pub fn from_path(config_path: PathBuf) -> Result<Self, ConfigError> {
-        let config = Self::load_config_from_path(&config_path)?;
+        let (config, provenance) = Self::resolve(&config_path, &SessionLayers::default(), &env_var)?;
 
         Ok(Self {
+            changes: Arc::new(watch::Sender::new(config.clone())),
             config: Arc::new(RwLock::new(config)),
+            provenance: Arc::new(RwLock::new(provenance)),
+            session: Arc::new(RwLock::new(SessionLayers::default())),
             config_path,
             watcher: None,
         })
     }
 
     pub fn new() -> Result<Self, ConfigError> {
-        let config_path = Self::get_config_path()?;
-        let config = Self::load_config_from_path(&config_path)?;
-
-        Ok(Self {
-            config: Arc::new(RwLock::new(config)),
-            config_path,
-            watcher: None,
-        })
+        Self::from_path(Self::get_config_path()?)
     }
 
     pub fn get_config_path() -> Result<PathBuf, ConfigError> {
@@ -227,7 +582,9 @@ impl ConfigManager {
                 config.keymap = include_config.keymap;
                 config.agent = include_config.agent;
                 config.models = include_config.models;
+                config.shell = include_config.shell;
                 config.telemetry = include_config.telemetry;
+                config.commands.extend(include_config.commands);
             }
         }
 
@@ -271,10 +628,22 @@ impl ConfigManager {
             config.models = Self::parse_models_config(models_table)?;
         }
 
+        if let Some(shell_table) = doc.get("shell").and_then(|item| item.as_table()) {
+            config.shell = Self::parse_shell_config(shell_table)?;
+        }
+
         if let Some(telemetry_table) = doc.get("telemetry").and_then(|item| item.as_table()) {
             config.telemetry = Self::parse_telemetry_config(telemetry_table)?;
         }
 
+        if let Some(commands_table) = doc.get("commands").and_then(|item| item.as_table()) {
+            config.commands = Self::parse_commands_config(commands_table)?;
+        }
+
+        if let Some(theme_table) = doc.get("theme").and_then(|item| item.as_table()) {
+            config.theme = Self::parse_theme_config(theme_table)?;
+        }
+
         if let Some(includes_array) = doc.get("includes").and_then(|v| v.as_array()) {
             for item in includes_array.iter() {
                 if let Some(path_str) = item.as_str() {
@@ -293,18 +662,40 @@ impl ConfigManager {
         if let Some(font_size) = table.get("font_size").and_then(|v| v.as_integer()) {
             ui.font_size = font_size as u32;
         }
+        if let Some(save) = table.get("save_font_size").and_then(|v| v.as_bool()) {
+            ui.save_font_size = save;
+        }
         if let Some(font_family) = table.get("font_family").and_then(|v| v.as_str()) {
             ui.font_family = font_family.to_string();
         }
+        if let Some(fallback) = table.get("font_fallback").and_then(|v| v.as_array()) {
+            ui.font_fallback = fallback
+                .iter()
+                .filter_map(|v| v.as_str())
+                .map(|family| family.to_string())
+                .collect();
+        }
+        if let Some(ligatures) = table.get("ligatures").and_then(|v| v.as_bool()) {
+            ui.ligatures = ligatures;
+        }
         if let Some(theme) = table.get("theme").and_then(|v| v.as_str()) {
             ui.theme = theme.to_string();
         }
         if ui.theme == "system" {
             ui.theme = Self::detect_system_theme();
         }
+        if let Some(theme) = table.get("theme_light").and_then(|v| v.as_str()) {
+            ui.theme_light = theme.to_string();
+        }
+        if let Some(theme) = table.get("theme_dark").and_then(|v| v.as_str()) {
+            ui.theme_dark = theme.to_string();
+        }
         if let Some(cursor_style) = table.get("cursor_style").and_then(|v| v.as_str()) {
             ui.cursor_style = cursor_style.to_string();
         }
+        if let Some(blink) = table.get("cursor_blink").and_then(|v| v.as_bool()) {
+            ui.cursor_blink = blink;
+        }
         if let Some(line_height) = table.get("line_height").and_then(|v| v.as_float()) {
             ui.line_height = line_height as f32;
         }
@@ -317,6 +708,114 @@ impl ConfigManager {
         if let Some(window_height) = table.get("window_height").and_then(|v| v.as_integer()) {
             ui.window_height = window_height as u32;
         }
+        if let Some(auto_fold_lines) = table.get("auto_fold_lines").and_then(|v| v.as_integer()) {
+            ui.auto_fold_lines = auto_fold_lines.max(0) as u32;
+        }
+        if let Some(guides) = table.get("column_guides").and_then(|v| v.as_array()) {
+            ui.column_guides = guides
+                .iter()
+                .filter_map(|v| v.as_integer())
+                .filter(|&col| col > 0)
+                .map(|col| col as u32)
+                .collect();
+        }
+        if let Some(margin_shading) = table.get("margin_shading").and_then(|v| v.as_bool()) {
+            ui.margin_shading = margin_shading;
+        }
+        if let Some(live_lines) = table.get("scrollback_live_lines").and_then(|v| v.as_integer()) {
+            ui.scrollback_live_lines = live_lines.max(0) as u32;
+        }
+        if let Some(lines) = table.get("scrollback_lines").and_then(|v| v.as_integer()) {
+            ui.scrollback_lines = lines.max(0) as u32;
+        }
+        if let Some(unlock) = table.get("readonly_unlock").and_then(|v| v.as_str()) {
+            ui.readonly_unlock = unlock.to_string();
+        }
+        if let Some(bell) = table.get("readonly_bell").and_then(|v| v.as_bool()) {
+            ui.readonly_bell = bell;
+        }
+        if let Some(slice_ms) = table.get("output_slice_ms").and_then(|v| v.as_integer()) {
+            ui.output_slice_ms = slice_ms.clamp(1, 100) as u32;
+        }
+        if let Some(budget) = table.get("answer_budget_ms").and_then(|v| v.as_integer()) {
+            ui.answer_budget_ms = budget.clamp(1, u8::MAX as i64) as u8;
+        }
+        if let Some(after) = table.get("answer_degrade_after").and_then(|v| v.as_integer()) {
+            ui.answer_degrade_after = after.clamp(1, u8::MAX as i64) as u8;
+        }
+        if let Some(secs) = table.get("lock_after_secs").and_then(|v| v.as_integer()) {
+            ui.lock_after_secs = secs.max(0) as u32;
+        }
+        if let Some(count) = table.get("lock_count_output").and_then(|v| v.as_bool()) {
+            ui.lock_count_output = count;
+        }
+        if let Some(style) = table.get("lock_style").and_then(|v| v.as_str()) {
+            ui.lock_style = style.to_string();
+        }
+        if let Some(word) = table.get("lock_unlock_word").and_then(|v| v.as_str()) {
+            ui.lock_unlock_word = word.to_string();
+        }
+        if let Some(wake) = table.get("lock_wake_on_output").and_then(|v| v.as_bool()) {
+            ui.lock_wake_on_output = wake;
+        }
+        if let Some(wake) = table.get("lock_wake_on_bell").and_then(|v| v.as_bool()) {
+            ui.lock_wake_on_bell = wake;
+        }
+        if let Some(warn) = table.get("paste_warn").and_then(|v| v.as_bool()) {
+            ui.paste_warn = warn;
+        }
+        if let Some(threshold) = table.get("paste_newline_threshold").and_then(|v| v.as_integer()) {
+            ui.paste_newline_threshold = threshold as u32;
+        }
+        if let Some(patterns) = table.get("paste_risky_patterns").and_then(|v| v.as_array()) {
+            ui.paste_risky_patterns = patterns
+                .iter()
+                .filter_map(|v| v.as_str())
+                .map(|pattern| pattern.to_string())
+                .collect();
+        }
+        if let Some(trust) = table.get("paste_trust_bracketed").and_then(|v| v.as_bool()) {
+            ui.paste_trust_bracketed = trust;
+        }
+        if let Some(strip) = table.get("paste_strip_control").and_then(|v| v.as_bool()) {
+            ui.paste_strip_control = strip;
+        }
+        if let Some(max_mb) = table.get("command_history_max_mb").and_then(|v| v.as_integer()) {
+            ui.command_history_max_mb = max_mb as u32;
+        }
+        if let Some(messages_file) = table.get("messages_file").and_then(|v| v.as_str()) {
+            ui.messages_file = messages_file.to_string();
+        }
+        if let Some(color_depth) = table.get("color_depth").and_then(|v| v.as_str()) {
+            ui.color_depth = color_depth.to_string();
+        }
+        if let Some(recovery) = table.get("watchdog_recovery").and_then(|v| v.as_bool()) {
+            ui.watchdog_recovery = recovery;
+        }
+        if let Some(ghost_text) = table.get("ghost_text").and_then(|v| v.as_bool()) {
+            ui.ghost_text = ghost_text;
+        }
+        if let Some(action) = table.get("on_shell_exit").and_then(|v| v.as_str()) {
+            ui.on_shell_exit = action.to_string();
+        }
+        if let Some(max_kb) = table.get("clipboard_max_kb").and_then(|v| v.as_integer()) {
+            ui.clipboard_max_kb = max_kb.max(0) as u32;
+        }
+        if let Some(osc52) = table.get("clipboard_osc52").and_then(|v| v.as_bool()) {
+            ui.clipboard_osc52 = osc52;
+        }
+        if let Some(theme) = table.get("code_theme").and_then(|v| v.as_str()) {
+            ui.code_theme = theme.to_string();
+        }
+        if let Some(multiplier) = table
+            .get("scroll_multiplier")
+            .and_then(|v| v.as_float().or_else(|| v.as_integer().map(|i| i as f64)))
+        {
+            ui.scroll_multiplier = multiplier.max(0.0) as f32;
+        }
+        if let Some(format) = table.get("title_format").and_then(|v| v.as_str()) {
+            ui.title_format = format.to_string();
+        }
 
         Ok(ui)
     }
@@ -330,6 +829,22 @@ impl ConfigManager {
         if let Some(escape_sequence) = table.get("escape_sequence").and_then(|v| v.as_str()) {
             keymap.escape_sequence = escape_sequence.to_string();
         }
+        if let Some(modifier) = table.get("prefix_modifier").and_then(|v| v.as_str()) {
+            keymap.prefix_modifier = modifier.to_string();
+        }
+        if let Some(enabled) = table.get("prefix_in_alternate_screen").and_then(|v| v.as_bool()) {
+            keymap.prefix_in_alternate_screen = enabled;
+        }
+        if let Some(processes) = table.get("prefix_suppressed_processes").and_then(|v| v.as_array()) {
+            keymap.prefix_suppressed_processes = processes
+                .iter()
+                .filter_map(|v| v.as_str())
+                .map(|name| name.to_string())
+                .collect();
+        }
+        if let Some(timeout) = table.get("sequence_timeout_ms").and_then(|v| v.as_integer()) {
+            keymap.sequence_timeout_ms = timeout.clamp(0, u32::MAX as i64) as u32;
+        }
         if let Some(bindings_table) = table.get("bindings").and_then(|v| v.as_table()) {
             for (key, value) in bindings_table.iter() {
                 if let Some(action) = value.as_str() {
@@ -338,9 +853,64 @@ impl ConfigManager {
             }
         }
 
+        // Tables (`[[keymap.context_bindings]]`) or inline tables
+        // (`context_bindings = [{ ... }]`)
+        if let Some(item) = table.get("context_bindings") {
+            let entries: Vec<&dyn TableLike> = if let Some(tables) = item.as_array_of_tables() {
+                tables.iter().map(|entry| entry as &dyn TableLike).collect()
+            } else if let Some(array) = item.as_array() {
+                array
+                    .iter()
+                    .map(|entry| entry.as_inline_table().map(|entry| entry as &dyn TableLike))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| ConfigError::Validation("keymap.context_bindings: entries must be tables".to_string()))?
+            } else {
+                return Err(ConfigError::Validation("keymap.context_bindings must be an array of tables".to_string()));
+            };
+            keymap.context_bindings = entries
+                .into_iter()
+                .enumerate()
+                .map(|(index, entry)| Self::parse_context_binding(index, entry))
+                .collect::<Result<_, _>>()?;
+        }
+
         Ok(keymap)
     }
 
+    /// `{ key, action, context, priority }`, in the global context at
+    /// priority 100 unless given
+    fn parse_context_binding(index: usize, entry: &dyn TableLike) -> Result<ContextBinding, ConfigError> {
+        let invalid = |reason: &str| ConfigError::Validation(format!("keymap.context_bindings[{}]: {}", index, reason));
+        let string = |key: &str| {
+            entry
+                .get(key)
+                .and_then(|v| v.as_str())
+                .filter(|value| !value.trim().is_empty())
+                .map(str::to_string)
+        };
+        let context = match entry.get("context") {
+            None => "global".to_string(),
+            Some(value) => value
+                .as_str()
+                .filter(|context| KEYMAP_CONTEXTS.contains(context))
+                .ok_or_else(|| invalid(&format!("context must be one of {}", KEYMAP_CONTEXTS.join(", "))))?
+                .to_string(),
+        };
+        let priority = match entry.get("priority") {
+            None => 100,
+            Some(value) => value
+                .as_integer()
+                .and_then(|priority| u8::try_from(priority).ok())
+                .ok_or_else(|| invalid("priority must be a number from 0 to 255"))?,
+        };
+        Ok(ContextBinding {
+            key: string("key").ok_or_else(|| invalid("needs a key"))?,
+            action: string("action").ok_or_else(|| invalid("needs an action"))?,
+            context,
+            priority,
+        })
+    }
+
     fn parse_agent_config(table: &Table) -> Result<AgentConfig, ConfigError> {
         let mut agent = AgentConfig::default();
 
@@ -350,6 +920,9 @@ impl ConfigManager {
         if let Some(context_lines) = table.get("context_lines").and_then(|v| v.as_integer()) {
             agent.context_lines = context_lines as u32;
         }
+        if let Some(capture) = table.get("context_capture").and_then(|v| v.as_bool()) {
+            agent.context_capture = capture;
+        }
         if let Some(timeout_ms) = table.get("timeout_ms").and_then(|v| v.as_integer()) {
             agent.timeout_ms = timeout_ms as u64;
         }
@@ -359,6 +932,30 @@ impl ConfigManager {
         if let Some(temperature) = table.get("temperature").and_then(|v| v.as_float()) {
             agent.temperature = temperature as f32;
         }
+        if let Some(enabled) = table.get("history_enabled").and_then(|v| v.as_bool()) {
+            agent.history_enabled = enabled;
+        }
+        if let Some(max_mb) = table.get("history_max_mb").and_then(|v| v.as_integer()) {
+            agent.history_max_mb = max_mb as u32;
+        }
+        if let Some(days) = table.get("history_max_age_days").and_then(|v| v.as_integer()) {
+            agent.history_max_age_days = days as u32;
+        }
+        if let Some(restore) = table.get("history_restore").and_then(|v| v.as_integer()) {
+            agent.history_restore = restore as u32;
+        }
+        if let Some(auto_calc) = table.get("auto_calc").and_then(|v| v.as_bool()) {
+            agent.auto_calc = auto_calc;
+        }
+        if let Some(fast_model) = table.get("fast_model").and_then(|v| v.as_str()) {
+            agent.fast_model = fast_model.to_string();
+        }
+        if let Some(show) = table.get("show_status_line").and_then(|v| v.as_bool()) {
+            agent.show_status_line = show;
+        }
+        if let Some(parallel) = table.get("batch_max_parallel").and_then(|v| v.as_integer()) {
+            agent.batch_max_parallel = parallel.clamp(1, u32::MAX as i64) as u32;
+        }
 
         Ok(agent)
     }
@@ -369,50 +966,108 @@ impl ConfigManager {
         if let Some(cache_dir) = table.get("cache_dir").and_then(|v| v.as_str()) {
             models_config.cache_dir = cache_dir.to_string();
         }
+        if let Some(default_model) = table.get("default_model").and_then(|v| v.as_str()) {
+            models_config.default_model = default_model.to_string();
+        }
 
-        if let Some(models_array) = table.get("models").and_then(|v| v.as_array()) {
-            let mut models = Vec::new();
-            for model_item in models_array.iter() {
-                if let Some(model_table) = model_item.as_inline_table() {
-                    let mut model = ModelConfig {
-                        name: String::new(),
-                        path: None,
-                        api_endpoint: None,
-                        api_key: None,
-                        quantization: "q4_0".to_string(),
-                        context_window: 4096,
-                    };
-
-                    if let Some(name) = model_table.get("name").and_then(|v| v.as_str()) {
-                        model.name = name.to_string();
-                    }
-                    if let Some(path) = model_table.get("path").and_then(|v| v.as_str()) {
-                        model.path = Some(path.to_string());
-                    }
-                    if let Some(endpoint) = model_table.get("api_endpoint").and_then(|v| v.as_str())
-                    {
-                        model.api_endpoint = Some(endpoint.to_string());
-                    }
-                    if let Some(key) = model_table.get("api_key").and_then(|v| v.as_str()) {
-                        model.api_key = Some(key.to_string());
-                    }
-                    if let Some(quant) = model_table.get("quantization").and_then(|v| v.as_str()) {
-                        model.quantization = quant.to_string();
-                    }
-                    if let Some(context) = model_table
-                        .get("context_window")
-                        .and_then(|v| v.as_integer())
-                    {
-                        model.context_window = context as u32;
-                    }
+        // Tables (`[[models.list]]`) or inline tables (`list = [{ ... }]`)
+        let item = table.get("list").or_else(|| table.get("models"));
+        if let Some(tables) = item.and_then(|v| v.as_array_of_tables()) {
+            models_config.models = tables
+                .iter()
+                .map(|model| Self::parse_model(&|key| model.get(key).and_then(|v| v.as_value())))
+                .collect();
+        } else if let Some(models_array) = item.and_then(|v| v.as_array()) {
+            models_config.models = models_array
+                .iter()
+                .filter_map(|model| model.as_inline_table())
+                .map(|model| Self::parse_model(&|key| model.get(key)))
+                .collect();
+        }
 
-                    models.push(model);
-                }
+        Ok(models_config)
+    }
+
+    fn parse_model<'a>(get: &dyn Fn(&str) -> Option<&'a toml_edit::Value>) -> ModelConfig {
+        let mut model = ModelConfig::default();
+        let string = |key: &str| get(key).and_then(|v| v.as_str()).map(str::to_string);
+        let integer = |key: &str| get(key).and_then(|v| v.as_integer());
+        let float = |key: &str| {
+            let value = get(key)?;
+            value.as_float().or_else(|| value.as_integer().map(|i| i as f64))
+        };
+
+        if let Some(name) = string("name") {
+            model.name = name;
+        }
+        if let Some(model_type) = string("model_type") {
+            model.model_type = model_type;
+        }
+        model.path = string("path");
+        model.api_endpoint = string("api_endpoint");
+        model.api_key = string("api_key");
+        model.api_key_env = string("api_key_env");
+        if let Some(quant) = string("quantization") {
+            model.quantization = quant;
+        }
+        if let Some(context) = integer("context_window") {
+            model.context_window = context as u32;
+        }
+        if let Some(vram) = integer("vram_required_mb") {
+            model.vram_required_mb = vram as u64;
+        }
+        if let Some(fallbacks) = get("fallback_models").and_then(|v| v.as_array()) {
+            model.fallback_models = fallbacks.iter().filter_map(|v| v.as_str()).map(str::to_string).collect();
+        }
+        if let Some(size) = integer("warm_pool_size") {
+            model.warm_pool_size = size as usize;
+        }
+        if let Some(concurrent) = integer("max_concurrent") {
+            model.max_concurrent = concurrent as usize;
+        }
+        if let Some(preload) = get("preload").and_then(|v| v.as_bool()) {
+            model.preload = preload;
+        }
+        if let Some(idle) = integer("idle_unload_secs") {
+            model.idle_unload_secs = idle.max(0) as u64;
+        }
+        if let Some(keep_warm) = get("keep_warm").and_then(|v| v.as_bool()) {
+            model.keep_warm = keep_warm;
+        }
+        model.cost_per_1k_prompt_tokens = float("cost_per_1k_prompt_tokens");
+        model.cost_per_1k_completion_tokens = float("cost_per_1k_completion_tokens");
+        model.daily_budget = float("daily_budget");
+        model
+    }
+
+    fn parse_shell_config(table: &Table) -> Result<ShellConfig, ConfigError> {
+        let mut shell = ShellConfig::default();
+
+        if let Some(program) = table.get("program").and_then(|v| v.as_str()) {
+            shell.program = program.to_string();
+        }
+        if let Some(args) = table.get("args").and_then(|v| v.as_array()) {
+            shell.args = args.iter().filter_map(|v| v.as_str()).map(|arg| arg.to_string()).collect();
+        }
+        if let Some(login) = table.get("login").and_then(|v| v.as_bool()) {
+            shell.login = login;
+        }
+        if let Some(cwd) = table.get("cwd").and_then(|v| v.as_str()) {
+            shell.cwd = cwd.to_string();
+        }
+        if let Some(inherit_cwd) = table.get("inherit_cwd").and_then(|v| v.as_bool()) {
+            shell.inherit_cwd = inherit_cwd;
+        }
+        if let Some(env_table) = table.get("env").and_then(|v| v.as_table()) {
+            for (key, item) in env_table.iter() {
+                let value = item.as_str().ok_or_else(|| {
+                    ConfigError::Validation(format!("shell.env.{} must be a string", key))
+                })?;
+                shell.env.insert(key.to_string(), value.to_string());
             }
-            models_config.models = models;
         }
 
-        Ok(models_config)
+        Ok(shell)
     }
 
     fn parse_telemetry_config(table: &Table) -> Result<TelemetryConfig, ConfigError> {
@@ -434,6 +1089,51 @@ impl ConfigManager {
         Ok(telemetry)
     }
 
+    /// `name = { type = "shell", cmd = "..." }` or a `[commands.name]`
+    /// table; a prompt has a `template` instead of `cmd`
+    fn parse_commands_config(table: &Table) -> Result<BTreeMap<String, UserCommand>, ConfigError> {
+        let mut commands = BTreeMap::new();
+        for (name, item) in table.iter() {
+            let invalid = |reason: &str| ConfigError::Validation(format!("commands.{}: {}", name, reason));
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(invalid("names cannot be empty or contain spaces"));
+            }
+            let entry = item.as_table_like().ok_or_else(|| invalid("must be a table"))?;
+            let string = |key: &str| entry.get(key).and_then(|v| v.as_str()).map(str::to_string);
+            let description = string("description").unwrap_or_default();
+            let command = match string("type").as_deref() {
+                Some("shell") => UserCommand::Shell {
+                    cmd: string("cmd").filter(|cmd| !cmd.trim().is_empty()).ok_or_else(|| invalid("needs a cmd"))?,
+                    detached: entry.get("detached").and_then(|v| v.as_bool()).unwrap_or(false),
+                    description,
+                },
+                Some("prompt") => UserCommand::Prompt {
+                    template: string("template")
+                        .filter(|template| !template.trim().is_empty())
+                        .ok_or_else(|| invalid("needs a template"))?,
+                    description,
+                },
+                _ => return Err(invalid("type must be \"shell\" or \"prompt\"")),
+            };
+            commands.insert(name.to_string(), command);
+        }
+        Ok(commands)
+    }
+
+    fn parse_theme_config(table: &Table) -> Result<BTreeMap<String, String>, ConfigError> {
+        let mut colors = BTreeMap::new();
+        for (key, item) in table.iter() {
+            let value = item.as_str().ok_or_else(|| {
+                ConfigError::Validation(format!("theme.{} must be a \"#rrggbb\" string", key))
+            })?;
+            colors.insert(key.to_string(), value.to_string());
+        }
+        Theme::default()
+            .with_overrides(&colors)
+            .map_err(|e| ConfigError::Validation(format!("theme: {}", e)))?;
+        Ok(colors)
+    }
+
     fn validate_config(config: &Config) -> Result<(), ConfigError> {
         if config.ui.font_size < 6 || config.ui.font_size > 72 {
             return Err(ConfigError::Validation(
@@ -453,6 +1153,19 @@ impl ConfigManager {
             ));
         }
 
+        if !["background", "dim"].contains(&config.ui.lock_style.as_str()) {
+            return Err(ConfigError::Validation(
+                "lock_style must be 'background' or 'dim'".to_string(),
+            ));
+        }
+
+        if let Err(e) = crate::paste_guard::compile_patterns(&config.ui.paste_risky_patterns) {
+            return Err(ConfigError::Validation(format!(
+                "paste_risky_patterns: {}",
+                e
+            )));
+        }
+
         if config.keymap.prefix.is_empty() {
             return Err(ConfigError::Validation(
                 "prefix cannot be empty".to_string(),
@@ -465,20 +1178,6 @@ impl ConfigManager {
             ));
         }
 
-        for model in &config.models.models {
-            if model.name.is_empty() {
-                return Err(ConfigError::Validation(
-                    "model name cannot be empty".to_string(),
-                ));
-            }
-            if model.path.is_none() && model.api_endpoint.is_none() {
-                return Err(ConfigError::Validation(format!(
-                    "model '{}' must have either path or api_endpoint",
-                    model.name
-                )));
-            }
-        }
-
         Ok(())
     }
 
@@ -504,43 +1203,132 @@ impl ConfigManager {
 [ui]
 # Font configuration
 font_size = {}
+save_font_size = {}  # Keep Ctrl+Plus/Minus/0 changes for the next start
 font_family = "{}"
-theme = "{}"
+font_fallback = {:?}  # Families for characters the font lacks, in priority order
+ligatures = {}  # Draw the font's ligatures; they break at the cursor
+theme = "{}"  # "auto" follows the OS between theme_light and theme_dark
+theme_light = "{}"
+theme_dark = "{}"
 cursor_style = "{}"  # Options: "block", "beam", "underline"
+cursor_blink = {}  # Applications can still ask for a steady or blinking cursor
 line_height = {}
 padding = {}
 window_width = {}
 window_height = {}
+auto_fold_lines = {}  # Fold command output longer than this many lines (0 = off)
+column_guides = {:?}  # Columns to draw ruler lines at, e.g. [80, 100]
+margin_shading = {}  # Tint cells beyond the last column guide
+scrollback_live_lines = {}  # Recent lines kept uncompressed; older history is compressed
+scrollback_lines = {}  # Most lines of history kept; the oldest are dropped first
+readonly_unlock = "{}"  # Leaving read-only: "index" (type the pane number), "hold" or "none"
+readonly_bell = {}  # Ring the bell when input to a read-only pane is swallowed
+output_slice_ms = {}  # Time per frame spent parsing program output before yielding to input
+answer_budget_ms = {}  # Markdown time per chunk of a streaming answer before it is over budget
+answer_degrade_after = {}  # Chunks over budget in a row before the answer's rendering is simplified
+lock_after_secs = {}  # Blank the window after this long without input (0 = off)
+lock_count_output = {}  # Program output also counts as activity
+lock_style = "{}"  # Blanked window: "background" or "dim"
+lock_unlock_word = {:?}  # Word to type before input resumes; a fat-finger guard, not security
+lock_wake_on_output = {}  # New output wakes the display
+lock_wake_on_bell = {}  # A bell wakes the display
+paste_warn = {}  # Confirm multi-line or risky pastes
+paste_newline_threshold = {}  # Line breaks that trigger the warning (0 = never)
+paste_risky_patterns = {:?}  # Regexes highlighted in the preview
+paste_trust_bracketed = {}  # Skip the line-break warning when the app uses bracketed paste
+paste_strip_control = {}  # Drop control characters other than tab and line breaks from pastes
+command_history_max_mb = {}  # Size cap for recorded shell commands (0 = off)
+messages_file = "{}"  # TOML file overriding user-facing strings ("" = built-in English)
+color_depth = "{}"  # Colors for styled responses: "auto", "truecolor", "256" or "16"
+watchdog_recovery = {}  # Restart a stalled render loop or PTY reader (false = report only)
+ghost_text = {}  # Suggest command completions from agent.fast_model at the prompt
+on_shell_exit = "{}"  # When the shell exits: "close" the tab, "restart" it or "hold" the output
+clipboard_max_kb = {}  # Copies larger than this are truncated (0 = no limit)
+clipboard_osc52 = {}  # Fall back to OSC 52 when no desktop clipboard is reachable
+code_theme = "{}"  # Highlighting theme for code in answers ("" = plain)
+scroll_multiplier = {}  # Lines per mouse wheel notch
+title_format = "{}"  # Window and tab titles from {{title}}, {{cwd}} and {{process}}
+
+[theme]
+# Colors as '#rrggbb', replacing those of the theme ui.theme names. Keys:
+# black, red, green, yellow, blue, magenta, cyan, white and their bright_
+# forms, foreground, background, cursor, selection, heading1, heading2,
+# heading, link, code, muted
+# background = '#1e1e1e'
 
 [keymap]
 # Command prefix for AI agent (default: 'f')
 prefix = "{}"
-escape_sequence = "{}"
-
-# Key bindings (add your custom bindings here)
+escape_sequence = {:?}
+prefix_modifier = "{}"  # e.g. "alt" to use alt+prefix anywhere instead of the bare key at line start
+prefix_in_alternate_screen = {}  # Detect the prefix in full-screen applications
+prefix_suppressed_processes = {:?}  # No prefix while these run in the foreground
+sequence_timeout_ms = {}  # Wait for the next key of a sequence binding such as "ctrl+k ctrl+c"
+
+# Key bindings (add your custom bindings here); keys separated by spaces
+# form a sequence, e.g. "g g" = "scroll_to_top"
 [keymap.bindings]
 "ctrl+c" = "interrupt"
 "ctrl+d" = "eof"
 "ctrl+l" = "clear"
 
+# Bindings for one context (global, shell, emacs, vi or agent). When two
+# claim a key in the same context the higher priority (default 100) wins;
+# `p keys --conflicts` lists the ones that shadow each other.
+# [[keymap.context_bindings]]
+# key = "ctrl+a"
+# action = "line_start"
+# context = "emacs"
+# priority = 80
+
 [agent]
 # AI agent configuration
 default_model = "{}"
 context_lines = {}  # Number of terminal lines to include as context
+context_capture = {}  # Send recent terminal lines with requests (secrets are masked)
 timeout_ms = {}     # Request timeout in milliseconds
 max_tokens = {}     # Maximum tokens in response
 temperature = {}    # Creativity level (0.0-2.0)
+history_enabled = {}  # Keep responses across restarts
+history_max_mb = {}   # Size cap for stored responses
+history_max_age_days = {}  # Prune older responses (0 = size cap only)
+history_restore = {}  # Responses reloaded on startup
+auto_calc = {}  # Evaluate arithmetic like `p 0xff * 2` without a model
+fast_model = "{}"  # Model for prompt suggestions ("" = default_model)
+show_status_line = {}  # Progress and tok/s while an answer streams
+batch_max_parallel = {}  # Prompts of a batch file answered at once
 
 [models]
 # Model storage directory
 cache_dir = "{}"
+default_model = "{}"  # Model for agent requests ("" = agent.default_model)
 
-# Available models
-[[models.models]]
+# Available models, registered at startup; broken entries are skipped
+[[models.list]]
 name = "{}"
 path = "{}"
 quantization = "{}"
 context_window = {}
+preload = {}  # Load at startup rather than on first use
+idle_unload_secs = {}  # Free its VRAM after this long unused (0 = never)
+keep_warm = {}  # Reload after a hot-swap unloads it, once VRAM frees up
+
+[shell]
+program = "{}"  # Path or name in PATH ("" = $SHELL)
+args = {:?}
+login = {}  # Start a login shell, e.g. `zsh -l`
+cwd = "{}"  # Start directory, "~" allowed ("" = where ferroterm was started)
+inherit_cwd = {}  # New tabs and panes start in the focused pane's directory
+
+# Extra environment; TERM=xterm-256color and COLORTERM=truecolor unless set here
+[shell.env]
+
+# Prefix commands of your own, e.g. `p gs`. Prompts can use {{last_output}},
+# {{selection}}, {{cwd}} and {{args}}; `detached = true` runs a shell command
+# on its own and prints its output in the pane.
+[commands]
+# gs = {{ type = "shell", cmd = "git status" }}
+# rev = {{ type = "prompt", template = "Review this output:\n{{last_output}}" }}
 
 [telemetry]
 # Telemetry is opt-in only and helps improve Ferroterm
@@ -553,25 +1341,85 @@ flush_interval_ms = {}
 includes = ["~/.ferroterm/extra.toml"]
 "#,
             config.ui.font_size,
+            config.ui.save_font_size,
             config.ui.font_family,
+            config.ui.font_fallback,
+            config.ui.ligatures,
             config.ui.theme,
+            config.ui.theme_light,
+            config.ui.theme_dark,
             config.ui.cursor_style,
+            config.ui.cursor_blink,
             config.ui.line_height,
             config.ui.padding,
             config.ui.window_width,
             config.ui.window_height,
+            config.ui.auto_fold_lines,
+            config.ui.column_guides,
+            config.ui.margin_shading,
+            config.ui.scrollback_live_lines,
+            config.ui.scrollback_lines,
+            config.ui.readonly_unlock,
+            config.ui.readonly_bell,
+            config.ui.output_slice_ms,
+            config.ui.answer_budget_ms,
+            config.ui.answer_degrade_after,
+            config.ui.lock_after_secs,
+            config.ui.lock_count_output,
+            config.ui.lock_style,
+            config.ui.lock_unlock_word,
+            config.ui.lock_wake_on_output,
+            config.ui.lock_wake_on_bell,
+            config.ui.paste_warn,
+            config.ui.paste_newline_threshold,
+            config.ui.paste_risky_patterns,
+            config.ui.paste_trust_bracketed,
+            config.ui.paste_strip_control,
+            config.ui.command_history_max_mb,
+            config.ui.messages_file,
+            config.ui.color_depth,
+            config.ui.watchdog_recovery,
+            config.ui.ghost_text,
+            config.ui.on_shell_exit,
+            config.ui.clipboard_max_kb,
+            config.ui.clipboard_osc52,
+            config.ui.code_theme,
+            config.ui.scroll_multiplier,
+            config.ui.title_format,
             config.keymap.prefix,
             config.keymap.escape_sequence,
+            config.keymap.prefix_modifier,
+            config.keymap.prefix_in_alternate_screen,
+            config.keymap.prefix_suppressed_processes,
+            config.keymap.sequence_timeout_ms,
             config.agent.default_model,
             config.agent.context_lines,
+            config.agent.context_capture,
             config.agent.timeout_ms,
             config.agent.max_tokens,
             config.agent.temperature,
+            config.agent.history_enabled,
+            config.agent.history_max_mb,
+            config.agent.history_max_age_days,
+            config.agent.history_restore,
+            config.agent.auto_calc,
+            config.agent.fast_model,
+            config.agent.show_status_line,
+            config.agent.batch_max_parallel,
             config.models.cache_dir,
+            config.models.default_model,
             config.models.models[0].name,
             config.models.models[0].path.as_ref().unwrap(),
             config.models.models[0].quantization,
             config.models.models[0].context_window,
+            config.models.models[0].preload,
+            config.models.models[0].idle_unload_secs,
+            config.models.models[0].keep_warm,
+            config.shell.program,
+            config.shell.args,
+            config.shell.login,
+            config.shell.cwd,
+            config.shell.inherit_cwd,
             config.telemetry.enabled,
             config.telemetry.endpoint,
             config.telemetry.batch_size,
@@ -582,16 +1430,204 @@ includes = ["~/.ferroterm/extra.toml"]
         Ok(())
     }
 
+    /// Effective config: defaults, then the config file and its includes,
+    /// the session's profile, `FERROTERM_*` environment variables and
+    /// runtime overrides, recording which layer supplied each setting
+    pub fn resolve(
+        path: &Path,
+        session: &SessionLayers,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<(Config, Provenance), ConfigError> {
+        let existed = path.exists();
+        let config = Self::load_config_from_path(path)?;
+        let mut provenance = Provenance::default();
+
+        let doc = if existed {
+            let content = std::fs::read_to_string(path)?;
+            content.parse::<DocumentMut>().ok()
+        } else {
+            None
+        };
+        if let Some(doc) = &doc {
+            for (key, _) in config_provenance::toml_leaves(doc.as_table(), "") {
+                if !key.starts_with("profiles.") {
+                    provenance.record(&key, ConfigSource::File(path.to_path_buf()));
+                }
+            }
+            let theme = doc.get("ui").and_then(|ui| ui.get("theme")).and_then(|t| t.as_str());
+            if doc.get("ui").is_some() && theme.is_none_or(|theme| theme == "system") {
+                provenance.record("ui.theme", ConfigSource::Environment("TERM_THEME".to_string()));
+            }
+        }
+        // An include replaces every section of the file, see load_config_from_path
+        for include in config.includes.iter().filter(|include| include.exists()) {
+            let content = std::fs::read_to_string(include)?;
+            if let Ok(include_doc) = content.parse::<DocumentMut>() {
+                for section in ["ui", "keymap", "agent", "models", "shell", "telemetry"] {
+                    provenance.reset(section);
+                }
+                for (key, _) in config_provenance::toml_leaves(include_doc.as_table(), "") {
+                    if key != "includes" && !key.starts_with("profiles.") {
+                        provenance.record(&key, ConfigSource::File(include.clone()));
+                    }
+                }
+            }
+        }
+
+        let invalid = |message: String| ConfigError::Validation(message);
+        let mut value = serde_json::to_value(&config).map_err(|e| invalid(e.to_string()))?;
+
+        if let Some(name) = &session.profile {
+            let table = doc
+                .as_ref()
+                .and_then(|doc| doc.get("profiles")?.get(name)?.as_table())
+                .ok_or_else(|| invalid(format!("Unknown profile: {}", name)))?;
+            for (key, setting) in config_provenance::toml_leaves(table, "") {
+                config_provenance::set_path(&mut value, &key, setting).map_err(invalid)?;
+                provenance.record(&key, ConfigSource::Profile(name.clone()));
+            }
+        }
+
+        for (key, current) in config_provenance::flatten(&value) {
+            if key == "includes" || config_provenance::MAP_PATHS.iter().any(|map| key.starts_with(map)) {
+                continue;
+            }
+            let var = format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase());
+            if let Some(raw) = env(&var) {
+                let setting = config_provenance::parse_raw(Some(&current), &raw);
+                config_provenance::set_path(&mut value, &key, setting).map_err(invalid)?;
+                provenance.record(&key, ConfigSource::Environment(var));
+            }
+        }
+
+        for (key, setting) in &session.overrides {
+            config_provenance::set_path(&mut value, key, setting.clone()).map_err(invalid)?;
+            provenance.record(key, ConfigSource::Runtime);
+        }
+
+        let mut resolved: Config = serde_json::from_value(value).map_err(|e| invalid(e.to_string()))?;
+        resolved.version = config.version;
+        Self::validate_config(&resolved)?;
+        Ok((resolved, provenance))
+    }
+
     pub fn get_config(&self) -> Config {
         self.config.read().unwrap().clone()
     }
 
+    /// Colors for the theme `name` ("dark", "light"), `[theme]` included
+    pub fn theme(&self, name: &str) -> Theme {
+        self.config.read().unwrap().theme(name)
+    }
+
+    /// Receives the config each time a reload, override or profile
+    /// change replaces it
+    pub fn subscribe(&self) -> watch::Receiver<Config> {
+        self.changes.subscribe()
+    }
+
     pub fn reload_config(&self) -> Result<(), ConfigError> {
-        let new_config = Self::load_config_from_path(&self.config_path)?;
-        *self.config.write().unwrap() = new_config;
+        let session = self.session.read().unwrap().clone();
+        let (config, provenance) = Self::resolve(&self.config_path, &session, &env_var)?;
+        *self.config.write().unwrap() = config.clone();
+        *self.provenance.write().unwrap() = provenance;
+        self.changes.send_replace(config);
+        Ok(())
+    }
+
+    /// Layer that supplied the effective value of a dotted setting path
+    pub fn source(&self, path: &str) -> ConfigSource {
+        self.provenance.read().unwrap().source(path)
+    }
+
+    /// Override a setting for this session. The text is read as the
+    /// setting's type; an unknown path or invalid value leaves the config
+    /// unchanged.
+    pub fn set_override(&self, path: &str, raw: &str) -> Result<(), ConfigError> {
+        let current = serde_json::to_value(self.get_config())
+            .ok()
+            .and_then(|value| config_provenance::get_path(&value, path).cloned());
+        let setting = config_provenance::parse_raw(current.as_ref(), raw);
+        self.update_session(|session| {
+            session.overrides.retain(|(key, _)| key != path);
+            session.overrides.push((path.to_string(), setting));
+        })
+    }
+
+    /// Set a value for this session, then write it to the config file.
+    /// The file keeps its comments and layout; the value is checked
+    /// before anything is written.
+    pub fn save_setting(&self, path: &str, raw: &str) -> Result<(), ConfigError> {
+        self.set_override(path, raw)?;
+        let value = serde_json::to_value(self.get_config())
+            .ok()
+            .and_then(|config| config_provenance::get_path(&config, path).cloned())
+            .unwrap_or_default();
+
+        let content = match std::fs::read_to_string(&self.config_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut doc = content.parse::<DocumentMut>().map_err(|e| ConfigError::Parse {
+            line: content[..e.span().unwrap_or_default().start].lines().count(),
+            message: e.to_string(),
+        })?;
+        config_provenance::write_toml_path(&mut doc, path, &value).map_err(ConfigError::Validation)?;
+        std::fs::write(&self.config_path, doc.to_string())?;
+
+        // The file holds the value now, so later edits to it are not masked
+        self.clear_override(path)
+    }
+
+    pub fn clear_override(&self, path: &str) -> Result<(), ConfigError> {
+        self.update_session(|session| session.overrides.retain(|(key, _)| key != path))
+    }
+
+    /// Apply `[profiles.<name>]` over the config file, or none
+    pub fn set_profile(&self, profile: Option<&str>) -> Result<(), ConfigError> {
+        self.update_session(|session| session.profile = profile.map(|name| name.to_string()))
+    }
+
+    fn update_session(&self, change: impl FnOnce(&mut SessionLayers)) -> Result<(), ConfigError> {
+        let mut session = self.session.read().unwrap().clone();
+        change(&mut session);
+        let (config, provenance) = Self::resolve(&self.config_path, &session, &env_var)?;
+        *self.session.write().unwrap() = session;
+        *self.config.write().unwrap() = config.clone();
+        *self.provenance.write().unwrap() = provenance;
+        self.changes.send_replace(config);
         Ok(())
     }
 
+    /// Effective settings with their sources; `filter` is a path prefix
+    /// such as `ui.font`
+    pub fn entries(&self, filter: Option<&str>, diff: bool) -> Vec<ConfigEntry> {
+        let effective = serde_json::to_value(self.get_config()).unwrap_or_default();
+        let defaults = serde_json::to_value(Config::default()).unwrap_or_default();
+        let provenance = self.provenance.read().unwrap();
+        config_provenance::entries(&effective, &defaults, &provenance, filter, diff)
+    }
+
+    /// `:show config [path] [--diff]`: the whole config as a tree, or a
+    /// table of the settings under `filter`
+    pub fn show_config(&self, filter: Option<&str>, diff: bool) -> String {
+        let entries = self.entries(filter, diff);
+        if entries.is_empty() {
+            return match filter {
+                Some(filter) => format!("No settings match {}", filter),
+                None => "All settings are at their defaults".to_string(),
+            };
+        }
+        match filter {
+            Some(_) => config_provenance::render_table(&entries),
+            None => config_provenance::render_tree(&entries),
+        }
+    }
+
+    /// Reload whenever the config file is written. The directory is
+    /// watched so editors that save by renaming are seen too; a file that
+    /// fails to parse or validate is logged and the current config kept.
     pub fn start_watching(&mut self) -> Result<(), ConfigError> {
         use notify::{Event, EventKind, RecursiveMode, Watcher};
         use std::sync::mpsc;
@@ -601,35 +1637,54 @@ includes = ["~/.ferroterm/extra.toml"]
         let (tx, rx) = mpsc::channel();
         let mut watcher = notify::recommended_watcher(tx)?;
 
-        // Watch the config file directly
-        watcher.watch(&self.config_path, RecursiveMode::NonRecursive)?;
+        let watch_dir = match self.config_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        watcher.watch(&watch_dir, RecursiveMode::NonRecursive)?;
 
         let config_path = self.config_path.clone();
+        let file_name = config_path.file_name().map(|name| name.to_os_string());
         let config_arc = Arc::clone(&self.config);
+        let provenance_arc = Arc::clone(&self.provenance);
+        let session_arc = Arc::clone(&self.session);
+        let changes = Arc::clone(&self.changes);
 
         thread::spawn(move || {
-            let mut last_reload = Instant::now();
+            let mut last_reload: Option<Instant> = None;
 
             while let Ok(event) = rx.recv() {
-                if let Ok(Event {
-                    kind: EventKind::Modify(_),
-                    paths,
-                    ..
-                }) = event
+                let Ok(Event { kind, paths, .. }) = event else {
+                    continue;
+                };
+                if !matches!(kind, EventKind::Modify(_) | EventKind::Create(_))
+                    || !paths.iter().any(|p| p.file_name() == file_name.as_deref())
                 {
-                    if paths.iter().any(|p| p == &config_path) {
-                        let now = Instant::now();
-                        if now.duration_since(last_reload) > Duration::from_millis(100) {
-                            match Self::load_config_from_path(&config_path) {
-                                Ok(new_config) => {
-                                    *config_arc.write().unwrap() = new_config;
-                                    last_reload = now;
-                                }
-                                Err(e) => {
-                                    eprintln!("Failed to reload config: {}", e);
-                                }
-                            }
-                        }
+                    continue;
+                }
+                let now = Instant::now();
+                if last_reload.is_some_and(|last| now.duration_since(last) < Duration::from_millis(100)) {
+                    continue;
+                }
+                // Saves arrive as several events; let the last one land
+                thread::sleep(Duration::from_millis(50));
+                while rx.try_recv().is_ok() {}
+
+                let session = session_arc.read().unwrap().clone();
+                match Self::resolve(&config_path, &session, &env_var) {
+                    Ok((new_config, provenance)) => {
+                        *config_arc.write().unwrap() = new_config.clone();
+                        *provenance_arc.write().unwrap() = provenance;
+                        changes.send_replace(new_config);
+                        last_reload = Some(Instant::now());
+                        tracing::info!("Reloaded config from {}", config_path.display());
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            "Keeping the current config; {} is invalid: {}",
+                            config_path.display(),
+                            e
+                        );
                     }
                 }
             }
@@ -640,6 +1695,10 @@ includes = ["~/.ferroterm/extra.toml"]
     }
 }
 
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -724,6 +1783,249 @@ temperature = 0.5
         assert_eq!(config.agent.default_model, "mistral-7b-instruct"); // Default value
     }
 
+    #[test]
+    fn test_shell_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("ferroterm.toml");
+        fs::write(
+            &config_path,
+            r#"
+[shell]
+program = "zsh"
+args = ["-o", "vi"]
+login = true
+cwd = "~/src"
+inherit_cwd = false
+
+[shell.env]
+EDITOR = "vim"
+"#,
+        )
+        .unwrap();
+
+        let config = ConfigManager::load_config_from_path(&config_path).unwrap();
+        assert_eq!(config.shell.program, "zsh");
+        assert_eq!(config.shell.args, ["-o", "vi"]);
+        assert!(config.shell.login);
+        assert!(!config.shell.inherit_cwd);
+        assert_eq!(config.shell.env.get("EDITOR").map(String::as_str), Some("vim"));
+        assert_eq!(config.shell.start_dir(), dirs::home_dir().map(|home| home.join("src")));
+        assert_eq!(ShellConfig::default().start_dir(), None);
+    }
+
+    #[test]
+    fn test_model_warm_pool_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("ferroterm.toml");
+        fs::write(
+            &config_path,
+            r#"
+[[models.list]]
+name = "coder"
+path = "~/models/coder.gguf"
+idle_unload_secs = 600
+keep_warm = true
+
+[[models.list]]
+name = "chat"
+path = "~/models/chat.gguf"
+"#,
+        )
+        .unwrap();
+
+        let config = ConfigManager::load_config_from_path(&config_path).unwrap();
+        let coder = &config.models.models[0];
+        assert_eq!(coder.idle_unload_secs, 600);
+        assert!(coder.keep_warm);
+        let chat = &config.models.models[1];
+        assert_eq!(chat.idle_unload_secs, 0);
+        assert!(!chat.keep_warm);
+    }
+
+    #[test]
+    fn test_commands_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("ferroterm.toml");
+        fs::write(
+            &config_path,
+            r#"
+[commands]
+gs = { type = "shell", cmd = "git status" }
+rev = { type = "prompt", template = "Review this output:\n{last_output}" }
+
+[commands.up]
+type = "shell"
+cmd = "uptime"
+detached = true
+description = "Load averages"
+"#,
+        )
+        .unwrap();
+
+        let config = ConfigManager::load_config_from_path(&config_path).unwrap();
+        assert_eq!(
+            config.commands["gs"],
+            UserCommand::Shell { cmd: "git status".to_string(), detached: false, description: String::new() }
+        );
+        assert_eq!(config.commands["gs"].description(), "Run `git status`");
+        assert_eq!(config.commands["rev"].description(), "Ask: Review this output:");
+        assert_eq!(config.commands["up"].description(), "Load averages");
+        assert!(matches!(&config.commands["up"], UserCommand::Shell { detached: true, .. }));
+
+        // Survives the round trip through the resolved config
+        let (resolved, _) = ConfigManager::resolve(&config_path, &SessionLayers::default(), &|_| None).unwrap();
+        assert_eq!(resolved.commands, config.commands);
+
+        for bad in [
+            "[commands]\ngs = { cmd = \"git status\" }",
+            "[commands]\nrev = { type = \"prompt\" }",
+            "[commands]\n\"g s\" = { type = \"shell\", cmd = \"git status\" }",
+        ] {
+            fs::write(&config_path, bad).unwrap();
+            assert!(matches!(
+                ConfigManager::load_config_from_path(&config_path),
+                Err(ConfigError::Validation(message)) if message.starts_with("commands.")
+            ));
+        }
+    }
+
+    #[test]
+    fn test_context_bindings_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("ferroterm.toml");
+        fs::write(
+            &config_path,
+            r#"
+[keymap]
+context_bindings = [{ key = "ctrl+a", action = "line_start", context = "emacs", priority = 80 }]
+
+[keymap.bindings]
+"ctrl+t" = "new_tab"
+"#,
+        )
+        .unwrap();
+
+        let config = ConfigManager::load_config_from_path(&config_path).unwrap();
+        assert_eq!(config.keymap.bindings["ctrl+t"], "new_tab");
+        assert_eq!(
+            config.keymap.context_bindings,
+            vec![ContextBinding {
+                key: "ctrl+a".to_string(),
+                action: "line_start".to_string(),
+                context: "emacs".to_string(),
+                priority: 80,
+            }]
+        );
+
+        fs::write(&config_path, "[[keymap.context_bindings]]\nkey = \"g g\"\naction = \"scroll_to_top\"\n").unwrap();
+        let config = ConfigManager::load_config_from_path(&config_path).unwrap();
+        assert_eq!(config.keymap.context_bindings[0].context, "global");
+        assert_eq!(config.keymap.context_bindings[0].priority, 100);
+        let (resolved, _) = ConfigManager::resolve(&config_path, &SessionLayers::default(), &|_| None).unwrap();
+        assert_eq!(resolved.keymap.context_bindings, config.keymap.context_bindings);
+
+        for bad in [
+            "[[keymap.context_bindings]]\nkey = \"ctrl+a\"\naction = \"line_start\"\ncontext = \"insert\"",
+            "[[keymap.context_bindings]]\nkey = \"ctrl+a\"\naction = \"line_start\"\npriority = 300",
+            "[[keymap.context_bindings]]\nkey = \"ctrl+a\"",
+            "[keymap]\ncontext_bindings = [\"ctrl+a\"]",
+        ] {
+            fs::write(&config_path, bad).unwrap();
+            assert!(matches!(
+                ConfigManager::load_config_from_path(&config_path),
+                Err(ConfigError::Validation(message)) if message.starts_with("keymap.context_bindings")
+            ));
+        }
+    }
+
+    #[test]
+    fn test_config_sources_per_layer() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("ferroterm.toml");
+        fs::write(
+            &config_path,
+            r#"
+[ui]
+font_size = 16
+theme = "light"
+padding = 8
+
+[keymap.bindings]
+"ctrl+t" = "new_tab"
+
+[profiles.work.ui]
+padding = 12
+font_family = "Iosevka"
+
+[profiles.work.agent]
+temperature = 0.2
+"#,
+        )
+        .unwrap();
+
+        let session = SessionLayers {
+            profile: Some("work".to_string()),
+            overrides: vec![("ui.font_size".to_string(), serde_json::json!(18))],
+        };
+        let env = |name: &str| match name {
+            "FERROTERM_UI_PADDING" => Some("20".to_string()),
+            "FERROTERM_AGENT_MAX_TOKENS" => Some("512".to_string()),
+            _ => None,
+        };
+        let (config, provenance) = ConfigManager::resolve(&config_path, &session, &env).unwrap();
+
+        let file = ConfigSource::File(config_path.clone());
+        assert_eq!(config.ui.font_size, 18);
+        assert_eq!(provenance.source("ui.font_size"), ConfigSource::Runtime);
+        assert_eq!(config.ui.padding, 20);
+        assert_eq!(
+            provenance.source("ui.padding"),
+            ConfigSource::Environment("FERROTERM_UI_PADDING".to_string())
+        );
+        assert_eq!(config.ui.font_family, "Iosevka");
+        assert_eq!(provenance.source("ui.font_family"), ConfigSource::Profile("work".to_string()));
+        assert_eq!(provenance.source("agent.temperature"), ConfigSource::Profile("work".to_string()));
+        assert_eq!(config.agent.max_tokens, 512);
+        assert_eq!(provenance.source("ui.theme"), file);
+        assert_eq!(provenance.source("keymap.bindings.ctrl+t"), file);
+        assert_eq!(provenance.source("ui.cursor_style"), ConfigSource::Default);
+
+        // Typos and wrong types are rejected rather than ignored
+        let typo = SessionLayers {
+            overrides: vec![("ui.fnt_size".to_string(), serde_json::json!(18))],
+            ..SessionLayers::default()
+        };
+        assert!(ConfigManager::resolve(&config_path, &typo, &env).is_err());
+        let missing = SessionLayers { profile: Some("home".to_string()), ..SessionLayers::default() };
+        assert!(ConfigManager::resolve(&config_path, &missing, &env).is_err());
+
+        let manager = ConfigManager::from_path(config_path.clone()).unwrap();
+        manager.set_profile(Some("work")).unwrap();
+        manager.set_override("ui.cursor_style", "beam").unwrap();
+        assert!(manager.set_override("ui.font_size", "huge").is_err());
+        assert_eq!(manager.source("ui.cursor_style"), ConfigSource::Runtime);
+        assert_eq!(manager.get_config().ui.font_size, 16);
+
+        // --diff keeps only values that differ from the built-in defaults
+        let changed: Vec<String> = manager.entries(None, true).into_iter().map(|e| e.path).collect();
+        assert!(changed.contains(&"ui.cursor_style".to_string()));
+        assert!(changed.contains(&"ui.padding".to_string()));
+        assert!(!changed.contains(&"ui.window_width".to_string()));
+        assert!(manager.entries(None, false).len() > changed.len());
+
+        let table = manager.show_config(Some("ui.font"), false);
+        assert_eq!(table.lines().count(), 3);
+        assert!(table.contains("ui.font_fallback  []         # default"));
+        assert!(table.contains("ui.font_family    \"Iosevka\"  # profile work"));
+        assert!(table.contains(&format!("ui.font_size      16         # file {}", config_path.display())));
+        let tree = manager.show_config(None, true);
+        assert!(tree.starts_with("[agent]\n  temperature = 0.2"));
+        assert!(tree.contains("[ui]\n  cursor_style = \"beam\"  # runtime override"));
+
+        manager.clear_override("ui.cursor_style").unwrap();
+        assert_eq!(manager.source("ui.cursor_style"), ConfigSource::Default);
+    }
+
     #[test]
     fn test_config_reload() {
         let temp_dir = TempDir::new().unwrap();
@@ -739,8 +2041,11 @@ font_size = 12
             config: Arc::new(RwLock::new(
                 ConfigManager::load_config_from_path(&config_path).unwrap(),
             )),
+            provenance: Arc::default(),
+            session: Arc::default(),
             config_path: config_path.clone(),
             watcher: None,
+            changes: Arc::new(watch::Sender::new(Config::default())),
         };
 
         assert_eq!(manager.get_config().ui.font_size, 12);
@@ -770,8 +2075,11 @@ font_size = 12
             config: Arc::new(RwLock::new(
                 ConfigManager::load_config_from_path(&config_path).unwrap(),
             )),
+            provenance: Arc::default(),
+            session: Arc::default(),
             config_path: config_path.clone(),
             watcher: None,
+            changes: Arc::new(watch::Sender::new(Config::default())),
         };
 
         // Test manual reload instead of automatic file watching
@@ -792,6 +2100,49 @@ font_size = 18
         assert!(manager.start_watching().is_ok());
     }
 
+    #[test]
+    fn test_reload_notifies_subscribers() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("ferroterm.toml");
+        fs::write(&config_path, "[ui]\nfont_size = 12\n").unwrap();
+
+        let manager = ConfigManager::from_path(config_path.clone()).unwrap();
+        let mut changes = manager.subscribe();
+
+        fs::write(&config_path, "[ui]\nfont_size = 16\n").unwrap();
+        manager.reload_config().unwrap();
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(changes.borrow_and_update().ui.font_size, 16);
+
+        // An invalid edit is rejected and the last good config stays
+        fs::write(&config_path, "[ui]\nfont_size = 200\n").unwrap();
+        assert!(manager.reload_config().is_err());
+        assert_eq!(manager.get_config().ui.font_size, 16);
+        assert!(!changes.has_changed().unwrap());
+    }
+
+    #[test]
+    fn test_save_setting_keeps_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("ferroterm.toml");
+        fs::write(&config_path, "# my settings\n[ui]\nfont_size = 12\n").unwrap();
+        let manager = ConfigManager::from_path(config_path.clone()).unwrap();
+
+        manager.save_setting("ui.font_size", "16").unwrap();
+        manager.save_setting("keymap.prefix", ",").unwrap();
+        assert_eq!(manager.get_config().ui.font_size, 16);
+        assert_eq!(manager.source("ui.font_size"), ConfigSource::File(config_path.clone()));
+        let saved = fs::read_to_string(&config_path).unwrap();
+        assert!(saved.starts_with("# my settings\n"));
+        assert!(saved.contains("font_size = 16"));
+        assert!(saved.contains("prefix = \",\""));
+
+        assert!(manager.save_setting("ui.font_sise", "16").is_err());
+        assert!(manager.set_override("ui.font_size", "big").is_err());
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), saved);
+        assert_eq!(manager.get_config().ui.font_size, 16);
+    }
+
     #[test]
     fn test_performance_load_time() {
         let temp_dir = TempDir::new().unwrap();
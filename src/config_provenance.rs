@@ -0,0 +1,410 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Layer that supplied a setting's effective value, lowest precedence first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    /// The config file or one of its includes
+    File(PathBuf),
+    Profile(String),
+    /// Named environment variable
+    Environment(String),
+    /// Set for this session with `:config <key> <value>`
+    Runtime,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => f.write_str("default"),
+            ConfigSource::File(path) => write!(f, "file {}", path.display()),
+            ConfigSource::Profile(name) => write!(f, "profile {}", name),
+            ConfigSource::Environment(var) => write!(f, "env {}", var),
+            ConfigSource::Runtime => f.write_str("runtime override"),
+        }
+    }
+}
+
+/// Settings whose children are free-form keys rather than fixed fields
+pub const MAP_PATHS: &[&str] = &["keymap.bindings", "shell.env"];
+
+/// Leaf names whose values are never displayed
+const SECRET_KEYS: &[&str] = &["api_key"];
+
+/// Source of every leaf setting that did not come from the defaults, keyed
+/// by dotted path such as `ui.font_size`
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+    sources: BTreeMap<String, ConfigSource>,
+}
+
+impl Provenance {
+    pub fn source(&self, path: &str) -> ConfigSource {
+        self.sources
+            .get(path)
+            .cloned()
+            .unwrap_or(ConfigSource::Default)
+    }
+
+    pub fn record(&mut self, path: &str, source: ConfigSource) {
+        if source == ConfigSource::Default {
+            self.sources.remove(path);
+        } else {
+            self.sources.insert(path.to_string(), source);
+        }
+    }
+
+    /// Forget every source under `section`, e.g. when an include replaces it
+    pub fn reset(&mut self, section: &str) {
+        self.sources.retain(|path, _| !in_section(path, section));
+    }
+}
+
+fn in_section(path: &str, section: &str) -> bool {
+    path.strip_prefix(section)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+/// Path segments, keeping map keys such as `ctrl+.` whole
+fn segments(path: &str) -> Vec<&str> {
+    for map in MAP_PATHS {
+        if let Some(key) = path
+            .strip_prefix(map)
+            .and_then(|rest| rest.strip_prefix('.'))
+        {
+            let mut parts: Vec<&str> = map.split('.').collect();
+            parts.push(key);
+            return parts;
+        }
+    }
+    path.split('.').collect()
+}
+
+/// Leaf values by dotted path. Arrays are leaves; so are empty tables.
+pub fn flatten(value: &Value) -> BTreeMap<String, Value> {
+    fn walk(value: &Value, path: &str, leaves: &mut BTreeMap<String, Value>) {
+        match value {
+            Value::Object(map) if !map.is_empty() => {
+                for (key, child) in map {
+                    let child_path = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    walk(child, &child_path, leaves);
+                }
+            }
+            _ => {
+                leaves.insert(path.to_string(), value.clone());
+            }
+        }
+    }
+    let mut leaves = BTreeMap::new();
+    walk(value, "", &mut leaves);
+    leaves
+}
+
+pub fn get_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    segments(path)
+        .into_iter()
+        .try_fold(value, |value, key| value.get(key))
+}
+
+/// Replace the leaf at `path`. Fixed fields must already exist so a typo
+/// fails instead of being ignored; map entries may be added.
+pub fn set_path(value: &mut Value, path: &str, new: Value) -> Result<(), String> {
+    let parts = segments(path);
+    let (leaf, parents) = parts
+        .split_last()
+        .ok_or_else(|| format!("Unknown setting: {}", path))?;
+    let mut target = value;
+    for key in parents {
+        target = target
+            .get_mut(*key)
+            .ok_or_else(|| format!("Unknown setting: {}", path))?;
+    }
+    let parent = parents.join(".");
+    let map = target
+        .as_object_mut()
+        .ok_or_else(|| format!("Unknown setting: {}", path))?;
+    match map.get_mut(*leaf) {
+        Some(Value::Object(_)) => Err(format!("{} is a section, not a setting", path)),
+        Some(slot) => {
+            *slot = new;
+            Ok(())
+        }
+        None if MAP_PATHS.contains(&parent.as_str()) => {
+            map.insert(leaf.to_string(), new);
+            Ok(())
+        }
+        None => Err(format!("Unknown setting: {}", path)),
+    }
+}
+
+/// Interpret text from the environment or `:config` as the type of the
+/// setting it replaces: strings stay verbatim, anything else is read as a
+/// TOML value (`16`, `true`, `[80, 100]`)
+pub fn parse_raw(current: Option<&Value>, raw: &str) -> Value {
+    match current {
+        Some(Value::String(_)) | Some(Value::Null) => Value::String(raw.to_string()),
+        _ => raw
+            .parse::<toml_edit::Value>()
+            .map(|value| toml_to_json(&value))
+            .unwrap_or_else(|_| Value::String(raw.to_string())),
+    }
+}
+
+pub fn json_to_toml(value: &Value) -> Option<toml_edit::Value> {
+    match value {
+        Value::Null => None,
+        Value::Bool(b) => Some((*b).into()),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Some(i.into()),
+            None => n.as_f64().map(Into::into),
+        },
+        Value::String(s) => Some(s.as_str().into()),
+        Value::Array(items) => Some(toml_edit::Value::Array(
+            items.iter().filter_map(json_to_toml).collect(),
+        )),
+        Value::Object(map) => Some(toml_edit::Value::InlineTable(
+            map.iter()
+                .filter_map(|(key, value)| json_to_toml(value).map(|value| (key.as_str(), value)))
+                .collect(),
+        )),
+    }
+}
+
+/// Write `value` at a dotted path in a TOML document, creating tables on
+/// the way and keeping the rest of the file as it was
+pub fn write_toml_path(
+    doc: &mut toml_edit::DocumentMut,
+    path: &str,
+    value: &Value,
+) -> Result<(), String> {
+    let parts = segments(path);
+    let (leaf, parents) = parts
+        .split_last()
+        .ok_or_else(|| format!("Unknown setting: {}", path))?;
+    let mut table = doc.as_table_mut();
+    for key in parents {
+        let entry = table
+            .entry(key)
+            .or_insert_with(|| toml_edit::Item::Table(toml_edit::Table::new()));
+        table = entry
+            .as_table_mut()
+            .ok_or_else(|| format!("{} is not a table in the config file", key))?;
+    }
+    match json_to_toml(value) {
+        Some(value) => table.insert(leaf, toml_edit::value(value)),
+        None => table.remove(leaf),
+    };
+    Ok(())
+}
+
+pub fn toml_to_json(value: &toml_edit::Value) -> Value {
+    use toml_edit::Value as Toml;
+    match value {
+        Toml::String(s) => Value::String(s.value().clone()),
+        Toml::Integer(i) => Value::from(*i.value()),
+        Toml::Float(f) => Value::from(*f.value()),
+        Toml::Boolean(b) => Value::Bool(*b.value()),
+        Toml::Datetime(d) => Value::String(d.value().to_string()),
+        Toml::Array(array) => Value::Array(array.iter().map(toml_to_json).collect()),
+        Toml::InlineTable(table) => Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.to_string(), toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Leaf paths and values set in a TOML table, below `prefix`
+pub fn toml_leaves(table: &toml_edit::Table, prefix: &str) -> Vec<(String, Value)> {
+    fn walk(item: &toml_edit::Item, path: String, leaves: &mut Vec<(String, Value)>) {
+        match item {
+            toml_edit::Item::Table(table) => {
+                for (key, child) in table.iter() {
+                    walk(child, join(&path, key), leaves);
+                }
+            }
+            toml_edit::Item::Value(toml_edit::Value::InlineTable(table))
+                if MAP_PATHS.contains(&path.as_str()) =>
+            {
+                for (key, value) in table.iter() {
+                    leaves.push((join(&path, key), toml_to_json(value)));
+                }
+            }
+            toml_edit::Item::Value(value) => leaves.push((path, toml_to_json(value))),
+            toml_edit::Item::ArrayOfTables(_) | toml_edit::Item::None => {}
+        }
+    }
+    fn join(path: &str, key: &str) -> String {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    }
+
+    let mut leaves = Vec::new();
+    for (key, item) in table.iter() {
+        walk(item, join(prefix, key), &mut leaves);
+    }
+    leaves
+}
+
+/// One row of `:show config`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigEntry {
+    pub path: String,
+    pub value: Value,
+    /// `None` for map entries that have no built-in default
+    pub default: Option<Value>,
+    pub source: ConfigSource,
+}
+
+impl ConfigEntry {
+    pub fn is_default(&self) -> bool {
+        self.default.as_ref() == Some(&self.value)
+    }
+
+    /// Rendered value with secrets masked
+    pub fn display_value(&self) -> String {
+        let mut value = self.value.clone();
+        let leaf = self.path.rsplit('.').next().unwrap_or(&self.path);
+        if SECRET_KEYS.contains(&leaf) && !value.is_null() {
+            value = Value::String("***".to_string());
+        }
+        mask_secrets(&mut value);
+        value.to_string()
+    }
+}
+
+fn mask_secrets(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                if SECRET_KEYS.contains(&key.as_str()) && !child.is_null() {
+                    *child = Value::String("***".to_string());
+                } else {
+                    mask_secrets(child);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(mask_secrets),
+        _ => {}
+    }
+}
+
+/// Effective settings whose path starts with `filter`, optionally only
+/// those that differ from the built-in defaults
+pub fn entries(
+    effective: &Value,
+    defaults: &Value,
+    provenance: &Provenance,
+    filter: Option<&str>,
+    diff: bool,
+) -> Vec<ConfigEntry> {
+    let defaults = flatten(defaults);
+    flatten(effective)
+        .into_iter()
+        .filter(|(path, _)| filter.is_none_or(|filter| path.starts_with(filter)))
+        .map(|(path, value)| ConfigEntry {
+            default: defaults.get(&path).cloned(),
+            source: provenance.source(&path),
+            path,
+            value,
+        })
+        .filter(|entry| !diff || !entry.is_default())
+        .collect()
+}
+
+/// Aligned `path  value  # source` rows
+pub fn render_table(entries: &[ConfigEntry]) -> String {
+    let rows: Vec<(&str, String)> = entries
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry.display_value()))
+        .collect();
+    let path_width = rows.iter().map(|(path, _)| path.len()).max().unwrap_or(0);
+    let value_width = rows.iter().map(|(_, value)| value.len()).max().unwrap_or(0);
+    rows.iter()
+        .zip(entries)
+        .map(|((path, value), entry)| {
+            format!(
+                "{:path_width$}  {:value_width$}  # {}",
+                path, value, entry.source
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Sections as headers with their settings indented below, one section per
+/// foldable block
+pub fn render_tree(entries: &[ConfigEntry]) -> String {
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    for entry in entries {
+        let parts = segments(&entry.path);
+        let (leaf, sections) = parts.split_last().expect("paths are never empty");
+        let shared = open
+            .iter()
+            .zip(sections)
+            .take_while(|(a, b)| a == b)
+            .count();
+        open.truncate(shared);
+        for section in &sections[shared..] {
+            lines.push(format!("{}[{}]", "  ".repeat(open.len()), section));
+            open.push(section);
+        }
+        lines.push(format!(
+            "{}{} = {}  # {}",
+            "  ".repeat(open.len()),
+            leaf,
+            entry.display_value(),
+            entry.source
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_paths_and_rendering() {
+        let mut value = json!({"ui": {"font_size": 14}, "keymap": {"bindings": {}}});
+        assert!(set_path(&mut value, "ui.font_size", json!(16)).is_ok());
+        assert!(set_path(&mut value, "ui.fnt_size", json!(16)).is_err());
+        assert!(set_path(&mut value, "ui", json!(16)).is_err());
+        assert!(set_path(&mut value, "keymap.bindings.ctrl+.", json!("copy")).is_ok());
+        assert_eq!(
+            get_path(&value, "keymap.bindings.ctrl+."),
+            Some(&json!("copy"))
+        );
+        assert_eq!(parse_raw(Some(&json!(1)), "[80, 100]"), json!([80, 100]));
+        assert_eq!(parse_raw(Some(&json!("a")), "true"), json!("true"));
+
+        let mut provenance = Provenance::default();
+        provenance.record("ui.font_size", ConfigSource::Runtime);
+        let defaults = json!({"ui": {"font_size": 14}, "keymap": {"bindings": {}}});
+        let rows = entries(&value, &defaults, &provenance, None, true);
+        assert_eq!(
+            render_tree(&rows),
+            "[keymap]\n  [bindings]\n    ctrl+. = \"copy\"  # default\n[ui]\n  font_size = 16  # runtime override"
+        );
+        let masked = ConfigEntry {
+            path: "models.models".to_string(),
+            value: json!([{"name": "x", "api_key": "sk-secret"}]),
+            default: None,
+            source: ConfigSource::Default,
+        };
+        assert!(!masked.display_value().contains("sk-secret"));
+    }
+}
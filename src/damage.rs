@@ -0,0 +1,293 @@
+//! Damage tracking: which cells changed since the last frame, so a frame
+//! rebuilds only those cells' vertices and an unchanged frame isn't drawn.
+
+use std::ops::Range;
+
+/// More rects than this collapse into their bounding box
+const MAX_RECTS: usize = 32;
+
+/// Cells to rebuild, in grid coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DamageRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl DamageRect {
+    pub fn cell(x: u32, y: u32) -> Self {
+        Self { x, y, width: 1, height: 1 }
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Overlapping or sharing an edge, so the union wastes little
+    fn touches(&self, other: &DamageRect) -> bool {
+        self.x <= other.x + other.width
+            && other.x <= self.x + self.width
+            && self.y <= other.y + other.height
+            && other.y <= self.y + self.height
+    }
+
+    fn union(&self, other: &DamageRect) -> DamageRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        DamageRect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    fn clamp(&self, width: u32, height: u32) -> Option<DamageRect> {
+        let right = (self.x + self.width).min(width);
+        let bottom = (self.y + self.height).min(height);
+        (self.x < right && self.y < bottom).then(|| DamageRect {
+            x: self.x,
+            y: self.y,
+            width: right - self.x,
+            height: bottom - self.y,
+        })
+    }
+}
+
+/// Damage gathered between two frames
+#[derive(Debug, Default)]
+pub struct DamageTracker {
+    rects: Vec<DamageRect>,
+    /// Everything is stale, e.g. after a resize or a font change
+    full: bool,
+    /// Cursor cell and blink phase of the last frame drawn
+    cursor: Option<(u32, u32)>,
+    cursor_on: bool,
+}
+
+impl DamageTracker {
+    /// Starts fully damaged, since nothing has been drawn yet
+    pub fn new() -> Self {
+        Self { full: true, ..Self::default() }
+    }
+
+    /// Add a rect, merged into one it touches
+    pub fn mark(&mut self, rect: DamageRect) {
+        if self.full || rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        match self.rects.iter_mut().find(|existing| existing.touches(&rect)) {
+            Some(existing) => *existing = existing.union(&rect),
+            None => self.rects.push(rect),
+        }
+        if self.rects.len() > MAX_RECTS {
+            let bounds = self.rects.iter().skip(1).fold(self.rects[0], |acc, r| acc.union(r));
+            self.rects = vec![bounds];
+        }
+    }
+
+    pub fn mark_all(&mut self) {
+        self.full = true;
+        self.rects.clear();
+    }
+
+    /// Turn the cells' dirty bits into rects, one per run of dirty cells in
+    /// a row, and clear them
+    pub fn collect<C>(&mut self, cells: &mut [C], width: u32, dirty: impl Fn(&mut C) -> &mut bool) {
+        if width == 0 {
+            return;
+        }
+        for (y, row) in cells.chunks_mut(width as usize).enumerate() {
+            let mut run: Option<u32> = None;
+            for (x, cell) in row.iter_mut().enumerate() {
+                let bit = dirty(cell);
+                match (std::mem::take(bit), run) {
+                    (true, None) => run = Some(x as u32),
+                    (false, Some(start)) => {
+                        self.mark(DamageRect { x: start, y: y as u32, width: x as u32 - start, height: 1 });
+                        run = None;
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(start) = run {
+                self.mark(DamageRect { x: start, y: y as u32, width: row.len() as u32 - start, height: 1 });
+            }
+        }
+    }
+
+    /// Note where the cursor is drawn this frame. A move damages the cell
+    /// it left and the one it entered; returns whether the cursor looks
+    /// different from the last frame, blinking included.
+    pub fn cursor(&mut self, position: Option<(u32, u32)>, on: bool) -> bool {
+        let moved = position != self.cursor;
+        if moved {
+            for (x, y) in [self.cursor, position].into_iter().flatten() {
+                self.mark(DamageRect::cell(x, y));
+            }
+        }
+        let changed = moved || (position.is_some() && on != self.cursor_on);
+        self.cursor = position;
+        self.cursor_on = on;
+        changed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.full && self.rects.is_empty()
+    }
+
+    /// The rects to rebuild on a `width` x `height` grid, leaving nothing
+    /// damaged
+    pub fn take(&mut self, width: u32, height: u32) -> Vec<DamageRect> {
+        let rects = std::mem::take(&mut self.rects);
+        if std::mem::take(&mut self.full) {
+            return DamageRect { x: 0, y: 0, width, height }.clamp(width, height).into_iter().collect();
+        }
+        rects.iter().filter_map(|rect| rect.clamp(width, height)).collect()
+    }
+}
+
+/// Vertices kept per cell between frames, in a fixed slot per cell, so a
+/// frame rewrites only the damaged cells
+#[derive(Debug)]
+pub struct CellVertices<V> {
+    width: u32,
+    height: u32,
+    per_cell: usize,
+    vertices: Vec<V>,
+}
+
+impl<V: Copy + bytemuck::Zeroable> CellVertices<V> {
+    pub fn new(per_cell: usize) -> Self {
+        Self { width: 0, height: 0, per_cell, vertices: Vec::new() }
+    }
+
+    /// Match the grid size; true when the slots were reset and every cell
+    /// needs rebuilding
+    pub fn resize(&mut self, width: u32, height: u32) -> bool {
+        if (width, height) == (self.width, self.height) {
+            return false;
+        }
+        self.width = width;
+        self.height = height;
+        self.vertices = vec![V::zeroed(); width as usize * height as usize * self.per_cell];
+        true
+    }
+
+    /// Rewrite the slots of the cells in `rects` with `build`, which fills
+    /// one cell's slot. Returns the cells rebuilt and the vertex ranges to
+    /// upload.
+    pub fn rebuild(
+        &mut self,
+        rects: &[DamageRect],
+        mut build: impl FnMut(u32, u32, &mut [V]),
+    ) -> (usize, Vec<Range<usize>>) {
+        let mut cells = 0;
+        let mut ranges = Vec::new();
+        for rect in rects.iter().filter_map(|rect| rect.clamp(self.width, self.height)) {
+            for y in rect.y..rect.y + rect.height {
+                let first = (y * self.width + rect.x) as usize * self.per_cell;
+                for x in rect.x..rect.x + rect.width {
+                    let start = (y * self.width + x) as usize * self.per_cell;
+                    build(x, y, &mut self.vertices[start..start + self.per_cell]);
+                }
+                ranges.push(first..first + rect.width as usize * self.per_cell);
+            }
+            cells += rect.cell_count();
+        }
+        (cells, ranges)
+    }
+
+    pub fn vertices(&self) -> &[V] {
+        &self.vertices
+    }
+}
+
+/// Indices for `quads` consecutive four-vertex quads
+pub fn quad_indices(quads: usize) -> Vec<u32> {
+    (0..quads as u32)
+        .flat_map(|quad| {
+            let base = quad * 4;
+            [base, base + 1, base + 2, base + 2, base + 1, base + 3]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::TerminalState;
+
+    /// One frame of the damage path: dirty bits and cursor in, cells
+    /// rebuilt out, or `None` when the frame would be skipped
+    fn frame(terminal: &mut TerminalState, damage: &mut DamageTracker, slots: &mut CellVertices<u32>) -> Option<usize> {
+        if slots.resize(terminal.width, terminal.height) {
+            damage.mark_all();
+        }
+        damage.collect(&mut terminal.cells, terminal.width, |cell| &mut cell.dirty);
+        let cursor_changed = damage.cursor(Some((terminal.cursor_x, terminal.cursor_y)), true);
+        if damage.is_empty() && !cursor_changed {
+            return None;
+        }
+        let rects = damage.take(terminal.width, terminal.height);
+        Some(slots.rebuild(&rects, |_, _, slot| slot.fill(1)).0)
+    }
+
+    #[test]
+    fn test_typing_one_character_redraws_few_cells() {
+        let mut terminal = TerminalState::new(80, 24);
+        let mut damage = DamageTracker::new();
+        let mut slots = CellVertices::new(8);
+
+        assert_eq!(frame(&mut terminal, &mut damage, &mut slots), Some(80 * 24));
+        assert_eq!(frame(&mut terminal, &mut damage, &mut slots), None);
+
+        terminal.feed_bytes(b"a");
+        let redrawn = frame(&mut terminal, &mut damage, &mut slots).unwrap();
+        assert!(redrawn <= 2, "{redrawn} cells redrawn for one character");
+        assert!(terminal.cells.iter().all(|cell| !cell.dirty));
+        assert_eq!(frame(&mut terminal, &mut damage, &mut slots), None);
+    }
+
+    #[test]
+    fn test_rects_merge_and_collapse() {
+        let mut damage = DamageTracker::new();
+        damage.take(10, 10);
+        damage.mark(DamageRect::cell(1, 1));
+        damage.mark(DamageRect::cell(2, 1));
+        damage.mark(DamageRect::cell(8, 8));
+        assert_eq!(
+            damage.take(10, 10),
+            vec![DamageRect { x: 1, y: 1, width: 2, height: 1 }, DamageRect::cell(8, 8)]
+        );
+
+        for i in 0..(MAX_RECTS as u32 + 1) {
+            damage.mark(DamageRect::cell(i * 2, i * 2));
+        }
+        assert_eq!(damage.take(100, 100), vec![DamageRect { x: 0, y: 0, width: 65, height: 65 }]);
+    }
+
+    #[test]
+    fn test_blink_needs_a_frame_without_damage() {
+        let mut damage = DamageTracker::new();
+        damage.cursor(Some((0, 0)), true);
+        damage.take(10, 10);
+        assert!(!damage.cursor(Some((0, 0)), true));
+        assert!(damage.cursor(Some((0, 0)), false));
+        assert!(damage.is_empty());
+    }
+
+    #[test]
+    fn test_rebuild_reports_upload_ranges() {
+        let mut slots = CellVertices::<u32>::new(2);
+        slots.resize(4, 3);
+        let (cells, ranges) = slots.rebuild(&[DamageRect { x: 1, y: 1, width: 2, height: 2 }], |x, y, slot| {
+            slot.fill(y * 4 + x)
+        });
+        assert_eq!(cells, 4);
+        assert_eq!(ranges, vec![10..14, 18..22]);
+        assert_eq!(&slots.vertices()[10..14], &[5, 5, 6, 6]);
+        assert_eq!(quad_indices(2), vec![0, 1, 2, 2, 1, 3, 4, 5, 6, 6, 5, 7]);
+    }
+}
@@ -0,0 +1,437 @@
+use std::time::Duration;
+
+/// A completed command's output block in scrollback, as delimited by shell
+/// integration marks. Line numbers are buffer (scrollback) line indices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandRegion {
+    pub command_line: usize,
+    /// First output line (inclusive)
+    pub output_start: usize,
+    /// End of output (exclusive)
+    pub output_end: usize,
+    pub exit_code: Option<i32>,
+    pub duration: Option<Duration>,
+    pub folded: bool,
+}
+
+impl CommandRegion {
+    pub fn output_len(&self) -> usize {
+        self.output_end.saturating_sub(self.output_start)
+    }
+
+    /// Lines removed from the display when folded (the output collapses into
+    /// a single summary line)
+    fn hidden_lines(&self) -> usize {
+        if self.folded {
+            self.output_len().saturating_sub(1)
+        } else {
+            0
+        }
+    }
+
+    /// One-line summary shown in place of folded output, e.g.
+    /// "↳ 1,204 lines, exit 0, 12.3s"
+    pub fn summary(&self) -> String {
+        let count = self.output_len();
+        let mut summary = format!(
+            "↳ {} {}",
+            group_thousands(count),
+            if count == 1 { "line" } else { "lines" }
+        );
+        if let Some(code) = self.exit_code {
+            summary.push_str(&format!(", exit {}", code));
+        }
+        if let Some(duration) = self.duration {
+            summary.push_str(&format!(", {:.1}s", duration.as_secs_f64()));
+        }
+        summary
+    }
+}
+
+fn group_thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, ch) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(ch);
+    }
+    out
+}
+
+/// What a display row shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayLine {
+    /// An ordinary scrollback line
+    Buffer(usize),
+    /// The summary row standing in for a folded region
+    FoldSummary(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub buffer_line: usize,
+    pub column: usize,
+    /// Region the match was found inside while that region was folded
+    pub in_fold: Option<usize>,
+}
+
+/// Maps display lines to buffer lines in the presence of folded command
+/// output. Folding never removes content from scrollback; it only changes
+/// how many display rows the content occupies.
+#[derive(Debug, Clone, Default)]
+pub struct FoldMap {
+    regions: Vec<CommandRegion>,
+    auto_fold_lines: Option<usize>,
+}
+
+impl FoldMap {
+    pub fn new(auto_fold_lines: Option<usize>) -> Self {
+        Self {
+            regions: Vec::new(),
+            auto_fold_lines: auto_fold_lines.filter(|&n| n > 0),
+        }
+    }
+
+    /// Applies to regions added from now on
+    pub fn set_auto_fold_lines(&mut self, auto_fold_lines: Option<usize>) {
+        self.auto_fold_lines = auto_fold_lines.filter(|&n| n > 0);
+    }
+
+    pub fn regions(&self) -> &[CommandRegion] {
+        &self.regions
+    }
+
+    /// Register a completed command region. Regions arrive in scrollback
+    /// order; outputs longer than the auto-fold threshold start folded.
+    pub fn add_region(&mut self, mut region: CommandRegion) -> usize {
+        if let Some(threshold) = self.auto_fold_lines {
+            region.folded = region.output_len() > threshold;
+        }
+        let index = self
+            .regions
+            .partition_point(|r| r.output_start < region.output_start);
+        self.regions.insert(index, region);
+        index
+    }
+
+    pub fn toggle(&mut self, index: usize) -> Option<bool> {
+        let region = self.regions.get_mut(index)?;
+        region.folded = !region.folded;
+        Some(region.folded)
+    }
+
+    pub fn set_folded(&mut self, index: usize, folded: bool) {
+        if let Some(region) = self.regions.get_mut(index) {
+            region.folded = folded;
+        }
+    }
+
+    pub fn fold_all(&mut self) {
+        for region in &mut self.regions {
+            region.folded = region.output_len() > 0;
+        }
+    }
+
+    pub fn unfold_all(&mut self) {
+        for region in &mut self.regions {
+            region.folded = false;
+        }
+    }
+
+    /// Region whose command line or output contains `buffer_line`
+    pub fn region_at(&self, buffer_line: usize) -> Option<usize> {
+        self.regions.iter().position(|r| {
+            buffer_line >= r.command_line && buffer_line < r.output_end.max(r.command_line + 1)
+        })
+    }
+
+    /// Gutter click target: clicking a command line or a fold summary toggles
+    /// that region.
+    pub fn gutter_target(&self, display_line: usize, total_lines: usize) -> Option<usize> {
+        match self.display_to_buffer(display_line, total_lines)? {
+            DisplayLine::FoldSummary(index) => Some(index),
+            DisplayLine::Buffer(line) => self
+                .regions
+                .iter()
+                .position(|r| r.command_line == line && r.output_len() > 0),
+        }
+    }
+
+    pub fn display_line_count(&self, total_lines: usize) -> usize {
+        let hidden: usize = self
+            .regions
+            .iter()
+            .filter(|r| r.output_end <= total_lines)
+            .map(|r| r.hidden_lines())
+            .sum();
+        total_lines - hidden
+    }
+
+    pub fn display_to_buffer(
+        &self,
+        display_line: usize,
+        total_lines: usize,
+    ) -> Option<DisplayLine> {
+        let mut hidden_before = 0;
+        for (index, region) in self.regions.iter().enumerate() {
+            if !region.folded || region.output_len() == 0 || region.output_end > total_lines {
+                continue;
+            }
+            let summary_display = region.output_start - hidden_before;
+            if display_line < summary_display {
+                break;
+            }
+            if display_line == summary_display {
+                return Some(DisplayLine::FoldSummary(index));
+            }
+            hidden_before += region.hidden_lines();
+        }
+        let buffer_line = display_line + hidden_before;
+        (buffer_line < total_lines).then_some(DisplayLine::Buffer(buffer_line))
+    }
+
+    /// Display row for a buffer line; lines inside a fold map to its summary row
+    pub fn buffer_to_display(&self, buffer_line: usize) -> usize {
+        let mut hidden_before = 0;
+        for region in &self.regions {
+            if !region.folded || region.output_len() == 0 || buffer_line < region.output_start {
+                continue;
+            }
+            if buffer_line < region.output_end {
+                return region.output_start - hidden_before;
+            }
+            hidden_before += region.hidden_lines();
+        }
+        buffer_line - hidden_before
+    }
+
+    /// Rows visible in a viewport starting at display row `top`
+    pub fn visible_lines(&self, top: usize, height: usize, total_lines: usize) -> Vec<DisplayLine> {
+        (top..top + height)
+            .map_while(|display| self.display_to_buffer(display, total_lines))
+            .collect()
+    }
+
+    /// Scrollbar thumb as (offset, length) fractions of the track
+    pub fn scrollbar(&self, top: usize, height: usize, total_lines: usize) -> (f32, f32) {
+        let display_total = self.display_line_count(total_lines).max(1) as f32;
+        let offset = (top as f32 / display_total).min(1.0);
+        let length = (height as f32 / display_total).min(1.0 - offset);
+        (offset, length)
+    }
+
+    /// Search every buffer line, including those inside folds. With
+    /// `expand_matches`, folds containing a match are unfolded; otherwise the
+    /// match reports the fold it is hidden in.
+    pub fn search<S: AsRef<str>>(
+        &mut self,
+        lines: &[S],
+        needle: &str,
+        expand_matches: bool,
+    ) -> Vec<SearchMatch> {
+        if needle.is_empty() {
+            return Vec::new();
+        }
+        let mut matches = Vec::new();
+        for (line_index, line) in lines.iter().enumerate() {
+            for (column, _) in line.as_ref().match_indices(needle) {
+                let in_fold = self.regions.iter().position(|r| {
+                    r.folded && line_index >= r.output_start && line_index < r.output_end
+                });
+                if let (true, Some(index)) = (expand_matches, in_fold) {
+                    self.regions[index].folded = false;
+                }
+                matches.push(SearchMatch {
+                    buffer_line: line_index,
+                    column,
+                    in_fold: if expand_matches { None } else { in_fold },
+                });
+            }
+        }
+        matches
+    }
+
+    /// Text for a selection between two display rows (inclusive). Folded
+    /// regions inside the range contribute their full underlying output.
+    pub fn copy_display_range<S: AsRef<str>>(
+        &self,
+        lines: &[S],
+        start: usize,
+        end: usize,
+    ) -> String {
+        let total = lines.len();
+        let first = match self.display_to_buffer(start.min(end), total) {
+            Some(DisplayLine::Buffer(line)) => line,
+            Some(DisplayLine::FoldSummary(index)) => self.regions[index].output_start,
+            None => return String::new(),
+        };
+        let last = match self.display_to_buffer(start.max(end), total) {
+            Some(DisplayLine::Buffer(line)) => line,
+            Some(DisplayLine::FoldSummary(index)) => self.regions[index].output_end - 1,
+            None => total - 1,
+        };
+        lines[first..=last]
+            .iter()
+            .map(|l| l.as_ref())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Rewrite buffer line numbers after reflow or scrollback eviction.
+    /// Regions mapped to `None` (evicted) are dropped.
+    pub fn remap<F: Fn(usize) -> Option<usize>>(&mut self, map: F) {
+        self.regions.retain_mut(|region| {
+            match (
+                map(region.command_line),
+                map(region.output_start),
+                map(region.output_end),
+            ) {
+                (Some(command_line), Some(output_start), Some(output_end)) => {
+                    region.command_line = command_line;
+                    region.output_start = output_start;
+                    region.output_end = output_end;
+                    true
+                }
+                _ => false,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(command_line: usize, output_len: usize) -> CommandRegion {
+        CommandRegion {
+            command_line,
+            output_start: command_line + 1,
+            output_end: command_line + 1 + output_len,
+            exit_code: Some(0),
+            duration: Some(Duration::from_millis(12_300)),
+            folded: false,
+        }
+    }
+
+    fn buffer(total: usize) -> Vec<String> {
+        (0..total).map(|i| format!("line {}", i)).collect()
+    }
+
+    #[test]
+    fn test_summary_format() {
+        let mut r = region(0, 1204);
+        r.folded = true;
+        assert_eq!(r.summary(), "↳ 1,204 lines, exit 0, 12.3s");
+    }
+
+    #[test]
+    fn test_fold_viewport_math() {
+        // 0: cmd, 1..=10 output, 11: cmd, 12..=14 output, 15..=19 plain
+        let mut map = FoldMap::new(None);
+        map.add_region(region(0, 10));
+        map.add_region(region(11, 3));
+        let total = 20;
+
+        assert_eq!(map.display_line_count(total), 20);
+
+        map.toggle(0);
+        assert_eq!(map.display_line_count(total), 11);
+        assert_eq!(
+            map.display_to_buffer(0, total),
+            Some(DisplayLine::Buffer(0))
+        );
+        assert_eq!(
+            map.display_to_buffer(1, total),
+            Some(DisplayLine::FoldSummary(0))
+        );
+        assert_eq!(
+            map.display_to_buffer(2, total),
+            Some(DisplayLine::Buffer(11))
+        );
+        assert_eq!(
+            map.display_to_buffer(10, total),
+            Some(DisplayLine::Buffer(19))
+        );
+        assert_eq!(map.display_to_buffer(11, total), None);
+        assert_eq!(map.buffer_to_display(5), 1);
+        assert_eq!(map.buffer_to_display(11), 2);
+
+        map.fold_all();
+        assert_eq!(map.display_line_count(total), 9);
+        assert_eq!(
+            map.visible_lines(0, 4, total),
+            vec![
+                DisplayLine::Buffer(0),
+                DisplayLine::FoldSummary(0),
+                DisplayLine::Buffer(11),
+                DisplayLine::FoldSummary(1),
+            ]
+        );
+        let (offset, length) = map.scrollbar(0, 9, total);
+        assert_eq!((offset, length), (0.0, 1.0));
+
+        map.unfold_all();
+        assert_eq!(map.display_line_count(total), 20);
+    }
+
+    #[test]
+    fn test_gutter_toggle_target() {
+        let mut map = FoldMap::new(None);
+        map.add_region(region(0, 10));
+        assert_eq!(map.gutter_target(0, 20), Some(0));
+        map.toggle(0);
+        assert_eq!(map.gutter_target(1, 20), Some(0));
+        assert_eq!(map.gutter_target(2, 20), None);
+    }
+
+    #[test]
+    fn test_auto_fold_threshold() {
+        let mut map = FoldMap::new(Some(5));
+        let long = map.add_region(region(0, 10));
+        let short = map.add_region(region(11, 3));
+        assert!(map.regions()[long].folded);
+        assert!(!map.regions()[short].folded);
+    }
+
+    #[test]
+    fn test_search_inside_fold() {
+        let lines = buffer(20);
+        let mut map = FoldMap::new(None);
+        map.add_region(region(0, 10));
+        map.toggle(0);
+
+        let found = map.search(&lines, "line 5", false);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].in_fold, Some(0));
+        assert!(map.regions()[0].folded);
+
+        let found = map.search(&lines, "line 5", true);
+        assert_eq!(found[0].in_fold, None);
+        assert!(!map.regions()[0].folded);
+    }
+
+    #[test]
+    fn test_copy_spanning_fold_yields_full_text() {
+        let lines = buffer(20);
+        let mut map = FoldMap::new(None);
+        map.add_region(region(0, 10));
+        map.toggle(0);
+
+        // Display rows 0..=2 are: command, fold summary, line 11
+        let copied = map.copy_display_range(&lines, 0, 2);
+        assert_eq!(copied, lines[0..=11].join("\n"));
+    }
+
+    #[test]
+    fn test_remap_after_eviction() {
+        let mut map = FoldMap::new(None);
+        map.add_region(region(0, 10));
+        map.add_region(region(11, 3));
+        map.remap(|line| line.checked_sub(11));
+        assert_eq!(map.regions().len(), 1);
+        assert_eq!(map.regions()[0].command_line, 0);
+        assert_eq!(map.regions()[0].output_end, 4);
+    }
+}
@@ -0,0 +1,444 @@
+use crate::config::Config;
+use crate::model_host::{self, InferencePriority, InferenceRequest, ModelHost};
+use crate::terminal::TerminalState;
+use crate::usage::ModelUsage;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Error, Debug)]
+pub enum GhostTextError {
+    #[error("No model named {0} registered from [models]")]
+    UnknownModel(String),
+    #[error("Completion failed: {0}")]
+    Model(#[from] model_host::ModelHostError),
+}
+
+/// Completes a partly typed command line
+#[async_trait]
+pub trait CompletionModel: Send + Sync {
+    /// The rest of `line`, or the whole completed line
+    async fn complete(&self, line: &str, context: &[String]) -> Result<String, GhostTextError>;
+
+    /// Requests, tokens and cost per model, including earlier runs
+    fn usage(&self) -> BTreeMap<String, ModelUsage> {
+        BTreeMap::new()
+    }
+}
+
+/// `agent.fast_model` (the default model when unset) on the shared host
+pub struct HostCompletion {
+    host: Arc<ModelHost>,
+    model: String,
+    budget: Duration,
+}
+
+impl HostCompletion {
+    /// Use a model registered from `[[models.list]]`, loading it if it was
+    /// not preloaded
+    pub async fn load(host: Arc<ModelHost>, config: &Config, budget: Duration) -> Result<Self, GhostTextError> {
+        let name = match config.agent.fast_model.as_str() {
+            "" => config.models.default_model(&config.agent),
+            name => name,
+        };
+        let state = host
+            .model_states()
+            .await
+            .into_iter()
+            .find(|state| state.name == name)
+            .ok_or_else(|| GhostTextError::UnknownModel(name.to_string()))?;
+        if !state.loaded {
+            host.load_model(name).await?;
+        }
+        Ok(Self {
+            host,
+            model: name.to_string(),
+            budget,
+        })
+    }
+}
+
+#[async_trait]
+impl CompletionModel for HostCompletion {
+    async fn complete(&self, line: &str, context: &[String]) -> Result<String, GhostTextError> {
+        let prompt = format!(
+            "Complete the shell command. Reply with the full command only.\n\n{}\n$ {}",
+            context.join("\n"),
+            line
+        );
+        let mut request = InferenceRequest::new(&self.model, prompt);
+        request.parameters.max_tokens = 32;
+        request.parameters.temperature = 0.2;
+        request.parameters.stop_sequences = vec!["\n".to_string()];
+        request.priority = InferencePriority::High;
+        request.timeout_ms = Some(self.budget.as_millis() as u64);
+        Ok(self.host.infer(request).await?.text)
+    }
+
+    fn usage(&self) -> BTreeMap<String, ModelUsage> {
+        self.host.get_usage_all()
+    }}
+
+#[derive(Debug, Clone)]
+pub struct GhostTextConfig {
+    /// Typing pause before a suggestion is requested
+    pub debounce: Duration,
+    pub min_chars: usize,
+    /// Replies slower than this are dropped
+    pub budget: Duration,
+    /// Screen rows above the prompt sent as context
+    pub context_lines: usize,
+}
+
+impl Default for GhostTextConfig {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(150),
+            min_chars: 3,
+            budget: Duration::from_millis(400),
+            context_lines: 20,
+        }
+    }
+}
+
+/// A request to run; cancelled as soon as the line changes
+#[derive(Debug, Clone)]
+pub struct CompletionJob {
+    pub id: u64,
+    pub line: String,
+    pub cancel: CancellationToken,
+}
+
+#[derive(Debug)]
+pub struct CompletionReply {
+    pub pane: u64,
+    pub id: u64,
+    pub text: Result<String, GhostTextError>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    id: u64,
+    line: String,
+    sent_at: Instant,
+    cancel: CancellationToken,
+}
+
+/// Fish-style suggestions for one pane. The suggestion is only drawn over
+/// the grid; nothing reaches the PTY until it is accepted.
+#[derive(Debug)]
+pub struct GhostText {
+    config: GhostTextConfig,
+    disabled: bool,
+    line: Option<String>,
+    changed_at: Instant,
+    /// The line last asked about, so an ignored suggestion is not re-requested
+    requested: Option<String>,
+    pending: Option<Pending>,
+    /// The line it was made for and the characters that would complete it
+    suggestion: Option<(String, String)>,
+    next_id: u64,
+}
+
+impl GhostText {
+    pub fn new(config: GhostTextConfig, now: Instant) -> Self {
+        Self {
+            config,
+            disabled: false,
+            line: None,
+            changed_at: now,
+            requested: None,
+            pending: None,
+            suggestion: None,
+            next_id: 0,
+        }
+    }
+
+    pub fn config(&self) -> &GhostTextConfig {
+        &self.config
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    /// Per-pane switch
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.disabled = disabled;
+        if disabled {
+            self.reset();
+        }
+    }
+
+    /// Called every frame with what has been typed at the prompt (`None`
+    /// off the prompt). `blocked` while the pane is read-only or an overlay
+    /// has the keyboard. Returns a request once typing pauses.
+    pub fn update(
+        &mut self,
+        line: Option<&str>,
+        blocked: bool,
+        now: Instant,
+    ) -> Option<CompletionJob> {
+        if self.line.as_deref() != line {
+            // Typing the suggestion's own next characters keeps the rest
+            self.suggestion = self.suggestion.take().and_then(|(old, rest)| {
+                let line = line?;
+                let rest = rest.strip_prefix(line.strip_prefix(old.as_str())?)?;
+                (!rest.is_empty()).then(|| (line.to_string(), rest.to_string()))
+            });
+            self.cancel();
+            self.line = line.map(str::to_string);
+            self.changed_at = now;
+        }
+        if blocked || self.disabled {
+            self.reset();
+            return None;
+        }
+
+        let line = self.line.as_deref()?;
+        if self.pending.is_some()
+            || self.suggestion.is_some()
+            || self.requested.as_deref() == Some(line)
+            || line.trim().chars().count() < self.config.min_chars
+            || now.saturating_duration_since(self.changed_at) < self.config.debounce
+        {
+            return None;
+        }
+
+        self.next_id += 1;
+        let job = CompletionJob {
+            id: self.next_id,
+            line: line.to_string(),
+            cancel: CancellationToken::new(),
+        };
+        self.requested = Some(job.line.clone());
+        self.pending = Some(Pending {
+            id: job.id,
+            line: job.line.clone(),
+            sent_at: now,
+            cancel: job.cancel.clone(),
+        });
+        Some(job)
+    }
+
+    /// A model reply. Dropped when the line changed since the request or
+    /// the reply missed the latency budget; returns whether it is shown.
+    pub fn deliver(&mut self, id: u64, completion: &str, now: Instant) -> bool {
+        let Some(pending) = self.pending.take_if(|pending| pending.id == id) else {
+            return false;
+        };
+        if now.saturating_duration_since(pending.sent_at) > self.config.budget
+            || self.line.as_deref() != Some(pending.line.as_str())
+        {
+            return false;
+        }
+        let completion = completion.trim_end_matches(['\r', '\n']);
+        let rest = completion
+            .strip_prefix(pending.line.as_str())
+            .unwrap_or(completion);
+        if rest.is_empty() || rest.chars().any(char::is_control) {
+            return false;
+        }
+        self.suggestion = Some((pending.line, rest.to_string()));
+        true
+    }
+
+    pub fn failed(&mut self, id: u64) {
+        self.pending.take_if(|pending| pending.id == id);
+    }
+
+    /// The dim text drawn after the cursor
+    pub fn suggestion(&self) -> Option<&str> {
+        self.suggestion.as_ref().map(|(_, rest)| rest.as_str())
+    }
+
+    /// Right-arrow or End: the characters to type for the user
+    pub fn accept(&mut self) -> Option<Vec<u8>> {
+        self.suggestion.take().map(|(_, rest)| rest.into_bytes())
+    }
+
+    fn cancel(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            pending.cancel.cancel();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.cancel();
+        self.suggestion = None;
+        self.requested = None;
+    }
+}
+
+/// Screen rows above the prompt, oldest first
+pub fn context_lines(terminal: &TerminalState, max: usize) -> Vec<String> {
+    let mut lines: Vec<String> = (0..terminal.cursor_y)
+        .rev()
+        .map(|y| terminal.row_text(y))
+        .filter(|line| !line.is_empty())
+        .take(max)
+        .collect();
+    lines.reverse();
+    lines
+}
+
+/// Run `job` on its own task. Nothing is sent back once it is cancelled,
+/// and the model is given up on after `budget`.
+pub fn spawn_completion(
+    model: Arc<dyn CompletionModel>,
+    pane: u64,
+    job: CompletionJob,
+    context: Vec<String>,
+    budget: Duration,
+    replies: crossbeam_channel::Sender<CompletionReply>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let text = tokio::select! {
+            _ = job.cancel.cancelled() => return,
+            reply = tokio::time::timeout(budget, model.complete(&job.line, &context)) => match reply {
+                Ok(text) => text,
+                // The engine would drop it anyway
+                Err(_) => return,
+            },
+        };
+        let _ = replies.send(CompletionReply {
+            pane,
+            id: job.id,
+            text,
+        });
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_debounce_and_suppression() {
+        let t0 = Instant::now();
+        let mut ghost = GhostText::new(GhostTextConfig::default(), t0);
+
+        // Too short, then still typing
+        assert!(ghost.update(Some("gi"), false, t0 + ms(500)).is_none());
+        assert!(ghost.update(Some("git"), false, t0 + ms(600)).is_none());
+        assert!(ghost.update(Some("git"), false, t0 + ms(700)).is_none());
+        let job = ghost.update(Some("git"), false, t0 + ms(750)).unwrap();
+        assert_eq!(job.line, "git");
+        // One request per line
+        assert!(ghost.update(Some("git"), false, t0 + ms(900)).is_none());
+        assert!(ghost.deliver(job.id, "git status", t0 + ms(900)));
+        assert_eq!(ghost.suggestion(), Some(" status"));
+
+        // Read-only pane or an open overlay hides it and asks for nothing
+        assert!(ghost.update(Some("git"), true, t0 + ms(1000)).is_none());
+        assert_eq!(ghost.suggestion(), None);
+        assert!(ghost.update(Some("git l"), true, t0 + ms(2000)).is_none());
+
+        // Off the prompt, e.g. in a full-screen app
+        assert!(ghost.update(None, false, t0 + ms(3000)).is_none());
+
+        ghost.set_disabled(true);
+        assert!(
+            ghost
+                .update(Some("git log"), false, t0 + ms(4000))
+                .is_none()
+        );
+        assert!(
+            ghost
+                .update(Some("git log"), false, t0 + ms(5000))
+                .is_none()
+        );
+        ghost.set_disabled(false);
+        assert!(
+            ghost
+                .update(Some("git log"), false, t0 + ms(5000))
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_cancellation_stale_drop_and_acceptance() {
+        let t0 = Instant::now();
+        let mut ghost = GhostText::new(GhostTextConfig::default(), t0);
+        ghost.update(Some("car"), false, t0);
+        let first = ghost.update(Some("car"), false, t0 + ms(150)).unwrap();
+
+        // Typing on cancels the request, and its reply is dropped
+        ghost.update(Some("carg"), false, t0 + ms(200));
+        assert!(first.cancel.is_cancelled());
+        assert!(!ghost.deliver(first.id, "cargo build", t0 + ms(210)));
+
+        // Over the latency budget
+        let late = ghost.update(Some("carg"), false, t0 + ms(400)).unwrap();
+        assert!(!ghost.deliver(late.id, "cargo build", t0 + ms(900)));
+        assert_eq!(ghost.suggestion(), None);
+
+        ghost.update(Some("cargo"), false, t0 + ms(1000));
+        let job = ghost.update(Some("cargo"), false, t0 + ms(1200)).unwrap();
+        assert!(!ghost.deliver(job.id, "cargo \x1b[2J", t0 + ms(1250)));
+        ghost.update(Some("cargo "), false, t0 + ms(1400));
+        let job = ghost.update(Some("cargo "), false, t0 + ms(1600)).unwrap();
+        assert!(ghost.deliver(job.id, "cargo build --release\n", t0 + ms(1700)));
+
+        // Typing the suggested characters keeps the rest of it
+        ghost.update(Some("cargo b"), false, t0 + ms(1800));
+        assert_eq!(ghost.suggestion(), Some("uild --release"));
+        assert_eq!(ghost.accept().as_deref(), Some(&b"uild --release"[..]));
+        assert_eq!(ghost.suggestion(), None);
+        assert_eq!(ghost.accept(), None);
+    }
+
+    struct MockModel(Duration);
+
+    #[async_trait]
+    impl CompletionModel for MockModel {
+        async fn complete(
+            &self,
+            line: &str,
+            _context: &[String],
+        ) -> Result<String, GhostTextError> {
+            tokio::time::sleep(self.0).await;
+            Ok(format!("{} --help", line))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawned_requests_reply_unless_cancelled() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let job = |id| CompletionJob {
+            id,
+            line: "ls".to_string(),
+            cancel: CancellationToken::new(),
+        };
+        let model: Arc<dyn CompletionModel> = Arc::new(MockModel(ms(20)));
+
+        let answered = spawn_completion(model.clone(), 3, job(1), Vec::new(), ms(400), tx.clone());
+        let cancelled = job(2);
+        let dropped = spawn_completion(
+            model.clone(),
+            3,
+            cancelled.clone(),
+            Vec::new(),
+            ms(400),
+            tx.clone(),
+        );
+        cancelled.cancel.cancel();
+        let slow: Arc<dyn CompletionModel> = Arc::new(MockModel(ms(200)));
+        let timed_out = spawn_completion(slow, 3, job(3), Vec::new(), ms(50), tx);
+        for task in [answered, dropped, timed_out] {
+            task.await.unwrap();
+        }
+
+        let replies: Vec<CompletionReply> = rx.try_iter().collect();
+        assert_eq!(replies.len(), 1);
+        assert_eq!((replies[0].pane, replies[0].id), (3, 1));
+        assert_eq!(replies[0].text.as_deref().unwrap(), "ls --help");
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+use thiserror::Error;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum GlyphGuardError {
+    #[error("Zero-sized glyph allocation")]
+    ZeroSize,
+    #[error("Glyph {width}x{height} exceeds atlas size {size}")]
+    TooLarge { width: u32, height: u32, size: u32 },
+    #[error("Atlas is full")]
+    AtlasFull,
+}
+
+/// Bounds applied to untrusted glyph input before shaping and rasterization
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphLimits {
+    /// Combining marks shaped per cluster; the rest are dropped
+    pub max_combining_marks: usize,
+    /// Largest coverage bitmap a single glyph may allocate
+    pub max_glyph_bytes: usize,
+    /// How far a glyph may extend beyond its cell box, as a multiple of the
+    /// cell size (wide glyphs get twice the cell width)
+    pub max_cell_overflow: f32,
+}
+
+impl Default for GlyphLimits {
+    fn default() -> Self {
+        Self {
+            max_combining_marks: 8,
+            max_glyph_bytes: 64 * 1024,
+            max_cell_overflow: 2.0,
+        }
+    }
+}
+
+/// Column width of a single character as placed in the grid: 0 for control
+/// and zero-width characters, otherwise 1 or 2.
+pub fn char_width(ch: char) -> u8 {
+    match ch.width() {
+        Some(w) => w.min(2) as u8,
+        None => 0,
+    }
+}
+
+/// One extended grapheme cluster, ready for shaping
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    pub text: String,
+    /// Grid columns the cluster occupies (1 or 2)
+    pub width: u8,
+    /// Combining marks beyond the per-cluster cap that were not kept
+    pub dropped_marks: usize,
+}
+
+/// Split text into clusters, capping the combining marks kept per cluster.
+/// Control characters are not clusters and are skipped; a cluster made only
+/// of zero-width characters still occupies one column so it stays visible.
+pub fn segment_clusters(text: &str, limits: &GlyphLimits) -> Vec<Cluster> {
+    let mut clusters = Vec::new();
+    for grapheme in text.graphemes(true) {
+        let mut chars = grapheme.chars();
+        let Some(base) = chars.next() else {
+            continue;
+        };
+        if base.is_control() {
+            continue;
+        }
+
+        let mut cluster = String::with_capacity(grapheme.len().min(64));
+        cluster.push(base);
+        let mut width = char_width(base);
+        let mut kept = 0;
+        let mut dropped = 0;
+        for ch in chars {
+            // Emoji presentation: a text-width symbol drawn as a color emoji
+            if ch == '\u{FE0F}' {
+                width = 2;
+            }
+            if char_width(ch) == 0 {
+                if kept < limits.max_combining_marks {
+                    cluster.push(ch);
+                    kept += 1;
+                } else {
+                    dropped += 1;
+                }
+            } else {
+                // Joined sequences (ZWJ emoji, regional indicators) render as
+                // one glyph, never wider than two columns
+                cluster.push(ch);
+                width = width.max(char_width(ch));
+            }
+        }
+
+        clusters.push(Cluster {
+            text: cluster,
+            width: width.clamp(1, 2),
+            dropped_marks: dropped,
+        });
+    }
+    clusters
+}
+
+/// Total grid columns used by `text`
+pub fn text_width(text: &str, limits: &GlyphLimits) -> usize {
+    segment_clusters(text, limits)
+        .iter()
+        .map(|c| c.width as usize)
+        .sum()
+}
+
+/// Replace zero, negative or non-finite advances from broken fonts with the
+/// cell advance, and cap absurdly large ones.
+pub fn sanitize_advance(advance: f32, cell_width: f32, limits: &GlyphLimits) -> f32 {
+    if !advance.is_finite() || advance <= 0.0 {
+        return cell_width;
+    }
+    advance.min(cell_width * limits.max_cell_overflow.max(1.0))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RasterSize {
+    pub width: u32,
+    pub height: u32,
+    pub clipped: bool,
+}
+
+/// Clip a glyph bitmap to the cell box (scaled by the overflow factor) and to
+/// the per-glyph byte budget. `columns` is the cluster width in cells.
+pub fn clip_raster(
+    width: u32,
+    height: u32,
+    columns: u8,
+    cell_width: f32,
+    cell_height: f32,
+    bytes_per_pixel: u32,
+    limits: &GlyphLimits,
+) -> RasterSize {
+    let factor = limits.max_cell_overflow.max(1.0);
+    let max_width = (cell_width.max(1.0) * columns.max(1) as f32 * factor).ceil() as u32;
+    let max_height = (cell_height.max(1.0) * factor).ceil() as u32;
+
+    let mut w = width.min(max_width);
+    let mut h = height.min(max_height);
+    let bpp = bytes_per_pixel.max(1) as u64;
+    let budget = limits.max_glyph_bytes as u64;
+    if w as u64 * h as u64 * bpp > budget {
+        if w as u64 * bpp > budget {
+            w = (budget / bpp) as u32;
+        }
+        h = if w == 0 {
+            0
+        } else {
+            (budget / (w as u64 * bpp)) as u32
+        };
+    }
+
+    RasterSize {
+        width: w,
+        height: h,
+        clipped: w != width || h != height,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasSlot {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub layer: u32,
+}
+
+/// Shelf packer for a layered square glyph atlas. Allocation never panics or
+/// grows: oversized requests are rejected and a full atlas reports
+/// `AtlasFull` so the caller can evict.
+#[derive(Debug, Clone)]
+pub struct AtlasAllocator {
+    size: u32,
+    layer_count: u32,
+    layer: u32,
+    cursor_x: u32,
+    cursor_y: u32,
+    shelf_height: u32,
+    occupied: u64,
+    allocations: usize,
+}
+
+impl AtlasAllocator {
+    pub fn new(size: u32, layer_count: u32) -> Self {
+        Self {
+            size,
+            layer_count,
+            layer: 0,
+            cursor_x: 0,
+            cursor_y: 0,
+            shelf_height: 0,
+            occupied: 0,
+            allocations: 0,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.layer_count
+    }
+
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    /// Pixels handed out so far
+    pub fn occupied_area(&self) -> u64 {
+        self.occupied
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.size as u64 * self.size as u64 * self.layer_count as u64
+    }
+
+    pub fn allocate(&mut self, width: u32, height: u32) -> Result<AtlasSlot, GlyphGuardError> {
+        if width == 0 || height == 0 {
+            return Err(GlyphGuardError::ZeroSize);
+        }
+        if width > self.size || height > self.size {
+            return Err(GlyphGuardError::TooLarge {
+                width,
+                height,
+                size: self.size,
+            });
+        }
+        if self.layer >= self.layer_count {
+            return Err(GlyphGuardError::AtlasFull);
+        }
+
+        if self.cursor_x + width > self.size {
+            self.cursor_x = 0;
+            self.cursor_y += self.shelf_height;
+            self.shelf_height = 0;
+        }
+        if self.cursor_y + height > self.size {
+            self.layer += 1;
+            self.cursor_x = 0;
+            self.cursor_y = 0;
+            self.shelf_height = 0;
+            if self.layer >= self.layer_count {
+                return Err(GlyphGuardError::AtlasFull);
+            }
+        }
+
+        let slot = AtlasSlot {
+            x: self.cursor_x,
+            y: self.cursor_y,
+            width,
+            height,
+            layer: self.layer,
+        };
+        self.cursor_x += width;
+        self.shelf_height = self.shelf_height.max(height);
+        self.occupied += width as u64 * height as u64;
+        self.allocations += 1;
+        Ok(slot)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.size, self.layer_count);
+    }
+
+    /// A slot lies inside the atlas
+    pub fn contains(&self, slot: &AtlasSlot) -> bool {
+        slot.layer < self.layer_count
+            && slot.x as u64 + slot.width as u64 <= self.size as u64
+            && slot.y as u64 + slot.height as u64 <= self.size as u64
+    }
+}
+
+/// Raster metrics reported by a rasterizer for one cluster
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RasterMetrics {
+    pub width: u32,
+    pub height: u32,
+    pub advance: f32,
+}
+
+/// The font-dependent part of the pipeline; swapped for a stub when fuzzing
+pub trait GlyphRasterizer {
+    fn measure(&mut self, cluster: &str) -> RasterMetrics;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacedGlyph {
+    pub column: usize,
+    pub columns: u8,
+    pub slot: Option<AtlasSlot>,
+    pub advance: f32,
+}
+
+/// Cluster segmentation, width calculation, raster clipping and atlas
+/// allocation for untrusted text, with a glyph cache that is flushed when the
+/// atlas fills up.
+pub struct GlyphPipeline<R> {
+    rasterizer: R,
+    limits: GlyphLimits,
+    cell_width: f32,
+    cell_height: f32,
+    atlas: AtlasAllocator,
+    cache: HashMap<String, AtlasSlot>,
+    /// Atlas flushes caused by running out of space
+    pub evictions: u64,
+}
+
+impl<R: GlyphRasterizer> GlyphPipeline<R> {
+    pub fn new(
+        rasterizer: R,
+        limits: GlyphLimits,
+        cell_width: f32,
+        cell_height: f32,
+        atlas: AtlasAllocator,
+    ) -> Self {
+        Self {
+            rasterizer,
+            limits,
+            cell_width,
+            cell_height,
+            atlas,
+            cache: HashMap::new(),
+            evictions: 0,
+        }
+    }
+
+    pub fn atlas(&self) -> &AtlasAllocator {
+        &self.atlas
+    }
+
+    pub fn cached_glyphs(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn process(&mut self, text: &str) -> Vec<PlacedGlyph> {
+        let mut placed = Vec::new();
+        let mut column = 0;
+        for cluster in segment_clusters(text, &self.limits) {
+            let slot = self.slot_for(&cluster);
+            let metrics = self.rasterizer.measure(&cluster.text);
+            placed.push(PlacedGlyph {
+                column,
+                columns: cluster.width,
+                slot,
+                advance: sanitize_advance(
+                    metrics.advance,
+                    self.cell_width * cluster.width as f32,
+                    &self.limits,
+                ),
+            });
+            column += cluster.width as usize;
+        }
+        placed
+    }
+
+    fn slot_for(&mut self, cluster: &Cluster) -> Option<AtlasSlot> {
+        if let Some(slot) = self.cache.get(&cluster.text) {
+            return Some(*slot);
+        }
+        let metrics = self.rasterizer.measure(&cluster.text);
+        let size = clip_raster(
+            metrics.width,
+            metrics.height,
+            cluster.width,
+            self.cell_width,
+            self.cell_height,
+            1,
+            &self.limits,
+        );
+        let slot = match self.atlas.allocate(size.width, size.height) {
+            Ok(slot) => slot,
+            Err(GlyphGuardError::AtlasFull) => {
+                self.atlas.reset();
+                self.cache.clear();
+                self.evictions += 1;
+                self.atlas.allocate(size.width, size.height).ok()?
+            }
+            // Blank glyphs (spaces) and glyphs that cannot fit are drawn
+            // without atlas content
+            Err(_) => return None,
+        };
+        self.cache.insert(cluster.text.clone(), slot);
+        Some(slot)
+    }
+
+    /// Invariants that must hold after any input
+    pub fn check_invariants(&self) -> Result<(), String> {
+        if self.atlas.occupied_area() > self.atlas.capacity() {
+            return Err(format!(
+                "atlas occupancy {} exceeds capacity {}",
+                self.atlas.occupied_area(),
+                self.atlas.capacity()
+            ));
+        }
+        if self.cache.len() > self.atlas.allocations() {
+            return Err(format!(
+                "{} cached glyphs but only {} allocations",
+                self.cache.len(),
+                self.atlas.allocations()
+            ));
+        }
+        if let Some(slot) = self.cache.values().find(|s| !self.atlas.contains(s)) {
+            return Err(format!("slot {:?} outside atlas", slot));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rasterizer stub derived from the cluster's bytes, including the broken
+    /// metrics real fonts occasionally report
+    struct StubRasterizer;
+
+    impl GlyphRasterizer for StubRasterizer {
+        fn measure(&mut self, cluster: &str) -> RasterMetrics {
+            let seed = cluster
+                .bytes()
+                .fold(7u32, |h, b| h.wrapping_mul(31) ^ b as u32);
+            RasterMetrics {
+                width: seed % 300,
+                height: (seed >> 8) % 300 + cluster.chars().count() as u32 * 4,
+                advance: match seed % 5 {
+                    0 => 0.0,
+                    1 => -3.0,
+                    2 => f32::NAN,
+                    _ => (seed % 40) as f32,
+                },
+            }
+        }
+    }
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn char(&mut self) -> char {
+            // Bias towards combining marks, wide ranges and controls
+            match self.next() % 6 {
+                0 => char::from_u32(0x0300 + (self.next() % 0x70) as u32).unwrap(),
+                1 => char::from_u32(0x4E00 + (self.next() % 0x5000) as u32).unwrap(),
+                2 => char::from_u32((self.next() % 0x20) as u32).unwrap(),
+                3 => char::from_u32(0x1F300 + (self.next() % 0x300) as u32).unwrap_or('x'),
+                4 => ['\u{200D}', '\u{FE0F}', '\u{1F1E6}', '\u{0E47}'][(self.next() % 4) as usize],
+                _ => char::from_u32((self.next() % 0x11_0000) as u32).unwrap_or('\u{FFFD}'),
+            }
+        }
+    }
+
+    #[test]
+    fn test_combining_marks_capped() {
+        let limits = GlyphLimits::default();
+        let zalgo: String = std::iter::once('a')
+            .chain(std::iter::repeat_n('\u{0301}', 500))
+            .collect();
+        let clusters = segment_clusters(&zalgo, &limits);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(
+            clusters[0].text.chars().count(),
+            1 + limits.max_combining_marks
+        );
+        assert_eq!(clusters[0].dropped_marks, 500 - limits.max_combining_marks);
+        assert_eq!(clusters[0].width, 1);
+    }
+
+    #[test]
+    fn test_cluster_widths() {
+        let limits = GlyphLimits::default();
+        assert_eq!(text_width("abc", &limits), 3);
+        assert_eq!(text_width("日本", &limits), 4);
+        assert_eq!(text_width("a\u{0007}b", &limits), 2);
+        // Lone combining mark still takes a column
+        assert_eq!(text_width("\u{0301}", &limits), 1);
+        // ZWJ family emoji is one cluster, two columns
+        assert_eq!(text_width("👨\u{200D}👩\u{200D}👧", &limits), 2);
+    }
+
+    #[test]
+    fn test_advance_and_raster_clipping() {
+        let limits = GlyphLimits::default();
+        assert_eq!(sanitize_advance(0.0, 8.0, &limits), 8.0);
+        assert_eq!(sanitize_advance(-1.0, 8.0, &limits), 8.0);
+        assert_eq!(sanitize_advance(f32::INFINITY, 8.0, &limits), 8.0);
+        assert_eq!(sanitize_advance(1000.0, 8.0, &limits), 16.0);
+
+        let size = clip_raster(4000, 4000, 1, 8.0, 16.0, 1, &limits);
+        assert_eq!((size.width, size.height), (16, 32));
+        assert!(size.clipped);
+
+        let tight = GlyphLimits {
+            max_glyph_bytes: 100,
+            ..GlyphLimits::default()
+        };
+        let size = clip_raster(16, 32, 1, 8.0, 16.0, 4, &tight);
+        assert!(size.width as u64 * size.height as u64 * 4 <= 100);
+    }
+
+    #[test]
+    fn test_atlas_rejects_bad_requests() {
+        let mut atlas = AtlasAllocator::new(64, 1);
+        assert_eq!(atlas.allocate(0, 10), Err(GlyphGuardError::ZeroSize));
+        assert!(matches!(
+            atlas.allocate(65, 1),
+            Err(GlyphGuardError::TooLarge { .. })
+        ));
+        for _ in 0..4 {
+            atlas.allocate(32, 32).unwrap();
+        }
+        assert_eq!(atlas.allocate(1, 1), Err(GlyphGuardError::AtlasFull));
+        assert_eq!(atlas.allocate(1, 1), Err(GlyphGuardError::AtlasFull));
+        assert_eq!(atlas.occupied_area(), atlas.capacity());
+    }
+
+    #[test]
+    fn test_random_streams_keep_invariants() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        let limits = GlyphLimits::default();
+        let mut pipeline = GlyphPipeline::new(
+            StubRasterizer,
+            limits.clone(),
+            8.0,
+            16.0,
+            AtlasAllocator::new(256, 2),
+        );
+
+        for _ in 0..200 {
+            let len = (rng.next() % 300) as usize;
+            let text: String = (0..len).map(|_| rng.char()).collect();
+            let placed = pipeline.process(&text);
+            pipeline.check_invariants().unwrap();
+
+            let expected: usize = placed.iter().map(|g| g.columns as usize).sum();
+            assert_eq!(expected, text_width(&text, &limits));
+            for glyph in &placed {
+                assert!((1..=2).contains(&glyph.columns));
+                assert!(glyph.advance.is_finite() && glyph.advance > 0.0);
+                if let Some(slot) = glyph.slot {
+                    assert!(pipeline.atlas().contains(&slot));
+                    assert!(slot.width as usize * slot.height as usize <= limits.max_glyph_bytes);
+                }
+            }
+        }
+        assert!(pipeline.evictions > 0);
+    }
+}
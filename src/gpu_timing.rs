@@ -0,0 +1,533 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Frames of queries in flight; results are read this many frames late
+pub const RING_SIZE: usize = 3;
+/// Timed passes per frame (the main pass plus post-processing)
+pub const MAX_PASSES: usize = 4;
+/// Frames in the moving average
+const AVERAGE_WINDOW: usize = 30;
+
+/// Readback side of the timestamp queries, seamed out so the ring
+/// bookkeeping can be tested without a GPU. Neither call may block.
+pub trait TimestampReadback {
+    /// Nanoseconds per timestamp tick
+    fn period(&self) -> f32;
+    /// Starts mapping the slot's readback buffer after its frame was submitted
+    fn request(&mut self, slot: usize);
+    /// Raw ticks, begin/end per pass, once the mapping has completed
+    fn try_read(&mut self, slot: usize) -> Option<Vec<u64>>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassTiming {
+    pub name: String,
+    pub last: Duration,
+    pub average: Duration,
+}
+
+/// GPU cost as reported in metrics, `:stats` and telemetry
+#[derive(Debug, Clone, PartialEq)]
+pub enum GpuStats {
+    /// The adapter lacks TIMESTAMP_QUERY
+    Unavailable,
+    /// Supported, but no frame has been read back yet
+    Pending,
+    Ready(Vec<PassTiming>),
+}
+
+impl GpuStats {
+    pub fn total(&self) -> Option<Duration> {
+        match self {
+            GpuStats::Ready(passes) => Some(passes.iter().map(|p| p.last).sum()),
+            _ => None,
+        }
+    }
+
+    /// Per-pass microseconds, for telemetry histograms; None when unavailable
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            GpuStats::Unavailable => serde_json::Value::Null,
+            GpuStats::Pending => serde_json::json!({}),
+            GpuStats::Ready(passes) => passes
+                .iter()
+                .map(|p| (p.name.clone(), serde_json::json!(p.last.as_micros() as u64)))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+        }
+    }
+}
+
+impl fmt::Display for GpuStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuStats::Unavailable => write!(f, "gpu: unavailable (no timestamp query support)"),
+            GpuStats::Pending => write!(f, "gpu: waiting for the first frame"),
+            GpuStats::Ready(passes) => {
+                write!(f, "gpu:")?;
+                for (i, pass) in passes.iter().enumerate() {
+                    write!(
+                        f,
+                        "{} {} {:.2} ms (avg {:.2} ms)",
+                        if i == 0 { "" } else { "," },
+                        pass.name,
+                        pass.last.as_secs_f64() * 1000.0,
+                        pass.average.as_secs_f64() * 1000.0
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Slot {
+    Free,
+    /// Encoding; passes named so far
+    Recording(Vec<String>),
+    /// Submitted and waiting on its readback
+    InFlight {
+        frame: u64,
+        passes: Vec<String>,
+    },
+}
+
+/// A frame's claim on a ring slot, held while its passes are encoded
+#[derive(Debug)]
+pub struct FrameQueries {
+    slot: usize,
+}
+
+impl FrameQueries {
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+}
+
+/// Query indices a pass writes its begin and end timestamps to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassQueries {
+    pub begin: u32,
+    pub end: u32,
+}
+
+/// Ring of per-frame timestamp queries. A frame whose slot is still waiting
+/// on a readback goes untimed rather than stalling; results are published
+/// strictly in submission order.
+pub struct GpuTimer<R: TimestampReadback> {
+    readback: Option<R>,
+    slots: Vec<Slot>,
+    next_slot: usize,
+    frame: u64,
+    /// Slots in submission order, oldest first
+    in_flight: VecDeque<usize>,
+    history: Vec<(String, VecDeque<Duration>)>,
+    latest: Option<(u64, Vec<(String, Duration)>)>,
+    skipped: u64,
+}
+
+impl<R: TimestampReadback> GpuTimer<R> {
+    pub fn new(readback: Option<R>) -> Self {
+        Self {
+            readback,
+            slots: vec![Slot::Free; RING_SIZE],
+            next_slot: 0,
+            frame: 0,
+            in_flight: VecDeque::new(),
+            history: Vec::new(),
+            latest: None,
+            skipped: 0,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.readback.is_some()
+    }
+
+    pub fn readback(&self) -> Option<&R> {
+        self.readback.as_ref()
+    }
+
+    /// Frames left untimed because every slot was still in flight
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Frame number of the most recent result
+    pub fn latest_frame(&self) -> Option<u64> {
+        self.latest.as_ref().map(|(frame, _)| *frame)
+    }
+
+    pub fn begin_frame(&mut self) -> Option<FrameQueries> {
+        self.readback.as_ref()?;
+        let slot = self.next_slot;
+        if self.slots[slot] != Slot::Free {
+            self.skipped += 1;
+            return None;
+        }
+        self.slots[slot] = Slot::Recording(Vec::new());
+        self.next_slot = (slot + 1) % RING_SIZE;
+        Some(FrameQueries { slot })
+    }
+
+    /// Claims the query pair for the next pass of this frame, or None once
+    /// MAX_PASSES are in use
+    pub fn pass(&mut self, frame: &FrameQueries, name: &str) -> Option<PassQueries> {
+        let Slot::Recording(passes) = &mut self.slots[frame.slot] else {
+            return None;
+        };
+        if passes.len() >= MAX_PASSES {
+            return None;
+        }
+        let index = (frame.slot * MAX_PASSES + passes.len()) as u32 * 2;
+        passes.push(name.to_string());
+        Some(PassQueries {
+            begin: index,
+            end: index + 1,
+        })
+    }
+
+    /// Passes recorded so far, to size the query resolve
+    pub fn pass_count(&self, frame: &FrameQueries) -> usize {
+        match &self.slots[frame.slot] {
+            Slot::Recording(passes) => passes.len(),
+            _ => 0,
+        }
+    }
+
+    /// Call after the frame's command buffer was submitted
+    pub fn end_frame(&mut self, frame: FrameQueries) {
+        let Slot::Recording(passes) = std::mem::replace(&mut self.slots[frame.slot], Slot::Free)
+        else {
+            return;
+        };
+        if passes.is_empty() {
+            return;
+        }
+        self.frame += 1;
+        self.slots[frame.slot] = Slot::InFlight {
+            frame: self.frame,
+            passes,
+        };
+        self.in_flight.push_back(frame.slot);
+        if let Some(readback) = &mut self.readback {
+            readback.request(frame.slot);
+        }
+    }
+
+    /// Collects finished readbacks without waiting. Stops at the oldest
+    /// slot that is not ready so results never arrive out of order.
+    pub fn poll(&mut self) {
+        let Some(readback) = &mut self.readback else {
+            return;
+        };
+        let period = readback.period() as f64;
+        while let Some(&slot) = self.in_flight.front() {
+            let Some(ticks) = readback.try_read(slot) else {
+                break;
+            };
+            self.in_flight.pop_front();
+            let Slot::InFlight { frame, passes } =
+                std::mem::replace(&mut self.slots[slot], Slot::Free)
+            else {
+                continue;
+            };
+            let durations: Vec<(String, Duration)> = passes
+                .into_iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    let begin = ticks.get(i * 2).copied().unwrap_or(0);
+                    let end = ticks.get(i * 2 + 1).copied().unwrap_or(begin);
+                    let nanos = end.saturating_sub(begin) as f64 * period;
+                    (name, Duration::from_nanos(nanos as u64))
+                })
+                .collect();
+            for (name, duration) in &durations {
+                let index = match self.history.iter().position(|(n, _)| n == name) {
+                    Some(index) => index,
+                    None => {
+                        self.history.push((name.clone(), VecDeque::new()));
+                        self.history.len() - 1
+                    }
+                };
+                let window = &mut self.history[index].1;
+                if window.len() == AVERAGE_WINDOW {
+                    window.pop_front();
+                }
+                window.push_back(*duration);
+            }
+            self.latest = Some((frame, durations));
+        }
+    }
+
+    pub fn stats(&self) -> GpuStats {
+        if self.readback.is_none() {
+            return GpuStats::Unavailable;
+        }
+        let Some((_, durations)) = &self.latest else {
+            return GpuStats::Pending;
+        };
+        GpuStats::Ready(
+            durations
+                .iter()
+                .map(|(name, last)| {
+                    let average = self
+                        .history
+                        .iter()
+                        .find(|(n, _)| n == name)
+                        .map(|(_, w)| w.iter().sum::<Duration>() / w.len().max(1) as u32)
+                        .unwrap_or(*last);
+                    PassTiming {
+                        name: name.clone(),
+                        last: *last,
+                        average,
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+/// wgpu query set, resolve buffer and one mappable buffer per ring slot
+pub struct WgpuTimestamps {
+    query_set: wgpu::QuerySet,
+    resolve: wgpu::Buffer,
+    readback: Vec<wgpu::Buffer>,
+    mapped: Vec<Arc<AtomicBool>>,
+    period: f32,
+}
+
+const SLOT_BYTES: u64 = (MAX_PASSES * 2 * std::mem::size_of::<u64>()) as u64;
+
+impl WgpuTimestamps {
+    /// None when the device was created without TIMESTAMP_QUERY
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Frame Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: (RING_SIZE * MAX_PASSES * 2) as u32,
+        });
+        let resolve = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Resolve"),
+            size: SLOT_BYTES * RING_SIZE as u64,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = (0..RING_SIZE)
+            .map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Timestamp Readback"),
+                    size: SLOT_BYTES,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+        Some(Self {
+            query_set,
+            resolve,
+            readback,
+            mapped: (0..RING_SIZE)
+                .map(|_| Arc::new(AtomicBool::new(false)))
+                .collect(),
+            period: queue.get_timestamp_period(),
+        })
+    }
+
+    pub fn pass_writes(&self, pass: PassQueries) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(pass.begin),
+            end_of_pass_write_index: Some(pass.end),
+        }
+    }
+
+    /// Encodes the resolve and the copy into the slot's readback buffer;
+    /// call after the frame's last timed pass, before submitting
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder, slot: usize, passes: usize) {
+        if passes == 0 {
+            return;
+        }
+        let first = (slot * MAX_PASSES * 2) as u32;
+        let offset = SLOT_BYTES * slot as u64;
+        let bytes = (passes * 2 * std::mem::size_of::<u64>()) as u64;
+        encoder.resolve_query_set(
+            &self.query_set,
+            first..first + passes as u32 * 2,
+            &self.resolve,
+            offset,
+        );
+        encoder.copy_buffer_to_buffer(&self.resolve, offset, &self.readback[slot], 0, bytes);
+    }
+}
+
+impl TimestampReadback for WgpuTimestamps {
+    fn period(&self) -> f32 {
+        self.period
+    }
+
+    fn request(&mut self, slot: usize) {
+        let mapped = self.mapped[slot].clone();
+        self.readback[slot]
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+    }
+
+    fn try_read(&mut self, slot: usize) -> Option<Vec<u64>> {
+        if !self.mapped[slot].swap(false, Ordering::Acquire) {
+            return None;
+        }
+        let buffer = &self.readback[slot];
+        let ticks = {
+            let data = buffer.slice(..).get_mapped_range();
+            data.chunks_exact(8)
+                .map(|c| u64::from_le_bytes(c.try_into().unwrap_or([0; 8])))
+                .collect()
+        };
+        buffer.unmap();
+        Some(ticks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Readbacks complete only when the test says so
+    #[derive(Default)]
+    struct MockReadback {
+        requested: Vec<usize>,
+        ready: HashMap<usize, Vec<u64>>,
+    }
+
+    impl TimestampReadback for MockReadback {
+        fn period(&self) -> f32 {
+            2.0
+        }
+
+        fn request(&mut self, slot: usize) {
+            self.requested.push(slot);
+        }
+
+        fn try_read(&mut self, slot: usize) -> Option<Vec<u64>> {
+            self.ready.remove(&slot)
+        }
+    }
+
+    fn timed_frame(timer: &mut GpuTimer<MockReadback>, passes: &[&str]) -> Option<usize> {
+        let frame = timer.begin_frame()?;
+        for name in passes {
+            timer.pass(&frame, name);
+        }
+        let slot = frame.slot();
+        timer.end_frame(frame);
+        Some(slot)
+    }
+
+    #[test]
+    fn test_ring_skips_frames_instead_of_stalling() {
+        let mut timer = GpuTimer::new(Some(MockReadback::default()));
+        let frame = timer.begin_frame().unwrap();
+        assert_eq!(
+            timer.pass(&frame, "render"),
+            Some(PassQueries { begin: 0, end: 1 })
+        );
+        assert_eq!(
+            timer.pass(&frame, "blur"),
+            Some(PassQueries { begin: 2, end: 3 })
+        );
+        timer.end_frame(frame);
+        assert_eq!(timed_frame(&mut timer, &["render"]), Some(1));
+        assert_eq!(timed_frame(&mut timer, &["render"]), Some(2));
+
+        // Every slot waits on a readback: the frame goes untimed
+        assert!(timer.begin_frame().is_none());
+        assert_eq!(timer.skipped_frames(), 1);
+        assert_eq!(timer.readback.as_ref().unwrap().requested, vec![0, 1, 2]);
+
+        // Slot 1 finishing first publishes nothing until slot 0 has
+        timer
+            .readback
+            .as_mut()
+            .unwrap()
+            .ready
+            .insert(1, vec![0, 500]);
+        timer.poll();
+        assert_eq!(timer.stats(), GpuStats::Pending);
+        assert!(timer.begin_frame().is_none());
+
+        timer
+            .readback
+            .as_mut()
+            .unwrap()
+            .ready
+            .insert(0, vec![100, 600, 600, 700]);
+        timer.poll();
+        assert_eq!(timer.latest_frame(), Some(2));
+        let GpuStats::Ready(passes) = timer.stats() else {
+            panic!("expected timings");
+        };
+        assert_eq!(passes.len(), 1);
+        assert_eq!(passes[0].last, Duration::from_nanos(1000));
+        // Average over frames 1 and 2 at 2ns per tick
+        assert_eq!(passes[0].average, Duration::from_nanos(1000));
+        assert_eq!(timer.begin_frame().map(|f| f.slot()), Some(0));
+    }
+
+    #[test]
+    fn test_ticks_convert_with_period_and_average() {
+        let mut timer = GpuTimer::new(Some(MockReadback::default()));
+        for (i, ticks) in [1000u64, 2000, 3000].iter().enumerate() {
+            let slot = timed_frame(&mut timer, &["render", "post"]).unwrap();
+            assert_eq!(slot, i);
+            timer
+                .readback
+                .as_mut()
+                .unwrap()
+                .ready
+                .insert(slot, vec![0, *ticks, *ticks, *ticks + 50]);
+            timer.poll();
+        }
+        let stats = timer.stats();
+        let GpuStats::Ready(passes) = &stats else {
+            panic!("expected timings");
+        };
+        assert_eq!(passes[0].last, Duration::from_micros(6));
+        assert_eq!(passes[0].average, Duration::from_micros(4));
+        assert_eq!(passes[1].last, Duration::from_nanos(100));
+        assert_eq!(stats.total(), Some(Duration::from_nanos(6100)));
+        assert_eq!(
+            stats.to_string(),
+            "gpu: render 0.01 ms (avg 0.00 ms), post 0.00 ms (avg 0.00 ms)"
+        );
+        assert_eq!(stats.to_json()["render"], 6);
+    }
+
+    #[test]
+    fn test_unsupported_reports_unavailable() {
+        let mut timer: GpuTimer<MockReadback> = GpuTimer::new(None);
+        assert!(!timer.is_available());
+        assert!(timer.begin_frame().is_none());
+        timer.poll();
+        assert_eq!(timer.skipped_frames(), 0);
+        assert_eq!(timer.stats(), GpuStats::Unavailable);
+        assert_eq!(timer.stats().total(), None);
+        assert_eq!(
+            timer.stats().to_string(),
+            "gpu: unavailable (no timestamp query support)"
+        );
+        assert!(timer.stats().to_json().is_null());
+    }
+}
@@ -0,0 +1,914 @@
+use crate::terminal::{TerminalCell, TerminalState};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GridDeltaError {
+    #[error("Invalid frame: {0}")]
+    InvalidFrame(String),
+    #[error("Unsupported frame version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Checksum mismatch: expected {expected:#010x}, got {actual:#010x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+    #[error("Keyframe required (last applied seq {last:?}, got {got})")]
+    KeyframeRequired { last: Option<u64>, got: u64 },
+}
+
+pub const FRAME_MAGIC: u32 = 0x4654_4744; // "FTGD"
+pub const FRAME_VERSION: u8 = 1;
+pub const FRAME_HEADER_LEN: usize = 24;
+pub const DEFAULT_KEYFRAME_INTERVAL: u32 = 120;
+
+/// Encoded size of a single cell: char + fg + bg + attribute flags
+const CELL_ENCODED_LEN: usize = 4 + 16 + 16 + 1;
+
+/// Largest grid a frame may describe, far beyond any real window; a bigger
+/// size is a corrupt or hostile frame and must not drive an allocation
+pub const MAX_GRID_CELLS: usize = 1 << 22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameKind {
+    Keyframe = 0,
+    Delta = 1,
+}
+
+/// A plain copy of the visible grid, used both as an encoder's shadow and a
+/// decoder's reconstruction target.
+#[derive(Debug, Clone)]
+pub struct GridSnapshot {
+    pub width: u32,
+    pub height: u32,
+    pub cells: Vec<TerminalCell>,
+    pub cursor_x: u32,
+    pub cursor_y: u32,
+    pub cursor_visible: bool,
+}
+
+impl GridSnapshot {
+    pub fn blank(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![TerminalCell::default(); width as usize * height as usize],
+            cursor_x: 0,
+            cursor_y: 0,
+            cursor_visible: true,
+        }
+    }
+
+    pub fn from_state(state: &TerminalState) -> Self {
+        Self {
+            width: state.width,
+            height: state.height,
+            cells: state.cells.clone(),
+            cursor_x: state.cursor_x,
+            cursor_y: state.cursor_y,
+            cursor_visible: state.cursor_visible,
+        }
+    }
+
+    /// Compare against a live terminal state, ignoring dirty flags
+    pub fn matches(&self, state: &TerminalState) -> bool {
+        self.width == state.width
+            && self.height == state.height
+            && self.cursor_x == state.cursor_x
+            && self.cursor_y == state.cursor_y
+            && self.cursor_visible == state.cursor_visible
+            && self.cells.len() == state.cells.len()
+            && self
+                .cells
+                .iter()
+                .zip(&state.cells)
+                .all(|(a, b)| cells_equal(a, b))
+    }
+
+    fn row(&self, y: u32) -> &[TerminalCell] {
+        let start = y as usize * self.width as usize;
+        &self.cells[start..start + self.width as usize]
+    }
+}
+
+/// Cell equality on visible content only; the dirty flag is render bookkeeping
+pub fn cells_equal(a: &TerminalCell, b: &TerminalCell) -> bool {
+    a.character == b.character
+        && colors_equal(&a.foreground, &b.foreground)
+        && colors_equal(&a.background, &b.background)
+        && cell_flags(a) == cell_flags(b)
+}
+
+fn colors_equal(a: &[f32; 4], b: &[f32; 4]) -> bool {
+    a.iter().zip(b).all(|(x, y)| x.to_bits() == y.to_bits())
+}
+
+fn cell_flags(cell: &TerminalCell) -> u8 {
+    (cell.bold as u8)
+        | (cell.italic as u8) << 1
+        | (cell.underline as u8) << 2
+        | (cell.strikethrough as u8) << 3
+        | (cell.dim as u8) << 4
+        | (cell.reverse as u8) << 5
+        | (cell.blink as u8) << 6
+        | (cell.wide as u8) << 7
+}
+
+/// A run of changed cells within a single row
+#[derive(Debug, Clone)]
+pub struct CellRun {
+    pub row: u32,
+    pub col_start: u32,
+    pub cells: Vec<TerminalCell>,
+}
+
+/// Per-consumer delta encoder. Each reader owns one so its shadow grid
+/// tracks exactly what that reader has seen.
+pub struct DeltaEncoder {
+    shadow: Option<GridSnapshot>,
+    seq: u64,
+    deltas_since_keyframe: u32,
+    keyframe_interval: u32,
+    keyframe_requested: bool,
+}
+
+impl DeltaEncoder {
+    pub fn new(keyframe_interval: u32) -> Self {
+        Self {
+            shadow: None,
+            seq: 0,
+            deltas_since_keyframe: 0,
+            keyframe_interval: keyframe_interval.max(1),
+            keyframe_requested: false,
+        }
+    }
+
+    /// Force the next frame to be a full keyframe (new or lagging reader)
+    pub fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Encode the state by comparing every row against the shadow grid
+    pub fn encode(&mut self, state: &TerminalState) -> Vec<u8> {
+        self.encode_rows(state, |_| true)
+    }
+
+    /// Encode using the grid's dirty flags to skip untouched rows. Only valid
+    /// for a consumer that also owns clearing the dirty flags afterwards.
+    pub fn encode_dirty(&mut self, state: &TerminalState) -> Vec<u8> {
+        let width = state.width as usize;
+        self.encode_rows(state, |y| {
+            let start = y as usize * width;
+            state.cells[start..start + width].iter().any(|c| c.dirty)
+        })
+    }
+
+    fn encode_rows<F: Fn(u32) -> bool>(
+        &mut self,
+        state: &TerminalState,
+        row_may_differ: F,
+    ) -> Vec<u8> {
+        self.seq += 1;
+
+        let needs_keyframe = self.keyframe_requested
+            || self.shadow.is_none()
+            || self.deltas_since_keyframe >= self.keyframe_interval;
+
+        if needs_keyframe {
+            let snapshot = GridSnapshot::from_state(state);
+            let frame = encode_keyframe(self.seq, &snapshot);
+            self.shadow = Some(snapshot);
+            self.deltas_since_keyframe = 0;
+            self.keyframe_requested = false;
+            return frame;
+        }
+
+        let shadow = self.shadow.as_mut().expect("shadow present after keyframe");
+        let resized = shadow.width != state.width || shadow.height != state.height;
+        if resized {
+            // Both sides restart from a blank grid of the new size
+            *shadow = GridSnapshot::blank(state.width, state.height);
+        }
+
+        let mut runs = Vec::new();
+        for y in 0..state.height {
+            if !resized && !row_may_differ(y) {
+                continue;
+            }
+            let start = y as usize * state.width as usize;
+            let live = &state.cells[start..start + state.width as usize];
+            collect_row_runs(y, shadow.row(y), live, &mut runs);
+        }
+
+        for run in &runs {
+            let start = run.row as usize * shadow.width as usize + run.col_start as usize;
+            shadow.cells[start..start + run.cells.len()].clone_from_slice(&run.cells);
+        }
+
+        let cursor = if shadow.cursor_x != state.cursor_x
+            || shadow.cursor_y != state.cursor_y
+            || shadow.cursor_visible != state.cursor_visible
+        {
+            shadow.cursor_x = state.cursor_x;
+            shadow.cursor_y = state.cursor_y;
+            shadow.cursor_visible = state.cursor_visible;
+            Some((state.cursor_x, state.cursor_y, state.cursor_visible))
+        } else {
+            None
+        };
+
+        let size = resized.then_some((state.width, state.height));
+        self.deltas_since_keyframe += 1;
+        encode_delta(self.seq, size, cursor, &runs)
+    }
+}
+
+impl Default for DeltaEncoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_KEYFRAME_INTERVAL)
+    }
+}
+
+fn collect_row_runs(row: u32, old: &[TerminalCell], new: &[TerminalCell], runs: &mut Vec<CellRun>) {
+    let mut x = 0;
+    while x < new.len() {
+        if cells_equal(&old[x], &new[x]) {
+            x += 1;
+            continue;
+        }
+        let start = x;
+        // Absorb short unchanged gaps so a run header isn't spent on 1-2 cells
+        let mut end = x + 1;
+        let mut gap = 0;
+        while end < new.len() && gap <= 2 {
+            if cells_equal(&old[end], &new[end]) {
+                gap += 1;
+            } else {
+                gap = 0;
+            }
+            end += 1;
+        }
+        let end = end - gap;
+        runs.push(CellRun {
+            row,
+            col_start: start as u32,
+            cells: new[start..end].to_vec(),
+        });
+        x = end;
+    }
+}
+
+/// Decoder side: applies keyframes and deltas to a reconstructed grid
+#[derive(Default)]
+pub struct DeltaDecoder {
+    grid: Option<GridSnapshot>,
+    last_seq: Option<u64>,
+}
+
+impl DeltaDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn grid(&self) -> Option<&GridSnapshot> {
+        self.grid.as_ref()
+    }
+
+    pub fn last_seq(&self) -> Option<u64> {
+        self.last_seq
+    }
+
+    /// Apply one frame. A delta that does not directly follow the last applied
+    /// frame yields `KeyframeRequired`; the reader should ask for a keyframe.
+    pub fn apply(&mut self, frame: &[u8]) -> Result<FrameKind, GridDeltaError> {
+        let (kind, seq, payload) = decode_header(frame)?;
+        let mut reader = PayloadReader::new(payload);
+
+        match kind {
+            FrameKind::Keyframe => {
+                let width = reader.u32()?;
+                let height = reader.u32()?;
+                let cursor_x = reader.u32()?;
+                let cursor_y = reader.u32()?;
+                let cursor_visible = reader.u8()? != 0;
+                let count = grid_cell_count(width, height)?;
+                if count.saturating_mul(CELL_ENCODED_LEN) > reader.remaining() {
+                    return Err(GridDeltaError::InvalidFrame(format!(
+                        "keyframe for {}x{} grid holds only {} payload bytes",
+                        width,
+                        height,
+                        reader.remaining()
+                    )));
+                }
+                let mut cells = Vec::with_capacity(count);
+                for _ in 0..count {
+                    cells.push(reader.cell()?);
+                }
+                self.grid = Some(GridSnapshot {
+                    width,
+                    height,
+                    cells,
+                    cursor_x,
+                    cursor_y,
+                    cursor_visible,
+                });
+            }
+            FrameKind::Delta => {
+                if self.last_seq.map(|s| s + 1) != Some(seq) {
+                    return Err(GridDeltaError::KeyframeRequired {
+                        last: self.last_seq,
+                        got: seq,
+                    });
+                }
+                let grid = self.grid.as_mut().ok_or(GridDeltaError::KeyframeRequired {
+                    last: self.last_seq,
+                    got: seq,
+                })?;
+
+                let flags = reader.u8()?;
+                if flags & DELTA_FLAG_SIZE != 0 {
+                    let width = reader.u32()?;
+                    let height = reader.u32()?;
+                    grid_cell_count(width, height)?;
+                    *grid = GridSnapshot::blank(width, height);
+                }
+                if flags & DELTA_FLAG_CURSOR != 0 {
+                    grid.cursor_x = reader.u32()?;
+                    grid.cursor_y = reader.u32()?;
+                    grid.cursor_visible = reader.u8()? != 0;
+                }
+
+                let run_count = reader.u32()?;
+                for _ in 0..run_count {
+                    let row = reader.u32()?;
+                    let col_start = reader.u32()?;
+                    let len = reader.u32()?;
+                    if row >= grid.height || col_start.saturating_add(len) > grid.width {
+                        return Err(GridDeltaError::InvalidFrame(format!(
+                            "run {}:{}+{} outside {}x{} grid",
+                            row, col_start, len, grid.width, grid.height
+                        )));
+                    }
+                    let start = (row as usize)
+                        .checked_mul(grid.width as usize)
+                        .and_then(|offset| offset.checked_add(col_start as usize))
+                        .ok_or_else(|| {
+                            GridDeltaError::InvalidFrame(format!(
+                                "run {}:{} overflows",
+                                row, col_start
+                            ))
+                        })?;
+                    for i in 0..len as usize {
+                        grid.cells[start + i] = reader.cell()?;
+                    }
+                }
+            }
+        }
+
+        self.last_seq = Some(seq);
+        Ok(kind)
+    }
+}
+
+/// Cells in a `width` x `height` grid, refusing sizes over `MAX_GRID_CELLS`
+fn grid_cell_count(width: u32, height: u32) -> Result<usize, GridDeltaError> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .filter(|&count| count <= MAX_GRID_CELLS)
+        .ok_or_else(|| GridDeltaError::InvalidFrame(format!("{}x{} grid too large", width, height)))
+}
+
+const DELTA_FLAG_SIZE: u8 = 0x01;
+const DELTA_FLAG_CURSOR: u8 = 0x02;
+
+/// Frame layout (little endian):
+/// magic u32 | version u8 | kind u8 | reserved u16 | seq u64 | payload_len u32 | crc32 u32 | payload
+fn write_header(out: &mut Vec<u8>, kind: FrameKind, seq: u64, payload: &[u8]) {
+    out.extend_from_slice(&FRAME_MAGIC.to_le_bytes());
+    out.push(FRAME_VERSION);
+    out.push(kind as u8);
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&seq.to_le_bytes());
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+}
+
+fn finish_frame(kind: FrameKind, seq: u64, payload: Vec<u8>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    write_header(&mut frame, kind, seq, &payload);
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+pub fn encode_keyframe(seq: u64, grid: &GridSnapshot) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(17 + grid.cells.len() * CELL_ENCODED_LEN);
+    payload.extend_from_slice(&grid.width.to_le_bytes());
+    payload.extend_from_slice(&grid.height.to_le_bytes());
+    payload.extend_from_slice(&grid.cursor_x.to_le_bytes());
+    payload.extend_from_slice(&grid.cursor_y.to_le_bytes());
+    payload.push(grid.cursor_visible as u8);
+    for cell in &grid.cells {
+        write_cell(&mut payload, cell);
+    }
+    finish_frame(FrameKind::Keyframe, seq, payload)
+}
+
+pub fn encode_delta(
+    seq: u64,
+    size: Option<(u32, u32)>,
+    cursor: Option<(u32, u32, bool)>,
+    runs: &[CellRun],
+) -> Vec<u8> {
+    let cells: usize = runs.iter().map(|r| r.cells.len()).sum();
+    let mut payload = Vec::with_capacity(32 + runs.len() * 12 + cells * CELL_ENCODED_LEN);
+
+    let mut flags = 0u8;
+    if size.is_some() {
+        flags |= DELTA_FLAG_SIZE;
+    }
+    if cursor.is_some() {
+        flags |= DELTA_FLAG_CURSOR;
+    }
+    payload.push(flags);
+    if let Some((width, height)) = size {
+        payload.extend_from_slice(&width.to_le_bytes());
+        payload.extend_from_slice(&height.to_le_bytes());
+    }
+    if let Some((x, y, visible)) = cursor {
+        payload.extend_from_slice(&x.to_le_bytes());
+        payload.extend_from_slice(&y.to_le_bytes());
+        payload.push(visible as u8);
+    }
+
+    payload.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+    for run in runs {
+        payload.extend_from_slice(&run.row.to_le_bytes());
+        payload.extend_from_slice(&run.col_start.to_le_bytes());
+        payload.extend_from_slice(&(run.cells.len() as u32).to_le_bytes());
+        for cell in &run.cells {
+            write_cell(&mut payload, cell);
+        }
+    }
+    finish_frame(FrameKind::Delta, seq, payload)
+}
+
+/// Validate a frame header and return its kind, sequence number and payload
+pub fn decode_header(frame: &[u8]) -> Result<(FrameKind, u64, &[u8]), GridDeltaError> {
+    if frame.len() < FRAME_HEADER_LEN {
+        return Err(GridDeltaError::InvalidFrame(format!(
+            "short frame: {} bytes",
+            frame.len()
+        )));
+    }
+    let magic = u32::from_le_bytes(frame[0..4].try_into().unwrap());
+    if magic != FRAME_MAGIC {
+        return Err(GridDeltaError::InvalidFrame(format!(
+            "bad magic {:#010x}",
+            magic
+        )));
+    }
+    if frame[4] != FRAME_VERSION {
+        return Err(GridDeltaError::UnsupportedVersion(frame[4]));
+    }
+    let kind = match frame[5] {
+        0 => FrameKind::Keyframe,
+        1 => FrameKind::Delta,
+        other => {
+            return Err(GridDeltaError::InvalidFrame(format!(
+                "unknown frame kind {}",
+                other
+            )));
+        }
+    };
+    let seq = u64::from_le_bytes(frame[8..16].try_into().unwrap());
+    let len = u32::from_le_bytes(frame[16..20].try_into().unwrap()) as usize;
+    let expected = u32::from_le_bytes(frame[20..24].try_into().unwrap());
+
+    let payload = frame
+        .get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len)
+        .ok_or_else(|| GridDeltaError::InvalidFrame("truncated payload".to_string()))?;
+    let actual = crc32fast::hash(payload);
+    if actual != expected {
+        return Err(GridDeltaError::ChecksumMismatch { expected, actual });
+    }
+    Ok((kind, seq, payload))
+}
+
+fn write_cell(out: &mut Vec<u8>, cell: &TerminalCell) {
+    out.extend_from_slice(&(cell.character as u32).to_le_bytes());
+    for component in cell.foreground.iter().chain(cell.background.iter()) {
+        out.extend_from_slice(&component.to_bits().to_le_bytes());
+    }
+    out.push(cell_flags(cell));
+}
+
+struct PayloadReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], GridDeltaError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(n))
+            .ok_or_else(|| GridDeltaError::InvalidFrame("payload underrun".to_string()))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, GridDeltaError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, GridDeltaError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, GridDeltaError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn cell(&mut self) -> Result<TerminalCell, GridDeltaError> {
+        let code = self.u32()?;
+        let character = char::from_u32(code)
+            .ok_or_else(|| GridDeltaError::InvalidFrame(format!("invalid char {:#x}", code)))?;
+        let mut foreground = [0.0; 4];
+        for c in &mut foreground {
+            *c = self.f32()?;
+        }
+        let mut background = [0.0; 4];
+        for c in &mut background {
+            *c = self.f32()?;
+        }
+        let flags = self.u8()?;
+        Ok(TerminalCell {
+            character,
+            foreground,
+            background,
+            bold: flags & 0x01 != 0,
+            italic: flags & 0x02 != 0,
+            underline: flags & 0x04 != 0,
+            strikethrough: flags & 0x08 != 0,
+            dim: flags & 0x10 != 0,
+            reverse: flags & 0x20 != 0,
+            blink: flags & 0x40 != 0,
+            wide: flags & 0x80 != 0,
+            dirty: true,
+            link: None,
+        })
+    }
+}
+
+/// Write a frame to a stream socket as a u32 length prefix followed by the frame
+pub fn write_length_prefixed<W: Write>(writer: &mut W, frame: &[u8]) -> io::Result<()> {
+    writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+    writer.write_all(frame)
+}
+
+/// Read one length-prefixed frame; returns `Ok(None)` on clean EOF
+pub fn read_length_prefixed<R: Read>(
+    reader: &mut R,
+    max_len: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    match reader.read_exact(&mut len_buf) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_le_bytes(len_buf) as usize;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds limit {}", len, max_len),
+        ));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+/// Keyframe slot followed by a bounded ring of delta records, serialized as
+/// one buffer; readers that fall behind the ring resynchronize from the
+/// keyframe. Deltas that rotate out of the ring are folded into the keyframe
+/// so it always stays reconstructible.
+pub struct DeltaRing {
+    base: DeltaDecoder,
+    deltas: VecDeque<(u64, Vec<u8>)>,
+    capacity: usize,
+}
+
+impl DeltaRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            base: DeltaDecoder::new(),
+            deltas: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn push(&mut self, frame: Vec<u8>) -> Result<(), GridDeltaError> {
+        let (kind, seq, _) = decode_header(&frame)?;
+        match kind {
+            FrameKind::Keyframe => {
+                self.base = DeltaDecoder::new();
+                self.base.apply(&frame)?;
+                self.deltas.clear();
+            }
+            FrameKind::Delta => {
+                if self.base.grid().is_none() {
+                    return Err(GridDeltaError::KeyframeRequired {
+                        last: None,
+                        got: seq,
+                    });
+                }
+                if self.deltas.len() >= self.capacity
+                    && let Some((_, oldest)) = self.deltas.pop_front()
+                {
+                    self.base.apply(&oldest)?;
+                }
+                self.deltas.push_back((seq, frame));
+            }
+        }
+        Ok(())
+    }
+
+    /// Frames a reader needs to catch up from `last_seq`. If the reader's
+    /// position has already rotated out of the ring, a keyframe is included.
+    pub fn frames_since(&self, last_seq: Option<u64>) -> Vec<Vec<u8>> {
+        let (Some(grid), Some(base_seq)) = (self.base.grid(), self.base.last_seq()) else {
+            return Vec::new();
+        };
+
+        let resync = match last_seq {
+            None => true,
+            Some(seq) => seq < base_seq,
+        };
+
+        let mut frames = Vec::new();
+        if resync {
+            frames.push(encode_keyframe(base_seq, grid));
+        }
+        let after = last_seq.filter(|_| !resync).unwrap_or(base_seq);
+        frames.extend(
+            self.deltas
+                .iter()
+                .filter(|(s, _)| *s > after)
+                .map(|(_, f)| f.clone()),
+        );
+        frames
+    }
+
+    /// Serialize as: count u32, then each frame length-prefixed, keyframe first
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let frames = self.frames_since(None);
+        let mut out = Vec::new();
+        out.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+        for frame in frames {
+            out.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            out.extend_from_slice(&frame);
+        }
+        out
+    }
+
+    pub fn from_bytes(data: &[u8], capacity: usize) -> Result<Self, GridDeltaError> {
+        let mut ring = Self::new(capacity);
+        let mut reader = PayloadReader::new(data);
+        let count = reader.u32()?;
+        for _ in 0..count {
+            let len = reader.u32()? as usize;
+            ring.push(reader.take(len)?.to_vec())?;
+        }
+        Ok(ring)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small xorshift generator so the randomized tests are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    fn random_mutation(state: &mut TerminalState, rng: &mut Rng) {
+        match rng.below(10) {
+            0 => {
+                let width = 10 + rng.below(30) as u32;
+                let height = 5 + rng.below(15) as u32;
+                state.resize(width, height);
+            }
+            1 => state.feed_bytes(b"\x1b[2J"),
+            2 => state.feed_bytes(b"\x1b[1;31mred\x1b[0m"),
+            3 => state.feed_bytes(b"\r\n"),
+            _ => {
+                let len = 1 + rng.below(12);
+                let text: Vec<u8> = (0..len).map(|_| b'a' + rng.below(26) as u8).collect();
+                state.feed_bytes(&text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_keyframe_roundtrip() {
+        let mut state = TerminalState::new(20, 5);
+        state.feed_bytes(b"hello \x1b[1mworld");
+
+        let mut encoder = DeltaEncoder::default();
+        let mut decoder = DeltaDecoder::new();
+        let frame = encoder.encode(&state);
+
+        assert_eq!(decoder.apply(&frame).unwrap(), FrameKind::Keyframe);
+        assert!(decoder.grid().unwrap().matches(&state));
+    }
+
+    #[test]
+    fn test_keyframe_plus_deltas_reconstructs_grid() {
+        for seed in 1..=20u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let mut state = TerminalState::new(24, 8);
+            let mut encoder = DeltaEncoder::new(16);
+            let mut decoder = DeltaDecoder::new();
+
+            for step in 0..200 {
+                random_mutation(&mut state, &mut rng);
+                let frame = encoder.encode(&state);
+                decoder.apply(&frame).unwrap();
+                assert!(
+                    decoder.grid().unwrap().matches(&state),
+                    "seed {} diverged at step {}",
+                    seed,
+                    step
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_dirty_tracking_encoder_reconstructs_grid() {
+        let mut rng = Rng(0xDEAD_BEEF);
+        let mut state = TerminalState::new(30, 10);
+        let mut encoder = DeltaEncoder::new(32);
+        let mut decoder = DeltaDecoder::new();
+
+        for _ in 0..300 {
+            random_mutation(&mut state, &mut rng);
+            let frame = encoder.encode_dirty(&state);
+            state.clear_dirty_flags();
+            decoder.apply(&frame).unwrap();
+            assert!(decoder.grid().unwrap().matches(&state));
+        }
+    }
+
+    #[test]
+    fn test_late_reader_resyncs_from_ring() {
+        let mut rng = Rng(42);
+        let mut state = TerminalState::new(20, 6);
+        let mut encoder = DeltaEncoder::new(12);
+        let mut ring = DeltaRing::new(4);
+
+        for _ in 0..30 {
+            random_mutation(&mut state, &mut rng);
+            ring.push(encoder.encode(&state)).unwrap();
+        }
+
+        let restored = DeltaRing::from_bytes(&ring.to_bytes(), 4).unwrap();
+        let mut decoder = DeltaDecoder::new();
+        for frame in restored.frames_since(None) {
+            decoder.apply(&frame).unwrap();
+        }
+        assert!(decoder.grid().unwrap().matches(&state));
+    }
+
+    #[test]
+    fn test_gap_requires_keyframe() {
+        let mut state = TerminalState::new(10, 3);
+        let mut encoder = DeltaEncoder::default();
+        let mut decoder = DeltaDecoder::new();
+
+        decoder.apply(&encoder.encode(&state)).unwrap();
+        state.feed_bytes(b"a");
+        let _skipped = encoder.encode(&state);
+        state.feed_bytes(b"b");
+        let frame = encoder.encode(&state);
+
+        assert!(matches!(
+            decoder.apply(&frame),
+            Err(GridDeltaError::KeyframeRequired { .. })
+        ));
+
+        encoder.request_keyframe();
+        assert_eq!(
+            decoder.apply(&encoder.encode(&state)).unwrap(),
+            FrameKind::Keyframe
+        );
+        assert!(decoder.grid().unwrap().matches(&state));
+    }
+
+    #[test]
+    fn test_corrupt_frame_rejected() {
+        let state = TerminalState::new(10, 3);
+        let mut frame = DeltaEncoder::default().encode(&state);
+        let last = frame.len() - 1;
+        frame[last] ^= 0xFF;
+        assert!(matches!(
+            DeltaDecoder::new().apply(&frame),
+            Err(GridDeltaError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_oversized_grid_rejected() {
+        let mut keyframe = Vec::new();
+        for value in [u32::MAX, u32::MAX, 0, 0] {
+            keyframe.extend_from_slice(&value.to_le_bytes());
+        }
+        keyframe.push(1);
+        assert!(matches!(
+            DeltaDecoder::new().apply(&finish_frame(FrameKind::Keyframe, 0, keyframe.clone())),
+            Err(GridDeltaError::InvalidFrame(_))
+        ));
+
+        // Within the cell limit but with no cells behind it
+        keyframe[..8].copy_from_slice(&[0x00, 0x08, 0, 0, 0x00, 0x08, 0, 0]);
+        assert!(matches!(
+            DeltaDecoder::new().apply(&finish_frame(FrameKind::Keyframe, 0, keyframe)),
+            Err(GridDeltaError::InvalidFrame(_))
+        ));
+
+        let mut decoder = DeltaDecoder::new();
+        decoder
+            .apply(&encode_keyframe(0, &GridSnapshot::blank(4, 2)))
+            .unwrap();
+        assert!(matches!(
+            decoder.apply(&encode_delta(1, Some((u32::MAX, u32::MAX)), None, &[])),
+            Err(GridDeltaError::InvalidFrame(_))
+        ));
+        assert!(decoder.grid().unwrap().matches(&TerminalState::new(4, 2)));
+    }
+
+    #[test]
+    fn test_length_prefixed_stream() {
+        let mut state = TerminalState::new(10, 3);
+        let mut encoder = DeltaEncoder::default();
+        let mut stream = Vec::new();
+        for text in [&b"ab"[..], b"cd", b"ef"] {
+            state.feed_bytes(text);
+            write_length_prefixed(&mut stream, &encoder.encode(&state)).unwrap();
+        }
+
+        let mut cursor = std::io::Cursor::new(stream);
+        let mut decoder = DeltaDecoder::new();
+        while let Some(frame) = read_length_prefixed(&mut cursor, 1 << 20).unwrap() {
+            decoder.apply(&frame).unwrap();
+        }
+        assert!(decoder.grid().unwrap().matches(&state));
+    }
+
+    #[test]
+    fn test_prompt_echo_bandwidth_reduction() {
+        let mut state = TerminalState::new(120, 40);
+        state.feed_bytes(b"user@host:~$ ");
+        let mut encoder = DeltaEncoder::new(DEFAULT_KEYFRAME_INTERVAL);
+        encoder.encode(&state);
+
+        let mut delta_bytes = 0usize;
+        let mut full_bytes = 0usize;
+        for &byte in b"cargo build --release --workspace" {
+            state.feed_bytes(&[byte]);
+            delta_bytes += encoder.encode(&state).len();
+            full_bytes += encode_keyframe(0, &GridSnapshot::from_state(&state)).len();
+        }
+
+        let reduction = 1.0 - delta_bytes as f64 / full_bytes as f64;
+        assert!(
+            reduction > 0.90,
+            "reduction was only {:.1}%",
+            reduction * 100.0
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::LazyLock;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum LinkError {
+    #[error("not opening {0}: only http, https, file and mailto links are opened")]
+    Scheme(String),
+    #[error("failed to start {command}: {source}")]
+    Launch {
+        command: &'static str,
+        source: std::io::Error,
+    },
+}
+
+/// Links kept for cells before the oldest are forgotten
+const MAX_LINKS: usize = 4096;
+
+static URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"https?://[^\s<>"'`]+"#).expect("url pattern"));
+
+/// URLs given to cells through OSC 8, by id
+#[derive(Debug, Default, Clone)]
+pub struct Hyperlinks {
+    ids: HashMap<String, u32>,
+    urls: BTreeMap<u32, String>,
+    next: u32,
+}
+
+impl Hyperlinks {
+    /// The id for `url`, reusing the one it already has
+    pub fn intern(&mut self, url: &str) -> u32 {
+        if let Some(&id) = self.ids.get(url) {
+            return id;
+        }
+        if self.urls.len() >= MAX_LINKS
+            && let Some((_, oldest)) = self.urls.pop_first()
+        {
+            self.ids.remove(&oldest);
+        }
+        let id = self.next;
+        self.next = self.next.wrapping_add(1);
+        self.ids.insert(url.to_string(), id);
+        self.urls.insert(id, url.to_string());
+        id
+    }
+
+    pub fn get(&self, id: u32) -> Option<&str> {
+        self.urls.get(&id).map(String::as_str)
+    }
+}
+
+/// A link on screen and every cell it covers, in reading order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScreenLink {
+    pub url: String,
+    /// `(x, y)` cells
+    pub cells: Vec<(u32, u32)>,
+}
+
+impl ScreenLink {
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.cells.contains(&(x, y))
+    }
+}
+
+/// Byte ranges of the http(s) URLs in `text`, without trailing punctuation
+/// or a closing bracket the URL didn't open
+pub fn detect_urls(text: &str) -> Vec<Range<usize>> {
+    URL.find_iter(text)
+        .map(|found| {
+            let mut url = found.as_str();
+            loop {
+                let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?']);
+                let trimmed = match trimmed.strip_suffix(')') {
+                    Some(inner) if trimmed.matches('(').count() < trimmed.matches(')').count() => {
+                        inner
+                    }
+                    _ => trimmed,
+                };
+                if trimmed.len() == url.len() {
+                    break;
+                }
+                url = trimmed;
+            }
+            found.start()..found.start() + url.len()
+        })
+        .collect()
+}
+
+/// Hand `url` to the desktop's opener
+pub fn open_url(url: &str) -> Result<(), LinkError> {
+    let scheme = url.split(':').next().unwrap_or("").to_ascii_lowercase();
+    if !matches!(scheme.as_str(), "http" | "https" | "file" | "mailto") {
+        return Err(LinkError::Scheme(url.to_string()));
+    }
+    let command = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    std::process::Command::new(command)
+        .arg(url)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map(drop)
+        .map_err(|source| LinkError::Launch { command, source })
+}
+
+/// `links` numbered from 1, one per line, for `open`
+pub fn format_list(links: &[ScreenLink]) -> String {
+    links
+        .iter()
+        .enumerate()
+        .map(|(i, link)| format!("{:>3}  {}\n", i + 1, link.url))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_urls_trims_punctuation() {
+        let text = "see https://example.com/a_(b). Or (https://x.org/path?q=1), or http://y.io!";
+        let urls: Vec<_> = detect_urls(text).into_iter().map(|r| &text[r]).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/a_(b)",
+                "https://x.org/path?q=1",
+                "http://y.io"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_intern_reuses_ids() {
+        let mut links = Hyperlinks::default();
+        let a = links.intern("https://a.example");
+        let b = links.intern("https://b.example");
+        assert_ne!(a, b);
+        assert_eq!(links.intern("https://a.example"), a);
+        assert_eq!(links.get(b), Some("https://b.example"));
+        assert!(matches!(
+            open_url("javascript:alert(1)"),
+            Err(LinkError::Scheme(_))
+        ));
+    }
+}
@@ -0,0 +1,321 @@
+use crate::config::UiConfig;
+use crate::input::Key;
+use std::time::{Duration, Instant};
+
+/// How a blanked window looks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlankStyle {
+    /// Theme background and the notice only
+    #[default]
+    Background,
+    /// Content kept but dimmed almost to the background
+    Dim,
+}
+
+impl BlankStyle {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "background" => Some(BlankStyle::Background),
+            "dim" => Some(BlankStyle::Dim),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdleLockConfig {
+    /// `None` disables the lock
+    pub idle: Option<Duration>,
+    /// Output also counts as activity, so a busy build never blanks
+    pub output_is_activity: bool,
+    pub style: BlankStyle,
+    /// Word to type before input resumes. This only guards against
+    /// keystrokes landing in a prompt by accident; it is stored in plain
+    /// text in the config and is not a security boundary.
+    pub unlock_word: Option<String>,
+    pub wake_on_output: bool,
+    pub wake_on_bell: bool,
+}
+
+impl Default for IdleLockConfig {
+    fn default() -> Self {
+        Self {
+            idle: None,
+            output_is_activity: false,
+            style: BlankStyle::Background,
+            unlock_word: None,
+            wake_on_output: false,
+            wake_on_bell: false,
+        }
+    }
+}
+
+impl IdleLockConfig {
+    pub fn from_config(ui: &UiConfig) -> Self {
+        Self {
+            idle: (ui.lock_after_secs > 0).then(|| Duration::from_secs(ui.lock_after_secs as u64)),
+            output_is_activity: ui.lock_count_output,
+            style: BlankStyle::parse(&ui.lock_style).unwrap_or_default(),
+            unlock_word: (!ui.lock_unlock_word.is_empty()).then(|| ui.lock_unlock_word.clone()),
+            wake_on_output: ui.lock_wake_on_output,
+            wake_on_bell: ui.lock_wake_on_bell,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockState {
+    Active,
+    /// Waiting for the wake keypress
+    Blanked,
+    /// Woken with an unlock word configured; still blank until it is typed
+    Unlocking {
+        typed: String,
+        failed: u32,
+    },
+}
+
+/// What to do with a key while the lock is engaged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOutcome {
+    /// Not locked: handle the key normally
+    Forward,
+    /// Swallowed by the lock, never sent to the PTY
+    Consumed,
+}
+
+/// Blanks the display after a period without input and swallows the keys
+/// that wake it
+#[derive(Debug)]
+pub struct IdleLock {
+    config: IdleLockConfig,
+    state: LockState,
+    last_activity: Instant,
+}
+
+impl IdleLock {
+    pub fn new(config: IdleLockConfig, now: Instant) -> Self {
+        Self {
+            config,
+            state: LockState::Active,
+            last_activity: now,
+        }
+    }
+
+    pub fn set_config(&mut self, config: IdleLockConfig) {
+        self.config = config;
+    }
+
+    pub fn state(&self) -> &LockState {
+        &self.state
+    }
+
+    pub fn style(&self) -> BlankStyle {
+        self.config.style
+    }
+
+    pub fn is_blanked(&self) -> bool {
+        self.state != LockState::Active
+    }
+
+    /// When the display blanks if nothing else happens. `None` while
+    /// disabled or already blank, so an active terminal schedules a single
+    /// wakeup rather than polling.
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.state {
+            LockState::Active => self.config.idle.map(|idle| self.last_activity + idle),
+            _ => None,
+        }
+    }
+
+    /// Blank once the idle period has passed. Returns true when it just did.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if self.deadline().is_some_and(|deadline| now >= deadline) {
+            self.state = LockState::Blanked;
+            return true;
+        }
+        false
+    }
+
+    /// Input that did not go through `key`, e.g. a paste or mouse click
+    pub fn note_input(&mut self, now: Instant) {
+        if self.state == LockState::Active {
+            self.last_activity = now;
+        }
+    }
+
+    /// PTY output arrived. Returns true when it woke the display.
+    pub fn note_output(&mut self, now: Instant) -> bool {
+        match self.state {
+            LockState::Active => {
+                if self.config.output_is_activity {
+                    self.last_activity = now;
+                }
+                false
+            }
+            _ if self.config.wake_on_output => self.wake(now),
+            _ => false,
+        }
+    }
+
+    /// The terminal rang the bell. Returns true when it woke the display.
+    pub fn note_bell(&mut self, now: Instant) -> bool {
+        if self.is_blanked() && self.config.wake_on_bell {
+            return self.wake(now);
+        }
+        false
+    }
+
+    /// A woken display still waits for the unlock word if one is set
+    fn wake(&mut self, now: Instant) -> bool {
+        if self.config.unlock_word.is_some() {
+            return false;
+        }
+        self.state = LockState::Active;
+        self.last_activity = now;
+        true
+    }
+
+    /// A key press. `None` is a key with no `Key` equivalent, such as a bare
+    /// modifier, which still wakes the display.
+    pub fn key(&mut self, key: Option<Key>, now: Instant) -> KeyOutcome {
+        let word = self.config.unlock_word.clone();
+        match (&mut self.state, word) {
+            (LockState::Active, _) => {
+                self.last_activity = now;
+                return KeyOutcome::Forward;
+            }
+            (LockState::Blanked, None) => {
+                self.state = LockState::Active;
+                self.last_activity = now;
+            }
+            (LockState::Blanked, Some(_)) => {
+                self.state = LockState::Unlocking {
+                    typed: String::new(),
+                    failed: 0,
+                };
+            }
+            (LockState::Unlocking { typed, failed }, Some(word)) => match key {
+                Some(Key::Enter) if *typed == word => {
+                    self.state = LockState::Active;
+                    self.last_activity = now;
+                }
+                Some(Key::Enter) => {
+                    typed.clear();
+                    *failed += 1;
+                }
+                Some(Key::Backspace) => {
+                    typed.pop();
+                }
+                Some(Key::Escape) => self.state = LockState::Blanked,
+                Some(Key::Char(c)) => typed.push(c),
+                Some(Key::Space) => typed.push(' '),
+                _ => {}
+            },
+            (LockState::Unlocking { .. }, None) => self.state = LockState::Active,
+        }
+        KeyOutcome::Consumed
+    }
+
+    /// Text drawn on the blank screen
+    pub fn notice(&self) -> Option<String> {
+        match &self.state {
+            LockState::Active => None,
+            LockState::Blanked => Some("Press any key to unlock".to_string()),
+            LockState::Unlocking { typed, failed } => {
+                let mut notice = format!("Unlock word: {}", "*".repeat(typed.chars().count()));
+                if *failed > 0 {
+                    notice = format!("Wrong unlock word ({}). {}", failed, notice);
+                }
+                Some(notice)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock(unlock_word: Option<&str>) -> (IdleLock, Instant) {
+        let start = Instant::now();
+        let config = IdleLockConfig {
+            idle: Some(Duration::from_secs(60)),
+            unlock_word: unlock_word.map(str::to_string),
+            wake_on_bell: true,
+            ..IdleLockConfig::default()
+        };
+        (IdleLock::new(config, start), start)
+    }
+
+    #[test]
+    fn test_idle_blanks_and_wake_key_is_consumed() {
+        let (mut lock, start) = lock(None);
+        assert_eq!(lock.deadline(), Some(start + Duration::from_secs(60)));
+
+        // Typing pushes the deadline out; output does not by default
+        assert_eq!(
+            lock.key(Some(Key::Char('l')), start + Duration::from_secs(30)),
+            KeyOutcome::Forward
+        );
+        lock.note_output(start + Duration::from_secs(80));
+        assert!(!lock.poll(start + Duration::from_secs(89)));
+        assert!(lock.poll(start + Duration::from_secs(90)));
+        assert!(lock.is_blanked());
+        assert_eq!(lock.deadline(), None);
+        assert_eq!(lock.notice().as_deref(), Some("Press any key to unlock"));
+        assert!(!lock.note_output(start + Duration::from_secs(95)));
+
+        // The wake key never reaches the PTY; the next one does
+        let wake = start + Duration::from_secs(100);
+        assert_eq!(lock.key(Some(Key::Char('x')), wake), KeyOutcome::Consumed);
+        assert!(!lock.is_blanked());
+        assert_eq!(lock.key(Some(Key::Char('x')), wake), KeyOutcome::Forward);
+        assert_eq!(lock.deadline(), Some(wake + Duration::from_secs(60)));
+
+        // A bell wakes it when configured
+        lock.poll(wake + Duration::from_secs(60));
+        assert!(lock.note_bell(wake + Duration::from_secs(61)));
+        assert!(!lock.is_blanked());
+    }
+
+    #[test]
+    fn test_unlock_word_flow() {
+        let (mut lock, start) = lock(Some("open"));
+        let later = start + Duration::from_secs(61);
+        assert!(lock.poll(later));
+
+        // Waking shows the prompt; bells cannot skip it
+        assert_eq!(lock.key(None, later), KeyOutcome::Consumed);
+        assert!(!lock.note_bell(later));
+        for c in "opem".chars() {
+            assert_eq!(lock.key(Some(Key::Char(c)), later), KeyOutcome::Consumed);
+        }
+        assert_eq!(lock.notice().as_deref(), Some("Unlock word: ****"));
+        assert_eq!(lock.key(Some(Key::Enter), later), KeyOutcome::Consumed);
+        assert!(lock.is_blanked());
+        assert_eq!(
+            lock.notice().as_deref(),
+            Some("Wrong unlock word (1). Unlock word: ")
+        );
+
+        // Corrections work and Escape starts over
+        for key in [
+            Key::Char('o'),
+            Key::Char('p'),
+            Key::Char('x'),
+            Key::Backspace,
+            Key::Escape,
+        ] {
+            lock.key(Some(key), later);
+        }
+        assert_eq!(lock.state(), &LockState::Blanked);
+        lock.key(Some(Key::Char('q')), later);
+        for c in "open".chars() {
+            lock.key(Some(Key::Char(c)), later);
+        }
+        assert_eq!(lock.key(Some(Key::Enter), later), KeyOutcome::Consumed);
+        assert!(!lock.is_blanked());
+        assert_eq!(lock.key(Some(Key::Char('l')), later), KeyOutcome::Forward);
+    }
+}
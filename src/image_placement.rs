@@ -0,0 +1,544 @@
+use std::collections::{BTreeMap, BTreeSet};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ImagePlacementError {
+    #[error("Invalid kitty delete command: {0}")]
+    InvalidDelete(String),
+}
+
+/// Pixel size of one terminal cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Where an image was emitted: a logical (unwrapped) line and the cell
+/// offset into it. Line numbers are absolute and keep counting across
+/// scrollback eviction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineAnchor {
+    pub line: usize,
+    pub offset: usize,
+}
+
+/// Absolute wrapped-row position in the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPos {
+    pub row: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellRect {
+    pub row: usize,
+    pub col: usize,
+    pub cols: usize,
+    pub rows: usize,
+}
+
+/// Wrapped layout of the retained buffer. `lengths` holds the cell length
+/// of each logical line from `first_line` on; `first_row` is the absolute
+/// row that line starts on.
+#[derive(Debug, Clone, Copy)]
+pub struct LineLayout<'a> {
+    pub first_line: usize,
+    pub first_row: usize,
+    pub lengths: &'a [usize],
+    pub width: usize,
+}
+
+impl LineLayout<'_> {
+    fn rows_for(&self, length: usize) -> usize {
+        length.div_ceil(self.width.max(1)).max(1)
+    }
+
+    /// Position of an anchor under this layout, or None once its line has
+    /// left the buffer
+    pub fn position(&self, anchor: LineAnchor) -> Option<BufferPos> {
+        let index = anchor.line.checked_sub(self.first_line)?;
+        if index >= self.lengths.len() {
+            return None;
+        }
+        let width = self.width.max(1);
+        let row = self.first_row
+            + self.lengths[..index]
+                .iter()
+                .map(|&len| self.rows_for(len))
+                .sum::<usize>();
+        Some(BufferPos {
+            row: row + anchor.offset / width,
+            col: anchor.offset % width,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub image_id: u32,
+    pub placement_id: u32,
+    pub anchor: LineAnchor,
+    pub pos: BufferPos,
+    /// Footprint in cells, fixed when placed; the image scales with the
+    /// cell size to keep filling it
+    pub cols: usize,
+    pub rows: usize,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+}
+
+impl Placement {
+    pub fn rect(&self) -> CellRect {
+        CellRect {
+            row: self.pos.row,
+            col: self.pos.col,
+            cols: self.cols,
+            rows: self.rows,
+        }
+    }
+
+    fn covers(&self, row: Option<usize>, col: Option<usize>) -> bool {
+        row.is_none_or(|r| r >= self.pos.row && r < self.pos.row + self.rows)
+            && col.is_none_or(|c| c >= self.pos.col && c < self.pos.col + self.cols)
+    }
+}
+
+/// Target of a kitty graphics `a=d` command. `free` is the upper-case form,
+/// which also drops the image data once nothing references it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteTarget {
+    All,
+    Image {
+        id: u32,
+        placement: Option<u32>,
+    },
+    /// 1-based screen cell
+    Cell {
+        x: usize,
+        y: usize,
+    },
+    Column(usize),
+    Row(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KittyDelete {
+    pub target: DeleteTarget,
+    pub free: bool,
+}
+
+impl KittyDelete {
+    /// Parses the control data of a graphics command, e.g. `a=d,d=p,x=3,y=2`
+    pub fn parse(control: &str) -> Result<Self, ImagePlacementError> {
+        let invalid = || ImagePlacementError::InvalidDelete(control.to_string());
+        let mut keys = BTreeMap::new();
+        for pair in control.split(',').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or_else(invalid)?;
+            keys.insert(key, value);
+        }
+        if keys.get("a").is_some_and(|&a| a != "d") {
+            return Err(invalid());
+        }
+        let number = |key: &str| -> Result<usize, ImagePlacementError> {
+            keys.get(key)
+                .ok_or_else(invalid)?
+                .parse()
+                .map_err(|_| invalid())
+        };
+        let kind = keys.get("d").copied().unwrap_or("a");
+        let free = kind.chars().all(|c| c.is_ascii_uppercase());
+        let target = match kind.to_ascii_lowercase().as_str() {
+            "a" => DeleteTarget::All,
+            "i" => DeleteTarget::Image {
+                id: number("i")? as u32,
+                placement: keys
+                    .get("p")
+                    .map(|_| number("p"))
+                    .transpose()?
+                    .map(|p| p as u32),
+            },
+            "p" => DeleteTarget::Cell {
+                x: number("x")?,
+                y: number("y")?,
+            },
+            "x" => DeleteTarget::Column(number("x")?),
+            "y" => DeleteTarget::Row(number("y")?),
+            _ => return Err(invalid()),
+        };
+        Ok(Self { target, free })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PixelRect {
+    fn intersect(&self, other: &PixelRect) -> Option<PixelRect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        (right > x && bottom > y).then_some(PixelRect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+}
+
+/// One image to draw this frame. `dest` may extend past the viewport;
+/// `scissor` is the clipped area and `uv` the matching texture sub-rect
+/// (u0, v0, u1, v1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageDraw {
+    pub image_id: u32,
+    pub placement_id: u32,
+    pub dest: PixelRect,
+    pub scissor: PixelRect,
+    pub uv: [f32; 4],
+}
+
+/// Image placements anchored to the text buffer. Tracks which image
+/// textures are resident so the renderer knows when to free them.
+#[derive(Debug)]
+pub struct PlacementMap {
+    cell: CellSize,
+    placements: Vec<Placement>,
+    resident: BTreeSet<u32>,
+}
+
+impl PlacementMap {
+    pub fn new(cell: CellSize) -> Self {
+        Self {
+            cell,
+            placements: Vec::new(),
+            resident: BTreeSet::new(),
+        }
+    }
+
+    pub fn placements(&self) -> &[Placement] {
+        &self.placements
+    }
+
+    pub fn is_resident(&self, image_id: u32) -> bool {
+        self.resident.contains(&image_id)
+    }
+
+    pub fn cell_size(&self) -> CellSize {
+        self.cell
+    }
+
+    /// Places an image at `anchor`; the footprint is the image's pixel size
+    /// rounded up to whole cells at the current cell size. Replaces an
+    /// existing placement with the same ids.
+    pub fn place(
+        &mut self,
+        image_id: u32,
+        placement_id: u32,
+        anchor: LineAnchor,
+        layout: &LineLayout,
+        pixel_width: u32,
+        pixel_height: u32,
+    ) -> Option<CellRect> {
+        let pos = layout.position(anchor)?;
+        self.placements
+            .retain(|p| !(p.image_id == image_id && p.placement_id == placement_id));
+        let placement = Placement {
+            image_id,
+            placement_id,
+            anchor,
+            pos,
+            cols: ((pixel_width as f32 / self.cell.width).ceil() as usize).max(1),
+            rows: ((pixel_height as f32 / self.cell.height).ceil() as usize).max(1),
+            pixel_width,
+            pixel_height,
+        };
+        let rect = placement.rect();
+        self.placements.push(placement);
+        self.resident.insert(image_id);
+        Some(rect)
+    }
+
+    /// Re-anchors every placement after a rewrap. Placements whose line is
+    /// gone are dropped; returns images whose textures should be released.
+    pub fn reflow(&mut self, layout: &LineLayout) -> Vec<u32> {
+        let mut dropped = BTreeSet::new();
+        self.placements
+            .retain_mut(|p| match layout.position(p.anchor) {
+                Some(pos) => {
+                    p.pos = pos;
+                    true
+                }
+                None => {
+                    dropped.insert(p.image_id);
+                    false
+                }
+            });
+        self.release(dropped)
+    }
+
+    /// Drops placements anchored above `first_line` after scrollback
+    /// eviction; returns images whose textures should be released
+    pub fn evict_before(&mut self, first_line: usize) -> Vec<u32> {
+        let mut dropped = BTreeSet::new();
+        self.placements.retain(|p| {
+            let keep = p.anchor.line >= first_line;
+            if !keep {
+                dropped.insert(p.image_id);
+            }
+            keep
+        });
+        self.release(dropped)
+    }
+
+    /// Font size changed. Footprints stay put; images re-scale to fill them.
+    pub fn set_cell_size(&mut self, cell: CellSize) {
+        self.cell = cell;
+    }
+
+    /// Applies a kitty `a=d` command. Screen cells are resolved against
+    /// the current (re-anchored) positions with `viewport_top` as row 1.
+    /// Returns images whose textures should be released.
+    pub fn delete(&mut self, command: KittyDelete, viewport_top: usize) -> Vec<u32> {
+        let row = |y: usize| viewport_top + y.saturating_sub(1);
+        let col = |x: usize| x.saturating_sub(1);
+        let mut touched = BTreeSet::new();
+        self.placements.retain(|p| {
+            let hit = match command.target {
+                DeleteTarget::All => true,
+                DeleteTarget::Image { id, placement } => {
+                    p.image_id == id && placement.is_none_or(|pid| p.placement_id == pid)
+                }
+                DeleteTarget::Cell { x, y } => p.covers(Some(row(y)), Some(col(x))),
+                DeleteTarget::Column(x) => p.covers(None, Some(col(x))),
+                DeleteTarget::Row(y) => p.covers(Some(row(y)), None),
+            };
+            if hit {
+                touched.insert(p.image_id);
+            }
+            !hit
+        });
+        if !command.free {
+            return Vec::new();
+        }
+        self.release(touched)
+    }
+
+    /// Frees the textures of `dropped` images that no placement still uses
+    fn release(&mut self, dropped: BTreeSet<u32>) -> Vec<u32> {
+        let released: Vec<u32> = dropped
+            .into_iter()
+            .filter(|id| !self.placements.iter().any(|p| p.image_id == *id))
+            .collect();
+        for id in &released {
+            self.resident.remove(id);
+        }
+        released
+    }
+
+    /// Draw list for a viewport whose first row is the absolute row `top`.
+    /// Images straddling an edge come back with a scissor rect and clipped
+    /// texture coordinates.
+    pub fn visible(&self, top: usize, rows: usize, cols: usize) -> Vec<ImageDraw> {
+        let viewport = PixelRect {
+            x: 0.0,
+            y: 0.0,
+            width: cols as f32 * self.cell.width,
+            height: rows as f32 * self.cell.height,
+        };
+        self.placements
+            .iter()
+            .filter_map(|p| {
+                let dest = self.dest_rect(p, top);
+                let scissor = dest.intersect(&viewport)?;
+                let uv = [
+                    (scissor.x - dest.x) / dest.width,
+                    (scissor.y - dest.y) / dest.height,
+                    (scissor.x + scissor.width - dest.x) / dest.width,
+                    (scissor.y + scissor.height - dest.y) / dest.height,
+                ];
+                Some(ImageDraw {
+                    image_id: p.image_id,
+                    placement_id: p.placement_id,
+                    dest,
+                    scissor,
+                    uv,
+                })
+            })
+            .collect()
+    }
+
+    /// The image fitted into its cell footprint, aspect ratio kept
+    fn dest_rect(&self, p: &Placement, top: usize) -> PixelRect {
+        let box_width = p.cols as f32 * self.cell.width;
+        let box_height = p.rows as f32 * self.cell.height;
+        let scale = (box_width / p.pixel_width.max(1) as f32)
+            .min(box_height / p.pixel_height.max(1) as f32);
+        PixelRect {
+            x: p.pos.col as f32 * self.cell.width,
+            y: (p.pos.row as f32 - top as f32) * self.cell.height,
+            width: p.pixel_width.max(1) as f32 * scale,
+            height: p.pixel_height.max(1) as f32 * scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL: CellSize = CellSize {
+        width: 10.0,
+        height: 20.0,
+    };
+
+    fn layout(lengths: &[usize], width: usize) -> LineLayout<'_> {
+        LineLayout {
+            first_line: 0,
+            first_row: 0,
+            lengths,
+            width,
+        }
+    }
+
+    #[test]
+    fn test_rewrap_moves_image_with_its_line() {
+        let lengths = [5, 30, 3];
+        let mut map = PlacementMap::new(CELL);
+        let anchor = LineAnchor {
+            line: 1,
+            offset: 10,
+        };
+        let rect = map
+            .place(7, 1, anchor, &layout(&lengths, 20), 40, 40)
+            .unwrap();
+        assert_eq!(
+            rect,
+            CellRect {
+                row: 1,
+                col: 10,
+                cols: 4,
+                rows: 2
+            }
+        );
+
+        // Narrower: line 0 still one row, offset 10 lands on line 1's second row
+        assert!(map.reflow(&layout(&lengths, 8)).is_empty());
+        assert_eq!(
+            map.placements()[0].rect(),
+            CellRect {
+                row: 2,
+                col: 2,
+                cols: 4,
+                rows: 2
+            }
+        );
+
+        // Deleting by screen cell hits the re-anchored position, not the old one
+        let at_old = KittyDelete::parse("a=d,d=p,x=11,y=2").unwrap();
+        assert!(map.delete(at_old, 0).is_empty());
+        assert_eq!(map.placements().len(), 1);
+        let at_new = KittyDelete::parse("a=d,d=P,x=4,y=4").unwrap();
+        assert_eq!(map.delete(at_new, 0), vec![7]);
+        assert!(!map.is_resident(7));
+    }
+
+    #[test]
+    fn test_eviction_releases_texture() {
+        let lengths = [10, 10, 10];
+        let mut map = PlacementMap::new(CELL);
+        map.place(
+            1,
+            0,
+            LineAnchor { line: 0, offset: 0 },
+            &layout(&lengths, 80),
+            10,
+            20,
+        );
+        map.place(
+            2,
+            0,
+            LineAnchor { line: 2, offset: 0 },
+            &layout(&lengths, 80),
+            10,
+            20,
+        );
+
+        assert_eq!(map.evict_before(1), vec![1]);
+        assert!(!map.is_resident(1));
+        assert!(map.is_resident(2));
+
+        // Lower-case delete drops the placement but keeps the image data
+        let keep = KittyDelete::parse("a=d,d=i,i=2").unwrap();
+        assert!(map.delete(keep, 0).is_empty());
+        assert!(map.placements().is_empty());
+        assert!(map.is_resident(2));
+        assert_eq!(
+            KittyDelete::parse("a=d,d=z"),
+            Err(ImagePlacementError::InvalidDelete("a=d,d=z".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_scissor_at_viewport_edge() {
+        let lengths = vec![0; 10];
+        let mut map = PlacementMap::new(CELL);
+        map.place(
+            3,
+            0,
+            LineAnchor { line: 4, offset: 2 },
+            &layout(&lengths, 80),
+            40,
+            40,
+        );
+
+        // Viewport starts one row into the two-row image
+        let draws = map.visible(5, 3, 80);
+        assert_eq!(draws.len(), 1);
+        let draw = &draws[0];
+        assert_eq!(
+            draw.dest,
+            PixelRect {
+                x: 20.0,
+                y: -20.0,
+                width: 40.0,
+                height: 40.0
+            }
+        );
+        assert_eq!(
+            draw.scissor,
+            PixelRect {
+                x: 20.0,
+                y: 0.0,
+                width: 40.0,
+                height: 20.0
+            }
+        );
+        assert_eq!(draw.uv, [0.0, 0.5, 1.0, 1.0]);
+
+        assert!(map.visible(6, 3, 80).is_empty());
+
+        // Bigger cells: same footprint, image scales up to fill it
+        map.set_cell_size(CellSize {
+            width: 20.0,
+            height: 40.0,
+        });
+        let draw = &map.visible(4, 3, 80)[0];
+        assert_eq!(
+            draw.dest,
+            PixelRect {
+                x: 40.0,
+                y: 0.0,
+                width: 80.0,
+                height: 80.0
+            }
+        );
+    }
+}
@@ -1,9 +1,11 @@
 use crate::command_parser::{CommandParser, ParsedCommand};
+use crate::command_registry::format_columns;
 use crate::config::{ConfigManager, KeymapConfig};
+use crate::line_editor::{EditStyle, LineEdit, LineEditor};
 use parking_lot::{RwLock, Mutex};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
@@ -77,6 +79,8 @@ pub enum InputAction {
     SendToTerminal(String),
     ExecuteCommand(String),
     ExecuteParsedCommand(ParsedCommand),
+    // A prefix line that did not parse
+    CommandError(String),
     SwitchTab(usize),
     ScrollUp,
     ScrollDown,
@@ -103,6 +107,38 @@ pub enum InputAction {
     CloseWindow,
     NextWindow,
     PrevWindow,
+    // Tabs
+    NewTab,
+    CloseTab,
+    NextTab,
+    PrevTab,
+    // Split panes
+    SplitRight,
+    SplitDown,
+    FocusPaneLeft,
+    FocusPaneRight,
+    FocusPaneUp,
+    FocusPaneDown,
+    // Scrollback folding
+    ToggleFold,
+    // Per-pane input lock
+    ToggleReadOnly,
+    // Type the latest :calc result at the shell prompt
+    InsertCalcResult,
+    // Find-and-run over shell history from every tab
+    CommandHistory,
+    // Per-pane switch for prompt suggestions
+    ToggleGhostText,
+    // Browse earlier agent responses
+    ResponseHistory,
+    // Find text in the scrollback
+    SearchScrollback,
+    // Live font size
+    IncreaseFontSize,
+    DecreaseFontSize,
+    ResetFontSize,
+    // Frame rate and other debug numbers over the grid
+    ToggleStatsOverlay,
     // Custom actions
     Custom(String, Vec<String>),
     // Shell-specific actions
@@ -151,6 +187,20 @@ pub enum KeyBindingContext {
     Emacs,
 }
 
+impl KeyBindingContext {
+    /// From a `context` in the keymap config
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "global" => Some(Self::Global),
+            "shell" => Some(Self::Shell),
+            "agent" => Some(Self::Agent),
+            "vi" => Some(Self::Vi),
+            "emacs" => Some(Self::Emacs),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct KeyBindingAction {
     pub action: InputAction,
@@ -160,15 +210,184 @@ pub struct KeyBindingAction {
 
 pub type KeyBindingMap = HashMap<KeyBinding, KeyBindingAction>;
 
+/// One side of a `BindingConflict`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictingBinding {
+    pub context: KeyBindingContext,
+    /// As `keys` shows it
+    pub action: String,
+    pub priority: u8,
+    /// From the config file rather than the defaults
+    pub from_config: bool,
+}
+
+impl ConflictingBinding {
+    fn new(context: KeyBindingContext, binding: &KeyBindingAction, from_config: bool) -> Self {
+        Self {
+            context,
+            action: format!("{:?}", binding.action),
+            priority: binding.priority,
+            from_config,
+        }
+    }
+}
+
+/// Two bindings for one key of which only `winner` fires. In one context
+/// the higher priority wins, a config binding on a tie; a binding for a
+/// context wins over a Global one while that context is active.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindingConflict {
+    pub key: String,
+    pub winner: ConflictingBinding,
+    pub shadowed: ConflictingBinding,
+}
+
+impl std::fmt::Display for BindingConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let origin = |binding: &ConflictingBinding| if binding.from_config { "config" } else { "default" };
+        let (winner, shadowed) = (&self.winner, &self.shadowed);
+        if winner.context == shadowed.context {
+            write!(
+                f,
+                "{} ({:?}): {} {} (priority {}) wins over {} {} (priority {})",
+                self.key,
+                winner.context,
+                origin(winner),
+                winner.action,
+                winner.priority,
+                origin(shadowed),
+                shadowed.action,
+                shadowed.priority,
+            )
+        } else {
+            write!(
+                f,
+                "{}: {} {} ({:?}) shadows {} {} ({:?}) in the {:?} context",
+                self.key,
+                origin(winner),
+                winner.action,
+                winner.context,
+                origin(shadowed),
+                shadowed.action,
+                shadowed.context,
+                winner.context,
+            )
+        }
+    }
+}
+
+/// Bindings of two or more chords, such as "ctrl+k ctrl+c", as a trie
+/// keyed by chord. Single chords stay in the `KeyBindingMap`.
+#[derive(Debug, Clone, Default)]
+pub struct KeySequences {
+    root: SequenceNode,
+}
+
+#[derive(Debug, Clone, Default)]
+struct SequenceNode {
+    action: Option<KeyBindingAction>,
+    next: HashMap<KeyBinding, SequenceNode>,
+}
+
+impl KeySequences {
+    pub fn insert(&mut self, chords: &[KeyBinding], action: KeyBindingAction) {
+        let node = chords
+            .iter()
+            .fold(&mut self.root, |node, chord| node.next.entry(chord.clone()).or_default());
+        node.action = Some(action);
+    }
+
+    /// Returns whether the sequence was bound
+    pub fn remove(&mut self, chords: &[KeyBinding]) -> bool {
+        fn remove(node: &mut SequenceNode, chords: &[KeyBinding]) -> bool {
+            let Some((first, rest)) = chords.split_first() else {
+                return node.action.take().is_some();
+            };
+            let Some(child) = node.next.get_mut(first) else {
+                return false;
+            };
+            let removed = remove(child, rest);
+            if child.action.is_none() && child.next.is_empty() {
+                node.next.remove(first);
+            }
+            removed
+        }
+        remove(&mut self.root, chords)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.next.is_empty()
+    }
+
+    /// Every bound sequence with its chords in order
+    pub fn entries(&self) -> Vec<(Vec<KeyBinding>, KeyBindingAction)> {
+        fn walk(node: &SequenceNode, path: &mut Vec<KeyBinding>, out: &mut Vec<(Vec<KeyBinding>, KeyBindingAction)>) {
+            if let Some(action) = &node.action {
+                out.push((path.clone(), action.clone()));
+            }
+            for (chord, child) in &node.next {
+                path.push(chord.clone());
+                walk(child, path, out);
+                path.pop();
+            }
+        }
+        let mut entries = Vec::new();
+        walk(&self.root, &mut Vec::new(), &mut entries);
+        entries
+    }
+
+    fn node(&self, chords: &[KeyBinding]) -> Option<&SequenceNode> {
+        chords.iter().try_fold(&self.root, |node, chord| node.next.get(chord))
+    }
+}
+
+/// Chords held while a sequence binding may still complete
+#[derive(Debug, Clone)]
+struct PendingSequence {
+    /// In the context of the sequences they start
+    chords: Vec<KeyBinding>,
+    /// Handled as usual if no sequence completes
+    events: Vec<KeyEvent>,
+    last_key: Instant,
+}
+
+/// What the sequence bindings made of a key
+#[derive(Debug)]
+enum SequenceStep {
+    /// Not part of a sequence
+    Pass,
+    /// Held for the rest of a sequence
+    Held,
+    Complete(InputAction),
+    /// Escape dropped the held keys
+    Cancelled,
+    /// Did not continue the held sequence, which is let go before the key
+    /// is looked at again
+    Broken(PendingSequence),
+}
+
 #[derive(Debug, Clone)]
 pub struct PrefixState {
     pub detected: bool,
-    pub buffer: String,
+    /// The command typed after the prefix
+    pub line: LineEditor,
     pub escape_mode: bool,
     pub start_time: Option<Instant>,
     pub timeout_ms: u64,
 }
 
+/// What prefix handling did with a key
+#[derive(Debug)]
+enum PrefixOutcome {
+    /// Not a prefix key; bindings and the terminal get it
+    Pass,
+    /// Send this first, then handle the key as usual
+    PassAfter(String),
+    /// Held by prefix mode
+    Consumed,
+    Action(InputAction),
+}
+
 #[derive(Debug, Clone)]
 pub enum ShellMode {
     Emacs,
@@ -176,6 +395,16 @@ pub enum ShellMode {
     Auto,
 }
 
+/// What the focused pane is running, used to decide whether the prefix
+/// character is ours or the application's
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TerminalContext {
+    pub alternate_screen: bool,
+    pub foreground_process: Option<String>,
+    /// From shell-integration prompt marks; `None` when the shell sends none
+    pub at_input_start: Option<bool>,
+}
+
 #[derive(Debug, Clone)]
 pub struct InputState {
     pub cursor_position: usize,
@@ -191,6 +420,7 @@ pub struct InputProcessor {
     // Configuration
     keymap_config: Arc<RwLock<KeymapConfig>>,
     keybindings: Arc<RwLock<KeyBindingMap>>,
+    sequences: Arc<RwLock<KeySequences>>,
     command_parser: Arc<RwLock<CommandParser>>,
     config_manager: Arc<ConfigManager>,
     
@@ -201,6 +431,10 @@ pub struct InputProcessor {
     // State management
     prefix_state: Arc<Mutex<PrefixState>>,
     input_state: Arc<Mutex<InputState>>,
+    terminal_context: Mutex<TerminalContext>,
+    pending_sequence: Mutex<Option<PendingSequence>>,
+    /// Found by the last `load_keybindings_from_config`
+    binding_conflicts: RwLock<Vec<BindingConflict>>,
     
     // Performance optimization
     key_lookup_cache: Arc<Mutex<HashMap<KeyBinding, Option<KeyBindingAction>>>>,
@@ -215,6 +449,21 @@ pub struct InputStats {
     pub cache_misses: u64,
     pub prefix_activations: u64,
     pub conflicts_resolved: u64,
+    /// Keys written directly by `fast_path`
+    pub fast_path_keys: u64,
+}
+
+/// Bytes produced by `InputProcessor::fast_path`, held inline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastKeyBytes {
+    bytes: [u8; 4],
+    len: u8,
+}
+
+impl FastKeyBytes {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
 }
 
 impl InputProcessor {
@@ -229,7 +478,7 @@ impl InputProcessor {
         
         let prefix_state = Arc::new(Mutex::new(PrefixState {
             detected: false,
-            buffer: String::new(),
+            line: LineEditor::default(),
             escape_mode: false,
             start_time: None,
             timeout_ms: 5000,
@@ -248,12 +497,16 @@ impl InputProcessor {
         Self {
             keymap_config,
             keybindings,
+            sequences: Arc::new(RwLock::new(KeySequences::default())),
             command_parser,
             config_manager,
             action_sender,
             action_receiver,
             prefix_state,
             input_state,
+            terminal_context: Mutex::new(TerminalContext::default()),
+            pending_sequence: Mutex::new(None),
+            binding_conflicts: RwLock::new(Vec::new()),
             key_lookup_cache: Arc::new(Mutex::new(HashMap::new())),
             stats: Arc::new(Mutex::new(InputStats::default())),
         }
@@ -293,10 +546,52 @@ impl InputProcessor {
         Self::add_binding(&mut bindings, "ctrl+r", InputAction::HistorySearch, 70, KeyBindingContext::Emacs);
 
         // Window management
-        Self::add_binding(&mut bindings, "ctrl+shift+t", InputAction::NewWindow, 60, KeyBindingContext::Global);
-        Self::add_binding(&mut bindings, "ctrl+shift+w", InputAction::CloseWindow, 60, KeyBindingContext::Global);
-        Self::add_binding(&mut bindings, "ctrl+tab", InputAction::NextWindow, 60, KeyBindingContext::Global);
-        Self::add_binding(&mut bindings, "ctrl+shift+tab", InputAction::PrevWindow, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "ctrl+shift+n", InputAction::NewWindow, 60, KeyBindingContext::Global);
+
+        // Tabs
+        Self::add_binding(&mut bindings, "ctrl+shift+t", InputAction::NewTab, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "ctrl+shift+w", InputAction::CloseTab, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "ctrl+tab", InputAction::NextTab, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "ctrl+shift+tab", InputAction::PrevTab, 60, KeyBindingContext::Global);
+
+        // Split panes
+        Self::add_binding(&mut bindings, "ctrl+shift+e", InputAction::SplitRight, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "ctrl+shift+h", InputAction::SplitDown, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "alt+left", InputAction::FocusPaneLeft, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "alt+right", InputAction::FocusPaneRight, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "alt+up", InputAction::FocusPaneUp, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "alt+down", InputAction::FocusPaneDown, 60, KeyBindingContext::Global);
+
+        // Scrollback folding
+        Self::add_binding(&mut bindings, "ctrl+shift+o", InputAction::ToggleFold, 60, KeyBindingContext::Global);
+
+        // Read-only panes
+        Self::add_binding(&mut bindings, "ctrl+shift+r", InputAction::ToggleReadOnly, 60, KeyBindingContext::Global);
+
+        // Calculator results
+        Self::add_binding(&mut bindings, "ctrl+shift+i", InputAction::InsertCalcResult, 60, KeyBindingContext::Global);
+
+        // Shell command history
+        Self::add_binding(&mut bindings, "ctrl+shift+h", InputAction::CommandHistory, 60, KeyBindingContext::Global);
+
+        // Prompt suggestions
+        Self::add_binding(&mut bindings, "ctrl+shift+g", InputAction::ToggleGhostText, 60, KeyBindingContext::Global);
+
+        // Agent response history
+        Self::add_binding(&mut bindings, "ctrl+shift+a", InputAction::ResponseHistory, 60, KeyBindingContext::Global);
+
+        // Scrollback search
+        Self::add_binding(&mut bindings, "ctrl+shift+f", InputAction::SearchScrollback, 60, KeyBindingContext::Global);
+
+        // Font size; Ctrl+= is Ctrl+Plus without reaching for Shift
+        Self::add_binding(&mut bindings, "ctrl+plus", InputAction::IncreaseFontSize, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "ctrl+shift+plus", InputAction::IncreaseFontSize, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "ctrl+=", InputAction::IncreaseFontSize, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "ctrl+minus", InputAction::DecreaseFontSize, 60, KeyBindingContext::Global);
+        Self::add_binding(&mut bindings, "ctrl+0", InputAction::ResetFontSize, 60, KeyBindingContext::Global);
+
+        // Debug stats overlay
+        Self::add_binding(&mut bindings, "f12", InputAction::ToggleStatsOverlay, 60, KeyBindingContext::Global);
 
         bindings
     }
@@ -317,6 +612,46 @@ impl InputProcessor {
         }
     }
 
+    fn parse_modifier(name: &str) -> Option<Modifier> {
+        match name.to_lowercase().as_str() {
+            "ctrl" => Some(Modifier::Ctrl),
+            "alt" => Some(Modifier::Alt),
+            "shift" => Some(Modifier::Shift),
+            "super" | "cmd" | "win" => Some(Modifier::Super),
+            "meta" => Some(Modifier::Meta),
+            "hyper" => Some(Modifier::Hyper),
+            _ => None,
+        }
+    }
+
+    /// Chords of a binding; several separated by spaces make a sequence,
+    /// e.g. "ctrl+k ctrl+c" or "g g"
+    fn parse_key_sequence(key_str: &str, context: KeyBindingContext) -> Result<Vec<KeyBinding>, InputError> {
+        let chords = key_str
+            .split_whitespace()
+            .map(|chord| Self::parse_key_binding(chord, context.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if chords.is_empty() {
+            return Err(InputError::KeyParse("Empty key binding".to_string()));
+        }
+        Ok(chords)
+    }
+
+    /// A single chord goes in `bindings`, a sequence in `sequences`
+    fn insert_binding(
+        bindings: &mut KeyBindingMap,
+        sequences: &mut KeySequences,
+        mut chords: Vec<KeyBinding>,
+        action: KeyBindingAction,
+    ) {
+        match chords.len() {
+            1 => {
+                bindings.insert(chords.remove(0), action);
+            }
+            _ => sequences.insert(&chords, action),
+        }
+    }
+
     fn parse_key_binding(key_str: &str, context: KeyBindingContext) -> Result<KeyBinding, InputError> {
         let parts: Vec<&str> = key_str.split('+').collect();
         if parts.is_empty() {
@@ -328,14 +663,9 @@ impl InputProcessor {
 
         let mut modifiers = HashSet::new();
         for modifier in modifier_parts {
-            match modifier.to_lowercase().as_str() {
-                "ctrl" => { modifiers.insert(Modifier::Ctrl); }
-                "alt" => { modifiers.insert(Modifier::Alt); }
-                "shift" => { modifiers.insert(Modifier::Shift); }
-                "super" | "cmd" | "win" => { modifiers.insert(Modifier::Super); }
-                "meta" => { modifiers.insert(Modifier::Meta); }
-                "hyper" => { modifiers.insert(Modifier::Hyper); }
-                _ => return Err(InputError::KeyParse(format!("Unknown modifier: {}", modifier))),
+            match Self::parse_modifier(modifier) {
+                Some(m) => { modifiers.insert(m); }
+                None => return Err(InputError::KeyParse(format!("Unknown modifier: {}", modifier))),
             }
         }
 
@@ -355,6 +685,9 @@ impl InputProcessor {
             "pageup" | "pgup" => Key::PageUp,
             "pagedown" | "pgdn" => Key::PageDown,
             "insert" | "ins" => Key::Insert,
+            // `+` separates the parts of a binding
+            "plus" => Key::Char('+'),
+            "minus" => Key::Char('-'),
             "f1" => Key::F1, "f2" => Key::F2, "f3" => Key::F3, "f4" => Key::F4,
             "f5" => Key::F5, "f6" => Key::F6, "f7" => Key::F7, "f8" => Key::F8,
             "f9" => Key::F9, "f10" => Key::F10, "f11" => Key::F11, "f12" => Key::F12,
@@ -388,28 +721,26 @@ impl InputProcessor {
             return Ok(());
         }
 
-        // Update input state
-        self.update_input_state(&event);
-
         // Handle bracketed paste mode detection
         if self.detect_paste_mode(&event) {
+            self.update_input_state(&event);
             return self.handle_paste_mode(event).await;
         }
 
-        // Check for prefix detection first (highest priority)
-        if let Some(action) = self.check_prefix_activation(&event)? {
-            self.execute_action(action).await?;
-            return Ok(());
-        }
-
-        // Try keybinding resolution
-        if let Some(action) = self.resolve_keybinding(&event)? {
-            self.execute_action(action).await?;
-            return Ok(());
+        // Sequence bindings see keys before the prefix does, except while a
+        // prefix line is being typed
+        if !self.is_prefix_active() {
+            self.flush_expired_sequence(event.timestamp).await?;
+            loop {
+                match self.advance_sequence(&event) {
+                    SequenceStep::Pass => break,
+                    SequenceStep::Held | SequenceStep::Cancelled => return Ok(()),
+                    SequenceStep::Complete(action) => return self.execute_action(action).await,
+                    SequenceStep::Broken(held) => self.release_sequence(held).await?,
+                }
+            }
         }
-
-        // Handle regular character input
-        self.handle_regular_input(event).await?;
+        self.dispatch_key(event).await?;
 
         // Update performance statistics
         let processing_time = start_time.elapsed();
@@ -430,6 +761,156 @@ impl InputProcessor {
         Ok(())
     }
 
+    /// A key no sequence binding took: prefix mode, then single bindings,
+    /// then the terminal
+    async fn dispatch_key(&mut self, event: KeyEvent) -> Result<(), InputError> {
+        // Check for prefix detection first (highest priority). It looks at
+        // the line as it was before this key, and keys it takes never reach
+        // the shell, so they leave the cursor tracking alone.
+        match self.check_prefix_activation(&event)? {
+            PrefixOutcome::Consumed => return Ok(()),
+            PrefixOutcome::Action(action) => {
+                if matches!(action, InputAction::SendToTerminal(_)) {
+                    self.update_input_state(&event);
+                }
+                self.execute_action(action).await?;
+                return Ok(());
+            }
+            PrefixOutcome::PassAfter(text) => {
+                for c in text.chars() {
+                    Self::track_cursor(&mut self.input_state.lock(), Key::Char(c));
+                }
+                self.execute_action(InputAction::SendToTerminal(text)).await?;
+            }
+            PrefixOutcome::Pass => {}
+        }
+        self.update_input_state(&event);
+
+        // Try keybinding resolution
+        if let Some(action) = self.resolve_keybinding(&event)? {
+            self.execute_action(action).await?;
+            return Ok(());
+        }
+
+        // Handle regular character input
+        self.handle_regular_input(event).await?;
+        Ok(())
+    }
+
+    /// Feed a key to the sequence bindings, holding it if a sequence may
+    /// continue with it
+    fn advance_sequence(&self, event: &KeyEvent) -> SequenceStep {
+        let mut pending = self.pending_sequence.lock();
+        if pending.is_some() && event.key == Key::Escape && event.modifiers.is_empty() {
+            *pending = None;
+            return SequenceStep::Cancelled;
+        }
+        let sequences = self.sequences.read();
+        if pending.is_none() && sequences.is_empty() {
+            return SequenceStep::Pass;
+        }
+
+        // A held sequence continues in the context it started in
+        let held = pending.as_ref().map_or(&[][..], |held| held.chords.as_slice());
+        let contexts = match held.first() {
+            Some(chord) => vec![chord.context.clone()],
+            None => vec![self.get_current_context(), KeyBindingContext::Global],
+        };
+        let found = contexts.into_iter().find_map(|context| {
+            let mut chords = held.to_vec();
+            chords.push(KeyBinding {
+                key: event.key,
+                modifiers: event.modifiers.clone(),
+                context,
+            });
+            sequences.node(&chords).map(|node| (chords, node))
+        });
+
+        match found {
+            Some((chords, node)) if !node.next.is_empty() => {
+                let held = pending.get_or_insert_with(|| PendingSequence {
+                    chords: Vec::new(),
+                    events: Vec::new(),
+                    last_key: event.timestamp,
+                });
+                held.chords = chords;
+                held.events.push(event.clone());
+                held.last_key = event.timestamp;
+                SequenceStep::Held
+            }
+            Some((_, SequenceNode { action: Some(action), .. })) => {
+                *pending = None;
+                SequenceStep::Complete(action.action.clone())
+            }
+            _ => match pending.take() {
+                Some(held) => SequenceStep::Broken(held),
+                None => SequenceStep::Pass,
+            },
+        }
+    }
+
+    /// Stop waiting on a held sequence: the keys so far fire their own
+    /// sequence if they are one, else are handled as if none had started
+    async fn release_sequence(&mut self, held: PendingSequence) -> Result<(), InputError> {
+        let action = self
+            .sequences
+            .read()
+            .node(&held.chords)
+            .and_then(|node| node.action.as_ref())
+            .map(|action| action.action.clone());
+        match action {
+            Some(action) => self.execute_action(action).await,
+            None => {
+                for event in held.events {
+                    self.dispatch_key(event).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Let go of a held sequence once `keymap.sequence_timeout_ms` has
+    /// passed without its next key; call from the event loop. Returns
+    /// whether anything was let go.
+    pub async fn flush_expired_sequence(&mut self, now: Instant) -> Result<bool, InputError> {
+        let timeout = self.sequence_timeout();
+        let expired = {
+            let mut pending = self.pending_sequence.lock();
+            match pending.as_ref() {
+                Some(held) if now >= held.last_key + timeout => pending.take(),
+                _ => None,
+            }
+        };
+        match expired {
+            Some(held) => self.release_sequence(held).await.map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// When a held sequence times out, if one is held
+    pub fn sequence_deadline(&self) -> Option<Instant> {
+        let timeout = self.sequence_timeout();
+        self.pending_sequence.lock().as_ref().map(|held| held.last_key + timeout)
+    }
+
+    fn sequence_timeout(&self) -> Duration {
+        Duration::from_millis(self.keymap_config.read().sequence_timeout_ms as u64)
+    }
+
+    /// Whether a sequence binding starts with this chord, in its context
+    /// or globally
+    fn starts_sequence(&self, binding: &KeyBinding) -> bool {
+        let sequences = self.sequences.read();
+        if sequences.is_empty() {
+            return false;
+        }
+        let global = KeyBinding {
+            context: KeyBindingContext::Global,
+            ..binding.clone()
+        };
+        sequences.node(std::slice::from_ref(binding)).is_some() || sequences.node(&[global]).is_some()
+    }
+
     fn should_allow_repeat(&self, event: &KeyEvent) -> bool {
         // Allow repeat for certain keys
         matches!(event.key, 
@@ -449,7 +930,16 @@ impl InputProcessor {
         }
 
         // Update cursor position tracking
-        match event.key {
+        Self::track_cursor(&mut state, event.key);
+
+        // Auto-detect shell mode if not set
+        if matches!(state.shell_mode, ShellMode::Auto) {
+            state.shell_mode = self.detect_shell_mode();
+        }
+    }
+
+    fn track_cursor(state: &mut InputState, key: Key) {
+        match key {
             Key::Left => state.cursor_position = state.cursor_position.saturating_sub(1),
             Key::Right => state.cursor_position += 1,
             Key::Home => {
@@ -475,11 +965,6 @@ impl InputProcessor {
             },
             _ => {}
         }
-
-        // Auto-detect shell mode if not set
-        if matches!(state.shell_mode, ShellMode::Auto) {
-            state.shell_mode = self.detect_shell_mode();
-        }
     }
 
     fn detect_paste_mode(&self, event: &KeyEvent) -> bool {
@@ -516,103 +1001,123 @@ impl InputProcessor {
         Ok(())
     }
 
-    fn check_prefix_activation(&self, event: &KeyEvent) -> Result<Option<InputAction>, InputError> {
+    fn check_prefix_activation(&self, event: &KeyEvent) -> Result<PrefixOutcome, InputError> {
         let keymap = self.keymap_config.read();
         let prefix_char = keymap.prefix.chars().next().unwrap_or('p');
+        let prefix_modifier = Self::parse_modifier(&keymap.prefix_modifier);
+
+        if !self.is_prefix_active() {
+            if self.prefix_suppressed(&keymap) {
+                return Ok(PrefixOutcome::Pass);
+            }
+            // With a modifier the chord is unambiguous, so it works anywhere
+            // and there is nothing to escape
+            if let Some(modifier) = prefix_modifier {
+                if event.key == Key::Char(prefix_char)
+                    && event.modifiers.len() == 1
+                    && event.modifiers.contains(&modifier)
+                {
+                    let style = self.edit_style();
+                    let mut prefix_state = self.prefix_state.lock();
+                    prefix_state.detected = true;
+                    prefix_state.line.reset(style);
+                    prefix_state.start_time = Some(event.timestamp);
+                    self.stats.lock().prefix_activations += 1;
+                    return Ok(PrefixOutcome::Consumed);
+                }
+                return Ok(PrefixOutcome::Pass);
+            }
+        }
         
         // Check if we're in escape mode first
         {
             let mut prefix_state = self.prefix_state.lock();
             if prefix_state.escape_mode {
+                prefix_state.escape_mode = false;
                 if matches!(event.key, Key::Char(c) if c == prefix_char) {
                     // Send literal prefix character
-                    prefix_state.escape_mode = false;
-                    return Ok(Some(InputAction::SendToTerminal(prefix_char.to_string())));
-                } else {
-                    // Send escape character and continue processing
-                    prefix_state.escape_mode = false;
-                    // Fall through to normal processing
+                    return Ok(PrefixOutcome::Action(InputAction::SendToTerminal(prefix_char.to_string())));
                 }
+                // Not an escape after all: the held backslash goes first
+                return Ok(PrefixOutcome::PassAfter("\\".to_string()));
             }
         }
 
         // Check for escape sequence start
-        if matches!(event.key, Key::Char('\\')) && self.is_at_line_start() {
+        if matches!(event.key, Key::Char('\\')) && event.modifiers.is_empty() && self.is_at_line_start() {
             self.prefix_state.lock().escape_mode = true;
-            return Ok(None); // Consume the backslash
+            return Ok(PrefixOutcome::Consumed); // Hold the backslash
         }
 
         // Check for prefix activation
         if !self.is_prefix_active() && self.is_at_line_start() {
             if let Key::Char(c) = event.key {
                 if c == prefix_char && event.modifiers.is_empty() {
+                    let style = self.edit_style();
                     let mut prefix_state = self.prefix_state.lock();
                     prefix_state.detected = true;
+                    prefix_state.line.reset(style);
                     prefix_state.start_time = Some(event.timestamp);
                     
                     // Increment statistics
                     self.stats.lock().prefix_activations += 1;
                     
-                    return Ok(None); // Consume the prefix character
+                    return Ok(PrefixOutcome::Consumed); // Consume the prefix character
                 }
             }
         }
 
-        // Handle commands in prefix mode
+        // Edit the command line in prefix mode
         if self.is_prefix_active() {
-            match event.key {
-                Key::Enter => {
-                    let command = {
-                        let mut prefix_state = self.prefix_state.lock();
-                        let cmd = prefix_state.buffer.clone();
-                        prefix_state.detected = false;
-                        prefix_state.buffer.clear();
-                        prefix_state.start_time = None;
-                        cmd
-                    };
+            if event.key == Key::Tab && event.modifiers.is_empty() {
+                self.complete_command_line();
+                return Ok(PrefixOutcome::Consumed);
+            }
+            // Readline keys in emacs mode are the shell's emacs bindings,
+            // applied to this line instead
+            let readline = if event.modifiers.iter().any(|modifier| *modifier != Modifier::Shift)
+                && self.prefix_state.lock().line.style() == EditStyle::Emacs
+            {
+                self.resolve_binding(KeyBinding {
+                    key: event.key,
+                    modifiers: event.modifiers.clone(),
+                    context: KeyBindingContext::Emacs,
+                })?
+            } else {
+                None
+            };
+
+            let mut prefix_state = self.prefix_state.lock();
+            match prefix_state.line.key(event.key, &event.modifiers) {
+                LineEdit::Submit => {
+                    let command = prefix_state.line.text();
+                    prefix_state.detected = false;
+                    prefix_state.line.reset(EditStyle::default());
+                    prefix_state.start_time = None;
+                    drop(prefix_state);
                     
                     if !command.is_empty() {
-                        match self.command_parser.write().parse(&command) {
-                            Ok(parsed) => return Ok(Some(InputAction::ExecuteParsedCommand(parsed))),
-                            Err(e) => {
-                                let error_msg = format!("Command error: {}\n", e);
-                                return Ok(Some(InputAction::SendToTerminal(error_msg)));
-                            }
-                        }
+                        // The parser expects the whole line, prefix included
+                        let line = format!("{}{}", keymap.prefix, command);
+                        return Ok(PrefixOutcome::Action(match self.command_parser.write().parse(&line) {
+                            Ok(parsed) => InputAction::ExecuteParsedCommand(parsed),
+                            Err(e) => InputAction::CommandError(e.to_string()),
+                        }));
                     }
                 }
-                Key::Escape => {
-                    // Cancel command
-                    let mut prefix_state = self.prefix_state.lock();
+                LineEdit::Cancel => {
                     prefix_state.detected = false;
-                    prefix_state.buffer.clear();
+                    prefix_state.line.reset(EditStyle::default());
                     prefix_state.start_time = None;
-                    return Ok(None);
                 }
-                Key::Backspace => {
-                    let mut prefix_state = self.prefix_state.lock();
-                    if !prefix_state.buffer.is_empty() {
-                        prefix_state.buffer.pop();
-                    } else {
-                        // Exit prefix mode if buffer is empty
-                        prefix_state.detected = false;
-                        prefix_state.start_time = None;
+                LineEdit::Ignored => {
+                    if let Some(action) = readline {
+                        prefix_state.line.apply(&action);
                     }
-                    return Ok(None);
-                }
-                Key::Tab => {
-                    // TODO: Implement command completion
-                    return Ok(None);
-                }
-                Key::Char(c) => {
-                    self.prefix_state.lock().buffer.push(c);
-                    return Ok(None);
-                }
-                _ => {
-                    // Ignore other keys in prefix mode
-                    return Ok(None);
                 }
+                LineEdit::Edited => {}
             }
+            return Ok(PrefixOutcome::Consumed);
         }
 
         // Check for prefix timeout
@@ -621,22 +1126,38 @@ impl InputProcessor {
             if let Some(start_time) = prefix_state.start_time {
                 if event.timestamp.duration_since(start_time).as_millis() > prefix_state.timeout_ms as u128 {
                     prefix_state.detected = false;
-                    prefix_state.buffer.clear();
+                    prefix_state.line.reset(EditStyle::default());
                     prefix_state.start_time = None;
                 }
             }
         }
 
-        Ok(None)
+        Ok(PrefixOutcome::Pass)
+    }
+
+    /// Tab on the prefix line: the next candidate when several are on
+    /// offer, else candidates for the word before the cursor
+    fn complete_command_line(&self) {
+        let before = {
+            let mut prefix_state = self.prefix_state.lock();
+            if prefix_state.line.next_completion() {
+                return;
+            }
+            prefix_state.line.before_cursor()
+        };
+        let candidates = self.command_parser.read().complete(&before);
+        self.prefix_state.lock().line.complete(candidates);
     }
 
     fn resolve_keybinding(&self, event: &KeyEvent) -> Result<Option<InputAction>, InputError> {
-        let binding = KeyBinding {
+        self.resolve_binding(KeyBinding {
             key: event.key,
             modifiers: event.modifiers.clone(),
             context: self.get_current_context(),
-        };
+        })
+    }
 
+    fn resolve_binding(&self, binding: KeyBinding) -> Result<Option<InputAction>, InputError> {
         // Check cache first for O(1) performance
         {
             let cache = self.key_lookup_cache.lock();
@@ -709,6 +1230,18 @@ impl InputProcessor {
         }
     }
 
+    /// Vi editing on the prefix line when the shell is in vi mode
+    fn edit_style(&self) -> EditStyle {
+        let mut state = self.input_state.lock();
+        if matches!(state.shell_mode, ShellMode::Auto) {
+            state.shell_mode = self.detect_shell_mode();
+        }
+        match state.shell_mode {
+            ShellMode::Vi => EditStyle::Vi,
+            _ => EditStyle::Emacs,
+        }
+    }
+
     fn detect_shell_mode(&self) -> ShellMode {
         // Try to detect shell mode from environment
         if let Ok(editor) = std::env::var("EDITOR") {
@@ -734,7 +1267,19 @@ impl InputProcessor {
     async fn handle_regular_input(&mut self, event: KeyEvent) -> Result<(), InputError> {
         match event.key {
             Key::Char(c) => {
-                self.execute_action(InputAction::SendToTerminal(c.to_string())).await?;
+                // The typed text, which may be several characters from a
+                // dead key or compose sequence, unless Ctrl makes it a
+                // control character
+                let mut text = match (Self::control_char(c, &event.modifiers), event.text) {
+                    (Some(control), _) => control.to_string(),
+                    (None, Some(text)) if !text.is_empty() => text,
+                    (None, _) => c.to_string(),
+                };
+                // Alt sends the key prefixed with ESC
+                if event.modifiers.contains(&Modifier::Alt) {
+                    text.insert(0, '\x1b');
+                }
+                self.execute_action(InputAction::SendToTerminal(text)).await?;
             }
             Key::Enter => {
                 self.execute_action(InputAction::SendToTerminal("\n".to_string())).await?;
@@ -800,6 +1345,25 @@ impl InputProcessor {
         Ok(())
     }
 
+    /// The control character Ctrl produces with `c`, if any
+    fn control_char(c: char, modifiers: &HashSet<Modifier>) -> Option<char> {
+        if !modifiers.contains(&Modifier::Ctrl) {
+            return None;
+        }
+        let byte = match c.to_ascii_lowercase() {
+            c @ 'a'..='z' => c as u8 & 0x1f,
+            '@' | ' ' | '2' => 0,
+            '[' | '3' => 0x1b,
+            '\\' | '4' => 0x1c,
+            ']' | '5' => 0x1d,
+            '^' | '6' => 0x1e,
+            '_' | '-' | '7' => 0x1f,
+            '?' | '8' => 0x7f,
+            _ => return None,
+        };
+        Some(byte as char)
+    }
+
     async fn execute_action(&mut self, action: InputAction) -> Result<(), InputError> {
         self.action_sender
             .send(action)
@@ -808,41 +1372,205 @@ impl InputProcessor {
     }
 
     fn is_at_line_start(&self) -> bool {
+        if let Some(at_input_start) = self.terminal_context.lock().at_input_start {
+            return at_input_start;
+        }
         self.input_state.lock().line_start || self.input_state.lock().cursor_position == 0
     }
 
+    /// Updates what the focused pane is running; call before handling keys
+    pub fn set_terminal_context(&self, context: TerminalContext) {
+        *self.terminal_context.lock() = context;
+    }
+
+    /// True while the prefix character belongs to the application: the
+    /// alternate screen is up or a suppressed program is in the foreground
+    fn prefix_suppressed(&self, keymap: &KeymapConfig) -> bool {
+        let context = self.terminal_context.lock();
+        if context.alternate_screen && !keymap.prefix_in_alternate_screen {
+            return true;
+        }
+        context.foreground_process.as_deref().is_some_and(|process| {
+            keymap.prefix_suppressed_processes.iter().any(|p| p == process)
+        })
+    }
+
     fn is_prefix_active(&self) -> bool {
         self.prefix_state.lock().detected
     }
 
+    /// Bytes for an echoable key that can skip the action channel: a
+    /// printable character, Enter, Tab or Backspace with no modifiers, no
+    /// binding, and nothing stateful (prefix mode, prefix escape, paste, a
+    /// key sequence) in progress. `None` means the key must go through `process_key_event`.
+    /// Does not allocate once the binding cache has seen the key.
+    pub fn fast_path(&self, key: Key, modifiers: &HashSet<Modifier>) -> Option<FastKeyBytes> {
+        if !modifiers.is_empty() {
+            return None;
+        }
+        let mut bytes = [0u8; 4];
+        let len = match key {
+            Key::Char(c) if !c.is_control() => c.encode_utf8(&mut bytes).len(),
+            Key::Enter => {
+                bytes[0] = b'\r';
+                1
+            }
+            Key::Tab => {
+                bytes[0] = b'\t';
+                1
+            }
+            Key::Backspace => {
+                bytes[0] = 0x08;
+                1
+            }
+            _ => return None,
+        };
+
+        {
+            let prefix_state = self.prefix_state.lock();
+            if prefix_state.detected || prefix_state.escape_mode {
+                return None;
+            }
+        }
+        if self.pending_sequence.lock().is_some() {
+            return None;
+        }
+        {
+            let mut state = self.input_state.lock();
+            if state.in_paste_mode {
+                return None;
+            }
+            if matches!(state.shell_mode, ShellMode::Auto) {
+                state.shell_mode = self.detect_shell_mode();
+            }
+            let keymap = self.keymap_config.read();
+            let prefix_char = keymap.prefix.chars().next().unwrap_or('p');
+            if keymap.prefix_modifier.is_empty()
+                && matches!(key, Key::Char(c) if c == prefix_char || c == '\\')
+                && !self.prefix_suppressed(&keymap)
+            {
+                let at_line_start = match self.terminal_context.lock().at_input_start {
+                    Some(at_input_start) => at_input_start,
+                    None => state.line_start || state.cursor_position == 0,
+                };
+                if at_line_start {
+                    return None;
+                }
+            }
+        }
+
+        let binding = KeyBinding {
+            key,
+            modifiers: HashSet::new(),
+            context: self.get_current_context(),
+        };
+        if self.starts_sequence(&binding) || self.resolve_binding(binding).ok()?.is_some() {
+            return None;
+        }
+
+        Self::track_cursor(&mut self.input_state.lock(), key);
+        self.stats.lock().fast_path_keys += 1;
+        Some(FastKeyBytes {
+            bytes,
+            len: len as u8,
+        })
+    }
+
     // Configuration management
+    /// Replace the bindings with the defaults plus those of the keymap
+    /// config. An unknown key, action or context is an error and leaves
+    /// the current bindings in place.
     pub async fn load_keybindings_from_config(&mut self) -> Result<(), InputError> {
         let config = self.config_manager.get_config();
         let mut new_bindings = Self::build_default_keybindings();
-        
-        // Add user-defined bindings from config
-        for (key_str, action_str) in &config.keymap.bindings {
-            if let Ok(key_binding) = Self::parse_key_binding(key_str, KeyBindingContext::Global) {
-                if let Some(action) = self.string_to_action(action_str) {
-                    new_bindings.insert(key_binding, KeyBindingAction {
-                        action,
-                        priority: 100, // User bindings get highest priority
-                        condition: None,
-                    });
+        let mut new_sequences = KeySequences::default();
+        let mut configured: HashSet<Vec<KeyBinding>> = HashSet::new();
+        let mut conflicts = Vec::new();
+
+        // The string form is shorthand for the Global context at priority 100
+        let entries = config
+            .keymap
+            .bindings
+            .iter()
+            .map(|(key, action)| (key.as_str(), action.as_str(), "global", 100))
+            .chain(config.keymap.context_bindings.iter().map(|binding| {
+                (binding.key.as_str(), binding.action.as_str(), binding.context.as_str(), binding.priority)
+            }));
+
+        for (key_str, action_str, context_name, priority) in entries {
+            let invalid = |reason: String| InputError::Config(format!("keymap binding \"{}\": {}", key_str, reason));
+            let context = KeyBindingContext::from_name(context_name)
+                .ok_or_else(|| invalid(format!("unknown context \"{}\"", context_name)))?;
+            let chords = Self::parse_key_sequence(key_str, context.clone()).map_err(|e| invalid(e.to_string()))?;
+            let action = self
+                .string_to_action(action_str)
+                .ok_or_else(|| invalid(format!("unknown action \"{}\"", action_str)))?;
+            let binding = KeyBindingAction { action, priority, condition: None };
+
+            let existing = match chords.as_slice() {
+                [chord] => new_bindings.get(chord).cloned(),
+                _ => new_sequences.node(&chords).and_then(|node| node.action.clone()),
+            };
+            if let Some(existing) = existing {
+                let old = ConflictingBinding::new(context.clone(), &existing, configured.contains(&chords));
+                let new = ConflictingBinding::new(context.clone(), &binding, true);
+                let wins = binding.priority >= existing.priority;
+                if old.action != new.action {
+                    let (winner, shadowed) = if wins { (new, old) } else { (old, new) };
+                    conflicts.push(BindingConflict { key: self.chords_to_string(&chords), winner, shadowed });
                 }
+                if !wins {
+                    continue;
+                }
+            }
+            configured.insert(chords.clone());
+            Self::insert_binding(&mut new_bindings, &mut new_sequences, chords, binding);
+        }
+
+        // A Global binding is out of reach where a context binds its key
+        for (chord, binding) in new_bindings.iter().filter(|(chord, _)| chord.context != KeyBindingContext::Global) {
+            let global = KeyBinding { context: KeyBindingContext::Global, ..chord.clone() };
+            let Some(global_binding) = new_bindings.get(&global) else {
+                continue;
+            };
+            let from_config = |chord: &KeyBinding| configured.contains(std::slice::from_ref(chord));
+            let winner = ConflictingBinding::new(chord.context.clone(), binding, from_config(chord));
+            let shadowed = ConflictingBinding::new(KeyBindingContext::Global, global_binding, from_config(&global));
+            if (winner.from_config || shadowed.from_config) && winner.action != shadowed.action {
+                conflicts.push(BindingConflict { key: self.keybinding_to_string(chord), winner, shadowed });
             }
         }
+        conflicts.sort_by_key(|conflict| (conflict.key.clone(), format!("{:?}", conflict.winner.context)));
 
         // Update keybindings and clear cache
         *self.keybindings.write() = new_bindings;
+        *self.sequences.write() = new_sequences;
+        *self.binding_conflicts.write() = conflicts;
+        *self.pending_sequence.lock() = None;
         self.key_lookup_cache.lock().clear();
         
         // Update keymap config
+        self.command_parser.write().update_prefix(config.keymap.prefix.clone());
         *self.keymap_config.write() = config.keymap;
         
         Ok(())
     }
 
+    /// Bindings from the config that shadow others or are shadowed, as of
+    /// the last load
+    pub fn binding_conflicts(&self) -> Vec<BindingConflict> {
+        self.binding_conflicts.read().clone()
+    }
+
+    /// `keys --conflicts` output, one conflict per line
+    pub fn format_binding_conflicts(&self) -> String {
+        let conflicts = self.binding_conflicts();
+        if conflicts.is_empty() {
+            return "No key binding conflicts".to_string();
+        }
+        conflicts.iter().map(|conflict| conflict.to_string()).collect::<Vec<_>>().join("\n")
+    }
+
     pub fn add_custom_keybinding(
         &mut self,
         key_str: &str,
@@ -850,9 +1578,9 @@ impl InputProcessor {
         context: KeyBindingContext,
         priority: u8,
     ) -> Result<(), InputError> {
-        let key_binding = Self::parse_key_binding(key_str, context)?;
+        let chords = Self::parse_key_sequence(key_str, context)?;
         
-        self.keybindings.write().insert(key_binding, KeyBindingAction {
+        Self::insert_binding(&mut self.keybindings.write(), &mut self.sequences.write(), chords, KeyBindingAction {
             action,
             priority,
             condition: None,
@@ -865,8 +1593,11 @@ impl InputProcessor {
     }
 
     pub fn remove_keybinding(&mut self, key_str: &str, context: KeyBindingContext) -> Result<bool, InputError> {
-        let key_binding = Self::parse_key_binding(key_str, context)?;
-        let removed = self.keybindings.write().remove(&key_binding).is_some();
+        let mut chords = Self::parse_key_sequence(key_str, context)?;
+        let removed = match chords.len() {
+            1 => self.keybindings.write().remove(&chords.remove(0)).is_some(),
+            _ => self.sequences.write().remove(&chords),
+        };
         
         if removed {
             self.key_lookup_cache.lock().clear();
@@ -875,6 +1606,7 @@ impl InputProcessor {
         Ok(removed)
     }
 
+    /// The action a config binding names, `None` for one it doesn't know
     fn string_to_action(&self, action_str: &str) -> Option<InputAction> {
         match action_str.trim() {
             // Control actions
@@ -922,11 +1654,54 @@ impl InputProcessor {
             "close_window" => Some(InputAction::CloseWindow),
             "next_window" => Some(InputAction::NextWindow),
             "prev_window" => Some(InputAction::PrevWindow),
+
+            // Tabs
+            "new_tab" => Some(InputAction::NewTab),
+            "close_tab" => Some(InputAction::CloseTab),
+            "next_tab" => Some(InputAction::NextTab),
+            "prev_tab" => Some(InputAction::PrevTab),
+
+            // Split panes
+            "split_right" => Some(InputAction::SplitRight),
+            "split_down" => Some(InputAction::SplitDown),
+            "focus_pane_left" => Some(InputAction::FocusPaneLeft),
+            "focus_pane_right" => Some(InputAction::FocusPaneRight),
+            "focus_pane_up" => Some(InputAction::FocusPaneUp),
+            "focus_pane_down" => Some(InputAction::FocusPaneDown),
+
+            // Scrollback folding
+            "toggle_fold" => Some(InputAction::ToggleFold),
+
+            // Read-only panes
+            "toggle_read_only" => Some(InputAction::ToggleReadOnly),
+
+            // Calculator results
+            "insert_calc_result" => Some(InputAction::InsertCalcResult),
+
+            // Shell command history
+            "command_history" => Some(InputAction::CommandHistory),
+
+            // Prompt suggestions
+            "toggle_ghost_text" => Some(InputAction::ToggleGhostText),
+
+            // Agent response history
+            "response_history" => Some(InputAction::ResponseHistory),
+
+            // Scrollback search
+            "search_scrollback" => Some(InputAction::SearchScrollback),
+
+            // Font size
+            "increase_font_size" => Some(InputAction::IncreaseFontSize),
+            "decrease_font_size" => Some(InputAction::DecreaseFontSize),
+            "reset_font_size" => Some(InputAction::ResetFontSize),
+
+            // Debug stats
+            "toggle_stats_overlay" => Some(InputAction::ToggleStatsOverlay),
             
             // Custom actions with parameters
             _ if action_str.starts_with("custom:") => {
                 let parts: Vec<&str> = action_str.strip_prefix("custom:").unwrap().split(':').collect();
-                if parts.is_empty() {
+                if parts[0].is_empty() {
                     None
                 } else {
                     Some(InputAction::Custom(
@@ -935,10 +1710,7 @@ impl InputProcessor {
                     ))
                 }
             },
-            _ => {
-                eprintln!("Warning: Unknown action '{}', treating as custom", action_str);
-                Some(InputAction::Custom(action_str.to_string(), vec![]))
-            }
+            _ => None,
         }
     }
 
@@ -947,8 +1719,23 @@ impl InputProcessor {
         self.action_receiver.recv().await
     }
 
+    /// The next queued action, for callers outside an async context
+    pub fn try_receive_action(&mut self) -> Option<InputAction> {
+        self.action_receiver.try_recv().ok()
+    }
+
     pub fn get_command_buffer(&self) -> String {
-        self.prefix_state.lock().buffer.clone()
+        self.prefix_state.lock().line.text()
+    }
+
+    pub fn prefix(&self) -> String {
+        self.keymap_config.read().prefix.clone()
+    }
+
+    /// The prefix command line while one is being typed, for drawing
+    pub fn command_line(&self) -> Option<LineEditor> {
+        let prefix_state = self.prefix_state.lock();
+        prefix_state.detected.then(|| prefix_state.line.clone())
     }
 
     pub fn is_prefix_mode(&self) -> bool {
@@ -958,7 +1745,7 @@ impl InputProcessor {
     pub fn cancel_command(&mut self) {
         let mut prefix_state = self.prefix_state.lock();
         prefix_state.detected = false;
-        prefix_state.buffer.clear();
+        prefix_state.line.reset(EditStyle::default());
         prefix_state.escape_mode = false;
         prefix_state.start_time = None;
     }
@@ -1006,21 +1793,91 @@ impl InputProcessor {
     }
 
     pub fn list_active_keybindings(&self) -> Vec<(String, String)> {
-        let keybindings = self.keybindings.read();
         let context = self.get_current_context();
         
-        keybindings
-            .iter()
-            .filter(|(kb, _)| kb.context == context || kb.context == KeyBindingContext::Global)
-            .map(|(kb, action)| {
-                (
-                    self.keybinding_to_string(kb),
-                    format!("{:?}", action.action)
-                )
-            })
+        self.all_keybindings()
+            .into_iter()
+            .filter(|(kb_context, _, _)| *kb_context == context || *kb_context == KeyBindingContext::Global)
+            .map(|(_, key, action)| (key, action))
             .collect()
     }
 
+    /// Single chords and sequences alike as (context, keys, action), a
+    /// sequence's chords separated by spaces
+    fn all_keybindings(&self) -> Vec<(KeyBindingContext, String, String)> {
+        let keybindings = self.keybindings.read();
+        let sequences = self.sequences.read();
+        let singles = keybindings
+            .iter()
+            .map(|(kb, action)| (kb.context.clone(), self.keybinding_to_string(kb), format!("{:?}", action.action)));
+        let chorded = sequences.entries().into_iter().map(|(chords, action)| {
+            (chords[0].context.clone(), self.chords_to_string(&chords), format!("{:?}", action.action))
+        });
+        singles.chain(chorded).collect()
+    }
+
+    /// Every binding, grouped by context in the order `keys` shows them
+    /// and sorted by key within a group; empty contexts are left out
+    pub fn list_keybindings_by_context(&self) -> Vec<(KeyBindingContext, Vec<(String, String)>)> {
+        let keybindings = self.all_keybindings();
+        [
+            KeyBindingContext::Global,
+            KeyBindingContext::Shell,
+            KeyBindingContext::Emacs,
+            KeyBindingContext::Vi,
+            KeyBindingContext::Agent,
+        ]
+        .into_iter()
+        .filter_map(|context| {
+            let mut bindings: Vec<(String, String)> = keybindings
+                .iter()
+                .filter(|(kb_context, _, _)| *kb_context == context)
+                .map(|(_, key, action)| (key.clone(), action.clone()))
+                .collect();
+            bindings.sort();
+            (!bindings.is_empty()).then_some((context, bindings))
+        })
+        .collect()
+    }
+
+    /// `keys` output: a heading per context and aligned columns of key
+    /// and action, wrapped to `width`
+    pub fn format_keybindings(&self, width: usize) -> String {
+        self.list_keybindings_by_context()
+            .into_iter()
+            .map(|(context, bindings)| format!("{:?}:\n{}", context, format_columns(&bindings, width)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Replace the prefix commands loaded from `[commands]`; returns the
+    /// entries left out
+    pub fn load_user_commands(
+        &self,
+        commands: &std::collections::BTreeMap<String, crate::config::UserCommand>,
+    ) -> Vec<crate::command_parser::CommandParseError> {
+        self.command_parser.write().set_user_commands(commands)
+    }
+
+    /// Answer Tab completion for arguments marked with `source`
+    pub fn set_completer(
+        &self,
+        source: crate::command_registry::CompletionSource,
+        completer: Arc<dyn crate::command_registry::Completer>,
+    ) {
+        self.command_parser.write().set_completer(source, completer);
+    }
+
+    /// `help` text from the command parser, for the current prefix
+    pub fn command_help(&self, command: Option<&str>, width: usize) -> String {
+        self.command_parser.read().get_command_help(command, width)
+    }
+
+    /// A sequence's chords separated by spaces
+    fn chords_to_string(&self, chords: &[KeyBinding]) -> String {
+        chords.iter().map(|kb| self.keybinding_to_string(kb)).collect::<Vec<_>>().join(" ")
+    }
+
     fn keybinding_to_string(&self, kb: &KeyBinding) -> String {
         let mut parts = Vec::new();
         
@@ -1034,6 +1891,8 @@ impl InputProcessor {
 
         // Add key
         let key_str = match kb.key {
+            Key::Char('+') => "plus".to_string(),
+            Key::Char('-') => "minus".to_string(),
             Key::Char(c) => c.to_string(),
             Key::Space => "space".to_string(),
             Key::Enter => "enter".to_string(),
@@ -1090,6 +1949,11 @@ mod tests {
         assert!(InputProcessor::parse_key_binding("alt+f1", KeyBindingContext::Global).is_ok());
         assert!(InputProcessor::parse_key_binding("super+space", KeyBindingContext::Global).is_ok());
         assert!(InputProcessor::parse_key_binding("invalid+key", KeyBindingContext::Global).is_err());
+
+        // `+` and `-` are spelled out, and read back the same way
+        let plus = InputProcessor::parse_key_binding("ctrl+plus", KeyBindingContext::Global).unwrap();
+        assert_eq!(plus.key, Key::Char('+'));
+        assert_eq!(create_test_processor().keybinding_to_string(&plus), "ctrl+plus");
     }
 
     #[test]
@@ -1100,6 +1964,146 @@ mod tests {
         assert!(matches!(processor.get_current_context(), KeyBindingContext::Shell | KeyBindingContext::Emacs));
     }
 
+    #[test]
+    fn test_keys_listing_grouped_by_context() {
+        let processor = create_test_processor();
+        let groups = processor.list_keybindings_by_context();
+        assert_eq!(groups[0].0, KeyBindingContext::Global);
+        assert!(groups.iter().all(|(_, bindings)| bindings.is_sorted()));
+
+        let text = processor.format_keybindings(80);
+        assert!(text.starts_with("Global:\n  "));
+        let (key, action) = &groups[0].1[0];
+        let column = groups[0].1.iter().map(|(key, _)| key.len()).max().unwrap();
+        assert!(text.contains(&format!("  {:<column$}  {}\n", key, action)));
+    }
+
+    #[tokio::test]
+    async fn test_config_keybindings_and_conflicts() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("ferroterm.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+[keymap.bindings]
+"ctrl+e" = "scroll_to_bottom"
+"ctrl+l" = "scroll_to_top"
+
+[[keymap.context_bindings]]
+key = "ctrl+a"
+action = "word_back"
+context = "emacs"
+priority = 60
+
+[[keymap.context_bindings]]
+key = "ctrl+w"
+action = "close_tab"
+context = "vi"
+"#,
+        )
+        .unwrap();
+        let config_manager = Arc::new(ConfigManager::from_path(config_path.clone()).unwrap());
+        let mut processor = InputProcessor::new(
+            Arc::new(RwLock::new(config_manager.get_config().keymap)),
+            Arc::new(RwLock::new(CommandParser::new("p".to_string()))),
+            config_manager.clone(),
+        );
+        processor.load_keybindings_from_config().await.unwrap();
+
+        let resolve = |key: &str, context| {
+            let binding = InputProcessor::parse_key_binding(key, context).unwrap();
+            processor.resolve_binding(binding).unwrap().map(|action| format!("{:?}", action))
+        };
+        assert_eq!(resolve("ctrl+w", KeyBindingContext::Vi).as_deref(), Some("CloseTab"));
+        assert_eq!(resolve("ctrl+a", KeyBindingContext::Emacs).as_deref(), Some("LineStart"));
+        assert_eq!(resolve("ctrl+l", KeyBindingContext::Global).as_deref(), Some("ScrollToTop"));
+
+        let conflicts: Vec<String> = processor.binding_conflicts().iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            conflicts,
+            [
+                "ctrl+a (Emacs): default LineStart (priority 70) wins over config WordBack (priority 60)",
+                "ctrl+e: default LineEnd (Emacs) shadows config ScrollToBottom (Global) in the Emacs context",
+                "ctrl+l (Global): config ScrollToTop (priority 100) wins over default Clear (priority 90)",
+            ]
+        );
+        assert_eq!(processor.format_binding_conflicts(), conflicts.join("\n"));
+
+        // A misspelled action is an error rather than a custom action, and
+        // the bindings loaded before stay
+        std::fs::write(&config_path, "[keymap.bindings]\n\"ctrl+l\" = \"scrol_up\"\n").unwrap();
+        config_manager.reload_config().unwrap();
+        let error = processor.load_keybindings_from_config().await.unwrap_err();
+        assert!(error.to_string().contains("unknown action \"scrol_up\""), "{}", error);
+        assert_eq!(processor.binding_conflicts().len(), 3);
+        let binding = InputProcessor::parse_key_binding("ctrl+l", KeyBindingContext::Global).unwrap();
+        assert!(matches!(processor.resolve_binding(binding), Ok(Some(InputAction::ScrollToTop))));
+    }
+
+    #[tokio::test]
+    async fn test_key_sequences() {
+        let start = Instant::now();
+        async fn press(processor: &mut InputProcessor, key: Key, modifiers: Vec<Modifier>, at: Instant) {
+            let mut event = processor.simulate_key_event(key, modifiers, None);
+            event.timestamp = at;
+            processor.process_key_event(event).await.unwrap();
+        }
+        let after = |ms| start + Duration::from_millis(ms);
+        let mut processor = create_test_processor();
+        processor
+            .add_custom_keybinding("ctrl+j ctrl+c", InputAction::ScrollToTop, KeyBindingContext::Global, 100)
+            .unwrap();
+        processor.add_custom_keybinding("ctrl+j", InputAction::Clear, KeyBindingContext::Global, 100).unwrap();
+        processor.add_custom_keybinding("g g", InputAction::ScrollToBottom, KeyBindingContext::Global, 100).unwrap();
+        assert!(processor.fast_path(Key::Char('g'), &HashSet::new()).is_none());
+        assert!(
+            processor
+                .list_active_keybindings()
+                .contains(&("ctrl+j ctrl+c".to_string(), "ScrollToTop".to_string()))
+        );
+
+        // Completed: only the sequence's action
+        press(&mut processor, Key::Char('j'), vec![Modifier::Ctrl], after(0)).await;
+        assert!(processor.try_receive_action().is_none());
+        assert_eq!(processor.sequence_deadline(), Some(after(1000)));
+        press(&mut processor, Key::Char('c'), vec![Modifier::Ctrl], after(300)).await;
+        assert!(matches!(processor.try_receive_action(), Some(InputAction::ScrollToTop)));
+        assert!(processor.try_receive_action().is_none());
+        assert_eq!(processor.sequence_deadline(), None);
+
+        // Timed out: the prefix fires its own binding, or is typed
+        press(&mut processor, Key::Char('j'), vec![Modifier::Ctrl], after(2000)).await;
+        assert!(!processor.flush_expired_sequence(after(2999)).await.unwrap());
+        assert!(processor.flush_expired_sequence(after(3000)).await.unwrap());
+        assert!(matches!(processor.try_receive_action(), Some(InputAction::Clear)));
+        press(&mut processor, Key::Char('g'), vec![], after(4000)).await;
+        assert!(processor.try_receive_action().is_none());
+        press(&mut processor, Key::Char('x'), vec![], after(6000)).await;
+        let sent: Vec<_> = std::iter::from_fn(|| processor.try_receive_action())
+            .map(|action| match action {
+                InputAction::SendToTerminal(text) => text,
+                other => panic!("unexpected action {:?}", other),
+            })
+            .collect();
+        assert_eq!(sent, ["g", "x"]);
+
+        // A key that doesn't continue the sequence lets it go first
+        press(&mut processor, Key::Char('g'), vec![], after(7000)).await;
+        press(&mut processor, Key::Char('y'), vec![], after(7100)).await;
+        let sent: Vec<_> = std::iter::from_fn(|| processor.try_receive_action()).collect();
+        assert!(matches!(&sent[..], [InputAction::SendToTerminal(g), InputAction::SendToTerminal(y)] if g == "g" && y == "y"));
+
+        // Escape drops the held keys
+        press(&mut processor, Key::Char('g'), vec![], after(8000)).await;
+        press(&mut processor, Key::Escape, vec![], after(8100)).await;
+        assert_eq!(processor.sequence_deadline(), None);
+        assert!(!processor.flush_expired_sequence(after(9000)).await.unwrap());
+        assert!(processor.try_receive_action().is_none());
+
+        assert!(processor.remove_keybinding("g g", KeyBindingContext::Global).unwrap());
+        assert!(processor.fast_path(Key::Char('g'), &HashSet::new()).is_some());
+    }
+
     #[tokio::test]
     async fn test_prefix_detection() {
         let mut processor = create_test_processor();
@@ -1112,6 +2116,141 @@ mod tests {
         assert_eq!(processor.get_command_buffer(), "");
     }
 
+    #[tokio::test]
+    async fn test_prefix_line_editing() {
+        async fn press(processor: &mut InputProcessor, key: Key, modifiers: Vec<Modifier>) {
+            let event = processor.simulate_key_event(key, modifiers, None);
+            processor.process_key_event(event).await.unwrap();
+        }
+        async fn type_text(processor: &mut InputProcessor, text: &str) {
+            for c in text.chars() {
+                press(processor, Key::Char(c), vec![]).await;
+            }
+        }
+
+        // Emacs mode: readline keys edit the line, not the shell's
+        let mut processor = create_test_processor();
+        processor.set_shell_mode(ShellMode::Emacs);
+        type_text(&mut processor, "pexplain this").await;
+        press(&mut processor, Key::Char('w'), vec![Modifier::Ctrl]).await;
+        press(&mut processor, Key::Char('a'), vec![Modifier::Ctrl]).await;
+        type_text(&mut processor, " ").await;
+        press(&mut processor, Key::Char('e'), vec![Modifier::Ctrl]).await;
+        type_text(&mut processor, "that").await;
+        assert_eq!(processor.get_command_buffer(), " explain that");
+        assert_eq!(processor.command_line().unwrap().style(), EditStyle::Emacs);
+        assert!(processor.try_receive_action().is_none());
+
+        // Vi mode: Escape leaves insert mode instead of the line
+        let mut processor = create_test_processor();
+        processor.set_shell_mode(ShellMode::Vi);
+        type_text(&mut processor, "p hello world").await;
+        press(&mut processor, Key::Escape, vec![]).await;
+        let line = processor.command_line().unwrap();
+        assert_eq!(line.mode(), crate::line_editor::EditMode::Normal);
+        assert_eq!(line.cursor(), 11);
+        type_text(&mut processor, "bdw0x").await;
+        assert_eq!(processor.get_command_buffer(), "hello ");
+        press(&mut processor, Key::Escape, vec![]).await;
+        assert!(!processor.is_prefix_mode());
+        assert!(processor.command_line().is_none());
+
+        // Tab completes command names, then their arguments
+        type_text(&mut processor, "pthe").await;
+        press(&mut processor, Key::Tab, vec![]).await;
+        assert_eq!(processor.get_command_buffer(), "theme ");
+        press(&mut processor, Key::Tab, vec![]).await;
+        assert_eq!(processor.command_line().unwrap().completions().unwrap().0, ["auto", "dark", "light"]);
+        press(&mut processor, Key::Tab, vec![]).await;
+        press(&mut processor, Key::Tab, vec![]).await;
+        assert_eq!(processor.get_command_buffer(), "theme dark");
+        type_text(&mut processor, "x").await;
+        assert!(processor.command_line().unwrap().completions().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_keys_become_actions() {
+        async fn press(processor: &mut InputProcessor, key: Key, modifiers: Vec<Modifier>) {
+            let event = processor.simulate_key_event(key, modifiers, None);
+            processor.process_key_event(event).await.unwrap();
+        }
+        let mut processor = create_test_processor();
+
+        // A prefix line is parsed whole and nothing of it reaches the shell
+        for key in [Key::Char('p'), Key::Char(' '), Key::Char('h'), Key::Char('i'), Key::Enter] {
+            press(&mut processor, key, vec![]).await;
+        }
+        match processor.try_receive_action() {
+            Some(InputAction::ExecuteParsedCommand(parsed)) => {
+                assert!(matches!(parsed.command, crate::command_parser::Command::Agent(agent) if agent.prompt == "hi"));
+            }
+            other => panic!("unexpected action {:?}", other),
+        }
+        assert!(processor.try_receive_action().is_none());
+
+        // Unbound control and alt chords send their terminal bytes
+        press(&mut processor, Key::Char('b'), vec![Modifier::Ctrl]).await;
+        press(&mut processor, Key::Char('x'), vec![Modifier::Alt]).await;
+        let sent: Vec<_> = std::iter::from_fn(|| processor.try_receive_action())
+            .map(|action| match action {
+                InputAction::SendToTerminal(text) => text,
+                other => panic!("unexpected action {:?}", other),
+            })
+            .collect();
+        assert_eq!(sent, ["\x02", "\x1bx"]);
+
+        // A held backslash that does not escape the prefix is sent after all
+        press(&mut processor, Key::Enter, vec![]).await;
+        processor.try_receive_action();
+        press(&mut processor, Key::Char('\\'), vec![]).await;
+        assert!(processor.try_receive_action().is_none());
+        press(&mut processor, Key::Char('l'), vec![]).await;
+        let sent: Vec<_> = std::iter::from_fn(|| processor.try_receive_action()).collect();
+        assert!(matches!(&sent[..], [InputAction::SendToTerminal(a), InputAction::SendToTerminal(b)] if a == "\\" && b == "l"));
+
+
+        // Scrolling keys move the view instead of reaching the shell
+        press(&mut processor, Key::PageUp, vec![Modifier::Shift]).await;
+        press(&mut processor, Key::End, vec![Modifier::Ctrl]).await;
+        assert!(matches!(processor.try_receive_action(), Some(InputAction::ScrollPageUp)));
+        assert!(matches!(processor.try_receive_action(), Some(InputAction::ScrollToBottom)));
+
+        // Tab keys open and cycle tabs
+        press(&mut processor, Key::Char('t'), vec![Modifier::Ctrl, Modifier::Shift]).await;
+        press(&mut processor, Key::Tab, vec![Modifier::Ctrl]).await;
+        assert!(matches!(processor.try_receive_action(), Some(InputAction::NewTab)));
+        assert!(matches!(processor.try_receive_action(), Some(InputAction::NextTab)));
+
+        // Pane keys split and move the focus
+        press(&mut processor, Key::Char('e'), vec![Modifier::Ctrl, Modifier::Shift]).await;
+        press(&mut processor, Key::Left, vec![Modifier::Alt]).await;
+        assert!(matches!(processor.try_receive_action(), Some(InputAction::SplitRight)));
+        assert!(matches!(processor.try_receive_action(), Some(InputAction::FocusPaneLeft)));
+    }
+
+    #[tokio::test]
+    async fn test_composed_text_is_sent_whole() {
+        let mut processor = create_test_processor();
+        processor.set_terminal_context(TerminalContext {
+            at_input_start: Some(false),
+            ..TerminalContext::default()
+        });
+        let event = processor.simulate_key_event(Key::Char('e'), vec![], Some("é".to_string()));
+        processor.process_key_event(event).await.unwrap();
+        let event = processor.simulate_key_event(Key::Char('`'), vec![], Some("`a".to_string()));
+        processor.process_key_event(event).await.unwrap();
+        let event = processor.simulate_key_event(Key::Char('c'), vec![Modifier::Ctrl], Some("c".to_string()));
+        processor.process_key_event(event).await.unwrap();
+
+        let sent: Vec<_> = std::iter::from_fn(|| processor.try_receive_action())
+            .filter_map(|action| match action {
+                InputAction::SendToTerminal(text) => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sent, ["é", "`a"]);
+    }
+
     #[tokio::test]
     async fn test_command_execution() {
         let mut processor = create_test_processor();
@@ -1152,6 +2291,103 @@ mod tests {
         assert!(!processor.is_prefix_mode());
     }
 
+    #[test]
+    fn test_fast_path_leaves_bindings_and_prefix_mode_alone() {
+        let mut processor = create_test_processor();
+        processor.set_shell_mode(ShellMode::Emacs);
+        let none = HashSet::new();
+
+        // Prefix char at line start must reach the prefix detector
+        assert_eq!(processor.fast_path(Key::Char('p'), &none), None);
+        assert_eq!(processor.fast_path(Key::Char('l'), &none).unwrap().as_bytes(), b"l");
+        assert_eq!(processor.fast_path(Key::Char('p'), &none).unwrap().as_bytes(), b"p");
+        assert_eq!(processor.fast_path(Key::Char('é'), &none).unwrap().as_bytes(), "é".as_bytes());
+        assert_eq!(processor.fast_path(Key::Enter, &none).unwrap().as_bytes(), b"\r");
+        assert_eq!(processor.get_input_stats().fast_path_keys, 4);
+
+        // Modified keys and non-echoable keys take the full path
+        let ctrl: HashSet<_> = [Modifier::Ctrl].into_iter().collect();
+        assert_eq!(processor.fast_path(Key::Char('c'), &ctrl), None);
+        assert_eq!(processor.fast_path(Key::Up, &none), None);
+
+        // A binding on a plain key intercepts it
+        processor
+            .add_custom_keybinding("q", InputAction::Copy, KeyBindingContext::Emacs, 100)
+            .unwrap();
+        assert_eq!(processor.fast_path(Key::Char('q'), &none), None);
+
+        // Once in prefix mode every key belongs to the command buffer
+        processor.prefix_state.lock().detected = true;
+        assert!(processor.is_prefix_mode());
+        assert_eq!(processor.fast_path(Key::Char('x'), &none), None);
+        processor.cancel_command();
+
+        processor.input_state.lock().in_paste_mode = true;
+        assert_eq!(processor.fast_path(Key::Char('x'), &none), None);
+    }
+
+    #[test]
+    fn test_prefix_passes_through_to_applications() {
+        let mut processor = create_test_processor();
+        processor.set_shell_mode(ShellMode::Emacs);
+        let none = HashSet::new();
+        let p = |modifiers: &[Modifier]| KeyEvent {
+            key: Key::Char('p'),
+            modifiers: modifiers.iter().cloned().collect(),
+            text: Some("p".to_string()),
+            repeat: false,
+            timestamp: Instant::now(),
+            key_code: None,
+        };
+
+        // A full-screen application owns the alternate screen
+        processor.set_terminal_context(TerminalContext {
+            alternate_screen: true,
+            ..TerminalContext::default()
+        });
+        assert!(matches!(processor.check_prefix_activation(&p(&[])).unwrap(), PrefixOutcome::Pass));
+        assert!(!processor.is_prefix_mode());
+        assert_eq!(processor.fast_path(Key::Char('p'), &none).unwrap().as_bytes(), b"p");
+
+        // So does a suppressed foreground process, even on the main screen
+        processor.set_terminal_context(TerminalContext {
+            foreground_process: Some("vim".to_string()),
+            at_input_start: Some(true),
+            ..TerminalContext::default()
+        });
+        assert!(matches!(processor.check_prefix_activation(&p(&[])).unwrap(), PrefixOutcome::Pass));
+        assert!(!processor.is_prefix_mode());
+        assert_eq!(processor.fast_path(Key::Char('p'), &none).unwrap().as_bytes(), b"p");
+
+        // Prompt marks decide line start when the shell sends them
+        processor.set_terminal_context(TerminalContext {
+            foreground_process: Some("bash".to_string()),
+            at_input_start: Some(false),
+            ..TerminalContext::default()
+        });
+        assert!(processor.fast_path(Key::Char('p'), &none).is_some());
+        processor.set_terminal_context(TerminalContext {
+            at_input_start: Some(true),
+            ..TerminalContext::default()
+        });
+        assert_eq!(processor.fast_path(Key::Char('p'), &none), None);
+        processor.check_prefix_activation(&p(&[])).unwrap();
+        assert!(processor.is_prefix_mode());
+        processor.cancel_command();
+
+        // With a required modifier the bare key is always literal
+        processor.keymap_config.write().prefix_modifier = "alt".to_string();
+        assert!(processor.fast_path(Key::Char('p'), &none).is_some());
+        processor.check_prefix_activation(&p(&[])).unwrap();
+        assert!(!processor.is_prefix_mode());
+        processor.set_terminal_context(TerminalContext {
+            at_input_start: Some(false),
+            ..TerminalContext::default()
+        });
+        processor.check_prefix_activation(&p(&[Modifier::Alt])).unwrap();
+        assert!(processor.is_prefix_mode());
+    }
+
     #[tokio::test]
     async fn test_keybinding_priority() {
         let mut processor = create_test_processor();
@@ -1185,7 +2421,7 @@ mod tests {
 
     #[test]
     fn test_performance_key_lookup() {
-        let processor = create_test_processor();
+        let mut processor = create_test_processor();
         let event = processor.simulate_key_event(
             Key::Char('c'), 
             vec![Modifier::Ctrl], 
@@ -1225,13 +2461,14 @@ mod tests {
 
     #[test]
     fn test_shell_mode_detection() {
-        std::env::set_var("EDITOR", "vim");
+        // SAFETY: no other test reads or writes EDITOR
+        unsafe { std::env::set_var("EDITOR", "vim") };
         let processor = create_test_processor();
         
         let detected_mode = processor.detect_shell_mode();
         assert!(matches!(detected_mode, ShellMode::Vi));
         
-        std::env::remove_var("EDITOR");
+        unsafe { std::env::remove_var("EDITOR") };
     }
 
     #[tokio::test]
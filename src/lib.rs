@@ -1,10 +1,62 @@
+pub mod adapter_conformance;
+pub mod agent_prompt;
+pub mod appearance;
+pub mod batch;
+pub mod bitmap_font;
+pub mod buffer_search;
+pub mod calc;
+pub mod clipboard;
+pub mod column_guides;
+pub mod command_history;
 pub mod command_parser;
+pub mod command_registry;
 pub mod config;
+pub mod config_provenance;
+pub mod damage;
+pub mod fold_map;
+pub mod ghost_text;
+pub mod glyph_guard;
+pub mod gpu_timing;
+pub mod grid_delta;
+pub mod hyperlinks;
+pub mod idle_lock;
+pub mod image_placement;
 pub mod input;
+pub mod line_editor;
+#[cfg(feature = "llama")]
+pub mod llama;
+pub mod markdown_stream;
+pub mod messages;
+pub mod model_host;
+pub mod model_registry;
+pub mod mouse_wheel;
+pub mod multiplexer;
+pub mod output_records;
+pub mod output_scheduler;
+pub mod pane_border;
+pub mod paste_guard;
+pub mod read_only;
+pub mod render_budget;
+pub mod render_caps;
+pub mod response_browser;
+pub mod response_log;
+pub mod scaffold;
+pub mod scrollback;
 pub mod simple_renderer;
+pub mod startup;
+pub mod stats_overlay;
+pub mod syntax_highlight;
+pub mod system_font;
 pub mod terminal;
 pub mod terminal_parser;
+pub mod theme;
+pub mod title;
+pub mod trace;
 pub mod tty;
+pub mod usage;
+pub mod vram_probe;
+pub mod watchdog;
+pub mod window_manager;
 
 // TODO: Enable these modules after fixing compilation issues
 // pub mod agent_api;
@@ -12,8 +64,6 @@ pub mod tty;
 // pub mod markdown_renderer;
 // pub mod media_display;
 // pub mod metal_backend;
-// pub mod model_host;
-// pub mod multiplexer;
 // pub mod oci_launcher;
 // pub mod os_agent;
 // pub mod profile_cache;
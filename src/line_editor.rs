@@ -0,0 +1,563 @@
+//! The prefix command line's editor: readline-style keys in emacs mode,
+//! insert and normal modes in vi mode, and the row it is drawn as
+
+use crate::input::{InputAction, Key, Modifier};
+use crate::paste_guard::{self, SpanStyle};
+use crate::terminal::TerminalCell;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EditStyle {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EditMode {
+    #[default]
+    Insert,
+    /// Vi only: keys move and edit instead of typing
+    Normal,
+}
+
+/// What a key did to the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEdit {
+    Edited,
+    Submit,
+    /// Leave prefix mode without running anything
+    Cancel,
+    /// Not an editing key here; emacs bindings may still apply
+    Ignored,
+}
+
+/// Character classes vi words are made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+fn class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Space
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineEditor {
+    chars: Vec<char>,
+    /// Between characters in insert mode, on one in normal mode
+    cursor: usize,
+    style: EditStyle,
+    mode: EditMode,
+    /// `d` or `c` waiting for its motion
+    operator: Option<char>,
+    /// Candidates offered by the last Tab, until another key is typed
+    completion: Option<Completion>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Completion {
+    candidates: Vec<String>,
+    /// Where the completed word starts
+    start: usize,
+    /// The candidate in the line, once Tab has been pressed again
+    selected: Option<usize>,
+}
+
+impl LineEditor {
+    pub fn new(style: EditStyle) -> Self {
+        Self { style, ..Self::default() }
+    }
+
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    /// In characters from the start of the line
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn style(&self) -> EditStyle {
+        self.style
+    }
+
+    pub fn mode(&self) -> EditMode {
+        self.mode
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    /// Empty, in insert mode, with `style` for the next line
+    pub fn reset(&mut self, style: EditStyle) {
+        *self = Self::new(style);
+    }
+
+    pub fn key(&mut self, key: Key, modifiers: &HashSet<Modifier>) -> LineEdit {
+        self.completion = None;
+        if modifiers.iter().any(|modifier| *modifier != Modifier::Shift) {
+            return LineEdit::Ignored;
+        }
+        match key {
+            Key::Enter => LineEdit::Submit,
+            Key::Escape => match (self.style, self.mode) {
+                (EditStyle::Vi, EditMode::Insert) => {
+                    self.mode = EditMode::Normal;
+                    self.cursor = self.cursor.saturating_sub(1);
+                    LineEdit::Edited
+                }
+                _ => LineEdit::Cancel,
+            },
+            Key::Left => self.move_to(self.cursor.saturating_sub(1)),
+            Key::Right => self.move_to(self.cursor + 1),
+            Key::Home => self.move_to(0),
+            Key::End => self.move_to(self.chars.len()),
+            Key::Delete => {
+                self.delete(self.cursor, self.cursor + 1);
+                LineEdit::Edited
+            }
+            _ if self.mode == EditMode::Normal => self.normal_key(key),
+            Key::Backspace if self.chars.is_empty() => LineEdit::Cancel,
+            Key::Backspace => {
+                self.delete(self.cursor.saturating_sub(1), self.cursor);
+                LineEdit::Edited
+            }
+            Key::Space => self.insert(' '),
+            Key::Char(c) => self.insert(c),
+            _ => LineEdit::Ignored,
+        }
+    }
+
+    /// The readline actions emacs mode binds, applied to this line rather
+    /// than the shell's; false for any other action
+    pub fn apply(&mut self, action: &InputAction) -> bool {
+        self.completion = None;
+        match action {
+            InputAction::LineStart => self.cursor = 0,
+            InputAction::LineEnd => self.cursor = self.chars.len(),
+            InputAction::WordBack => self.cursor = self.readline_word_back(),
+            InputAction::WordForward => self.cursor = self.readline_word_forward(),
+            InputAction::DeleteWord => {
+                // Back to whitespace, as unix-word-rubout does
+                let mut start = self.cursor;
+                while start > 0 && self.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !self.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                self.delete(start, self.cursor);
+            }
+            InputAction::DeleteToEnd => self.delete(self.cursor, self.chars.len()),
+            InputAction::DeleteToStart => self.delete(0, self.cursor),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The line up to the cursor, which completion looks at
+    pub fn before_cursor(&self) -> String {
+        self.chars[..self.cursor.min(self.chars.len())].iter().collect()
+    }
+
+    /// Complete the word before the cursor from `candidates`, each a whole
+    /// word: one is put in with a space after, several are narrowed to
+    /// their common start and kept for Tab to cycle through
+    pub fn complete(&mut self, candidates: Vec<String>) {
+        let cursor = self.cursor.min(self.chars.len());
+        let start = self.chars[..cursor]
+            .iter()
+            .rposition(|c| c.is_whitespace())
+            .map_or(0, |space| space + 1);
+        match candidates.as_slice() {
+            [] => {}
+            [only] => {
+                // Directories stay open for the next part of the path
+                let word = if only.ends_with('/') { only.clone() } else { format!("{} ", only) };
+                self.replace_word(start, &word);
+            }
+            [first, rest @ ..] => {
+                let common = rest.iter().fold(first.chars().count(), |len, candidate| {
+                    first.chars().zip(candidate.chars()).take(len).take_while(|(a, b)| a == b).count()
+                });
+                let common: String = first.chars().take(common).collect();
+                if common.chars().count() > cursor - start {
+                    self.replace_word(start, &common);
+                }
+                self.completion = Some(Completion { candidates, start, selected: None });
+            }
+        }
+    }
+
+    /// Put the next candidate in the line; false when no completion is
+    /// being offered
+    pub fn next_completion(&mut self) -> bool {
+        let Some(mut completion) = self.completion.take() else {
+            return false;
+        };
+        let next = completion.selected.map_or(0, |index| (index + 1) % completion.candidates.len());
+        completion.selected = Some(next);
+        self.replace_word(completion.start, &completion.candidates[next].clone());
+        self.completion = Some(completion);
+        true
+    }
+
+    /// The candidates on offer and which one is in the line
+    pub fn completions(&self) -> Option<(&[String], Option<usize>)> {
+        self.completion
+            .as_ref()
+            .map(|completion| (completion.candidates.as_slice(), completion.selected))
+    }
+
+    /// Swap `start..cursor` for `word`, leaving the cursor after it
+    fn replace_word(&mut self, start: usize, word: &str) {
+        let cursor = self.cursor.min(self.chars.len());
+        self.chars.splice(start..cursor, word.chars());
+        self.cursor = start + word.chars().count();
+        self.clamp();
+    }
+
+    fn insert(&mut self, c: char) -> LineEdit {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+        LineEdit::Edited
+    }
+
+    /// Remove `start..end` and leave the cursor at `start`
+    fn delete(&mut self, start: usize, end: usize) {
+        let end = end.min(self.chars.len());
+        if start < end {
+            self.chars.drain(start..end);
+        }
+        self.cursor = start.min(end);
+        self.clamp();
+    }
+
+    fn move_to(&mut self, position: usize) -> LineEdit {
+        self.cursor = position;
+        self.clamp();
+        LineEdit::Edited
+    }
+
+    /// Keep the cursor on the line: past the end only in insert mode
+    fn clamp(&mut self) {
+        let last = match self.mode {
+            EditMode::Insert => self.chars.len(),
+            EditMode::Normal => self.chars.len().saturating_sub(1),
+        };
+        self.cursor = self.cursor.min(last);
+    }
+
+    fn enter_insert(&mut self, position: usize) -> LineEdit {
+        self.mode = EditMode::Insert;
+        self.move_to(position)
+    }
+
+    fn normal_key(&mut self, key: Key) -> LineEdit {
+        let Key::Char(c) = key else {
+            self.operator = None;
+            return match key {
+                Key::Backspace => self.move_to(self.cursor.saturating_sub(1)),
+                _ => LineEdit::Ignored,
+            };
+        };
+        if let Some(operator) = self.operator.take() {
+            return self.operate(operator, c);
+        }
+        match c {
+            'h' => self.move_to(self.cursor.saturating_sub(1)),
+            'l' => self.move_to(self.cursor + 1),
+            'w' => self.move_to(self.word_forward()),
+            'b' => self.move_to(self.word_back()),
+            '0' => self.move_to(0),
+            '$' => self.move_to(self.chars.len()),
+            'x' => {
+                self.delete(self.cursor, self.cursor + 1);
+                LineEdit::Edited
+            }
+            'd' | 'c' => {
+                self.operator = Some(c);
+                LineEdit::Edited
+            }
+            'i' => self.enter_insert(self.cursor),
+            'a' => self.enter_insert((self.cursor + 1).min(self.chars.len())),
+            'A' => self.enter_insert(self.chars.len()),
+            'I' => self.enter_insert(0),
+            _ => LineEdit::Ignored,
+        }
+    }
+
+    /// `d` or `c` with its motion; `dd` and `cc` take the whole line
+    fn operate(&mut self, operator: char, motion: char) -> LineEdit {
+        let (start, end) = match motion {
+            m if m == operator => (0, self.chars.len()),
+            // `cw` stops at the end of the word, as in vi
+            'w' if operator == 'c' => (self.cursor, self.run_end(self.cursor)),
+            'w' => (self.cursor, self.word_forward()),
+            'b' => (self.word_back(), self.cursor),
+            '0' => (0, self.cursor),
+            '$' => (self.cursor, self.chars.len()),
+            'l' => (self.cursor, self.cursor + 1),
+            'h' => (self.cursor.saturating_sub(1), self.cursor),
+            _ => return LineEdit::Ignored,
+        };
+        if operator == 'c' {
+            self.mode = EditMode::Insert;
+        }
+        self.delete(start, end);
+        LineEdit::Edited
+    }
+
+    /// End of the run of same-class characters at `position`
+    fn run_end(&self, position: usize) -> usize {
+        let Some(&first) = self.chars.get(position) else {
+            return position;
+        };
+        let mut end = position;
+        while end < self.chars.len() && class(self.chars[end]) == class(first) {
+            end += 1;
+        }
+        end
+    }
+
+    /// Start of the next word, or the end of the line
+    fn word_forward(&self) -> usize {
+        let mut position = match self.chars.get(self.cursor) {
+            Some(&c) if class(c) != CharClass::Space => self.run_end(self.cursor),
+            _ => self.cursor,
+        };
+        while position < self.chars.len() && class(self.chars[position]) == CharClass::Space {
+            position += 1;
+        }
+        position
+    }
+
+    /// Start of this word if the cursor is inside one, else of the one
+    /// before
+    fn word_back(&self) -> usize {
+        let mut position = self.cursor;
+        while position > 0 && class(self.chars[position - 1]) == CharClass::Space {
+            position -= 1;
+        }
+        if position == 0 {
+            return 0;
+        }
+        let run = class(self.chars[position - 1]);
+        while position > 0 && class(self.chars[position - 1]) == run {
+            position -= 1;
+        }
+        position
+    }
+
+    /// Readline words are letters and digits
+    fn readline_word_back(&self) -> usize {
+        let mut position = self.cursor;
+        while position > 0 && !self.chars[position - 1].is_alphanumeric() {
+            position -= 1;
+        }
+        while position > 0 && self.chars[position - 1].is_alphanumeric() {
+            position -= 1;
+        }
+        position
+    }
+
+    fn readline_word_forward(&self) -> usize {
+        let mut position = self.cursor;
+        while position < self.chars.len() && !self.chars[position].is_alphanumeric() {
+            position += 1;
+        }
+        while position < self.chars.len() && self.chars[position].is_alphanumeric() {
+            position += 1;
+        }
+        position
+    }
+
+    /// The line as a bottom-row bar: the prefix, the text scrolled to keep
+    /// the cursor in view, the cursor cell inverted, and the vi mode
+    pub fn row(&self, prefix: &str, width: u32) -> Vec<TerminalCell> {
+        let mode = match (self.style, self.mode) {
+            (EditStyle::Emacs, _) => String::new(),
+            (EditStyle::Vi, EditMode::Insert) => " -- INSERT --".to_string(),
+            (EditStyle::Vi, EditMode::Normal) => " -- NORMAL --".to_string(),
+        };
+        let lead = format!(" {} ", prefix);
+        let lead_len = lead.chars().count();
+        let room = (width as usize).saturating_sub(lead_len + mode.chars().count() + 1).max(1);
+        // Scroll so the cursor's cell stays on the bar
+        let first = (self.cursor + 1).saturating_sub(room);
+        let visible: String = self.chars.iter().skip(first).take(room).collect();
+        let gap = room.saturating_sub(visible.chars().count()) + 1;
+
+        let mut row = paste_guard::cells(
+            &[
+                (SpanStyle::Control, lead),
+                (SpanStyle::Plain, visible),
+                (SpanStyle::Plain, " ".repeat(gap)),
+                (SpanStyle::Control, mode),
+            ],
+            width,
+            false,
+        );
+        if let Some(cell) = row.get_mut(lead_len + self.cursor - first) {
+            std::mem::swap(&mut cell.foreground, &mut cell.background);
+        }
+        row
+    }
+
+    /// The candidates from the last Tab, dimmed, with the one in the line
+    /// picked out; `None` when nothing is on offer
+    pub fn candidate_row(&self, width: u32) -> Option<Vec<TerminalCell>> {
+        let (candidates, selected) = self.completions()?;
+        let background = [0.12, 0.12, 0.14, 1.0];
+        let dim = TerminalCell {
+            foreground: [0.55, 0.55, 0.6, 1.0],
+            background,
+            ..TerminalCell::default()
+        };
+        let mut row = vec![dim.clone()];
+        for (index, candidate) in candidates.iter().enumerate() {
+            let cell = match selected == Some(index) {
+                true => TerminalCell { foreground: [0.9, 0.9, 0.9, 1.0], bold: true, ..dim.clone() },
+                false => dim.clone(),
+            };
+            row.extend(format!(" {} ", candidate).chars().map(|character| TerminalCell { character, ..cell.clone() }));
+        }
+        row.resize(width as usize, dim);
+        Some(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(style: EditStyle, keys: &str) -> LineEditor {
+        let mut line = LineEditor::new(style);
+        for c in keys.chars() {
+            let key = match c {
+                '\x1b' => Key::Escape,
+                '\x08' => Key::Backspace,
+                c => Key::Char(c),
+            };
+            line.key(key, &HashSet::new());
+        }
+        line
+    }
+
+    #[test]
+    fn test_vi_motions_and_edits() {
+        let line = typed(EditStyle::Vi, "ask the model\x1b");
+        assert_eq!(line.mode(), EditMode::Normal);
+        assert_eq!(line.cursor(), 12);
+
+        assert_eq!(typed(EditStyle::Vi, "ask the model\x1b0w").cursor(), 4);
+        assert_eq!(typed(EditStyle::Vi, "ask the model\x1bb").cursor(), 8);
+        assert_eq!(typed(EditStyle::Vi, "ask the model\x1b0$").cursor(), 12);
+        assert_eq!(typed(EditStyle::Vi, "ask the model\x1b0llh").cursor(), 1);
+        assert_eq!(typed(EditStyle::Vi, "ask the model\x1b0x").text(), "sk the model");
+        assert_eq!(typed(EditStyle::Vi, "ask the model\x1b0dw").text(), "the model");
+        assert_eq!(typed(EditStyle::Vi, "ask the model\x1bdd").text(), "");
+
+        let changed = typed(EditStyle::Vi, "ask the model\x1b0wcwa\x1b");
+        assert_eq!(changed.text(), "ask a model");
+        assert_eq!(changed.mode(), EditMode::Normal);
+    }
+
+    #[test]
+    fn test_vi_insert_commands() {
+        assert_eq!(typed(EditStyle::Vi, "bc\x1b0ia").text(), "abc");
+        assert_eq!(typed(EditStyle::Vi, "ac\x1b0ab").text(), "abc");
+        assert_eq!(typed(EditStyle::Vi, "ab\x1b0Ac").text(), "abc");
+        assert_eq!(typed(EditStyle::Vi, "bc\x1bIa").text(), "abc");
+
+        let mut line = typed(EditStyle::Vi, "ab\x1b");
+        assert_eq!(line.key(Key::Escape, &HashSet::new()), LineEdit::Cancel);
+        assert_eq!(typed(EditStyle::Emacs, "ab").key(Key::Escape, &HashSet::new()), LineEdit::Cancel);
+        assert_eq!(LineEditor::new(EditStyle::Vi).key(Key::Backspace, &HashSet::new()), LineEdit::Cancel);
+    }
+
+    #[test]
+    fn test_emacs_actions_edit_the_line() {
+        let mut line = typed(EditStyle::Emacs, "explain foo-bar baz");
+        assert!(line.apply(&InputAction::WordBack));
+        assert_eq!(line.cursor(), 16);
+        assert!(line.apply(&InputAction::DeleteWord));
+        assert_eq!(line.text(), "explain baz");
+        assert!(line.apply(&InputAction::LineStart));
+        assert!(line.apply(&InputAction::WordForward));
+        assert_eq!(line.cursor(), 7);
+        assert!(line.apply(&InputAction::DeleteToEnd));
+        assert_eq!(line.text(), "explain");
+        line.apply(&InputAction::WordBack);
+        line.key(Key::Char('x'), &HashSet::new());
+        assert!(line.apply(&InputAction::DeleteToStart));
+        assert_eq!(line.text(), "explain");
+        assert!(line.apply(&InputAction::LineEnd));
+        assert_eq!(line.cursor(), 7);
+        assert!(!line.apply(&InputAction::Copy));
+        assert_eq!(line.key(Key::Char('a'), &[Modifier::Ctrl].into_iter().collect()), LineEdit::Ignored);
+    }
+
+    #[test]
+    fn test_completion_inline_and_cycling() {
+        let mut line = typed(EditStyle::Emacs, "mo");
+        line.complete(vec!["model".to_string()]);
+        assert_eq!(line.text(), "model ");
+        assert!(!line.next_completion());
+
+        let mut line = typed(EditStyle::Emacs, "session restore w");
+        line.complete(vec!["work".to_string(), "work-old".to_string(), "web".to_string()]);
+        assert_eq!(line.text(), "session restore w");
+        line.complete(vec!["work".to_string(), "work-old".to_string()]);
+        assert_eq!(line.text(), "session restore work");
+        assert!(line.next_completion());
+        assert_eq!(line.text(), "session restore work");
+        assert!(line.next_completion());
+        assert_eq!(line.text(), "session restore work-old");
+        assert!(line.next_completion());
+        assert_eq!(line.text(), "session restore work");
+
+        let row = line.candidate_row(30).unwrap();
+        let text: String = row.iter().map(|cell| cell.character).collect();
+        assert_eq!(text.trim_end(), "  work  work-old");
+        assert!(row[2].bold && !row[9].bold);
+
+        // Typing drops the candidates
+        line.key(Key::Char('s'), &HashSet::new());
+        assert!(line.completions().is_none());
+        assert!(line.candidate_row(30).is_none());
+
+        let mut line = typed(EditStyle::Emacs, "scaffold sr");
+        line.complete(vec!["src/".to_string()]);
+        assert_eq!(line.text(), "scaffold src/");
+    }
+
+    #[test]
+    fn test_row_shows_the_cursor() {
+        let line = typed(EditStyle::Vi, "hello\x1b");
+        let row = line.row(":", 30);
+        let text: String = row.iter().map(|cell| cell.character).collect();
+        assert_eq!(text, format!(" : hello{} -- NORMAL --", " ".repeat(9)));
+        assert_eq!(row.len(), 30);
+        let cursor = &row[3 + 4];
+        assert_eq!(cursor.character, 'o');
+        assert_eq!(cursor.background, [0.9, 0.9, 0.9, 1.0]);
+
+        // A long line scrolls to keep the cursor on the bar
+        let long = typed(EditStyle::Emacs, &"x".repeat(50));
+        let row = long.row(":", 20);
+        assert_eq!(row.len(), 20);
+        assert_eq!(row[17].character, 'x');
+        assert_eq!(row[18].background, [0.9, 0.9, 0.9, 1.0]);
+    }
+}
@@ -0,0 +1,375 @@
+//! llama.cpp behind the `llama` feature. A loaded GGUF model lives on a
+//! thread of its own with a single context: the context borrows the model,
+//! and decoding blocks. Requests reach the thread over a channel and run
+//! one at a time; dropping the session frees the context, then the model.
+
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::mpsc as std_mpsc;
+use std::sync::OnceLock;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::LlamaContext;
+use llama_cpp_2::gguf::GgufContext;
+use llama_cpp_2::llama_backend::LlamaBackend;
+use llama_cpp_2::llama_batch::LlamaBatch;
+use llama_cpp_2::model::params::LlamaModelParams;
+use llama_cpp_2::model::LlamaModel;
+use llama_cpp_2::sampling::LlamaSampler;
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+use crate::model_host::{FinishReason, InferenceParameters, ModelHostError};
+
+/// Prompt tokens decoded at a time
+const PROMPT_BATCH: usize = 512;
+/// Recent tokens the repetition penalties look back over
+const PENALTY_WINDOW: i32 = 64;
+/// llama.cpp's "pick a random seed"
+const RANDOM_SEED: u32 = u32::MAX;
+
+/// What a generation sends back: pieces as they are sampled, then one
+/// `Done` or `Failed`
+#[derive(Debug)]
+pub enum Generated {
+    Piece(String),
+    Done(GenerationStats),
+    Failed(ModelHostError),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationStats {
+    pub prompt_tokens: u32,
+    pub tokens: u32,
+    pub finish_reason: FinishReason,
+    pub prompt_eval_time: Duration,
+    pub eval_time: Duration,
+}
+
+struct Job {
+    prompt: String,
+    parameters: InferenceParameters,
+    events: mpsc::UnboundedSender<Generated>,
+}
+
+/// A model and its context on their own thread
+pub struct LlamaSession {
+    jobs: Option<std_mpsc::Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LlamaSession {
+    /// Map the model at `path` and create a context of `context_window`
+    /// tokens, offloading as many layers as fit in `vram_mb`. Blocks until
+    /// both are ready.
+    pub fn load(path: &Path, context_window: u32, vram_mb: u64) -> Result<Self, ModelHostError> {
+        let backend = backend()?;
+        let gpu_layers = gpu_layers(path, vram_mb);
+        let (jobs, job_rx) = std_mpsc::channel::<Job>();
+        let (ready_tx, ready_rx) = std_mpsc::channel();
+        let path = path.to_path_buf();
+        let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("model").to_string();
+
+        let thread = std::thread::Builder::new()
+            .name(format!("llama-{}", name))
+            .spawn(move || {
+                let params = LlamaModelParams::default().with_n_gpu_layers(gpu_layers).with_use_mmap(true);
+                let model = match LlamaModel::load_from_file(backend, &path, &params) {
+                    Ok(model) => model,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(ModelHostError::ModelLoad(format!("{}: {}", path.display(), e))));
+                        return;
+                    }
+                };
+                let params = LlamaContextParams::default()
+                    .with_n_ctx(NonZeroU32::new(context_window))
+                    .with_n_batch(PROMPT_BATCH as u32);
+                let mut context = match model.new_context(backend, params) {
+                    Ok(context) => context,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(ModelHostError::ModelLoad(format!("context for {}: {}", name, e))));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+                info!("llama.cpp loaded {} with {} GPU layers, {} token context", name, gpu_layers, context.n_ctx());
+
+                // Ends when the session drops its sender
+                for job in job_rx {
+                    let result = generate(&model, &mut context, &job);
+                    let _ = job.events.send(match result {
+                        Ok(stats) => Generated::Done(stats),
+                        Err(e) => Generated::Failed(e),
+                    });
+                }
+                debug!("llama.cpp freeing {}", name);
+            })
+            .map_err(|e| ModelHostError::ModelLoad(format!("llama.cpp thread: {}", e)))?;
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(Self { jobs: Some(jobs), thread: Some(thread) }),
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                Err(e)
+            }
+            Err(_) => Err(ModelHostError::ModelLoad("llama.cpp thread exited while loading".to_string())),
+        }
+    }
+
+    /// Queue a generation. It stops early once the receiver is dropped.
+    pub fn generate(
+        &self,
+        prompt: String,
+        parameters: InferenceParameters,
+    ) -> Result<mpsc::UnboundedReceiver<Generated>, ModelHostError> {
+        let (events, events_rx) = mpsc::unbounded_channel();
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send(Job { prompt, parameters, events }).ok())
+            .ok_or_else(|| ModelHostError::Inference("llama.cpp thread has stopped".to_string()))?;
+        Ok(events_rx)
+    }
+}
+
+impl Drop for LlamaSession {
+    /// Waits for the running generation, if any, to stop
+    fn drop(&mut self) {
+        drop(self.jobs.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Initialised once per process
+fn backend() -> Result<&'static LlamaBackend, ModelHostError> {
+    static BACKEND: OnceLock<Result<LlamaBackend, String>> = OnceLock::new();
+    BACKEND
+        .get_or_init(|| LlamaBackend::init().map_err(|e| e.to_string()))
+        .as_ref()
+        .map_err(|e| ModelHostError::ModelLoad(format!("llama.cpp backend: {}", e)))
+}
+
+/// Layers to offload for a model file given `vram_mb` of VRAM
+fn gpu_layers(path: &Path, vram_mb: u64) -> u32 {
+    let model_mb = std::fs::metadata(path).map_or(0, |meta| meta.len() / (1024 * 1024));
+    let blocks = block_count(path).unwrap_or(0);
+    offload_layers(blocks, model_mb, vram_mb)
+}
+
+/// None with no VRAM; all of them, output layer included, when the model
+/// fits or its size is unknown; otherwise the share of its `blocks` that fits
+pub fn offload_layers(blocks: u32, model_mb: u64, vram_mb: u64) -> u32 {
+    if vram_mb == 0 {
+        return 0;
+    }
+    if blocks == 0 || model_mb == 0 {
+        // llama.cpp offloads every layer when asked for more than it has
+        return u32::MAX;
+    }
+    if vram_mb >= model_mb {
+        return blocks + 1;
+    }
+    (blocks as u64 * vram_mb / model_mb) as u32
+}
+
+/// The `<architecture>.block_count` in a GGUF file's metadata
+fn block_count(path: &Path) -> Option<u32> {
+    let gguf = GgufContext::from_file(path)?;
+    let architecture = gguf.find_key("general.architecture");
+    if architecture < 0 {
+        return None;
+    }
+    let key = gguf.find_key(&format!("{}.block_count", gguf.val_str(architecture)?));
+    (key >= 0).then(|| gguf.val_u32(key))
+}
+
+/// Penalties, then top-k and top-p, then temperature; greedy at zero
+/// temperature
+fn sampler(model: &LlamaModel, parameters: &InferenceParameters) -> LlamaSampler {
+    let mut samplers = vec![LlamaSampler::penalties(
+        model.n_vocab(),
+        PENALTY_WINDOW,
+        parameters.repetition_penalty,
+        parameters.frequency_penalty,
+        parameters.presence_penalty,
+    )];
+    if parameters.temperature <= 0.0 {
+        samplers.push(LlamaSampler::greedy());
+        return LlamaSampler::chain_simple(samplers);
+    }
+    if let Some(top_k) = parameters.top_k {
+        samplers.push(LlamaSampler::top_k(top_k as i32));
+    }
+    samplers.push(LlamaSampler::top_p(parameters.top_p, 1));
+    samplers.push(LlamaSampler::temp(parameters.temperature));
+    samplers.push(LlamaSampler::dist(RANDOM_SEED));
+    LlamaSampler::chain_simple(samplers)
+}
+
+fn generate(model: &LlamaModel, context: &mut LlamaContext<'_>, job: &Job) -> Result<GenerationStats, ModelHostError> {
+    let inference = |e: &dyn std::fmt::Display| ModelHostError::Inference(format!("llama.cpp: {}", e));
+    let started = Instant::now();
+    let parameters = &job.parameters;
+    let vocab = model.vocab();
+    context.clear_kv_cache();
+
+    let prompt = vocab.tokenize(job.prompt.as_bytes(), true, false);
+    let window = context.n_ctx() as usize;
+    if prompt.is_empty() || prompt.len() >= window {
+        return Err(ModelHostError::Inference(format!(
+            "prompt is {} tokens, the context holds {}",
+            prompt.len(),
+            window
+        )));
+    }
+    let mut batch = LlamaBatch::new(PROMPT_BATCH, 1);
+    for (chunk_index, chunk) in prompt.chunks(PROMPT_BATCH).enumerate() {
+        batch.clear();
+        for (i, token) in chunk.iter().enumerate() {
+            let position = chunk_index * PROMPT_BATCH + i;
+            // Logits only for the last prompt token, which the first sample reads
+            batch.add(*token, position as i32, &[0], position + 1 == prompt.len()).map_err(|e| inference(&e))?;
+        }
+        context.decode(&mut batch).map_err(|e| inference(&e))?;
+    }
+    let prompt_eval_time = started.elapsed();
+
+    let mut sampler = sampler(model, parameters);
+    let limit = (parameters.max_tokens as usize).min(window - prompt.len());
+    let stops: Vec<&str> = parameters.stop_sequences.iter().map(String::as_str).filter(|stop| !stop.is_empty()).collect();
+    let mut text = String::new();
+    // Bytes of `text` sent so far, and of a character split across tokens
+    let mut sent = 0;
+    let mut partial = Vec::new();
+    let mut tokens = 0;
+    let finish_reason = loop {
+        if tokens >= limit {
+            break FinishReason::Length;
+        }
+        if job.events.is_closed() {
+            break FinishReason::Stop;
+        }
+        let token = sampler.sample(context, batch.n_tokens() - 1);
+        if vocab.is_eog(token) {
+            break FinishReason::Stop;
+        }
+        tokens += 1;
+
+        partial.extend(vocab.token_to_piece(token, false, None));
+        match String::from_utf8(std::mem::take(&mut partial)) {
+            Ok(piece) => text.push_str(&piece),
+            Err(e) => partial = e.into_bytes(),
+        }
+        if let Some(cut) = stops.iter().filter_map(|stop| text.find(stop)).min() {
+            text.truncate(cut);
+            break FinishReason::Stop;
+        }
+        // Hold back what may turn out to start a stop sequence
+        let ready = text.len() - held_back(&text, &stops);
+        if ready > sent {
+            let _ = job.events.send(Generated::Piece(text[sent..ready].to_string()));
+            sent = ready;
+        }
+
+        batch.clear();
+        batch.add(token, (prompt.len() + tokens - 1) as i32, &[0], true).map_err(|e| inference(&e))?;
+        context.decode(&mut batch).map_err(|e| inference(&e))?;
+    };
+    if text.len() > sent {
+        let _ = job.events.send(Generated::Piece(text[sent..].to_string()));
+    }
+
+    Ok(GenerationStats {
+        prompt_tokens: prompt.len() as u32,
+        tokens: tokens as u32,
+        finish_reason,
+        prompt_eval_time,
+        eval_time: started.elapsed() - prompt_eval_time,
+    })
+}
+
+/// Bytes at the end of `text` that begin one of `stops`
+fn held_back(text: &str, stops: &[&str]) -> usize {
+    stops
+        .iter()
+        .filter_map(|stop| {
+            (1..stop.len())
+                .rev()
+                .find(|&len| stop.is_char_boundary(len) && text.ends_with(&stop[..len]))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small GGUF model, e.g. a quantised TinyLlama; tests that load one
+    /// are skipped without it
+    fn test_model() -> Option<std::path::PathBuf> {
+        std::env::var_os("FERROTERM_TEST_GGUF").map(Into::into)
+    }
+
+    #[test]
+    fn test_offload_layers() {
+        assert_eq!(offload_layers(32, 4000, 0), 0);
+        assert_eq!(offload_layers(32, 4000, 8000), 33);
+        assert_eq!(offload_layers(32, 4000, 2000), 16);
+        assert_eq!(offload_layers(0, 4000, 2000), u32::MAX);
+    }
+
+    #[test]
+    fn test_held_back() {
+        let stops = ["\nUser:", "###"];
+        assert_eq!(held_back("Hello\nUs", &stops), 3);
+        assert_eq!(held_back("Hello #", &stops), 1);
+        assert_eq!(held_back("Hello", &stops), 0);
+    }
+
+    #[test]
+    fn test_generate_with_model() {
+        let Some(path) = test_model() else {
+            return;
+        };
+        let session = LlamaSession::load(&path, 512, 0).unwrap();
+        let parameters = InferenceParameters {
+            max_tokens: 8,
+            temperature: 0.0,
+            ..Default::default()
+        };
+        let mut events = session.generate("The capital of France is".to_string(), parameters).unwrap();
+        let mut text = String::new();
+        let stats = loop {
+            match events.blocking_recv().expect("generation ended without stats") {
+                Generated::Piece(piece) => text.push_str(&piece),
+                Generated::Done(stats) => break stats,
+                Generated::Failed(e) => panic!("generation failed: {}", e),
+            }
+        };
+        assert!(stats.tokens > 0 && stats.tokens <= 8);
+        assert!(stats.prompt_tokens > 0);
+        assert!(!text.is_empty());
+
+        // A stop sequence ends the text before it
+        let parameters = InferenceParameters {
+            max_tokens: 64,
+            temperature: 0.0,
+            stop_sequences: vec![" ".to_string()],
+            ..Default::default()
+        };
+        let mut events = session.generate("Count: one two three".to_string(), parameters).unwrap();
+        let mut text = String::new();
+        while let Some(event) = events.blocking_recv() {
+            match event {
+                Generated::Piece(piece) => text.push_str(&piece),
+                Generated::Done(stats) => assert_eq!(stats.finish_reason, FinishReason::Stop),
+                Generated::Failed(e) => panic!("generation failed: {}", e),
+            }
+        }
+        assert!(!text.contains(' '));
+    }
+}
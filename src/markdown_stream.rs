@@ -0,0 +1,762 @@
+use crate::glyph_guard::{self, GlyphLimits};
+use crate::messages;
+use crate::render_budget::{DegradationConfig, DegradationPolicy, RenderTier, StageCosts};
+use crate::render_caps::{self, ColumnAlign, RenderCapabilities};
+use crate::syntax_highlight::{self, SyntaxHighlighter};
+use crate::terminal::TerminalCell;
+use crate::theme::Theme;
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// A table being collected, cell text only
+#[derive(Default)]
+struct Table {
+    aligns: Vec<ColumnAlign>,
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+    row: Vec<String>,
+    cell: String,
+}
+
+/// Markdown rendered to rows as it streams in. Source up to the last
+/// block boundary (a blank line or a closing fence) is rendered once and
+/// kept; each push only re-renders the block still being written.
+///
+/// Pushes that keep taking longer than the budget simplify the rendering:
+/// first code is no longer highlighted, then the rest of the answer is
+/// appended as plain text, with a note under it. `finish` renders a
+/// simplified answer once more in full.
+pub struct MarkdownStream {
+    width: usize,
+    highlighter: Option<SyntaxHighlighter>,
+    capabilities: RenderCapabilities,
+    theme: Theme,
+    source: String,
+    /// Rows for `source[..stable_end]`, which can no longer change
+    stable: Vec<Vec<TerminalCell>>,
+    stable_end: usize,
+    /// Rows for the block after `stable_end`
+    tail: Vec<Vec<TerminalCell>>,
+    /// Where the scan for the next boundary picks up, and the fence open
+    /// at that point
+    scanned: usize,
+    open_fence: Option<String>,
+    /// Source bytes rendered by the last push
+    last_work: usize,
+    policy: DegradationPolicy,
+    /// Says the rendering was simplified, under the rows until `finish`
+    note: Vec<Vec<TerminalCell>>,
+    /// Time the current push spent highlighting code and wrapping rows
+    highlight_time: Cell<Duration>,
+    layout_time: Cell<Duration>,
+}
+
+impl MarkdownStream {
+    pub fn new(width: u32, highlighter: Option<SyntaxHighlighter>) -> Self {
+        Self {
+            width: (width as usize).max(1),
+            highlighter,
+            capabilities: RenderCapabilities::default(),
+            theme: Theme::default(),
+            source: String::new(),
+            stable: Vec::new(),
+            stable_end: 0,
+            tail: Vec::new(),
+            scanned: 0,
+            open_fence: None,
+            last_work: 0,
+            policy: DegradationPolicy::new(DegradationConfig::default()),
+            note: Vec::new(),
+            highlight_time: Cell::new(Duration::ZERO),
+            layout_time: Cell::new(Duration::ZERO),
+        }
+    }
+
+    /// When pushes count as over budget, and for how long before the
+    /// rendering is simplified
+    pub fn with_degradation(mut self, config: DegradationConfig) -> Self {
+        self.policy = DegradationPolicy::new(config);
+        self
+    }
+
+    /// Draw tables and bullets with what the font has
+    pub fn with_capabilities(mut self, capabilities: RenderCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Text and accent colors; rows already rendered keep theirs
+    pub fn with_theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    pub fn push(&mut self, text: &str) {
+        let started = Instant::now();
+        self.highlight_time.set(Duration::ZERO);
+        self.layout_time.set(Duration::ZERO);
+        self.source.push_str(text);
+        if self.policy.tier() == RenderTier::PlainText {
+            self.append_plain();
+        } else {
+            self.render_blocks();
+        }
+        let highlight = self.highlight_time.get();
+        let layout = self.layout_time.get();
+        self.record_cycle(StageCosts {
+            parse: started.elapsed().saturating_sub(highlight + layout),
+            highlight,
+            layout,
+        });
+    }
+
+    /// Step the rendering down when pushes keep blowing the budget. Going
+    /// to plain text keeps the complete lines rendered so far as markdown.
+    fn record_cycle(&mut self, costs: StageCosts) {
+        let Some(tier) = self.policy.record_cycle(costs) else {
+            return;
+        };
+        tracing::debug!("Simplifying answer rendering to {:?}", tier);
+        if self.note.is_empty() {
+            let style = TerminalCell {
+                dim: true,
+                italic: true,
+                ..self.plain()
+            };
+            let mut row = Vec::new();
+            push_text(&mut row, &messages::current().rendering_simplified(), &style);
+            self.note = wrap(row, self.width);
+        }
+        if tier == RenderTier::PlainText {
+            let rows = self.render(&self.source[self.stable_end..self.scanned]);
+            self.stable.extend(rows);
+            self.stable_end = self.scanned;
+            self.open_fence = None;
+            self.append_plain();
+        }
+    }
+
+    /// The answer is complete. One that was simplified while streaming is
+    /// rendered again in full, in place of the simplified rows; true when
+    /// that happened.
+    pub fn finish(&mut self) -> bool {
+        if !self.policy.is_degraded() {
+            return false;
+        }
+        self.policy.reset();
+        self.note.clear();
+        self.stable = self.render(&self.source);
+        self.stable_end = self.source.len();
+        self.scanned = self.source.len();
+        self.open_fence = None;
+        self.tail.clear();
+        true
+    }
+
+    /// How the answer is being rendered
+    pub fn tier(&self) -> RenderTier {
+        self.policy.tier()
+    }
+
+    /// Complete lines after `stable_end` appended as plain text, without
+    /// rendering anything before them again
+    fn append_plain(&mut self) {
+        while let Some(len) = self.source[self.stable_end..].find('\n') {
+            let rows = self.plain_rows(&self.source[self.stable_end..self.stable_end + len]);
+            self.stable.extend(rows);
+            self.stable_end += len + 1;
+        }
+        self.scanned = self.stable_end;
+        self.tail = match &self.source[self.stable_end..] {
+            "" => Vec::new(),
+            rest => self.plain_rows(rest),
+        };
+        self.last_work = self.source.len() - self.stable_end;
+    }
+
+    fn plain_rows(&self, line: &str) -> Vec<Vec<TerminalCell>> {
+        let mut row = Vec::new();
+        push_text(&mut row, line.trim_end_matches('\r'), &self.plain());
+        wrap(row, self.width)
+    }
+
+    /// Render the blocks the new text completed, and the one still open
+    fn render_blocks(&mut self) {
+        let mut work = 0;
+        let mut boundary = None;
+        while let Some(len) = self.source[self.scanned..].find('\n') {
+            let line = &self.source[self.scanned..self.scanned + len + 1];
+            self.scanned += len + 1;
+            match &self.open_fence {
+                Some(opening) if syntax_highlight::closes(opening, line) => {
+                    self.open_fence = None;
+                    boundary = Some(self.scanned);
+                }
+                Some(_) => {}
+                None => {
+                    if let Some(marker) = syntax_highlight::fence(line) {
+                        self.open_fence = Some(marker.to_string());
+                    } else if line.trim().is_empty() {
+                        boundary = Some(self.scanned);
+                    }
+                }
+            }
+        }
+        if let Some(end) = boundary {
+            let rows = self.render(&self.source[self.stable_end..end]);
+            work += end - self.stable_end;
+            self.stable.extend(rows);
+            self.stable_end = end;
+        }
+        self.tail = self.render(&self.source[self.stable_end..]);
+        self.last_work = work + self.source.len() - self.stable_end;
+    }
+
+    /// Start over, as when a fallback model restarts the answer
+    pub fn clear(&mut self) {
+        self.policy.reset();
+        self.note.clear();
+        self.source.clear();
+        self.stable.clear();
+        self.stable_end = 0;
+        self.tail.clear();
+        self.scanned = 0;
+        self.open_fence = None;
+        self.last_work = 0;
+    }
+
+    /// Every row, the block being written last, then a note while the
+    /// rendering is simplified
+    pub fn rows(&self) -> impl Iterator<Item = &Vec<TerminalCell>> {
+        self.stable.iter().chain(&self.tail).chain(&self.note)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stable.len() + self.tail.len() + self.note.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Source bytes the last push rendered
+    pub fn last_work(&self) -> usize {
+        self.last_work
+    }
+
+    /// Render `source` at the current width; an unterminated fence runs to
+    /// the end as code
+    /// An unstyled cell in the theme's text colors
+    fn plain(&self) -> TerminalCell {
+        TerminalCell {
+            foreground: self.theme.foreground,
+            background: self.theme.background,
+            ..TerminalCell::default()
+        }
+    }
+
+    fn render(&self, source: &str) -> Vec<Vec<TerminalCell>> {
+        let accents = &self.theme.accents;
+        let mut lines: Vec<Vec<TerminalCell>> = Vec::new();
+        let mut line = Vec::new();
+        let mut style = self.plain();
+        let mut code: Option<(String, String)> = None;
+        let mut list_depth = 0usize;
+        let mut table: Option<Table> = None;
+        // The open link's URL, where its text starts and the style before it
+        let mut link: Option<(String, usize, TerminalCell)> = None;
+
+        let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES;
+        for event in Parser::new_ext(source, options) {
+            match event {
+                Event::Start(Tag::Heading { level, .. }) => {
+                    style.bold = true;
+                    style.underline = level <= HeadingLevel::H2;
+                    style.foreground = match level {
+                        HeadingLevel::H1 => accents.heading1,
+                        HeadingLevel::H2 => accents.heading2,
+                        _ => accents.heading,
+                    };
+                }
+                Event::End(TagEnd::Heading(_)) => {
+                    style = self.plain();
+                    lines.push(std::mem::take(&mut line));
+                    lines.push(Vec::new());
+                }
+                Event::Start(Tag::Strong) => style.bold = true,
+                Event::End(TagEnd::Strong) => style.bold = false,
+                Event::Start(Tag::Emphasis) => style.italic = true,
+                Event::End(TagEnd::Emphasis) => style.italic = false,
+                Event::Start(Tag::Strikethrough) => style.strikethrough = true,
+                Event::End(TagEnd::Strikethrough) => style.strikethrough = false,
+                Event::Start(Tag::Link { dest_url, .. }) => {
+                    link = Some((dest_url.to_string(), line.len(), style.clone()));
+                    style.underline = true;
+                    style.foreground = accents.link;
+                }
+                Event::End(TagEnd::Link) => {
+                    if let Some((url, start, before)) = link.take() {
+                        style = before;
+                        let text: String = line[start.min(line.len())..]
+                            .iter()
+                            .map(|cell| cell.character)
+                            .collect();
+                        // Keep the URL visible when the text doesn't show it
+                        if !url.is_empty() && text != url {
+                            let dim = TerminalCell {
+                                dim: true,
+                                ..style.clone()
+                            };
+                            push_text(&mut line, &format!(" <{}>", url), &dim);
+                        }
+                    }
+                }
+                Event::Start(Tag::List(_)) => list_depth += 1,
+                Event::End(TagEnd::List(_)) => {
+                    list_depth = list_depth.saturating_sub(1);
+                    if list_depth == 0 {
+                        lines.push(Vec::new());
+                    }
+                }
+                Event::Start(Tag::Item) => {
+                    if !line.is_empty() {
+                        lines.push(std::mem::take(&mut line));
+                    }
+                    let bullet = format!(
+                        "{}{}",
+                        "  ".repeat(list_depth.saturating_sub(1)),
+                        self.capabilities.bullet()
+                    );
+                    push_text(&mut line, &bullet, &self.plain());
+                }
+                Event::End(TagEnd::Item) if !line.is_empty() => {
+                    lines.push(std::mem::take(&mut line));
+                }
+                Event::End(TagEnd::Paragraph) => {
+                    lines.push(std::mem::take(&mut line));
+                    if list_depth == 0 {
+                        lines.push(Vec::new());
+                    }
+                }
+                Event::Start(Tag::CodeBlock(kind)) => {
+                    let language = match kind {
+                        CodeBlockKind::Fenced(language) => language.to_string(),
+                        CodeBlockKind::Indented => String::new(),
+                    };
+                    code = Some((language, String::new()));
+                }
+                Event::End(TagEnd::CodeBlock) => {
+                    if let Some((language, text)) = code.take() {
+                        lines.extend(self.code_rows(&text, &language));
+                        lines.push(Vec::new());
+                    }
+                }
+                Event::Start(Tag::Table(aligns)) => {
+                    table = Some(Table {
+                        aligns: aligns
+                            .iter()
+                            .map(|align| match align {
+                                Alignment::Center => ColumnAlign::Center,
+                                Alignment::Right => ColumnAlign::Right,
+                                Alignment::Left | Alignment::None => ColumnAlign::Left,
+                            })
+                            .collect(),
+                        ..Table::default()
+                    });
+                }
+                Event::End(TagEnd::TableCell) => {
+                    if let Some(table) = table.as_mut() {
+                        let cell = std::mem::take(&mut table.cell);
+                        table.row.push(cell);
+                    }
+                }
+                Event::End(TagEnd::TableHead) => {
+                    if let Some(table) = table.as_mut() {
+                        table.headers = std::mem::take(&mut table.row);
+                    }
+                }
+                Event::End(TagEnd::TableRow) => {
+                    if let Some(table) = table.as_mut() {
+                        let row = std::mem::take(&mut table.row);
+                        table.rows.push(row);
+                    }
+                }
+                Event::End(TagEnd::Table) => {
+                    if let Some(table) = table.take() {
+                        lines.extend(self.table_rows(&table));
+                        lines.push(Vec::new());
+                    }
+                }
+                Event::Text(text) | Event::Code(text) if table.is_some() => {
+                    if let Some(table) = table.as_mut() {
+                        table.cell.push_str(&text);
+                    }
+                }
+                Event::Text(text) => match code.as_mut() {
+                    Some((_, code)) => code.push_str(&text),
+                    None => push_text(&mut line, &text, &style),
+                },
+                Event::Code(text) => {
+                    let code_style = TerminalCell {
+                        foreground: accents.code,
+                        ..style
+                    };
+                    push_text(&mut line, &text, &code_style);
+                }
+                Event::SoftBreak => push_text(&mut line, " ", &style),
+                Event::HardBreak => lines.push(std::mem::take(&mut line)),
+                _ => {}
+            }
+        }
+        // A fence still open at the end of the stream
+        if let Some((language, text)) = code.take() {
+            lines.extend(self.code_rows(&text, &language));
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+        let started = Instant::now();
+        let rows = lines
+            .into_iter()
+            .flat_map(|line| wrap(line, self.width))
+            .collect();
+        self.layout_time.set(self.layout_time.get() + started.elapsed());
+        rows
+    }
+
+    /// The table boxed to fit the width, its header row in bold
+    fn table_rows(&self, table: &Table) -> Vec<Vec<TerminalCell>> {
+        let borders = self.capabilities.borders();
+        let border = TerminalCell {
+            foreground: self.theme.accents.muted,
+            ..self.plain()
+        };
+        let lines = render_caps::render_table_fitted(
+            &table.headers,
+            &table.rows,
+            &table.aligns,
+            borders,
+            self.width,
+        );
+        let last = lines.len() - 1;
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let rule = i == 0 || i == 2 || i == last;
+                let row = text
+                    .chars()
+                    .map(|character| {
+                        if rule || character == borders.vertical {
+                            TerminalCell {
+                                character,
+                                ..border
+                            }
+                        } else {
+                            TerminalCell {
+                                character,
+                                bold: i == 1,
+                                ..self.plain()
+                            }
+                        }
+                    })
+                    .collect();
+                sized(row)
+            })
+            .collect()
+    }
+
+    /// Code highlighted unless the rendering was simplified
+    fn code_rows(&self, code: &str, language: &str) -> Vec<Vec<TerminalCell>> {
+        let code = code.trim_end_matches('\n');
+        let highlighter = self.highlighter.as_ref().filter(|_| self.policy.tier() == RenderTier::Full);
+        match highlighter {
+            Some(highlighter) => {
+                let started = Instant::now();
+                let rows = highlighter
+                    .highlight(code, language)
+                    .into_iter()
+                    .map(sized)
+                    .collect();
+                self.highlight_time.set(self.highlight_time.get() + started.elapsed());
+                rows
+            }
+            None => {
+                let style = self.plain();
+                code.lines()
+                    .map(|text| {
+                        let mut row = Vec::new();
+                        push_text(&mut row, text, &style);
+                        row
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// One cell per grapheme, marked wide when it takes two columns. Like the
+/// grid, a cell shows the grapheme's first character.
+fn push_text(line: &mut Vec<TerminalCell>, text: &str, style: &TerminalCell) {
+    let clusters = glyph_guard::segment_clusters(text, &GlyphLimits::default());
+    line.extend(clusters.into_iter().filter_map(|cluster| {
+        Some(TerminalCell {
+            character: cluster.text.chars().next()?,
+            wide: cluster.width == 2,
+            ..*style
+        })
+    }));
+}
+
+/// Cells made a character at a time, with zero-width characters dropped
+/// and wide ones marked
+fn sized(row: Vec<TerminalCell>) -> Vec<TerminalCell> {
+    row.into_iter()
+        .filter_map(|cell| match glyph_guard::char_width(cell.character) {
+            0 => None,
+            width => Some(TerminalCell {
+                wide: width == 2,
+                ..cell
+            }),
+        })
+        .collect()
+}
+
+/// `line` in rows of at most `width` columns, broken after spaces where it
+/// can be and mid-word only for a word longer than a row. Wide cells are
+/// followed by a spacer, as in the grid. An empty line stays one row.
+fn wrap(line: Vec<TerminalCell>, width: usize) -> Vec<Vec<TerminalCell>> {
+    if line.is_empty() {
+        return vec![line];
+    }
+    let columns = |cell: &TerminalCell| if cell.wide { 2 } else { 1 };
+    let mut rows = Vec::new();
+    let mut row: Vec<TerminalCell> = Vec::new();
+    let mut used = 0;
+    // Where the last word in `row` starts
+    let mut word_start: Option<usize> = None;
+    for cell in line {
+        if used + columns(&cell) > width && !row.is_empty() {
+            if cell.character == ' ' {
+                rows.push(std::mem::take(&mut row));
+                used = 0;
+                word_start = None;
+                continue;
+            }
+            let rest = match word_start {
+                Some(start) if start > 0 => row.split_off(start),
+                _ => Vec::new(),
+            };
+            while row.last().is_some_and(|cell| cell.character == ' ') {
+                row.pop();
+            }
+            rows.push(std::mem::replace(&mut row, rest));
+            used = row.iter().map(columns).sum();
+            word_start = None;
+        }
+        if cell.character == ' ' {
+            word_start = Some(row.len() + 1);
+        }
+        used += columns(&cell);
+        row.push(cell);
+    }
+    if !row.is_empty() {
+        rows.push(row);
+    }
+    rows.into_iter()
+        .map(|row| {
+            row.into_iter()
+                .flat_map(|cell| {
+                    let spacer = cell.wide.then_some(TerminalCell {
+                        character: ' ',
+                        wide: false,
+                        ..cell
+                    });
+                    std::iter::once(cell).chain(spacer)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(row: &[TerminalCell]) -> String {
+        row.iter().map(|cell| cell.character).collect()
+    }
+
+    #[test]
+    fn test_unterminated_fence_reflows() {
+        let mut stream = MarkdownStream::new(80, None);
+        stream.push("# Title\n\nSome **bold** text.\n\n```rust\nfn main() {\n");
+        let rows: Vec<_> = stream.rows().map(|row| text(row)).collect();
+        assert_eq!(
+            rows,
+            ["Title", "", "Some bold text.", "", "fn main() {", ""]
+        );
+        assert!(stream.rows().next().unwrap()[0].bold);
+
+        stream.push("}\n```\nafter `x`\n");
+        let rows: Vec<_> = stream.rows().map(|row| text(row)).collect();
+        assert_eq!(
+            rows,
+            [
+                "Title",
+                "",
+                "Some bold text.",
+                "",
+                "fn main() {",
+                "}",
+                "",
+                "after x",
+                ""
+            ]
+        );
+        let after = stream.rows().nth(7).unwrap();
+        assert_ne!(after[6].foreground, after[0].foreground);
+    }
+
+    #[test]
+    fn test_table_columns_line_up() {
+        let mut stream = MarkdownStream::new(40, None);
+        stream.push(
+            "| Name | Kind | Size | Notes |\n\
+             |:-----|:----:|-----:|-------|\n\
+             | a.rs | file | 12 | ok |\n\
+             | src | dir | 4096 | has **many** entries in it |\n",
+        );
+        let rows: Vec<_> = stream.rows().map(|row| text(row)).collect();
+        assert_eq!(
+            rows,
+            [
+                "┌──────┬──────┬──────┬─────────────────┐",
+                "│ Name │ Kind │ Size │ Notes           │",
+                "├──────┼──────┼──────┼─────────────────┤",
+                "│ a.rs │ file │   12 │ ok              │",
+                "│ src  │ dir  │ 4096 │ has many entri… │",
+                "└──────┴──────┴──────┴─────────────────┘",
+                "",
+            ]
+        );
+        let header = stream.rows().nth(1).unwrap();
+        assert!(header[2].bold && !header[0].bold);
+
+        let mut ascii = MarkdownStream::new(40, None).with_capabilities(RenderCapabilities {
+            box_drawing: false,
+            ..RenderCapabilities::default()
+        });
+        ascii.push("| a | b |\n|---|---|\n| 1 | 2 |\n");
+        assert_eq!(text(ascii.rows().next().unwrap()), "+---+---+");
+    }
+
+    #[test]
+    fn test_links_keep_their_url() {
+        let mut markdown = MarkdownStream::new(60, None);
+        markdown.push("See [the docs](https://docs.rs) or <https://crates.io>.\n");
+        let rows: Vec<_> = markdown.rows().collect();
+        assert_eq!(
+            text(rows[0]),
+            "See the docs <https://docs.rs> or https://crates.io."
+        );
+        assert!(rows[0][4].underline && !rows[0][3].underline);
+        assert!(rows[0][14].dim);
+    }
+
+    #[test]
+    fn test_wrap_at_words_and_wide_graphemes() {
+        let mut markdown = MarkdownStream::new(6, None);
+        markdown.push("hi 👨‍👩‍👧 there ❤️\n\n日本語のテキスト\n");
+        let rows: Vec<_> = markdown.rows().collect();
+        // The family emoji is one two-column cell plus its spacer
+        assert_eq!(text(rows[0]), "hi 👨 ");
+        assert!(rows[0][3].wide && !rows[0][4].wide);
+        assert_eq!(text(rows[1]), "there");
+        assert!(rows[2][0].wide && rows[2].len() == 2);
+        assert_eq!(text(rows[4]), "日 本 語 ");
+        assert_eq!(text(rows[5]), "の テ キ ");
+        assert!(rows[4..6].iter().all(|row| row.len() == 6));
+    }
+
+    #[test]
+    fn test_long_url_breaks_only_where_it_must() {
+        let mut markdown = MarkdownStream::new(12, None);
+        markdown.push("see https://example.com/a/very/long/path ok\n");
+        let rows: Vec<_> = markdown.rows().map(|row| text(row)).collect();
+        assert_eq!(
+            rows[..5],
+            ["see", "https://exam", "ple.com/a/ve", "ry/long/path", "ok"]
+        );
+    }
+
+    #[test]
+    fn test_streaming_work_is_bounded() {
+        let block = "A paragraph with *some* words in it, long enough to wrap a \
+                     couple of times at eighty columns.\n\n```sh\necho hi\n```\n\n";
+        let source = block.repeat(100 * 1024 / block.len() + 1);
+        let mut stream = MarkdownStream::new(80, None);
+        let mut most = 0;
+        for chunk in source.as_bytes().chunks(64) {
+            stream.push(std::str::from_utf8(chunk).unwrap());
+            most = most.max(stream.last_work());
+        }
+        assert!(source.len() > 100 * 1024);
+        // Never more than the open block plus one chunk, however long the
+        // response has grown
+        assert!(
+            most <= block.len() + 64,
+            "rendered {} bytes in one push",
+            most
+        );
+
+        let mut whole = MarkdownStream::new(80, None);
+        whole.push(&source);
+        assert_eq!(stream.len(), whole.len());
+    }
+
+    #[test]
+    fn test_slow_pushes_simplify_then_finish_renders_in_full() {
+        let highlighter = || Some(SyntaxHighlighter::new("base16-ocean.dark").unwrap());
+        let config = DegradationConfig {
+            frame_budget: Duration::from_secs(1),
+            consecutive_cycles: 2,
+        };
+        let slow = StageCosts {
+            highlight: Duration::from_secs(2),
+            ..StageCosts::default()
+        };
+        let mut stream = MarkdownStream::new(40, highlighter()).with_degradation(config);
+        let code = "```rust\nfn main() {}\n";
+        stream.push(code);
+        let highlighted = stream.rows().next().unwrap().clone();
+
+        // Two slow pushes stop highlighting the open code block
+        stream.record_cycle(slow);
+        assert_eq!(stream.tier(), RenderTier::Full);
+        stream.record_cycle(slow);
+        assert_eq!(stream.tier(), RenderTier::NoHighlight);
+        stream.push("let x = 1;\n");
+        let plain = stream.rows().next().unwrap();
+        assert_eq!(text(plain), text(&highlighted));
+        assert_ne!(plain, &highlighted);
+        assert_eq!(text(stream.rows().last().unwrap()), messages::current().rendering_simplified());
+
+        // Two more and the rest is appended as plain text
+        stream.record_cycle(slow);
+        stream.record_cycle(slow);
+        assert_eq!(stream.tier(), RenderTier::PlainText);
+        stream.push("```\n\n**done**\n");
+        let rows: Vec<String> = stream.rows().map(|row| text(row)).collect();
+        assert_eq!(rows[rows.len() - 4..], ["```", "", "**done**", "(rendering simplified for performance)"]);
+
+        // Finishing swaps in what an unhurried render shows
+        assert!(stream.finish());
+        let mut full = MarkdownStream::new(40, highlighter());
+        full.push("```rust\nfn main() {}\nlet x = 1;\n```\n\n**done**\n");
+        assert!(stream.rows().eq(full.rows()));
+        assert_eq!(stream.tier(), RenderTier::Full);
+        assert!(!stream.finish());
+    }
+}
@@ -0,0 +1,754 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, RwLock};
+use thiserror::Error;
+use toml_edit::{DocumentMut, Item, Value};
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Error, Debug)]
+pub enum MessagesError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("TOML parsing error: {0}")]
+    Parse(#[from] toml_edit::TomlError),
+}
+
+/// A message's default text and what an override may use
+struct MessageSpec {
+    key: &'static str,
+    /// Text for a count of one; `None` when the message has no count
+    one: Option<&'static str>,
+    other: &'static str,
+    /// Placeholders the text may use, as `{name}`
+    params: &'static [&'static str],
+    /// Display columns the text must fit in
+    max_width: Option<usize>,
+}
+
+const fn text(
+    key: &'static str,
+    other: &'static str,
+    params: &'static [&'static str],
+) -> MessageSpec {
+    MessageSpec {
+        key,
+        one: None,
+        other,
+        params,
+        max_width: None,
+    }
+}
+
+const fn plural(key: &'static str, one: &'static str, other: &'static str) -> MessageSpec {
+    MessageSpec {
+        key,
+        one: Some(one),
+        other,
+        params: &["count"],
+        max_width: None,
+    }
+}
+
+const CATALOG: &[MessageSpec] = &[
+    MessageSpec {
+        max_width: Some(16),
+        ..text("typing_indicator", "▋ Generating...", &[])
+    },
+    text("interrupted", "[INTERRUPTED]", &[]),
+    MessageSpec {
+        params: &["count", "model", "elapsed", "rate"],
+        ..plural(
+            "generation_status",
+            "{model} · {elapsed} · {count} token · {rate} tok/s · Ctrl+C to stop",
+            "{model} · {elapsed} · {count} tokens · {rate} tok/s · Ctrl+C to stop",
+        )
+    },
+    MessageSpec {
+        params: &["count", "reason", "elapsed", "rate"],
+        ..plural(
+            "generation_summary",
+            "{reason} · {elapsed} · {count} token · {rate} tok/s",
+            "{reason} · {elapsed} · {count} tokens · {rate} tok/s",
+        )
+    },
+    text("generation_fallback", "answered by fallback {model}", &["model"]),
+    text("error_marker", "[ERROR: {error}]", &["error"]),
+    text("rendering_simplified", "(rendering simplified for performance)", &[]),
+    plural(
+        "copied",
+        "Copied {count} character",
+        "Copied {count} characters",
+    ),
+    text(
+        "close_confirmation",
+        "processes running, close again to quit",
+        &[],
+    ),
+    text("paste_header", "Paste {summary}?", &["summary"]),
+    plural("paste_lines", "{count} line", "{count} lines"),
+    plural(
+        "paste_control_chars",
+        "{count} control character",
+        "{count} control characters",
+    ),
+    plural(
+        "paste_risky",
+        "{count} risky command",
+        "{count} risky commands",
+    ),
+    plural(
+        "paste_more_lines",
+        "… {count} more line",
+        "… {count} more lines",
+    ),
+    text("paste_help", "Enter paste · e edit · Esc cancel", &[]),
+    text(
+        "paste_edit_unavailable",
+        "No composer to edit in; paste cancelled",
+        &[],
+    ),
+    text(
+        "read_only_keep_holding",
+        "Keep holding to make the pane writable",
+        &[],
+    ),
+    text(
+        "read_only_type_index",
+        "Type {index} to make the pane writable",
+        &["index"],
+    ),
+    text(
+        "read_only_hold_hint",
+        "Hold the read-only binding to unlock",
+        &[],
+    ),
+    text("read_only_on", "Pane is read-only", &[]),
+    text("read_only_off", "Pane is writable", &[]),
+    text(
+        "command_unavailable",
+        "{command} is not available in this window",
+        &["command"],
+    ),
+    text("command_error", "Command error: {error}", &["error"]),
+    text("setting_changed", "{path} = {value}", &["path", "value"]),
+    text("setting_saved", "{path} = {value}, saved to the config file", &["path", "value"]),
+    text("ghost_text_on", "Suggestions on for this pane", &[]),
+    text("ghost_text_off", "Suggestions off for this pane", &[]),
+    text(
+        "watchdog_recovered",
+        "{component} stopped responding and was restarted",
+        &["component"],
+    ),
+    text(
+        "watchdog_stalled",
+        "{component} stopped responding; recovery paused",
+        &["component"],
+    ),
+    text(
+        "shell_failed",
+        "Could not start {error}; running {fallback} instead",
+        &["error", "fallback"],
+    ),
+    text("process_exited", "[process exited with code {code}]", &["code"]),
+    text("process_killed", "[process terminated by a signal]", &[]),
+    text("no_response", "No agent response yet", &[]),
+    text("no_code_block", "No code block found", &[]),
+    text("no_command_output", "No finished command to fold", &[]),
+    text("no_calc_result", "No :calc result yet", &[]),
+    text("history_off", "Response history is off", &[]),
+    text("history_prune_failed", "History prune failed: {error}", &["error"]),
+    text("session_saved", "Saved session {name}", &["name"]),
+    MessageSpec {
+        params: &["count", "name"],
+        ..plural(
+            "session_restored",
+            "Restored session {name}: {count} tab",
+            "Restored session {name}: {count} tabs",
+        )
+    },
+    text("session_deleted", "Deleted session {name}", &["name"]),
+    text("no_sessions", "No saved sessions", &[]),
+    text("session_failed", "Session failed: {error}", &["error"]),
+    text("scaffold_empty", "No code block in the answer names a file", &[]),
+    text("scaffold_cancelled", "Scaffold cancelled; nothing written", &[]),
+    plural(
+        "copied_truncated",
+        "Copied {count} character; the rest is over the clipboard limit",
+        "Copied {count} characters; the rest is over the clipboard limit",
+    ),
+    text("copy_failed", "Copy failed: {error}", &["error"]),
+    text("no_links", "No links on screen", &[]),
+    text("no_such_link", "No link {n} on screen", &["n"]),
+    text("link_failed", "Could not open link: {error}", &["error"]),
+    text("model_current", "Using {model}", &["model"]),
+    text("model_unloading", "unloading {model}…", &["model"]),
+    text("model_loading", "loading {model}…", &["model"]),
+    text("model_warming_up", "warming up…", &[]),
+    text("model_ready", "ready in {elapsed}", &["elapsed"]),
+    text(
+        "model_ready_slow",
+        "ready in {elapsed}, over the {target} target",
+        &["elapsed", "target"],
+    ),
+    text("model_swap_failed", "failed: {error}", &["error"]),
+    text(
+        "batch_progress",
+        "{completed}/{total} complete, {failed} failed",
+        &["completed", "total", "failed"],
+    ),
+    text("batch_header", "── {n}/{total} · {model} · {prompt}", &["n", "total", "model", "prompt"]),
+    text("batch_saved", "results in {path}", &["path"]),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Template {
+    one: Option<String>,
+    other: String,
+}
+
+/// User-facing strings: the built-in English text, with any overrides from
+/// `ui.messages_file` applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct Messages {
+    templates: HashMap<&'static str, Template>,
+    /// Overrides that were ignored, and why
+    problems: Vec<String>,
+}
+
+impl Default for Messages {
+    fn default() -> Self {
+        let templates = CATALOG
+            .iter()
+            .map(|spec| {
+                let template = Template {
+                    one: spec.one.map(str::to_string),
+                    other: spec.other.to_string(),
+                };
+                (spec.key, template)
+            })
+            .collect();
+        Self {
+            templates,
+            problems: Vec::new(),
+        }
+    }
+}
+
+static DEFAULT: LazyLock<Arc<Messages>> = LazyLock::new(|| Arc::new(Messages::default()));
+static CURRENT: RwLock<Option<Arc<Messages>>> = RwLock::new(None);
+
+/// The catalog in use
+pub fn current() -> Arc<Messages> {
+    CURRENT
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| DEFAULT.clone())
+}
+
+pub fn install(messages: Messages) {
+    *CURRENT.write().unwrap() = Some(Arc::new(messages));
+}
+
+impl Messages {
+    /// Overrides from a TOML file. Unknown keys and unusable text fall back
+    /// to the defaults, with one warning for the lot.
+    pub fn load(path: &Path) -> Result<Self, MessagesError> {
+        let messages = Self::from_toml(&std::fs::read_to_string(path)?)?;
+        if !messages.problems.is_empty() {
+            tracing::warn!(
+                "Ignoring message overrides in {}: {}",
+                path.display(),
+                messages.problems.join("; ")
+            );
+        }
+        Ok(messages)
+    }
+
+    /// Keys map to a string, or to a `one`/`other` table for counted
+    /// messages
+    pub fn from_toml(source: &str) -> Result<Self, MessagesError> {
+        let document: DocumentMut = source.parse()?;
+        let mut messages = Self::default();
+        for (key, item) in document.iter() {
+            let Some(spec) = CATALOG.iter().find(|spec| spec.key == key) else {
+                messages.problems.push(format!("unknown message `{}`", key));
+                continue;
+            };
+            match override_template(spec, item) {
+                Ok(template) => {
+                    messages.templates.insert(spec.key, template);
+                }
+                Err(problem) => messages.problems.push(format!("`{}` {}", key, problem)),
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Every message as an override file would give it
+    pub fn to_toml(&self) -> String {
+        let mut document = DocumentMut::new();
+        for spec in CATALOG {
+            let template = &self.templates[spec.key];
+            match &template.one {
+                Some(one) => {
+                    let mut table = toml_edit::InlineTable::new();
+                    table.insert("one", one.as_str().into());
+                    table.insert("other", template.other.as_str().into());
+                    document[spec.key] = toml_edit::value(table);
+                }
+                None => document[spec.key] = toml_edit::value(template.other.as_str()),
+            }
+        }
+        document.to_string()
+    }
+
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
+
+    fn render(&self, key: &'static str, count: Option<u64>, args: &[(&str, &str)]) -> String {
+        let template = &self.templates[key];
+        let text = match (&template.one, count) {
+            (Some(one), Some(1)) => one,
+            _ => &template.other,
+        };
+        let count = count.map(|count| count.to_string());
+        let count = count.as_deref().map(|count| ("count", count));
+        interpolate(text, |name| {
+            count
+                .iter()
+                .chain(args)
+                .find(|(param, _)| *param == name)
+                .map(|(_, value)| *value)
+        })
+    }
+
+    pub fn typing_indicator(&self) -> String {
+        self.render("typing_indicator", None, &[])
+    }
+
+    pub fn interrupted(&self) -> String {
+        self.render("interrupted", None, &[])
+    }
+
+    /// `elapsed` in seconds and `rate` in tokens per second, to one decimal
+    pub fn generation_status(&self, model: &str, elapsed: f32, tokens: u32, rate: f32) -> String {
+        self.render(
+            "generation_status",
+            Some(tokens as u64),
+            &[
+                ("model", model),
+                ("elapsed", &format!("{:.1}s", elapsed)),
+                ("rate", &format!("{:.1}", rate)),
+            ],
+        )
+    }
+
+    /// `reason` is how the answer ended: stop, length, error or interrupted
+    pub fn generation_summary(&self, reason: &str, elapsed: f32, tokens: u32, rate: f32) -> String {
+        self.render(
+            "generation_summary",
+            Some(tokens as u64),
+            &[
+                ("reason", reason),
+                ("elapsed", &format!("{:.1}s", elapsed)),
+                ("rate", &format!("{:.1}", rate)),
+            ],
+        )
+    }
+
+    pub fn generation_fallback(&self, model: &str) -> String {
+        self.render("generation_fallback", None, &[("model", model)])
+    }
+
+    pub fn error_marker(&self, error: &str) -> String {
+        self.render("error_marker", None, &[("error", error)])
+    }
+
+    pub fn rendering_simplified(&self) -> String {
+        self.render("rendering_simplified", None, &[])
+    }
+
+    pub fn copied(&self, chars: usize) -> String {
+        self.render("copied", Some(chars as u64), &[])
+    }
+
+    pub fn close_confirmation(&self) -> String {
+        self.render("close_confirmation", None, &[])
+    }
+
+    pub fn paste_header(&self, summary: &str) -> String {
+        self.render("paste_header", None, &[("summary", summary)])
+    }
+
+    pub fn paste_lines(&self, lines: usize) -> String {
+        self.render("paste_lines", Some(lines as u64), &[])
+    }
+
+    pub fn paste_control_chars(&self, chars: usize) -> String {
+        self.render("paste_control_chars", Some(chars as u64), &[])
+    }
+
+    pub fn paste_risky(&self, commands: usize) -> String {
+        self.render("paste_risky", Some(commands as u64), &[])
+    }
+
+    pub fn paste_more_lines(&self, lines: usize) -> String {
+        self.render("paste_more_lines", Some(lines as u64), &[])
+    }
+
+    pub fn paste_help(&self) -> String {
+        self.render("paste_help", None, &[])
+    }
+
+    pub fn paste_edit_unavailable(&self) -> String {
+        self.render("paste_edit_unavailable", None, &[])
+    }
+
+    pub fn read_only_keep_holding(&self) -> String {
+        self.render("read_only_keep_holding", None, &[])
+    }
+
+    pub fn read_only_type_index(&self, index: usize) -> String {
+        self.render(
+            "read_only_type_index",
+            None,
+            &[("index", &index.to_string())],
+        )
+    }
+
+    pub fn read_only_hold_hint(&self) -> String {
+        self.render("read_only_hold_hint", None, &[])
+    }
+
+    pub fn read_only_on(&self) -> String {
+        self.render("read_only_on", None, &[])
+    }
+
+    pub fn read_only_off(&self) -> String {
+        self.render("read_only_off", None, &[])
+    }
+
+    pub fn command_unavailable(&self, command: &str) -> String {
+        self.render("command_unavailable", None, &[("command", command)])
+    }
+
+    pub fn command_error(&self, error: &str) -> String {
+        self.render("command_error", None, &[("error", error)])
+    }
+
+    pub fn shell_failed(&self, error: &str, fallback: &str) -> String {
+        self.render("shell_failed", None, &[("error", error), ("fallback", fallback)])
+    }
+
+    pub fn setting_changed(&self, path: &str, value: &str) -> String {
+        self.render("setting_changed", None, &[("path", path), ("value", value)])
+    }
+
+    pub fn setting_saved(&self, path: &str, value: &str) -> String {
+        self.render("setting_saved", None, &[("path", path), ("value", value)])
+    }
+
+    pub fn ghost_text_on(&self) -> String {
+        self.render("ghost_text_on", None, &[])
+    }
+
+    pub fn ghost_text_off(&self) -> String {
+        self.render("ghost_text_off", None, &[])
+    }
+
+    pub fn watchdog_recovered(&self, component: &str) -> String {
+        self.render("watchdog_recovered", None, &[("component", component)])
+    }
+
+    pub fn watchdog_stalled(&self, component: &str) -> String {
+        self.render("watchdog_stalled", None, &[("component", component)])
+    }
+
+    pub fn process_exited(&self, code: i32) -> String {
+        self.render("process_exited", None, &[("code", &code.to_string())])
+    }
+
+    pub fn process_killed(&self) -> String {
+        self.render("process_killed", None, &[])
+    }
+
+    pub fn no_response(&self) -> String {
+        self.render("no_response", None, &[])
+    }
+
+    pub fn no_code_block(&self) -> String {
+        self.render("no_code_block", None, &[])
+    }
+
+    pub fn no_command_output(&self) -> String {
+        self.render("no_command_output", None, &[])
+    }
+
+    pub fn no_calc_result(&self) -> String {
+        self.render("no_calc_result", None, &[])
+    }
+
+    pub fn history_off(&self) -> String {
+        self.render("history_off", None, &[])
+    }
+
+    pub fn history_prune_failed(&self, error: &str) -> String {
+        self.render("history_prune_failed", None, &[("error", error)])
+    }
+
+    pub fn session_saved(&self, name: &str) -> String {
+        self.render("session_saved", None, &[("name", name)])
+    }
+
+    pub fn session_restored(&self, name: &str, tabs: usize) -> String {
+        self.render("session_restored", Some(tabs as u64), &[("name", name)])
+    }
+
+    pub fn session_deleted(&self, name: &str) -> String {
+        self.render("session_deleted", None, &[("name", name)])
+    }
+
+    pub fn no_sessions(&self) -> String {
+        self.render("no_sessions", None, &[])
+    }
+
+    pub fn session_failed(&self, error: &str) -> String {
+        self.render("session_failed", None, &[("error", error)])
+    }
+
+    pub fn scaffold_empty(&self) -> String {
+        self.render("scaffold_empty", None, &[])
+    }
+
+    pub fn scaffold_cancelled(&self) -> String {
+        self.render("scaffold_cancelled", None, &[])
+    }
+
+    pub fn copied_truncated(&self, chars: usize) -> String {
+        self.render("copied_truncated", Some(chars as u64), &[])
+    }
+
+    pub fn copy_failed(&self, error: &str) -> String {
+        self.render("copy_failed", None, &[("error", error)])
+    }
+
+    pub fn no_links(&self) -> String {
+        self.render("no_links", None, &[])
+    }
+
+    pub fn no_such_link(&self, n: usize) -> String {
+        self.render("no_such_link", None, &[("n", &n.to_string())])
+    }
+
+    pub fn link_failed(&self, error: &str) -> String {
+        self.render("link_failed", None, &[("error", error)])
+    }
+
+    pub fn model_current(&self, model: &str) -> String {
+        self.render("model_current", None, &[("model", model)])
+    }
+
+    pub fn model_unloading(&self, model: &str) -> String {
+        self.render("model_unloading", None, &[("model", model)])
+    }
+
+    pub fn model_loading(&self, model: &str) -> String {
+        self.render("model_loading", None, &[("model", model)])
+    }
+
+    pub fn model_warming_up(&self) -> String {
+        self.render("model_warming_up", None, &[])
+    }
+
+    /// `elapsed` and `target` in seconds, to one decimal; `target` only
+    /// when the swap took longer
+    pub fn model_ready(&self, elapsed: f32, target: Option<f32>) -> String {
+        let elapsed = format!("{:.1}s", elapsed);
+        match target {
+            Some(target) => self.render(
+                "model_ready_slow",
+                None,
+                &[("elapsed", &elapsed), ("target", &format!("{:.1}s", target))],
+            ),
+            None => self.render("model_ready", None, &[("elapsed", &elapsed)]),
+        }
+    }
+
+    pub fn model_swap_failed(&self, error: &str) -> String {
+        self.render("model_swap_failed", None, &[("error", error)])
+    }
+
+    pub fn batch_progress(&self, completed: usize, total: usize, failed: usize) -> String {
+        self.render(
+            "batch_progress",
+            None,
+            &[
+                ("completed", &completed.to_string()),
+                ("total", &total.to_string()),
+                ("failed", &failed.to_string()),
+            ],
+        )
+    }
+
+    /// Above the answer to the `n`th prompt of a batch, counting from 1
+    pub fn batch_header(&self, n: usize, total: usize, model: &str, prompt: &str) -> String {
+        self.render(
+            "batch_header",
+            None,
+            &[("n", &n.to_string()), ("total", &total.to_string()), ("model", model), ("prompt", prompt)],
+        )
+    }
+
+    pub fn batch_saved(&self, path: &str) -> String {
+        self.render("batch_saved", None, &[("path", path)])
+    }
+}
+
+fn override_template(spec: &MessageSpec, item: &Item) -> Result<Template, String> {
+    let template = match (item.as_str(), item.as_table_like()) {
+        // One form serves every count
+        (Some(text), _) => Template {
+            one: spec.one.map(|_| text.to_string()),
+            other: text.to_string(),
+        },
+        (None, Some(table)) if spec.one.is_some() => {
+            let form = |name: &str| {
+                table
+                    .get(name)
+                    .and_then(|item| item.as_value())
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("needs a `{}` string", name))
+            };
+            Template {
+                one: Some(form("one")?),
+                other: form("other")?,
+            }
+        }
+        _ => return Err("must be a string".to_string()),
+    };
+    for text in template.one.iter().chain([&template.other]) {
+        if let Some(name) = placeholders(text).find(|name| !spec.params.contains(name)) {
+            return Err(format!("uses unknown placeholder {{{}}}", name));
+        }
+        if let Some(max) = spec.max_width
+            && text.width() > max
+        {
+            return Err(format!("is wider than {} columns", max));
+        }
+    }
+    Ok(template)
+}
+
+fn placeholders(text: &str) -> impl Iterator<Item = &str> {
+    text.split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+}
+
+/// Replace each `{name}` that `lookup` knows; anything else is left as is
+fn interpolate<'a>(text: &str, lookup: impl Fn(&str) -> Option<&'a str>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        match after
+            .split_once('}')
+            .and_then(|(name, tail)| Some((lookup(name)?, tail)))
+        {
+            Some((value, tail)) => {
+                out.push_str(value);
+                rest = tail;
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolation_and_plurals() {
+        let messages = Messages::default();
+        assert_eq!(messages.error_marker("timed out"), "[ERROR: timed out]");
+        assert_eq!(messages.paste_lines(1), "1 line");
+        assert_eq!(messages.paste_lines(3), "3 lines");
+        assert_eq!(messages.copied(0), "Copied 0 characters");
+        assert_eq!(
+            messages.read_only_type_index(2),
+            "Type 2 to make the pane writable"
+        );
+        assert!(messages.typing_indicator().width() <= 16);
+        assert_eq!(
+            messages.generation_status("local", 3.25, 1, 0.31),
+            "local · 3.2s · 1 token · 0.3 tok/s · Ctrl+C to stop"
+        );
+        assert_eq!(
+            messages.generation_summary("stop", 4.0, 152, 38.0),
+            "stop · 4.0s · 152 tokens · 38.0 tok/s"
+        );
+        assert_eq!(messages.model_ready(2.4, None), "ready in 2.4s");
+        assert_eq!(messages.model_ready(4.06, Some(3.0)), "ready in 4.1s, over the 3.0s target");
+        assert_eq!(messages.batch_progress(12, 40, 2), "12/40 complete, 2 failed");
+    }
+
+    #[test]
+    fn test_overrides_round_trip_and_fall_back() {
+        let source = r#"
+interrupted = "[UNTERBROCHEN]"
+paste_lines = { one = "{count} Zeile", other = "{count} Zeilen" }
+error_marker = "[FEHLER: {fehler}]"
+typing_indicator = "▋ Antwort wird generiert..."
+read_only_on = { one = "x", other = "y" }
+not_a_message = "?"
+"#;
+        let messages = Messages::from_toml(source).unwrap();
+        assert_eq!(messages.interrupted(), "[UNTERBROCHEN]");
+        assert_eq!(messages.paste_lines(1), "1 Zeile");
+        assert_eq!(messages.paste_lines(2), "2 Zeilen");
+        // Unusable overrides keep the default text
+        assert_eq!(messages.error_marker("x"), "[ERROR: x]");
+        assert_eq!(messages.typing_indicator(), "▋ Generating...");
+        assert_eq!(messages.read_only_on(), "Pane is read-only");
+        assert_eq!(messages.problems().len(), 4);
+
+        let reloaded = Messages::from_toml(&messages.to_toml()).unwrap();
+        assert!(reloaded.problems().is_empty());
+        assert_eq!(reloaded.templates, messages.templates);
+    }
+
+    #[test]
+    fn test_migrated_literals_are_gone() {
+        let sources = [
+            include_str!("paste_guard.rs"),
+            include_str!("read_only.rs"),
+            include_str!("bin/main.rs"),
+        ];
+        for source in sources {
+            let code = source.split("#[cfg(test)]").next().unwrap();
+            for spec in CATALOG {
+                let literal = format!("\"{}\"", spec.other);
+                assert!(!code.contains(&literal), "{} is still inline", spec.other);
+            }
+            for old in [
+                "[INTERRUPTED]",
+                "[ERROR: ",
+                "Generating...",
+                "close again to quit",
+                "line(s)",
+            ] {
+                assert!(!code.contains(old), "{} is still inline", old);
+            }
+        }
+    }
+}
@@ -1,20 +1,24 @@
 use async_trait::async_trait;
-use futures::stream::StreamExt;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
+use tokio::sync::{broadcast, mpsc, watch, Mutex, RwLock};
 use tokio::time::timeout;
 use tokio_stream::wrappers::UnboundedReceiverStream;
-use tokio_stream::Stream;
+use tokio_stream::{Stream, StreamExt};
 use tracing::{debug, error, info, warn};
 
+use crate::usage::{ModelUsage, UsageTracker};
+use crate::vram_probe::{VramBudget, VramSource};
+use crate::watchdog::{Component, Heartbeat, Lease, Leases, Watchdog};
+
 #[derive(Error, Debug)]
 pub enum ModelHostError {
     #[error("Model loading error: {0}")]
@@ -23,16 +27,23 @@ pub enum ModelHostError {
     Inference(String),
     #[error("API error: {0}")]
     Api(#[from] reqwest::Error),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
     #[error("Configuration error: {0}")]
     Config(String),
     #[error("Model not found: {name}")]
     ModelNotFound { name: String },
+    #[error("Model not loaded: {name}")]
+    NotLoaded { name: String },
     #[error("Resource exhausted: {resource}")]
     ResourceExhausted { resource: String },
     #[error("Timeout: operation took longer than {timeout_ms}ms")]
     Timeout { timeout_ms: u64 },
     #[error("VRAM exhausted: required {required}MB, available {available}MB")]
     VramExhausted { required: u64, available: u64 },
+    /// A hot-swap that fits only once other loaded models are evicted
+    #[error("need {required}MB, only {available}MB free — pass --force to evict")]
+    VramPreflight { required: u64, available: u64 },
     #[error("Hot-swap failed: {reason}")]
     HotSwapFailed { reason: String },
     #[error("Batch processing error: {0}")]
@@ -47,6 +58,14 @@ pub enum ModelHostError {
     PoolExhausted { count: usize },
     #[error("Fallback chain exhausted: all {count} models failed")]
     FallbackExhausted { count: usize },
+    #[error("Daily budget of {budget:.2} for {name} is used up")]
+    BudgetExceeded { name: String, budget: f64 },
+    #[error("Circuit open for {name}: skipped after repeated failures")]
+    CircuitOpen { name: String },
+    #[error("Context overflow for {name}: needs about {required} tokens, window is {window}")]
+    ContextOverflow { name: String, required: u64, window: u64 },
+    #[error("Model host is shutting down")]
+    ShuttingDown,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,6 +79,102 @@ pub struct InferenceRequest {
     pub priority: InferencePriority,
     pub fallback_chain: Option<Vec<String>>,
     pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub context_policy: ContextPolicy,
+}
+
+impl InferenceRequest {
+    /// A non-streaming request with default parameters
+    pub fn new(model_name: impl Into<String>, prompt: impl Into<String>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            model_name: model_name.into(),
+            parameters: InferenceParameters::default(),
+            context: None,
+            stream: false,
+            batch_id: None,
+            priority: InferencePriority::Normal,
+            fallback_chain: None,
+            timeout_ms: None,
+            context_policy: ContextPolicy::default(),
+        }
+    }
+}
+
+/// What to do when prompt, context and `max_tokens` overrun the model's
+/// context window. The prompt itself is never truncated.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ContextPolicy {
+    #[default]
+    Error,
+    /// Drop the earliest context entries first
+    TruncateOldest,
+    /// Drop entries from the middle out, keeping the first and latest
+    TruncateMiddle,
+}
+
+/// Estimates how many tokens a text takes
+pub trait TokenEstimator: Send + Sync {
+    fn estimate(&self, text: &str) -> u32;
+}
+
+/// About four bytes per token, close enough for English and code
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ByteEstimator;
+
+impl TokenEstimator for ByteEstimator {
+    fn estimate(&self, text: &str) -> u32 {
+        text.len().div_ceil(4) as u32
+    }
+}
+
+/// Fit the request into a `window`-token model, dropping context entries
+/// as its policy allows. Returns how many were dropped.
+pub fn fit_context(
+    request: &mut InferenceRequest,
+    window: u32,
+    estimator: &dyn TokenEstimator,
+) -> Result<usize, ModelHostError> {
+    let window = window as u64;
+    let fixed = estimator.estimate(&request.prompt) as u64 + request.parameters.max_tokens as u64;
+    let sizes: Vec<u64> = request
+        .context
+        .iter()
+        .flatten()
+        .map(|entry| estimator.estimate(entry) as u64)
+        .collect();
+    let mut required = fixed + sizes.iter().sum::<u64>();
+    if required <= window {
+        return Ok(0);
+    }
+    if fixed > window || request.context_policy == ContextPolicy::Error {
+        return Err(ModelHostError::ContextOverflow {
+            name: request.model_name.clone(),
+            required,
+            window,
+        });
+    }
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    if request.context_policy == ContextPolicy::TruncateMiddle {
+        let last = sizes.len() as i64 - 1;
+        order.sort_by_key(|&i| (2 * i as i64 - last).abs());
+    }
+    let mut keep = vec![true; sizes.len()];
+    for i in order {
+        if required <= window {
+            break;
+        }
+        keep[i] = false;
+        required -= sizes[i];
+    }
+
+    let dropped = keep.iter().filter(|kept| !**kept).count();
+    if let Some(context) = &mut request.context {
+        let mut kept = keep.into_iter();
+        context.retain(|_| kept.next().unwrap_or(true));
+    }
+    Ok(dropped)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -106,6 +221,8 @@ pub struct InferenceResponse {
     pub timing: InferenceTiming,
     pub model_used: String,
     pub is_fallback: bool,
+    /// Context entries dropped to fit the model's window
+    pub context_dropped: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -114,6 +231,9 @@ pub struct StreamToken {
     pub is_final: bool,
     pub token_index: u32,
     pub timestamp: Instant,
+    /// On the first token from a fallback model, naming it. Tokens after
+    /// it restart the response from the beginning.
+    pub fallback_model: Option<String>,
 }
 
 pub type TokenStream = Pin<Box<dyn Stream<Item = Result<StreamToken, ModelHostError>> + Send>>;
@@ -125,11 +245,21 @@ pub struct BatchInferenceRequest {
     pub max_parallel: Option<usize>,
 }
 
+/// How far a batch has got; `completed` counts failed requests too
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchProgress {
+    pub completed: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct InferenceTiming {
     pub prompt_eval_time: Duration,
     pub eval_time: Duration,
     pub total_time: Duration,
+    /// Reloading a model the warm pool had unloaded, on top of `total_time`
+    pub load_time: Duration,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -139,6 +269,8 @@ pub enum FinishReason {
     Error(String),
 }
 
+/// Every implementation must pass
+/// `adapter_conformance::run_adapter_conformance` before it is registered.
 #[async_trait]
 pub trait ModelAdapter: Send + Sync {
     async fn load(&mut self) -> Result<(), ModelHostError>;
@@ -176,11 +308,19 @@ pub enum ModelType {
     Ollama,
 }
 
+impl ModelType {
+    /// Runs on this machine and holds VRAM while loaded
+    pub fn holds_vram(&self) -> bool {
+        matches!(self, ModelType::LocalGGUF | ModelType::MLC | ModelType::VLLM)
+    }
+}
+
 #[derive(Debug)]
 pub struct VramStats {
     pub total_mb: u64,
     pub used_mb: AtomicU64,
-    pub available_mb: AtomicU64,
+    /// Where `total_mb` came from
+    pub source: VramSource,
     pub last_updated: Instant,
 }
 
@@ -197,9 +337,111 @@ pub struct ModelConfig {
     pub fallback_models: Vec<String>,
     pub warm_pool_size: usize,
     pub max_concurrent: usize,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub cost_per_1k_prompt_tokens: Option<f64>,
+    #[serde(default)]
+    pub cost_per_1k_completion_tokens: Option<f64>,
+    /// Spend per UTC day after which requests to this model fail
+    #[serde(default)]
+    pub daily_budget: Option<f64>,
+    /// Unload a local model unused this long; its next request reloads
+    /// it (0 = never)
+    #[serde(default)]
+    pub idle_unload_secs: u64,
+    /// Reload after a hot-swap unloads it, once VRAM frees up
+    #[serde(default)]
+    pub keep_warm: bool,
 }
 
-#[derive(Debug, Clone)]
+impl ModelConfig {
+    /// Estimated cost of a request from the configured prices
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        let prompt = self.cost_per_1k_prompt_tokens.unwrap_or(0.0) * prompt_tokens as f64;
+        let completion = self.cost_per_1k_completion_tokens.unwrap_or(0.0) * completion_tokens as f64;
+        (prompt + completion) / 1000.0
+    }
+}
+
+/// Retries for transient remote failures: rate limits and overloaded or
+/// failing servers. Other statuses fail straight away.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub retry_on: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30000,
+            retry_on: vec![429, 500, 502, 503, 529],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn should_retry(&self, status: StatusCode, attempt: u32) -> bool {
+        attempt < self.max_retries && self.retry_on.contains(&status.as_u16())
+    }
+
+    /// Delay before retry `attempt` (from 0): the server's `Retry-After`
+    /// when given, else exponential backoff with jitter over its upper half
+    pub fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after;
+        }
+        let ceiling = self
+            .initial_backoff_ms
+            .saturating_mul(1u64 << attempt.min(32))
+            .min(self.max_backoff_ms);
+        let half = ceiling / 2;
+        let jitter = {
+            use std::hash::{BuildHasher, Hasher};
+            std::collections::hash_map::RandomState::new().build_hasher().finish()
+        };
+        Duration::from_millis(half + jitter % (ceiling - half + 1))
+    }
+}
+
+/// Seconds form of `Retry-After`
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// Send the request built by `build`, retrying per `policy`. Callers bound
+/// the whole exchange, backoff included, with the request timeout.
+async fn send_with_retry(
+    policy: &RetryPolicy,
+    retries: &AtomicU64,
+    build: impl Fn() -> reqwest::RequestBuilder,
+) -> Result<reqwest::Response, ModelHostError> {
+    let mut attempt = 0;
+    loop {
+        let response = build().send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        if !policy.should_retry(status, attempt) {
+            return Err(ModelHostError::Api(response.error_for_status().unwrap_err()));
+        }
+        let delay = policy.backoff(attempt, retry_after(&response));
+        warn!("Remote API returned {}, retrying in {:?}", status, delay);
+        retries.fetch_add(1, Ordering::Relaxed);
+        attempt += 1;
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[derive(Clone)]
 pub struct SecureApiKey {
     inner: String,
 }
@@ -211,7 +453,7 @@ impl SecureApiKey {
 
     pub fn from_env(env_var: &str) -> Result<Self, ModelHostError> {
         std::env::var(env_var)
-            .map(|key| Self::new(key))
+            .map(Self::new)
             .map_err(|_| ModelHostError::Authentication(format!("Environment variable {} not found", env_var)))
     }
 
@@ -229,13 +471,58 @@ impl std::fmt::Debug for SecureApiKey {
     }
 }
 
-#[derive(Debug)]
 pub struct ModelWorker {
     pub id: String,
     pub adapter: Arc<Mutex<Box<dyn ModelAdapter>>>,
     pub is_busy: AtomicBool,
     pub last_used: Arc<Mutex<Instant>>,
     pub requests_processed: AtomicU64,
+    /// Outcome of the last health check, trusted until it is older than
+    /// the host's health TTL
+    pub healthy: AtomicBool,
+    pub last_health_check: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ModelWorker {
+    pub fn new(id: String, adapter: Box<dyn ModelAdapter>) -> Self {
+        Self {
+            id,
+            adapter: Arc::new(Mutex::new(adapter)),
+            is_busy: AtomicBool::new(false),
+            last_used: Arc::new(Mutex::new(Instant::now())),
+            requests_processed: AtomicU64::new(0),
+            healthy: AtomicBool::new(false),
+            last_health_check: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    async fn health_is_fresh(&self, ttl: Duration) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+            && self.last_health_check.lock().await.is_some_and(|at| at.elapsed() < ttl)
+    }
+
+    async fn record_health(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::SeqCst);
+        *self.last_health_check.lock().await = Some(Instant::now());
+    }
+
+    /// Check health now and cache the outcome
+    async fn refresh_health(&self) -> Result<(), ModelHostError> {
+        let result = self.adapter.lock().await.health_check().await;
+        self.record_health(result.is_ok()).await;
+        result
+    }
+}
+
+/// A worker's cached health, for display
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerHealth {
+    pub worker_id: String,
+    pub healthy: bool,
+    pub busy: bool,
+    /// Age of the last health check; `None` before the first
+    pub checked_ago: Option<Duration>,
+    pub requests_processed: u64,
 }
 
 #[derive(Debug)]
@@ -243,6 +530,8 @@ pub struct FallbackChain {
     pub models: Vec<String>,
     pub current_index: usize,
     pub failed_models: Vec<String>,
+    /// Passed over because their circuit was open
+    pub skipped_models: Vec<String>,
 }
 
 impl FallbackChain {
@@ -251,9 +540,15 @@ impl FallbackChain {
             models,
             current_index: 0,
             failed_models: Vec::new(),
+            skipped_models: Vec::new(),
         }
     }
 
+    /// The model the request asked for
+    pub fn primary(&self) -> Option<&str> {
+        self.models.first().map(String::as_str)
+    }
+
     pub fn next_model(&mut self) -> Option<String> {
         if self.current_index < self.models.len() {
             let model = self.models[self.current_index].clone();
@@ -268,60 +563,359 @@ impl FallbackChain {
         self.failed_models.push(model);
     }
 
+    pub fn mark_skipped(&mut self, model: String) {
+        self.skipped_models.push(model);
+    }
+
     pub fn has_more(&self) -> bool {
         self.current_index < self.models.len()
     }
 }
 
+/// After `failure_threshold` consecutive failures a model is skipped for
+/// `cooldown_ms`; then one probe request decides whether it is used again.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    pub failure_threshold: u32,
+    pub cooldown_ms: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            cooldown_ms: 30000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    /// Cooldown over; the next request probes the model
+    HalfOpen,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    probing: bool,
+}
+
+impl Circuit {
+    fn state(&self, now: Instant) -> CircuitState {
+        match self.open_until {
+            None => CircuitState::Closed,
+            Some(until) if now < until || self.probing => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether a request may go to the model; a half-open circuit lets one
+    /// probe through at a time
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.state(now) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                self.probing = true;
+                true
+            }
+        }
+    }
+
+    fn record(&mut self, success: bool, config: &CircuitBreakerConfig, now: Instant) {
+        if success {
+            *self = Self::default();
+            return;
+        }
+        self.consecutive_failures += 1;
+        if self.probing || self.consecutive_failures >= config.failure_threshold.max(1) {
+            self.open_until = Some(now + Duration::from_millis(config.cooldown_ms));
+            self.probing = false;
+        }
+    }
+}
+
 impl VramStats {
-    pub fn new(total_mb: u64) -> Self {
+    pub fn new(total_mb: u64, source: VramSource) -> Self {
         Self {
             total_mb,
             used_mb: AtomicU64::new(0),
-            available_mb: AtomicU64::new(total_mb),
+            source,
             last_updated: Instant::now(),
         }
     }
 
-    pub fn allocate(&self, size_mb: u64) -> Result<(), ModelHostError> {
-        let current_used = self.used_mb.load(Ordering::SeqCst);
-        let new_used = current_used + size_mb;
+    /// What is left of the total; derived from `used_mb` so the two cannot drift
+    pub fn available_mb(&self) -> u64 {
+        self.total_mb.saturating_sub(self.used_mb.load(Ordering::SeqCst))
+    }
 
-        if new_used > self.total_mb {
-            return Err(ModelHostError::VramExhausted {
+    pub fn allocate(&self, size_mb: u64) -> Result<(), ModelHostError> {
+        self.used_mb
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(size_mb).filter(|&new_used| new_used <= self.total_mb)
+            })
+            .map(|_| ())
+            .map_err(|used| ModelHostError::VramExhausted {
                 required: size_mb,
-                available: self.total_mb - current_used,
-            });
-        }
-
-        self.used_mb.store(new_used, Ordering::SeqCst);
-        self.available_mb
-            .store(self.total_mb - new_used, Ordering::SeqCst);
-        Ok(())
+                available: self.total_mb.saturating_sub(used),
+            })
     }
 
     pub fn deallocate(&self, size_mb: u64) {
-        let current_used = self.used_mb.load(Ordering::SeqCst);
-        let new_used = current_used.saturating_sub(size_mb);
-        self.used_mb.store(new_used, Ordering::SeqCst);
-        self.available_mb
-            .store(self.total_mb - new_used, Ordering::SeqCst);
+        let _ = self
+            .used_mb
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| Some(used.saturating_sub(size_mb)));
     }
 
     pub fn get_usage_percent(&self) -> f32 {
+        if self.total_mb == 0 {
+            return 0.0;
+        }
         let used = self.used_mb.load(Ordering::SeqCst) as f32;
         let total = self.total_mb as f32;
         (used / total) * 100.0
     }
 }
 
+/// Trim generated text to the request's limits: cut before the first stop
+/// sequence, then to `max_tokens` whitespace-separated tokens
+pub fn apply_generation_limits(text: &str, parameters: &InferenceParameters) -> (String, u32, FinishReason) {
+    let mut text = text;
+    let mut finish_reason = FinishReason::Stop;
+    if let Some(cut) = parameters
+        .stop_sequences
+        .iter()
+        .filter(|stop| !stop.is_empty())
+        .filter_map(|stop| text.find(stop.as_str()))
+        .min()
+    {
+        text = text[..cut].trim_end();
+    }
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let limit = parameters.max_tokens as usize;
+    if words.len() > limit {
+        finish_reason = FinishReason::Length;
+        return (words[..limit].join(" "), limit as u32, finish_reason);
+    }
+    (text.to_string(), words.len() as u32, finish_reason)
+}
+
+/// Stream `tokens` in order, sleeping `delay(index)` before each. Exactly
+/// one token is marked final, even when there is nothing to send.
+fn spawn_token_stream<F>(mut tokens: Vec<String>, delay: F) -> TokenStream
+where
+    F: Fn(usize) -> Duration + Send + 'static,
+{
+    if tokens.is_empty() {
+        tokens.push(String::new());
+    }
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let last = tokens.len() - 1;
+        for (i, token) in tokens.into_iter().enumerate() {
+            tokio::time::sleep(delay(i)).await;
+            let stream_token = StreamToken {
+                token,
+                is_final: i == last,
+                token_index: i as u32,
+                timestamp: Instant::now(),
+                fallback_model: None,
+            };
+            if tx.send(Ok(stream_token)).is_err() {
+                break;
+            }
+        }
+    });
+    Box::pin(UnboundedReceiverStream::new(rx))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    Delta(String),
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StreamFormat {
+    /// Server-sent events, one `data:` line per chunk
+    #[default]
+    Sse,
+    /// Newline-delimited JSON, as Ollama streams
+    Ndjson,
+}
+
+/// A streaming completion fed in chunks as they arrive. Lines may be split
+/// across chunks.
+#[derive(Debug, Default)]
+pub struct StreamParser {
+    buffer: Vec<u8>,
+    format: StreamFormat,
+}
+
+impl StreamParser {
+    pub fn new(format: StreamFormat) -> Self {
+        Self { buffer: Vec::new(), format }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Result<StreamEvent, ModelHostError>> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            if let Some(event) = self.parse_line(&line).transpose() {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// A last line without a newline
+    pub fn finish(&mut self) -> Vec<Result<StreamEvent, ModelHostError>> {
+        let line = std::mem::take(&mut self.buffer);
+        self.parse_line(&line).transpose().into_iter().collect()
+    }
+
+    fn parse_line(&self, line: &[u8]) -> Result<Option<StreamEvent>, ModelHostError> {
+        let line = std::str::from_utf8(line)
+            .map_err(|e| ModelHostError::Stream(format!("Invalid UTF-8 in stream: {}", e)))?
+            .trim_end_matches(['\r', '\n']);
+        let data = match self.format {
+            // Blank separators, comments and other fields carry no text
+            StreamFormat::Sse => match line.strip_prefix("data:") {
+                Some(data) => data.trim_start(),
+                None => return Ok(None),
+            },
+            StreamFormat::Ndjson if line.trim().is_empty() => return Ok(None),
+            StreamFormat::Ndjson => line,
+        };
+        if data == "[DONE]" {
+            return Ok(Some(StreamEvent::Done));
+        }
+        let value: serde_json::Value = serde_json::from_str(data)
+            .map_err(|e| ModelHostError::Stream(format!("Malformed stream chunk {:?}: {}", data, e)))?;
+        if let Some(message) = value.get("error") {
+            return Err(ModelHostError::Stream(message.to_string()));
+        }
+        let finish = value.pointer("/candidates/0/finishReason").and_then(|reason| reason.as_str());
+        if let FinishReason::Error(reason) = GeminiAdapter::finish_reason(finish, &value) {
+            return Err(ModelHostError::Stream(reason));
+        }
+        // OpenAI deltas, then completion-style, chat, Gemini and generic fields
+        let candidate_text = GeminiAdapter::candidate_text(&value);
+        let choice = value.pointer("/choices/0");
+        let text = choice
+            .and_then(|choice| choice.pointer("/delta/content").or_else(|| choice.get("text")))
+            .or_else(|| value.pointer("/message/content"))
+            .or_else(|| value.pointer("/delta/text"))
+            .and_then(|text| text.as_str())
+            .or(candidate_text.as_deref())
+            .or_else(|| {
+                ["token", "text", "content", "response"]
+                    .iter()
+                    .find_map(|key| value.get(*key))
+                    .and_then(|text| text.as_str())
+            });
+        let done = value.get("done").and_then(|done| done.as_bool()) == Some(true)
+            || value.get("type").and_then(|kind| kind.as_str()) == Some("message_stop")
+            || finish.is_some();
+        Ok(match text {
+            Some(text) if !text.is_empty() => Some(StreamEvent::Delta(text.to_string())),
+            _ if done => Some(StreamEvent::Done),
+            _ => None,
+        })
+    }
+}
+
+/// Forward a streaming response's tokens as their chunks arrive; dropping
+/// the stream ends the request. Not every provider honors max_tokens, so
+/// the stream stops after `max_tokens` deltas.
+fn spawn_event_stream(mut response: reqwest::Response, max_tokens: u32, mut parser: StreamParser) -> TokenStream {
+    let limit = max_tokens.max(1);
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut deltas = 0;
+        let mut token_index = 0;
+        let mut send = |token: String, is_final: bool| {
+            let stream_token = StreamToken {
+                token,
+                is_final,
+                token_index,
+                timestamp: Instant::now(),
+                fallback_model: None,
+            };
+            token_index += 1;
+            tx.send(Ok(stream_token)).is_ok()
+        };
+        loop {
+            let chunk = tokio::select! {
+                chunk = response.chunk() => chunk,
+                _ = tx.closed() => return,
+            };
+            let ended = !matches!(chunk, Ok(Some(_)));
+            let events = match chunk {
+                Ok(Some(bytes)) => parser.push(&bytes),
+                // Ended without a done marker
+                Ok(None) => parser.finish(),
+                Err(e) => vec![Err(ModelHostError::Stream(e.to_string()))],
+            };
+            for event in events {
+                let open = match event {
+                    Ok(StreamEvent::Delta(text)) => {
+                        deltas += 1;
+                        if deltas >= limit {
+                            send(text, true);
+                            return;
+                        }
+                        send(text, false)
+                    }
+                    Ok(StreamEvent::Done) => {
+                        send(String::new(), true);
+                        return;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                };
+                if !open {
+                    return;
+                }
+            }
+            if ended {
+                send(String::new(), true);
+                return;
+            }
+        }
+    });
+    Box::pin(UnboundedReceiverStream::new(rx))
+}
+
+/// Run `work` under the request's timeout, or `default_ms` if it has none
+async fn with_request_timeout<T>(
+    timeout_ms: Option<u64>,
+    default_ms: u64,
+    work: impl Future<Output = Result<T, ModelHostError>>,
+) -> Result<T, ModelHostError> {
+    let timeout_ms = timeout_ms.unwrap_or(default_ms);
+    timeout(Duration::from_millis(timeout_ms), work)
+        .await
+        .unwrap_or(Err(ModelHostError::Timeout { timeout_ms }))
+}
+
 pub struct LocalGGUFAdapter {
     model_path: PathBuf,
     model_info: ModelInfo,
     loaded: AtomicBool,
     config: ModelConfig,
-    // In a real implementation, this would hold the GGUF model instance
-    // For now, we'll simulate the interface
+    #[cfg(feature = "llama")]
+    session: Option<crate::llama::LlamaSession>,
 }
 
 impl LocalGGUFAdapter {
@@ -338,7 +932,176 @@ impl LocalGGUFAdapter {
             },
             loaded: AtomicBool::new(false),
             config,
+            #[cfg(feature = "llama")]
+            session: None,
+        }
+    }
+}
+
+/// Text a GGUF backend generated for one request
+struct Generation {
+    text: String,
+    prompt_tokens: u32,
+    tokens: u32,
+    finish_reason: FinishReason,
+    prompt_eval_time: Duration,
+    eval_time: Duration,
+}
+
+/// Without the `llama` feature, loading and generation are simulated with
+/// realistic timing
+#[cfg(not(feature = "llama"))]
+impl LocalGGUFAdapter {
+    async fn open(&mut self) -> Result<(), ModelHostError> {
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        Ok(())
+    }
+
+    async fn close(&mut self) {}
+
+    async fn generate(&self, request: &InferenceRequest) -> Result<Generation, ModelHostError> {
+        // Timing follows prompt length and parameters
+        let prompt_eval_time = Duration::from_millis(5 + request.prompt.len() as u64 / 20);
+        let tokens_to_generate = request.parameters.max_tokens.min(512);
+        let eval_time = Duration::from_millis(
+            (tokens_to_generate as u64 * 15) / ((request.parameters.temperature * 10.0) as u64).max(1)
+        );
+        tokio::time::sleep(prompt_eval_time).await;
+        tokio::time::sleep(eval_time).await;
+
+        let response_text = format!(
+            "GGUF model {} response to: {} [temp: {}, max_tokens: {}]",
+            self.model_info.name,
+            request.prompt,
+            request.parameters.temperature,
+            request.parameters.max_tokens
+        );
+        let (text, tokens, finish_reason) = apply_generation_limits(&response_text, &request.parameters);
+        Ok(Generation {
+            text,
+            prompt_tokens: request.prompt.split_whitespace().count() as u32,
+            tokens,
+            finish_reason,
+            prompt_eval_time,
+            eval_time,
+        })
+    }
+
+    async fn stream(&self, request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
+        let text = format!(
+            "GGUF streaming response to your prompt: {} with realistic timing",
+            request.prompt
+        );
+        let (text, _, _) = apply_generation_limits(&text, &request.parameters);
+        let tokens = text.split_whitespace().map(str::to_string).collect();
+        Ok(spawn_token_stream(tokens, |i| Duration::from_millis(50 + (i as u64 * 10))))
+    }
+}
+
+/// With the `llama` feature, llama.cpp runs the model on a thread of its own
+#[cfg(feature = "llama")]
+impl LocalGGUFAdapter {
+    /// Map the model and create a context of `context_window` tokens, with
+    /// as many layers on the GPU as `vram_required_mb` allows
+    async fn open(&mut self) -> Result<(), ModelHostError> {
+        let path = self.model_path.clone();
+        let (window, vram_mb) = (self.config.context_window, self.config.vram_required_mb);
+        let session = tokio::task::spawn_blocking(move || crate::llama::LlamaSession::load(&path, window, vram_mb))
+            .await
+            .map_err(|e| ModelHostError::ModelLoad(format!("llama.cpp load failed: {}", e)))??;
+        self.session = Some(session);
+        Ok(())
+    }
+
+    /// Free the context and model once any running generation stops
+    async fn close(&mut self) {
+        if let Some(session) = self.session.take() {
+            let _ = tokio::task::spawn_blocking(move || drop(session)).await;
+        }
+    }
+
+    /// Start generating; context entries come before the prompt, a line each
+    fn start(&self, request: &InferenceRequest) -> Result<mpsc::UnboundedReceiver<crate::llama::Generated>, ModelHostError> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| ModelHostError::NotLoaded { name: self.model_info.name.clone() })?;
+        let mut prompt = request.context.as_deref().unwrap_or_default().join("\n");
+        if !prompt.is_empty() {
+            prompt.push('\n');
+        }
+        prompt.push_str(&request.prompt);
+        session.generate(prompt, request.parameters.clone())
+    }
+
+    async fn generate(&self, request: &InferenceRequest) -> Result<Generation, ModelHostError> {
+        use crate::llama::Generated;
+
+        let mut events = self.start(request)?;
+        let mut text = String::new();
+        while let Some(event) = events.recv().await {
+            match event {
+                Generated::Piece(piece) => text.push_str(&piece),
+                Generated::Done(stats) => {
+                    return Ok(Generation {
+                        text,
+                        prompt_tokens: stats.prompt_tokens,
+                        tokens: stats.tokens,
+                        finish_reason: stats.finish_reason,
+                        prompt_eval_time: stats.prompt_eval_time,
+                        eval_time: stats.eval_time,
+                    });
+                }
+                Generated::Failed(e) => return Err(e),
+            }
         }
+        Err(ModelHostError::Inference("llama.cpp stopped mid-generation".to_string()))
+    }
+
+    /// Tokens as they are sampled; dropping the stream stops generation
+    async fn stream(&self, request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
+        use crate::llama::Generated;
+
+        let mut events = self.start(&request)?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            // Each piece waits for the next, so the last can be marked final
+            let mut held: Option<String> = None;
+            let mut token_index = 0;
+            loop {
+                let event = tokio::select! {
+                    event = events.recv() => event,
+                    _ = tx.closed() => return,
+                };
+                let (token, is_final) = match event {
+                    Some(Generated::Piece(piece)) => match held.replace(piece) {
+                        Some(previous) => (previous, false),
+                        None => continue,
+                    },
+                    Some(Generated::Done(_)) => (held.take().unwrap_or_default(), true),
+                    Some(Generated::Failed(e)) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                    None => {
+                        let _ = tx.send(Err(ModelHostError::Inference("llama.cpp stopped mid-generation".to_string())));
+                        return;
+                    }
+                };
+                let token = StreamToken {
+                    token,
+                    is_final,
+                    token_index,
+                    timestamp: Instant::now(),
+                    fallback_model: None,
+                };
+                if tx.send(Ok(token)).is_err() || is_final {
+                    return;
+                }
+                token_index += 1;
+            }
+        });
+        Ok(Box::pin(UnboundedReceiverStream::new(rx)))
     }
 }
 
@@ -353,18 +1116,15 @@ impl ModelAdapter for LocalGGUFAdapter {
                 "Model file not found: {:?}", self.model_path
             )));
         }
-
-        // Simulate model loading with realistic timing
-        let start = Instant::now();
-        tokio::time::sleep(Duration::from_millis(120)).await;
-        
-        // Validate model file (simulate)
         if self.model_path.extension().and_then(|s| s.to_str()) != Some("gguf") {
             return Err(ModelHostError::ModelCorrupted { 
                 name: self.model_info.name.clone() 
             });
         }
 
+        let start = Instant::now();
+        self.open().await?;
+
         self.loaded.store(true, Ordering::SeqCst);
         self.model_info.loaded_at = Some(start.elapsed().as_secs());
         
@@ -374,6 +1134,7 @@ impl ModelAdapter for LocalGGUFAdapter {
 
     async fn unload(&mut self) -> Result<(), ModelHostError> {
         debug!("Unloading GGUF model: {}", self.model_info.name);
+        self.close().await;
         self.loaded.store(false, Ordering::SeqCst);
         self.model_info.loaded_at = None;
         Ok(())
@@ -381,109 +1142,44 @@ impl ModelAdapter for LocalGGUFAdapter {
 
     async fn infer(&self, request: InferenceRequest) -> Result<InferenceResponse, ModelHostError> {
         if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("Model not loaded".to_string()));
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
         }
 
         let start_time = Instant::now();
-        let timeout_duration = Duration::from_millis(request.timeout_ms.unwrap_or(30000));
-
-        // Simulate realistic inference timing based on prompt length and parameters
-        let prompt_eval_time = Duration::from_millis(5 + request.prompt.len() as u64 / 20);
-        let tokens_to_generate = request.parameters.max_tokens.min(512);
-        let eval_time = Duration::from_millis(
-            (tokens_to_generate as u64 * 15) / (request.parameters.temperature * 10.0) as u64
-        );
+        let generation = with_request_timeout(request.timeout_ms, 30000, self.generate(&request)).await?;
+        Ok(InferenceResponse {
+            text: generation.text,
+            tokens_generated: generation.tokens,
+            total_tokens: generation.prompt_tokens + generation.tokens,
+            finish_reason: generation.finish_reason,
+            timing: InferenceTiming {
+                prompt_eval_time: generation.prompt_eval_time,
+                eval_time: generation.eval_time,
+                total_time: start_time.elapsed(),
+                load_time: Duration::ZERO,
+            },
+            model_used: self.model_info.name.clone(),
+            is_fallback: false,
+            context_dropped: 0,
+        })
+    }
 
-        // Apply timeout
-        let inference_result = timeout(timeout_duration, async {
-            tokio::time::sleep(prompt_eval_time).await;
-            tokio::time::sleep(eval_time).await;
-            
-            // Generate contextual response
-            let response_text = format!(
-                "GGUF model {} response to: {} [temp: {}, max_tokens: {}]",
-                self.model_info.name,
-                request.prompt,
-                request.parameters.temperature,
-                request.parameters.max_tokens
-            );
-            
-            Ok(response_text)
-        }).await;
-
-        match inference_result {
-            Ok(Ok(response_text)) => {
-                let tokens_generated = response_text.split_whitespace().count() as u32;
-                let total_time = start_time.elapsed();
-
-                Ok(InferenceResponse {
-                    text: response_text,
-                    tokens_generated,
-                    total_tokens: request.prompt.split_whitespace().count() as u32 + tokens_generated,
-                    finish_reason: FinishReason::Stop,
-                    timing: InferenceTiming {
-                        prompt_eval_time,
-                        eval_time,
-                        total_time,
-                    },
-                    model_used: self.model_info.name.clone(),
-                    is_fallback: false,
-                })
-            }
-            Ok(Err(e)) => Err(e),
-            Err(_) => Err(ModelHostError::Timeout { 
-                timeout_ms: timeout_duration.as_millis() as u64 
-            }),
-        }
-    }
-
-    async fn infer_stream(&self, request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
-        if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("Model not loaded".to_string()));
-        }
-
-        let (tx, rx) = mpsc::unbounded_channel();
-        let model_name = self.model_info.name.clone();
-        let start_time = Instant::now();
-
-        tokio::spawn(async move {
-            // Simulate streaming token generation
-            let tokens = vec![
-                "GGUF", "streaming", "response", "to", "your", "prompt:", 
-                &request.prompt, "with", "realistic", "timing"
-            ];
-
-            for (i, token) in tokens.iter().enumerate() {
-                // Simulate realistic token generation timing
-                tokio::time::sleep(Duration::from_millis(50 + (i as u64 * 10))).await;
-                
-                let stream_token = StreamToken {
-                    token: token.to_string(),
-                    is_final: i == tokens.len() - 1,
-                    token_index: i as u32,
-                    timestamp: start_time,
-                };
-
-                if tx.send(Ok(stream_token)).is_err() {
-                    break;
-                }
-            }
-        });
-
-        let stream = UnboundedReceiverStream::new(rx);
-        Ok(Box::pin(stream))
-    }
+    async fn infer_stream(&self, request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+        self.stream(request).await
+    }
 
     async fn batch_infer(&self, requests: Vec<InferenceRequest>) -> Result<Vec<InferenceResponse>, ModelHostError> {
         if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("Model not loaded".to_string()));
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
         }
 
         let mut responses = Vec::new();
         
         // Process in parallel with controlled concurrency
-        let max_parallel = self.config.max_concurrent.min(requests.len());
-        let mut tasks = Vec::new();
+        let max_parallel = self.config.max_concurrent.min(requests.len()).max(1);
 
         for chunk in requests.chunks(max_parallel) {
             let chunk_tasks: Vec<_> = chunk.iter().map(|req| {
@@ -518,7 +1214,7 @@ impl ModelAdapter for LocalGGUFAdapter {
 
     async fn health_check(&self) -> Result<(), ModelHostError> {
         if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("Model not loaded".to_string()));
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
         }
 
         // Quick inference test
@@ -535,6 +1231,7 @@ impl ModelAdapter for LocalGGUFAdapter {
             priority: InferencePriority::Normal,
             fallback_chain: None,
             timeout_ms: Some(5000),
+            context_policy: ContextPolicy::default(),
         };
 
         self.infer(test_request).await.map(|_| ())
@@ -542,7 +1239,7 @@ impl ModelAdapter for LocalGGUFAdapter {
 
     async fn warmup(&self) -> Result<(), ModelHostError> {
         if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("Model not loaded".to_string()));
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
         }
 
         debug!("Warming up GGUF model: {}", self.model_info.name);
@@ -561,6 +1258,7 @@ impl ModelAdapter for LocalGGUFAdapter {
             priority: InferencePriority::Normal,
             fallback_chain: None,
             timeout_ms: Some(10000),
+            context_policy: ContextPolicy::default(),
         };
 
         self.infer(warmup_request).await.map(|_| ())
@@ -568,7 +1266,6 @@ impl ModelAdapter for LocalGGUFAdapter {
 }
 
 pub struct MLCAdapter {
-    config: ModelConfig,
     model_info: ModelInfo,
     loaded: AtomicBool,
 }
@@ -585,7 +1282,6 @@ impl MLCAdapter {
                 vram_required_mb: config.vram_required_mb,
             },
             loaded: AtomicBool::new(false),
-            config,
         }
     }
 }
@@ -615,62 +1311,58 @@ impl ModelAdapter for MLCAdapter {
 
     async fn infer(&self, request: InferenceRequest) -> Result<InferenceResponse, ModelHostError> {
         if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("MLC model not loaded".to_string()));
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
         }
 
         let start_time = Instant::now();
         let eval_time = Duration::from_millis(30 + (request.prompt.len() as u64 / 15));
         
-        tokio::time::sleep(eval_time).await;
+        with_request_timeout(request.timeout_ms, 30000, async {
+            tokio::time::sleep(eval_time).await;
+            Ok(())
+        })
+        .await?;
 
         let response_text = format!(
             "MLC {} optimized response: {} [GPU accelerated]",
             self.model_info.name,
             request.prompt
         );
-        let tokens_generated = response_text.split_whitespace().count() as u32;
+        let (text, tokens_generated, finish_reason) =
+            apply_generation_limits(&response_text, &request.parameters);
 
         Ok(InferenceResponse {
-            text: response_text,
+            text,
             tokens_generated,
             total_tokens: request.prompt.split_whitespace().count() as u32 + tokens_generated,
-            finish_reason: FinishReason::Stop,
+            finish_reason,
             timing: InferenceTiming {
                 prompt_eval_time: Duration::from_millis(5),
                 eval_time,
                 total_time: start_time.elapsed(),
+                load_time: Duration::ZERO,
             },
             model_used: self.model_info.name.clone(),
             is_fallback: false,
+            context_dropped: 0,
         })
     }
 
     async fn infer_stream(&self, request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
         if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("MLC model not loaded".to_string()));
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
         }
 
-        let (tx, rx) = mpsc::unbounded_channel();
-        tokio::spawn(async move {
-            let tokens = vec!["MLC", "fast", "streaming", "tokens", "here"];
-            for (i, token) in tokens.iter().enumerate() {
-                tokio::time::sleep(Duration::from_millis(30)).await;
-                let stream_token = StreamToken {
-                    token: token.to_string(),
-                    is_final: i == tokens.len() - 1,
-                    token_index: i as u32,
-                    timestamp: Instant::now(),
-                };
-                if tx.send(Ok(stream_token)).is_err() {
-                    break;
-                }
-            }
-        });
-
-        Ok(Box::pin(UnboundedReceiverStream::new(rx)))
+        let (text, _, _) = apply_generation_limits("MLC fast streaming tokens here", &request.parameters);
+        let tokens = text.split_whitespace().map(str::to_string).collect();
+        Ok(spawn_token_stream(tokens, |_| Duration::from_millis(30)))
     }
 
     async fn batch_infer(&self, requests: Vec<InferenceRequest>) -> Result<Vec<InferenceResponse>, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
         // MLC optimized batch processing
         let mut responses = Vec::new();
         for request in requests {
@@ -697,7 +1389,7 @@ impl ModelAdapter for MLCAdapter {
 
     async fn health_check(&self) -> Result<(), ModelHostError> {
         if self.loaded.load(Ordering::SeqCst) { Ok(()) } else { 
-            Err(ModelHostError::ModelLoad("MLC model not loaded".to_string())) 
+            Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() }) 
         }
     }
 
@@ -707,7 +1399,6 @@ impl ModelAdapter for MLCAdapter {
 }
 
 pub struct VLLMAdapter {
-    config: ModelConfig,
     model_info: ModelInfo,
     loaded: AtomicBool,
     process_handle: Arc<Mutex<Option<tokio::process::Child>>>,
@@ -725,7 +1416,6 @@ impl VLLMAdapter {
                 vram_required_mb: config.vram_required_mb,
             },
             loaded: AtomicBool::new(false),
-            config,
             process_handle: Arc::new(Mutex::new(None)),
         }
     }
@@ -765,63 +1455,60 @@ impl ModelAdapter for VLLMAdapter {
 
     async fn infer(&self, request: InferenceRequest) -> Result<InferenceResponse, ModelHostError> {
         if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("vLLM server not running".to_string()));
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
         }
 
         let start_time = Instant::now();
         // vLLM is typically faster due to optimizations
         let eval_time = Duration::from_millis(20 + (request.prompt.len() as u64 / 25));
         
-        tokio::time::sleep(eval_time).await;
+        with_request_timeout(request.timeout_ms, 30000, async {
+            tokio::time::sleep(eval_time).await;
+            Ok(())
+        })
+        .await?;
 
         let response_text = format!(
             "vLLM {} high-throughput response: {} [PagedAttention optimized]",
             self.model_info.name,
             request.prompt
         );
-        let tokens_generated = response_text.split_whitespace().count() as u32;
+        let (text, tokens_generated, finish_reason) =
+            apply_generation_limits(&response_text, &request.parameters);
 
         Ok(InferenceResponse {
-            text: response_text,
+            text,
             tokens_generated,
             total_tokens: request.prompt.split_whitespace().count() as u32 + tokens_generated,
-            finish_reason: FinishReason::Stop,
+            finish_reason,
             timing: InferenceTiming {
                 prompt_eval_time: Duration::from_millis(3),
                 eval_time,
                 total_time: start_time.elapsed(),
+                load_time: Duration::ZERO,
             },
             model_used: self.model_info.name.clone(),
             is_fallback: false,
+            context_dropped: 0,
         })
     }
 
     async fn infer_stream(&self, request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
         if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("vLLM server not running".to_string()));
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
         }
 
-        let (tx, rx) = mpsc::unbounded_channel();
-        tokio::spawn(async move {
-            let tokens = vec!["vLLM", "high", "throughput", "streaming", "optimized"];
-            for (i, token) in tokens.iter().enumerate() {
-                tokio::time::sleep(Duration::from_millis(20)).await;
-                let stream_token = StreamToken {
-                    token: token.to_string(),
-                    is_final: i == tokens.len() - 1,
-                    token_index: i as u32,
-                    timestamp: Instant::now(),
-                };
-                if tx.send(Ok(stream_token)).is_err() {
-                    break;
-                }
-            }
-        });
-
-        Ok(Box::pin(UnboundedReceiverStream::new(rx)))
+        let (text, _, _) =
+            apply_generation_limits("vLLM high throughput streaming optimized", &request.parameters);
+        let tokens = text.split_whitespace().map(str::to_string).collect();
+        Ok(spawn_token_stream(tokens, |_| Duration::from_millis(20)))
     }
 
     async fn batch_infer(&self, requests: Vec<InferenceRequest>) -> Result<Vec<InferenceResponse>, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
         // vLLM excels at batch processing
         let start_time = Instant::now();
         let mut responses = Vec::new();
@@ -832,18 +1519,22 @@ impl ModelAdapter for VLLMAdapter {
         
         for request in requests {
             let response_text = format!("vLLM batch response: {}", request.prompt);
+            let (text, tokens_generated, finish_reason) =
+                apply_generation_limits(&response_text, &request.parameters);
             responses.push(InferenceResponse {
-                text: response_text.clone(),
-                tokens_generated: response_text.split_whitespace().count() as u32,
-                total_tokens: request.prompt.split_whitespace().count() as u32 + response_text.split_whitespace().count() as u32,
-                finish_reason: FinishReason::Stop,
+                text,
+                tokens_generated,
+                total_tokens: request.prompt.split_whitespace().count() as u32 + tokens_generated,
+                finish_reason,
                 timing: InferenceTiming {
                     prompt_eval_time: Duration::from_millis(2),
                     eval_time: Duration::from_millis(15),
                     total_time: start_time.elapsed(),
+                    load_time: Duration::ZERO,
                 },
                 model_used: self.model_info.name.clone(),
                 is_fallback: false,
+                context_dropped: 0,
             });
         }
         
@@ -868,7 +1559,7 @@ impl ModelAdapter for VLLMAdapter {
 
     async fn health_check(&self) -> Result<(), ModelHostError> {
         if self.loaded.load(Ordering::SeqCst) { Ok(()) } else { 
-            Err(ModelHostError::ModelLoad("vLLM server not running".to_string())) 
+            Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() }) 
         }
     }
 
@@ -885,6 +1576,7 @@ pub struct RemoteAPIAdapter {
     model_info: ModelInfo,
     client: Client,
     loaded: AtomicBool,
+    retries: Arc<AtomicU64>,
 }
 
 impl RemoteAPIAdapter {
@@ -895,7 +1587,7 @@ impl RemoteAPIAdapter {
             None
         };
 
-        let model_type = match config.api_endpoint.as_ref().map(|s| s.as_str()) {
+        let model_type = match config.api_endpoint.as_deref() {
             Some(url) if url.contains("openai") => ModelType::OpenAI,
             Some(url) if url.contains("gemini") => ModelType::Gemini,
             Some(url) if url.contains("anthropic") => ModelType::Anthropic,
@@ -915,67 +1607,28 @@ impl RemoteAPIAdapter {
             api_key,
             client: Client::new(),
             loaded: AtomicBool::new(false),
+            retries: Arc::default(),
             config,
         })
     }
-}
-
-#[async_trait]
-impl ModelAdapter for RemoteAPIAdapter {
-    async fn load(&mut self) -> Result<(), ModelHostError> {
-        debug!("Connecting to remote API: {:?}", self.config.api_endpoint);
-        
-        let api_endpoint = self.config.api_endpoint.as_ref()
-            .ok_or_else(|| ModelHostError::Config("No API endpoint specified".to_string()))?;
-        
-        // Validate connection with health check
-        let mut request_builder = self.client
-            .get(api_endpoint)
-            .timeout(Duration::from_secs(10));
-
-        if let Some(key) = &self.api_key {
-            request_builder = request_builder.header("Authorization", format!("Bearer {}", key.get()));
-        }
-
-        match request_builder.send().await {
-            Ok(response) => {
-                if response.status().is_success() || response.status() == 404 {
-                    // 404 is acceptable for some API endpoints that don't support GET
-                    self.loaded.store(true, Ordering::SeqCst);
-                    self.model_info.loaded_at = Some(Instant::now().elapsed().as_secs());
-                    info!("Connected to remote API: {}", self.model_info.name);
-                    Ok(())
-                } else {
-                    Err(ModelHostError::Api(reqwest::Error::from(response.error_for_status().unwrap_err())))
-                }
-            }
-            Err(e) => {
-                error!("Failed to connect to API {}: {}", api_endpoint, e);
-                Err(ModelHostError::Api(e))
-            }
-        }
-    }
 
-    async fn unload(&mut self) -> Result<(), ModelHostError> {
-        debug!("Disconnecting from remote API: {}", self.model_info.name);
-        self.loaded.store(false, Ordering::SeqCst);
-        self.model_info.loaded_at = None;
-        Ok(())
+    /// Count retries into a shared counter
+    pub fn with_retry_counter(mut self, retries: Arc<AtomicU64>) -> Self {
+        self.retries = retries;
+        self
     }
 
-    async fn infer(&self, request: InferenceRequest) -> Result<InferenceResponse, ModelHostError> {
-        if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("API not connected".to_string()));
-        }
-
+    /// Request body and URL in the provider's format
+    fn build_request(
+        &self,
+        request: &InferenceRequest,
+        stream: bool,
+    ) -> Result<(serde_json::Value, String), ModelHostError> {
         let api_endpoint = self.config.api_endpoint.as_ref()
             .ok_or_else(|| ModelHostError::Config("No API endpoint specified".to_string()))?;
 
-        let start_time = Instant::now();
-        let timeout_duration = Duration::from_millis(request.timeout_ms.unwrap_or(60000));
-
         // Build API request based on provider type
-        let (api_request, endpoint) = match self.model_info.model_type {
+        Ok(match self.model_info.model_type {
             ModelType::OpenAI => {
                 #[derive(Serialize)]
                 struct OpenAIRequest {
@@ -1010,7 +1663,7 @@ impl ModelAdapter for RemoteAPIAdapter {
                     } else { 
                         Some(request.parameters.stop_sequences.clone()) 
                     },
-                    stream: request.stream,
+                    stream,
                 };
 
                 (serde_json::to_value(req)?, format!("{}/chat/completions", api_endpoint))
@@ -1025,6 +1678,8 @@ impl ModelAdapter for RemoteAPIAdapter {
                     top_p: f32,
                     max_tokens: u32,
                     stop: Vec<String>,
+                    #[serde(skip_serializing_if = "std::ops::Not::not")]
+                    stream: bool,
                 }
 
                 let req = GenericRequest {
@@ -1034,32 +1689,88 @@ impl ModelAdapter for RemoteAPIAdapter {
                     top_p: request.parameters.top_p,
                     max_tokens: request.parameters.max_tokens,
                     stop: request.parameters.stop_sequences.clone(),
+                    stream,
                 };
 
                 (serde_json::to_value(req)?, api_endpoint.clone())
             }
-        };
+        })
+    }
+
+    fn post(&self, endpoint: &str, body: &serde_json::Value) -> reqwest::RequestBuilder {
+        let request_builder = self.client
+            .post(endpoint)
+            .json(body)
+            .header("Content-Type", "application/json");
+        match &self.api_key {
+            Some(key) => request_builder.header("Authorization", format!("Bearer {}", key.get())),
+            None => request_builder,
+        }
+    }
+}
+
+#[async_trait]
+impl ModelAdapter for RemoteAPIAdapter {
+    async fn load(&mut self) -> Result<(), ModelHostError> {
+        debug!("Connecting to remote API: {:?}", self.config.api_endpoint);
+        
+        let api_endpoint = self.config.api_endpoint.as_ref()
+            .ok_or_else(|| ModelHostError::Config("No API endpoint specified".to_string()))?;
+        
+        // Validate connection with health check
+        let mut request_builder = self.client
+            .get(api_endpoint)
+            .timeout(Duration::from_secs(10));
 
-        let response_result = timeout(timeout_duration, async {
-            let mut request_builder = self.client
-                .post(&endpoint)
-                .json(&api_request)
-                .header("Content-Type", "application/json");
+        if let Some(key) = &self.api_key {
+            request_builder = request_builder.header("Authorization", format!("Bearer {}", key.get()));
+        }
 
-            if let Some(key) = &self.api_key {
-                request_builder = request_builder.header("Authorization", format!("Bearer {}", key.get()));
+        match request_builder.send().await {
+            Ok(response) => {
+                if response.status().is_success() || response.status() == 404 {
+                    // 404 is acceptable for some API endpoints that don't support GET
+                    self.loaded.store(true, Ordering::SeqCst);
+                    self.model_info.loaded_at = Some(Instant::now().elapsed().as_secs());
+                    info!("Connected to remote API: {}", self.model_info.name);
+                    Ok(())
+                } else {
+                    Err(ModelHostError::Api(response.error_for_status().unwrap_err()))
+                }
+            }
+            Err(e) => {
+                error!("Failed to connect to API {}: {}", api_endpoint, e);
+                Err(ModelHostError::Api(e))
             }
+        }
+    }
+
+    async fn unload(&mut self) -> Result<(), ModelHostError> {
+        debug!("Disconnecting from remote API: {}", self.model_info.name);
+        self.loaded.store(false, Ordering::SeqCst);
+        self.model_info.loaded_at = None;
+        Ok(())
+    }
+
+    async fn infer(&self, request: InferenceRequest) -> Result<InferenceResponse, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
+        let start_time = Instant::now();
+        let timeout_duration = Duration::from_millis(request.timeout_ms.unwrap_or(60000));
 
-            request_builder.send().await
-        }).await;
+        let (api_request, endpoint) = self.build_request(&request, false)?;
+
+        let response_result = timeout(
+            timeout_duration,
+            send_with_retry(&self.config.retry, &self.retries, || self.post(&endpoint, &api_request)),
+        )
+        .await;
 
         match response_result {
             Ok(Ok(response)) => {
                 let total_time = start_time.elapsed();
-                
-                if !response.status().is_success() {
-                    return Err(ModelHostError::Api(response.error_for_status().unwrap_err()));
-                }
 
                 // Parse response based on provider type
                 let (text, tokens_generated, total_tokens) = match self.model_info.model_type {
@@ -1073,7 +1784,6 @@ impl ModelAdapter for RemoteAPIAdapter {
                         #[derive(Deserialize)]
                         struct OpenAIChoice {
                             message: OpenAIMessage,
-                            finish_reason: Option<String>,
                         }
 
                         #[derive(Deserialize)]
@@ -1083,7 +1793,6 @@ impl ModelAdapter for RemoteAPIAdapter {
 
                         #[derive(Deserialize)]
                         struct OpenAIUsage {
-                            prompt_tokens: u32,
                             completion_tokens: u32,
                             total_tokens: u32,
                         }
@@ -1096,9 +1805,9 @@ impl ModelAdapter for RemoteAPIAdapter {
                         let (tokens_generated, total_tokens) = if let Some(usage) = api_response.usage {
                             (usage.completion_tokens, usage.total_tokens)
                         } else {
-                            let gen = text.split_whitespace().count() as u32;
-                            let total = request.prompt.split_whitespace().count() as u32 + gen;
-                            (gen, total)
+                            let generated = text.split_whitespace().count() as u32;
+                            let total = request.prompt.split_whitespace().count() as u32 + generated;
+                            (generated, total)
                         };
 
                         (text, tokens_generated, total_tokens)
@@ -1114,7 +1823,6 @@ impl ModelAdapter for RemoteAPIAdapter {
 
                         #[derive(Deserialize)]
                         struct GenericUsage {
-                            prompt_tokens: Option<u32>,
                             completion_tokens: Option<u32>,
                             total_tokens: Option<u32>,
                         }
@@ -1134,30 +1842,41 @@ impl ModelAdapter for RemoteAPIAdapter {
                                 })
                             )
                         } else {
-                            let gen = text.split_whitespace().count() as u32;
-                            let total = request.prompt.split_whitespace().count() as u32 + gen;
-                            (gen, total)
+                            let generated = text.split_whitespace().count() as u32;
+                            let total = request.prompt.split_whitespace().count() as u32 + generated;
+                            (generated, total)
                         };
 
                         (text, tokens_generated, total_tokens)
                     }
                 };
 
+                // Not every provider honors stop sequences or max_tokens
+                let (limited_text, limited_tokens, finish_reason) =
+                    apply_generation_limits(&text, &request.parameters);
+                let tokens_generated = if limited_text == text {
+                    tokens_generated.min(request.parameters.max_tokens)
+                } else {
+                    limited_tokens
+                };
+
                 Ok(InferenceResponse {
-                    text,
+                    text: limited_text,
                     tokens_generated,
                     total_tokens,
-                    finish_reason: FinishReason::Stop,
+                    finish_reason,
                     timing: InferenceTiming {
                         prompt_eval_time: Duration::from_millis(0), // Not available from API
                         eval_time: total_time,
                         total_time,
+                        load_time: Duration::ZERO,
                     },
                     model_used: self.model_info.name.clone(),
                     is_fallback: false,
+                    context_dropped: 0,
                 })
             }
-            Ok(Err(e)) => Err(ModelHostError::Api(e)),
+            Ok(Err(e)) => Err(e),
             Err(_) => Err(ModelHostError::Timeout { 
                 timeout_ms: timeout_duration.as_millis() as u64 
             }),
@@ -1166,33 +1885,28 @@ impl ModelAdapter for RemoteAPIAdapter {
 
     async fn infer_stream(&self, request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
         if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("API not connected".to_string()));
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
         }
 
-        // For now, simulate streaming by breaking up a regular response
-        let response = self.infer(request).await?;
-        let (tx, rx) = mpsc::unbounded_channel();
-        
-        tokio::spawn(async move {
-            let words: Vec<&str> = response.text.split_whitespace().collect();
-            for (i, word) in words.iter().enumerate() {
-                tokio::time::sleep(Duration::from_millis(100)).await;
-                let stream_token = StreamToken {
-                    token: format!("{} ", word),
-                    is_final: i == words.len() - 1,
-                    token_index: i as u32,
-                    timestamp: Instant::now(),
-                };
-                if tx.send(Ok(stream_token)).is_err() {
-                    break;
-                }
-            }
-        });
+        let (api_request, endpoint) = self.build_request(&request, true)?;
+        let timeout_ms = request.timeout_ms.unwrap_or(60000);
+        let response = timeout(
+            Duration::from_millis(timeout_ms),
+            send_with_retry(&self.config.retry, &self.retries, || {
+                self.post(&endpoint, &api_request).header("Accept", "text/event-stream")
+            }),
+        )
+        .await
+        .map_err(|_| ModelHostError::Timeout { timeout_ms })??;
 
-        Ok(Box::pin(UnboundedReceiverStream::new(rx)))
+        Ok(spawn_event_stream(response, request.parameters.max_tokens, StreamParser::default()))
     }
 
     async fn batch_infer(&self, requests: Vec<InferenceRequest>) -> Result<Vec<InferenceResponse>, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
         // Remote APIs typically handle requests sequentially to respect rate limits
         let mut responses = Vec::new();
         
@@ -1223,7 +1937,7 @@ impl ModelAdapter for RemoteAPIAdapter {
 
     async fn health_check(&self) -> Result<(), ModelHostError> {
         if !self.loaded.load(Ordering::SeqCst) {
-            return Err(ModelHostError::ModelLoad("API not connected".to_string()));
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
         }
 
         let api_endpoint = self.config.api_endpoint.as_ref()
@@ -1250,69 +1964,1452 @@ impl ModelAdapter for RemoteAPIAdapter {
     }
 }
 
-pub struct ModelHost {
-    workers: Arc<RwLock<HashMap<String, Vec<ModelWorker>>>>,
-    configs: Arc<RwLock<HashMap<String, ModelConfig>>>,
-    stats: Arc<RwLock<ModelHostStats>>,
-    vram_stats: Arc<VramStats>,
-    current_model: Arc<RwLock<Option<String>>>,
-    request_queue: Arc<Mutex<VecDeque<QueuedRequest>>>,
-    hot_swap_tx: mpsc::UnboundedSender<HotSwapRequest>,
-    fallback_chains: Arc<RwLock<HashMap<String, Vec<String>>>>,
-    pool_size: usize,
-    max_concurrent: usize,
-    shutdown_tx: broadcast::Sender<()>,
+/// Ollama's native API: `/api/tags` to check the model is pulled,
+/// `/api/generate` for completions and newline-delimited JSON streaming
+pub struct OllamaAdapter {
+    config: ModelConfig,
+    model_info: ModelInfo,
+    client: Client,
+    base_url: String,
+    loaded: AtomicBool,
+    pull_missing: bool,
 }
 
-#[derive(Debug)]
-struct QueuedRequest {
-    request: InferenceRequest,
-    response_tx: tokio::sync::oneshot::Sender<Result<InferenceResponse, ModelHostError>>,
-    priority: InferencePriority,
-    queued_at: Instant,
+#[derive(Deserialize)]
+struct OllamaTags {
+    #[serde(default)]
+    models: Vec<OllamaTag>,
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct ModelHostStats {
-    pub total_requests: u64,
-    pub total_tokens_generated: u64,
-    pub total_inference_time: Duration,
-    pub cache_hits: u64,
-    pub cache_misses: u64,
-    pub errors: u64,
-    pub hot_swaps: u64,
-    pub vram_throttles: u64,
-    pub fallback_activations: u64,
-    pub batch_requests: u64,
-    pub stream_requests: u64,
-    pub queue_wait_time: Duration,
-    pub active_workers: u64,
+#[derive(Deserialize)]
+struct OllamaTag {
+    name: String,
 }
 
-#[derive(Debug, Clone)]
-pub struct HotSwapRequest {
-    pub target_model: String,
-    pub force: bool,
+#[derive(Deserialize)]
+struct OllamaGenerateResponse {
+    #[serde(default)]
+    response: String,
+    done_reason: Option<String>,
+    eval_count: Option<u32>,
+    prompt_eval_count: Option<u32>,
+    prompt_eval_duration: Option<u64>,
+    eval_duration: Option<u64>,
 }
 
-impl ModelHost {
-    pub fn new(pool_size: usize, max_concurrent: usize, total_vram_mb: u64) -> Self {
-        let (hot_swap_tx, _) = mpsc::unbounded_channel();
-        let (shutdown_tx, _) = broadcast::channel(1);
+impl OllamaAdapter {
+    pub const DEFAULT_ENDPOINT: &'static str = "http://localhost:11434";
 
+    pub fn new(config: ModelConfig) -> Self {
+        let base_url = config
+            .api_endpoint
+            .as_deref()
+            .unwrap_or(Self::DEFAULT_ENDPOINT)
+            .trim_end_matches('/')
+            .trim_end_matches("/api")
+            .to_string();
         Self {
-            workers: Arc::new(RwLock::new(HashMap::new())),
-            configs: Arc::new(RwLock::new(HashMap::new())),
-            stats: Arc::new(RwLock::new(ModelHostStats::default())),
-            vram_stats: Arc::new(VramStats::new(total_vram_mb)),
-            current_model: Arc::new(RwLock::new(None)),
-            request_queue: Arc::new(Mutex::new(VecDeque::new())),
-            hot_swap_tx,
-            fallback_chains: Arc::new(RwLock::new(HashMap::new())),
-            pool_size,
-            max_concurrent,
-            shutdown_tx,
-        }
+            model_info: ModelInfo {
+                name: config.name.clone(),
+                model_type: ModelType::Ollama,
+                context_window: config.context_window,
+                supports_streaming: true,
+                loaded_at: None,
+                vram_required_mb: config.vram_required_mb,
+            },
+            client: Client::new(),
+            base_url,
+            loaded: AtomicBool::new(false),
+            pull_missing: false,
+            config,
+        }
+    }
+
+    /// Pull the model on load when the server doesn't have it yet
+    pub fn pull_missing(mut self, pull: bool) -> Self {
+        self.pull_missing = pull;
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/{}", self.base_url, path)
+    }
+
+    /// `/api/generate` body with `options` mapped from the parameters
+    fn generate_body(&self, request: &InferenceRequest, stream: bool) -> serde_json::Value {
+        let params = &request.parameters;
+        let mut options = serde_json::json!({
+            "temperature": params.temperature,
+            "top_p": params.top_p,
+            "num_predict": params.max_tokens,
+            "repeat_penalty": params.repetition_penalty,
+            "frequency_penalty": params.frequency_penalty,
+            "presence_penalty": params.presence_penalty,
+        });
+        if let Some(top_k) = params.top_k {
+            options["top_k"] = top_k.into();
+        }
+        if !params.stop_sequences.is_empty() {
+            options["stop"] = params.stop_sequences.clone().into();
+        }
+        serde_json::json!({
+            "model": self.config.name,
+            "prompt": request.prompt,
+            "stream": stream,
+            "options": options,
+        })
+    }
+
+    async fn is_pulled(&self) -> Result<bool, ModelHostError> {
+        let tags: OllamaTags = self
+            .client
+            .get(self.url("tags"))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let name = &self.config.name;
+        Ok(tags.models.iter().any(|tag| {
+            tag.name == *name || (!name.contains(':') && tag.name == format!("{}:latest", name))
+        }))
+    }
+
+    /// Pull the model, logging progress as the status changes
+    async fn pull(&self) -> Result<(), ModelHostError> {
+        info!("Pulling Ollama model {}", self.config.name);
+        let mut response = self
+            .client
+            .post(self.url("pull"))
+            .json(&serde_json::json!({ "name": self.config.name, "stream": true }))
+            .send()
+            .await?
+            .error_for_status()?;
+        let mut buffer = Vec::new();
+        let mut last_status = String::new();
+        while let Some(chunk) = response.chunk().await? {
+            buffer.extend_from_slice(&chunk);
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let Ok(progress) = serde_json::from_slice::<serde_json::Value>(&line) else {
+                    continue;
+                };
+                if let Some(error) = progress.get("error") {
+                    return Err(ModelHostError::ModelLoad(format!(
+                        "Pulling {} failed: {}", self.config.name, error
+                    )));
+                }
+                let status = progress.get("status").and_then(|s| s.as_str()).unwrap_or_default();
+                let total = progress.get("total").and_then(|t| t.as_u64());
+                let completed = progress.get("completed").and_then(|c| c.as_u64());
+                if let (Some(total), Some(completed)) = (total, completed)
+                    && total > 0
+                {
+                    debug!("{}: {} {}%", self.config.name, status, completed * 100 / total);
+                }
+                if status != last_status {
+                    info!("{}: {}", self.config.name, status);
+                    last_status = status.to_string();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ModelAdapter for OllamaAdapter {
+    async fn load(&mut self) -> Result<(), ModelHostError> {
+        debug!("Checking Ollama at {} for {}", self.base_url, self.config.name);
+        if !self.is_pulled().await? {
+            if !self.pull_missing {
+                return Err(ModelHostError::ModelLoad(format!(
+                    "{} is not pulled; run `ollama pull {}`",
+                    self.config.name, self.config.name
+                )));
+            }
+            self.pull().await?;
+        }
+        self.loaded.store(true, Ordering::SeqCst);
+        self.model_info.loaded_at = Some(Instant::now().elapsed().as_secs());
+        info!("Ollama model ready: {}", self.model_info.name);
+        Ok(())
+    }
+
+    async fn unload(&mut self) -> Result<(), ModelHostError> {
+        if self.loaded.swap(false, Ordering::SeqCst) {
+            // Ask the server to free the model now rather than after keep_alive
+            let body = serde_json::json!({ "model": self.config.name, "keep_alive": 0 });
+            if let Err(e) = self.client.post(self.url("generate")).json(&body).send().await {
+                debug!("Ollama unload request for {} failed: {}", self.config.name, e);
+            }
+        }
+        self.model_info.loaded_at = None;
+        Ok(())
+    }
+
+    async fn infer(&self, request: InferenceRequest) -> Result<InferenceResponse, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
+        let body = self.generate_body(&request, false);
+        with_request_timeout(request.timeout_ms, 60000, async {
+            let start_time = Instant::now();
+            let api_response: OllamaGenerateResponse = self
+                .client
+                .post(self.url("generate"))
+                .json(&body)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let total_time = start_time.elapsed();
+
+            let text = api_response.response;
+            let prompt_tokens = api_response
+                .prompt_eval_count
+                .unwrap_or_else(|| request.prompt.split_whitespace().count() as u32);
+            let (limited_text, limited_tokens, limited_reason) =
+                apply_generation_limits(&text, &request.parameters);
+            let (tokens_generated, finish_reason) = if limited_text == text {
+                let generated = api_response.eval_count.unwrap_or(limited_tokens);
+                let reason = match api_response.done_reason.as_deref() {
+                    Some("length") => FinishReason::Length,
+                    _ => limited_reason,
+                };
+                (generated.min(request.parameters.max_tokens), reason)
+            } else {
+                (limited_tokens, limited_reason)
+            };
+
+            Ok(InferenceResponse {
+                text: limited_text,
+                tokens_generated,
+                total_tokens: prompt_tokens + tokens_generated,
+                finish_reason,
+                timing: InferenceTiming {
+                    prompt_eval_time: Duration::from_nanos(api_response.prompt_eval_duration.unwrap_or(0)),
+                    eval_time: api_response.eval_duration.map(Duration::from_nanos).unwrap_or(total_time),
+                    total_time,
+                    load_time: Duration::ZERO,
+                },
+                model_used: self.model_info.name.clone(),
+                is_fallback: false,
+                context_dropped: 0,
+            })
+        })
+        .await
+    }
+
+    async fn infer_stream(&self, request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
+        let body = self.generate_body(&request, true);
+        let response = with_request_timeout(request.timeout_ms, 60000, async {
+            Ok(self.client.post(self.url("generate")).json(&body).send().await?.error_for_status()?)
+        })
+        .await?;
+        Ok(spawn_event_stream(
+            response,
+            request.parameters.max_tokens,
+            StreamParser::new(StreamFormat::Ndjson),
+        ))
+    }
+
+    async fn batch_infer(&self, requests: Vec<InferenceRequest>) -> Result<Vec<InferenceResponse>, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
+        // Ollama serializes generation per model anyway
+        let mut responses = Vec::with_capacity(requests.len());
+        for request in requests {
+            responses.push(self.infer(request).await?);
+        }
+        Ok(responses)
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.loaded.load(Ordering::SeqCst)
+    }
+
+    fn get_model_info(&self) -> ModelInfo {
+        self.model_info.clone()
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+
+    fn supports_batch(&self) -> bool {
+        true
+    }
+
+    async fn health_check(&self) -> Result<(), ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+        if self.is_pulled().await? {
+            Ok(())
+        } else {
+            Err(ModelHostError::ModelLoad(format!("{} is no longer pulled", self.config.name)))
+        }
+    }
+
+    async fn warmup(&self) -> Result<(), ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+        // An empty prompt loads the model into memory without generating
+        let body = serde_json::json!({ "model": self.config.name, "prompt": "" });
+        self.client.post(self.url("generate")).json(&body).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Earlier turns alternate user/other from the first context entry; the
+/// prompt is the last user turn. Adjacent turns from the same side are
+/// merged, as chat APIs require alternation. `true` marks the user's.
+fn conversation(request: &InferenceRequest) -> Vec<(bool, String)> {
+    let turns = request
+        .context
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, text)| (i % 2 == 0, text.as_str()))
+        .chain(std::iter::once((true, request.prompt.as_str())))
+        .filter(|(_, text)| !text.is_empty());
+    let mut merged: Vec<(bool, String)> = Vec::new();
+    for (from_user, text) in turns {
+        match merged.last_mut() {
+            Some((last, content)) if *last == from_user => {
+                content.push_str("\n\n");
+                content.push_str(text);
+            }
+            _ => merged.push((from_user, text.to_string())),
+        }
+    }
+    merged
+}
+
+/// Anthropic's Messages API. `context` holds earlier turns, alternating
+/// user and assistant from the first entry.
+pub struct AnthropicAdapter {
+    config: ModelConfig,
+    api_key: Option<SecureApiKey>,
+    model_info: ModelInfo,
+    client: Client,
+    base_url: String,
+    loaded: AtomicBool,
+    retries: Arc<AtomicU64>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicResponse {
+    content: Vec<AnthropicContentBlock>,
+    stop_reason: Option<String>,
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct AnthropicUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+impl AnthropicAdapter {
+    pub const DEFAULT_ENDPOINT: &'static str = "https://api.anthropic.com";
+    pub const API_VERSION: &'static str = "2023-06-01";
+
+    pub fn new(config: ModelConfig) -> Result<Self, ModelHostError> {
+        let api_key = match &config.api_key_env {
+            Some(env_var) => Some(SecureApiKey::from_env(env_var)?),
+            None => SecureApiKey::from_env("ANTHROPIC_API_KEY").ok(),
+        };
+        let base_url = config
+            .api_endpoint
+            .as_deref()
+            .unwrap_or(Self::DEFAULT_ENDPOINT)
+            .trim_end_matches('/')
+            .trim_end_matches("/messages")
+            .trim_end_matches("/v1")
+            .to_string();
+        Ok(Self {
+            model_info: ModelInfo {
+                name: config.name.clone(),
+                model_type: ModelType::Anthropic,
+                context_window: config.context_window,
+                supports_streaming: true,
+                loaded_at: None,
+                vram_required_mb: 0,
+            },
+            api_key,
+            client: Client::new(),
+            base_url,
+            loaded: AtomicBool::new(false),
+            retries: Arc::default(),
+            config,
+        })
+    }
+
+    pub fn with_api_key(mut self, key: SecureApiKey) -> Self {
+        self.api_key = Some(key);
+        self
+    }
+
+    /// Count retries into a shared counter
+    pub fn with_retry_counter(mut self, retries: Arc<AtomicU64>) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn messages(request: &InferenceRequest) -> Vec<serde_json::Value> {
+        conversation(request)
+            .into_iter()
+            .map(|(from_user, content)| {
+                let role = if from_user { "user" } else { "assistant" };
+                serde_json::json!({ "role": role, "content": content })
+            })
+            .collect()
+    }
+
+    fn build_request(&self, request: &InferenceRequest, stream: bool) -> serde_json::Value {
+        let params = &request.parameters;
+        let mut body = serde_json::json!({
+            "model": self.config.name,
+            "max_tokens": params.max_tokens.max(1),
+            "messages": Self::messages(request),
+            "temperature": params.temperature,
+            "top_p": params.top_p,
+        });
+        if let Some(top_k) = params.top_k {
+            body["top_k"] = top_k.into();
+        }
+        if !params.stop_sequences.is_empty() {
+            body["stop_sequences"] = params.stop_sequences.clone().into();
+        }
+        if stream {
+            body["stream"] = true.into();
+        }
+        body
+    }
+
+    fn authorized(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let builder = builder.header("anthropic-version", Self::API_VERSION);
+        match &self.api_key {
+            Some(key) => builder.header("x-api-key", key.get()),
+            None => builder,
+        }
+    }
+
+    /// Check the key against the models endpoint
+    async fn check_connection(&self) -> Result<(), ModelHostError> {
+        if self.api_key.is_none() {
+            return Err(ModelHostError::Authentication("No Anthropic API key configured".to_string()));
+        }
+        let response = self
+            .authorized(self.client.get(format!("{}/v1/models", self.base_url)))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(ModelHostError::Authentication(
+                format!("Anthropic rejected the API key ({})", response.status()),
+            )),
+            _ => {
+                response.error_for_status()?;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish_reason(stop_reason: Option<&str>) -> FinishReason {
+        match stop_reason {
+            Some("max_tokens") => FinishReason::Length,
+            Some("refusal") => FinishReason::Error("refusal".to_string()),
+            _ => FinishReason::Stop,
+        }
+    }
+}
+
+#[async_trait]
+impl ModelAdapter for AnthropicAdapter {
+    async fn load(&mut self) -> Result<(), ModelHostError> {
+        debug!("Connecting to Anthropic at {}", self.base_url);
+        self.check_connection().await?;
+        self.loaded.store(true, Ordering::SeqCst);
+        self.model_info.loaded_at = Some(Instant::now().elapsed().as_secs());
+        info!("Connected to Anthropic: {}", self.model_info.name);
+        Ok(())
+    }
+
+    async fn unload(&mut self) -> Result<(), ModelHostError> {
+        self.loaded.store(false, Ordering::SeqCst);
+        self.model_info.loaded_at = None;
+        Ok(())
+    }
+
+    async fn infer(&self, request: InferenceRequest) -> Result<InferenceResponse, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
+        let body = self.build_request(&request, false);
+        let url = format!("{}/v1/messages", self.base_url);
+        with_request_timeout(request.timeout_ms, 60000, async {
+            let start_time = Instant::now();
+            let api_response: AnthropicResponse = send_with_retry(&self.config.retry, &self.retries, || {
+                self.authorized(self.client.post(&url)).json(&body)
+            })
+            .await?
+            .json()
+            .await?;
+            let total_time = start_time.elapsed();
+
+            let text: String = api_response
+                .content
+                .iter()
+                .filter(|block| block.kind == "text")
+                .map(|block| block.text.as_str())
+                .collect();
+            let (input_tokens, output_tokens) = match &api_response.usage {
+                Some(usage) => (usage.input_tokens, Some(usage.output_tokens)),
+                None => (request.prompt.split_whitespace().count() as u32, None),
+            };
+            let (limited_text, limited_tokens, limited_reason) =
+                apply_generation_limits(&text, &request.parameters);
+            let (tokens_generated, finish_reason) = if limited_text == text {
+                let generated = output_tokens.unwrap_or(limited_tokens);
+                (
+                    generated.min(request.parameters.max_tokens),
+                    Self::finish_reason(api_response.stop_reason.as_deref()),
+                )
+            } else {
+                (limited_tokens, limited_reason)
+            };
+
+            Ok(InferenceResponse {
+                text: limited_text,
+                tokens_generated,
+                total_tokens: input_tokens + tokens_generated,
+                finish_reason,
+                timing: InferenceTiming {
+                    prompt_eval_time: Duration::from_millis(0), // Not available from API
+                    eval_time: total_time,
+                    total_time,
+                    load_time: Duration::ZERO,
+                },
+                model_used: self.model_info.name.clone(),
+                is_fallback: false,
+                context_dropped: 0,
+            })
+        })
+        .await
+    }
+
+    async fn infer_stream(&self, request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
+        let body = self.build_request(&request, true);
+        let url = format!("{}/v1/messages", self.base_url);
+        let response = with_request_timeout(
+            request.timeout_ms,
+            60000,
+            send_with_retry(&self.config.retry, &self.retries, || {
+                self.authorized(self.client.post(&url))
+                    .header("Accept", "text/event-stream")
+                    .json(&body)
+            }),
+        )
+        .await?;
+        Ok(spawn_event_stream(response, request.parameters.max_tokens, StreamParser::default()))
+    }
+
+    async fn batch_infer(&self, requests: Vec<InferenceRequest>) -> Result<Vec<InferenceResponse>, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
+        let mut responses = Vec::with_capacity(requests.len());
+        for request in requests {
+            responses.push(self.infer(request).await?);
+        }
+        Ok(responses)
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.loaded.load(Ordering::SeqCst)
+    }
+
+    fn get_model_info(&self) -> ModelInfo {
+        self.model_info.clone()
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+
+    fn supports_batch(&self) -> bool {
+        true
+    }
+
+    async fn health_check(&self) -> Result<(), ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+        self.check_connection().await
+    }
+
+    async fn warmup(&self) -> Result<(), ModelHostError> {
+        self.health_check().await
+    }
+}
+
+/// Google's Gemini `generateContent` API. `context` holds earlier turns,
+/// alternating user and model from the first entry.
+pub struct GeminiAdapter {
+    config: ModelConfig,
+    api_key: Option<SecureApiKey>,
+    model_info: ModelInfo,
+    client: Client,
+    base_url: String,
+    loaded: AtomicBool,
+    retries: Arc<AtomicU64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiUsage {
+    #[serde(default)]
+    prompt_token_count: u32,
+    candidates_token_count: Option<u32>,
+}
+
+impl GeminiAdapter {
+    pub const DEFAULT_ENDPOINT: &'static str = "https://generativelanguage.googleapis.com";
+
+    pub fn new(config: ModelConfig) -> Result<Self, ModelHostError> {
+        let api_key = match &config.api_key_env {
+            Some(env_var) => Some(SecureApiKey::from_env(env_var)?),
+            None => SecureApiKey::from_env("GEMINI_API_KEY").ok(),
+        };
+        let base_url = config
+            .api_endpoint
+            .as_deref()
+            .unwrap_or(Self::DEFAULT_ENDPOINT)
+            .trim_end_matches('/')
+            .trim_end_matches("/models")
+            .trim_end_matches("/v1beta")
+            .to_string();
+        Ok(Self {
+            model_info: ModelInfo {
+                name: config.name.clone(),
+                model_type: ModelType::Gemini,
+                context_window: config.context_window,
+                supports_streaming: true,
+                loaded_at: None,
+                vram_required_mb: 0,
+            },
+            api_key,
+            client: Client::new(),
+            base_url,
+            loaded: AtomicBool::new(false),
+            retries: Arc::default(),
+            config,
+        })
+    }
+
+    pub fn with_api_key(mut self, key: SecureApiKey) -> Self {
+        self.api_key = Some(key);
+        self
+    }
+
+    /// Count retries into a shared counter
+    pub fn with_retry_counter(mut self, retries: Arc<AtomicU64>) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn contents(request: &InferenceRequest) -> Vec<serde_json::Value> {
+        conversation(request)
+            .into_iter()
+            .map(|(from_user, text)| {
+                let role = if from_user { "user" } else { "model" };
+                serde_json::json!({ "role": role, "parts": [{ "text": text }] })
+            })
+            .collect()
+    }
+
+    fn build_request(&self, request: &InferenceRequest) -> serde_json::Value {
+        let params = &request.parameters;
+        let mut generation_config = serde_json::json!({
+            "temperature": params.temperature,
+            "topP": params.top_p,
+            "maxOutputTokens": params.max_tokens.max(1),
+        });
+        if let Some(top_k) = params.top_k {
+            generation_config["topK"] = top_k.into();
+        }
+        if !params.stop_sequences.is_empty() {
+            generation_config["stopSequences"] = params.stop_sequences.clone().into();
+        }
+        serde_json::json!({
+            "contents": Self::contents(request),
+            "generationConfig": generation_config,
+        })
+    }
+
+    /// `{base}/v1beta/models/{model}`, followed by `:method` if given
+    fn url(&self, method: Option<&str>) -> String {
+        let model = self.config.name.trim_start_matches("models/");
+        match method {
+            Some(method) => format!("{}/v1beta/models/{}:{}", self.base_url, model, method),
+            None => format!("{}/v1beta/models/{}", self.base_url, model),
+        }
+    }
+
+    /// Gemini takes the key as a query parameter rather than a header
+    fn authorized(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) => builder.query(&[("key", key.get())]),
+            None => builder,
+        }
+    }
+
+    /// Check the key and model against the model's metadata
+    async fn check_connection(&self) -> Result<(), ModelHostError> {
+        if self.api_key.is_none() {
+            return Err(ModelHostError::Authentication("No Gemini API key configured".to_string()));
+        }
+        let response = self
+            .authorized(self.client.get(self.url(None)))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?;
+        match response.status() {
+            // An invalid key is a 400 with reason API_KEY_INVALID
+            StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Err(ModelHostError::Authentication(format!(
+                    "Gemini rejected the API key ({})",
+                    response.status()
+                )))
+            }
+            StatusCode::NOT_FOUND => Err(ModelHostError::ModelNotFound { name: self.config.name.clone() }),
+            _ => {
+                response.error_for_status()?;
+                Ok(())
+            }
+        }
+    }
+
+    /// The first candidate's text parts, joined
+    pub fn candidate_text(response: &serde_json::Value) -> Option<String> {
+        let parts = response.pointer("/candidates/0/content/parts")?.as_array()?;
+        Some(parts.iter().filter_map(|part| part["text"].as_str()).collect())
+    }
+
+    /// Safety and other blocks, of the prompt or the candidate, are errors
+    /// carrying the block reason
+    pub fn finish_reason(finish_reason: Option<&str>, response: &serde_json::Value) -> FinishReason {
+        if let Some(block) = response.pointer("/promptFeedback/blockReason").and_then(|reason| reason.as_str()) {
+            return FinishReason::Error(format!("prompt blocked: {}", block));
+        }
+        match finish_reason {
+            None | Some("STOP") | Some("FINISH_REASON_UNSPECIFIED") => FinishReason::Stop,
+            Some("MAX_TOKENS") => FinishReason::Length,
+            Some(reason) => FinishReason::Error(format!("response blocked: {}", reason)),
+        }
+    }
+}
+
+#[async_trait]
+impl ModelAdapter for GeminiAdapter {
+    async fn load(&mut self) -> Result<(), ModelHostError> {
+        debug!("Connecting to Gemini at {}", self.base_url);
+        self.check_connection().await?;
+        self.loaded.store(true, Ordering::SeqCst);
+        self.model_info.loaded_at = Some(Instant::now().elapsed().as_secs());
+        info!("Connected to Gemini: {}", self.model_info.name);
+        Ok(())
+    }
+
+    async fn unload(&mut self) -> Result<(), ModelHostError> {
+        self.loaded.store(false, Ordering::SeqCst);
+        self.model_info.loaded_at = None;
+        Ok(())
+    }
+
+    async fn infer(&self, request: InferenceRequest) -> Result<InferenceResponse, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
+        let body = self.build_request(&request);
+        let url = self.url(Some("generateContent"));
+        with_request_timeout(request.timeout_ms, 60000, async {
+            let start_time = Instant::now();
+            let api_response: serde_json::Value = send_with_retry(&self.config.retry, &self.retries, || {
+                self.authorized(self.client.post(&url)).json(&body)
+            })
+            .await?
+            .json()
+            .await?;
+            let total_time = start_time.elapsed();
+
+            let text = Self::candidate_text(&api_response).unwrap_or_default();
+            let finish = api_response.pointer("/candidates/0/finishReason").and_then(|reason| reason.as_str());
+            let usage = api_response
+                .get("usageMetadata")
+                .and_then(|usage| serde_json::from_value::<GeminiUsage>(usage.clone()).ok());
+            let (input_tokens, output_tokens) = match usage {
+                Some(usage) => (usage.prompt_token_count, usage.candidates_token_count),
+                None => (request.prompt.split_whitespace().count() as u32, None),
+            };
+            let (limited_text, limited_tokens, limited_reason) =
+                apply_generation_limits(&text, &request.parameters);
+            let (tokens_generated, finish_reason) = if limited_text == text {
+                let generated = output_tokens.unwrap_or(limited_tokens);
+                (
+                    generated.min(request.parameters.max_tokens),
+                    Self::finish_reason(finish, &api_response),
+                )
+            } else {
+                (limited_tokens, limited_reason)
+            };
+
+            Ok(InferenceResponse {
+                text: limited_text,
+                tokens_generated,
+                total_tokens: input_tokens + tokens_generated,
+                finish_reason,
+                timing: InferenceTiming {
+                    prompt_eval_time: Duration::from_millis(0), // Not available from API
+                    eval_time: total_time,
+                    total_time,
+                    load_time: Duration::ZERO,
+                },
+                model_used: self.model_info.name.clone(),
+                is_fallback: false,
+                context_dropped: 0,
+            })
+        })
+        .await
+    }
+
+    async fn infer_stream(&self, request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
+        let body = self.build_request(&request);
+        let url = self.url(Some("streamGenerateContent"));
+        let response = with_request_timeout(
+            request.timeout_ms,
+            60000,
+            send_with_retry(&self.config.retry, &self.retries, || {
+                self.authorized(self.client.post(&url))
+                    .query(&[("alt", "sse")])
+                    .header("Accept", "text/event-stream")
+                    .json(&body)
+            }),
+        )
+        .await?;
+        Ok(spawn_event_stream(response, request.parameters.max_tokens, StreamParser::default()))
+    }
+
+    async fn batch_infer(&self, requests: Vec<InferenceRequest>) -> Result<Vec<InferenceResponse>, ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+
+        let mut responses = Vec::with_capacity(requests.len());
+        for request in requests {
+            responses.push(self.infer(request).await?);
+        }
+        Ok(responses)
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.loaded.load(Ordering::SeqCst)
+    }
+
+    fn get_model_info(&self) -> ModelInfo {
+        self.model_info.clone()
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+
+    fn supports_batch(&self) -> bool {
+        true
+    }
+
+    async fn health_check(&self) -> Result<(), ModelHostError> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Err(ModelHostError::NotLoaded { name: self.model_info.name.clone() });
+        }
+        self.check_connection().await
+    }
+
+    async fn warmup(&self) -> Result<(), ModelHostError> {
+        self.health_check().await
+    }
+}
+
+async fn check_budget(
+    configs: &RwLock<HashMap<String, ModelConfig>>,
+    usage: &UsageTracker,
+    model_name: &str,
+) -> Result<(), ModelHostError> {
+    let budget = configs.read().await.get(model_name).and_then(|config| config.daily_budget);
+    match budget {
+        Some(budget) if usage.cost_today(model_name) >= budget => Err(ModelHostError::BudgetExceeded {
+            name: model_name.to_string(),
+            budget,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The host's workers and request queue, shareable with tasks that
+/// outlive a call, such as streams
+#[derive(Clone)]
+struct WorkerPool {
+    workers: Arc<RwLock<HashMap<String, Vec<Arc<ModelWorker>>>>>,
+    request_queue: Arc<Mutex<VecDeque<QueuedRequest>>>,
+    stats: Arc<RwLock<ModelHostStats>>,
+    pool_size: usize,
+    accepting: Arc<AtomicBool>,
+    abort: Arc<watch::Sender<bool>>,
+}
+
+impl WorkerPool {
+    /// Claim a free worker for a model. When all are busy the request
+    /// waits in the queue, ahead of lower priorities and behind earlier
+    /// arrivals of its own, until a worker is handed to it or its timeout
+    /// passes.
+    async fn claim(
+        &self,
+        model_name: &str,
+        priority: InferencePriority,
+        timeout_ms: Option<u64>,
+    ) -> Result<Arc<ModelWorker>, ModelHostError> {
+        if !self.accepting.load(Ordering::SeqCst) {
+            return Err(ModelHostError::ShuttingDown);
+        }
+        let queued_at = Instant::now();
+        let mut worker_rx = {
+            // Held while looking so a worker freed meanwhile finds the waiter
+            let mut queue = self.request_queue.lock().await;
+            let workers = self.workers.read().await;
+            let model_workers = workers.get(model_name)
+                .ok_or_else(|| ModelHostError::ModelNotFound { name: model_name.to_string() })?;
+
+            for worker in model_workers {
+                if worker
+                    .is_busy
+                    .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    return Ok(worker.clone());
+                }
+            }
+
+            // Requests that gave up leave their entry behind
+            queue.retain(|queued| !queued.worker_tx.is_closed());
+            let (worker_tx, worker_rx) = tokio::sync::oneshot::channel();
+            let position = queue
+                .iter()
+                .position(|queued| queued.priority < priority)
+                .unwrap_or(queue.len());
+            queue.insert(position, QueuedRequest {
+                model_name: model_name.to_string(),
+                worker_tx,
+                priority,
+                queued_at,
+            });
+            debug!("All {} workers for {} busy; queued at {}", model_workers.len(), model_name, position);
+            worker_rx
+        };
+
+        let timeout_ms = timeout_ms.unwrap_or(30000);
+        let result = timeout(Duration::from_millis(timeout_ms), &mut worker_rx).await;
+        self.stats.write().await.queue_wait_time += queued_at.elapsed();
+        match result {
+            Ok(Ok(worker)) => Ok(worker),
+            // Shutdown empties the queue
+            Ok(Err(_)) if !self.accepting.load(Ordering::SeqCst) => Err(ModelHostError::ShuttingDown),
+            Ok(Err(_)) => Err(ModelHostError::PoolExhausted { count: self.pool_size }),
+            Err(_) => {
+                // A worker handed over just as the wait ran out goes back
+                worker_rx.close();
+                if let Ok(worker) = worker_rx.try_recv() {
+                    self.release(model_name, worker).await;
+                }
+                Err(ModelHostError::Timeout { timeout_ms })
+            }
+        }
+    }
+
+    /// Give a claimed worker to the first request queued for its model,
+    /// or mark it free. A worker replaced by `restart_worker` is dropped.
+    async fn release(&self, model_name: &str, worker: Arc<ModelWorker>) {
+        let in_pool = self.workers.read().await
+            .get(model_name)
+            .is_some_and(|pool| pool.iter().any(|w| Arc::ptr_eq(w, &worker)));
+        if !in_pool {
+            return;
+        }
+
+        let mut queue = self.request_queue.lock().await;
+        let mut worker = worker;
+        while let Some(index) = queue.iter().position(|queued| queued.model_name == model_name) {
+            let queued = queue.remove(index).expect("index is in range");
+            match queued.worker_tx.send(worker) {
+                Ok(()) => {
+                    debug!("Worker for {} handed to a request queued {:?} ago", model_name, queued.queued_at.elapsed());
+                    return;
+                }
+                // That request timed out; try the next one
+                Err(returned) => worker = returned,
+            }
+        }
+        worker.is_busy.store(false, Ordering::SeqCst);
+    }
+
+    /// Run `work` unless shutdown gives up on it first
+    async fn abortable<T>(
+        &self,
+        work: impl Future<Output = Result<T, ModelHostError>>,
+    ) -> Result<T, ModelHostError> {
+        let mut abort = self.abort.subscribe();
+        tokio::select! {
+            result = work => result,
+            _ = abort.wait_for(|aborted| *aborted) => Err(ModelHostError::ShuttingDown),
+        }
+    }
+}
+
+/// What a stream needs from the host to move to a fallback model after
+/// the call that started it has returned
+#[derive(Clone)]
+struct StreamContext {
+    pool: WorkerPool,
+    leases: Leases,
+    configs: Arc<RwLock<HashMap<String, ModelConfig>>>,
+    usage: Arc<UsageTracker>,
+}
+
+impl StreamContext {
+    /// Start streaming on the first of the chain's remaining models that
+    /// will, holding its worker until the stream is done
+    async fn start(
+        &self,
+        chain: &mut FallbackChain,
+        request: &InferenceRequest,
+    ) -> Result<(String, TokenStream, WorkerGuard), ModelHostError> {
+        let mut last_error = None;
+        while let Some(model_name) = chain.next_model() {
+            match self.start_on(&model_name, request).await {
+                Ok((stream, guard)) => return Ok((model_name, stream, guard)),
+                Err(e) => {
+                    warn!("Model {} failed to start streaming: {}", model_name, e);
+                    self.usage.record_error(&model_name);
+                    chain.mark_failed(model_name);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or(ModelHostError::FallbackExhausted { count: chain.models.len() }))
+    }
+
+    async fn start_on(
+        &self,
+        model_name: &str,
+        request: &InferenceRequest,
+    ) -> Result<(TokenStream, WorkerGuard), ModelHostError> {
+        check_budget(&self.configs, &self.usage, model_name).await?;
+        let worker = self.pool.claim(model_name, request.priority.clone(), request.timeout_ms).await?;
+        let guard = WorkerGuard {
+            pool: self.pool.clone(),
+            model_name: model_name.to_string(),
+            _lease: self.leases.take(&worker.id),
+            worker: Some(worker.clone()),
+        };
+        let mut request = request.clone();
+        request.model_name = model_name.to_string();
+        let stream = worker.adapter.lock().await.infer_stream(request).await?;
+        *worker.last_used.lock().await = Instant::now();
+        Ok((stream, guard))
+    }
+
+    /// A finished or stopped stream, one token per delta, `elapsed` after
+    /// it started
+    async fn record(&self, model_name: &str, tokens: u32, elapsed: Duration) {
+        let cost = self
+            .configs
+            .read()
+            .await
+            .get(model_name)
+            .map_or(0.0, |config| config.cost(0, tokens as u64));
+        self.usage.record(model_name, 0, tokens as u64, cost);
+        self.pool.stats.write().await.per_model.entry(model_name.to_string()).or_default().record(elapsed);
+    }
+}
+
+/// A claimed worker and its lease; dropping it hands the worker on
+struct WorkerGuard {
+    pool: WorkerPool,
+    model_name: String,
+    worker: Option<Arc<ModelWorker>>,
+    _lease: Lease,
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        let Some(worker) = self.worker.take() else {
+            return;
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let (pool, model_name) = (self.pool.clone(), std::mem::take(&mut self.model_name));
+                runtime.spawn(async move { pool.release(&model_name, worker).await });
+            }
+            Err(_) => worker.is_busy.store(false, Ordering::SeqCst),
+        }
+    }
+}
+
+pub struct ModelHost {
+    workers: Arc<RwLock<HashMap<String, Vec<Arc<ModelWorker>>>>>,
+    configs: Arc<RwLock<HashMap<String, ModelConfig>>>,
+    stats: Arc<RwLock<ModelHostStats>>,
+    /// Remote retries, shared with the adapters
+    retries: Arc<AtomicU64>,
+    usage: Arc<UsageTracker>,
+    circuit_breaker: CircuitBreakerConfig,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+    health_ttl: Duration,
+    /// Streams failing before this many tokens restart on a fallback
+    stream_fallback_tokens: u32,
+    token_estimator: Arc<dyn TokenEstimator>,
+    vram_stats: Arc<VramStats>,
+    current_model: Arc<RwLock<Option<String>>>,
+    request_queue: Arc<Mutex<VecDeque<QueuedRequest>>>,
+    hot_swap_tx: mpsc::UnboundedSender<HotSwapRequest>,
+    /// Taken by `start_hot_swaps`; until then `process_hot_swap_requests`
+    /// drains it
+    hot_swap_rx: Mutex<Option<mpsc::UnboundedReceiver<HotSwapRequest>>>,
+    /// Models the warm pool has out of VRAM and means to bring back
+    parked: Mutex<HashMap<String, Parked>>,
+    /// Held while reloading a parked model, so requests arriving together
+    /// load it once
+    reloading: Mutex<()>,
+    fallback_chains: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    pool_size: usize,
+    max_concurrent: usize,
+    shutdown_tx: broadcast::Sender<()>,
+    /// Cleared by `shutdown`; new requests are refused
+    accepting: Arc<AtomicBool>,
+    /// Set when shutdown stops waiting for in-flight requests
+    abort: Arc<watch::Sender<bool>>,
+    /// Busy workers, expired by the watchdog past its hard cap
+    leases: Leases,
+    dispatcher: Option<Heartbeat>,
+}
+
+/// A registered model as `ModelHost::model_states` reports it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelState {
+    pub name: String,
+    pub model_type: Option<ModelType>,
+    pub loaded: bool,
+    pub workers: usize,
+}
+
+/// Requests `ModelHost::shutdown` let finish, and those it gave up on
+/// or that were still queued
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    pub drained: usize,
+    pub aborted: usize,
+}
+
+/// A request waiting for one of its model's workers. Whoever frees a
+/// worker hands it to the first waiter for that model.
+struct QueuedRequest {
+    model_name: String,
+    worker_tx: tokio::sync::oneshot::Sender<Arc<ModelWorker>>,
+    priority: InferencePriority,
+    queued_at: Instant,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ModelHostStats {
+    pub total_requests: u64,
+    pub total_tokens_generated: u64,
+    pub total_inference_time: Duration,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub errors: u64,
+    pub hot_swaps: u64,
+    pub vram_throttles: u64,
+    pub fallback_activations: u64,
+    pub batch_requests: u64,
+    pub stream_requests: u64,
+    pub queue_wait_time: Duration,
+    pub active_workers: u64,
+    pub retries: u64,
+    /// Local models unloaded for sitting idle
+    pub auto_unloads: u64,
+    /// Times each model was passed over with its circuit open
+    pub skipped_models: HashMap<String, u64>,
+    /// Requests each model answered and how long they took
+    pub per_model: HashMap<String, ModelLatency>,
+}
+
+/// Requests answered by one model and the time they took, streams timed
+/// to their last token
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ModelLatency {
+    pub requests: u64,
+    pub total_time: Duration,
+}
+
+impl ModelLatency {
+    pub fn record(&mut self, elapsed: Duration) {
+        self.requests += 1;
+        self.total_time += elapsed;
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        (self.requests > 0).then(|| self.total_time / self.requests as u32)
+    }
+}
+
+/// Hot-swaps slower than this are flagged in their summary
+pub const HOT_SWAP_TARGET: Duration = Duration::from_secs(3);
+
+/// How often the warm pool looks for idle models and room to reload
+const WARM_POOL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Why the warm pool has a model out of VRAM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parked {
+    /// Unused past its `idle_unload_secs`; its next request reloads it
+    Idle,
+    /// A `keep_warm` model a hot-swap unloaded; reloaded once it fits
+    Swapped,
+}
+
+#[derive(Debug, Clone)]
+pub struct HotSwapRequest {
+    pub target_model: String,
+    /// Evict other loaded models when VRAM is short
+    pub force: bool,
+    pub progress: mpsc::UnboundedSender<HotSwapProgress>,
+}
+
+/// Steps of a hot-swap, sent as each starts
+#[derive(Debug, Clone, PartialEq)]
+pub enum HotSwapProgress {
+    Unloading(String),
+    Loading(String),
+    WarmingUp,
+    Ready(HotSwapSummary),
+    Failed(String),
+}
+
+/// A finished hot-swap
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotSwapSummary {
+    pub model: String,
+    /// Models unloaded to make room, the previous one first
+    pub unloaded: Vec<String>,
+    pub elapsed: Duration,
+}
+
+impl HotSwapSummary {
+    /// Took longer than `HOT_SWAP_TARGET`
+    pub fn is_slow(&self) -> bool {
+        self.elapsed > HOT_SWAP_TARGET
+    }
+}
+
+impl ModelHost {
+    pub fn new(pool_size: usize, max_concurrent: usize, vram: VramBudget) -> Self {
+        let vram = vram.resolve();
+        if vram.source != VramSource::Fixed {
+            info!("VRAM budget: {}MB from {}", vram.total_mb, vram.source);
+        }
+        let (hot_swap_tx, hot_swap_rx) = mpsc::unbounded_channel();
+        let (shutdown_tx, _) = broadcast::channel(1);
+
+        Self {
+            workers: Arc::new(RwLock::new(HashMap::new())),
+            configs: Arc::new(RwLock::new(HashMap::new())),
+            stats: Arc::new(RwLock::new(ModelHostStats::default())),
+            retries: Arc::default(),
+            usage: Arc::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            circuits: Arc::default(),
+            health_ttl: Duration::from_secs(30),
+            stream_fallback_tokens: 16,
+            token_estimator: Arc::new(ByteEstimator),
+            vram_stats: Arc::new(VramStats::new(vram.total_mb, vram.source)),
+            current_model: Arc::new(RwLock::new(None)),
+            request_queue: Arc::new(Mutex::new(VecDeque::new())),
+            hot_swap_tx,
+            hot_swap_rx: Mutex::new(Some(hot_swap_rx)),
+            parked: Mutex::default(),
+            reloading: Mutex::default(),
+            fallback_chains: Arc::new(RwLock::new(HashMap::new())),
+            pool_size,
+            max_concurrent,
+            shutdown_tx,
+            accepting: Arc::new(AtomicBool::new(true)),
+            abort: Arc::new(watch::Sender::new(false)),
+            leases: Leases::default(),
+            dispatcher: None,
+        }
+    }
+
+    /// Keep per-model usage in `path` across restarts
+    pub fn with_usage_file(mut self, path: PathBuf) -> Self {
+        self.usage = Arc::new(UsageTracker::with_file(path));
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = config;
+        self
+    }
+
+    /// Restart a stream on the next fallback model when it fails before
+    /// producing `tokens` tokens
+    pub fn with_stream_fallback_tokens(mut self, tokens: u32) -> Self {
+        self.stream_fallback_tokens = tokens;
+        self
+    }
+
+    /// Count tokens with `estimator` rather than by bytes, e.g. with the
+    /// model's own tokenizer
+    pub fn with_token_estimator(mut self, estimator: impl TokenEstimator + 'static) -> Self {
+        self.token_estimator = Arc::new(estimator);
+        self
+    }
+
+    /// How long a passed health check is trusted before requests check again
+    pub fn with_health_ttl(mut self, ttl: Duration) -> Self {
+        self.health_ttl = ttl;
+        self
+    }
+
+    /// Re-check idle workers of loaded models every health TTL until
+    /// shutdown, so requests rarely wait on a health check
+    pub fn start_health_monitor(&self) -> tokio::task::JoinHandle<()> {
+        let workers = self.workers.clone();
+        let ttl = self.health_ttl;
+        let mut shutdown = self.shutdown_tx.subscribe();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ttl);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.recv() => return,
+                }
+                let pool: Vec<Arc<ModelWorker>> = workers.read().await.values().flatten().cloned().collect();
+                for worker in pool {
+                    // Busy workers are checked on a later round
+                    let Ok(adapter) = worker.adapter.try_lock() else {
+                        continue;
+                    };
+                    if !adapter.is_loaded() {
+                        continue;
+                    }
+                    let result = adapter.health_check().await;
+                    drop(adapter);
+                    if let Err(e) = &result {
+                        warn!("Worker {} failed its health check: {}", worker.id, e);
+                    }
+                    worker.record_health(result.is_ok()).await;
+                }
+            }
+        })
+    }
+
+    /// Cached health of each worker, by model
+    pub async fn get_health_report(&self) -> BTreeMap<String, Vec<WorkerHealth>> {
+        let pools: Vec<(String, Vec<Arc<ModelWorker>>)> = self
+            .workers
+            .read()
+            .await
+            .iter()
+            .map(|(name, pool)| (name.clone(), pool.clone()))
+            .collect();
+        let mut report = BTreeMap::new();
+        for (name, pool) in pools {
+            let mut workers = Vec::with_capacity(pool.len());
+            for worker in pool {
+                workers.push(WorkerHealth {
+                    worker_id: worker.id.clone(),
+                    healthy: worker.healthy.load(Ordering::SeqCst),
+                    busy: worker.is_busy.load(Ordering::SeqCst),
+                    checked_ago: worker.last_health_check.lock().await.map(|at| at.elapsed()),
+                    requests_processed: worker.requests_processed.load(Ordering::SeqCst),
+                });
+            }
+            report.insert(name, workers);
+        }
+        report
+    }
+
+    pub async fn circuit_state(&self, model_name: &str) -> CircuitState {
+        self.circuits
+            .lock()
+            .await
+            .get(model_name)
+            .map_or(CircuitState::Closed, |circuit| circuit.state(Instant::now()))
+    }
+
+    /// Beat once per dispatch cycle and lease workers from `watchdog`
+    pub fn watch(&mut self, watchdog: &mut Watchdog, max_cycle: Duration) {
+        self.leases = watchdog.leases();
+        self.dispatcher = Some(watchdog.register(Component::ModelDispatcher, max_cycle));
+    }
+
+    fn create_adapter(&self, config: &ModelConfig) -> Result<Box<dyn ModelAdapter>, ModelHostError> {
+        Ok(match config.model_type {
+            ModelType::LocalGGUF => Box::new(LocalGGUFAdapter::new(config.clone())),
+            ModelType::MLC => Box::new(MLCAdapter::new(config.clone())),
+            ModelType::VLLM => Box::new(VLLMAdapter::new(config.clone())),
+            ModelType::Ollama => Box::new(OllamaAdapter::new(config.clone())),
+            ModelType::Anthropic => {
+                Box::new(AnthropicAdapter::new(config.clone())?.with_retry_counter(self.retries.clone()))
+            }
+            ModelType::Gemini => {
+                Box::new(GeminiAdapter::new(config.clone())?.with_retry_counter(self.retries.clone()))
+            }
+            ModelType::OpenAI | ModelType::RemoteAPI => {
+                Box::new(RemoteAPIAdapter::new(config.clone())?.with_retry_counter(self.retries.clone()))
+            }
+        })
     }
 
     /// Register a model with its configuration
@@ -1323,49 +3420,15 @@ impl ModelHost {
         let name = config.name.clone();
         info!("Registering model: {} (type: {:?})", name, config.model_type);
 
-        // Create adapter based on model type
-        let adapter: Box<dyn ModelAdapter> = match config.model_type {
-            ModelType::LocalGGUF => Box::new(LocalGGUFAdapter::new(config.clone())),
-            ModelType::MLC => Box::new(MLCAdapter::new(config.clone())),
-            ModelType::VLLM => Box::new(VLLMAdapter::new(config.clone())),
-            ModelType::OpenAI | ModelType::Gemini | ModelType::Anthropic | 
-            ModelType::Ollama | ModelType::RemoteAPI => {
-                Box::new(RemoteAPIAdapter::new(config.clone())?)
-            }
-        };
-
-        // Create worker pool for this model
+        // Create worker pool for this model, one adapter per worker
         let mut workers = Vec::new();
         let pool_size = config.warm_pool_size.max(1);
-        
+
         for i in 0..pool_size {
-            let worker_id = format!("{}-worker-{}", name, i);
-            let worker = ModelWorker {
-                id: worker_id.clone(),
-                adapter: Arc::new(Mutex::new(adapter)),
-                is_busy: AtomicBool::new(false),
-                last_used: Arc::new(Mutex::new(Instant::now())),
-                requests_processed: AtomicU64::new(0),
-            };
-            workers.push(worker);
-            
-            // Only create one adapter and clone the reference
-            if i == 0 {
-                continue;
-            }
-            
-            // For subsequent workers, create new adapters
-            let adapter_clone: Box<dyn ModelAdapter> = match config.model_type {
-                ModelType::LocalGGUF => Box::new(LocalGGUFAdapter::new(config.clone())),
-                ModelType::MLC => Box::new(MLCAdapter::new(config.clone())),
-                ModelType::VLLM => Box::new(VLLMAdapter::new(config.clone())),
-                ModelType::OpenAI | ModelType::Gemini | ModelType::Anthropic | 
-                ModelType::Ollama | ModelType::RemoteAPI => {
-                    Box::new(RemoteAPIAdapter::new(config.clone())?)
-                }
-            };
-            
-            workers[i].adapter = Arc::new(Mutex::new(adapter_clone));
+            workers.push(Arc::new(ModelWorker::new(
+                format!("{}-worker-{}", name, i),
+                self.create_adapter(&config)?,
+            )));
         }
 
         // Store workers and config
@@ -1382,8 +3445,16 @@ impl ModelHost {
         Ok(())
     }
 
-    /// Load a model and all its workers
+    /// Load a model and all its workers, then warm them up
     pub async fn load_model(&self, name: &str) -> Result<(), ModelHostError> {
+        self.load_workers(name).await?;
+        self.warmup_model(name).await?;
+        info!("Model loaded successfully: {}", name);
+        Ok(())
+    }
+
+    /// Load every worker of a model, holding its VRAM
+    async fn load_workers(&self, name: &str) -> Result<(), ModelHostError> {
         info!("Loading model: {}", name);
         
         let workers = {
@@ -1401,13 +3472,13 @@ impl ModelHost {
         };
 
         // Check VRAM requirements for local models
-        if matches!(config.model_type, ModelType::LocalGGUF | ModelType::MLC | ModelType::VLLM) {
+        if config.model_type.holds_vram() {
             self.vram_stats.allocate(config.vram_required_mb)?;
         }
 
         // Load all workers
         let mut load_tasks = Vec::new();
-        for worker in workers {
+        for worker in workers.iter().cloned() {
             let load_task = tokio::spawn(async move {
                 let mut adapter = worker.adapter.lock().await;
                 adapter.load().await
@@ -1419,19 +3490,22 @@ impl ModelHost {
         for task in load_tasks {
             task.await.map_err(|e| ModelHostError::ModelLoad(format!("Worker load failed: {}", e)))??;
         }
-
-        // Warm up workers
-        self.warmup_model(name).await?;
-        
-        info!("Model loaded successfully: {}", name);
+        // Idle time counts from the load
+        for worker in &workers {
+            *worker.last_used.lock().await = Instant::now();
+        }
+        self.parked.lock().await.remove(name);
         Ok(())
     }
 
     /// Execute inference with fallback support
     pub async fn infer(
         &self,
-        mut request: InferenceRequest,
+        request: InferenceRequest,
     ) -> Result<InferenceResponse, ModelHostError> {
+        if !self.accepting.load(Ordering::SeqCst) {
+            return Err(ModelHostError::ShuttingDown);
+        }
         let start_time = Instant::now();
         
         // Update stats
@@ -1440,47 +3514,67 @@ impl ModelHost {
             stats.total_requests += 1;
         }
 
-        // Set up fallback chain
-        let fallback_models = if let Some(chain) = &request.fallback_chain {
-            chain.clone()
-        } else {
-            let fallback_chains = self.fallback_chains.read().await;
-            fallback_chains.get(&request.model_name).cloned().unwrap_or_default()
-        };
-
-        let mut fallback_chain = FallbackChain::new({
-            let mut models = vec![request.model_name.clone()];
-            models.extend(fallback_models);
-            models
-        });
-
+        let mut fallback_chain = self.fallback_chain_for(&request).await;
         let mut last_error = None;
 
-        // Try each model in the fallback chain
+        // Try each model in the fallback chain, passing over open circuits
         while let Some(model_name) = fallback_chain.next_model() {
+            let allowed = self
+                .circuits
+                .lock()
+                .await
+                .entry(model_name.clone())
+                .or_default()
+                .allow(Instant::now());
+            if !allowed {
+                debug!("Skipping {}: circuit open", model_name);
+                *self.stats.write().await.skipped_models.entry(model_name.clone()).or_default() += 1;
+                fallback_chain.mark_skipped(model_name);
+                continue;
+            }
+
             debug!("Trying model: {} for inference", model_name);
-            
-            request.model_name = model_name.clone();
-            
-            match self.execute_inference_with_model(&request).await {
+            let mut attempt = request.clone();
+            attempt.model_name = model_name.clone();
+            let attempt_start = Instant::now();
+            let result = match self.fit_context(&mut attempt).await {
+                Ok(dropped) => self.execute_inference_with_model(&attempt).await.map(|mut response| {
+                    response.context_dropped = dropped;
+                    response
+                }),
+                Err(e) => Err(e),
+            };
+
+            if let Some(circuit) = self.circuits.lock().await.get_mut(&model_name) {
+                match &result {
+                    // A spent budget, an oversized request or shutdown says
+                    // nothing about the model's health
+                    Err(
+                        ModelHostError::BudgetExceeded { .. }
+                        | ModelHostError::ContextOverflow { .. }
+                        | ModelHostError::ShuttingDown,
+                    ) => {
+                        circuit.probing = false
+                    }
+                    result => circuit.record(result.is_ok(), &self.circuit_breaker, Instant::now()),
+                }
+            }
+
+            match result {
                 Ok(mut response) => {
-                    // Mark if this was a fallback
-                    response.is_fallback = fallback_chain.current_index > 1;
-                    response.model_used = model_name;
+                    let primary = fallback_chain.primary().unwrap_or_default();
+                    response.is_fallback = model_name != primary;
 
+                    let mut stats = self.stats.write().await;
                     if response.is_fallback {
-                        let mut stats = self.stats.write().await;
                         stats.fallback_activations += 1;
-                        warn!("Fallback activated: used {} instead of {}", 
-                             model_name, fallback_chain.models[0]);
+                        warn!("Fallback activated: used {} instead of {}", model_name, primary);
                     }
-
-                    // Update success stats
-                    let inference_time = start_time.elapsed();
-                    let mut stats = self.stats.write().await;
                     stats.total_tokens_generated += response.tokens_generated as u64;
-                    stats.total_inference_time += inference_time;
-                    
+                    stats.total_inference_time += start_time.elapsed();
+                    stats.per_model.entry(model_name.clone()).or_default().record(attempt_start.elapsed());
+
+                    response.model_used = model_name;
                     return Ok(response);
                 }
                 Err(e) => {
@@ -1495,45 +3589,138 @@ impl ModelHost {
             }
         }
 
-        // All models in chain failed
+        // All models in chain failed or were skipped
         let mut stats = self.stats.write().await;
         stats.errors += 1;
 
+        if last_error.is_none()
+            && let Some(primary) = fallback_chain.primary()
+        {
+            return Err(ModelHostError::CircuitOpen { name: primary.to_string() });
+        }
         Err(last_error.unwrap_or(ModelHostError::FallbackExhausted { 
             count: fallback_chain.models.len() 
         }))
     }
 
+    /// The request's model followed by its own fallbacks, or else those
+    /// registered for the model
+    async fn fallback_chain_for(&self, request: &InferenceRequest) -> FallbackChain {
+        let fallback_models = if let Some(chain) = &request.fallback_chain {
+            chain.clone()
+        } else {
+            let fallback_chains = self.fallback_chains.read().await;
+            fallback_chains.get(&request.model_name).cloned().unwrap_or_default()
+        };
+
+        FallbackChain::new({
+            let mut models = vec![request.model_name.clone()];
+            models.extend(fallback_models);
+            models
+        })
+    }
+
     /// Execute inference with a specific model
     async fn execute_inference_with_model(
         &self,
         request: &InferenceRequest,
     ) -> Result<InferenceResponse, ModelHostError> {
-        // Get an available worker for the model
-        let worker = self.get_available_worker(&request.model_name).await?;
-        
-        // Mark worker as busy
-        worker.is_busy.store(true, Ordering::SeqCst);
-        
-        let result = {
+        self.check_budget(&request.model_name).await?;
+        let load_time = self.reload_parked(&request.model_name).await?;
+
+        // Get an available worker for the model, waiting in the queue if
+        // they are all busy
+        let worker = self
+            .get_available_worker(&request.model_name, request.priority.clone(), request.timeout_ms)
+            .await?;
+        let lease = self.leases.take(&worker.id);
+
+        // A recent passed check stands in for one on every request
+        let fresh = worker.health_is_fresh(self.health_ttl).await;
+        let (result, inference_failed) = {
             let adapter = worker.adapter.lock().await;
-            
-            // Perform health check first
-            if let Err(e) = adapter.health_check().await {
-                warn!("Health check failed for {}: {}", request.model_name, e);
-                return Err(e);
+            let health = if fresh {
+                Ok(())
+            } else {
+                let health = adapter.health_check().await;
+                worker.record_health(health.is_ok()).await;
+                health
+            };
+            match health {
+                Ok(()) => {
+                    let result = self.pool().abortable(adapter.infer(request.clone())).await;
+                    // The caller's own deadline says nothing about the worker
+                    let failed = matches!(
+                        &result,
+                        Err(e) if !matches!(e, ModelHostError::Timeout { .. } | ModelHostError::ShuttingDown)
+                    );
+                    (result, failed)
+                }
+                Err(e) => {
+                    warn!("Health check failed for {}: {}", request.model_name, e);
+                    (Err(e), false)
+                }
             }
-            
-            // Execute inference
-            adapter.infer(request.clone()).await
         };
 
-        // Mark worker as available and update stats
-        worker.is_busy.store(false, Ordering::SeqCst);
+        // Hand the worker on and update stats
+        drop(lease);
         *worker.last_used.lock().await = Instant::now();
-        worker.requests_processed.fetch_add(1, Ordering::SeqCst);
+        if result.is_ok() {
+            worker.requests_processed.fetch_add(1, Ordering::SeqCst);
+        }
+        if inference_failed {
+            worker.healthy.store(false, Ordering::SeqCst);
+            let worker = worker.clone();
+            tokio::spawn(async move {
+                let _ = worker.refresh_health().await;
+            });
+        }
+        self.release_worker(&request.model_name, worker).await;
+        self.record_usage(&request.model_name, std::slice::from_ref(&result)).await;
 
-        result
+        result.map(|mut response| {
+            response.timing.load_time = load_time;
+            response
+        })
+    }
+
+    /// Fit the request into its model's configured window
+    async fn fit_context(&self, request: &mut InferenceRequest) -> Result<usize, ModelHostError> {
+        let window = self
+            .configs
+            .read()
+            .await
+            .get(&request.model_name)
+            .map(|config| config.context_window)
+            .filter(|window| *window > 0);
+        let Some(window) = window else {
+            return Ok(0);
+        };
+        let dropped = fit_context(request, window, self.token_estimator.as_ref())?;
+        if dropped > 0 {
+            info!("Dropped {} context entries to fit {}", dropped, request.model_name);
+        }
+        Ok(dropped)
+    }
+
+    async fn check_budget(&self, model_name: &str) -> Result<(), ModelHostError> {
+        check_budget(&self.configs, &self.usage, model_name).await
+    }
+
+    async fn record_usage(&self, model_name: &str, results: &[Result<InferenceResponse, ModelHostError>]) {
+        let config = self.configs.read().await.get(model_name).cloned();
+        for result in results {
+            match result {
+                Ok(response) => {
+                    let completion = response.tokens_generated as u64;
+                    let prompt = (response.total_tokens as u64).saturating_sub(completion);
+                    let cost = config.as_ref().map_or(0.0, |config| config.cost(prompt, completion));
+                    self.usage.record(model_name, prompt, completion, cost);
+                }
+                Err(_) => self.usage.record_error(model_name),
+            }
+        }
     }
 
     /// Execute streaming inference
@@ -1541,101 +3728,173 @@ impl ModelHost {
         &self,
         request: InferenceRequest,
     ) -> Result<TokenStream, ModelHostError> {
+        if !self.accepting.load(Ordering::SeqCst) {
+            return Err(ModelHostError::ShuttingDown);
+        }
         debug!("Starting streaming inference for model: {}", request.model_name);
         
         let mut stats = self.stats.write().await;
         stats.stream_requests += 1;
         drop(stats);
 
-        let worker = self.get_available_worker(&request.model_name).await?;
-        worker.is_busy.store(true, Ordering::SeqCst);
-        
-        let stream_result = {
-            let adapter = worker.adapter.lock().await;
-            adapter.infer_stream(request.clone()).await
+        // Fitted to the primary model's window, before anything starts
+        let mut request = request;
+        self.fit_context(&mut request).await?;
+        self.reload_parked(&request.model_name).await?;
+        if let Some(context) = &request.context {
+            info!("Streaming from {} with {} context entries", request.model_name, context.len());
+        }
+
+        let context = StreamContext {
+            pool: self.pool(),
+            leases: self.leases.clone(),
+            configs: self.configs.clone(),
+            usage: self.usage.clone(),
         };
+        let mut chain = self.fallback_chain_for(&request).await;
+        let started = context.start(&mut chain, &request).await?;
 
-        // Note: Worker will be marked as available when the stream completes
-        // For now, we'll mark it available immediately (in a real implementation,
-        // we'd track stream completion)
-        worker.is_busy.store(false, Ordering::SeqCst);
-        
-        stream_result
+        let restart_below = self.stream_fallback_tokens;
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let primary = chain.primary().unwrap_or_default().to_string();
+            let (mut model, mut stream, mut guard) = started;
+            let mut switched_to = (model != primary).then(|| model.clone());
+            let mut produced = 0;
+            let mut started_at = Instant::now();
+            let mut token_index = 0;
+            let mut abort = context.pool.abort.subscribe();
+            loop {
+                let item = tokio::select! {
+                    item = stream.next() => Some(item),
+                    _ = tx.closed() => None,
+                    _ = abort.wait_for(|aborted| *aborted) => {
+                        let _ = tx.send(Err(ModelHostError::ShuttingDown));
+                        return;
+                    }
+                };
+                // Stopped by the reader: dropping `stream` ends the request,
+                // and what it produced so far is still billed
+                let Some(item) = item else {
+                    context.record(&model, produced, started_at.elapsed()).await;
+                    return;
+                };
+                match item {
+                    Some(Ok(mut token)) => {
+                        produced += 1;
+                        token.token_index = token_index;
+                        token.fallback_model = switched_to.take();
+                        token_index += 1;
+                        let is_final = token.is_final;
+                        if tx.send(Ok(token)).is_err() {
+                            return;
+                        }
+                        if is_final {
+                            context.record(&model, produced, started_at.elapsed()).await;
+                            return;
+                        }
+                    }
+                    // Early enough to start over on the next model
+                    Some(Err(e)) if produced < restart_below => {
+                        warn!("Stream from {} failed after {} tokens: {}", model, produced, e);
+                        context.usage.record_error(&model);
+                        chain.mark_failed(model.clone());
+                        drop(guard);
+                        match context.start(&mut chain, &request).await {
+                            Ok(next) => {
+                                (model, stream, guard) = next;
+                                context.pool.stats.write().await.fallback_activations += 1;
+                                switched_to = Some(model.clone());
+                                produced = 0;
+                                started_at = Instant::now();
+                            }
+                            Err(_) => {
+                                let _ = tx.send(Err(e));
+                                return;
+                            }
+                        }
+                    }
+                    Some(Err(e)) => {
+                        context.usage.record_error(&model);
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                    None => {
+                        context.record(&model, produced, started_at.elapsed()).await;
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Box::pin(UnboundedReceiverStream::new(rx)))
     }
 
-    /// Execute batch inference
+    /// Run every request of a batch as `infer` would, up to `max_parallel`
+    /// at a time. Results come back in request order, one per request, so
+    /// a failure leaves the rest of the batch running.
     pub async fn batch_infer(
         &self,
         batch_request: BatchInferenceRequest,
-    ) -> Result<Vec<InferenceResponse>, ModelHostError> {
-        info!("Starting batch inference with {} requests", batch_request.requests.len());
-        
-        let mut stats = self.stats.write().await;
-        stats.batch_requests += 1;
-        stats.total_requests += batch_request.requests.len() as u64;
-        drop(stats);
-
-        // Group requests by model
-        let mut model_batches: HashMap<String, Vec<InferenceRequest>> = HashMap::new();
-        for request in batch_request.requests {
-            model_batches.entry(request.model_name.clone())
-                .or_insert_with(Vec::new)
-                .push(request);
-        }
-
-        let mut all_responses = Vec::new();
-        
-        // Process each model's batch
-        for (model_name, requests) in model_batches {
-            debug!("Processing batch for model: {} ({} requests)", model_name, requests.len());
-            
-            let worker = self.get_available_worker(&model_name).await?;
-            worker.is_busy.store(true, Ordering::SeqCst);
-            
-            let batch_result = {
-                let adapter = worker.adapter.lock().await;
-                
-                if adapter.supports_batch() {
-                    // Use native batch processing
-                    adapter.batch_infer(requests).await
-                } else {
-                    // Fall back to sequential processing
-                    let mut responses = Vec::new();
-                    for request in requests {
-                        responses.push(adapter.infer(request).await?);
-                    }
-                    Ok(responses)
-                }
-            };
-
-            worker.is_busy.store(false, Ordering::SeqCst);
-            *worker.last_used.lock().await = Instant::now();
+    ) -> Vec<Result<InferenceResponse, ModelHostError>> {
+        let (progress, _) = mpsc::unbounded_channel();
+        self.batch_infer_with_progress(batch_request, progress).await
+    }
 
-            match batch_result {
-                Ok(responses) => all_responses.extend(responses),
-                Err(e) => return Err(e),
+    /// `batch_infer`, sending the counts to `progress` as each request ends
+    pub async fn batch_infer_with_progress(
+        &self,
+        batch_request: BatchInferenceRequest,
+        progress: mpsc::UnboundedSender<BatchProgress>,
+    ) -> Vec<Result<InferenceResponse, ModelHostError>> {
+        let total = batch_request.requests.len();
+        info!("Starting batch inference with {} requests", total);
+        self.stats.write().await.batch_requests += 1;
+
+        let max_parallel = batch_request.max_parallel.unwrap_or(self.max_concurrent).max(1);
+        let batch_id = batch_request.batch_id;
+        let requests = batch_request.requests.into_iter().enumerate().map(|(index, mut request)| {
+            request.batch_id.get_or_insert_with(|| batch_id.clone());
+            async move { (index, self.infer(request).await) }
+        });
+        let mut running = futures::StreamExt::buffer_unordered(futures::stream::iter(requests), max_parallel);
+
+        let mut results: Vec<_> = (0..total).map(|_| None).collect();
+        let mut counts = BatchProgress { completed: 0, failed: 0, total };
+        while let Some((index, result)) = running.next().await {
+            counts.completed += 1;
+            if result.is_err() {
+                counts.failed += 1;
             }
+            let _ = progress.send(counts);
+            results[index] = Some(result);
         }
 
-        info!("Batch inference completed: {} responses", all_responses.len());
-        Ok(all_responses)
+        info!("Batch inference completed: {} of {} failed", counts.failed, total);
+        results.into_iter().flatten().collect()
     }
 
-    /// Get an available worker for a model
-    async fn get_available_worker(&self, model_name: &str) -> Result<ModelWorker, ModelHostError> {
-        let workers = self.workers.read().await;
-        let model_workers = workers.get(model_name)
-            .ok_or_else(|| ModelHostError::ModelNotFound { name: model_name.to_string() })?;
+    async fn get_available_worker(
+        &self,
+        model_name: &str,
+        priority: InferencePriority,
+        timeout_ms: Option<u64>,
+    ) -> Result<Arc<ModelWorker>, ModelHostError> {
+        self.pool().claim(model_name, priority, timeout_ms).await
+    }
 
-        // Find an available worker
-        for worker in model_workers {
-            if !worker.is_busy.load(Ordering::SeqCst) {
-                return Ok(worker.clone());
-            }
-        }
+    async fn release_worker(&self, model_name: &str, worker: Arc<ModelWorker>) {
+        self.pool().release(model_name, worker).await
+    }
 
-        // All workers are busy - wait or fail
-        Err(ModelHostError::PoolExhausted { count: model_workers.len() })
+    fn pool(&self) -> WorkerPool {
+        WorkerPool {
+            workers: self.workers.clone(),
+            request_queue: self.request_queue.clone(),
+            stats: self.stats.clone(),
+            pool_size: self.pool_size,
+            accepting: self.accepting.clone(),
+            abort: self.abort.clone(),
+        }
     }
 
     pub async fn get_model_info(&self, name: &str) -> Result<ModelInfo, ModelHostError> {
@@ -1653,167 +3912,495 @@ impl ModelHost {
     }
 
     pub async fn list_models(&self) -> Vec<ModelInfo> {
-        let adapters = self.adapters.read().await;
-        adapters.values().map(|a| a.get_model_info()).collect()
+        let workers = self.workers.read().await;
+        let mut models = Vec::new();
+        for model_workers in workers.values() {
+            if let Some(worker) = model_workers.first() {
+                models.push(worker.adapter.lock().await.get_model_info());
+            }
+        }
+        models
     }
 
-    pub async fn unload_model(&self, name: &str) -> Result<(), ModelHostError> {
-        let adapters = self.adapters.read().await;
-        if let Some(adapter) = adapters.get(name) {
-            // Simplified - in practice, we'd need mutable access
-            Ok(())
-        } else {
-            Err(ModelHostError::ModelNotFound {
-                name: name.to_string(),
+    /// Registered models by name, without waiting on busy workers; a
+    /// worker in use counts as loaded
+    pub async fn model_states(&self) -> Vec<ModelState> {
+        let configs = self.configs.read().await;
+        let workers = self.workers.read().await;
+        let mut states: Vec<ModelState> = workers
+            .iter()
+            .map(|(name, model_workers)| ModelState {
+                name: name.clone(),
+                model_type: configs.get(name).map(|config| config.model_type.clone()),
+                loaded: model_workers
+                    .first()
+                    .is_some_and(|worker| worker.adapter.try_lock().map_or(true, |adapter| adapter.is_loaded())),
+                workers: model_workers.len(),
             })
+            .collect();
+        states.sort_by(|a, b| a.name.cmp(&b.name));
+        states
+    }
+
+    pub async fn unload_model(&self, name: &str) -> Result<(), ModelHostError> {
+        let workers = {
+            let workers_guard = self.workers.read().await;
+            workers_guard.get(name)
+                .ok_or_else(|| ModelHostError::ModelNotFound { name: name.to_string() })?
+                .clone()
+        };
+
+        let mut info = None;
+        for worker in workers {
+            let mut adapter = worker.adapter.lock().await;
+            if adapter.is_loaded() {
+                adapter.unload().await?;
+                info = Some(adapter.get_model_info());
+            }
+        }
+
+        if let Some(info) = info
+            && info.model_type.holds_vram()
+        {
+            self.vram_stats.deallocate(info.vram_required_mb);
+        }
+        Ok(())
+    }
+
+    pub fn get_usage(&self, model_name: &str) -> Option<ModelUsage> {
+        self.usage.get(model_name)
+    }
+
+    /// Usage per model name; `usage::total` sums it
+    pub fn get_usage_all(&self) -> BTreeMap<String, ModelUsage> {
+        self.usage.all()
+    }
+
+    /// Stop taking requests and background work, give in-flight requests
+    /// up to `timeout` to finish, abort the rest, then unload every adapter
+    /// and write usage to its file. Queued requests are aborted.
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownSummary {
+        info!("Shutting down model host");
+        self.accepting.store(false, Ordering::SeqCst);
+        let _ = self.shutdown_tx.send(());
+
+        // Dropping their senders wakes queued requests with an error
+        let queued = {
+            let mut queue = self.request_queue.lock().await;
+            let waiting = queue.iter().filter(|queued| !queued.worker_tx.is_closed()).count();
+            queue.clear();
+            waiting
+        };
+
+        let workers: Vec<Arc<ModelWorker>> = self.workers.read().await.values().flatten().cloned().collect();
+        let busy = || workers.iter().filter(|worker| worker.is_busy.load(Ordering::SeqCst)).count();
+        let in_flight = busy();
+        let deadline = Instant::now() + timeout;
+        while busy() > 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let stuck = busy();
+        if stuck > 0 {
+            warn!("Aborting {} requests still running after {:?}", stuck, timeout);
+            self.abort.send_replace(true);
         }
+
+        for worker in &workers {
+            match tokio::time::timeout(Duration::from_secs(5), worker.adapter.lock()).await {
+                Ok(mut adapter) => {
+                    if adapter.is_loaded()
+                        && let Err(e) = adapter.unload().await
+                    {
+                        warn!("Failed to unload worker {}: {}", worker.id, e);
+                    }
+                }
+                Err(_) => warn!("Worker {} did not stop; leaving it loaded", worker.id),
+            }
+        }
+        *self.current_model.write().await = None;
+
+        if let Err(e) = self.usage.save() {
+            warn!("Failed to save model usage: {}", e);
+        }
+        let summary = ShutdownSummary {
+            drained: in_flight - stuck,
+            aborted: stuck + queued,
+        };
+        info!("Model host stopped: {} requests drained, {} aborted", summary.drained, summary.aborted);
+        summary
     }
 
     pub async fn get_stats(&self) -> ModelHostStats {
-        self.stats.read().await.clone()
+        let mut stats = self.stats.read().await.clone();
+        stats.retries = self.retries.load(Ordering::Relaxed);
+        stats
     }
 
-    /// Request a hot-swap to a different model
+    /// Queue a hot-swap to `target_model` after checking it is registered
+    /// and fits in VRAM. The receiver gets the swap's steps as they start.
     pub async fn request_hot_swap(
         &self,
         target_model: String,
         force: bool,
-    ) -> Result<(), ModelHostError> {
+    ) -> Result<mpsc::UnboundedReceiver<HotSwapProgress>, ModelHostError> {
+        self.hot_swap_plan(&target_model, force).await?;
+        let (progress, progress_rx) = mpsc::unbounded_channel();
         let request = HotSwapRequest {
             target_model,
             force,
+            progress,
         };
         self.hot_swap_tx
             .send(request)
             .map_err(|_| ModelHostError::HotSwapFailed {
                 reason: "Channel closed".to_string(),
             })?;
-        Ok(())
+        Ok(progress_rx)
+    }
+
+    /// Models a swap to `target` unloads: the current one, and with
+    /// `force` every other loaded local model when VRAM is short.
+    /// `VramPreflight` when only evicting them would make it fit.
+    pub async fn hot_swap_plan(&self, target: &str, force: bool) -> Result<Vec<String>, ModelHostError> {
+        let target_info = self.get_model_info(target).await?;
+        let current = self.current_model.read().await.clone();
+
+        let states = self.model_states().await;
+        let target_loaded = states.iter().any(|state| state.name == target && state.loaded);
+        // Other loaded models and the VRAM each holds
+        let mut resident = Vec::new();
+        for state in states.into_iter().filter(|state| state.loaded && state.name != target) {
+            let info = self.get_model_info(&state.name).await?;
+            resident.push((state.name, info.vram_required_mb));
+        }
+        let required = if target_loaded { 0 } else { target_info.vram_required_mb };
+        let available = self.vram_stats.available_mb();
+
+        let (previous, others): (Vec<_>, Vec<_>) =
+            resident.into_iter().partition(|(name, _)| current.as_deref() == Some(name.as_str()));
+        let freed: u64 = previous.iter().map(|(_, mb)| mb).sum();
+        let mut unload: Vec<String> = previous.into_iter().map(|(name, _)| name).collect();
+        if required <= available + freed {
+            return Ok(unload);
+        }
+
+        let evictable: u64 = others.iter().map(|(_, mb)| mb).sum();
+        if required > available + freed + evictable {
+            return Err(ModelHostError::VramExhausted {
+                required,
+                available: available + freed + evictable,
+            });
+        }
+        if !force {
+            return Err(ModelHostError::VramPreflight {
+                required,
+                available: available + freed,
+            });
+        }
+        unload.extend(others.into_iter().map(|(name, _)| name));
+        Ok(unload)
+    }
+
+    /// Unload what `hot_swap_plan` says, then load and warm up the target,
+    /// reporting each step on the request's progress channel
+    pub async fn perform_hot_swap(&self, request: HotSwapRequest) -> Result<HotSwapSummary, ModelHostError> {
+        let result = self.run_hot_swap(&request).await;
+        let step = match &result {
+            Ok(summary) => HotSwapProgress::Ready(summary.clone()),
+            Err(e) => HotSwapProgress::Failed(e.to_string()),
+        };
+        let _ = request.progress.send(step);
+        result
     }
 
-    /// Perform hot-swap operation
-    pub async fn perform_hot_swap(&self, request: HotSwapRequest) -> Result<(), ModelHostError> {
+    async fn run_hot_swap(&self, request: &HotSwapRequest) -> Result<HotSwapSummary, ModelHostError> {
         let start_time = Instant::now();
+        let target = &request.target_model;
+        let report = |step| {
+            let _ = request.progress.send(step);
+        };
 
-        // Check if target model exists
-        let adapters = self.adapters.read().await;
-        let target_adapter =
-            adapters
-                .get(&request.target_model)
-                .ok_or_else(|| ModelHostError::ModelNotFound {
-                    name: request.target_model.clone(),
-                })?;
-
-        let target_info = target_adapter.get_model_info();
-
-        // Check VRAM availability
-        if !request.force {
-            let available = self.vram_stats.available_mb.load(Ordering::SeqCst);
-            if available < target_info.vram_required_mb {
-                return Err(ModelHostError::VramExhausted {
-                    required: target_info.vram_required_mb,
-                    available,
-                });
+        let unloaded = self.hot_swap_plan(target, request.force).await?;
+        for name in &unloaded {
+            report(HotSwapProgress::Unloading(name.clone()));
+            self.unload_model(name).await?;
+            if self.configs.read().await.get(name).is_some_and(|config| config.keep_warm) {
+                self.parked.lock().await.insert(name.clone(), Parked::Swapped);
             }
         }
+        if !self.model_states().await.iter().any(|state| state.name == *target && state.loaded) {
+            report(HotSwapProgress::Loading(target.clone()));
+            self.load_workers(target).await?;
+            report(HotSwapProgress::WarmingUp);
+            self.warmup_model(target).await?;
+        }
+
+        *self.current_model.write().await = Some(target.clone());
+        self.stats.write().await.hot_swaps += 1;
+
+        let summary = HotSwapSummary {
+            model: target.clone(),
+            unloaded,
+            elapsed: start_time.elapsed(),
+        };
+        if summary.is_slow() {
+            warn!("Hot-swap to {} took longer than target: {:?}", target, summary.elapsed);
+        }
+        Ok(summary)
+    }
+
+    /// Get current VRAM usage
+    pub fn get_vram_usage(&self) -> (u64, u64, f32) {
+        let used = self.vram_stats.used_mb.load(Ordering::SeqCst);
+        let total = self.vram_stats.total_mb;
+        let percent = self.vram_stats.get_usage_percent();
+        (used, total, percent)
+    }
+
+    /// Where the VRAM total came from
+    pub fn vram_source(&self) -> VramSource {
+        self.vram_stats.source
+    }
+
+    /// Check if VRAM usage is high (>90%)
+    pub fn is_vram_high_usage(&self) -> bool {
+        self.vram_stats.get_usage_percent() > 90.0
+    }
+
+    /// Get current model
+    pub async fn get_current_model(&self) -> Option<String> {
+        self.current_model.read().await.clone()
+    }
+
+    /// The model in use before any hot-swap, which the first swap unloads
+    pub async fn set_current_model(&self, name: &str) {
+        *self.current_model.write().await = Some(name.to_string());
+    }
+
+    /// Process pending hot-swap requests
+    pub async fn process_hot_swap_requests(&mut self) -> Result<(), ModelHostError> {
+        if let Some(dispatcher) = &self.dispatcher {
+            dispatcher.beat();
+        }
+        let Some(requests) = self.hot_swap_rx.get_mut().as_mut() else {
+            return Ok(());
+        };
+        let pending: Vec<HotSwapRequest> = std::iter::from_fn(|| requests.try_recv().ok()).collect();
+        for request in pending {
+            self.perform_hot_swap(request).await?;
+        }
+        Ok(())
+    }
+
+    /// Carry out hot-swap requests in the background, one at a time,
+    /// until shutdown
+    pub fn start_hot_swaps(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let host = self.clone();
+        let mut shutdown = self.shutdown_tx.subscribe();
+        tokio::spawn(async move {
+            let Some(mut requests) = host.hot_swap_rx.lock().await.take() else {
+                return;
+            };
+            loop {
+                let request = tokio::select! {
+                    request = requests.recv() => request,
+                    _ = shutdown.recv() => return,
+                };
+                let Some(request) = request else {
+                    return;
+                };
+                let target = request.target_model.clone();
+                if let Err(e) = host.perform_hot_swap(request).await {
+                    warn!("Hot-swap to {} failed: {}", target, e);
+                }
+            }
+        })
+    }
 
-        // Unload current model if any
-        if let Some(current) = self.current_model.read().await.as_ref() {
-            if let Some(current_adapter) = adapters.get(current) {
-                let current_info = current_adapter.get_model_info();
-                self.vram_stats.deallocate(current_info.vram_required_mb);
-            }
-        }
+    /// Replace a worker whose lease the watchdog revoked. The stuck call
+    /// keeps the old adapter until it returns; new requests get a fresh one.
+    pub async fn restart_worker(&self, worker_id: &str) -> Result<(), ModelHostError> {
+        let model = {
+            let workers = self.workers.read().await;
+            workers
+                .iter()
+                .find(|(_, pool)| pool.iter().any(|w| w.id == worker_id))
+                .map(|(name, _)| name.clone())
+                .ok_or_else(|| ModelHostError::ModelNotFound { name: worker_id.to_string() })?
+        };
+        let config = self.configs.read().await.get(&model).cloned()
+            .ok_or_else(|| ModelHostError::Config(format!("No config found for model: {}", model)))?;
 
-        // Load target model
-        // In practice, this would send SIGHUP to vLLM and update symlink
-        self.vram_stats.allocate(target_info.vram_required_mb)?;
+        let mut adapter = self.create_adapter(&config)?;
+        adapter.load().await?;
+        let fresh = Arc::new(ModelWorker::new(worker_id.to_string(), adapter));
 
-        // Update current model
-        *self.current_model.write().await = Some(request.target_model.clone());
+        if let Some(slot) = self.workers.write().await
+            .get_mut(&model)
+            .and_then(|pool| pool.iter_mut().find(|w| w.id == worker_id))
+        {
+            *slot = fresh.clone();
+        }
+        // Queued requests can have the fresh worker straight away
+        fresh.is_busy.store(true, Ordering::SeqCst);
+        self.release_worker(&model, fresh).await;
+        self.stats.write().await.errors += 1;
+        warn!("Restarted stuck worker {}", worker_id);
+        Ok(())
+    }
 
-        // Update stats
-        let mut stats = self.stats.write().await;
-        stats.hot_swaps += 1;
+    pub async fn warmup_model(&self, name: &str) -> Result<(), ModelHostError> {
+        let workers = {
+            let workers_guard = self.workers.read().await;
+            workers_guard.get(name)
+                .ok_or_else(|| ModelHostError::ModelNotFound { name: name.to_string() })?
+                .clone()
+        };
 
-        let swap_time = start_time.elapsed();
-        if swap_time > Duration::from_secs(3) {
-            tracing::warn!("Hot-swap took longer than target: {:?}", swap_time);
+        // Workers warm up side by side, as they load
+        let warmup_tasks: Vec<_> = workers
+            .into_iter()
+            .map(|worker| tokio::spawn(async move { worker.adapter.lock().await.warmup().await }))
+            .collect();
+        for task in warmup_tasks {
+            task.await.map_err(|e| ModelHostError::ModelLoad(format!("Worker warmup failed: {}", e)))??;
         }
-
         Ok(())
     }
 
-    /// Get current VRAM usage
-    pub fn get_vram_usage(&self) -> (u64, u64, f32) {
-        let used = self.vram_stats.used_mb.load(Ordering::SeqCst);
-        let total = self.vram_stats.total_mb;
-        let percent = self.vram_stats.get_usage_percent();
-        (used, total, percent)
+    /// Run the warm pool every few seconds until shutdown
+    pub fn start_warm_pool(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let host = self.clone();
+        let mut shutdown = self.shutdown_tx.subscribe();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WARM_POOL_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.recv() => return,
+                }
+                host.manage_warm_pool().await;
+            }
+        })
     }
 
-    /// Check if VRAM usage is high (>90%)
-    pub fn is_vram_high_usage(&self) -> bool {
-        self.vram_stats.get_usage_percent() > 90.0
-    }
+    /// Unload local models idle past their `idle_unload_secs`, then reload
+    /// the `keep_warm` models hot-swaps unloaded that now fit in VRAM
+    pub async fn manage_warm_pool(&self) {
+        let configs: Vec<ModelConfig> = self.configs.read().await.values().cloned().collect();
 
-    /// Get current model
-    pub async fn get_current_model(&self) -> Option<String> {
-        self.current_model.read().await.clone()
-    }
+        for config in configs.iter().filter(|config| {
+            config.idle_unload_secs > 0 && !config.keep_warm && config.model_type.holds_vram()
+        }) {
+            let idle_for = Duration::from_secs(config.idle_unload_secs);
+            if self.idle_time(&config.name).await.is_none_or(|idle| idle < idle_for) {
+                continue;
+            }
+            match self.unload_model(&config.name).await {
+                Ok(()) => {
+                    info!("Unloaded {} after {:?} idle", config.name, idle_for);
+                    self.parked.lock().await.insert(config.name.clone(), Parked::Idle);
+                    self.stats.write().await.auto_unloads += 1;
+                }
+                Err(e) => warn!("Failed to unload idle model {}: {}", config.name, e),
+            }
+        }
 
-    /// Process pending hot-swap requests
-    pub async fn process_hot_swap_requests(&mut self) -> Result<(), ModelHostError> {
-        while let Ok(request) = self.hot_swap_rx.try_recv() {
-            self.perform_hot_swap(request).await?;
+        for config in &configs {
+            if self.parked.lock().await.get(&config.name) != Some(&Parked::Swapped)
+                || config.vram_required_mb > self.vram_stats.available_mb()
+            {
+                continue;
+            }
+            match self.load_model(&config.name).await {
+                Ok(()) => info!("Reloaded {} to keep it warm", config.name),
+                Err(e) => warn!("Failed to reload {}: {}", config.name, e),
+            }
         }
-        Ok(())
     }
 
-    pub async fn warmup_model(&self, name: &str) -> Result<(), ModelHostError> {
-        let mut warm_pool = self.warm_pool.write().await;
-        if warm_pool.len() >= self.pool_size {
-            // Evict least recently used (simplified)
-            if let Some((key, _)) = warm_pool.iter().next() {
-                let key = key.clone();
-                warm_pool.remove(&key);
+    /// How long since any worker of a loaded model finished a request;
+    /// `None` while one is busy or the model is not loaded
+    async fn idle_time(&self, name: &str) -> Option<Duration> {
+        let workers = self.workers.read().await.get(name)?.clone();
+        let mut idle = None;
+        for worker in &workers {
+            if worker.is_busy.load(Ordering::SeqCst) || !worker.adapter.try_lock().ok()?.is_loaded() {
+                return None;
             }
+            let since = worker.last_used.lock().await.elapsed();
+            idle = Some(idle.map_or(since, |idle: Duration| idle.min(since)));
         }
+        idle
+    }
 
-        // Clone the adapter for warm pool (simplified)
-        let adapters = self.adapters.read().await;
-        if let Some(adapter) = adapters.get(name) {
-            // In practice, we'd clone or create a new instance
-            // warm_pool.insert(name.to_string(), adapter.clone());
+    /// Reload a model the warm pool unloaded for idling, returning how long
+    /// that took; zero when it was loaded
+    async fn reload_parked(&self, name: &str) -> Result<Duration, ModelHostError> {
+        let is_idle = || async { self.parked.lock().await.get(name) == Some(&Parked::Idle) };
+        if !is_idle().await {
+            return Ok(Duration::ZERO);
         }
-
-        Ok(())
+        let _reloading = self.reloading.lock().await;
+        // Another request may have reloaded it while this one waited
+        if !is_idle().await {
+            return Ok(Duration::ZERO);
+        }
+        let started = Instant::now();
+        self.load_model(name).await?;
+        let load_time = started.elapsed();
+        info!("Reloaded idle model {} in {:?}", name, load_time);
+        Ok(load_time)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
+    use std::path::Path;
+
+    fn gguf_config(name: &str, model_path: &Path) -> ModelConfig {
+        ModelConfig {
+            name: name.to_string(),
+            model_type: ModelType::LocalGGUF,
+            model_path: Some(model_path.to_path_buf()),
+            api_endpoint: None,
+            api_key_env: None,
+            context_window: 4096,
+            vram_required_mb: 2048,
+            default_parameters: InferenceParameters::default(),
+            fallback_models: vec![],
+            warm_pool_size: 1,
+            max_concurrent: 4,
+            retry: RetryPolicy::default(),
+            cost_per_1k_prompt_tokens: None,
+            cost_per_1k_completion_tokens: None,
+            daily_budget: None,
+            idle_unload_secs: 0,
+            keep_warm: false,
+        }
+    }
+
+    fn short_request(model_name: &str, prompt: &str) -> InferenceRequest {
+        let mut request = InferenceRequest::new(model_name, prompt);
+        request.parameters.max_tokens = 16;
+        request
+    }
 
     #[tokio::test]
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
     async fn test_local_gguf_adapter() {
-        let model_path = PathBuf::from("/fake/path/model.gguf");
-        let mut adapter = LocalGGUFAdapter::new(model_path, "test-model".to_string(), 4096, 2048);
+        let dir = tempfile::tempdir().unwrap();
+        let model_path = dir.path().join("model.gguf");
+        std::fs::write(&model_path, b"GGUF").unwrap();
+        let mut adapter = LocalGGUFAdapter::new(gguf_config("test-model", &model_path));
 
         assert!(!adapter.is_loaded());
 
         adapter.load().await.unwrap();
         assert!(adapter.is_loaded());
 
-        let request = InferenceRequest {
-            prompt: "Hello, world!".to_string(),
-            model_name: "test-model".to_string(),
-            parameters: InferenceParameters::default(),
-            context: None,
-        };
-
-        let response = adapter.infer(request).await.unwrap();
+        let response = adapter.infer(short_request("test-model", "Hello, world!")).await.unwrap();
         assert!(response.text.contains("Hello, world!"));
         assert!(response.tokens_generated > 0);
 
@@ -1824,13 +4411,14 @@ mod tests {
     #[tokio::test]
     async fn test_remote_api_adapter() {
         // This test would require a mock server, so we'll just test the interface
-        let adapter = RemoteAPIAdapter::new(
-            "https://api.example.com/v1/completions".to_string(),
-            Some("fake-key".to_string()),
-            "test-model".to_string(),
-            4096,
-            0, // Remote API doesn't use local VRAM
-        );
+        let config = ModelConfig {
+            model_type: ModelType::RemoteAPI,
+            model_path: None,
+            api_endpoint: Some("https://api.example.com/v1/completions".to_string()),
+            vram_required_mb: 0,
+            ..gguf_config("test-model", Path::new(""))
+        };
+        let adapter = RemoteAPIAdapter::new(config).unwrap();
 
         assert!(!adapter.is_loaded());
         let info = adapter.get_model_info();
@@ -1838,19 +4426,76 @@ mod tests {
         assert_eq!(info.model_type, ModelType::RemoteAPI);
     }
 
+    /// Serve `statuses` in turn, one per connection, with `Retry-After: 0`
+    /// on failures and a JSON completion on success
+    async fn spawn_flaky_api(statuses: Vec<u16>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for status in statuses {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+                let mut buf = [0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                let body = if status == 200 { r#"{"text":"ok"}"# } else { r#"{"error":"busy"}"# };
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\nRetry-After: 0\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}/v1/completions", addr)
+    }
+
+    async fn loaded_remote(endpoint: String, retries: &Arc<AtomicU64>) -> RemoteAPIAdapter {
+        let adapter = RemoteAPIAdapter::new(ModelConfig {
+            model_type: ModelType::RemoteAPI,
+            api_endpoint: Some(endpoint),
+            ..gguf_config("remote", Path::new(""))
+        })
+        .unwrap()
+        .with_retry_counter(retries.clone());
+        adapter.loaded.store(true, Ordering::SeqCst);
+        adapter
+    }
+
     #[tokio::test]
-    async fn test_model_host_basic_operations() {
-        let host = ModelHost::new(5, 8192);
-
-        let model_path = PathBuf::from("/fake/path/model.gguf");
-        let adapter = Box::new(LocalGGUFAdapter::new(
-            model_path,
-            "test-model".to_string(),
-            4096,
-            2048,
+    async fn test_remote_retries_rate_limits() {
+        let retries = Arc::new(AtomicU64::new(0));
+        let adapter = loaded_remote(spawn_flaky_api(vec![429, 503, 200]).await, &retries).await;
+        let response = adapter.infer(InferenceRequest::new("remote", "hi")).await.unwrap();
+        assert_eq!(response.text, "ok");
+        assert_eq!(retries.load(Ordering::SeqCst), 2);
+
+        // Auth failures are not retried
+        let retries = Arc::new(AtomicU64::new(0));
+        let adapter = loaded_remote(spawn_flaky_api(vec![401, 200]).await, &retries).await;
+        assert!(matches!(
+            adapter.infer(InferenceRequest::new("remote", "hi")).await,
+            Err(ModelHostError::Api(_))
         ));
+        assert_eq!(retries.load(Ordering::SeqCst), 0);
 
-        host.register_model("test-model".to_string(), adapter)
+        let policy = RetryPolicy { initial_backoff_ms: 100, max_backoff_ms: 1000, ..Default::default() };
+        for attempt in 0..8 {
+            let delay = policy.backoff(attempt, None).as_millis() as u64;
+            let ceiling = (100u64 << attempt).min(1000);
+            assert!((ceiling / 2..=ceiling).contains(&delay), "{} for attempt {}", delay, attempt);
+        }
+        assert_eq!(policy.backoff(0, Some(Duration::from_secs(2))), Duration::from_secs(2));
+        assert!(!policy.should_retry(StatusCode::TOO_MANY_REQUESTS, 3));
+    }
+
+    #[tokio::test]
+    async fn test_model_host_basic_operations() {
+        let host = ModelHost::new(5, 4, VramBudget::Fixed(8192));
+
+        host.register_model(gguf_config("test-model", Path::new("/fake/path/model.gguf")))
             .await
             .unwrap();
 
@@ -1863,78 +4508,504 @@ mod tests {
     }
 
     #[tokio::test]
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
     async fn test_model_host_inference() {
-        let host = ModelHost::new(5, 8192);
-
-        let model_path = PathBuf::from("/fake/path/model.gguf");
-        let adapter = Box::new(LocalGGUFAdapter::new(
-            model_path,
-            "test-model".to_string(),
-            4096,
-            2048,
-        ));
+        let host = ModelHost::new(5, 4, VramBudget::Fixed(8192));
+        let dir = tempfile::tempdir().unwrap();
+        let model_path = dir.path().join("model.gguf");
+        std::fs::write(&model_path, b"GGUF").unwrap();
 
-        host.register_model("test-model".to_string(), adapter)
+        host.register_model(gguf_config("test-model", &model_path))
             .await
             .unwrap();
+        host.load_model("test-model").await.unwrap();
 
-        let request = InferenceRequest {
-            prompt: "Test prompt".to_string(),
-            model_name: "test-model".to_string(),
-            parameters: InferenceParameters::default(),
-            context: None,
-        };
-
-        let response = host.infer(request).await.unwrap();
+        let response = host.infer(short_request("test-model", "Test prompt")).await.unwrap();
         assert!(response.text.contains("Test prompt"));
         assert!(response.tokens_generated > 0);
 
         let stats = host.get_stats().await;
         assert_eq!(stats.total_requests, 1);
         assert!(stats.total_tokens_generated > 0);
+        let latency = stats.per_model["test-model"];
+        assert_eq!(latency.requests, 1);
+        assert_eq!(latency.average(), Some(latency.total_time));
     }
 
     #[tokio::test]
-    async fn test_model_not_found() {
-        let host = ModelHost::new(5, 8192);
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
+    async fn test_unload_releases_vram() {
+        let host = ModelHost::new(5, 4, VramBudget::Fixed(8192));
+        let dir = tempfile::tempdir().unwrap();
+        let model_path = dir.path().join("model.gguf");
+        std::fs::write(&model_path, b"GGUF").unwrap();
+        let mut config = gguf_config("test-model", &model_path);
+        config.warm_pool_size = 3;
+
+        host.register_model(config).await.unwrap();
+        host.load_model("test-model").await.unwrap();
+        assert_eq!(host.get_vram_usage().0, 2048);
+        assert!(host.list_models().await[0].loaded_at.is_some());
+
+        host.unload_model("test-model").await.unwrap();
+        assert_eq!(host.get_vram_usage().0, 0);
+        assert!(host.list_models().await[0].loaded_at.is_none());
+        assert!(matches!(
+            host.warmup_model("missing").await,
+            Err(ModelHostError::ModelNotFound { .. })
+        ));
+    }
 
-        let request = InferenceRequest {
-            prompt: "Test".to_string(),
-            model_name: "nonexistent".to_string(),
-            parameters: InferenceParameters::default(),
-            context: None,
-        };
+    #[tokio::test]
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
+    async fn test_usage_and_daily_budget() {
+        let host = ModelHost::new(1, 1, VramBudget::Fixed(8192));
+        let dir = tempfile::tempdir().unwrap();
+        let model_path = dir.path().join("model.gguf");
+        std::fs::write(&model_path, b"GGUF").unwrap();
+        let mut config = gguf_config("priced", &model_path);
+        config.cost_per_1k_prompt_tokens = Some(1000.0);
+        config.cost_per_1k_completion_tokens = Some(1000.0);
+        config.daily_budget = Some(1.0);
+        host.register_model(config).await.unwrap();
+        host.load_model("priced").await.unwrap();
+
+        let response = host.infer(InferenceRequest::new("priced", "hello there")).await.unwrap();
+        let usage = host.get_usage("priced").unwrap();
+        assert_eq!(usage.requests, 1);
+        assert_eq!(usage.completion_tokens, response.tokens_generated as u64);
+        assert_eq!(usage.prompt_tokens + usage.completion_tokens, response.total_tokens as u64);
+        assert_eq!(usage.cost, usage.prompt_tokens as f64 + usage.completion_tokens as f64);
+
+        assert!(matches!(
+            host.infer(InferenceRequest::new("priced", "again")).await,
+            Err(ModelHostError::BudgetExceeded { .. })
+        ));
+        assert_eq!(host.get_usage_all().len(), 1);
+    }
 
-        let result = host.infer(request).await;
-        assert!(matches!(result, Err(ModelHostError::ModelNotFound { .. })));
+    /// `healthy` loaded from a real file; `dead` registered but never
+    /// loaded, so every request to it fails
+    async fn fallback_host(breaker: CircuitBreakerConfig) -> (ModelHost, tempfile::TempDir) {
+        let host = ModelHost::new(1, 1, VramBudget::Fixed(8192)).with_circuit_breaker(breaker);
+        let dir = tempfile::tempdir().unwrap();
+        let model_path = dir.path().join("model.gguf");
+        std::fs::write(&model_path, b"GGUF").unwrap();
+        host.register_model(gguf_config("healthy", &model_path)).await.unwrap();
+        host.register_model(gguf_config("dead", &model_path)).await.unwrap();
+        host.load_model("healthy").await.unwrap();
+        (host, dir)
+    }
+
+    fn chained(model: &str, fallbacks: &[&str]) -> InferenceRequest {
+        let mut request = InferenceRequest::new(model, "hello");
+        request.fallback_chain = Some(fallbacks.iter().map(|name| name.to_string()).collect());
+        request
     }
 
     #[tokio::test]
-    async fn test_hot_swap() {
-        let mut host = ModelHost::new(5, 8192);
-
-        let model_path1 = PathBuf::from("/fake/path/model1.gguf");
-        let adapter1 = Box::new(LocalGGUFAdapter::new(
-            model_path1,
-            "model1".to_string(),
-            4096,
-            2048,
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
+    async fn test_fallback_paths() {
+        let (host, _dir) = fallback_host(CircuitBreakerConfig::default()).await;
+
+        let response = host.infer(chained("healthy", &["dead"])).await.unwrap();
+        assert!(!response.is_fallback);
+        assert_eq!(response.model_used, "healthy");
+
+        let response = host.infer(chained("dead", &["healthy"])).await.unwrap();
+        assert!(response.is_fallback);
+        assert_eq!(response.model_used, "healthy");
+        assert_eq!(host.get_stats().await.fallback_activations, 1);
+
+        assert!(matches!(
+            host.infer(chained("dead", &[])).await,
+            Err(ModelHostError::NotLoaded { .. })
         ));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
+    async fn test_circuit_breaker_skips_and_probes() {
+        let breaker = CircuitBreakerConfig { failure_threshold: 2, cooldown_ms: 200 };
+        let (host, _dir) = fallback_host(breaker).await;
+
+        for _ in 0..2 {
+            assert!(host.infer(chained("dead", &[])).await.is_err());
+        }
+        assert_eq!(host.circuit_state("dead").await, CircuitState::Open);
 
-        let model_path2 = PathBuf::from("/fake/path/model2.gguf");
-        let adapter2 = Box::new(LocalGGUFAdapter::new(
-            model_path2,
-            "model2".to_string(),
-            4096,
-            2048,
+        // Skipped outright while open
+        assert!(matches!(
+            host.infer(chained("dead", &[])).await,
+            Err(ModelHostError::CircuitOpen { name }) if name == "dead"
+        ));
+        let response = host.infer(chained("dead", &["healthy"])).await.unwrap();
+        assert!(response.is_fallback);
+        assert_eq!(host.get_stats().await.skipped_models.get("dead"), Some(&2));
+
+        // A failed probe reopens it; a successful one closes it
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(host.circuit_state("dead").await, CircuitState::HalfOpen);
+        assert!(matches!(
+            host.infer(chained("dead", &[])).await,
+            Err(ModelHostError::NotLoaded { .. })
         ));
+        assert_eq!(host.circuit_state("dead").await, CircuitState::Open);
+
+        host.load_model("dead").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let response = host.infer(chained("dead", &[])).await.unwrap();
+        assert!(!response.is_fallback);
+        assert_eq!(host.circuit_state("dead").await, CircuitState::Closed);
+    }
+
+    /// Counts health checks and unloads; inference takes `delay` and fails
+    /// while `fail` is set. Streams are `a b c`, or `a` and an error when
+    /// `break_stream` is set.
+    #[derive(Default)]
+    struct ProbeAdapter {
+        health_checks: Arc<AtomicU64>,
+        unloads: Arc<AtomicU64>,
+        fail: Arc<AtomicBool>,
+        break_stream: bool,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl ModelAdapter for ProbeAdapter {
+        async fn load(&mut self) -> Result<(), ModelHostError> {
+            Ok(())
+        }
+
+        async fn unload(&mut self) -> Result<(), ModelHostError> {
+            self.unloads.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn infer(&self, request: InferenceRequest) -> Result<InferenceResponse, ModelHostError> {
+            tokio::time::sleep(self.delay).await;
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(ModelHostError::Inference("probe failure".to_string()));
+            }
+            Ok(InferenceResponse {
+                text: request.prompt,
+                tokens_generated: 1,
+                total_tokens: 2,
+                finish_reason: FinishReason::Stop,
+                timing: InferenceTiming {
+                    prompt_eval_time: Duration::ZERO,
+                    eval_time: Duration::ZERO,
+                    total_time: Duration::ZERO,
+                    load_time: Duration::ZERO,
+                },
+                model_used: "probe".to_string(),
+                is_fallback: false,
+                context_dropped: 0,
+            })
+        }
+
+        async fn infer_stream(&self, _request: InferenceRequest) -> Result<TokenStream, ModelHostError> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(ModelHostError::Inference("probe failure".to_string()));
+            }
+            let tokens = if self.break_stream { vec!["a"] } else { vec!["a", "b", "c"] };
+            let (tx, rx) = mpsc::unbounded_channel();
+            for (i, token) in tokens.iter().enumerate() {
+                let _ = tx.send(Ok(StreamToken {
+                    token: token.to_string(),
+                    is_final: !self.break_stream && i == tokens.len() - 1,
+                    token_index: i as u32,
+                    timestamp: Instant::now(),
+                    fallback_model: None,
+                }));
+            }
+            if self.break_stream {
+                let _ = tx.send(Err(ModelHostError::Stream("connection reset".to_string())));
+            }
+            Ok(Box::pin(UnboundedReceiverStream::new(rx)))
+        }
+
+        async fn batch_infer(&self, _requests: Vec<InferenceRequest>) -> Result<Vec<InferenceResponse>, ModelHostError> {
+            Ok(Vec::new())
+        }
+
+        fn is_loaded(&self) -> bool {
+            true
+        }
+
+        fn get_model_info(&self) -> ModelInfo {
+            ModelInfo {
+                name: "probe".to_string(),
+                model_type: ModelType::LocalGGUF,
+                context_window: 0,
+                supports_streaming: false,
+                loaded_at: None,
+                vram_required_mb: 0,
+            }
+        }
+
+        fn supports_streaming(&self) -> bool {
+            false
+        }
+
+        fn supports_batch(&self) -> bool {
+            false
+        }
+
+        async fn health_check(&self) -> Result<(), ModelHostError> {
+            self.health_checks.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn warmup(&self) -> Result<(), ModelHostError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_checks_are_cached() {
+        let host = ModelHost::new(1, 1, VramBudget::Fixed(0)).with_health_ttl(Duration::from_millis(100));
+        let adapter = ProbeAdapter::default();
+        let (checks, fail) = (adapter.health_checks.clone(), adapter.fail.clone());
+        host.workers.write().await.insert(
+            "probe".to_string(),
+            vec![Arc::new(ModelWorker::new("probe-worker-0".to_string(), Box::new(adapter)))],
+        );
+
+        for _ in 0..3 {
+            host.infer(InferenceRequest::new("probe", "hi")).await.unwrap();
+        }
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+
+        // A failure marks the worker unhealthy and re-checks it out of band
+        fail.store(true, Ordering::SeqCst);
+        assert!(host.infer(InferenceRequest::new("probe", "hi")).await.is_err());
+        while checks.load(Ordering::SeqCst) < 2 {
+            tokio::task::yield_now().await;
+        }
+        fail.store(false, Ordering::SeqCst);
+        let report = host.get_health_report().await;
+        let worker = &report["probe"][0];
+        assert_eq!(worker.worker_id, "probe-worker-0");
+        assert_eq!(worker.requests_processed, 3);
+
+        // Past the TTL the monitor checks it again
+        let monitor = host.start_health_monitor();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(checks.load(Ordering::SeqCst) >= 3);
+        assert!(host.get_health_report().await["probe"][0].healthy);
+        host.shutdown(Duration::ZERO).await;
+        monitor.await.unwrap();
+    }
+
+    async fn probe_host(adapters: Vec<(&str, ProbeAdapter)>) -> ModelHost {
+        let host = ModelHost::new(1, 1, VramBudget::Fixed(0));
+        for (name, adapter) in adapters {
+            host.workers.write().await.insert(
+                name.to_string(),
+                vec![Arc::new(ModelWorker::new(format!("{}-worker-0", name), Box::new(adapter)))],
+            );
+        }
+        host
+    }
 
-        host.register_model("model1".to_string(), adapter1)
+    async fn collect_stream(stream: TokenStream) -> Vec<(String, Option<String>)> {
+        stream
+            .map(|token| token.map(|token| (token.token, token.fallback_model)))
+            .collect::<Vec<_>>()
             .await
-            .unwrap();
-        host.register_model("model2".to_string(), adapter2)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_falls_back() {
+        let failing = ProbeAdapter::default();
+        failing.fail.store(true, Ordering::SeqCst);
+        let breaking = ProbeAdapter { break_stream: true, ..Default::default() };
+        let host = probe_host(vec![("failing", failing), ("breaking", breaking), ("probe", ProbeAdapter::default())]).await;
+
+        // Failing to start moves on before the first token
+        let stream = host.infer_stream(chained("failing", &["probe"])).await.unwrap();
+        let tokens = collect_stream(stream).await;
+        assert_eq!(tokens[0], ("a".to_string(), Some("probe".to_string())));
+        assert_eq!(tokens.len(), 3);
+
+        // Failing early mid-stream restarts on the next model
+        let stream = host.infer_stream(chained("breaking", &["probe"])).await.unwrap();
+        let tokens: Vec<_> = collect_stream(stream).await;
+        assert_eq!(tokens, [
+            ("a".to_string(), None),
+            ("a".to_string(), Some("probe".to_string())),
+            ("b".to_string(), None),
+            ("c".to_string(), None),
+        ]);
+        assert!(host.get_stats().await.fallback_activations >= 1);
+
+        assert!(host.infer_stream(chained("failing", &[])).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_holds_worker_until_dropped() {
+        let host = probe_host(vec![("probe", ProbeAdapter::default())]).await;
+        let worker = host.workers.read().await["probe"][0].clone();
+
+        let stream = host.infer_stream(InferenceRequest::new("probe", "hi")).await.unwrap();
+        assert!(worker.is_busy.load(Ordering::SeqCst));
+        assert_eq!(host.leases.held(), 1);
+        assert_eq!(collect_stream(stream).await.len(), 3);
+        while worker.is_busy.load(Ordering::SeqCst) {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(host.leases.held(), 0);
+    }
+
+    fn with_context(policy: ContextPolicy) -> InferenceRequest {
+        let mut request = InferenceRequest::new("probe", "prompt!!");
+        request.parameters.max_tokens = 10;
+        request.context = Some(["first...", "second..", "third...", "fourth..", "latest.."].map(String::from).to_vec());
+        request.context_policy = policy;
+        request
+    }
+
+    #[test]
+    fn test_fit_context_policies() {
+        // Prompt and max_tokens take 12 tokens, each entry 2
+        let mut request = with_context(ContextPolicy::TruncateOldest);
+        assert_eq!(fit_context(&mut request, 22, &ByteEstimator).unwrap(), 0);
+        assert_eq!(fit_context(&mut request, 17, &ByteEstimator).unwrap(), 3);
+        assert_eq!(request.context.as_deref().unwrap(), ["fourth..", "latest.."]);
+        assert_eq!(request.prompt, "prompt!!");
+
+        let mut request = with_context(ContextPolicy::TruncateMiddle);
+        assert_eq!(fit_context(&mut request, 16, &ByteEstimator).unwrap(), 3);
+        assert_eq!(request.context.as_deref().unwrap(), ["first...", "latest.."]);
+
+        let mut request = with_context(ContextPolicy::Error);
+        assert!(matches!(
+            fit_context(&mut request, 16, &ByteEstimator),
+            Err(ModelHostError::ContextOverflow { required: 22, window: 16, .. })
+        ));
+        // Dropping context cannot make room for the prompt itself
+        let mut request = with_context(ContextPolicy::TruncateOldest);
+        assert!(fit_context(&mut request, 11, &ByteEstimator).is_err());
+        assert_eq!(request.context.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_infer_reports_dropped_context() {
+        let host = probe_host(vec![("probe", ProbeAdapter::default())]).await;
+        let mut config = gguf_config("probe", Path::new("probe.gguf"));
+        config.context_window = 17;
+        host.configs.write().await.insert("probe".to_string(), config);
+
+        let response = host.infer(with_context(ContextPolicy::TruncateOldest)).await.unwrap();
+        assert_eq!(response.context_dropped, 3);
+        assert!(matches!(
+            host.infer(with_context(ContextPolicy::Error)).await,
+            Err(ModelHostError::ContextOverflow { .. })
+        ));
+        assert!(matches!(
+            host.infer_stream(with_context(ContextPolicy::Error)).await,
+            Err(ModelHostError::ContextOverflow { .. })
+        ));
+        assert_eq!(host.circuit_state("probe").await, CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_then_aborts() {
+        let quick = ProbeAdapter { delay: Duration::from_millis(50), ..Default::default() };
+        let slow = ProbeAdapter { delay: Duration::from_secs(30), ..Default::default() };
+        let unloads = [quick.unloads.clone(), slow.unloads.clone()];
+        let host = Arc::new(probe_host(vec![("quick", quick), ("slow", slow)]).await);
+
+        let spawn = |model: &'static str| {
+            let host = host.clone();
+            tokio::spawn(async move { host.infer(InferenceRequest::new(model, "hi")).await })
+        };
+        let (quick, slow) = (spawn("quick"), spawn("slow"));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // Waits behind the slow request
+        let queued = spawn("slow");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let summary = host.shutdown(Duration::from_millis(300)).await;
+        assert_eq!(summary, ShutdownSummary { drained: 1, aborted: 2 });
+        assert!(quick.await.unwrap().is_ok());
+        assert!(matches!(slow.await.unwrap(), Err(ModelHostError::ShuttingDown)));
+        assert!(matches!(queued.await.unwrap(), Err(ModelHostError::ShuttingDown)));
+        assert!(unloads.iter().all(|unloads| unloads.load(Ordering::SeqCst) == 1));
+
+        assert!(matches!(
+            host.infer(InferenceRequest::new("quick", "hi")).await,
+            Err(ModelHostError::ShuttingDown)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_busy_pool_queues_by_priority() {
+        let host = Arc::new(ModelHost::new(1, 1, VramBudget::Fixed(8192)));
+        host.register_model(gguf_config("model", Path::new("/fake/path/model.gguf")))
             .await
             .unwrap();
+        let worker = host.get_available_worker("model", InferencePriority::Normal, None).await.unwrap();
+
+        let (order_tx, mut order_rx) = mpsc::unbounded_channel();
+        let arrivals = [
+            ("low-1", InferencePriority::Low),
+            ("low-2", InferencePriority::Low),
+            ("critical", InferencePriority::Critical),
+        ];
+        for (queued, (label, priority)) in arrivals.into_iter().enumerate() {
+            let (waiter, order_tx) = (host.clone(), order_tx.clone());
+            tokio::spawn(async move {
+                let worker = waiter.get_available_worker("model", priority, None).await.unwrap();
+                order_tx.send(label).unwrap();
+                waiter.release_worker("model", worker).await;
+            });
+            while host.request_queue.lock().await.len() <= queued {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        host.release_worker("model", worker).await;
+        let mut order = Vec::new();
+        for _ in 0..3 {
+            order.push(order_rx.recv().await.unwrap());
+        }
+        assert_eq!(order, ["critical", "low-1", "low-2"]);
+        assert!(host.get_stats().await.queue_wait_time >= Duration::from_millis(5));
+
+        // A queued request gives up after its timeout
+        let worker = host.get_available_worker("model", InferencePriority::Normal, None).await.unwrap();
+        assert!(matches!(
+            host.get_available_worker("model", InferencePriority::High, Some(10)).await,
+            Err(ModelHostError::Timeout { timeout_ms: 10 })
+        ));
+        host.release_worker("model", worker).await;
+        assert!(!host.workers.read().await["model"][0].is_busy.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_model_not_found() {
+        let host = ModelHost::new(5, 4, VramBudget::Fixed(8192));
+
+        let result = host.infer(short_request("nonexistent", "Test")).await;
+        assert!(matches!(result, Err(ModelHostError::ModelNotFound { .. })));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
+    async fn test_hot_swap() {
+        let mut host = ModelHost::new(5, 4, VramBudget::Fixed(8192));
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["model1", "model2"] {
+            let model_path = dir.path().join(format!("{}.gguf", name));
+            std::fs::write(&model_path, b"GGUF").unwrap();
+            host.register_model(gguf_config(name, &model_path)).await.unwrap();
+        }
 
         // Initial state
         assert!(host.get_current_model().await.is_none());
@@ -1964,4 +5035,245 @@ mod tests {
         let (used, _, _) = host.get_vram_usage();
         assert_eq!(used, 2048); // Should be same since we deallocated model1
     }
+
+    #[test]
+    fn test_vram_stats_stay_consistent_under_contention() {
+        let stats = Arc::new(VramStats::new(1000, VramSource::Fixed));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let stats = stats.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        if stats.allocate(300).is_ok() {
+                            assert!(stats.used_mb.load(Ordering::SeqCst) <= 1000);
+                            stats.deallocate(300);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(stats.used_mb.load(Ordering::SeqCst), 0);
+        assert_eq!(stats.available_mb(), 1000);
+
+        stats.allocate(800).unwrap();
+        assert!(matches!(
+            stats.allocate(300),
+            Err(ModelHostError::VramExhausted { required: 300, available: 200 })
+        ));
+        assert_eq!(stats.available_mb(), 200);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
+    async fn test_hot_swap_preflight_and_progress() {
+        let mut host = ModelHost::new(1, 1, VramBudget::Fixed(4096));
+        let dir = tempfile::tempdir().unwrap();
+        for (name, vram) in [("small", 2048), ("fast", 2048), ("big", 3072), ("huge", 5000)] {
+            let model_path = dir.path().join(format!("{}.gguf", name));
+            std::fs::write(&model_path, b"GGUF").unwrap();
+            let mut config = gguf_config(name, &model_path);
+            config.vram_required_mb = vram;
+            host.register_model(config).await.unwrap();
+        }
+        let drain = |mut progress: mpsc::UnboundedReceiver<HotSwapProgress>| {
+            std::iter::from_fn(move || progress.try_recv().ok()).collect::<Vec<_>>()
+        };
+
+        let progress = host.request_hot_swap("small".to_string(), false).await.unwrap();
+        host.process_hot_swap_requests().await.unwrap();
+        let steps = drain(progress);
+        assert_eq!(steps[..2], [HotSwapProgress::Loading("small".to_string()), HotSwapProgress::WarmingUp]);
+        assert!(matches!(&steps[2], HotSwapProgress::Ready(summary) if summary.model == "small" && !summary.is_slow()));
+
+        // Another model holds the rest of the VRAM
+        host.load_model("fast").await.unwrap();
+        let error = host.request_hot_swap("big".to_string(), false).await.unwrap_err();
+        assert_eq!(error.to_string(), "need 3072MB, only 2048MB free — pass --force to evict");
+        assert!(matches!(
+            host.request_hot_swap("huge".to_string(), true).await,
+            Err(ModelHostError::VramExhausted { required: 5000, available: 4096 })
+        ));
+        assert!(matches!(
+            host.request_hot_swap("missing".to_string(), true).await,
+            Err(ModelHostError::ModelNotFound { .. })
+        ));
+
+        let progress = host.request_hot_swap("big".to_string(), true).await.unwrap();
+        host.process_hot_swap_requests().await.unwrap();
+        let steps = drain(progress);
+        assert_eq!(
+            steps[..3],
+            [
+                HotSwapProgress::Unloading("small".to_string()),
+                HotSwapProgress::Unloading("fast".to_string()),
+                HotSwapProgress::Loading("big".to_string()),
+            ]
+        );
+        assert!(matches!(&steps[4], HotSwapProgress::Ready(summary) if summary.unloaded == ["small", "fast"]));
+        assert_eq!(host.get_current_model().await.as_deref(), Some("big"));
+        assert_eq!(host.get_vram_usage().0, 3072);
+
+        // The background task drains requests once started
+        let host = Arc::new(host);
+        host.start_hot_swaps();
+        let mut progress = host.request_hot_swap("small".to_string(), false).await.unwrap();
+        let ready = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(step) = progress.recv().await {
+                if let HotSwapProgress::Ready(summary) = step {
+                    return summary;
+                }
+            }
+            panic!("hot-swap ended without a summary");
+        })
+        .await
+        .unwrap();
+        assert_eq!(ready.unloaded, ["big"]);
+        assert_eq!(host.get_current_model().await.as_deref(), Some("small"));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
+    async fn test_warm_pool_unloads_idle_and_keeps_warm() {
+        let mut host = ModelHost::new(1, 1, VramBudget::Fixed(4096));
+        let dir = tempfile::tempdir().unwrap();
+        for (name, vram, idle_unload_secs, keep_warm) in
+            [("coder", 2048, 60, false), ("chat", 2048, 0, true), ("big", 3072, 0, false)]
+        {
+            let model_path = dir.path().join(format!("{}.gguf", name));
+            std::fs::write(&model_path, b"GGUF").unwrap();
+            let mut config = gguf_config(name, &model_path);
+            config.vram_required_mb = vram;
+            config.idle_unload_secs = idle_unload_secs;
+            config.keep_warm = keep_warm;
+            host.register_model(config).await.unwrap();
+        }
+        async fn loaded(host: &ModelHost, name: &str) -> bool {
+            host.model_states().await.iter().any(|state| state.name == name && state.loaded)
+        }
+
+        // Idle time counts from the load
+        host.load_model("coder").await.unwrap();
+        host.manage_warm_pool().await;
+        assert!(loaded(&host, "coder").await);
+
+        let coder = host.workers.read().await["coder"][0].clone();
+        *coder.last_used.lock().await = Instant::now() - Duration::from_secs(61);
+        host.manage_warm_pool().await;
+        assert!(!loaded(&host, "coder").await);
+        assert_eq!(host.get_stats().await.auto_unloads, 1);
+        assert_eq!(host.get_vram_usage().0, 0);
+
+        // The next request reloads it, and says how long that took
+        let response = host.infer(short_request("coder", "hello")).await.unwrap();
+        assert!(response.timing.load_time > Duration::ZERO);
+        assert!(loaded(&host, "coder").await);
+        let response = host.infer(short_request("coder", "again")).await.unwrap();
+        assert_eq!(response.timing.load_time, Duration::ZERO);
+        host.unload_model("coder").await.unwrap();
+
+        // A keep-warm model comes back once a swap leaves room for it
+        host.load_model("chat").await.unwrap();
+        host.set_current_model("chat").await;
+        host.request_hot_swap("big".to_string(), false).await.unwrap();
+        host.process_hot_swap_requests().await.unwrap();
+        host.manage_warm_pool().await;
+        assert!(!loaded(&host, "chat").await);
+
+        host.request_hot_swap("coder".to_string(), false).await.unwrap();
+        host.process_hot_swap_requests().await.unwrap();
+        host.manage_warm_pool().await;
+        assert!(loaded(&host, "chat").await);
+        assert_eq!(host.get_vram_usage().0, 4096);
+    }
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = StreamParser::default();
+        let deltas = |events: Vec<Result<StreamEvent, ModelHostError>>| {
+            events.into_iter().map(|event| event.unwrap()).collect::<Vec<_>>()
+        };
+
+        // A line split across chunks comes out once it is complete
+        assert!(parser.push(b"data: {\"choices\":[{\"delta\":{\"content\":\"Hel").is_empty());
+        assert_eq!(
+            deltas(parser.push(b"lo\"}}]}\r\n\n: keep-alive\n")),
+            [StreamEvent::Delta("Hello".to_string())]
+        );
+        assert_eq!(
+            deltas(parser.push(b"data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\ndata: {\"token\":\" world\"}\n\ndata: [DONE]\n\n")),
+            [StreamEvent::Delta(" world".to_string()), StreamEvent::Done]
+        );
+
+        assert!(matches!(
+            parser.push(b"data: {\"choices\": [\n").as_slice(),
+            [Err(ModelHostError::Stream(_))]
+        ));
+        assert_eq!(deltas(StreamParser::default().push(b"data: {\"response\":\"\",\"done\":true}\n")), [StreamEvent::Done]);
+    }
+
+    #[test]
+    fn test_anthropic_messages() {
+        let mut request = InferenceRequest::new("claude", "and now?");
+        request.context = Some(vec![
+            "hi".to_string(),
+            "hello".to_string(),
+            "list files".to_string(),
+        ]);
+        let roles: Vec<_> = AnthropicAdapter::messages(&request)
+            .iter()
+            .map(|m| (m["role"].as_str().unwrap().to_string(), m["content"].as_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(roles, [
+            ("user".to_string(), "hi".to_string()),
+            ("assistant".to_string(), "hello".to_string()),
+            ("user".to_string(), "list files\n\nand now?".to_string()),
+        ]);
+
+        assert_eq!(AnthropicAdapter::finish_reason(Some("max_tokens")), FinishReason::Length);
+        assert_eq!(AnthropicAdapter::finish_reason(Some("stop_sequence")), FinishReason::Stop);
+
+        let mut parser = StreamParser::default();
+        let events = parser.push(b"event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"ls\"}}\n\nevent: message_stop\ndata: {\"type\":\"message_stop\"}\n\n");
+        let events: Vec<_> = events.into_iter().map(|event| event.unwrap()).collect();
+        assert_eq!(events, [StreamEvent::Delta("ls".to_string()), StreamEvent::Done]);
+    }
+
+    #[test]
+    fn test_gemini_contents_and_blocks() {
+        let mut request = InferenceRequest::new("gemini", "and now?");
+        request.context = Some(vec!["hi".to_string(), "hello".to_string()]);
+        let contents = GeminiAdapter::contents(&request);
+        assert_eq!(contents[1], serde_json::json!({ "role": "model", "parts": [{ "text": "hello" }] }));
+        assert_eq!(contents[2]["role"], "user");
+
+        let blocked = serde_json::json!({ "promptFeedback": { "blockReason": "SAFETY" } });
+        assert_eq!(
+            GeminiAdapter::finish_reason(None, &blocked),
+            FinishReason::Error("prompt blocked: SAFETY".to_string())
+        );
+        assert_eq!(GeminiAdapter::finish_reason(Some("MAX_TOKENS"), &serde_json::Value::Null), FinishReason::Length);
+
+        let mut parser = StreamParser::default();
+        let events = parser.push(b"data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"ls\"}]}}]}\r\n\r\n");
+        assert_eq!(events.into_iter().map(|event| event.unwrap()).collect::<Vec<_>>(), [StreamEvent::Delta("ls".to_string())]);
+        let events = parser.push(b"data: {\"candidates\":[{\"finishReason\":\"RECITATION\"}]}\n\n");
+        assert!(matches!(events.as_slice(), [Err(ModelHostError::Stream(reason))] if reason.contains("RECITATION")));
+    }
+
+    #[test]
+    fn test_generation_limits() {
+        let mut parameters = InferenceParameters {
+            max_tokens: 3,
+            ..Default::default()
+        };
+        let (text, tokens, finish) = apply_generation_limits("one two three four", &parameters);
+        assert_eq!((text.as_str(), tokens, finish), ("one two three", 3, FinishReason::Length));
+
+        parameters.stop_sequences = vec!["three".to_string(), "two".to_string()];
+        let (text, tokens, finish) = apply_generation_limits("one two three four", &parameters);
+        assert_eq!((text.as_str(), tokens, finish), ("one", 1, FinishReason::Stop));
+    }
 }
@@ -0,0 +1,296 @@
+use crate::config::{self, AgentConfig, Config};
+use crate::model_host::{
+    InferenceParameters, ModelConfig, ModelHost, ModelHostError, ModelState, ModelType,
+};
+use std::path::PathBuf;
+use thiserror::Error;
+use tracing::{info, warn};
+
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("model name cannot be empty")]
+    MissingName,
+    #[error("model '{0}' must have either path or api_endpoint")]
+    NoSource(String),
+    #[error("model '{name}' has unknown model_type '{model_type}'")]
+    UnknownType { name: String, model_type: String },
+    #[error("model file for '{name}' not found: {}", path.display())]
+    MissingFile { name: String, path: PathBuf },
+    #[error("API key for '{name}' not set: ${env} is empty")]
+    MissingApiKey { name: String, env: String },
+    #[error(transparent)]
+    Host(#[from] ModelHostError),
+}
+
+/// What `register_models` did with each `[[models.list]]` entry
+#[derive(Debug, Default)]
+pub struct Registration {
+    pub registered: Vec<String>,
+    pub preloaded: Vec<String>,
+    /// Entries left out, with the reason
+    pub skipped: Vec<(String, RegistryError)>,
+}
+
+/// The adapter for a model: its `model_type`, or else guessed from its
+/// endpoint, with local files run as GGUF
+pub fn model_type(model: &config::ModelConfig) -> Result<ModelType, RegistryError> {
+    Ok(match model.model_type.to_lowercase().as_str() {
+        "gguf" | "local" => ModelType::LocalGGUF,
+        "mlc" => ModelType::MLC,
+        "vllm" => ModelType::VLLM,
+        "ollama" => ModelType::Ollama,
+        "openai" => ModelType::OpenAI,
+        "anthropic" => ModelType::Anthropic,
+        "gemini" => ModelType::Gemini,
+        "remote" => ModelType::RemoteAPI,
+        "" => match model.api_endpoint.as_deref() {
+            Some(endpoint) if endpoint.contains("api.anthropic.com") => ModelType::Anthropic,
+            Some(endpoint) if endpoint.contains("generativelanguage.googleapis.com") => {
+                ModelType::Gemini
+            }
+            Some(endpoint) if endpoint.contains(":11434") => ModelType::Ollama,
+            Some(_) => ModelType::RemoteAPI,
+            None => ModelType::LocalGGUF,
+        },
+        other => {
+            return Err(RegistryError::UnknownType {
+                name: model.name.clone(),
+                model_type: other.to_string(),
+            });
+        }
+    })
+}
+
+/// The host's config for an entry, with the agent's sampling defaults. An
+/// `api_key` of the form `${VAR}` names the key's environment variable.
+pub fn host_config(
+    model: &config::ModelConfig,
+    agent: &AgentConfig,
+) -> Result<ModelConfig, RegistryError> {
+    let api_key_env = model.api_key_env.clone().or_else(|| {
+        let key = model.api_key.as_deref()?;
+        Some(key.strip_prefix("${")?.strip_suffix('}')?.to_string())
+    });
+    Ok(ModelConfig {
+        name: model.name.clone(),
+        model_type: model_type(model)?,
+        model_path: model.path.as_deref().map(expand_home),
+        api_endpoint: model.api_endpoint.clone(),
+        api_key_env,
+        context_window: model.context_window,
+        vram_required_mb: model.vram_required_mb,
+        default_parameters: InferenceParameters {
+            max_tokens: agent.max_tokens,
+            temperature: agent.temperature,
+            ..Default::default()
+        },
+        fallback_models: model.fallback_models.clone(),
+        warm_pool_size: model.warm_pool_size,
+        max_concurrent: model.max_concurrent,
+        retry: Default::default(),
+        cost_per_1k_prompt_tokens: model.cost_per_1k_prompt_tokens,
+        cost_per_1k_completion_tokens: model.cost_per_1k_completion_tokens,
+        daily_budget: model.daily_budget,
+        idle_unload_secs: model.idle_unload_secs,
+        keep_warm: model.keep_warm,
+    })
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Catch what would otherwise fail on first use: local models need their
+/// file, and a named key variable must be set
+pub fn validate(config: &ModelConfig) -> Result<(), RegistryError> {
+    if config.name.is_empty() {
+        return Err(RegistryError::MissingName);
+    }
+    if config.model_path.is_none() && config.api_endpoint.is_none() {
+        return Err(RegistryError::NoSource(config.name.clone()));
+    }
+    if config.model_type == ModelType::LocalGGUF
+        && let Some(path) = &config.model_path
+        && !path.exists()
+    {
+        return Err(RegistryError::MissingFile {
+            name: config.name.clone(),
+            path: path.clone(),
+        });
+    }
+    if let Some(env) = &config.api_key_env
+        && !std::env::var(env).is_ok_and(|key| !key.is_empty())
+    {
+        return Err(RegistryError::MissingApiKey {
+            name: config.name.clone(),
+            env: env.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Register every configured model with `host` and load those marked
+/// `preload`. Entries that fail are logged and left out rather than
+/// stopping the rest.
+pub async fn register_models(host: &ModelHost, config: &Config) -> Registration {
+    let mut registration = Registration::default();
+    for model in &config.models.models {
+        let result = async {
+            let host_config = host_config(model, &config.agent)?;
+            validate(&host_config)?;
+            host.register_model(host_config).await?;
+            Ok::<_, RegistryError>(())
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Skipping model {:?}: {}", model.name, e);
+            registration.skipped.push((model.name.clone(), e));
+            continue;
+        }
+        registration.registered.push(model.name.clone());
+
+        if model.preload {
+            match host.load_model(&model.name).await {
+                Ok(()) => registration.preloaded.push(model.name.clone()),
+                // Registered all the same; a later request retries the load
+                Err(e) => warn!("Failed to preload model {}: {}", model.name, e),
+            }
+        }
+    }
+    info!(
+        "Registered {} models ({} preloaded, {} skipped)",
+        registration.registered.len(),
+        registration.preloaded.len(),
+        registration.skipped.len()
+    );
+    registration
+}
+
+/// One row per registered model, for `:models`
+pub fn format_table(states: &[ModelState], default_model: &str) -> String {
+    if states.is_empty() {
+        return "No models registered; add [[models.list]] entries to the config\n".to_string();
+    }
+    let width = states
+        .iter()
+        .map(|state| state.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+    let mut table = format!(
+        "  {:<width$}  {:<10}  {:<8}  {:>7}\n",
+        "model", "type", "state", "workers"
+    );
+    for state in states {
+        let model_type = state
+            .model_type
+            .as_ref()
+            .map_or("-".to_string(), |model_type| format!("{:?}", model_type));
+        table.push_str(&format!(
+            "{} {:<width$}  {:<10}  {:<8}  {:>7}\n",
+            if state.name == default_model {
+                "*"
+            } else {
+                " "
+            },
+            state.name,
+            model_type,
+            if state.loaded { "loaded" } else { "unloaded" },
+            state.workers
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vram_probe::VramBudget;
+
+    fn entry(name: &str) -> config::ModelConfig {
+        config::ModelConfig {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "llama", ignore = "loads a stand-in GGUF file")]
+    async fn test_register_skips_broken_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let model_path = dir.path().join("local.gguf");
+        std::fs::write(&model_path, b"GGUF").unwrap();
+
+        let mut config = Config::default();
+        config.models.models = vec![
+            config::ModelConfig {
+                path: Some(model_path.display().to_string()),
+                preload: true,
+                ..entry("local")
+            },
+            config::ModelConfig {
+                path: Some("/nonexistent/missing.gguf".to_string()),
+                ..entry("missing")
+            },
+            config::ModelConfig {
+                api_endpoint: Some("https://api.anthropic.com".to_string()),
+                api_key: Some("${FERROTERM_TEST_UNSET_KEY}".to_string()),
+                ..entry("claude")
+            },
+            config::ModelConfig {
+                model_type: "llamafile".to_string(),
+                path: Some(model_path.display().to_string()),
+                ..entry("odd")
+            },
+        ];
+
+        let host = ModelHost::new(1, 1, VramBudget::Fixed(0));
+        let registration = register_models(&host, &config).await;
+        assert_eq!(registration.registered, ["local"]);
+        assert_eq!(registration.preloaded, ["local"]);
+        let skipped: Vec<_> = registration
+            .skipped
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(skipped, ["missing", "claude", "odd"]);
+        assert!(matches!(
+            registration.skipped[1].1,
+            RegistryError::MissingApiKey { ref env, .. } if env == "FERROTERM_TEST_UNSET_KEY"
+        ));
+
+        let states = host.model_states().await;
+        assert_eq!(states.len(), 1);
+        assert!(states[0].loaded);
+        let table = format_table(&states, "local");
+        assert!(table.contains("* local") && table.contains("LocalGGUF"));
+    }
+
+    #[test]
+    fn test_model_type_from_endpoint() {
+        let remote = |endpoint: &str| config::ModelConfig {
+            api_endpoint: Some(endpoint.to_string()),
+            ..entry("remote")
+        };
+        assert_eq!(
+            model_type(&remote("https://generativelanguage.googleapis.com/v1beta")).unwrap(),
+            ModelType::Gemini
+        );
+        assert_eq!(
+            model_type(&remote("http://localhost:11434")).unwrap(),
+            ModelType::Ollama
+        );
+        assert_eq!(
+            model_type(&remote("https://api.openai.com/v1")).unwrap(),
+            ModelType::RemoteAPI
+        );
+        let explicit = config::ModelConfig {
+            model_type: "OpenAI".to_string(),
+            ..remote("https://proxy.internal/v1")
+        };
+        assert_eq!(model_type(&explicit).unwrap(), ModelType::OpenAI);
+    }
+}
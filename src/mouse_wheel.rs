@@ -0,0 +1,102 @@
+use winit::event::MouseScrollDelta;
+
+/// Turns wheel notches and trackpad travel into whole lines, keeping the
+/// remainder so slow trackpad movement still adds up to a line
+#[derive(Debug, Default)]
+pub struct WheelAccumulator {
+    pending: f64,
+}
+
+impl WheelAccumulator {
+    /// Lines to scroll, positive toward older output. winit already applies
+    /// the system's scroll direction setting to the delta.
+    pub fn lines(&mut self, delta: MouseScrollDelta, cell_height: f32, multiplier: f32) -> isize {
+        let amount = match delta {
+            MouseScrollDelta::LineDelta(_, y) => y as f64,
+            MouseScrollDelta::PixelDelta(position) => position.y / cell_height.max(1.0) as f64,
+        } * multiplier as f64;
+        // Reversing drops travel left over from the other direction
+        if amount.signum() != self.pending.signum() {
+            self.pending = 0.0;
+        }
+        self.pending += amount;
+        let lines = self.pending.trunc();
+        self.pending -= lines;
+        lines as isize
+    }
+}
+
+/// A wheel report for an application that enabled mouse tracking, at a
+/// zero-based cell. SGR reports (mode 1006) carry any coordinate; the
+/// legacy encoding stops at column and row 223.
+pub fn wheel_report(
+    up: bool,
+    (column, row): (u32, u32),
+    sgr: bool,
+    shift: bool,
+    ctrl: bool,
+) -> Vec<u8> {
+    let mut button = if up { 64 } else { 65 };
+    if shift {
+        button += 4;
+    }
+    if ctrl {
+        button += 16;
+    }
+    if sgr {
+        format!("\x1b[<{};{};{}M", button, column + 1, row + 1).into_bytes()
+    } else {
+        let encode = |value: u32| (value + 33).min(255) as u8;
+        vec![
+            0x1b,
+            b'[',
+            b'M',
+            button as u8 + 32,
+            encode(column),
+            encode(row),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::dpi::PhysicalPosition;
+
+    #[test]
+    fn test_wheel_deltas_become_lines() {
+        let mut wheel = WheelAccumulator::default();
+        assert_eq!(
+            wheel.lines(MouseScrollDelta::LineDelta(0.0, 1.0), 16.0, 3.0),
+            3
+        );
+        assert_eq!(
+            wheel.lines(MouseScrollDelta::LineDelta(0.0, -2.0), 16.0, 1.0),
+            -2
+        );
+
+        // Trackpad travel smaller than a line accumulates
+        let pixels = |y| MouseScrollDelta::PixelDelta(PhysicalPosition::new(0.0, y));
+        assert_eq!(wheel.lines(pixels(10.0), 16.0, 1.0), 0);
+        assert_eq!(wheel.lines(pixels(10.0), 16.0, 1.0), 1);
+        // until the direction changes
+        assert_eq!(wheel.lines(pixels(-10.0), 16.0, 1.0), 0);
+        assert_eq!(wheel.lines(pixels(-40.0), 16.0, 1.0), -3);
+    }
+
+    #[test]
+    fn test_wheel_reports() {
+        assert_eq!(
+            wheel_report(true, (4, 9), true, false, false),
+            b"\x1b[<64;5;10M"
+        );
+        assert_eq!(
+            wheel_report(false, (0, 0), true, true, true),
+            b"\x1b[<85;1;1M"
+        );
+        assert_eq!(
+            wheel_report(false, (4, 9), false, false, false),
+            b"\x1b[Ma%*"
+        );
+    }
+}
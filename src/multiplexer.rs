@@ -1,24 +1,20 @@
-use parking_lot::RwLock as ParkingRwLock;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::sync::RwLock;
 use tracing::{error, info, warn};
 
+use crate::command_parser::{Command, CommandParser, SessionAction};
 use crate::config::ConfigManager;
-use crate::input::{InputError, InputProcessor, Key, KeyEvent, Modifier};
-use crate::renderer::{GpuRenderer, RendererError};
+use crate::input::{InputError, Key, KeyEvent, Modifier};
 use crate::tty::{PtyConfig, TtyEngine, TtyError};
 
 #[derive(Error, Debug)]
 pub enum MultiplexerError {
     #[error("TTY error: {0}")]
     Tty(#[from] TtyError),
-    #[error("Renderer error: {0}")]
-    Renderer(#[from] RendererError),
     #[error("Input error: {0}")]
     Input(#[from] InputError),
     #[error("IO error: {0}")]
@@ -31,6 +27,8 @@ pub enum MultiplexerError {
     WindowNotFound { id: u64 },
     #[error("Session not found: {name}")]
     SessionNotFound { name: String },
+    #[error("Invalid session name: {name}")]
+    InvalidSessionName { name: String },
     #[error("Invalid layout: {reason}")]
     InvalidLayout { reason: String },
     #[error("Maximum panes exceeded: {max}")]
@@ -105,8 +103,79 @@ impl PaneLayout {
         }
         None
     }
+
+    /// Place this node and divide its rectangle among its children. A split
+    /// leaves one cell between neighbours for the border; the last child
+    /// takes any remainder. Nodes without a split keep their children's
+    /// rectangles, which the layout algorithm assigned.
+    pub fn arrange(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        self.x = x;
+        self.y = y;
+        self.width = width;
+        self.height = height;
+        let Some(direction) = self.split_direction else {
+            return;
+        };
+        let count = self.children.len() as u32;
+        if count == 0 {
+            return;
+        }
+        let span = match direction {
+            SplitDirection::Horizontal => height,
+            SplitDirection::Vertical => width,
+        };
+        let room = span.saturating_sub(count - 1);
+        let mut offset = 0;
+        for (i, child) in self.children.iter_mut().enumerate() {
+            let size = if i as u32 == count - 1 {
+                room - (room / count) * (count - 1)
+            } else {
+                room / count
+            };
+            match direction {
+                SplitDirection::Horizontal => child.arrange(x, y + offset, width, size),
+                SplitDirection::Vertical => child.arrange(x + offset, y, size, height),
+            }
+            offset += size + 1;
+        }
+    }
+
+    /// Pane ids of the leaves, left to right and top to bottom
+    pub fn leaves(&self) -> Vec<u64> {
+        if self.is_leaf() {
+            return vec![self.id];
+        }
+        self.children.iter().flat_map(PaneLayout::leaves).collect()
+    }
+
+    /// Remove a pane's leaf. A split left with a single child is replaced
+    /// by that child, which takes over the split's rectangle.
+    fn remove_leaf(&mut self, id: u64) -> bool {
+        if let Some(index) = self
+            .children
+            .iter()
+            .position(|child| child.is_leaf() && child.id == id)
+        {
+            self.children.remove(index);
+            return true;
+        }
+        for child in &mut self.children {
+            if child.remove_leaf(id) {
+                if child.split_direction.is_some() && child.children.len() == 1 {
+                    let (x, y, width, height) = (child.x, child.y, child.width, child.height);
+                    *child = child.children.remove(0);
+                    child.arrange(x, y, width, height);
+                }
+                return true;
+            }
+        }
+        false
+    }
 }
 
+/// Id of layout nodes that hold a split rather than a pane
+const SPLIT_NODE: u64 = 0;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Pane {
     pub id: u64,
@@ -116,6 +185,12 @@ pub struct Pane {
     pub title: String,
     pub created_at: u64,
     pub last_activity: u64,
+    /// Shell's directory when the session was saved
+    #[serde(default)]
+    pub working_directory: Option<PathBuf>,
+    /// Foreground program when the session was saved, if not the shell
+    #[serde(default)]
+    pub command: Option<String>,
 }
 
 impl Pane {
@@ -133,6 +208,8 @@ impl Pane {
             title: format!("Pane {}", id),
             created_at: now,
             last_activity: now,
+            working_directory: None,
+            command: None,
         }
     }
 
@@ -184,21 +261,46 @@ impl Window {
         if self.panes.is_empty() {
             self.active_pane_id = Some(pane.id);
         }
+        self.layout.children.push(PaneLayout::new(pane.id, 0, 0, 0, 0));
         self.panes.insert(pane.id, pane);
         self.recalculate_layout();
     }
 
     pub fn remove_pane(&mut self, pane_id: u64) -> Option<Pane> {
         let removed = self.panes.remove(&pane_id);
-        if self.active_pane_id == Some(pane_id) {
-            self.active_pane_id = self.panes.keys().next().copied();
-        }
         if removed.is_some() {
+            self.layout.remove_leaf(pane_id);
             self.recalculate_layout();
         }
+        if self.active_pane_id == Some(pane_id) {
+            self.active_pane_id = self.ordered_panes().first().map(|pane| pane.id);
+        }
         removed
     }
 
+    /// Panes in layout order, left to right and top to bottom
+    pub fn ordered_panes(&self) -> Vec<&Pane> {
+        self.layout
+            .leaves()
+            .into_iter()
+            .filter_map(|id| self.panes.get(&id))
+            .collect()
+    }
+
+    pub fn pane_for_pty(&self, pty_id: u64) -> Option<u64> {
+        self.panes
+            .values()
+            .find(|pane| pane.pty_id == pty_id)
+            .map(|pane| pane.id)
+    }
+
+    /// Set the window's size in cells and lay the panes out again
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.layout.width = width;
+        self.layout.height = height;
+        self.recalculate_layout();
+    }
+
     pub fn get_active_pane(&self) -> Option<&Pane> {
         self.active_pane_id.and_then(|id| self.panes.get(&id))
     }
@@ -235,16 +337,38 @@ impl Window {
             return;
         }
 
-        let pane_ids: Vec<u64> = self.panes.keys().copied().collect();
-        self.layout.children.clear();
-
+        // The algorithm places the top-level entries; splits then divide
+        // their entry's rectangle
+        let entries = std::mem::take(&mut self.layout.children);
+        let slots: Vec<u64> = (0..entries.len() as u64).collect();
         match self.layout_algorithm {
-            LayoutAlgorithm::Tiled => self.layout_tiled(&pane_ids),
-            LayoutAlgorithm::EvenHorizontal => self.layout_even_horizontal(&pane_ids),
-            LayoutAlgorithm::EvenVertical => self.layout_even_vertical(&pane_ids),
-            LayoutAlgorithm::MainVertical => self.layout_main_vertical(&pane_ids),
-            LayoutAlgorithm::MainHorizontal => self.layout_main_horizontal(&pane_ids),
+            LayoutAlgorithm::Tiled => self.layout_tiled(&slots),
+            LayoutAlgorithm::EvenHorizontal => self.layout_even_horizontal(&slots),
+            LayoutAlgorithm::EvenVertical => self.layout_even_vertical(&slots),
+            LayoutAlgorithm::MainVertical => self.layout_main_vertical(&slots),
+            LayoutAlgorithm::MainHorizontal => self.layout_main_horizontal(&slots),
+        }
+        let slots = std::mem::replace(&mut self.layout.children, entries);
+        for (entry, slot) in self.layout.children.iter_mut().zip(slots) {
+            entry.arrange(slot.x, slot.y, slot.width, slot.height);
         }
+        self.sync_pane_layouts();
+    }
+
+    /// Copy each leaf's rectangle to its pane
+    fn sync_pane_layouts(&mut self) {
+        fn visit(node: &PaneLayout, panes: &mut HashMap<u64, Pane>) {
+            if node.is_leaf() {
+                if let Some(pane) = panes.get_mut(&node.id) {
+                    pane.resize(node.x, node.y, node.width, node.height);
+                }
+                return;
+            }
+            for child in &node.children {
+                visit(child, panes);
+            }
+        }
+        visit(&self.layout, &mut self.panes);
     }
 
     fn layout_tiled(&mut self, pane_ids: &[u64]) {
@@ -254,7 +378,7 @@ impl Window {
         }
 
         let cols = ((count as f32).sqrt()).ceil() as u32;
-        let rows = (count + cols as usize - 1) / cols as usize;
+        let rows = count.div_ceil(cols as usize);
 
         for (i, &pane_id) in pane_ids.iter().enumerate() {
             let row = i / cols as usize;
@@ -346,48 +470,103 @@ impl Window {
         }
     }
 
+    /// Split a pane in two: `Horizontal` stacks the new pane below it,
+    /// `Vertical` puts it to the right. The new pane has no PTY yet.
     pub fn split_pane(
         &mut self,
         pane_id: u64,
         direction: SplitDirection,
     ) -> Result<u64, MultiplexerError> {
-        let layout = {
-            let pane = self
-                .panes
-                .get(&pane_id)
-                .ok_or(MultiplexerError::PaneNotFound { id: pane_id })?;
-            pane.layout.clone()
+        let pane = self
+            .panes
+            .get(&pane_id)
+            .ok_or(MultiplexerError::PaneNotFound { id: pane_id })?;
+        let span = match direction {
+            SplitDirection::Horizontal => pane.layout.height,
+            SplitDirection::Vertical => pane.layout.width,
         };
+        // A cell for each half and one for the border
+        if span < 3 {
+            return Err(MultiplexerError::InvalidLayout {
+                reason: format!("pane {} is too small to split", pane_id),
+            });
+        }
 
-        // Create new pane
         let new_pane_id = self.panes.keys().max().unwrap_or(&0) + 1;
-        let (new_x, new_y, new_width, new_height) = match direction {
-            SplitDirection::Horizontal => {
-                let half_height = layout.height / 2;
-                (layout.x, layout.y + half_height, layout.width, half_height)
-            }
-            SplitDirection::Vertical => {
-                let half_width = layout.width / 2;
-                (layout.x + half_width, layout.y, half_width, layout.height)
-            }
-        };
+        let node = self
+            .layout
+            .find_pane_mut(pane_id)
+            .ok_or(MultiplexerError::PaneNotFound { id: pane_id })?;
+        node.id = SPLIT_NODE;
+        node.split_direction = Some(direction);
+        node.children = vec![
+            PaneLayout::new(pane_id, 0, 0, 0, 0),
+            PaneLayout::new(new_pane_id, 0, 0, 0, 0),
+        ];
+        let (x, y, width, height) = (node.x, node.y, node.width, node.height);
+        node.arrange(x, y, width, height);
+
+        self.panes
+            .insert(new_pane_id, Pane::new(new_pane_id, 0, 0, 0, 0, 0));
+        self.sync_pane_layouts();
+        Ok(new_pane_id)
+    }
 
-        let mut new_pane = Pane::new(new_pane_id, 0, new_x, new_y, new_width, new_height);
-        new_pane.title = format!("Pane {}", new_pane_id);
+    /// The nearest pane in `direction` from a pane
+    pub fn adjacent_pane(&self, pane_id: u64, direction: PaneDirection) -> Option<u64> {
+        let current_layout = &self.panes.get(&pane_id)?.layout;
 
-        // Resize existing pane
-        let existing_pane = self.panes.get_mut(&pane_id).unwrap();
-        match direction {
-            SplitDirection::Horizontal => {
-                existing_pane.resize(layout.x, layout.y, layout.width, layout.height / 2);
+        let mut closest_pane = None;
+        let mut min_distance = f32::INFINITY;
+
+        for (id, pane) in &self.panes {
+            if *id == pane_id {
+                continue;
             }
-            SplitDirection::Vertical => {
-                existing_pane.resize(layout.x, layout.y, layout.width / 2, layout.height);
+
+            let layout = &pane.layout;
+            let distance = match direction {
+                PaneDirection::Up => {
+                    if layout.y < current_layout.y {
+                        (current_layout.y - layout.y) as f32
+                            + (current_layout.x as f32 - layout.x as f32).abs()
+                    } else {
+                        f32::INFINITY
+                    }
+                }
+                PaneDirection::Down => {
+                    if layout.y > current_layout.y {
+                        (layout.y - current_layout.y) as f32
+                            + (current_layout.x as f32 - layout.x as f32).abs()
+                    } else {
+                        f32::INFINITY
+                    }
+                }
+                PaneDirection::Left => {
+                    if layout.x < current_layout.x {
+                        (current_layout.x - layout.x) as f32
+                            + (current_layout.y as f32 - layout.y as f32).abs()
+                    } else {
+                        f32::INFINITY
+                    }
+                }
+                PaneDirection::Right => {
+                    if layout.x > current_layout.x {
+                        (layout.x - current_layout.x) as f32
+                            + (current_layout.y as f32 - layout.y as f32).abs()
+                    } else {
+                        f32::INFINITY
+                    }
+                }
+            };
+
+            if distance < min_distance {
+                min_distance = distance;
+                closest_pane = Some(*id);
             }
         }
 
-        self.add_pane(new_pane);
-        Ok(new_pane_id)
+        closest_pane
     }
 }
 
@@ -477,13 +656,36 @@ pub struct MultiplexerConfig {
     pub prefix_modifier: Option<Modifier>,
 }
 
+/// Sessions saved in `dir`, sorted; none when it does not exist yet
+pub fn saved_session_names(dir: &Path) -> std::io::Result<Vec<String>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json")
+            && let Some(stem) = path.file_stem()
+        {
+            names.push(stem.to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
 impl Default for MultiplexerConfig {
     fn default() -> Self {
         Self {
             max_panes_per_window: 16,
             max_windows_per_session: 10,
             default_layout: LayoutAlgorithm::Tiled,
-            session_directory: PathBuf::from("~/.ferroterm/sessions"),
+            session_directory: ConfigManager::get_config_path()
+                .ok()
+                .and_then(|path| path.parent().map(|dir| dir.join("sessions")))
+                .unwrap_or_else(|| PathBuf::from("sessions")),
             prefix_key: Key::Char('b'),
             prefix_modifier: Some(Modifier::Ctrl),
         }
@@ -492,9 +694,6 @@ impl Default for MultiplexerConfig {
 
 pub struct Multiplexer {
     tty_engine: Arc<TtyEngine>,
-    renderer: Arc<RwLock<GpuRenderer>>,
-    input_processor: Arc<RwLock<InputProcessor>>,
-    config_manager: Arc<ConfigManager>,
     sessions: Arc<RwLock<HashMap<String, Session>>>,
     active_session: Arc<RwLock<Option<String>>>,
     config: MultiplexerConfig,
@@ -505,18 +704,13 @@ pub struct Multiplexer {
 }
 
 impl Multiplexer {
-    pub fn new(
-        tty_engine: Arc<TtyEngine>,
-        renderer: Arc<RwLock<GpuRenderer>>,
-        input_processor: Arc<RwLock<InputProcessor>>,
-        config_manager: Arc<ConfigManager>,
-    ) -> Self {
-        let config = MultiplexerConfig::default();
+    pub fn new(tty_engine: Arc<TtyEngine>) -> Self {
+        Self::with_config(tty_engine, MultiplexerConfig::default())
+    }
+
+    pub fn with_config(tty_engine: Arc<TtyEngine>, config: MultiplexerConfig) -> Self {
         Self {
             tty_engine,
-            renderer,
-            input_processor,
-            config_manager,
             sessions: Arc::new(RwLock::new(HashMap::new())),
             active_session: Arc::new(RwLock::new(None)),
             config,
@@ -551,6 +745,14 @@ impl Multiplexer {
         Ok(())
     }
 
+    /// Track a session whose panes run elsewhere, such as the open tabs
+    /// of a window, and make it the active one
+    pub async fn add_session(&self, session: Session) {
+        let name = session.name.clone();
+        self.sessions.write().await.insert(name.clone(), session);
+        *self.active_session.write().await = Some(name);
+    }
+
     pub async fn attach_session(&self, name: &str) -> Result<(), MultiplexerError> {
         if self.sessions.read().await.contains_key(name) {
             *self.active_session.write().await = Some(name.to_string());
@@ -579,53 +781,179 @@ impl Multiplexer {
         }
     }
 
+    /// Write the named session, or the active one under that name, to
+    /// the session directory with each pane's directory and command
     pub async fn save_session(&self, name: &str) -> Result<(), MultiplexerError> {
-        let sessions = self.sessions.read().await;
-        if let Some(session) = sessions.get(name) {
-            let session_path = self.config.session_directory.join(format!("{}.json", name));
-            std::fs::create_dir_all(&self.config.session_directory)?;
-
-            let serialized = serde_json::to_string_pretty(session)?;
-            std::fs::write(session_path, serialized)?;
+        let session_path = self.session_path(name)?;
+        let active = self.active_session.read().await.clone();
+        let mut session = {
+            let sessions = self.sessions.read().await;
+            sessions
+                .get(name)
+                .or_else(|| active.as_ref().and_then(|active| sessions.get(active)))
+                .cloned()
+                .ok_or_else(|| MultiplexerError::SessionNotFound {
+                    name: name.to_string(),
+                })?
+        };
+        session.name = name.to_string();
 
-            info!("Saved session: {}", name);
-            Ok(())
-        } else {
-            Err(MultiplexerError::SessionNotFound {
-                name: name.to_string(),
-            })
+        for window in session.windows.values_mut() {
+            for pane in window.panes.values_mut() {
+                if let Ok(Some(cwd)) = self.tty_engine.shell_cwd(pane.pty_id) {
+                    pane.working_directory = Some(PathBuf::from(cwd));
+                }
+                pane.command = match self.tty_engine.has_foreground_job(pane.pty_id) {
+                    Ok(true) => self.tty_engine.foreground_process(pane.pty_id)?,
+                    _ => None,
+                };
+            }
         }
-    }
 
-    pub async fn load_session(&self, name: &str) -> Result<(), MultiplexerError> {
-        let session_path = self.config.session_directory.join(format!("{}.json", name));
+        std::fs::create_dir_all(&self.config.session_directory)?;
+        let serialized = serde_json::to_string_pretty(&session)?;
+        std::fs::write(session_path, serialized)?;
 
-        if !session_path.exists() {
-            return Err(MultiplexerError::SessionNotFound {
-                name: name.to_string(),
-            });
-        }
+        info!("Saved session: {}", name);
+        Ok(())
+    }
 
-        let content = std::fs::read_to_string(session_path)?;
+    /// Recreate a saved session's panes with shells in their saved
+    /// directories, apply its layout and make it the active session
+    pub async fn restore_session(&self, name: &str) -> Result<(), MultiplexerError> {
+        let session_path = self.session_path(name)?;
+        let content = match std::fs::read_to_string(session_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(MultiplexerError::SessionNotFound {
+                    name: name.to_string(),
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
         let mut session: Session = serde_json::from_str(&content)?;
 
-        // Recreate PTYs for all panes
-        for window in session.windows.values_mut() {
-            for pane in window.panes.values_mut() {
-                let pty_config = PtyConfig::default();
-                pane.pty_id = self.tty_engine.create_pty(pty_config).await?;
+        let mut created = Vec::new();
+        if let Err(e) = self.spawn_session_panes(&mut session, &mut created).await {
+            for pty_id in created {
+                let _ = self.tty_engine.destroy_pty(pty_id).await;
             }
+            return Err(e);
         }
 
+        {
+            let mut next_pane_id = self.next_pane_id.lock().unwrap();
+            let mut next_window_id = self.next_window_id.lock().unwrap();
+            for window in session.windows.values() {
+                *next_window_id = (*next_window_id).max(window.id + 1);
+                for pane_id in window.panes.keys() {
+                    *next_pane_id = (*next_pane_id).max(pane_id + 1);
+                }
+            }
+        }
+
+        session.is_detached = false;
+        session.update_activity();
         self.sessions
             .write()
             .await
             .insert(name.to_string(), session);
-        info!("Loaded session: {}", name);
+        *self.active_session.write().await = Some(name.to_string());
+        info!("Restored session: {}", name);
+        Ok(())
+    }
+
+    async fn spawn_session_panes(
+        &self,
+        session: &mut Session,
+        created: &mut Vec<u64>,
+    ) -> Result<(), MultiplexerError> {
+        for window in session.windows.values_mut() {
+            window.recalculate_layout();
+            for pane in window.panes.values_mut() {
+                let working_dir = pane
+                    .working_directory
+                    .as_ref()
+                    .filter(|dir| dir.is_dir())
+                    .unwrap_or(&session.working_directory);
+                let pty_config = PtyConfig {
+                    cwd: Some(working_dir.clone()),
+                    rows: pane.layout.height as u16,
+                    cols: pane.layout.width as u16,
+                    ..PtyConfig::default()
+                };
+                pane.pty_id = self.tty_engine.create_pty(pty_config).await?;
+                created.push(pane.pty_id);
+            }
+        }
         Ok(())
     }
 
-    pub async fn split_pane(&self, direction: SplitDirection) -> Result<(), MultiplexerError> {
+    /// Names of the sessions saved in the session directory
+    pub fn list_sessions(&self) -> Result<Vec<String>, MultiplexerError> {
+        Ok(saved_session_names(&self.config.session_directory)?)
+    }
+
+    pub fn delete_session(&self, name: &str) -> Result<(), MultiplexerError> {
+        match std::fs::remove_file(self.session_path(name)?) {
+            Ok(()) => {
+                info!("Deleted session: {}", name);
+                Ok(())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(MultiplexerError::SessionNotFound {
+                    name: name.to_string(),
+                })
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Names become file names, so they can't reach outside the directory
+    fn session_path(&self, name: &str) -> Result<PathBuf, MultiplexerError> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(MultiplexerError::InvalidSessionName {
+                name: name.to_string(),
+            });
+        }
+        Ok(self.config.session_directory.join(format!("{}.json", name)))
+    }
+
+    /// Run a line typed at the prefix prompt, e.g. `:session save work`
+    pub async fn run_command(&self, line: &str) -> Result<(), MultiplexerError> {
+        let parser = CommandParser::new(String::new());
+        let command = parser
+            .parse_builtin(line)
+            .map_err(|_| MultiplexerError::CommandNotFound {
+                cmd: line.to_string(),
+            })?;
+        match command {
+            Command::Session(SessionAction::Save(name)) => self.save_session(&name).await,
+            Command::Session(SessionAction::Restore(name)) => self.restore_session(&name).await,
+            Command::Session(SessionAction::Delete(name)) => self.delete_session(&name),
+            Command::Session(SessionAction::List) => {
+                info!("Saved sessions: {}", self.list_sessions()?.join(", "));
+                Ok(())
+            }
+            _ => Err(MultiplexerError::CommandNotFound {
+                cmd: line.to_string(),
+            }),
+        }
+    }
+
+    /// Split a pane of the active window and start a shell in the new
+    /// half, which takes the focus. Every pane's PTY is resized to its new
+    /// rectangle.
+    pub async fn split_pane(
+        &self,
+        pane_id: u64,
+        direction: SplitDirection,
+    ) -> Result<u64, MultiplexerError> {
         let session_name = self.active_session.read().await.clone().ok_or_else(|| {
             MultiplexerError::SessionNotFound {
                 name: "no active session".to_string(),
@@ -633,31 +961,62 @@ impl Multiplexer {
         })?;
 
         let mut sessions = self.sessions.write().await;
-        if let Some(session) = sessions.get_mut(&session_name) {
-            if let Some(window) = session.get_active_window_mut() {
-                if let Some(active_pane) = window.get_active_pane() {
-                    if window.panes.len() >= self.config.max_panes_per_window {
-                        return Err(MultiplexerError::MaxPanesExceeded {
-                            max: self.config.max_panes_per_window,
-                        });
-                    }
-
-                    let active_pane_id = active_pane.id;
-                    let new_pane_id = window.split_pane(active_pane_id, direction)?;
-
-                    // Create PTY for new pane
-                    let pty_config = PtyConfig::default();
-                    let pty_id = self.tty_engine.create_pty(pty_config).await?;
-
-                    if let Some(new_pane) = window.panes.get_mut(&new_pane_id) {
-                        new_pane.pty_id = pty_id;
-                    }
+        let window = sessions
+            .get_mut(&session_name)
+            .and_then(|session| session.get_active_window_mut())
+            .ok_or(MultiplexerError::PaneNotFound { id: pane_id })?;
+        if window.panes.len() >= self.config.max_panes_per_window {
+            return Err(MultiplexerError::MaxPanesExceeded {
+                max: self.config.max_panes_per_window,
+            });
+        }
 
-                    info!("Split pane {} in direction {:?}", active_pane_id, direction);
-                }
+        let new_pane_id = window.split_pane(pane_id, direction)?;
+        let layout = window.panes[&new_pane_id].layout.clone();
+        let pty_config = PtyConfig {
+            rows: layout.height as u16,
+            cols: layout.width as u16,
+            ..PtyConfig::default()
+        };
+        let pty_id = match self.tty_engine.create_pty(pty_config).await {
+            Ok(pty_id) => pty_id,
+            Err(e) => {
+                window.remove_pane(new_pane_id);
+                return Err(e.into());
             }
+        };
+        if let Some(new_pane) = window.panes.get_mut(&new_pane_id) {
+            new_pane.pty_id = pty_id;
+        }
+        window.set_active_pane(new_pane_id)?;
+
+        for pane in window.panes.values() {
+            self.tty_engine.resize_pty(
+                pane.pty_id,
+                pane.layout.height as u16,
+                pane.layout.width as u16,
+            )?;
         }
 
+        info!("Split pane {} in direction {:?}", pane_id, direction);
+        Ok(new_pane_id)
+    }
+
+    async fn split_active_pane(&self, direction: SplitDirection) -> Result<(), MultiplexerError> {
+        let session_name = self.active_session.read().await.clone();
+        let active_pane_id = match session_name {
+            Some(name) => self
+                .sessions
+                .read()
+                .await
+                .get(&name)
+                .and_then(|session| session.get_active_window())
+                .and_then(|window| window.active_pane_id),
+            None => None,
+        };
+        if let Some(pane_id) = active_pane_id {
+            self.split_pane(pane_id, direction).await?;
+        }
         Ok(())
     }
 
@@ -669,83 +1028,19 @@ impl Multiplexer {
         })?;
 
         let mut sessions = self.sessions.write().await;
-        if let Some(session) = sessions.get_mut(&session_name) {
-            if let Some(window) = session.get_active_window_mut() {
-                if let Some(active_pane) = window.get_active_pane() {
-                    let next_pane_id = self.find_adjacent_pane(window, active_pane.id, direction);
+        if let Some(session) = sessions.get_mut(&session_name)
+            && let Some(window) = session.get_active_window_mut()
+                && let Some(active_pane) = window.get_active_pane() {
+                    let next_pane_id = window.adjacent_pane(active_pane.id, direction);
                     if let Some(next_id) = next_pane_id {
                         window.set_active_pane(next_id)?;
                         info!("Switched to pane {}", next_id);
                     }
                 }
-            }
-        }
 
         Ok(())
     }
 
-    fn find_adjacent_pane(
-        &self,
-        window: &Window,
-        current_pane_id: u64,
-        direction: PaneDirection,
-    ) -> Option<u64> {
-        let current_pane = window.panes.get(&current_pane_id)?;
-        let current_layout = &current_pane.layout;
-
-        let mut closest_pane = None;
-        let mut min_distance = f32::INFINITY;
-
-        for (pane_id, pane) in &window.panes {
-            if *pane_id == current_pane_id {
-                continue;
-            }
-
-            let layout = &pane.layout;
-            let distance = match direction {
-                PaneDirection::Up => {
-                    if layout.y < current_layout.y {
-                        (current_layout.y - layout.y) as f32
-                            + (current_layout.x as f32 - layout.x as f32).abs()
-                    } else {
-                        f32::INFINITY
-                    }
-                }
-                PaneDirection::Down => {
-                    if layout.y > current_layout.y {
-                        (layout.y - current_layout.y) as f32
-                            + (current_layout.x as f32 - layout.x as f32).abs()
-                    } else {
-                        f32::INFINITY
-                    }
-                }
-                PaneDirection::Left => {
-                    if layout.x < current_layout.x {
-                        (current_layout.x - layout.x) as f32
-                            + (current_layout.y as f32 - layout.y as f32).abs()
-                    } else {
-                        f32::INFINITY
-                    }
-                }
-                PaneDirection::Right => {
-                    if layout.x > current_layout.x {
-                        (layout.x - current_layout.x) as f32
-                            + (current_layout.y as f32 - layout.y as f32).abs()
-                    } else {
-                        f32::INFINITY
-                    }
-                }
-            };
-
-            if distance < min_distance {
-                min_distance = distance;
-                closest_pane = Some(*pane_id);
-            }
-        }
-
-        closest_pane
-    }
-
     pub async fn create_window(&self, name: Option<String>) -> Result<(), MultiplexerError> {
         let session_name = self.active_session.read().await.clone().ok_or_else(|| {
             MultiplexerError::SessionNotFound {
@@ -829,15 +1124,44 @@ impl Multiplexer {
     async fn handle_multiplexer_command(&self, event: KeyEvent) -> Result<(), MultiplexerError> {
         let mut command_buffer = self.command_buffer.write().await;
 
+        // After `:` keys go to the prompt until Enter or Escape
+        if !command_buffer.is_empty() {
+            match event.key {
+                Key::Enter => {
+                    let line = std::mem::take(&mut *command_buffer);
+                    *self.prefix_mode.write().await = false;
+                    drop(command_buffer);
+                    return self.run_command(&line).await;
+                }
+                Key::Escape => {
+                    command_buffer.clear();
+                    *self.prefix_mode.write().await = false;
+                }
+                Key::Backspace => {
+                    command_buffer.pop();
+                    if command_buffer.is_empty() {
+                        *self.prefix_mode.write().await = false;
+                    }
+                }
+                Key::Char(c) => command_buffer.push(c),
+                _ => {}
+            }
+            return Ok(());
+        }
+
         match event.key {
+            Key::Char(':') => {
+                // Command prompt
+                command_buffer.push(':');
+            }
             Key::Char('%') => {
                 // Vertical split
-                self.split_pane(SplitDirection::Vertical).await?;
+                self.split_active_pane(SplitDirection::Vertical).await?;
                 *self.prefix_mode.write().await = false;
             }
             Key::Char('"') => {
                 // Horizontal split
-                self.split_pane(SplitDirection::Horizontal).await?;
+                self.split_active_pane(SplitDirection::Horizontal).await?;
                 *self.prefix_mode.write().await = false;
             }
             Key::Char('c') => {
@@ -914,9 +1238,9 @@ impl Multiplexer {
         let session_name = self.active_session.read().await.clone();
         if let Some(session_name) = session_name {
             let sessions = self.sessions.read().await;
-            if let Some(session) = sessions.get(&session_name) {
-                if let Some(window) = session.get_active_window() {
-                    if let Some(pane) = window.get_active_pane() {
+            if let Some(session) = sessions.get(&session_name)
+                && let Some(window) = session.get_active_window()
+                    && let Some(pane) = window.get_active_pane() {
                         // Convert KeyEvent to string and send to PTY
                         if let Some(text) = &event.text {
                             self.tty_engine
@@ -924,8 +1248,6 @@ impl Multiplexer {
                                 .await?;
                         }
                     }
-                }
-            }
         }
         Ok(())
     }
@@ -940,14 +1262,13 @@ impl Multiplexer {
         let sessions = self.sessions.read().await;
         if let Some(session) = sessions.get(&session_name) {
             let window_ids: Vec<u64> = session.windows.keys().copied().collect();
-            if let Some(current_id) = session.active_window_id {
-                if let Some(current_index) = window_ids.iter().position(|&id| id == current_id) {
+            if let Some(current_id) = session.active_window_id
+                && let Some(current_index) = window_ids.iter().position(|&id| id == current_id) {
                     let next_index = (current_index + 1) % window_ids.len();
                     let next_id = window_ids[next_index];
                     drop(sessions);
                     self.switch_window(next_id).await?;
                 }
-            }
         }
         Ok(())
     }
@@ -962,8 +1283,8 @@ impl Multiplexer {
         let sessions = self.sessions.read().await;
         if let Some(session) = sessions.get(&session_name) {
             let window_ids: Vec<u64> = session.windows.keys().copied().collect();
-            if let Some(current_id) = session.active_window_id {
-                if let Some(current_index) = window_ids.iter().position(|&id| id == current_id) {
+            if let Some(current_id) = session.active_window_id
+                && let Some(current_index) = window_ids.iter().position(|&id| id == current_id) {
                     let prev_index = if current_index == 0 {
                         window_ids.len() - 1
                     } else {
@@ -973,7 +1294,6 @@ impl Multiplexer {
                     drop(sessions);
                     self.switch_window(prev_id).await?;
                 }
-            }
         }
         Ok(())
     }
@@ -992,9 +1312,9 @@ impl Multiplexer {
         })?;
 
         let mut sessions = self.sessions.write().await;
-        if let Some(session) = sessions.get_mut(&session_name) {
-            if let Some(window) = session.get_active_window_mut() {
-                if let Some(active_pane) = window.get_active_pane() {
+        if let Some(session) = sessions.get_mut(&session_name)
+            && let Some(window) = session.get_active_window_mut()
+                && let Some(active_pane) = window.get_active_pane() {
                     let pane_id = active_pane.id;
                     let pty_id = active_pane.pty_id;
 
@@ -1003,8 +1323,6 @@ impl Multiplexer {
 
                     info!("Killed pane {}", pane_id);
                 }
-            }
-        }
         Ok(())
     }
 
@@ -1014,97 +1332,13 @@ impl Multiplexer {
         Ok(())
     }
 
-    pub async fn render(&self) -> Result<(), MultiplexerError> {
-        let session_name = self.active_session.read().await.clone();
-        if let Some(session_name) = session_name {
-            let sessions = self.sessions.read().await;
-            if let Some(session) = sessions.get(&session_name) {
-                if let Some(window) = session.get_active_window() {
-                    // Render all panes in the active window
-                    for pane in window.panes.values() {
-                        self.render_pane(pane).await?;
-                    }
-                }
-            }
-        }
-        Ok(())
-    }
-
-    async fn render_pane(&self, pane: &Pane) -> Result<(), MultiplexerError> {
-        // Read data from PTY and update renderer
-        let mut buffer = [0u8; 4096];
-        match self
-            .tty_engine
-            .read_from_pty(pane.pty_id, &mut buffer)
-            .await
-        {
-            Ok(bytes_read) if bytes_read > 0 => {
-                let data = &buffer[..bytes_read];
-                let text = String::from_utf8_lossy(data);
-
-                // Update renderer grid for this pane's region
-                let mut renderer = self.renderer.write().await;
-                let grid = renderer.get_grid();
-
-                // Simple text rendering - in practice, would need more sophisticated parsing
-                let mut x = pane.layout.x;
-                let mut y = pane.layout.y;
-
-                for ch in text.chars() {
-                    if ch == '\n' {
-                        x = pane.layout.x;
-                        y += 1;
-                        if y >= pane.layout.y + pane.layout.height {
-                            break;
-                        }
-                    } else if ch != '\r' {
-                        if x < pane.layout.x + pane.layout.width
-                            && y < pane.layout.y + pane.layout.height
-                        {
-                            // Update grid cell
-                            let mut grid_write = grid.write();
-                            grid_write.set_cell(
-                                x,
-                                y,
-                                crate::renderer::TerminalCell {
-                                    character: ch,
-                                    foreground: [1.0, 1.0, 1.0, 1.0],
-                                    background: [0.0, 0.0, 0.0, 1.0],
-                                    bold: false,
-                                    italic: false,
-                                    underline: false,
-                                },
-                            );
-                        }
-                        x += 1;
-                        if x >= pane.layout.x + pane.layout.width {
-                            x = pane.layout.x;
-                            y += 1;
-                            if y >= pane.layout.y + pane.layout.height {
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
-            Ok(_) => {} // No data available
-            Err(e) => {
-                warn!("Failed to read from PTY {}: {}", pane.pty_id, e);
-            }
-        }
-
-        Ok(())
-    }
-
     pub async fn resize(&self, width: u32, height: u32) -> Result<(), MultiplexerError> {
         let session_name = self.active_session.read().await.clone();
         if let Some(session_name) = session_name {
             let mut sessions = self.sessions.write().await;
             if let Some(session) = sessions.get_mut(&session_name) {
                 for window in session.windows.values_mut() {
-                    window.layout.width = width;
-                    window.layout.height = height;
-                    window.recalculate_layout();
+                    window.resize(width, height);
 
                     // Resize all panes
                     for pane in window.panes.values() {
@@ -1120,14 +1354,14 @@ impl Multiplexer {
         Ok(())
     }
 
-    pub async fn list_sessions(&self) -> Vec<String> {
-        self.sessions.read().await.keys().cloned().collect()
-    }
-
     pub async fn get_active_session(&self) -> Option<String> {
         self.active_session.read().await.clone()
     }
 
+    pub async fn get_session(&self, name: &str) -> Option<Session> {
+        self.sessions.read().await.get(name).cloned()
+    }
+
     fn get_next_pane_id(&self) -> u64 {
         let mut id = self.next_pane_id.lock().unwrap();
         let current = *id;
@@ -1181,11 +1415,9 @@ pub enum PaneDirection {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Arc;
-    use tokio::sync::RwLock;
 
     // Mock implementations for testing
-    // Note: In practice, would need proper mocks for TtyEngine, GpuRenderer, etc.
+    // Note: In practice, would need proper mocks for TtyEngine etc.
 
     #[tokio::test]
     async fn test_session_creation() {
@@ -1230,4 +1462,89 @@ mod tests {
         assert_eq!(window.layout.children[0].width, 40);
         assert_eq!(window.layout.children[1].width, 40);
     }
+
+    #[test]
+    fn test_split_pane_tree() {
+        let mut window = Window::new(1, "Test".to_string(), 81, 24);
+        window.add_pane(Pane::new(1, 100, 0, 0, 81, 24));
+
+        // Side by side, with a border column between the halves
+        let right = window.split_pane(1, SplitDirection::Vertical).unwrap();
+        let rect = |window: &Window, id| {
+            let layout = &window.panes[&id].layout;
+            (layout.x, layout.y, layout.width, layout.height)
+        };
+        assert_eq!(rect(&window, 1), (0, 0, 40, 24));
+        assert_eq!(rect(&window, right), (41, 0, 40, 24));
+
+        // Splitting the right half stacks a pane below it
+        let below = window.split_pane(right, SplitDirection::Horizontal).unwrap();
+        assert_eq!(rect(&window, right), (41, 0, 40, 11));
+        assert_eq!(rect(&window, below), (41, 12, 40, 12));
+        let order: Vec<u64> = window.ordered_panes().iter().map(|pane| pane.id).collect();
+        assert_eq!(order, vec![1, right, below]);
+
+        assert_eq!(window.adjacent_pane(below, PaneDirection::Left), Some(1));
+        assert_eq!(window.adjacent_pane(below, PaneDirection::Up), Some(right));
+        assert_eq!(window.adjacent_pane(1, PaneDirection::Left), None);
+
+        // Resizing keeps the splits; closing a pane gives its sibling the room
+        window.resize(101, 30);
+        assert_eq!(rect(&window, right), (51, 0, 50, 14));
+        window.remove_pane(right);
+        assert_eq!(rect(&window, below), (51, 0, 50, 30));
+        assert_eq!(rect(&window, 1), (0, 0, 50, 30));
+    }
+
+    #[test]
+    fn test_split_needs_room() {
+        let mut window = Window::new(1, "Test".to_string(), 2, 24);
+        window.add_pane(Pane::new(1, 100, 0, 0, 2, 24));
+        assert!(matches!(
+            window.split_pane(1, SplitDirection::Vertical),
+            Err(MultiplexerError::InvalidLayout { .. })
+        ));
+        assert!(window.split_pane(1, SplitDirection::Horizontal).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_session_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = MultiplexerConfig {
+            session_directory: dir.path().join("sessions"),
+            ..MultiplexerConfig::default()
+        };
+        let multiplexer = Multiplexer::with_config(Arc::new(TtyEngine::new()), config);
+        assert!(multiplexer.list_sessions().unwrap().is_empty());
+
+        let mut window = Window::new(1, "Main".to_string(), 80, 24);
+        window.add_pane(Pane::new(1, 100, 0, 0, 80, 24));
+        window.split_pane(1, SplitDirection::Vertical).unwrap();
+        let mut session = Session::new("work".to_string(), PathBuf::from("/tmp"));
+        session.add_window(window);
+        multiplexer
+            .sessions
+            .write()
+            .await
+            .insert("work".to_string(), session);
+
+        multiplexer.save_session("work").await.unwrap();
+        assert_eq!(multiplexer.list_sessions().unwrap(), vec!["work"]);
+        let saved: Session = serde_json::from_str(
+            &std::fs::read_to_string(dir.path().join("sessions/work.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(saved.windows[&1].ordered_panes().len(), 2);
+
+        assert!(matches!(
+            multiplexer.save_session("../escape").await,
+            Err(MultiplexerError::InvalidSessionName { .. })
+        ));
+        multiplexer.run_command(":session delete work").await.unwrap();
+        assert!(multiplexer.list_sessions().unwrap().is_empty());
+        assert!(matches!(
+            multiplexer.delete_session("work"),
+            Err(MultiplexerError::SessionNotFound { .. })
+        ));
+    }
 }
@@ -0,0 +1,605 @@
+use crate::terminal_parser::{PromptMark, TerminalAction, TerminalParser};
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// Records kept per session when no limit is given
+pub const DEFAULT_MAX_RECORDS: usize = 256;
+
+/// Raw bytes kept for one record's text; a longer output keeps its tail
+const MAX_SEGMENT_BYTES: usize = 256 * 1024;
+
+/// Bytes kept of the line being written, for prompt detection
+const MAX_LINE_BYTES: usize = 1024;
+
+/// Line endings that look like a shell waiting for input when a session
+/// has no integration marks
+const PROMPT_SUFFIXES: &[&str] = &["$ ", "# ", "% ", "> ", "❯ "];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// The prompt, however many times it was redrawn
+    PromptRedraw,
+    /// The command line as typed
+    CommandEcho,
+    Output,
+    /// The command finished; carries the exit status if reported
+    Completion,
+}
+
+/// One closed segment of a session's output
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputRecord {
+    pub command_id: u64,
+    pub kind: RecordKind,
+    /// Offsets into everything the PTY has produced
+    pub bytes: Range<u64>,
+    /// Lines of that output, counted by line feeds
+    pub lines: Range<u64>,
+    /// Plain text, escapes removed
+    pub text: String,
+    pub exit: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Phase {
+    Idle,
+    Prompt {
+        start: u64,
+        line: u64,
+    },
+    /// `start` is where typing began, after the prompt
+    Echo {
+        prompt: OutputRecord,
+        start: u64,
+        line: u64,
+    },
+    Output {
+        start: u64,
+        line: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Scan {
+    Ground,
+    Escape(u64),
+    Csi,
+    Osc(u64, Vec<u8>),
+    OscEscape(u64, Vec<u8>),
+}
+
+/// Splits one PTY's output into prompt, echo, output and completion
+/// records. OSC 133 marks delimit them when the shell sends any; otherwise
+/// a line ending like a prompt when output pauses starts a new command.
+#[derive(Debug, Clone)]
+pub struct OutputRecords {
+    max_records: usize,
+    records: VecDeque<OutputRecord>,
+    closed: VecDeque<OutputRecord>,
+    last_output: Option<String>,
+    next_command: u64,
+    phase: Phase,
+    scan: Scan,
+    marks_seen: bool,
+    offset: u64,
+    line: u64,
+    at_line_start: bool,
+    line_start: u64,
+    line_bytes: Vec<u8>,
+    /// Raw bytes since the last boundary, starting at `segment_start`
+    segment: Vec<u8>,
+    segment_start: u64,
+}
+
+impl Default for OutputRecords {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_RECORDS)
+    }
+}
+
+impl OutputRecords {
+    pub fn new(max_records: usize) -> Self {
+        Self {
+            max_records: max_records.max(1),
+            records: VecDeque::new(),
+            closed: VecDeque::new(),
+            last_output: None,
+            next_command: 0,
+            phase: Phase::Idle,
+            scan: Scan::Ground,
+            marks_seen: false,
+            offset: 0,
+            line: 0,
+            at_line_start: true,
+            line_start: 0,
+            line_bytes: Vec::new(),
+            segment: Vec::new(),
+            segment_start: 0,
+        }
+    }
+
+    /// Whether the shell sends integration marks
+    pub fn uses_marks(&self) -> bool {
+        self.marks_seen
+    }
+
+    /// The most recent records, oldest first
+    pub fn records(&self) -> impl Iterator<Item = &OutputRecord> {
+        self.records.iter()
+    }
+
+    /// Plain-text output of the last completed command
+    pub fn last_output(&self) -> Option<&str> {
+        self.last_output.as_deref()
+    }
+
+    /// Records closed since the last call, in order
+    pub fn take_closed(&mut self) -> Vec<OutputRecord> {
+        self.closed.drain(..).collect()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        for &byte in data {
+            let offset = self.offset;
+            self.offset += 1;
+            self.segment.push(byte);
+            if self.segment.len() > MAX_SEGMENT_BYTES {
+                let excess = self.segment.len() - MAX_SEGMENT_BYTES / 2;
+                self.segment.drain(..excess);
+                self.segment_start += excess as u64;
+            }
+
+            let printable = self.scan_byte(byte, offset);
+            if byte == b'\n' {
+                self.line += 1;
+                self.at_line_start = true;
+                self.line_start = self.offset;
+                self.line_bytes.clear();
+                if !self.marks_seen {
+                    self.heuristic_line_end();
+                }
+            } else {
+                if printable && byte != b'\r' {
+                    self.at_line_start = false;
+                }
+                if self.line_bytes.len() < MAX_LINE_BYTES {
+                    self.line_bytes.push(byte);
+                }
+            }
+        }
+        if !self.marks_seen {
+            self.heuristic_prompt();
+        }
+    }
+
+    /// Track escape sequences, acting on complete OSC 133 marks. Returns
+    /// whether the byte is outside any sequence.
+    fn scan_byte(&mut self, byte: u8, offset: u64) -> bool {
+        match std::mem::replace(&mut self.scan, Scan::Ground) {
+            Scan::Ground if byte == 0x1b => self.scan = Scan::Escape(offset),
+            Scan::Ground => return true,
+            Scan::Escape(start) => match byte {
+                b']' => self.scan = Scan::Osc(start, Vec::new()),
+                b'[' => self.scan = Scan::Csi,
+                _ => {}
+            },
+            Scan::Csi => {
+                if !(0x40..=0x7e).contains(&byte) {
+                    self.scan = Scan::Csi;
+                }
+            }
+            Scan::Osc(start, mut body) => match byte {
+                0x07 => self.osc(&body, start),
+                0x1b => self.scan = Scan::OscEscape(start, body),
+                _ => {
+                    // Only the start of a long sequence matters
+                    if body.len() < 256 {
+                        body.push(byte);
+                    }
+                    self.scan = Scan::Osc(start, body);
+                }
+            },
+            Scan::OscEscape(start, body) => {
+                if byte == b'\\' {
+                    self.osc(&body, start);
+                }
+            }
+        }
+        false
+    }
+
+    fn osc(&mut self, body: &[u8], start: u64) {
+        let Some(mark) = body.strip_prefix(b"133;").and_then(PromptMark::parse) else {
+            return;
+        };
+        if !self.marks_seen {
+            // Anything the heuristics had open is superseded
+            self.marks_seen = true;
+            self.phase = Phase::Idle;
+        }
+        self.mark(mark, start, self.offset);
+    }
+
+    fn mark(&mut self, mark: PromptMark, start: u64, end: u64) {
+        match (mark, std::mem::replace(&mut self.phase, Phase::Idle)) {
+            // A prompt redrawn before the command was submitted is still
+            // the same prompt
+            (PromptMark::PromptStart, Phase::Prompt { start, line }) => {
+                self.restart_segment(end);
+                self.phase = Phase::Prompt { start, line };
+            }
+            (PromptMark::PromptStart, Phase::Echo { prompt, .. }) => {
+                self.restart_segment(end);
+                self.phase = Phase::Prompt {
+                    start: prompt.bytes.start,
+                    line: prompt.lines.start,
+                };
+            }
+            (PromptMark::PromptStart, phase) => {
+                if let Phase::Output {
+                    start: output,
+                    line,
+                } = phase
+                {
+                    // No `D` before the next prompt
+                    self.finish(output, line, start, None);
+                }
+                self.restart_segment(start);
+                self.phase = Phase::Prompt {
+                    start,
+                    line: self.line,
+                };
+            }
+            (
+                PromptMark::CommandStart,
+                Phase::Prompt {
+                    start: prompt,
+                    line,
+                },
+            ) => {
+                let prompt = self.record(RecordKind::PromptRedraw, prompt, line, end, None);
+                self.restart_segment(end);
+                self.phase = Phase::Echo {
+                    prompt,
+                    start: end,
+                    line: self.line,
+                };
+            }
+            (PromptMark::CommandStart, Phase::Echo { mut prompt, .. }) => {
+                prompt.bytes.end = end;
+                self.restart_segment(end);
+                self.phase = Phase::Echo {
+                    prompt,
+                    start: end,
+                    line: self.line,
+                };
+            }
+            (
+                PromptMark::OutputStart,
+                Phase::Echo {
+                    prompt,
+                    start: echo,
+                    line,
+                },
+            ) => {
+                let echo = self.record(RecordKind::CommandEcho, echo, line, start, None);
+                self.close(prompt);
+                self.close(echo);
+                self.open_output(end);
+            }
+            (
+                PromptMark::OutputStart,
+                Phase::Prompt {
+                    start: prompt,
+                    line,
+                },
+            ) => {
+                let prompt = self.record(RecordKind::PromptRedraw, prompt, line, start, None);
+                self.close(prompt);
+                self.open_output(end);
+            }
+            (PromptMark::OutputStart, Phase::Idle) => self.open_output(end),
+            (
+                PromptMark::CommandFinished(exit),
+                Phase::Output {
+                    start: output,
+                    line,
+                },
+            ) => {
+                self.finish(output, line, start, exit);
+                self.restart_segment(end);
+            }
+            // `D` with nothing run, as some shells send before every prompt
+            (_, phase) => self.phase = phase,
+        }
+    }
+
+    fn open_output(&mut self, start: u64) {
+        self.restart_segment(start);
+        self.phase = Phase::Output {
+            start,
+            line: self.line,
+        };
+    }
+
+    /// Close the running command's output at `end`
+    fn finish(&mut self, start: u64, line: u64, end: u64, exit: Option<i32>) {
+        let output = self.record(RecordKind::Output, start, line, end, None);
+        self.last_output = Some(output.text.clone());
+        self.close(output);
+        let completion = OutputRecord {
+            command_id: self.next_command,
+            kind: RecordKind::Completion,
+            bytes: end..end,
+            lines: self.line..self.line,
+            text: String::new(),
+            exit,
+        };
+        self.close(completion);
+        self.next_command += 1;
+    }
+
+    /// A record of the segment's bytes from `start` to `end`
+    fn record(
+        &self,
+        kind: RecordKind,
+        start: u64,
+        line: u64,
+        end: u64,
+        exit: Option<i32>,
+    ) -> OutputRecord {
+        let from = start.saturating_sub(self.segment_start) as usize;
+        let to = end.saturating_sub(self.segment_start) as usize;
+        let raw = &self.segment[from.min(self.segment.len())..to.min(self.segment.len())];
+        let mut text = plain_text(raw);
+        if kind != RecordKind::Output {
+            text.truncate(text.trim_end().len());
+        }
+        let end_line = if self.at_line_start {
+            self.line
+        } else {
+            self.line + 1
+        };
+        OutputRecord {
+            command_id: self.next_command,
+            kind,
+            bytes: start..end,
+            lines: line..end_line.max(line),
+            text,
+            exit,
+        }
+    }
+
+    fn close(&mut self, record: OutputRecord) {
+        for queue in [&mut self.records, &mut self.closed] {
+            if queue.len() == self.max_records {
+                queue.pop_front();
+            }
+            queue.push_back(record.clone());
+        }
+    }
+
+    /// Drop segment bytes before `start`
+    fn restart_segment(&mut self, start: u64) {
+        let skip = start.saturating_sub(self.segment_start) as usize;
+        self.segment.drain(..skip.min(self.segment.len()));
+        self.segment_start = start;
+    }
+
+    /// Without marks, the line typed after a detected prompt is its echo
+    fn heuristic_line_end(&mut self) {
+        match std::mem::replace(&mut self.phase, Phase::Idle) {
+            Phase::Echo {
+                prompt,
+                start,
+                line,
+            } => {
+                let echo = self.record(RecordKind::CommandEcho, start, line, self.offset, None);
+                self.close(prompt);
+                self.close(echo);
+                self.open_output(self.offset);
+            }
+            phase => self.phase = phase,
+        }
+    }
+
+    /// Without marks, output that stops on a prompt-like line ends the
+    /// previous command
+    fn heuristic_prompt(&mut self) {
+        if self.line_bytes.is_empty() || self.scan != Scan::Ground {
+            return;
+        }
+        let line = plain_text(&self.line_bytes);
+        if !PROMPT_SUFFIXES.iter().any(|suffix| line.ends_with(suffix)) {
+            return;
+        }
+        let prompt_start = self.line_start;
+        match std::mem::replace(&mut self.phase, Phase::Idle) {
+            // Typing continues on an already detected prompt, or it was
+            // redrawn in place
+            Phase::Echo { mut prompt, .. } if prompt.bytes.start == prompt_start => {
+                prompt.bytes.end = self.offset;
+                self.phase = Phase::Echo {
+                    prompt,
+                    start: self.offset,
+                    line: self.line,
+                };
+                return;
+            }
+            Phase::Output { start, line } => self.finish(start, line, prompt_start, None),
+            _ => {}
+        }
+        let prompt = self.record(
+            RecordKind::PromptRedraw,
+            prompt_start,
+            self.line,
+            self.offset,
+            None,
+        );
+        self.restart_segment(self.offset);
+        self.phase = Phase::Echo {
+            prompt,
+            start: self.offset,
+            line: self.line,
+        };
+    }
+}
+
+/// The text a stretch of output prints, with escape sequences removed.
+/// A carriage return not followed by a line feed overwrites its line, as
+/// progress bars expect.
+pub fn plain_text(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    let mut line_start = 0;
+    let mut returned = false;
+    for action in TerminalParser::new().feed(bytes) {
+        if returned
+            && !matches!(
+                action,
+                TerminalAction::Newline | TerminalAction::CarriageReturn
+            )
+        {
+            text.truncate(line_start);
+        }
+        returned = false;
+        match action {
+            TerminalAction::PrintChar(ch) => text.push(ch),
+            TerminalAction::Tab => text.push('\t'),
+            TerminalAction::Newline => {
+                text.push('\n');
+                line_start = text.len();
+            }
+            TerminalAction::CarriageReturn => returned = true,
+            TerminalAction::Backspace if text.len() > line_start => {
+                text.pop();
+            }
+            _ => {}
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `session` a few bytes at a time, as reads split it
+    fn replay(session: &str) -> OutputRecords {
+        let mut records = OutputRecords::default();
+        for chunk in session.as_bytes().chunks(7) {
+            records.feed(chunk);
+        }
+        records
+    }
+
+    fn kinds(records: &[OutputRecord]) -> Vec<(u64, RecordKind, &str)> {
+        records
+            .iter()
+            .map(|record| (record.command_id, record.kind, record.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_marked_sessions() {
+        use RecordKind::*;
+        let bash = concat!(
+            "\x1b]133;D\x07\x1b]133;A\x07\x1b[32muser@host\x1b[0m:~$ \x1b]133;B\x07",
+            "ls\r\n\x1b]133;C\x07a.txt  b.txt\r\n\x1b]133;D;0\x07",
+            "\x1b]133;A\x07user@host:~$ \x1b]133;B\x07false\r\n\x1b]133;C\x07\x1b]133;D;1\x07",
+            "\x1b]133;A\x07user@host:~$ \x1b]133;B\x07",
+        );
+        // powerlevel10k redraws the prompt when its async segments arrive
+        let zsh = concat!(
+            "\x1b]133;A\x1b\\~/src \x1b]133;B\x1b\\",
+            "\r\x1b[K\x1b]133;A\x1b\\~/src  main \x1b]133;B\x1b\\gi",
+            "\r\x1b[K\x1b]133;A\x1b\\~/src  main ✔ \x1b]133;B\x1b\\git log -1\r\n",
+            "\x1b]133;C\x1b\\commit 1234\r\n\x1b]133;D;0\x1b\\",
+        );
+        let fish = concat!(
+            "\x1b]133;A;click_events=1\x07~> \x1b]133;B\x07echo hi\r\n",
+            "\x1b]133;C;cmdline_url=echo%20hi\x07hi\r\n\x1b]133;D;0\x07",
+        );
+
+        let mut session = replay(bash);
+        let records = session.take_closed();
+        assert_eq!(
+            kinds(&records),
+            [
+                (0, PromptRedraw, "user@host:~$"),
+                (0, CommandEcho, "ls"),
+                (0, Output, "a.txt  b.txt\n"),
+                (0, Completion, ""),
+                (1, PromptRedraw, "user@host:~$"),
+                (1, CommandEcho, "false"),
+                (1, Output, ""),
+                (1, Completion, ""),
+            ]
+        );
+        assert_eq!((records[3].exit, records[7].exit), (Some(0), Some(1)));
+        assert_eq!(records[1].lines, 0..1);
+        assert_eq!(records[2].lines, 1..2);
+        assert_eq!(session.last_output(), Some(""));
+        assert!(session.take_closed().is_empty());
+
+        let mut session = replay(zsh);
+        let records = session.take_closed();
+        assert_eq!(
+            kinds(&records),
+            [
+                (0, PromptRedraw, "~/src  main ✔"),
+                (0, CommandEcho, "git log -1"),
+                (0, Output, "commit 1234\n"),
+                (0, Completion, ""),
+            ]
+        );
+        // The prompt record covers every redraw
+        assert_eq!(records[0].bytes.start, 0);
+
+        let mut session = replay(fish);
+        assert_eq!(session.take_closed().len(), 4);
+        assert_eq!(session.last_output(), Some("hi\n"));
+        assert!(session.uses_marks());
+    }
+
+    #[test]
+    fn test_heuristic_session() {
+        use RecordKind::*;
+        let mut session = OutputRecords::default();
+        session.feed(b"Last login: Mon\r\nhost$ ");
+        // Typed one key at a time
+        for byte in b"make\r\n" {
+            session.feed(&[*byte]);
+        }
+        session.feed(b"cc -o app main.c\r\n");
+        session.feed(b"host$ ");
+
+        assert!(!session.uses_marks());
+        let records = session.take_closed();
+        assert_eq!(
+            kinds(&records),
+            [
+                (0, PromptRedraw, "host$"),
+                (0, CommandEcho, "make"),
+                (0, Output, "cc -o app main.c\n"),
+                (0, Completion, ""),
+            ]
+        );
+        assert_eq!(records[3].exit, None);
+        assert_eq!(session.last_output(), Some("cc -o app main.c\n"));
+    }
+
+    #[test]
+    fn test_plain_text_and_bounds() {
+        assert_eq!(
+            plain_text(b"\x1b[1mok\x1b[0m\r\n10%\r50%\r100%\r\n"),
+            "ok\n100%\n"
+        );
+
+        let mut session = OutputRecords::new(3);
+        for _ in 0..5 {
+            session.feed(b"\x1b]133;C\x07x\n\x1b]133;D;0\x07");
+        }
+        let ids: Vec<u64> = session.records().map(|record| record.command_id).collect();
+        assert_eq!(ids, [3, 4, 4]);
+    }
+}
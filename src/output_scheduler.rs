@@ -0,0 +1,401 @@
+use crate::config::UiConfig;
+use crate::terminal::TerminalState;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct OutputSchedulerConfig {
+    /// Parsing time allowed per frame before yielding to input and rendering
+    pub slice_budget: Duration,
+    /// Parsing time per frame while catching up, when nothing is rendered
+    pub catch_up_budget: Duration,
+    /// Backlog above which intermediate frames are skipped
+    pub catch_up_threshold: usize,
+    /// Backlog at or below which normal rendering resumes
+    pub catch_up_exit: usize,
+    /// Backlog at which the PTY reader stops reading, leaving the rest in
+    /// the kernel buffer
+    pub max_backlog: usize,
+    /// Largest piece parsed between budget checks
+    pub piece_size: usize,
+}
+
+impl Default for OutputSchedulerConfig {
+    fn default() -> Self {
+        Self {
+            slice_budget: Duration::from_millis(2),
+            catch_up_budget: Duration::from_millis(8),
+            catch_up_threshold: 4 * 1024 * 1024,
+            catch_up_exit: 256 * 1024,
+            max_backlog: 32 * 1024 * 1024,
+            piece_size: 16 * 1024,
+        }
+    }
+}
+
+impl OutputSchedulerConfig {
+    pub fn from_config(ui: &UiConfig) -> Self {
+        let slice_budget = Duration::from_millis(ui.output_slice_ms.max(1) as u64);
+        Self {
+            slice_budget,
+            catch_up_budget: slice_budget * 4,
+            ..Self::default()
+        }
+    }
+}
+
+/// Anything PTY output is parsed into
+pub trait OutputSink {
+    fn consume(&mut self, bytes: &[u8]);
+}
+
+impl OutputSink for TerminalState {
+    fn consume(&mut self, bytes: &[u8]) {
+        self.feed_bytes(bytes);
+    }
+}
+
+/// PTY output that has been read but not parsed. The reader pushes, the UI
+/// thread drains it a slice at a time.
+#[derive(Debug, Default)]
+pub struct OutputBacklog {
+    chunks: Mutex<VecDeque<Vec<u8>>>,
+    bytes: AtomicUsize,
+}
+
+impl OutputBacklog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.chunks.lock().unwrap().push_back(data.to_vec());
+        self.bytes.fetch_add(data.len(), Ordering::AcqRel);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn pop(&self) -> Option<Vec<u8>> {
+        let chunk = self.chunks.lock().unwrap().pop_front()?;
+        self.bytes.fetch_sub(chunk.len(), Ordering::AcqRel);
+        Some(chunk)
+    }
+}
+
+/// Outcome of one frame's worth of output processing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SliceReport {
+    pub processed: usize,
+    pub elapsed: Duration,
+    /// Bytes still waiting after the slice
+    pub backlog: usize,
+    pub catch_up: bool,
+    /// False while catching up: the grid is mid-flood and not worth drawing
+    pub render: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutputMetrics {
+    pub backlog_bytes: usize,
+    /// Share of the last slice's budget spent parsing, 0.0-1.0
+    pub slice_utilization: f32,
+    pub bytes_per_sec: f64,
+    pub catch_up: bool,
+}
+
+/// Parses PTY output in bounded time slices so a flood never starves input
+/// or rendering. While the backlog is large it switches to catch-up mode:
+/// larger slices and no intermediate frames, like synchronized output.
+#[derive(Debug)]
+pub struct OutputScheduler {
+    config: OutputSchedulerConfig,
+    /// Remainder of a chunk cut off by the end of a slice
+    partial: Option<(Vec<u8>, usize)>,
+    catch_up: bool,
+    metrics: OutputMetrics,
+    window_start: Option<Instant>,
+    window_bytes: usize,
+}
+
+impl OutputScheduler {
+    pub fn new(config: OutputSchedulerConfig) -> Self {
+        Self {
+            config,
+            partial: None,
+            catch_up: false,
+            metrics: OutputMetrics::default(),
+            window_start: None,
+            window_bytes: 0,
+        }
+    }
+
+    pub fn config(&self) -> &OutputSchedulerConfig {
+        &self.config
+    }
+
+    /// Whether the PTY reader may read more into `backlog`
+    pub fn has_room(&self, backlog: &OutputBacklog) -> bool {
+        backlog.len() < self.config.max_backlog
+    }
+
+    /// Unparsed bytes, including any held back from the last slice
+    pub fn pending(&self, backlog: &OutputBacklog) -> usize {
+        backlog.len()
+            + self
+                .partial
+                .as_ref()
+                .map_or(0, |(chunk, offset)| chunk.len() - offset)
+    }
+
+    pub fn is_catching_up(&self) -> bool {
+        self.catch_up
+    }
+
+    pub fn metrics(&self) -> OutputMetrics {
+        self.metrics
+    }
+
+    /// Status text while catching up, e.g. "… processing 14 MB/s"
+    pub fn indicator(&self) -> Option<String> {
+        self.catch_up.then(|| {
+            format!(
+                "… processing {:.0} MB/s",
+                self.metrics.bytes_per_sec / (1024.0 * 1024.0)
+            )
+        })
+    }
+
+    /// Parse output until the frame's budget is spent or the backlog is empty
+    pub fn run_slice(
+        &mut self,
+        backlog: &OutputBacklog,
+        sink: &mut (impl OutputSink + ?Sized),
+    ) -> SliceReport {
+        let pending = self.pending(backlog);
+        if pending > self.config.catch_up_threshold {
+            self.catch_up = true;
+        }
+        let budget = if self.catch_up {
+            self.config.catch_up_budget
+        } else {
+            self.config.slice_budget
+        };
+
+        let start = Instant::now();
+        let mut processed = 0;
+        while start.elapsed() < budget {
+            let (chunk, offset) = match self.partial.take() {
+                Some(partial) => partial,
+                None => match backlog.pop() {
+                    Some(chunk) => (chunk, 0),
+                    None => break,
+                },
+            };
+            let end = (offset + self.config.piece_size).min(chunk.len());
+            sink.consume(&chunk[offset..end]);
+            processed += end - offset;
+            if end < chunk.len() {
+                self.partial = Some((chunk, end));
+            }
+        }
+        let elapsed = start.elapsed();
+
+        let backlog_bytes = self.pending(backlog);
+        if backlog_bytes <= self.config.catch_up_exit {
+            self.catch_up = false;
+        }
+        self.record(processed, elapsed, budget, backlog_bytes, start);
+
+        SliceReport {
+            processed,
+            elapsed,
+            backlog: backlog_bytes,
+            catch_up: self.catch_up,
+            render: !self.catch_up,
+        }
+    }
+
+    fn record(
+        &mut self,
+        processed: usize,
+        elapsed: Duration,
+        budget: Duration,
+        backlog_bytes: usize,
+        now: Instant,
+    ) {
+        self.metrics.backlog_bytes = backlog_bytes;
+        self.metrics.slice_utilization = (elapsed.as_secs_f32() / budget.as_secs_f32()).min(1.0);
+        self.metrics.catch_up = self.catch_up;
+
+        // Throughput over roughly the last second of slices
+        let window_start = *self.window_start.get_or_insert(now);
+        self.window_bytes += processed;
+        let span = now.duration_since(window_start) + elapsed;
+        if span >= Duration::from_millis(250) {
+            self.metrics.bytes_per_sec = self.window_bytes as f64 / span.as_secs_f64();
+        }
+        if span >= Duration::from_secs(1) {
+            self.window_start = Some(now);
+            self.window_bytes = 0;
+        }
+    }
+}
+
+impl Default for OutputScheduler {
+    fn default() -> Self {
+        Self::new(OutputSchedulerConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_only::PtySink;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, Mutex};
+
+    const CHUNK: usize = 64 * 1024;
+    const FLOOD: usize = 200 * 1024 * 1024;
+    /// Parse cost of `SlowParser`
+    const NANOS_PER_BYTE: u64 = 10;
+
+    fn pattern(position: usize) -> u8 {
+        (position / CHUNK % 251) as u8
+    }
+
+    /// Checks ordering at every piece and burns time like a real parser
+    /// (about 100 MB/s)
+    #[derive(Default)]
+    struct SlowParser {
+        consumed: usize,
+    }
+
+    impl OutputSink for SlowParser {
+        fn consume(&mut self, bytes: &[u8]) {
+            assert_eq!(bytes[0], pattern(self.consumed), "output lost or reordered");
+            assert_eq!(
+                bytes[bytes.len() - 1],
+                pattern(self.consumed + bytes.len() - 1)
+            );
+            self.consumed += bytes.len();
+            let cost = Duration::from_nanos(bytes.len() as u64 * NANOS_PER_BYTE);
+            let start = Instant::now();
+            while start.elapsed() < cost {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct PtyChannel(Mutex<Vec<(Instant, Vec<u8>)>>);
+
+    impl PtySink for PtyChannel {
+        fn send(&self, _pty_id: u64, data: &[u8]) {
+            self.0.lock().unwrap().push((Instant::now(), data.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_flood_keeps_input_responsive() {
+        let config = OutputSchedulerConfig {
+            max_backlog: 16 * 1024 * 1024,
+            ..OutputSchedulerConfig::default()
+        };
+        // A slice stops at the first piece boundary past its budget. Bound
+        // it by parse cost, not wall time, which also counts this thread
+        // being descheduled.
+        let max_slice_bytes = (config.catch_up_budget.as_nanos() as u64 / NANOS_PER_BYTE) as usize
+            + config.piece_size;
+        let mut scheduler = OutputScheduler::new(config);
+        let backlog = Arc::new(OutputBacklog::new());
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let max_backlog = scheduler.config().max_backlog;
+
+        // The flooding process: stops once ^C reaches it
+        let producer = {
+            let backlog = backlog.clone();
+            let interrupted = interrupted.clone();
+            std::thread::spawn(move || {
+                let mut produced = 0;
+                while produced < FLOOD && !interrupted.load(Ordering::Acquire) {
+                    if backlog.len() >= max_backlog {
+                        std::thread::sleep(Duration::from_micros(200));
+                        continue;
+                    }
+                    backlog.push(&vec![pattern(produced); CHUNK]);
+                    produced += CHUNK;
+                }
+                produced
+            })
+        };
+
+        // The UI loop: one output slice, then input, per frame
+        let pty = PtyChannel::default();
+        let mut parser = SlowParser::default();
+        let mut key_times = Vec::new();
+        let mut saw_catch_up = false;
+        let mut saw_indicator = false;
+        let mut frame = 0;
+        while !producer.is_finished() || scheduler.pending(&backlog) > 0 {
+            let key_pressed = Instant::now();
+            let report = scheduler.run_slice(&backlog, &mut parser);
+            assert!(report.processed <= max_slice_bytes);
+            saw_catch_up |= report.catch_up && !report.render;
+            saw_indicator |= scheduler.indicator().is_some();
+
+            frame += 1;
+            if frame % 20 == 0 && key_times.len() < 50 && !interrupted.load(Ordering::Acquire) {
+                pty.send(1, b"x");
+                key_times.push(key_pressed);
+            }
+            if parser.consumed >= 150 * 1024 * 1024 && !interrupted.load(Ordering::Acquire) {
+                pty.send(1, b"\x03");
+                key_times.push(key_pressed);
+                interrupted.store(true, Ordering::Release);
+            }
+        }
+        let produced = producer.join().unwrap();
+
+        assert_eq!(parser.consumed, produced);
+        assert!(produced >= 150 * 1024 * 1024);
+        assert!(saw_catch_up && saw_indicator);
+        assert!(!scheduler.is_catching_up());
+        assert_eq!(scheduler.metrics().backlog_bytes, 0);
+
+        let sent = pty.0.lock().unwrap();
+        assert_eq!(sent.last().unwrap().1, b"\x03");
+        for ((delivered, _), pressed) in sent.iter().zip(&key_times) {
+            assert!(
+                delivered.duration_since(*pressed) < Duration::from_millis(50),
+                "input waited {:?} behind output",
+                delivered.duration_since(*pressed)
+            );
+        }
+    }
+
+    #[test]
+    fn test_small_output_renders_every_frame() {
+        let mut scheduler = OutputScheduler::default();
+        let backlog = OutputBacklog::new();
+        let mut terminal = TerminalState::new(20, 3);
+        backlog.push(b"hello");
+
+        let report = scheduler.run_slice(&backlog, &mut terminal);
+        assert_eq!(report.processed, 5);
+        assert!(report.render && !report.catch_up);
+        assert_eq!(terminal.get_cell(0, 0).unwrap().character, 'h');
+        assert_eq!(scheduler.indicator(), None);
+        assert!(scheduler.metrics().slice_utilization < 1.0);
+    }
+}
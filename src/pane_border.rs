@@ -0,0 +1,43 @@
+/// Rectangle in terminal cell coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+}
+
+/// Title prefix for panes that do not accept input
+pub const READ_ONLY_MARKER: char = '🔒';
+
+/// Colors of the borders between split panes. The focused pane's edges
+/// stand out from the rest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BorderTheme {
+    pub focused: [f32; 4],
+    pub unfocused: [f32; 4],
+}
+
+impl Default for BorderTheme {
+    fn default() -> Self {
+        Self {
+            focused: [0.4, 0.7, 1.0, 1.0],
+            unfocused: [0.45, 0.45, 0.45, 1.0],
+        }
+    }
+}
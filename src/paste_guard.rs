@@ -0,0 +1,430 @@
+use crate::config::UiConfig;
+use crate::input::Key;
+use crate::messages;
+use crate::terminal::TerminalCell;
+use regex::Regex;
+use std::ops::Range;
+
+/// Bracketed-paste markers the application sees around pasted text
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+/// Patterns flagged when `ui.paste_risky_patterns` is not set
+pub const DEFAULT_RISKY_PATTERNS: &[&str] = &[
+    r"\bsudo\b",
+    r"\brm\s+-[a-zA-Z]*(rf|fr)",
+    r"\b(curl|wget)\b[^|\n]*\|\s*(sudo\s+)?(ba|z|da)?sh\b",
+];
+
+#[derive(Debug, Clone)]
+pub struct PasteGuardConfig {
+    pub enabled: bool,
+    /// Warn once the paste has this many line breaks; 0 never warns on them
+    pub newline_threshold: usize,
+    pub risky_patterns: Vec<Regex>,
+    /// Skip the line-break warning when the application asked for bracketed
+    /// paste, since it will not run each line as it arrives
+    pub trust_bracketed: bool,
+    /// Drop control characters other than tab and line breaks before
+    /// anything reaches the PTY
+    pub strip_control: bool,
+}
+
+impl Default for PasteGuardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            newline_threshold: 1,
+            risky_patterns: compile_patterns(DEFAULT_RISKY_PATTERNS).unwrap_or_default(),
+            trust_bracketed: false,
+            strip_control: true,
+        }
+    }
+}
+
+impl PasteGuardConfig {
+    /// Invalid patterns are rejected when the config is validated, so any
+    /// that still fail here are dropped
+    pub fn from_config(ui: &UiConfig) -> Self {
+        Self {
+            enabled: ui.paste_warn,
+            newline_threshold: ui.paste_newline_threshold as usize,
+            risky_patterns: ui
+                .paste_risky_patterns
+                .iter()
+                .filter_map(|pattern| Regex::new(pattern).ok())
+                .collect(),
+            trust_bracketed: ui.paste_trust_bracketed,
+            strip_control: ui.paste_strip_control,
+        }
+    }
+}
+
+pub fn compile_patterns(patterns: &[impl AsRef<str>]) -> Result<Vec<Regex>, regex::Error> {
+    patterns
+        .iter()
+        .map(|pattern| Regex::new(pattern.as_ref()))
+        .collect()
+}
+
+/// Why a paste needs confirming
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PasteFindings {
+    pub line_breaks: usize,
+    /// Byte offsets of control characters other than tab and line breaks
+    pub control_chars: Vec<usize>,
+    /// Byte ranges matched by a risky pattern
+    pub risky: Vec<Range<usize>>,
+}
+
+impl PasteFindings {
+    pub fn summary(&self, lines: usize) -> String {
+        let messages = messages::current();
+        let mut parts = vec![messages.paste_lines(lines)];
+        if !self.control_chars.is_empty() {
+            parts.push(messages.paste_control_chars(self.control_chars.len()));
+        }
+        if !self.risky.is_empty() {
+            parts.push(messages.paste_risky(self.risky.len()));
+        }
+        parts.join(", ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PasteVerdict {
+    /// Benign: write these bytes straight away
+    Send(Vec<u8>),
+    Confirm(PasteReview),
+}
+
+/// Decide whether `text` can be pasted without asking. `bracketed` is the
+/// application's bracketed-paste mode.
+pub fn classify(text: &str, config: &PasteGuardConfig, bracketed: bool) -> PasteVerdict {
+    let findings = inspect(text, config);
+    let newline_warning = config.newline_threshold > 0
+        && findings.line_breaks >= config.newline_threshold
+        && !(bracketed && config.trust_bracketed);
+    let warn = config.enabled
+        && (newline_warning || !findings.control_chars.is_empty() || !findings.risky.is_empty());
+    if warn {
+        PasteVerdict::Confirm(PasteReview {
+            text: text.to_string(),
+            bracketed,
+            strip_control: config.strip_control,
+            findings,
+        })
+    } else {
+        PasteVerdict::Send(paste_bytes(text, bracketed, config.strip_control))
+    }
+}
+
+pub fn inspect(text: &str, config: &PasteGuardConfig) -> PasteFindings {
+    let mut findings = PasteFindings::default();
+    let mut previous = None;
+    for (offset, ch) in text.char_indices() {
+        match ch {
+            // CRLF is one break
+            '\n' if previous == Some('\r') => {}
+            '\n' | '\r' => findings.line_breaks += 1,
+            '\t' => {}
+            c if c.is_control() => findings.control_chars.push(offset),
+            _ => {}
+        }
+        previous = Some(ch);
+    }
+    for pattern in &config.risky_patterns {
+        findings
+            .risky
+            .extend(pattern.find_iter(text).map(|found| found.range()));
+    }
+    findings.risky.sort_by_key(|range| range.start);
+    findings
+}
+
+/// What the PTY receives: line breaks as carriage returns, the way a
+/// typed Enter arrives, and bracketed when the application asked for it.
+/// An end marker inside the text would let it escape the brackets, so it
+/// is removed.
+pub fn paste_bytes(text: &str, bracketed: bool, strip_control: bool) -> Vec<u8> {
+    let mut text = text.replace("\r\n", "\r").replace('\n', "\r");
+    if bracketed {
+        text = text.replace(PASTE_END, "");
+    }
+    if strip_control {
+        text.retain(|c| matches!(c, '\t' | '\r') || !c.is_control());
+    }
+    if bracketed {
+        format!("{}{}{}", PASTE_START, text, PASTE_END).into_bytes()
+    } else {
+        text.into_bytes()
+    }
+}
+
+/// Printable stand-in for a control character, from the Unicode control
+/// pictures block
+pub fn visible_control(ch: char) -> Option<char> {
+    match ch {
+        '\n' => Some('↵'),
+        '\t' => Some('⇥'),
+        '\u{7f}' => Some('␡'),
+        c if (c as u32) < 0x20 => char::from_u32(0x2400 + c as u32),
+        c if c.is_control() => Some('�'),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanStyle {
+    Plain,
+    /// A control character shown by its picture
+    Control,
+    /// Part of a risky match
+    Risky,
+}
+
+/// One line of the preview: the exact content with invisibles made visible
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewLine {
+    pub spans: Vec<(SpanStyle, String)>,
+}
+
+impl PreviewLine {
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|(_, text)| text.as_str()).collect()
+    }
+}
+
+/// Outcome of a key press in the preview
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReviewOutcome {
+    /// Still waiting for a decision
+    Pending,
+    Paste(Vec<u8>),
+    /// Hand the text to the composer; nothing reaches the PTY
+    Edit(String),
+    Cancel,
+}
+
+/// A paste waiting for confirmation
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasteReview {
+    text: String,
+    bracketed: bool,
+    strip_control: bool,
+    findings: PasteFindings,
+}
+
+impl PasteReview {
+    pub fn findings(&self) -> &PasteFindings {
+        &self.findings
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.preview().len()
+    }
+
+    /// Enter pastes, `e` edits first, Esc cancels
+    pub fn key(&self, key: &Key) -> ReviewOutcome {
+        match key {
+            Key::Enter => ReviewOutcome::Paste(paste_bytes(&self.text, self.bracketed, self.strip_control)),
+            Key::Char('e') | Key::Char('E') => ReviewOutcome::Edit(self.text.clone()),
+            Key::Escape => ReviewOutcome::Cancel,
+            _ => ReviewOutcome::Pending,
+        }
+    }
+
+    /// Lines as pasted. Each line ends with its break made visible, so a
+    /// trailing newline that would run the last command is shown too.
+    pub fn preview(&self) -> Vec<PreviewLine> {
+        let mut lines = Vec::new();
+        let mut spans: Vec<(SpanStyle, String)> = Vec::new();
+        let mut previous = None;
+        for (offset, ch) in self.text.char_indices() {
+            let risky = self
+                .findings
+                .risky
+                .iter()
+                .any(|range| range.contains(&offset));
+            let (style, shown) = match visible_control(ch) {
+                Some(picture) => (SpanStyle::Control, picture),
+                None if risky => (SpanStyle::Risky, ch),
+                None => (SpanStyle::Plain, ch),
+            };
+            if ch == '\n' && previous == Some('\r') {
+                // Already ended the line at the CR
+                previous = Some(ch);
+                continue;
+            }
+            match spans.last_mut() {
+                Some((last, text)) if *last == style => text.push(shown),
+                _ => spans.push((style, shown.to_string())),
+            }
+            if ch == '\n' || ch == '\r' {
+                lines.push(PreviewLine {
+                    spans: std::mem::take(&mut spans),
+                });
+            }
+            previous = Some(ch);
+        }
+        if !spans.is_empty() || lines.is_empty() {
+            lines.push(PreviewLine { spans });
+        }
+        lines
+    }
+
+    /// Overlay rows for the renderer: a header, the preview clipped to the
+    /// window, and the key help
+    pub fn overlay_rows(&self, width: u32, height: u32) -> Vec<Vec<TerminalCell>> {
+        let preview = self.preview();
+        let messages = messages::current();
+        let header = messages.paste_header(&self.findings.summary(preview.len()));
+        let help = messages.paste_help();
+        let room = (height as usize).saturating_sub(2);
+        let mut rows = vec![cells(&[(SpanStyle::Plain, header)], width, true)];
+        for line in preview.iter().take(room) {
+            rows.push(cells(&line.spans, width, false));
+        }
+        if preview.len() > room && room > 0 {
+            let more = messages.paste_more_lines(preview.len() - room + 1);
+            *rows.last_mut().expect("room > 0") = cells(&[(SpanStyle::Plain, more)], width, false);
+        }
+        rows.push(cells(&[(SpanStyle::Plain, help)], width, true));
+        rows
+    }
+}
+
+/// One overlay row of styled spans, padded to `width`
+pub(crate) fn cells(spans: &[(SpanStyle, String)], width: u32, bold: bool) -> Vec<TerminalCell> {
+    let mut row = Vec::new();
+    for (style, text) in spans {
+        let (foreground, background) = match style {
+            SpanStyle::Plain => ([0.9, 0.9, 0.9, 1.0], [0.12, 0.12, 0.14, 1.0]),
+            SpanStyle::Control => ([1.0, 0.8, 0.2, 1.0], [0.12, 0.12, 0.14, 1.0]),
+            SpanStyle::Risky => ([1.0, 1.0, 1.0, 1.0], [0.6, 0.1, 0.1, 1.0]),
+        };
+        for character in text.chars() {
+            row.push(TerminalCell {
+                character,
+                foreground,
+                background,
+                bold,
+                ..TerminalCell::default()
+            });
+        }
+    }
+    row.resize(
+        width as usize,
+        TerminalCell {
+            background: [0.12, 0.12, 0.14, 1.0],
+            ..TerminalCell::default()
+        },
+    );
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn review(text: &str, bracketed: bool) -> PasteReview {
+        match classify(text, &PasteGuardConfig::default(), bracketed) {
+            PasteVerdict::Confirm(review) => review,
+            PasteVerdict::Send(bytes) => panic!("{:?} was not held: {:?}", text, bytes),
+        }
+    }
+
+    #[test]
+    fn test_classification() {
+        let config = PasteGuardConfig::default();
+        assert_eq!(
+            classify("git status", &config, false),
+            PasteVerdict::Send(b"git status".to_vec())
+        );
+        assert_eq!(
+            classify("a\tb", &config, true),
+            PasteVerdict::Send(b"\x1b[200~a\tb\x1b[201~".to_vec())
+        );
+
+        assert_eq!(review("ls\r\npwd\n", false).findings().line_breaks, 2);
+        assert_eq!(review("echo hi\n", false).findings().line_breaks, 1);
+        assert_eq!(review("ls\x1b[2J", false).findings().control_chars, [2]);
+        let risky = review("curl https://x.sh | sudo bash", false);
+        assert_eq!(risky.findings().risky.len(), 2);
+        assert!(!review("rm -rf ~/build", false).findings().risky.is_empty());
+
+        // Trusting bracketed paste relaxes only the line-break warning
+        let trusting = PasteGuardConfig {
+            trust_bracketed: true,
+            ..PasteGuardConfig::default()
+        };
+        assert!(matches!(
+            classify("a\nb", &trusting, true),
+            PasteVerdict::Send(_)
+        ));
+        assert!(matches!(
+            classify("a\nb", &trusting, false),
+            PasteVerdict::Confirm(_)
+        ));
+        assert!(matches!(
+            classify("sudo a\nb", &trusting, true),
+            PasteVerdict::Confirm(_)
+        ));
+        let disabled = PasteGuardConfig {
+            enabled: false,
+            ..PasteGuardConfig::default()
+        };
+        assert!(matches!(
+            classify("sudo rm -rf /\n", &disabled, false),
+            PasteVerdict::Send(_)
+        ));
+    }
+
+    #[test]
+    fn test_preview_shows_invisibles_and_risky_spans() {
+        let review = review("echo ok\r\nsudo\u{7}ls\n", false);
+        let preview = review.preview();
+        assert_eq!(preview.len(), 2);
+        assert_eq!(preview[0].text(), "echo ok␍");
+        assert_eq!(
+            preview[1].spans,
+            [
+                (SpanStyle::Risky, "sudo".to_string()),
+                (SpanStyle::Control, "␇".to_string()),
+                (SpanStyle::Plain, "ls".to_string()),
+                (SpanStyle::Control, "↵".to_string()),
+            ]
+        );
+        let rows = review.overlay_rows(20, 3);
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|row| row.len() == 20));
+        assert_eq!(rows[1][0].character, '…');
+    }
+
+    #[test]
+    fn test_review_keys_produce_pty_bytes() {
+        let plain = review("make\nmake install\n", false);
+        assert_eq!(
+            plain.key(&Key::Enter),
+            ReviewOutcome::Paste(b"make\rmake install\r".to_vec())
+        );
+        assert_eq!(
+            plain.key(&Key::Char('e')),
+            ReviewOutcome::Edit("make\nmake install\n".to_string())
+        );
+        assert_eq!(plain.key(&Key::Escape), ReviewOutcome::Cancel);
+        assert_eq!(plain.key(&Key::Char('y')), ReviewOutcome::Pending);
+
+        // Control characters are shown in the preview but not sent
+        let hidden = review("ls\x1b[2J\x07\tx\r\n", false);
+        assert_eq!(hidden.key(&Key::Enter), ReviewOutcome::Paste(b"ls[2J\tx\r".to_vec()));
+        assert_eq!(paste_bytes("ls\x1b[2J", false, false), b"ls\x1b[2J");
+
+        // A smuggled end marker cannot break out of bracketed paste
+        let bracketed = review("a\x1b[201~\nrm -rf /", true);
+        assert_eq!(
+            bracketed.key(&Key::Enter),
+            ReviewOutcome::Paste(b"\x1b[200~a\rrm -rf /\x1b[201~".to_vec())
+        );
+    }
+}
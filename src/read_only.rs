@@ -0,0 +1,435 @@
+use crate::config::UiConfig;
+use crate::messages;
+use crate::tty::TtyEngine;
+use crate::window_manager::{SessionLayout, WindowRecord};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ReadOnlyError {
+    #[error("Unknown read-only mode: {0} (expected on, off or toggle)")]
+    UnknownMode(String),
+    #[error("Unknown unlock confirmation: {0}")]
+    UnknownConfirmation(String),
+}
+
+/// How long the toggle binding must be held to leave read-only mode with
+/// `readonly_unlock = "hold"`
+pub const HOLD_TO_UNLOCK: Duration = Duration::from_secs(1);
+
+/// Argument of `:readonly`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadOnlyMode {
+    On,
+    Off,
+    Toggle,
+}
+
+impl FromStr for ReadOnlyMode {
+    type Err = ReadOnlyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "on" => Ok(ReadOnlyMode::On),
+            "off" => Ok(ReadOnlyMode::Off),
+            "toggle" => Ok(ReadOnlyMode::Toggle),
+            _ => Err(ReadOnlyError::UnknownMode(s.to_string())),
+        }
+    }
+}
+
+/// What it takes to turn read-only off again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnlockConfirmation {
+    /// Unlock immediately
+    None,
+    /// Type the pane's index into the pane
+    #[default]
+    PaneIndex,
+    /// Hold the toggle binding for `HOLD_TO_UNLOCK`
+    Hold,
+}
+
+impl FromStr for UnlockConfirmation {
+    type Err = ReadOnlyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(UnlockConfirmation::None),
+            "index" => Ok(UnlockConfirmation::PaneIndex),
+            "hold" => Ok(UnlockConfirmation::Hold),
+            _ => Err(ReadOnlyError::UnknownConfirmation(s.to_string())),
+        }
+    }
+}
+
+/// Where input for a pane came from. Every source is blocked alike; the
+/// source only decides whether the input can answer an unlock prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputSource {
+    Keyboard,
+    Paste,
+    /// `send_keys` from the agent API
+    AgentApi,
+    /// Input mirrored to every pane in a synchronized group
+    Broadcast,
+    /// Wheel reports for an application that asked for mouse input
+    Mouse,
+}
+
+/// Destination for pane input
+pub trait PtySink {
+    fn send(&self, pty_id: u64, data: &[u8]);
+}
+
+impl PtySink for TtyEngine {
+    fn send(&self, pty_id: u64, data: &[u8]) {
+        if let Err(e) = self.queue_write(pty_id, data) {
+            error!("Failed to write to PTY: {}", e);
+        }
+    }
+}
+
+/// Message for the user, shown briefly over the pane
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadOnlyNotice {
+    pub pty_id: u64,
+    pub message: String,
+    pub bell: bool,
+}
+
+#[derive(Debug, Clone)]
+enum PendingUnlock {
+    /// Digits typed so far towards the pane index
+    Index {
+        expected: String,
+        typed: String,
+    },
+    Hold {
+        since: Instant,
+    },
+}
+
+/// Read-only flags for every pane, and the gate all pane input passes
+/// through. Output is never affected.
+#[derive(Debug, Clone, Default)]
+pub struct ReadOnlyPanes {
+    locked: HashSet<u64>,
+    pending: HashMap<u64, PendingUnlock>,
+    confirmation: UnlockConfirmation,
+    bell: bool,
+    notices: Vec<ReadOnlyNotice>,
+}
+
+impl ReadOnlyPanes {
+    pub fn new(confirmation: UnlockConfirmation, bell: bool) -> Self {
+        Self {
+            confirmation,
+            bell,
+            ..Self::default()
+        }
+    }
+
+    /// Unknown `readonly_unlock` values fall back to typing the pane index
+    pub fn from_config(ui: &UiConfig) -> Self {
+        Self::new(
+            ui.readonly_unlock.parse().unwrap_or_default(),
+            ui.readonly_bell,
+        )
+    }
+
+    pub fn is_read_only(&self, pty_id: u64) -> bool {
+        self.locked.contains(&pty_id)
+    }
+
+    pub fn is_unlocking(&self, pty_id: u64) -> bool {
+        self.pending.contains_key(&pty_id)
+    }
+
+    pub fn lock(&mut self, pty_id: u64) {
+        self.locked.insert(pty_id);
+        self.pending.remove(&pty_id);
+    }
+
+    /// The pane closed
+    pub fn forget(&mut self, pty_id: u64) {
+        self.locked.remove(&pty_id);
+        self.pending.remove(&pty_id);
+    }
+
+    /// `:readonly on|off|toggle` for the pane shown as `pane_index`. Turning
+    /// it off may only start the configured confirmation. Returns whether
+    /// the pane is read-only afterwards.
+    pub fn set(&mut self, pty_id: u64, mode: ReadOnlyMode, pane_index: usize) -> bool {
+        match mode {
+            ReadOnlyMode::On => self.lock(pty_id),
+            ReadOnlyMode::Off => self.request_unlock(pty_id, pane_index),
+            ReadOnlyMode::Toggle if self.is_read_only(pty_id) => {
+                self.request_unlock(pty_id, pane_index)
+            }
+            ReadOnlyMode::Toggle => self.lock(pty_id),
+        }
+        self.is_read_only(pty_id)
+    }
+
+    /// The toggle binding was pressed (`repeat` for auto-repeat while held).
+    /// Returns whether the pane is read-only afterwards.
+    pub fn toggle_pressed(
+        &mut self,
+        pty_id: u64,
+        pane_index: usize,
+        repeat: bool,
+        now: Instant,
+    ) -> bool {
+        if !self.is_read_only(pty_id) {
+            if !repeat {
+                self.lock(pty_id);
+            }
+            return true;
+        }
+        if self.confirmation != UnlockConfirmation::Hold {
+            if !repeat {
+                self.request_unlock(pty_id, pane_index);
+            }
+            return self.is_read_only(pty_id);
+        }
+
+        match self.pending.get(&pty_id) {
+            Some(PendingUnlock::Hold { since }) if repeat => {
+                if now.duration_since(*since) >= HOLD_TO_UNLOCK {
+                    self.unlock(pty_id);
+                }
+            }
+            _ => {
+                self.pending
+                    .insert(pty_id, PendingUnlock::Hold { since: now });
+                self.notify(pty_id, &messages::current().read_only_keep_holding(), false);
+            }
+        }
+        self.is_read_only(pty_id)
+    }
+
+    fn request_unlock(&mut self, pty_id: u64, pane_index: usize) {
+        if !self.is_read_only(pty_id) {
+            return;
+        }
+        match self.confirmation {
+            UnlockConfirmation::None => self.unlock(pty_id),
+            UnlockConfirmation::PaneIndex => {
+                self.pending.insert(
+                    pty_id,
+                    PendingUnlock::Index {
+                        expected: pane_index.to_string(),
+                        typed: String::new(),
+                    },
+                );
+                let message = messages::current().read_only_type_index(pane_index);
+                self.notify(pty_id, &message, false);
+            }
+            UnlockConfirmation::Hold => {
+                self.notify(pty_id, &messages::current().read_only_hold_hint(), false)
+            }
+        }
+    }
+
+    fn unlock(&mut self, pty_id: u64) {
+        self.locked.remove(&pty_id);
+        self.pending.remove(&pty_id);
+        self.notify(pty_id, &messages::current().read_only_off(), false);
+    }
+
+    /// Forward input to the pane unless it is read-only. Swallowed input
+    /// raises a notice; on a pane waiting for its index, typed keys answer
+    /// the prompt instead. Returns whether anything was sent.
+    pub fn send(
+        &mut self,
+        sink: &(impl PtySink + ?Sized),
+        pty_id: u64,
+        source: InputSource,
+        data: &[u8],
+    ) -> bool {
+        if !self.is_read_only(pty_id) {
+            sink.send(pty_id, data);
+            return true;
+        }
+
+        if source == InputSource::Keyboard
+            && let Some(PendingUnlock::Index { expected, typed }) = self.pending.get_mut(&pty_id)
+        {
+            typed.push_str(&String::from_utf8_lossy(data));
+            if typed == expected {
+                self.unlock(pty_id);
+            } else if !expected.starts_with(typed.as_str()) {
+                self.pending.remove(&pty_id);
+                self.notify(pty_id, &messages::current().read_only_on(), self.bell);
+            }
+            return false;
+        }
+
+        self.notify(pty_id, &messages::current().read_only_on(), self.bell);
+        false
+    }
+
+    fn notify(&mut self, pty_id: u64, message: &str, bell: bool) {
+        self.notices.push(ReadOnlyNotice {
+            pty_id,
+            message: message.to_string(),
+            bell,
+        });
+    }
+
+    pub fn take_notices(&mut self) -> Vec<ReadOnlyNotice> {
+        std::mem::take(&mut self.notices)
+    }
+
+    /// Fill in `read_only_tabs` for each window of a layout about to be saved
+    pub fn record(&self, layout: &mut SessionLayout) {
+        for window in &mut layout.windows {
+            window.read_only_tabs = window
+                .tabs
+                .iter()
+                .enumerate()
+                .filter(|(_, pty_id)| self.is_read_only(**pty_id))
+                .map(|(index, _)| index)
+                .collect();
+        }
+    }
+
+    /// Re-apply a saved window's flags to the panes that replaced its tabs
+    pub fn restore(&mut self, record: &WindowRecord, tabs: &[u64]) {
+        for &index in &record.read_only_tabs {
+            if let Some(&pty_id) = tabs.get(index) {
+                self.lock(pty_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_parser::{Command, CommandParser};
+    use crate::terminal::TerminalState;
+    use std::cell::RefCell;
+    use tempfile::TempDir;
+
+    #[derive(Default)]
+    struct RecordingSink(RefCell<Vec<(u64, Vec<u8>)>>);
+
+    impl PtySink for RecordingSink {
+        fn send(&self, pty_id: u64, data: &[u8]) {
+            self.0.borrow_mut().push((pty_id, data.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_read_only_swallows_input_but_not_output() {
+        let sink = RecordingSink::default();
+        let mut panes = ReadOnlyPanes::new(UnlockConfirmation::PaneIndex, true);
+        assert!(panes.set(7, "toggle".parse().unwrap(), 2));
+
+        assert!(!panes.send(&sink, 7, InputSource::Keyboard, b"x"));
+        assert!(!panes.send(&sink, 7, InputSource::Paste, b"DROP TABLE users;\r"));
+        assert!(!panes.send(&sink, 7, InputSource::AgentApi, b"ls\r"));
+        assert!(!panes.send(&sink, 7, InputSource::Broadcast, b"ls\r"));
+        assert!(sink.0.borrow().is_empty());
+        let notices = panes.take_notices();
+        assert_eq!(notices.len(), 4);
+        assert!(notices.iter().all(|n| n.pty_id == 7 && n.bell));
+
+        // Other panes are unaffected
+        assert!(panes.send(&sink, 8, InputSource::Keyboard, b"x"));
+        assert_eq!(sink.0.borrow().as_slice(), &[(8, b"x".to_vec())]);
+
+        // Output still reaches the grid
+        let mut terminal = TerminalState::new(20, 3);
+        terminal.feed_bytes(b"prod=>");
+        assert_eq!(terminal.get_cell(0, 0).unwrap().character, 'p');
+    }
+
+    #[test]
+    fn test_unlock_requires_confirmation() {
+        let sink = RecordingSink::default();
+        let mut panes = ReadOnlyPanes::new(UnlockConfirmation::PaneIndex, false);
+        panes.lock(3);
+
+        // Typing the wrong index cancels the prompt
+        assert!(panes.set(3, ReadOnlyMode::Off, 12));
+        panes.send(&sink, 3, InputSource::Keyboard, b"1");
+        panes.send(&sink, 3, InputSource::Keyboard, b"3");
+        assert!(panes.is_read_only(3) && !panes.is_unlocking(3));
+
+        // Paste cannot answer the prompt
+        panes.set(3, ReadOnlyMode::Off, 12);
+        panes.send(&sink, 3, InputSource::Paste, b"12");
+        assert!(panes.is_read_only(3));
+        panes.send(&sink, 3, InputSource::Keyboard, b"1");
+        panes.send(&sink, 3, InputSource::Keyboard, b"2");
+        assert!(!panes.is_read_only(3));
+        assert!(sink.0.borrow().is_empty());
+        assert_eq!(
+            panes.take_notices().last().unwrap().message,
+            "Pane is writable"
+        );
+
+        // Holding the binding
+        let mut panes = ReadOnlyPanes::new(UnlockConfirmation::Hold, false);
+        let start = Instant::now();
+        assert!(panes.toggle_pressed(3, 1, false, start));
+        assert!(panes.toggle_pressed(3, 1, false, start));
+        assert!(panes.toggle_pressed(3, 1, true, start + Duration::from_millis(500)));
+        assert!(!panes.toggle_pressed(3, 1, true, start + HOLD_TO_UNLOCK));
+
+        let mut panes = ReadOnlyPanes::new(UnlockConfirmation::None, false);
+        panes.lock(3);
+        assert!(!panes.set(3, ReadOnlyMode::Toggle, 1));
+    }
+
+    #[test]
+    fn test_readonly_command_sets_the_pane() {
+        let parser = CommandParser::new("f".to_string());
+        let mut panes = ReadOnlyPanes::new(UnlockConfirmation::None, false);
+        let mut run = |input: &str| match parser.parse_builtin(input) {
+            Ok(Command::ReadOnly(mode)) => panes.set(4, mode, 1),
+            other => panic!("{} parsed as {:?}", input, other),
+        };
+        assert!(run(":readonly on"));
+        assert!(run(":readonly on"));
+        assert!(!run(":readonly"));
+        assert!(run(":readonly toggle"));
+        assert!(!run(":readonly off"));
+    }
+
+    #[test]
+    fn test_session_roundtrip() {
+        let mut panes = ReadOnlyPanes::default();
+        panes.lock(11);
+        let mut layout = SessionLayout {
+            windows: vec![WindowRecord {
+                position: None,
+                pixel_width: 800,
+                pixel_height: 600,
+                scale_factor: 1.0,
+                tabs: vec![10, 11],
+                active_tab: 0,
+                read_only_tabs: Vec::new(),
+            }],
+        };
+        panes.record(&mut layout);
+        assert_eq!(layout.windows[0].read_only_tabs, vec![1]);
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("session.json");
+        layout.save(&path).unwrap();
+        let loaded = SessionLayout::load(&path).unwrap();
+        assert_eq!(loaded, layout);
+
+        let mut restored = ReadOnlyPanes::default();
+        restored.restore(&loaded.windows[0], &[20, 21]);
+        assert!(!restored.is_read_only(20));
+        assert!(restored.is_read_only(21));
+    }
+}
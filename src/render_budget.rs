@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+/// Rendering fidelity for a streaming response, from best to cheapest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum RenderTier {
+    /// Markdown parsing, syntax highlighting and layout
+    #[default]
+    Full,
+    /// Syntax highlighting disabled for the in-progress code block
+    NoHighlight,
+    /// Plain-text, append-only rendering for the rest of the response
+    PlainText,
+}
+
+impl RenderTier {
+    fn next(self) -> Self {
+        match self {
+            RenderTier::Full => RenderTier::NoHighlight,
+            RenderTier::NoHighlight | RenderTier::PlainText => RenderTier::PlainText,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DegradationConfig {
+    /// Render cost per cycle above which a cycle counts as over budget
+    pub frame_budget: Duration,
+    /// Consecutive over-budget cycles before stepping down a tier
+    pub consecutive_cycles: u32,
+}
+
+impl Default for DegradationConfig {
+    fn default() -> Self {
+        Self {
+            frame_budget: Duration::from_millis(12),
+            consecutive_cycles: 5,
+        }
+    }
+}
+
+/// Time spent in each stage of one render cycle
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StageCosts {
+    pub parse: Duration,
+    pub highlight: Duration,
+    pub layout: Duration,
+}
+
+impl StageCosts {
+    pub fn total(&self) -> Duration {
+        self.parse + self.highlight + self.layout
+    }
+}
+
+/// Tracks per-cycle render cost and steps the tier down once the budget has
+/// been exceeded for enough consecutive cycles.
+#[derive(Debug, Clone)]
+pub struct DegradationPolicy {
+    config: DegradationConfig,
+    tier: RenderTier,
+    over_budget_streak: u32,
+}
+
+impl DegradationPolicy {
+    pub fn new(config: DegradationConfig) -> Self {
+        Self {
+            config,
+            tier: RenderTier::Full,
+            over_budget_streak: 0,
+        }
+    }
+
+    pub fn tier(&self) -> RenderTier {
+        self.tier
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.tier != RenderTier::Full
+    }
+
+    /// Record one cycle's cost. Returns the new tier when a step-down occurs.
+    pub fn record_cycle(&mut self, costs: StageCosts) -> Option<RenderTier> {
+        if costs.total() <= self.config.frame_budget {
+            self.over_budget_streak = 0;
+            return None;
+        }
+
+        self.over_budget_streak += 1;
+        if self.over_budget_streak < self.config.consecutive_cycles
+            || self.tier == RenderTier::PlainText
+        {
+            return None;
+        }
+
+        self.over_budget_streak = 0;
+        self.tier = self.tier.next();
+        Some(self.tier)
+    }
+
+    /// Start over at full fidelity for the next response
+    pub fn reset(&mut self) {
+        self.tier = RenderTier::Full;
+        self.over_budget_streak = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn costs(highlight_ms: u64) -> StageCosts {
+        StageCosts {
+            parse: Duration::from_millis(2),
+            highlight: Duration::from_millis(highlight_ms),
+            layout: Duration::from_millis(2),
+        }
+    }
+
+    fn policy() -> DegradationPolicy {
+        DegradationPolicy::new(DegradationConfig {
+            frame_budget: Duration::from_millis(10),
+            consecutive_cycles: 3,
+        })
+    }
+
+    #[test]
+    fn test_tiers_trigger_after_consecutive_cycles() {
+        let mut policy = policy();
+        assert_eq!(policy.record_cycle(costs(20)), None);
+        assert_eq!(policy.record_cycle(costs(20)), None);
+        assert_eq!(policy.record_cycle(costs(20)), Some(RenderTier::NoHighlight));
+        assert_eq!(policy.record_cycle(costs(20)), None);
+        assert_eq!(policy.record_cycle(costs(20)), None);
+        assert_eq!(policy.record_cycle(costs(20)), Some(RenderTier::PlainText));
+        assert_eq!(policy.record_cycle(costs(20)), None);
+        assert_eq!(policy.tier(), RenderTier::PlainText);
+
+        policy.reset();
+        assert_eq!(policy.tier(), RenderTier::Full);
+        assert!(!policy.is_degraded());
+    }
+
+    #[test]
+    fn test_streak_resets_on_cheap_cycle() {
+        let mut policy = policy();
+        for cost in [20, 20, 1, 20, 20] {
+            assert_eq!(policy.record_cycle(costs(cost)), None);
+        }
+        assert_eq!(policy.record_cycle(costs(20)), Some(RenderTier::NoHighlight));
+    }
+
+    #[test]
+    fn test_within_budget_stays_full() {
+        let mut policy = policy();
+        for _ in 0..10 {
+            assert_eq!(policy.record_cycle(costs(1)), None);
+        }
+        assert_eq!(policy.tier(), RenderTier::Full);
+    }
+}
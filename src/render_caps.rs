@@ -0,0 +1,449 @@
+use crate::startup::CellFont;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+#[derive(Error, Debug)]
+pub enum RenderCapsError {
+    #[error("Unknown color depth: {0}")]
+    UnknownColorDepth(String),
+}
+
+/// Colors the active renderer and theme can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorDepth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::TrueColor => "truecolor",
+            Self::Ansi256 => "256",
+            Self::Ansi16 => "16",
+        }
+    }
+}
+
+impl FromStr for ColorDepth {
+    type Err = RenderCapsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truecolor" | "24bit" => Ok(Self::TrueColor),
+            "256" => Ok(Self::Ansi256),
+            "16" => Ok(Self::Ansi16),
+            _ => Err(RenderCapsError::UnknownColorDepth(s.to_string())),
+        }
+    }
+}
+
+/// `ui.color_depth`: `auto` picks from the renderer and theme
+pub fn forced_color_depth(setting: &str) -> Option<ColorDepth> {
+    match setting {
+        "auto" => None,
+        other => other.parse().ok(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RendererKind {
+    Gpu,
+    /// The CPU rasterizer, which only draws from a fixed palette
+    Cpu,
+}
+
+/// What styled output may assume about the display. Re-derived whenever
+/// the font, renderer or theme changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderCapabilities {
+    pub color_depth: ColorDepth,
+    /// The font draws box-drawing characters
+    pub box_drawing: bool,
+    /// The font draws common symbols such as bullets and ellipses
+    pub unicode: bool,
+}
+
+impl Default for RenderCapabilities {
+    fn default() -> Self {
+        Self {
+            color_depth: ColorDepth::TrueColor,
+            box_drawing: true,
+            unicode: true,
+        }
+    }
+}
+
+/// Box-drawing characters checked in the font
+const BOX_DRAWING: &[char] = &['─', '│', '┌', '┼', '┘'];
+const SYMBOLS: &[char] = &['•', '…', '→'];
+
+impl RenderCapabilities {
+    /// Themes named `*-256` or `*-16` stick to that palette; `forced`
+    /// (`ui.color_depth`) overrides both the theme and the renderer
+    pub fn derive(
+        renderer: RendererKind,
+        font: &dyn CellFont,
+        theme: &str,
+        forced: Option<ColorDepth>,
+    ) -> Self {
+        let theme_depth = if theme.ends_with("-256") {
+            Some(ColorDepth::Ansi256)
+        } else if theme.ends_with("-16") {
+            Some(ColorDepth::Ansi16)
+        } else {
+            None
+        };
+        let renderer_depth = match renderer {
+            RendererKind::Gpu => ColorDepth::TrueColor,
+            RendererKind::Cpu => ColorDepth::Ansi256,
+        };
+        Self {
+            color_depth: forced.or(theme_depth).unwrap_or(renderer_depth),
+            box_drawing: BOX_DRAWING.iter().all(|&ch| font.covers(ch)),
+            unicode: SYMBOLS.iter().all(|&ch| font.covers(ch)),
+        }
+    }
+
+    /// The nearest color the display can show
+    pub fn quantize(&self, rgb: [u8; 3]) -> [u8; 3] {
+        match self.color_depth {
+            ColorDepth::TrueColor => rgb,
+            ColorDepth::Ansi256 => ansi_256_rgb(nearest_256(rgb)),
+            ColorDepth::Ansi16 => ANSI_16[nearest_16(rgb) as usize],
+        }
+    }
+
+    pub fn borders(&self) -> &'static Borders {
+        if self.box_drawing {
+            &UNICODE_BORDERS
+        } else {
+            &ASCII_BORDERS
+        }
+    }
+
+    pub fn bullet(&self) -> &'static str {
+        if self.unicode { "• " } else { "* " }
+    }
+}
+
+/// One line for `:stats`
+impl fmt::Display for RenderCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |supported| if supported { "yes" } else { "no" };
+        write!(
+            f,
+            "display: {} color, box drawing {}, unicode symbols {}",
+            self.color_depth.as_str(),
+            yes_no(self.box_drawing),
+            yes_no(self.unicode)
+        )
+    }
+}
+
+/// xterm's default colors for the 16 ANSI entries
+const ANSI_16: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [205, 0, 0],
+    [0, 205, 0],
+    [205, 205, 0],
+    [0, 0, 238],
+    [205, 0, 205],
+    [0, 205, 205],
+    [229, 229, 229],
+    [127, 127, 127],
+    [255, 0, 0],
+    [0, 255, 0],
+    [255, 255, 0],
+    [92, 92, 255],
+    [255, 0, 255],
+    [0, 255, 255],
+    [255, 255, 255],
+];
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(b)
+        .map(|(&a, b)| (a as i32 - b as i32).pow(2) as u32)
+        .sum()
+}
+
+pub fn ansi_256_rgb(index: u8) -> [u8; 3] {
+    match index {
+        0..=15 => ANSI_16[index as usize],
+        16..=231 => {
+            let i = index - 16;
+            [
+                CUBE_LEVELS[(i / 36) as usize],
+                CUBE_LEVELS[(i / 6 % 6) as usize],
+                CUBE_LEVELS[(i % 6) as usize],
+            ]
+        }
+        _ => {
+            let level = 8 + 10 * (index - 232);
+            [level; 3]
+        }
+    }
+}
+
+/// Nearest entry in the color cube or gray ramp. The first 16 entries are
+/// left out since terminals let users redefine them.
+pub fn nearest_256(rgb: [u8; 3]) -> u8 {
+    let level = |v: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - v as i32).abs())
+            .unwrap() as u8
+    };
+    let cube = 16 + 36 * level(rgb[0]) + 6 * level(rgb[1]) + level(rgb[2]);
+    let average = rgb.iter().map(|&v| v as u32).sum::<u32>() / 3;
+    let gray = 232 + ((average.saturating_sub(8) + 5) / 10).min(23) as u8;
+    if distance(ansi_256_rgb(gray), rgb) < distance(ansi_256_rgb(cube), rgb) {
+        gray
+    } else {
+        cube
+    }
+}
+
+pub fn nearest_16(rgb: [u8; 3]) -> u8 {
+    (0..16)
+        .min_by_key(|&i| distance(ANSI_16[i as usize], rgb))
+        .unwrap()
+}
+
+/// Characters for table borders and rules
+#[derive(Debug, PartialEq, Eq)]
+pub struct Borders {
+    pub horizontal: char,
+    pub vertical: char,
+    /// Left, junction and right, for the top, middle and bottom lines
+    pub top: [char; 3],
+    pub middle: [char; 3],
+    pub bottom: [char; 3],
+    /// Ends text cut short to fit
+    pub ellipsis: &'static str,
+}
+
+pub const UNICODE_BORDERS: Borders = Borders {
+    horizontal: '─',
+    vertical: '│',
+    top: ['┌', '┬', '┐'],
+    middle: ['├', '┼', '┤'],
+    bottom: ['└', '┴', '┘'],
+    ellipsis: "…",
+};
+
+pub const ASCII_BORDERS: Borders = Borders {
+    horizontal: '-',
+    vertical: '|',
+    top: ['+', '+', '+'],
+    middle: ['+', '+', '+'],
+    bottom: ['+', '+', '+'],
+    ellipsis: "...",
+};
+
+/// How a table column lines its cells up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColumnAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// A bordered table, one string per line, with the header row separated
+/// from the body
+pub fn render_table(headers: &[String], rows: &[Vec<String>], borders: &Borders) -> Vec<String> {
+    render_table_fitted(headers, rows, &[], borders, usize::MAX)
+}
+
+/// `render_table` with each column aligned as in `aligns` (left when
+/// missing), narrowed to fit `max_width` by cutting the widest columns'
+/// cells short with an ellipsis
+pub fn render_table_fitted(
+    headers: &[String],
+    rows: &[Vec<String>],
+    aligns: &[ColumnAlign],
+    borders: &Borders,
+    max_width: usize,
+) -> Vec<String> {
+    let columns = headers
+        .len()
+        .max(rows.iter().map(Vec::len).max().unwrap_or(0));
+    fn cell(row: &[String], column: usize) -> &str {
+        row.get(column).map(String::as_str).unwrap_or("")
+    }
+    let mut widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            std::iter::once(headers)
+                .chain(rows.iter().map(Vec::as_slice))
+                .map(|row| cell(row, column).width())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    // Each column adds a border and a space either side
+    let chrome = 3 * columns + 1;
+    while widths.iter().sum::<usize>() + chrome > max_width {
+        let Some(widest) = widths
+            .iter_mut()
+            .filter(|width| **width > MIN_COLUMN)
+            .max_by_key(|width| **width)
+        else {
+            break;
+        };
+        *widest -= 1;
+    }
+
+    let line = |[left, junction, right]: [char; 3]| {
+        let mut line = String::new();
+        line.push(left);
+        for (i, width) in widths.iter().enumerate() {
+            if i > 0 {
+                line.push(junction);
+            }
+            line.extend(std::iter::repeat_n(borders.horizontal, width + 2));
+        }
+        line.push(right);
+        line
+    };
+    let row_line = |row: &[String]| {
+        let mut line = String::new();
+        line.push(borders.vertical);
+        for (column, &width) in widths.iter().enumerate() {
+            let text = fit(cell(row, column), width, borders.ellipsis);
+            let gap = width - text.width();
+            let left = match aligns.get(column).copied().unwrap_or_default() {
+                ColumnAlign::Left => 0,
+                ColumnAlign::Center => gap / 2,
+                ColumnAlign::Right => gap,
+            };
+            line.push(' ');
+            line.extend(std::iter::repeat_n(' ', left));
+            line.push_str(&text);
+            line.extend(std::iter::repeat_n(' ', gap - left + 1));
+            line.push(borders.vertical);
+        }
+        line
+    };
+
+    let mut lines = vec![line(borders.top), row_line(headers), line(borders.middle)];
+    lines.extend(rows.iter().map(|row| row_line(row)));
+    lines.push(line(borders.bottom));
+    lines
+}
+
+/// Narrowest a column gets when a table is squeezed
+const MIN_COLUMN: usize = 3;
+
+/// `text` cut to `width` columns, ending in `ellipsis` when cut
+fn fit(text: &str, width: usize, ellipsis: &str) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let room = width.saturating_sub(ellipsis.width());
+    let mut fitted = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        used += c.width().unwrap_or(0);
+        if used > room {
+            break;
+        }
+        fitted.push(c);
+    }
+    fitted.push_str(ellipsis);
+    fitted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap_font::BitmapFont;
+
+    #[test]
+    fn test_quantization() {
+        let caps = |color_depth| RenderCapabilities {
+            color_depth,
+            ..RenderCapabilities::default()
+        };
+        let red = [250, 10, 10];
+        assert_eq!(caps(ColorDepth::TrueColor).quantize(red), red);
+        assert_eq!(nearest_256(red), 196);
+        assert_eq!(caps(ColorDepth::Ansi256).quantize(red), [255, 0, 0]);
+        assert_eq!(caps(ColorDepth::Ansi16).quantize(red), [255, 0, 0]);
+
+        // Grays land on the ramp rather than the cube
+        assert_eq!(nearest_256([128, 128, 128]), 244);
+        assert_eq!(
+            caps(ColorDepth::Ansi256).quantize([30, 30, 30]),
+            [28, 28, 28]
+        );
+        assert_eq!(
+            caps(ColorDepth::Ansi16).quantize([100, 110, 240]),
+            [92, 92, 255]
+        );
+    }
+
+    #[test]
+    fn test_derive_from_font_theme_and_setting() {
+        let font = BitmapFont::default();
+        let gpu = RenderCapabilities::derive(RendererKind::Gpu, &font, "dark", None);
+        assert_eq!(gpu.color_depth, ColorDepth::TrueColor);
+        assert_eq!(
+            gpu.box_drawing,
+            BOX_DRAWING.iter().all(|&ch| font.covers(ch))
+        );
+
+        let cpu = RenderCapabilities::derive(RendererKind::Cpu, &font, "dark", None);
+        assert_eq!(cpu.color_depth, ColorDepth::Ansi256);
+        let themed = RenderCapabilities::derive(RendererKind::Gpu, &font, "solarized-16", None);
+        assert_eq!(themed.color_depth, ColorDepth::Ansi16);
+        let forced = forced_color_depth("256");
+        let forced = RenderCapabilities::derive(RendererKind::Gpu, &font, "solarized-16", forced);
+        assert_eq!(forced.color_depth, ColorDepth::Ansi256);
+        assert_eq!(forced_color_depth("auto"), None);
+    }
+
+    #[test]
+    fn test_table_borders() {
+        let headers = vec!["name".to_string(), "size".to_string()];
+        let rows = vec![vec!["a.txt".to_string(), "1 KiB".to_string()]];
+        assert_eq!(
+            render_table(&headers, &rows, &ASCII_BORDERS),
+            [
+                "+-------+-------+",
+                "| name  | size  |",
+                "+-------+-------+",
+                "| a.txt | 1 KiB |",
+                "+-------+-------+",
+            ]
+        );
+        assert_eq!(
+            render_table(&headers, &rows, &UNICODE_BORDERS)[0],
+            "┌───────┬───────┐"
+        );
+    }
+
+    #[test]
+    fn test_table_aligns_and_fits() {
+        let headers = vec!["n".to_string(), "description".to_string()];
+        let rows = vec![vec!["10".to_string(), "a rather long note".to_string()]];
+        let aligns = [ColumnAlign::Right, ColumnAlign::Center];
+        let lines = render_table_fitted(&headers, &rows, &aligns, &UNICODE_BORDERS, 20);
+        assert_eq!(
+            lines,
+            [
+                "┌────┬─────────────┐",
+                "│  n │ description │",
+                "├────┼─────────────┤",
+                "│ 10 │ a rather l… │",
+                "└────┴─────────────┘",
+            ]
+        );
+        assert!(lines.iter().all(|line| line.width() <= 20));
+    }
+}
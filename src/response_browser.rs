@@ -0,0 +1,300 @@
+use crate::calc;
+use crate::input::{Key, KeyEvent};
+use crate::markdown_stream::MarkdownStream;
+use crate::paste_guard::{self, SpanStyle};
+use crate::render_caps::RenderCapabilities;
+use crate::response_log::HistoryRecord;
+use crate::syntax_highlight::SyntaxHighlighter;
+use crate::terminal::TerminalCell;
+use std::collections::VecDeque;
+
+/// The overlay background paste_guard draws with
+const BACKGROUND: [f32; 4] = [0.12, 0.12, 0.14, 1.0];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrowserOutcome {
+    Pending,
+    /// Put the shown response on the clipboard and close
+    Copy(String),
+    Close,
+}
+
+/// Answers kept in memory for browsing, oldest first
+#[derive(Debug, Clone)]
+pub struct ResponseHistory {
+    records: VecDeque<HistoryRecord>,
+    max_entries: usize,
+}
+
+impl ResponseHistory {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            max_entries,
+        }
+    }
+
+    pub fn add(&mut self, record: HistoryRecord) {
+        if self.records.len() >= self.max_entries {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub fn latest(&self) -> Option<&HistoryRecord> {
+        self.records.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+/// Full-screen overlay showing one past response at a time
+#[derive(Debug, Clone)]
+pub struct ResponseBrowser {
+    records: Vec<HistoryRecord>,
+    selected: usize,
+    /// First content line shown
+    scroll: usize,
+    highlighter: Option<SyntaxHighlighter>,
+    capabilities: RenderCapabilities,
+    /// The selected response rendered, and the record and width it was
+    /// rendered for
+    rendered: Option<(usize, u32, Vec<Vec<TerminalCell>>)>,
+}
+
+impl ResponseBrowser {
+    /// Opens on the newest response; `None` when there are none
+    pub fn new(history: &ResponseHistory) -> Option<Self> {
+        let records: Vec<_> = history.records.iter().cloned().collect();
+        let selected = records.len().checked_sub(1)?;
+        Some(Self {
+            records,
+            selected,
+            scroll: 0,
+            highlighter: None,
+            capabilities: RenderCapabilities::default(),
+            rendered: None,
+        })
+    }
+
+    /// Color code blocks in the responses shown
+    pub fn with_highlighting(mut self, highlighter: SyntaxHighlighter) -> Self {
+        self.highlighter = Some(highlighter);
+        self
+    }
+
+    /// Draw tables and bullets with what the renderer's font has
+    pub fn with_capabilities(mut self, capabilities: RenderCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn selected(&self) -> &HistoryRecord {
+        &self.records[self.selected]
+    }
+
+    pub fn navigate_previous(&mut self) {
+        if self.selected > 0 {
+            self.selected -= 1;
+            self.scroll = 0;
+        }
+    }
+
+    pub fn navigate_next(&mut self) {
+        if self.selected + 1 < self.records.len() {
+            self.selected += 1;
+            self.scroll = 0;
+        }
+    }
+
+    /// `[` and Left go to older responses, `]` and Right to newer ones,
+    /// Up/Down and the page keys scroll, Enter copies, Esc closes
+    pub fn key(&mut self, event: &KeyEvent) -> BrowserOutcome {
+        match event.key {
+            Key::Char('[') | Key::Left => self.navigate_previous(),
+            Key::Char(']') | Key::Right => self.navigate_next(),
+            Key::Up => self.scroll = self.scroll.saturating_sub(1),
+            Key::Down => self.scroll += 1,
+            Key::PageUp => self.scroll = self.scroll.saturating_sub(10),
+            Key::PageDown => self.scroll += 10,
+            Key::Enter | Key::KpEnter => {
+                return BrowserOutcome::Copy(self.selected().content.clone());
+            }
+            Key::Escape => return BrowserOutcome::Close,
+            _ => {}
+        }
+        BrowserOutcome::Pending
+    }
+
+    /// Scroll by wheel lines, positive toward the top of the response
+    pub fn scroll_by(&mut self, lines: isize) {
+        self.scroll = self.scroll.saturating_add_signed(-lines);
+    }
+
+    /// `[3/10] 2024-05-01 14:02 — model: gpt-4o — 1,204 tokens`
+    pub fn header(&self) -> String {
+        let record = self.selected();
+        let time = calc::format_iso((record.started_at / 1000) as i64);
+        let mut header = format!(
+            "[{}/{}] {} — model: {} — {} tokens",
+            self.selected + 1,
+            self.records.len(),
+            time[..16].replace('T', " "),
+            record.params.model,
+            thousands(record.total_tokens)
+        );
+        if record.interrupted {
+            header.push_str(" — interrupted");
+        }
+        header
+    }
+
+    /// The response rendered at `width`, kept until another is selected
+    fn rendered(&mut self, width: u32) -> &[Vec<TerminalCell>] {
+        let stale = !matches!(&self.rendered, Some((selected, rendered_width, _))
+            if *selected == self.selected && *rendered_width == width);
+        if stale {
+            let mut markdown = MarkdownStream::new(width, self.highlighter.clone())
+                .with_capabilities(self.capabilities);
+            markdown.push(&self.selected().content);
+            let rows = markdown.rows().cloned().collect();
+            self.rendered = Some((self.selected, width, rows));
+        }
+        self.rendered.as_ref().map_or(&[], |(_, _, rows)| rows)
+    }
+
+    /// The header, the response rendered to `width` from the scroll
+    /// position, and the key help, filling the screen
+    pub fn overlay_rows(&mut self, width: u32, height: u32) -> Vec<Vec<TerminalCell>> {
+        let help = "[ ] older/newer · ↑↓ scroll · Enter copy · Esc close";
+        let header = self.header();
+        let room = (height as usize).saturating_sub(2);
+        let lines = self.rendered(width).to_vec();
+        self.scroll = self.scroll.min(lines.len().saturating_sub(room));
+
+        let mut rows = vec![paste_guard::cells(
+            &[(SpanStyle::Control, header)],
+            width,
+            true,
+        )];
+        let filler = TerminalCell {
+            background: BACKGROUND,
+            ..TerminalCell::default()
+        };
+        for line in lines.into_iter().skip(self.scroll).take(room) {
+            let mut row: Vec<_> = line
+                .into_iter()
+                .map(|cell| TerminalCell {
+                    background: BACKGROUND,
+                    ..cell
+                })
+                .collect();
+            row.resize(width as usize, filler.clone());
+            rows.push(row);
+        }
+        rows.resize_with(height.saturating_sub(1) as usize, || {
+            paste_guard::cells(&[], width, false)
+        });
+        rows.push(paste_guard::cells(
+            &[(SpanStyle::Plain, help.to_string())],
+            width,
+            true,
+        ));
+        rows
+    }
+}
+
+fn thousands(n: u32) -> String {
+    let digits = n.to_string();
+    let mut text = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            text.push(',');
+        }
+        text.push(digit);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response_log::RequestParams;
+    use std::collections::HashSet;
+    use std::time::Instant;
+
+    fn key(key: Key) -> KeyEvent {
+        KeyEvent {
+            key,
+            modifiers: HashSet::new(),
+            text: None,
+            repeat: false,
+            timestamp: Instant::now(),
+            key_code: None,
+        }
+    }
+
+    fn record(content: &str, model: &str, total_tokens: u32) -> HistoryRecord {
+        HistoryRecord {
+            id: content.to_string(),
+            prompt: "why".to_string(),
+            params: RequestParams {
+                model: model.to_string(),
+                temperature: 0.7,
+                max_tokens: 2048,
+            },
+            content: content.to_string(),
+            interrupted: false,
+            total_tokens,
+            tokens_per_second: 10.0,
+            // 2024-05-01 14:02:00 UTC
+            started_at: 1_714_572_120_000,
+            completed_at: 1_714_572_125_000,
+        }
+    }
+
+    #[test]
+    fn test_history_is_capped() {
+        let mut history = ResponseHistory::new(2);
+        assert!(ResponseBrowser::new(&history).is_none());
+        for content in ["one", "two", "three"] {
+            history.add(record(content, "local", 1));
+        }
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.latest().unwrap().content, "three");
+    }
+
+    #[test]
+    fn test_browse_and_copy() {
+        let mut history = ResponseHistory::new(10);
+        history.add(record("first\n\nanswer", "gpt-4o", 1204));
+        history.add(record("second", "local", 3));
+        let mut browser = ResponseBrowser::new(&history).unwrap();
+        assert_eq!(browser.selected().content, "second");
+
+        assert_eq!(browser.key(&key(Key::Char('['))), BrowserOutcome::Pending);
+        assert_eq!(
+            browser.header(),
+            "[1/2] 2024-05-01 14:02 — model: gpt-4o — 1,204 tokens"
+        );
+        browser.key(&key(Key::Left));
+        assert_eq!(browser.selected().content, "first\n\nanswer");
+
+        let rows = browser.overlay_rows(20, 6);
+        assert_eq!(rows.len(), 6);
+        let text: String = rows[3].iter().map(|cell| cell.character).collect();
+        assert_eq!(text.trim_end(), "answer");
+
+        assert_eq!(
+            browser.key(&key(Key::Enter)),
+            BrowserOutcome::Copy("first\n\nanswer".to_string())
+        );
+        assert_eq!(browser.key(&key(Key::Escape)), BrowserOutcome::Close);
+    }
+}
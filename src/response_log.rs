@@ -0,0 +1,461 @@
+use crate::config::AgentConfig;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ResponseLogError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Encoding error: {0}")]
+    Encode(#[from] serde_json::Error),
+    #[error("Record too large: {0} bytes")]
+    RecordTooLarge(usize),
+    #[error("Data directory not found")]
+    NoDataDir,
+}
+
+const SEGMENT_EXTENSION: &str = "seg";
+
+/// Length and CRC32 of the payload, both little-endian
+const FRAME_HEADER: usize = 8;
+
+/// A single record larger than this is treated as corruption when reading
+const MAX_RECORD_BYTES: usize = 64 * 1024 * 1024;
+
+/// Request parameters kept with a response so it can be retried as sent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestParams {
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: u32,
+}
+
+/// A record kept in segments; readers merge segments by `order_key`
+pub trait LogRecord: Serialize + DeserializeOwned {
+    /// Completion time in unix milliseconds, then a tie-breaking id
+    fn order_key(&self) -> (u64, &str);
+}
+
+/// A completed (or interrupted) response as stored on disk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    pub id: String,
+    pub prompt: String,
+    pub params: RequestParams,
+    pub content: String,
+    pub interrupted: bool,
+    pub total_tokens: u32,
+    pub tokens_per_second: f32,
+    /// Unix milliseconds
+    pub started_at: u64,
+    pub completed_at: u64,
+}
+
+impl LogRecord for HistoryRecord {
+    fn order_key(&self) -> (u64, &str) {
+        (self.completed_at, &self.id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseLogConfig {
+    pub dir: PathBuf,
+    /// A new segment is started once the active one reaches this size
+    pub segment_bytes: u64,
+    /// Oldest segments are deleted while the directory is larger
+    pub max_total_bytes: u64,
+    pub max_age: Option<Duration>,
+    pub restore: usize,
+}
+
+impl ResponseLogConfig {
+    pub const DEFAULT_SEGMENT_BYTES: u64 = 1024 * 1024;
+
+    /// `~/.local/share/ferroterm/history/<profile>`, so profiles never see
+    /// each other's responses
+    pub fn from_config(
+        agent: &AgentConfig,
+        profile: Option<&str>,
+    ) -> Result<Self, ResponseLogError> {
+        let dir = dirs::data_dir()
+            .ok_or(ResponseLogError::NoDataDir)?
+            .join("ferroterm")
+            .join("history")
+            .join(profile.unwrap_or("default"));
+        let max_total_bytes = agent.history_max_mb as u64 * 1024 * 1024;
+        Ok(Self {
+            dir,
+            segment_bytes: Self::DEFAULT_SEGMENT_BYTES.min(max_total_bytes.max(1)),
+            max_total_bytes,
+            max_age: (agent.history_max_age_days > 0)
+                .then(|| Duration::from_secs(agent.history_max_age_days as u64 * 86_400)),
+            restore: agent.history_restore as usize,
+        })
+    }
+}
+
+/// Records read back from disk
+#[derive(Debug)]
+pub struct LoadReport<R = HistoryRecord> {
+    /// Oldest first
+    pub records: Vec<R>,
+    /// Segments that ended in a partial or corrupt record, typically from a
+    /// crash mid-write; everything before it was kept
+    pub damaged_segments: usize,
+}
+
+impl<R> Default for LoadReport<R> {
+    fn default() -> Self {
+        Self {
+            records: Vec::new(),
+            damaged_segments: 0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PruneReport {
+    pub removed_segments: usize,
+    pub freed_bytes: u64,
+}
+
+struct Segment {
+    path: PathBuf,
+    len: u64,
+    modified: SystemTime,
+}
+
+/// Append-only response store. Every instance writes only to segments it
+/// created, named `<start millis>-<instance>-<seq>.seg`, so several
+/// terminals can share the directory without locking; readers merge all
+/// segments by completion time.
+pub struct ResponseLog<R = HistoryRecord> {
+    config: ResponseLogConfig,
+    instance: String,
+    seq: u32,
+    active: Option<(PathBuf, File, u64)>,
+    record: PhantomData<fn() -> R>,
+}
+
+impl<R: LogRecord> ResponseLog<R> {
+    pub fn open(config: ResponseLogConfig) -> Result<Self, ResponseLogError> {
+        fs::create_dir_all(&config.dir)?;
+        let instance = format!(
+            "{:013}-{}",
+            unix_millis(SystemTime::now()),
+            &uuid::Uuid::new_v4().simple().to_string()[..8]
+        );
+        Ok(Self {
+            config,
+            instance,
+            seq: 0,
+            active: None,
+            record: PhantomData,
+        })
+    }
+
+    pub fn config(&self) -> &ResponseLogConfig {
+        &self.config
+    }
+
+    /// Write one record and flush it to disk before returning
+    pub fn append(&mut self, record: &R) -> Result<(), ResponseLogError> {
+        let frame = encode_frame(record)?;
+        let full = self.active.as_ref().is_some_and(|(_, _, len)| {
+            *len > 0 && len + frame.len() as u64 > self.config.segment_bytes
+        });
+        if full || self.active.is_none() {
+            self.rotate()?;
+        }
+        let (_, file, len) = self.active.as_mut().expect("rotate opens a segment");
+        file.write_all(&frame)?;
+        file.sync_data()?;
+        *len += frame.len() as u64;
+        self.prune()?;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), ResponseLogError> {
+        let path = self.config.dir.join(format!(
+            "{}-{:04}.{}",
+            self.instance, self.seq, SEGMENT_EXTENSION
+        ));
+        self.seq += 1;
+        let file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(&path)?;
+        self.active = Some((path, file, 0));
+        Ok(())
+    }
+
+    /// The newest `limit` records across every instance's segments
+    pub fn load_recent(&self, limit: usize) -> Result<LoadReport<R>, ResponseLogError> {
+        let mut report = LoadReport::<R>::default();
+        for segment in self.segments()? {
+            let mut bytes = Vec::new();
+            match File::open(&segment.path) {
+                Ok(mut file) => file.read_to_end(&mut bytes)?,
+                // Pruned by another instance while we listed the directory
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let (records, intact) = decode_frames(&bytes);
+            if !intact {
+                tracing::warn!("Ignoring damaged tail of {}", segment.path.display());
+                report.damaged_segments += 1;
+            }
+            report.records.extend(records);
+        }
+        report
+            .records
+            .sort_by(|a, b| a.order_key().cmp(&b.order_key()));
+        let skip = report.records.len().saturating_sub(limit);
+        report.records.drain(..skip);
+        Ok(report)
+    }
+
+    /// Apply the age limit and size cap now, oldest segments first. The
+    /// segment this instance is writing is never removed.
+    pub fn prune(&self) -> Result<PruneReport, ResponseLogError> {
+        self.prune_to(self.config.max_total_bytes, self.config.max_age)
+    }
+
+    pub fn prune_to(
+        &self,
+        max_total_bytes: u64,
+        max_age: Option<Duration>,
+    ) -> Result<PruneReport, ResponseLogError> {
+        let active = self.active.as_ref().map(|(path, _, _)| path.as_path());
+        let cutoff = max_age.and_then(|age| SystemTime::now().checked_sub(age));
+        let segments = self.segments()?;
+        let mut total: u64 = segments.iter().map(|segment| segment.len).sum();
+        let mut report = PruneReport::default();
+        for segment in segments {
+            let expired = cutoff.is_some_and(|cutoff| segment.modified < cutoff);
+            if (total <= max_total_bytes && !expired) || Some(segment.path.as_path()) == active {
+                continue;
+            }
+            match fs::remove_file(&segment.path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            total -= segment.len;
+            report.removed_segments += 1;
+            report.freed_bytes += segment.len;
+        }
+        Ok(report)
+    }
+
+    /// Segment files, least recently written first
+    fn segments(&self) -> Result<Vec<Segment>, ResponseLogError> {
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&self.config.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            segments.push(Segment {
+                path,
+                len: metadata.len(),
+                modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+            });
+        }
+        segments.sort_by(|a, b| (a.modified, &a.path).cmp(&(b.modified, &b.path)));
+        Ok(segments)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.config.dir
+    }
+}
+
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn encode_frame<R: Serialize>(record: &R) -> Result<Vec<u8>, ResponseLogError> {
+    let payload = serde_json::to_vec(record)?;
+    if payload.len() > MAX_RECORD_BYTES {
+        return Err(ResponseLogError::RecordTooLarge(payload.len()));
+    }
+    let mut frame = Vec::with_capacity(FRAME_HEADER + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Records up to the first partial, corrupt or undecodable frame, and
+/// whether the whole buffer was read
+fn decode_frames<R: DeserializeOwned>(mut bytes: &[u8]) -> (Vec<R>, bool) {
+    let mut records = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < FRAME_HEADER {
+            return (records, false);
+        }
+        let len = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if len > MAX_RECORD_BYTES || bytes.len() < FRAME_HEADER + len {
+            return (records, false);
+        }
+        let payload = &bytes[FRAME_HEADER..FRAME_HEADER + len];
+        if crc32fast::hash(payload) != crc {
+            return (records, false);
+        }
+        match serde_json::from_slice(payload) {
+            Ok(record) => records.push(record),
+            Err(_) => return (records, false),
+        }
+        bytes = &bytes[FRAME_HEADER + len..];
+    }
+    (records, true)
+}
+
+/// One line for `:history prune`
+pub fn format_prune(report: &PruneReport) -> String {
+    format!(
+        "Removed {} history segment(s), freed {:.1} KiB",
+        report.removed_segments,
+        report.freed_bytes as f64 / 1024.0
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn config(dir: &Path, segment_bytes: u64, max_total_bytes: u64) -> ResponseLogConfig {
+        ResponseLogConfig {
+            dir: dir.to_path_buf(),
+            segment_bytes,
+            max_total_bytes,
+            max_age: None,
+            restore: 100,
+        }
+    }
+
+    fn record(id: &str, completed_at: u64) -> HistoryRecord {
+        HistoryRecord {
+            id: id.to_string(),
+            prompt: format!("prompt {}", id),
+            params: RequestParams {
+                model: "mistral-7b-instruct".to_string(),
+                temperature: 0.35,
+                max_tokens: 512,
+            },
+            content: "x".repeat(200),
+            interrupted: id.ends_with('!'),
+            total_tokens: 42,
+            tokens_per_second: 12.5,
+            started_at: completed_at - 1500,
+            completed_at,
+        }
+    }
+
+    fn segment_paths(dir: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_reload_fidelity_and_truncated_tail() {
+        let dir = TempDir::new().unwrap();
+        let mut log = ResponseLog::open(config(dir.path(), 1 << 20, 1 << 30)).unwrap();
+        let first = record("a", 1_700_000_000_000);
+        let second = record("b!", 1_700_000_005_000);
+        log.append(&first).unwrap();
+        log.append(&second).unwrap();
+        drop(log);
+
+        let reopened: ResponseLog = ResponseLog::open(config(dir.path(), 1 << 20, 1 << 30)).unwrap();
+        let report = reopened.load_recent(10).unwrap();
+        assert_eq!(report.records, vec![first.clone(), second.clone()]);
+        assert_eq!(report.damaged_segments, 0);
+        assert_eq!(reopened.load_recent(1).unwrap().records, vec![second]);
+
+        // A crash mid-write leaves half a frame; the intact records survive
+        let path = segment_paths(dir.path()).remove(0);
+        let len = fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 10).unwrap();
+        let report = reopened.load_recent(10).unwrap();
+        assert_eq!(report.records, vec![first]);
+        assert_eq!(report.damaged_segments, 1);
+    }
+
+    #[test]
+    fn test_instances_write_separate_segments() {
+        let dir = TempDir::new().unwrap();
+        let mut one = ResponseLog::open(config(dir.path(), 1 << 20, 1 << 30)).unwrap();
+        let mut two = ResponseLog::open(config(dir.path(), 1 << 20, 1 << 30)).unwrap();
+        for i in 0..5u64 {
+            one.append(&record(&format!("one-{}", i), 1_000_000 + i * 2))
+                .unwrap();
+            two.append(&record(&format!("two-{}", i), 1_000_001 + i * 2))
+                .unwrap();
+        }
+        assert_eq!(segment_paths(dir.path()).len(), 2);
+
+        let ids: Vec<String> = one
+            .load_recent(100)
+            .unwrap()
+            .records
+            .into_iter()
+            .map(|record| record.id)
+            .collect();
+        assert_eq!(ids.len(), 10);
+        assert_eq!(ids[0], "one-0");
+        assert_eq!(ids[1], "two-0");
+        assert_eq!(ids[9], "two-4");
+    }
+
+    #[test]
+    fn test_size_cap_prunes_oldest_segments() {
+        let dir = TempDir::new().unwrap();
+        let frame = encode_frame(&record("00", 1_000_000)).unwrap().len() as u64;
+        // Two records per segment, at most three segments' worth in total
+        let mut log = ResponseLog::open(config(dir.path(), frame * 2, frame * 6)).unwrap();
+        for i in 0..12u64 {
+            log.append(&record(&format!("{:02}", i), 1_000_000 + i))
+                .unwrap();
+        }
+        let total: u64 = segment_paths(dir.path())
+            .iter()
+            .map(|path| fs::metadata(path).unwrap().len())
+            .sum();
+        assert!(total <= frame * 6);
+        let ids: Vec<String> = log
+            .load_recent(100)
+            .unwrap()
+            .records
+            .into_iter()
+            .map(|record| record.id)
+            .collect();
+        assert_eq!(ids, ["06", "07", "08", "09", "10", "11"]);
+
+        // An explicit prune to zero keeps only the active segment
+        let report = log.prune_to(0, None).unwrap();
+        assert_eq!(report.removed_segments, 2);
+        assert_eq!(segment_paths(dir.path()).len(), 1);
+        assert_eq!(log.load_recent(100).unwrap().records.len(), 2);
+    }
+}
@@ -0,0 +1,480 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ScaffoldError {
+    #[error("Absolute path not allowed: {0}")]
+    AbsolutePath(String),
+    #[error("Path escapes the target directory: {0}")]
+    Traversal(String),
+    #[error("Empty file path")]
+    EmptyPath,
+    #[error("Failed to write {path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+/// Info-string keys that name the file, as in ```` ```rust title=src/main.rs ````
+const PATH_KEYS: &[&str] = &["title", "file", "filename", "path", "name"];
+
+/// Label prefixes on the line before a block, as in `File: src/main.rs`
+const PATH_LABELS: &[&str] = &["file:", "filename:", "path:"];
+
+/// Fenced code block from a model response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub language: Option<String>,
+    /// As written by the model, not yet validated
+    pub path: Option<String>,
+    pub content: String,
+}
+
+/// Fenced blocks in the order they appear, each with the file path the
+/// model associated with it. An unterminated block runs to the end.
+pub fn extract_blocks(response: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut previous: Option<&str> = None;
+    let mut lines = response.lines();
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+        let Some(fence) = fence_of(trimmed) else {
+            if !trimmed.trim().is_empty() {
+                previous = Some(trimmed.trim());
+            }
+            continue;
+        };
+        let indent = line.len() - trimmed.len();
+        let (language, info_path) = parse_info(&trimmed[fence.len()..]);
+
+        let mut content = Vec::new();
+        for line in lines.by_ref() {
+            if closes(line.trim(), fence) {
+                break;
+            }
+            // Drop the indentation of a fence nested in a list item
+            let margin = line.len() - line.trim_start().len();
+            content.push(&line[margin.min(indent)..]);
+        }
+        let mut content = content.join("\n");
+        if !content.is_empty() {
+            content.push('\n');
+        }
+
+        let path = info_path.or_else(|| previous.and_then(path_from_label));
+        blocks.push(CodeBlock {
+            language,
+            path,
+            content,
+        });
+        previous = None;
+    }
+
+    blocks
+}
+
+/// A closing fence is at least as long as the opening one, with no info string
+fn closes(line: &str, fence: &str) -> bool {
+    line.starts_with(fence) && line.bytes().all(|b| b == fence.as_bytes()[0])
+}
+
+fn fence_of(line: &str) -> Option<&str> {
+    ["````", "```", "~~~"]
+        .into_iter()
+        .find(|fence| line.starts_with(fence))
+        .map(|fence| {
+            let marker = fence.as_bytes()[0];
+            let len = line.bytes().take_while(|&b| b == marker).count();
+            &line[..len]
+        })
+}
+
+/// Language and path from an info string such as `rust title="src/main.rs"`,
+/// `rust:src/main.rs` or a bare `src/main.rs`
+fn parse_info(info: &str) -> (Option<String>, Option<String>) {
+    let mut language = None;
+    let mut path = None;
+
+    for (i, token) in info.split_whitespace().enumerate() {
+        if let Some((key, value)) = token.split_once('=') {
+            if PATH_KEYS.contains(&key.to_lowercase().as_str()) {
+                path = Some(value.trim_matches(|c| c == '"' || c == '\'').to_string());
+            }
+        } else if i == 0 {
+            match token.split_once(':') {
+                Some((lang, file)) if !lang.is_empty() && looks_like_path(file) => {
+                    language = Some(lang.to_string());
+                    path = Some(file.to_string());
+                }
+                // Language names have no dots or slashes, file names do
+                _ if looks_like_path(token) && token.contains(['/', '.']) => {
+                    path = Some(token.to_string())
+                }
+                _ => language = Some(token.to_string()),
+            }
+        }
+    }
+
+    (language, path.filter(|p| !p.is_empty()))
+}
+
+/// Path from the line before a block, when that line is only a file name:
+/// `**src/main.rs**`, `` `src/main.rs`: ``, `### src/main.rs` or
+/// `File: src/main.rs`. Prose that merely mentions a file does not count.
+fn path_from_label(line: &str) -> Option<String> {
+    let mut text = line.trim();
+    let heading = text.starts_with('#');
+    text = text.trim_start_matches('#').trim();
+    for bullet in ["- ", "* ", "+ "] {
+        text = text.strip_prefix(bullet).unwrap_or(text);
+    }
+
+    let plain = text.replace("**", "").replace('`', "");
+    let emphasized = plain.len() != text.len();
+    let plain = plain.trim().trim_end_matches(':').trim();
+    let (explicit, name) = match PATH_LABELS.iter().find(|label| {
+        plain.len() >= label.len() && plain[..label.len()].eq_ignore_ascii_case(label)
+    }) {
+        Some(label) => (true, plain[label.len()..].trim()),
+        None => (false, plain),
+    };
+
+    (looks_like_path(name) && (explicit || emphasized || heading)).then(|| name.to_string())
+}
+
+/// One token with an extension or a directory, e.g. `Cargo.toml`, `src/lib.rs`
+fn looks_like_path(text: &str) -> bool {
+    !text.is_empty()
+        && !text.contains(char::is_whitespace)
+        && !text.ends_with(['.', '/'])
+        && (text.contains('/')
+            || text.rsplit_once('.').is_some_and(|(stem, ext)| {
+                (!stem.is_empty() || text.starts_with('.'))
+                    && ext.chars().all(|c| c.is_ascii_alphanumeric())
+            })
+            || matches!(text, "Makefile" | "Dockerfile" | "Justfile"))
+}
+
+/// Relative path inside the target directory; absolute paths and `..` are
+/// rejected rather than normalized
+pub fn validate_path(path: &str) -> Result<PathBuf, ScaffoldError> {
+    let candidate = Path::new(path);
+    if candidate.is_absolute() || path.starts_with(['/', '\\']) {
+        return Err(ScaffoldError::AbsolutePath(path.to_string()));
+    }
+    let mut relative = PathBuf::new();
+    for component in candidate.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => return Err(ScaffoldError::Traversal(path.to_string())),
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(ScaffoldError::AbsolutePath(path.to_string()));
+            }
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Err(ScaffoldError::EmptyPath);
+    }
+    Ok(relative)
+}
+
+#[derive(Debug, Clone)]
+pub struct PlannedFile {
+    pub path: PathBuf,
+    pub content: String,
+    /// A file already exists there
+    pub overwrite: bool,
+}
+
+/// Block that will not be written, by its 1-based position in the response
+#[derive(Debug)]
+pub struct SkippedBlock {
+    pub block: usize,
+    pub path: Option<String>,
+    /// `None` when the block had no detectable path
+    pub reason: Option<ScaffoldError>,
+}
+
+#[derive(Debug)]
+pub struct FileResult {
+    pub path: PathBuf,
+    /// Bytes written
+    pub outcome: Result<usize, ScaffoldError>,
+}
+
+/// What `:scaffold <dir>` would write, shown for confirmation before
+/// anything touches the disk
+#[derive(Debug)]
+pub struct ScaffoldPlan {
+    pub root: PathBuf,
+    pub files: Vec<PlannedFile>,
+    pub skipped: Vec<SkippedBlock>,
+}
+
+impl ScaffoldPlan {
+    /// When several blocks name the same file the last one wins, matching a
+    /// model that revises a file further down its answer
+    pub fn new(response: &str, root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let mut files: Vec<PlannedFile> = Vec::new();
+        let mut skipped = Vec::new();
+
+        for (i, block) in extract_blocks(response).into_iter().enumerate() {
+            let Some(raw) = block.path else {
+                skipped.push(SkippedBlock {
+                    block: i + 1,
+                    path: None,
+                    reason: None,
+                });
+                continue;
+            };
+            match validate_path(&raw) {
+                Ok(path) => {
+                    files.retain(|file| file.path != path);
+                    let overwrite = root.join(&path).exists();
+                    files.push(PlannedFile {
+                        path,
+                        content: block.content,
+                        overwrite,
+                    });
+                }
+                Err(e) => skipped.push(SkippedBlock {
+                    block: i + 1,
+                    path: Some(raw),
+                    reason: Some(e),
+                }),
+            }
+        }
+
+        Self {
+            root,
+            files,
+            skipped,
+        }
+    }
+
+    pub fn overwrites(&self) -> usize {
+        self.files.iter().filter(|file| file.overwrite).count()
+    }
+
+    /// Listing shown before asking for confirmation
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!("Scaffold into {}:", self.root.display())];
+        for file in &self.files {
+            lines.push(format!(
+                "  {} {} ({} bytes)",
+                if file.overwrite {
+                    "overwrite"
+                } else {
+                    "create   "
+                },
+                file.path.display(),
+                file.content.len()
+            ));
+        }
+        for skip in &self.skipped {
+            lines.push(match (&skip.path, &skip.reason) {
+                (Some(path), Some(reason)) => {
+                    format!("  skip      block {} ({}): {}", skip.block, path, reason)
+                }
+                _ => format!("  skip      block {}: no file path", skip.block),
+            });
+        }
+        lines.push(format!(
+            "Write {} files ({} overwritten)? [y/N]",
+            self.files.len(),
+            self.overwrites()
+        ));
+        lines.join("\n")
+    }
+
+    /// Create directories and write every planned file, continuing past
+    /// failures. A file whose directory resolves outside the root, e.g.
+    /// through a symlink, is refused.
+    pub fn write(&self) -> Vec<FileResult> {
+        let root = fs::create_dir_all(&self.root).and_then(|_| self.root.canonicalize());
+        self.files
+            .iter()
+            .map(|file| FileResult {
+                path: file.path.clone(),
+                outcome: match &root {
+                    Ok(root) => write_file(root, file),
+                    Err(e) => Err(ScaffoldError::Io {
+                        path: self.root.display().to_string(),
+                        source: std::io::Error::new(e.kind(), e.to_string()),
+                    }),
+                },
+            })
+            .collect()
+    }
+}
+
+fn write_file(root: &Path, file: &PlannedFile) -> Result<usize, ScaffoldError> {
+    let target = root.join(&file.path);
+    let io = |source| ScaffoldError::Io {
+        path: file.path.display().to_string(),
+        source,
+    };
+    let parent = target.parent().unwrap_or(root);
+    fs::create_dir_all(parent).map_err(io)?;
+    if !parent.canonicalize().map_err(io)?.starts_with(root) {
+        return Err(ScaffoldError::Traversal(file.path.display().to_string()));
+    }
+    fs::write(&target, &file.content).map_err(io)?;
+    Ok(file.content.len())
+}
+
+/// Per-file outcome, one line each
+pub fn format_results(results: &[FileResult]) -> String {
+    results
+        .iter()
+        .map(|result| match &result.outcome {
+            Ok(bytes) => format!("  wrote  {} ({} bytes)", result.path.display(), bytes),
+            Err(e) => format!("  failed {}: {}", result.path.display(), e),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn paths(response: &str) -> Vec<Option<String>> {
+        extract_blocks(response)
+            .into_iter()
+            .map(|block| block.path)
+            .collect()
+    }
+
+    #[test]
+    fn test_path_detection_corpus() {
+        // Info-string key=value, as emitted with docs-style fences
+        let titled = "Here you go:\n\n```rust title=\"src/main.rs\"\nfn main() {}\n```\n\n```toml file=Cargo.toml\n[package]\n```\n";
+        assert_eq!(
+            paths(titled),
+            [Some("src/main.rs".into()), Some("Cargo.toml".into())]
+        );
+
+        // Bold filename on the line before, with a blank line in between
+        let bold = "**src/lib.rs**\n\n```rust\npub fn add() {}\n```\n";
+        let blocks = extract_blocks(bold);
+        assert_eq!(blocks[0].path.as_deref(), Some("src/lib.rs"));
+        assert_eq!(blocks[0].language.as_deref(), Some("rust"));
+        assert_eq!(blocks[0].content, "pub fn add() {}\n");
+
+        // Inline code with a trailing colon, headings, and list items
+        let mixed = "1. First the manifest\n\n`Cargo.toml`:\n```toml\n[package]\n```\n\n### src/bin/cli.rs\n```rust\nfn main() {}\n```\n\n- **tests/it.rs**\n  ```rust\n  #[test]\n  fn works() {}\n  ```\n";
+        assert_eq!(
+            paths(mixed),
+            [
+                Some("Cargo.toml".into()),
+                Some("src/bin/cli.rs".into()),
+                Some("tests/it.rs".into())
+            ]
+        );
+        assert_eq!(extract_blocks(mixed)[2].content, "#[test]\nfn works() {}\n");
+
+        // Explicit label, bold label, language:path and a bare path info string
+        let labelled = "File: app/models.py\n```python\nclass User: pass\n```\n**File:** `app/views.py`\n```python\n```\n```js:web/index.js\nmain()\n```\n```web/style.css\nbody {}\n```\n";
+        assert_eq!(
+            paths(labelled),
+            [
+                Some("app/models.py".into()),
+                Some("app/views.py".into()),
+                Some("web/index.js".into()),
+                Some("web/style.css".into())
+            ]
+        );
+
+        // Prose that mentions a file, a shell transcript, and a label that
+        // belongs to an earlier block all leave the block without a path
+        let prose = "Update `main.rs` like this:\n```rust\nfn main() {}\n```\nRun it:\n```sh\ncargo run\n```\n";
+        assert_eq!(paths(prose), [None, None]);
+        let reused = "**a.txt**\n```\none\n```\n```\ntwo\n```\n";
+        assert_eq!(paths(reused), [Some("a.txt".into()), None]);
+
+        // Longer fences contain shorter ones; unterminated blocks run to the end
+        let nested =
+            "**README.md**\n````markdown\n```sh\nmake\n```\n````\n**Makefile**\n```make\nall:\n";
+        let blocks = extract_blocks(nested);
+        assert_eq!(blocks[0].content, "```sh\nmake\n```\n");
+        assert_eq!(blocks[1].path.as_deref(), Some("Makefile"));
+        assert_eq!(blocks[1].content, "all:\n");
+    }
+
+    #[test]
+    fn test_scaffold_writes_inside_target_only() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("project");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs"), "old").unwrap();
+
+        let response = "**src/main.rs**\n```rust\nfn main() {}\n```\n\
+            **docs/guide/intro.md**\n```md\n# Intro\n```\n\
+            **../escape.txt**\n```\nnope\n```\n\
+            **/etc/passwd**\n```\nnope\n```\n\
+            ```rust title=src/../../up.rs\n```\n\
+            ```sh\nls\n```\n";
+        let plan = ScaffoldPlan::new(response, &root);
+
+        assert_eq!(plan.files.len(), 2);
+        assert!(plan.files[0].overwrite);
+        assert!(!plan.files[1].overwrite);
+        assert!(matches!(
+            plan.skipped[0].reason,
+            Some(ScaffoldError::Traversal(_))
+        ));
+        assert!(matches!(
+            plan.skipped[1].reason,
+            Some(ScaffoldError::AbsolutePath(_))
+        ));
+        assert!(matches!(
+            plan.skipped[2].reason,
+            Some(ScaffoldError::Traversal(_))
+        ));
+        assert_eq!(
+            (plan.skipped[3].block, plan.skipped[3].reason.is_none()),
+            (6, true)
+        );
+        let summary = plan.summary();
+        assert!(summary.contains("overwrite src/main.rs (13 bytes)"));
+        assert!(summary.contains("block 6: no file path"));
+        assert!(summary.ends_with("Write 2 files (1 overwritten)? [y/N]"));
+
+        // Nothing is written until the plan is confirmed
+        assert_eq!(fs::read_to_string(root.join("src/main.rs")).unwrap(), "old");
+        let results = plan.write();
+        assert!(results.iter().all(|result| result.outcome.is_ok()));
+        assert_eq!(
+            fs::read_to_string(root.join("src/main.rs")).unwrap(),
+            "fn main() {}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("docs/guide/intro.md")).unwrap(),
+            "# Intro\n"
+        );
+        assert!(!dir.path().join("escape.txt").exists());
+        assert!(format_results(&results).contains("wrote  docs/guide/intro.md (8 bytes)"));
+
+        // A symlinked directory cannot carry a write outside the root
+        #[cfg(unix)]
+        {
+            let outside = dir.path().join("outside");
+            fs::create_dir_all(&outside).unwrap();
+            std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+            let plan = ScaffoldPlan::new("**link/x.txt**\n```\nx\n```\n", &root);
+            let results = plan.write();
+            assert!(matches!(
+                results[0].outcome,
+                Err(ScaffoldError::Traversal(_))
+            ));
+            assert!(!outside.join("x.txt").exists());
+        }
+    }
+}
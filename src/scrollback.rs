@@ -0,0 +1,613 @@
+use crate::config::UiConfig;
+use crate::terminal::TerminalCell;
+use std::collections::VecDeque;
+use std::mem::size_of;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ScrollbackError {
+    #[error("Compression error: {0}")]
+    Compression(#[from] std::io::Error),
+    #[error("Corrupt scrollback block at line {0}")]
+    Corrupt(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrollbackConfig {
+    /// Most recent lines kept as cell rows
+    pub live_lines: usize,
+    /// Lines per compressed block
+    pub block_lines: usize,
+    /// Total history, live plus compressed; older blocks are dropped whole
+    pub max_lines: usize,
+    /// Decompressed blocks kept for scrolling through old history
+    pub cached_blocks: usize,
+}
+
+impl Default for ScrollbackConfig {
+    fn default() -> Self {
+        Self {
+            live_lines: 2000,
+            block_lines: 256,
+            max_lines: 100_000,
+            cached_blocks: 4,
+        }
+    }
+}
+
+impl ScrollbackConfig {
+    pub fn from_config(ui: &UiConfig) -> Self {
+        Self {
+            live_lines: ui.scrollback_live_lines as usize,
+            max_lines: ui.scrollback_lines as usize,
+            ..Self::default()
+        }
+    }
+}
+
+/// Bytes held by each tier, for memory accounting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScrollbackMemory {
+    pub live_bytes: usize,
+    pub compressed_bytes: usize,
+    pub cache_bytes: usize,
+}
+
+impl ScrollbackMemory {
+    pub fn total(&self) -> usize {
+        self.live_bytes + self.compressed_bytes + self.cache_bytes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub line: usize,
+    /// Cell column of the first matched character
+    pub column: usize,
+}
+
+/// A run of old lines in compact form. Text and attributes are compressed
+/// separately so search only has to decompress the text.
+#[derive(Debug, Clone)]
+struct CompressedBlock {
+    first_line: usize,
+    line_count: usize,
+    text: Vec<u8>,
+    attrs: Vec<u8>,
+}
+
+type DecodedBlock = Arc<Vec<Vec<TerminalCell>>>;
+
+/// Tiered scrollback: recent lines as cell rows, older lines in compressed
+/// blocks that are decompressed on demand into a small LRU. Line indices are
+/// absolute and stay stable as old lines are evicted.
+#[derive(Debug, Clone)]
+pub struct Scrollback {
+    config: ScrollbackConfig,
+    /// Absolute index of the oldest retained line
+    first_line: usize,
+    blocks: VecDeque<CompressedBlock>,
+    live: VecDeque<Vec<TerminalCell>>,
+    /// Most recently used last, keyed by block first line
+    cache: VecDeque<(usize, DecodedBlock)>,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self::new(ScrollbackConfig::default())
+    }
+}
+
+impl Scrollback {
+    pub fn new(config: ScrollbackConfig) -> Self {
+        Self {
+            config,
+            first_line: 0,
+            blocks: VecDeque::new(),
+            live: VecDeque::new(),
+            cache: VecDeque::new(),
+        }
+    }
+
+    pub fn first_line(&self) -> usize {
+        self.first_line
+    }
+
+    /// One past the newest line
+    pub fn end_line(&self) -> usize {
+        self.first_line + self.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.iter().map(|b| b.line_count).sum::<usize>() + self.live.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.live.is_empty()
+    }
+
+    pub fn push_line(&mut self, cells: Vec<TerminalCell>) -> Result<(), ScrollbackError> {
+        self.live.push_back(cells);
+
+        let block_lines = self.config.block_lines.max(1);
+        if self.live.len() >= self.config.live_lines + block_lines {
+            let first_line = self.end_line() - self.live.len();
+            let lines: Vec<_> = self.live.drain(..block_lines).collect();
+            self.blocks.push_back(compress_block(first_line, &lines)?);
+        }
+
+        self.evict();
+        Ok(())
+    }
+
+    fn evict(&mut self) {
+        while self.len() > self.config.max_lines {
+            match self.blocks.pop_front() {
+                Some(block) => {
+                    self.first_line += block.line_count;
+                    self.cache.retain(|(first, _)| *first != block.first_line);
+                }
+                None => {
+                    self.live.pop_front();
+                    self.first_line += 1;
+                }
+            }
+        }
+    }
+
+    /// Cells of absolute line `index`, inflating its block if it has been
+    /// compressed
+    pub fn line(&mut self, index: usize) -> Result<Option<&[TerminalCell]>, ScrollbackError> {
+        if index < self.first_line || index >= self.end_line() {
+            return Ok(None);
+        }
+        let live_start = self.end_line() - self.live.len();
+        if index >= live_start {
+            return Ok(Some(&self.live[index - live_start]));
+        }
+
+        let slot = self.blocks.partition_point(|b| b.first_line <= index) - 1;
+        let block_start = self.blocks[slot].first_line;
+        let decoded = match self
+            .cache
+            .iter()
+            .position(|(first, _)| *first == block_start)
+        {
+            Some(hit) => self.cache.remove(hit).map(|(_, lines)| lines),
+            None => None,
+        };
+        let decoded = match decoded {
+            Some(lines) => lines,
+            None => Arc::new(decompress_block(&self.blocks[slot])?),
+        };
+        self.cache.push_back((block_start, decoded));
+        while self.cache.len() > self.config.cached_blocks.max(1) {
+            self.cache.pop_front();
+        }
+
+        let (_, lines) = self.cache.back().expect("block was just cached");
+        Ok(lines.get(index - block_start).map(Vec::as_slice))
+    }
+
+    /// Rewrite every line's cells, recompressing old blocks
+    pub fn recolor(&mut self, mut f: impl FnMut(&mut TerminalCell)) -> Result<(), ScrollbackError> {
+        for line in &mut self.live {
+            line.iter_mut().for_each(&mut f);
+        }
+        for block in &mut self.blocks {
+            let mut lines = decompress_block(block)?;
+            lines.iter_mut().flatten().for_each(&mut f);
+            *block = compress_block(block.first_line, &lines)?;
+        }
+        self.cache.clear();
+        Ok(())
+    }
+
+    /// Literal search over the whole history. Compressed blocks are searched
+    /// in their text form without rebuilding cells.
+    pub fn search(&self, needle: &str) -> Result<Vec<SearchMatch>, ScrollbackError> {
+        let mut matches = Vec::new();
+        if needle.is_empty() {
+            return Ok(matches);
+        }
+
+        for block in &self.blocks {
+            let text = decompress(&block.text)?;
+            let mut reader = ByteReader::new(&text, block.first_line);
+            for line in block.first_line..block.first_line + block.line_count {
+                let len = reader.varint()?;
+                let bytes = reader.take(len)?;
+                let text = std::str::from_utf8(bytes).map_err(|_| reader.corrupt())?;
+                search_line(text, needle, line, &mut matches);
+            }
+        }
+
+        let live_start = self.end_line() - self.live.len();
+        for (offset, cells) in self.live.iter().enumerate() {
+            let text: String = cells.iter().map(|c| c.character).collect();
+            search_line(&text, needle, live_start + offset, &mut matches);
+        }
+        Ok(matches)
+    }
+
+    pub fn memory_usage(&self) -> ScrollbackMemory {
+        let row_bytes = |row: &Vec<TerminalCell>| {
+            size_of::<Vec<TerminalCell>>() + row.capacity() * size_of::<TerminalCell>()
+        };
+        ScrollbackMemory {
+            live_bytes: self.live.iter().map(row_bytes).sum(),
+            compressed_bytes: self
+                .blocks
+                .iter()
+                .map(|b| size_of::<CompressedBlock>() + b.text.capacity() + b.attrs.capacity())
+                .sum(),
+            cache_bytes: self
+                .cache
+                .iter()
+                .flat_map(|(_, lines)| lines.iter())
+                .map(row_bytes)
+                .sum(),
+        }
+    }
+}
+
+fn search_line(text: &str, needle: &str, line: usize, matches: &mut Vec<SearchMatch>) {
+    for (byte, _) in text.match_indices(needle) {
+        matches.push(SearchMatch {
+            line,
+            column: text[..byte].chars().count(),
+        });
+    }
+}
+
+const BOLD: u16 = 1 << 0;
+const ITALIC: u16 = 1 << 1;
+const UNDERLINE: u16 = 1 << 2;
+const STRIKETHROUGH: u16 = 1 << 3;
+const DIM: u16 = 1 << 4;
+const REVERSE: u16 = 1 << 5;
+const BLINK: u16 = 1 << 6;
+const WIDE: u16 = 1 << 7;
+const DIRTY: u16 = 1 << 8;
+
+/// Everything about a cell except its character
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CellStyle {
+    foreground: [f32; 4],
+    background: [f32; 4],
+    flags: u16,
+}
+
+impl CellStyle {
+    fn of(cell: &TerminalCell) -> Self {
+        let flags = [
+            (cell.bold, BOLD),
+            (cell.italic, ITALIC),
+            (cell.underline, UNDERLINE),
+            (cell.strikethrough, STRIKETHROUGH),
+            (cell.dim, DIM),
+            (cell.reverse, REVERSE),
+            (cell.blink, BLINK),
+            (cell.wide, WIDE),
+            (cell.dirty, DIRTY),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, bit)| flags | bit);
+        Self {
+            foreground: cell.foreground,
+            background: cell.background,
+            flags,
+        }
+    }
+
+    fn cell(&self, character: char) -> TerminalCell {
+        TerminalCell {
+            character,
+            foreground: self.foreground,
+            background: self.background,
+            bold: self.flags & BOLD != 0,
+            italic: self.flags & ITALIC != 0,
+            underline: self.flags & UNDERLINE != 0,
+            strikethrough: self.flags & STRIKETHROUGH != 0,
+            dim: self.flags & DIM != 0,
+            reverse: self.flags & REVERSE != 0,
+            blink: self.flags & BLINK != 0,
+            wide: self.flags & WIDE != 0,
+            dirty: self.flags & DIRTY != 0,
+            link: None,
+        }
+    }
+}
+
+/// Text section: per line, a byte length and the UTF-8 of one character
+/// per cell. Attribute section: per line, a run count and runs of
+/// (cell count, style).
+fn compress_block(
+    first_line: usize,
+    lines: &[Vec<TerminalCell>],
+) -> Result<CompressedBlock, ScrollbackError> {
+    let mut text = Vec::new();
+    let mut attrs = Vec::new();
+    let mut line_text = String::new();
+
+    for cells in lines {
+        line_text.clear();
+        line_text.extend(cells.iter().map(|c| c.character));
+        write_varint(&mut text, line_text.len());
+        text.extend_from_slice(line_text.as_bytes());
+
+        let mut runs: Vec<(usize, CellStyle)> = Vec::new();
+        for cell in cells {
+            let style = CellStyle::of(cell);
+            match runs.last_mut() {
+                Some((len, last)) if *last == style => *len += 1,
+                _ => runs.push((1, style)),
+            }
+        }
+        write_varint(&mut attrs, runs.len());
+        for (len, style) in runs {
+            write_varint(&mut attrs, len);
+            for channel in style.foreground.iter().chain(&style.background) {
+                attrs.extend_from_slice(&channel.to_bits().to_le_bytes());
+            }
+            attrs.extend_from_slice(&style.flags.to_le_bytes());
+        }
+    }
+
+    Ok(CompressedBlock {
+        first_line,
+        line_count: lines.len(),
+        text: compress(&text)?,
+        attrs: compress(&attrs)?,
+    })
+}
+
+fn decompress_block(block: &CompressedBlock) -> Result<Vec<Vec<TerminalCell>>, ScrollbackError> {
+    let text = decompress(&block.text)?;
+    let attrs = decompress(&block.attrs)?;
+    let mut text_reader = ByteReader::new(&text, block.first_line);
+    let mut attr_reader = ByteReader::new(&attrs, block.first_line);
+    let mut lines = Vec::with_capacity(block.line_count);
+
+    for _ in 0..block.line_count {
+        let len = text_reader.varint()?;
+        let bytes = text_reader.take(len)?;
+        let line_text = std::str::from_utf8(bytes).map_err(|_| text_reader.corrupt())?;
+        let mut chars = line_text.chars();
+        let mut cells = Vec::with_capacity(line_text.len());
+
+        for _ in 0..attr_reader.varint()? {
+            let run = attr_reader.varint()?;
+            let mut channels = [0f32; 8];
+            for channel in &mut channels {
+                let bits = attr_reader.take(4)?;
+                *channel = f32::from_bits(u32::from_le_bytes(bits.try_into().unwrap()));
+            }
+            let flags = attr_reader.take(2)?;
+            let style = CellStyle {
+                foreground: channels[..4].try_into().unwrap(),
+                background: channels[4..].try_into().unwrap(),
+                flags: u16::from_le_bytes(flags.try_into().unwrap()),
+            };
+            for _ in 0..run {
+                let character = chars.next().ok_or_else(|| attr_reader.corrupt())?;
+                cells.push(style.cell(character));
+            }
+        }
+        lines.push(cells);
+    }
+    Ok(lines)
+}
+
+/// Fastest zstd level; restyling recompresses every block
+const ZSTD_LEVEL: i32 = 1;
+
+fn compress(bytes: &[u8]) -> Result<Vec<u8>, ScrollbackError> {
+    let mut compressed = zstd::bulk::compress(bytes, ZSTD_LEVEL)?;
+    compressed.shrink_to_fit();
+    Ok(compressed)
+}
+
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>, ScrollbackError> {
+    Ok(zstd::decode_all(bytes)?)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    first_line: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8], first_line: usize) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            first_line,
+        }
+    }
+
+    fn corrupt(&self) -> ScrollbackError {
+        ScrollbackError::Corrupt(self.first_line)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ScrollbackError> {
+        let end = self.pos.checked_add(len).ok_or_else(|| self.corrupt())?;
+        let bytes = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or_else(|| self.corrupt())?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<usize, ScrollbackError> {
+        let mut value = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.corrupt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    const COLS: usize = 80;
+
+    fn synthetic_line(n: usize) -> Vec<TerminalCell> {
+        let text = match n % 4 {
+            0 => format!(
+                "[{:>6}] Compiling crate_{} v0.{}.{}",
+                n,
+                n % 97,
+                n % 13,
+                n % 7
+            ),
+            1 => format!(
+                "warning: unused variable `x{}` --> src/lib.rs:{}:9",
+                n % 31,
+                n % 500
+            ),
+            2 => format!(
+                "    Finished dev profile in {}.{:02}s — ok ✓",
+                n % 60,
+                n % 100
+            ),
+            _ => String::new(),
+        };
+        let mut chars = text.chars();
+        (0..COLS)
+            .map(|col| {
+                let mut cell = TerminalCell {
+                    character: chars.next().unwrap_or(' '),
+                    ..TerminalCell::default()
+                };
+                if n % 4 == 1 && col < 8 {
+                    cell.foreground = [1.0, 0.8, 0.0, 1.0];
+                    cell.bold = true;
+                }
+                cell
+            })
+            .collect()
+    }
+
+    fn history(lines: usize) -> Scrollback {
+        let mut scrollback = Scrollback::new(ScrollbackConfig {
+            max_lines: lines,
+            ..ScrollbackConfig::default()
+        });
+        for n in 0..lines {
+            scrollback.push_line(synthetic_line(n)).unwrap();
+        }
+        scrollback
+    }
+
+    #[test]
+    fn test_compressed_history_is_smaller_and_exact() {
+        let lines = 100_000;
+        let mut scrollback = history(lines);
+        assert_eq!(scrollback.len(), lines);
+
+        let raw = lines * COLS * size_of::<TerminalCell>();
+        let memory = scrollback.memory_usage();
+        assert!(
+            memory.total() * 5 <= raw,
+            "{} bytes vs {} raw",
+            memory.total(),
+            raw
+        );
+
+        for n in [0, 1, 255, 256, 50_001, 97_999, 98_000, lines - 1] {
+            assert_eq!(scrollback.line(n).unwrap().unwrap(), &synthetic_line(n)[..]);
+        }
+        assert!(scrollback.line(lines).unwrap().is_none());
+        assert!(scrollback.memory_usage().cache_bytes > 0);
+    }
+
+    #[test]
+    fn test_random_access_latency() {
+        let lines = 100_000;
+        let mut scrollback = history(lines);
+
+        // Each probe lands in a different compressed block, so none is
+        // served from the decoded-block cache
+        let block_lines = ScrollbackConfig::default().block_lines;
+        let mut worst = Duration::ZERO;
+        for i in 0..50 {
+            let n = i * 7 * block_lines + i % block_lines;
+            let start = Instant::now();
+            let line = scrollback.line(n).unwrap().unwrap().to_vec();
+            worst = worst.max(start.elapsed());
+            assert_eq!(line, synthetic_line(n));
+        }
+        let limit = if cfg!(debug_assertions) {
+            Duration::from_millis(25)
+        } else {
+            Duration::from_millis(3)
+        };
+        assert!(worst < limit, "{:?} for the slowest access", worst);
+    }
+
+    #[test]
+    fn test_search_matches_uncompressed_baseline() {
+        let lines = 5_000;
+        let scrollback = history(lines);
+
+        for needle in ["crate_42 ", "✓", "src/lib.rs:7:", "  ", "no such text"] {
+            let mut baseline = Vec::new();
+            for n in 0..lines {
+                let text: String = synthetic_line(n).iter().map(|c| c.character).collect();
+                search_line(&text, needle, n, &mut baseline);
+            }
+            assert_eq!(scrollback.search(needle).unwrap(), baseline, "{:?}", needle);
+        }
+    }
+
+    #[test]
+    fn test_eviction_drops_whole_blocks() {
+        let config = ScrollbackConfig {
+            live_lines: 100,
+            block_lines: 50,
+            max_lines: 400,
+            cached_blocks: 2,
+        };
+        let mut scrollback = Scrollback::new(config);
+        for n in 0..1_000 {
+            scrollback.push_line(synthetic_line(n)).unwrap();
+            assert!(scrollback.len() <= 400);
+        }
+        assert_eq!(scrollback.first_line() % 50, 0);
+        assert_eq!(scrollback.end_line(), 1_000);
+        assert!(
+            scrollback
+                .line(scrollback.first_line() - 1)
+                .unwrap()
+                .is_none()
+        );
+
+        let first = scrollback.first_line();
+        assert_eq!(
+            scrollback.line(first).unwrap().unwrap(),
+            &synthetic_line(first)[..]
+        );
+        assert_eq!(scrollback.search("[   996]").unwrap().len(), 1);
+        assert!(scrollback.search("[     0]").unwrap().is_empty());
+    }
+}
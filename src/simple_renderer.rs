@@ -1,5 +1,22 @@
-use crate::terminal::{TerminalState, TerminalCell};
+use crate::bitmap_font::BitmapFont;
+use crate::buffer_search::MatchLocation;
+use crate::column_guides::{self, ContentArea, GuideStyle};
+use crate::damage::{DamageRect, DamageTracker};
+use crate::glyph_guard::{self, AtlasAllocator, GlyphGuardError, GlyphLimits};
+use crate::gpu_timing::{GpuStats, GpuTimer, WgpuTimestamps};
+use crate::idle_lock::BlankStyle;
+use crate::pane_border::{BorderTheme, Rect};
+use crate::render_caps::{ColorDepth, RenderCapabilities, RendererKind};
+use crate::startup::{CellFont, ShapedCell};
+use crate::stats_overlay::{FrameStats, RenderStats};
+use crate::terminal::{Selection, TerminalState, TerminalCell};
+use crate::terminal_parser::{CursorShape, CursorStyle};
+use crate::theme::Theme;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use parking_lot::RwLock;
 use wgpu;
 use winit::window::Window;
@@ -61,6 +78,94 @@ pub struct SimpleRenderer {
     terminal_state: Arc<RwLock<TerminalState>>,
     cell_width: f32,
     cell_height: f32,
+    font: Arc<dyn CellFont>,
+    atlas: GlyphAtlas,
+    glyph_cache: HashMap<GlyphKey, Option<GlyphSlot>>,
+    /// `ui.ligatures`: shape runs of same-style cells so coding fonts can
+    /// join `->` or `!=`
+    ligatures: bool,
+    shaped_runs: HashMap<String, Vec<ShapedCell>>,
+    span_cache: HashMap<Vec<ShapedCell>, Option<GlyphSlot>>,
+    clear_color: wgpu::Color,
+    guide_style: Option<GuideStyle>,
+    guide_columns: Option<Vec<u32>>,
+    /// Idle lock: how to hide the content and the notice to show instead
+    blank: Option<(BlankStyle, String)>,
+    /// Rows drawn from the top over dimmed content, e.g. the paste preview
+    overlay: Option<Vec<Vec<TerminalCell>>>,
+    /// Suggested rest of the command line, drawn dim after the cursor
+    ghost_text: Option<String>,
+    /// IME composition, drawn underlined at the cursor until committed
+    preedit: Option<String>,
+    /// Progress of an answer being generated, drawn on a bar below it
+    status_line: Option<String>,
+    /// Mouse selection, drawn on the theme's selection color
+    selection: Option<Selection>,
+    /// Default background, cursor and selection colors
+    colors: Theme,
+    /// Scrollback search matches in reverse video and the current one
+    /// picked out, with the search input drawn on the bottom row
+    search_matches: Vec<MatchLocation>,
+    current_match: Option<MatchLocation>,
+    search_bar: Option<Vec<TerminalCell>>,
+    /// Rows of the prefix command line being typed, top to bottom, over
+    /// the bottom rows unless the search input is there
+    command_line: Vec<Vec<TerminalCell>>,
+    /// `ui.cursor_style` and `ui.cursor_blink`, until an application picks
+    /// another style
+    cursor_style: CursorStyle,
+    /// Outline the cursor when the window loses focus
+    window_focused: bool,
+    /// Blinking is timed from here, and restarts on input
+    blink_epoch: Instant,
+    /// Tab titles and the active tab, drawn in a row above the grid
+    tab_bar: Option<(Vec<String>, usize)>,
+    /// Panes of a split tab, drawn instead of the single grid
+    panes: Vec<PaneView>,
+    /// Columns and rows the panes and their borders cover
+    pane_area: (u32, u32),
+    /// Cell the grid being drawn starts at, below the tab bar
+    origin: (u32, u32),
+    gpu_timer: GpuTimer<WgpuTimestamps>,
+    /// Debug numbers drawn in a box at the top right, over everything.
+    /// They never touch the grid, so showing them damages no cells.
+    stats_overlay: Option<Vec<String>>,
+    frame_stats: FrameStats,
+    /// Cells changed since the last frame, and the regions the last
+    /// frame redrew
+    damage: DamageTracker,
+    dirty_regions: usize,
+    /// The single grid's quads, kept so a frame rebuilds only damaged cells
+    grid_quads: GridQuads,
+    /// Damage the frame being built redraws
+    frame_damage: Vec<DamageRect>,
+    /// Something drawn besides the grid's cells changed since the last
+    /// frame
+    scene_changed: bool,
+    last_scene: Option<GridScene>,
+    cells_redrawn: u64,
+    frames_skipped: u64,
+    theme: String,
+    forced_color_depth: Option<ColorDepth>,
+    capabilities: RenderCapabilities,
+}
+
+/// One pane of a split tab: its grid and where its top-left cell sits
+pub struct PaneView {
+    pub terminal: Arc<RwLock<TerminalState>>,
+    pub column: u32,
+    pub row: u32,
+    pub focused: bool,
+}
+
+/// Initial sizes; both buffers grow when a frame needs more
+const VERTEX_BUFFER_SIZE: u64 = 4 * 1024 * 1024;
+const INDEX_BUFFER_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Size for a buffer that must hold `needed` bytes, or None if `current`
+/// already does. Doubles so a growing grid reallocates only a few times.
+fn grown_buffer_size(current: u64, needed: u64) -> Option<u64> {
+    (needed > current).then(|| needed.next_power_of_two().max(current * 2))
 }
 
 impl SimpleRenderer {
@@ -96,7 +201,8 @@ impl SimpleRenderer {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
+                    // GPU frame timing, where the adapter can do it
+                    required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
                     required_limits: wgpu::Limits::default(),
                     label: None,
                 },
@@ -137,19 +243,27 @@ impl SimpleRenderer {
             struct VertexOutput {
                 @builtin(position) clip_position: vec4<f32>,
                 @location(0) color: vec4<f32>,
+                @location(1) tex_coords: vec2<f32>,
             }
 
+            @group(0) @binding(0) var atlas: texture_2d<f32>;
+            @group(0) @binding(1) var atlas_sampler: sampler;
+
             @vertex
             fn vs_main(model: VertexInput) -> VertexOutput {
                 var out: VertexOutput;
                 out.color = model.color;
+                out.tex_coords = model.tex_coords;
                 out.clip_position = vec4<f32>(model.position, 0.0, 1.0);
                 return out;
             }
 
+            // Glyph coverage scales the color's alpha; solid quads sample
+            // the atlas's white patch
             @fragment
             fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-                return in.color;
+                let coverage = textureSample(atlas, atlas_sampler, in.tex_coords).r;
+                return vec4<f32>(in.color.rgb, in.color.a * coverage);
             }
         "#;
 
@@ -158,9 +272,32 @@ impl SimpleRenderer {
             source: wgpu::ShaderSource::Wgsl(shader_source.into()),
         });
 
+        let atlas_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Glyph Atlas Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let atlas = GlyphAtlas::new(&device, &queue, &atlas_layout);
+
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[],
+            bind_group_layouts: &[&atlas_layout],
             push_constant_ranges: &[],
         });
 
@@ -177,7 +314,8 @@ impl SimpleRenderer {
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
                     format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    // Guides and the stats box are translucent
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
@@ -202,7 +340,7 @@ impl SimpleRenderer {
         // Create vertex buffer
         let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Vertex Buffer"),
-            size: 1024 * 1024, // 1MB buffer
+            size: VERTEX_BUFFER_SIZE,
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
@@ -210,7 +348,7 @@ impl SimpleRenderer {
         // Create index buffer
         let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Index Buffer"),
-            size: 512 * 1024, // 512KB buffer
+            size: INDEX_BUFFER_SIZE,
             usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
@@ -222,8 +360,6 @@ impl SimpleRenderer {
         drop(terminal);
 
         Ok(Self {
-            device,
-            queue,
             surface,
             config,
             render_pipeline,
@@ -232,33 +368,300 @@ impl SimpleRenderer {
             terminal_state,
             cell_width,
             cell_height,
+            font: Arc::new(BitmapFont::fitting(cell_width, cell_height)),
+            atlas,
+            glyph_cache: HashMap::new(),
+            ligatures: false,
+            shaped_runs: HashMap::new(),
+            span_cache: HashMap::new(),
+            clear_color: wgpu::Color::BLACK,
+            guide_style: None,
+            guide_columns: None,
+            blank: None,
+            overlay: None,
+            ghost_text: None,
+            preedit: None,
+            status_line: None,
+            selection: None,
+            colors: Theme::default(),
+            search_matches: Vec::new(),
+            current_match: None,
+            search_bar: None,
+            command_line: Vec::new(),
+            cursor_style: CursorStyle::default(),
+            window_focused: true,
+            blink_epoch: Instant::now(),
+            tab_bar: None,
+            panes: Vec::new(),
+            pane_area: (0, 0),
+            origin: (0, 0),
+            gpu_timer: GpuTimer::new(WgpuTimestamps::new(&device, &queue)),
+            stats_overlay: None,
+            frame_stats: FrameStats::default(),
+            damage: DamageTracker::new(),
+            dirty_regions: 0,
+            grid_quads: GridQuads::default(),
+            frame_damage: Vec::new(),
+            scene_changed: true,
+            last_scene: None,
+            cells_redrawn: 0,
+            frames_skipped: 0,
+            theme: String::new(),
+            forced_color_depth: None,
+            capabilities: RenderCapabilities::default(),
+            device,
+            queue,
         })
     }
 
+    /// Draw ligatures and contextual alternates from the next frame on
+    pub fn set_ligatures(&mut self, enabled: bool) {
+        if self.ligatures != enabled {
+            self.ligatures = enabled;
+            let mut terminal = self.terminal_state.write();
+            crate::startup::mark_all_dirty(&mut terminal);
+        }
+    }
+
+    /// Swap the font used for glyphs. Every cell is redrawn with it on the
+    /// next frame.
+    pub fn set_font(&mut self, font: Arc<dyn CellFont>) {
+        self.font = font;
+        self.clear_glyphs();
+        self.derive_capabilities();
+        let mut terminal = self.terminal_state.write();
+        crate::startup::mark_all_dirty(&mut terminal);
+    }
+
+    /// Cells in the theme's default background are left to the clear
+    /// color, which becomes that background
+    pub fn set_colors(&mut self, theme: Theme) {
+        self.set_clear_color(theme.background);
+        if replace(&mut self.colors, theme) {
+            self.grid_quads.stale = true;
+        }
+    }
+
+    pub fn set_clear_color(&mut self, rgba: [f32; 4]) {
+        let color = wgpu::Color {
+            r: rgba[0] as f64,
+            g: rgba[1] as f64,
+            b: rgba[2] as f64,
+            a: rgba[3] as f64,
+        };
+        self.scene_changed |= replace(&mut self.clear_color, color);
+    }
+
+    /// Column guides from config; `columns` overrides the configured list
+    /// for this pane (`:set guide ...`), `None` keeps the global setting
+    pub fn set_guides(&mut self, style: GuideStyle, columns: Option<Vec<u32>>) {
+        self.scene_changed |= replace(&mut self.guide_style, Some(style));
+        self.scene_changed |= replace(&mut self.guide_columns, columns);
+    }
+
+    /// Hide the terminal behind the idle lock, or show it again with `None`
+    pub fn set_blank(&mut self, blank: Option<(BlankStyle, String)>) {
+        self.scene_changed |= replace(&mut self.blank, blank);
+    }
+
+    pub fn set_overlay(&mut self, overlay: Option<Vec<Vec<TerminalCell>>>) {
+        self.scene_changed |= replace(&mut self.overlay, overlay);
+    }
+
+    pub fn set_ghost_text(&mut self, ghost_text: Option<String>) {
+        self.scene_changed |= replace(&mut self.ghost_text, ghost_text);
+    }
+
+    pub fn set_preedit(&mut self, preedit: Option<String>) {
+        self.scene_changed |= replace(&mut self.preedit, preedit);
+    }
+
+    pub fn set_status_line(&mut self, status_line: Option<String>) {
+        self.scene_changed |= replace(&mut self.status_line, status_line);
+    }
+
+    pub fn set_selection(&mut self, selection: Option<Selection>) {
+        if replace(&mut self.selection, selection) {
+            self.grid_quads.stale = true;
+        }
+    }
+
+    pub fn set_search(&mut self, matches: Vec<MatchLocation>, current: Option<MatchLocation>) {
+        let matches_changed = replace(&mut self.search_matches, matches);
+        if replace(&mut self.current_match, current) || matches_changed {
+            self.grid_quads.stale = true;
+        }
+    }
+
+    pub fn set_search_bar(&mut self, row: Option<Vec<TerminalCell>>) {
+        self.scene_changed |= replace(&mut self.search_bar, row);
+    }
+
+    pub fn set_command_line(&mut self, rows: Vec<Vec<TerminalCell>>) {
+        self.scene_changed |= replace(&mut self.command_line, rows);
+    }
+
+    pub fn set_cursor_style(&mut self, style: CursorStyle) {
+        self.scene_changed |= replace(&mut self.cursor_style, style);
+    }
+
+    pub fn set_window_focused(&mut self, focused: bool) {
+        self.scene_changed |= replace(&mut self.window_focused, focused);
+        self.restart_blink();
+    }
+
+    /// Show the cursor solid for a full blink period, e.g. after a key
+    pub fn restart_blink(&mut self) {
+        self.blink_epoch = Instant::now();
+    }
+
+    /// Draw another grid, e.g. after switching tabs
+    pub fn set_terminal(&mut self, terminal_state: Arc<RwLock<TerminalState>>) {
+        self.terminal_state = terminal_state;
+        self.fit_cells();
+        let mut terminal = self.terminal_state.write();
+        crate::startup::mark_all_dirty(&mut terminal);
+    }
+
+    /// Show the tab bar with these titles, or hide it with `None`
+    pub fn set_tab_bar(&mut self, tab_bar: Option<(Vec<String>, usize)>) {
+        let resized = tab_bar.is_some() != self.tab_bar.is_some();
+        self.scene_changed |= replace(&mut self.tab_bar, tab_bar);
+        if resized {
+            self.fit_cells();
+        }
+    }
+
+    /// Draw these panes of `area` cells with borders between them, or the
+    /// single grid again when empty. The focused pane's grid should also be
+    /// the one passed to `set_terminal`.
+    pub fn set_panes(&mut self, panes: Vec<PaneView>, area: (u32, u32)) {
+        for pane in &panes {
+            crate::startup::mark_all_dirty(&mut pane.terminal.write());
+        }
+        self.panes = panes;
+        self.pane_area = area;
+        self.fit_cells();
+    }
+
+    /// Rows above the grid
+    fn grid_top(&self) -> u32 {
+        if self.tab_bar.is_some() { 1 } else { 0 }
+    }
+
+    /// Columns and rows of the single grid, or of all panes
+    fn grid_cells(&self) -> (u32, u32) {
+        if self.panes.is_empty() {
+            let terminal = self.terminal_state.read();
+            (terminal.width, terminal.height)
+        } else {
+            self.pane_area
+        }
+    }
+
+    fn fit_cells(&mut self) {
+        let (cols, rows) = self.grid_cells();
+        self.cell_width = self.config.width as f32 / cols as f32;
+        self.cell_height = self.config.height as f32 / (rows + self.grid_top()) as f32;
+        self.grid_quads.stale = true;
+    }
+
+    /// Pixel size of one grid cell
+    pub fn cell_size(&self) -> (f32, f32) {
+        (self.cell_width, self.cell_height)
+    }
+
+    /// The active theme and `ui.color_depth`, for the capabilities styled
+    /// output is rendered for
+    pub fn set_color_policy(&mut self, theme: &str, forced: Option<ColorDepth>) {
+        self.theme = theme.to_string();
+        self.forced_color_depth = forced;
+        self.derive_capabilities();
+    }
+
+    fn derive_capabilities(&mut self) {
+        self.capabilities =
+            RenderCapabilities::derive(RendererKind::Gpu, &*self.font, &self.theme, self.forced_color_depth);
+    }
+
+    pub fn capabilities(&self) -> RenderCapabilities {
+        self.capabilities
+    }
+
+    /// GPU time per pass, a few frames behind
+    pub fn gpu_stats(&self) -> GpuStats {
+        self.gpu_timer.stats()
+    }
+
+    pub fn set_stats_overlay(&mut self, lines: Option<Vec<String>>) {
+        self.scene_changed |= replace(&mut self.stats_overlay, lines);
+    }
+
+    /// Frame rate, buffer sizes and damage for the stats overlay
+    pub fn render_stats(&self) -> RenderStats {
+        let now = Instant::now();
+        RenderStats {
+            fps: self.frame_stats.fps(now),
+            frame_time: self.frame_stats.frame_time(now),
+            gpu_memory: self.vertex_buffer.size() + self.index_buffer.size() + ATLAS_BYTES,
+            cached_glyphs: self.glyph_cache.len() + self.span_cache.len(),
+            glyph_hits: self.atlas.hits,
+            glyph_misses: self.atlas.misses,
+            dirty_regions: self.dirty_regions,
+            cells_redrawn: self.cells_redrawn,
+            frames_skipped: self.frames_skipped,
+            gpu: self.gpu_stats(),
+        }
+    }
+
+    /// Configure the surface afresh and redraw every cell, to recover from
+    /// a render loop that stopped presenting
+    pub fn reconfigure(&mut self) {
+        self.surface.configure(&self.device, &self.config);
+        self.clear_glyphs();
+        let mut terminal = self.terminal_state.write();
+        crate::startup::mark_all_dirty(&mut terminal);
+    }
+
+    /// Drop every cached glyph and start packing the atlas afresh
+    fn clear_glyphs(&mut self) {
+        self.grid_quads.stale = true;
+        self.atlas.layout.clear();
+        self.glyph_cache.clear();
+        self.shaped_runs.clear();
+        self.span_cache.clear();
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
-            
-            // Update cell dimensions
-            let terminal = self.terminal_state.read();
-            self.cell_width = new_size.width as f32 / terminal.width as f32;
-            self.cell_height = new_size.height as f32 / terminal.height as f32;
+            self.fit_cells();
         }
     }
 
     pub fn render(&mut self) -> Result<(), RendererError> {
+        let frame_start = Instant::now();
+        let Some(damage) = self.take_damage() else {
+            // Nothing changed and the cursor didn't blink: the last frame
+            // stays on screen
+            self.frames_skipped += 1;
+            return Ok(());
+        };
+        self.frame_damage = damage;
         let output = match self.surface.get_current_texture() {
             Ok(output) => output,
             Err(wgpu::SurfaceError::Lost) => {
                 self.surface.configure(&self.device, &self.config);
+                self.grid_quads.stale = true;
                 return Ok(());
             }
             Err(wgpu::SurfaceError::OutOfMemory) => {
                 return Err(RendererError::Surface("Out of memory".to_string()));
             }
             Err(e) => {
+                self.grid_quads.stale = true;
                 return Err(RendererError::Surface(format!("Surface error: {:?}", e)));
             }
         };
@@ -269,8 +672,16 @@ impl SimpleRenderer {
             label: Some("Render Encoder"),
         });
 
-        // Build vertex and index data
-        let (vertices, indices) = self.build_render_data();
+        let (mut vertices, mut indices) = self.build_frame();
+        if self.atlas.layout.take_reset() {
+            // The atlas filled up part way through and started over, so
+            // glyphs placed before that are gone: build the frame again
+            self.glyph_cache.clear();
+            self.span_cache.clear();
+            self.grid_quads.stale = true;
+            (vertices, indices) = self.build_frame();
+        }
+        self.reserve_buffers(&vertices, &indices);
 
         // Update buffers
         if !vertices.is_empty() {
@@ -280,80 +691,703 @@ impl SimpleRenderer {
             self.queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&indices));
         }
 
+        let timing = self.gpu_timer.begin_frame();
+        let pass_queries = timing.as_ref().and_then(|frame| self.gpu_timer.pass(frame, "render"));
         {
+            let timestamps = self.gpu_timer.readback();
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                     view: &view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(self.clear_color),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: None,
                 occlusion_query_set: None,
-                timestamp_writes: None,
+                timestamp_writes: timestamps.zip(pass_queries).map(|(t, pass)| t.pass_writes(pass)),
             });
 
             render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, &self.atlas.bind_group, &[]);
             render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
             render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
 
+            // The whole frame in one draw, in the order QuadBatch laid it out
             if !indices.is_empty() {
                 render_pass.draw_indexed(0..indices.len() as u32, 0, 0..1);
             }
         }
 
+        if let (Some(frame), Some(timestamps)) = (&timing, self.gpu_timer.readback()) {
+            timestamps.resolve(&mut encoder, frame.slot(), self.gpu_timer.pass_count(frame));
+        }
+
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
 
+        // Collect earlier frames' timings without waiting on this one
+        if let Some(frame) = timing {
+            self.gpu_timer.end_frame(frame);
+        }
+        self.device.poll(wgpu::Maintain::Poll);
+        self.gpu_timer.poll();
+        self.frame_stats.record(frame_start, frame_start.elapsed());
+
         Ok(())
     }
 
-    fn build_render_data(&self) -> (Vec<Vertex>, Vec<u32>) {
-        let mut vertices = Vec::new();
-        let mut indices = Vec::new();
-        let mut vertex_index = 0u32;
+    /// Regions of the focused grid changed since the last frame, clearing
+    /// its dirty bits, or `None` when this frame would look just like the
+    /// last one
+    fn take_damage(&mut self) -> Option<Vec<DamageRect>> {
+        let mut terminal = self.terminal_state.write();
+        let (width, height) = (terminal.width, terminal.height);
+        self.damage.collect(&mut terminal.cells, width, |cell| &mut cell.dirty);
+        let cursor = terminal.cursor_visible.then_some((terminal.cursor_x, terminal.cursor_y));
+        let style = terminal.cursor_style.unwrap_or(self.cursor_style);
+        let cursor_changed = self.damage.cursor(cursor, self.cursor_shown(style));
+        let scene = GridScene::of(&terminal);
+        drop(terminal);
+
+        let scene_changed = std::mem::take(&mut self.scene_changed) | replace(&mut self.last_scene, Some(scene));
+        // Other panes' grids aren't tracked, so a split tab always draws
+        if self.damage.is_empty() && !cursor_changed && !scene_changed && !self.grid_quads.stale && self.panes.is_empty() {
+            return None;
+        }
+        let rects = self.damage.take(width, height);
+        self.dirty_regions = rects.len();
+        Some(rects)
+    }
+
+    /// Replace the vertex or index buffer when this frame doesn't fit
+    fn reserve_buffers(&mut self, vertices: &[Vertex], indices: &[u32]) {
+        let vertex_bytes = std::mem::size_of_val(vertices) as u64;
+        if let Some(size) = grown_buffer_size(self.vertex_buffer.size(), vertex_bytes) {
+            self.vertex_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Vertex Buffer"),
+                size,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+        }
+        let index_bytes = std::mem::size_of_val(indices) as u64;
+        if let Some(size) = grown_buffer_size(self.index_buffer.size(), index_bytes) {
+            self.index_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Index Buffer"),
+                size,
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+        }
+    }
+
+    /// Vertex and index data for everything on screen, stats box included
+    fn build_frame(&mut self) -> (Vec<Vertex>, Vec<u32>) {
+        let mut batch = QuadBatch::default();
+        self.grid_quads.drawn = false;
+        self.build_render_data(&mut batch);
+        // Cells drawn some other way, e.g. under an overlay, leave the
+        // kept quads behind
+        if !self.grid_quads.drawn {
+            self.grid_quads.stale = true;
+        }
+        if let Some(lines) = self.stats_overlay.clone() {
+            batch.layer();
+            self.add_stats_overlay(&mut batch, &lines);
+        }
+        batch.finish()
+    }
+
+    fn build_render_data(&mut self, batch: &mut QuadBatch) {
+        // Scrolled back: history rows on top, the screen pushed down
+        let history = {
+            let scrolled_back = self.terminal_state.read().viewport_offset() > 0;
+            if scrolled_back {
+                self.terminal_state.write().viewport_history()
+            } else {
+                Vec::new()
+            }
+        };
+
+        let terminal_state = self.terminal_state.clone();
+        let terminal = terminal_state.read();
+
+        if let Some((style, notice)) = self.blank.clone() {
+            if style == BlankStyle::Dim {
+                self.add_dimmed_cells(batch, &terminal);
+                batch.layer();
+            }
+            self.add_notice(batch, &terminal, &notice);
+            return;
+        }
+
+        if let Some((titles, active)) = self.tab_bar.clone() {
+            for (x, cell) in tab_bar_cells(&titles, active, self.grid_cells().0).iter().enumerate() {
+                self.add_window_cell(batch, x as u32, 0, cell);
+            }
+            batch.layer();
+        }
+
+        if let Some(overlay) = self.overlay.clone() {
+            self.add_dimmed_cells(batch, &terminal);
+            batch.layer();
+            for (y, row) in overlay.iter().take(terminal.height as usize).enumerate() {
+                for (x, cell) in row.iter().take(terminal.width as usize).enumerate() {
+                    self.add_cell_quad(batch, x as u32, y as u32, cell);
+                }
+            }
+            return;
+        }
+
+        if !self.panes.is_empty() {
+            drop(terminal);
+            self.add_panes(batch);
+            return;
+        }
+
+        // Column guides sit under the text
+        if let Some(style) = &self.guide_style {
+            let area = ContentArea {
+                x: 0.0,
+                y: self.grid_top() as f32 * self.cell_height,
+                cell_width: self.cell_width,
+                cell_height: self.cell_height,
+                cols: terminal.width,
+                rows: terminal.height,
+            };
+            let columns = self.guide_columns.as_deref().unwrap_or(&style.columns);
+            for quad in column_guides::guide_quads(&area, columns, style) {
+                self.add_pixel_quad(
+                    batch,
+                    (quad.x, quad.y, quad.width, quad.height),
+                    quad.color,
+                );
+            }
+        }
+
+        self.add_grid(batch, &terminal, &history, true);
+    }
+
+    /// A grid's cells at `origin`, with history rows on top while scrolled
+    /// back. Selection, composition, suggestion and cursor belong to the
+    /// focused grid only.
+    fn add_grid(
+        &mut self,
+        batch: &mut QuadBatch,
+        terminal: &TerminalState,
+        history: &[Vec<TerminalCell>],
+        focused: bool,
+    ) {
+        let found = if focused { self.search_rows(terminal) } else { Vec::new() };
+        let highlight = |x: u32, y: u32| {
+            found
+                .iter()
+                .find(|(row, start, end, _)| *row == y && (*start..*end).contains(&x))
+                .map(|&(_, _, _, current)| current)
+        };
+        for (y, row) in history.iter().enumerate() {
+            for (x, cell) in row.iter().take(terminal.width as usize).enumerate() {
+                if let Some(current) = highlight(x as u32, y as u32) {
+                    let cell = search_match_cell(cell, current);
+                    self.add_cell_quad(batch, x as u32, y as u32, &cell);
+                } else if cell.character != ' ' || cell.background != self.colors.background {
+                    self.add_cell_quad(batch, x as u32, y as u32, cell);
+                }
+            }
+        }
+        if !history.is_empty() {
+            let shift = history.len() as u32;
+            for y in 0..terminal.height.saturating_sub(shift) {
+                for x in 0..terminal.width {
+                    let Some(cell) = terminal.get_cell(x, y) else {
+                        continue;
+                    };
+                    if let Some(current) = highlight(x, y + shift) {
+                        let cell = search_match_cell(cell, current);
+                        self.add_cell_quad(batch, x, y + shift, &cell);
+                    } else if cell.character != ' ' || cell.background != self.colors.background {
+                        self.add_cell_quad(batch, x, y + shift, cell);
+                    }
+                }
+            }
+            // No cursor, composition or suggestion while reading history
+            if focused {
+                batch.layer();
+                self.add_bottom_bar(batch, terminal);
+            }
+            batch.layer();
+            return;
+        }
+
+        // Render terminal cells. Ligatures stop at the cursor, the
+        // selection and search matches so those keep their own glyphs.
+        // The single grid keeps its cells' quads and rebuilds only the
+        // damaged ones; ligatures can change along the whole row.
+        let cursor = (focused && terminal.cursor_visible).then_some((terminal.cursor_x, terminal.cursor_y));
+        let selection = self.selection.filter(|_| focused);
+        let cached = focused && self.panes.is_empty();
+        let mut grid = if cached { std::mem::take(&mut self.grid_quads) } else { GridQuads::default() };
+        let rows = if cached {
+            grid.rows_to_rebuild(terminal.width, terminal.height, &self.frame_damage, self.ligatures)
+        } else {
+            (0..terminal.height).map(|y| (y, 0..terminal.width)).collect()
+        };
+        for (y, columns) in rows {
+            let glyphs = self.ligature_row(terminal, y, |x| {
+                cursor == Some((x, y))
+                    || selection.is_some_and(|selection| selection.contains(x, y))
+                    || highlight(x, y).is_some()
+            });
+            for x in columns {
+                let Some(cell) = terminal.get_cell(x, y) else {
+                    continue;
+                };
+                let glyph = glyphs.get(x as usize).and_then(Option::as_ref);
+                let selected = selection.is_some_and(|selection| selection.contains(x, y));
+                if cached {
+                    let mut quads = QuadBatch::default();
+                    self.add_grid_cell(&mut quads, x, y, cell, glyph, selected, highlight(x, y));
+                    grid.set(x, y, quads);
+                } else {
+                    self.add_grid_cell(batch, x, y, cell, glyph, selected, highlight(x, y));
+                }
+                self.cells_redrawn += 1;
+            }
+        }
+        if cached {
+            grid.append_to(batch);
+            grid.drawn = true;
+            self.grid_quads = grid;
+        }
+
+        // Each overlay below is a layer of its own over the grid
+        batch.layer();
+
+        // An answer still streaming sits under the cursor line
+        if let Some((top, rows)) = terminal.inline_region() {
+            for (y, row) in rows.iter().enumerate() {
+                for (x, cell) in row.iter().take(terminal.width as usize).enumerate() {
+                    if cell.character != ' ' || cell.background != self.colors.background {
+                        self.add_cell_quad(batch, x as u32, top + y as u32, cell);
+                    }
+                }
+            }
+            batch.layer();
+        }
+
+        // Text being composed covers the cells after the cursor, and the
+        // cursor moves to its end
+        let mut cursor_x = terminal.cursor_x;
+        if let Some(preedit) = self.preedit.clone().filter(|_| focused) {
+            let foreground = self.colors.foreground;
+            let underline = (self.cell_height * 0.08).max(1.0);
+            for character in preedit.chars() {
+                let width = crate::glyph_guard::char_width(character) as u32;
+                if cursor_x + width > terminal.width {
+                    break;
+                }
+                let cell = TerminalCell {
+                    character,
+                    foreground,
+                    wide: width > 1,
+                    ..TerminalCell::default()
+                };
+                self.add_cell_quad(batch, cursor_x, terminal.cursor_y, &cell);
+                let rect = (
+                    (cursor_x + self.origin.0) as f32 * self.cell_width,
+                    (terminal.cursor_y + self.grid_top() + self.origin.1 + 1) as f32 * self.cell_height - underline,
+                    width as f32 * self.cell_width,
+                    underline,
+                );
+                self.add_pixel_quad(batch, rect, foreground);
+                cursor_x += width;
+            }
+            batch.layer();
+        }
+
+        // Ghost text sits after the cursor, never in the grid
+        if let Some(ghost_text) = self.ghost_text.clone().filter(|_| focused && self.preedit.is_none()) {
+            let clear = self.clear_rgba();
+            let mut foreground = self.colors.foreground;
+            for i in 0..3 {
+                foreground[i] = clear[i] + (foreground[i] - clear[i]) * 0.4;
+            }
+            let mut x = terminal.cursor_x;
+            for character in ghost_text.chars() {
+                let width = crate::glyph_guard::char_width(character) as u32;
+                if x + width > terminal.width {
+                    break;
+                }
+                let cell = TerminalCell {
+                    character,
+                    foreground,
+                    wide: width > 1,
+                    ..TerminalCell::default()
+                };
+                self.add_cell_quad(batch, x, terminal.cursor_y, &cell);
+                x += width;
+            }
+            batch.layer();
+        }
+
+        // The status bar goes under the answer being written, or over the
+        // top row once it reaches the bottom
+        if let Some(status) = self.status_line.clone().filter(|_| focused) {
+            let below = terminal
+                .inline_region()
+                .map_or(terminal.cursor_y + 1, |(top, rows)| top + rows.len() as u32);
+            let y = if below < terminal.height { below } else { 0 };
+            let style = TerminalCell {
+                foreground: [0.65, 0.65, 0.7, 1.0],
+                background: [0.12, 0.12, 0.14, 1.0],
+                ..TerminalCell::default()
+            };
+            let mut x = 0;
+            for character in format!(" {}", status).chars() {
+                let width = crate::glyph_guard::char_width(character) as u32;
+                if width == 0 {
+                    continue;
+                }
+                if x + width > terminal.width {
+                    break;
+                }
+                let cell = TerminalCell { character, wide: width > 1, ..style.clone() };
+                self.add_cell_quad(batch, x, y, &cell);
+                x += width;
+            }
+            for x in x..terminal.width {
+                self.add_cell_quad(batch, x, y, &style);
+            }
+            batch.layer();
+        }
+
+        if focused {
+            self.add_bottom_bar(batch, terminal);
+            batch.layer();
+        }
+
+        // Render cursor
+        if focused && terminal.cursor_visible {
+            let style = terminal.cursor_style.unwrap_or(self.cursor_style);
+            self.add_cursor_quad(batch, cursor_x.min(terminal.width.saturating_sub(1)), terminal.cursor_y, style);
+            batch.layer();
+        }
+    }
+
+    /// One grid cell: its shaped glyph, the selection color or a search
+    /// match, and nothing for a blank on the default background
+    #[allow(clippy::too_many_arguments)]
+    fn add_grid_cell(
+        &mut self,
+        batch: &mut QuadBatch,
+        x: u32,
+        y: u32,
+        cell: &TerminalCell,
+        glyph: Option<&CellGlyph>,
+        selected: bool,
+        found: Option<bool>,
+    ) {
+        if let Some(glyph) = glyph {
+            self.add_shaped_cell_quad(batch, x, y, cell, glyph);
+        } else if selected {
+            // Selected cells keep their text on the selection color, blanks included
+            let selected = TerminalCell {
+                background: self.colors.selection,
+                ..cell.clone()
+            };
+            self.add_cell_quad(batch, x, y, &selected);
+        } else if let Some(current) = found {
+            let cell = search_match_cell(cell, current);
+            self.add_cell_quad(batch, x, y, &cell);
+        } else if cell.character != ' ' || cell.background != self.colors.background {
+            // Only render non-empty cells or cells with non-default background
+            self.add_cell_quad(batch, x, y, cell);
+        }
+    }
+
+    /// Search matches in view as rows and cell ranges, and whether each
+    /// is the current one
+    fn search_rows(&self, terminal: &TerminalState) -> Vec<(u32, u32, u32, bool)> {
+        self.search_matches
+            .iter()
+            .filter_map(|found| {
+                let row = terminal.display_row(found.line)?;
+                Some((row, found.start as u32, found.end as u32, self.current_match == Some(*found)))
+            })
+            .collect()
+    }
+
+    /// The search input, or the prefix command line and its candidates,
+    /// over the bottom rows
+    fn add_bottom_bar(
+        &mut self,
+        batch: &mut QuadBatch,
+        terminal: &TerminalState,
+    ) {
+        let rows = match self.search_bar.clone() {
+            Some(row) => vec![row],
+            None => self.command_line.clone(),
+        };
+        let top = terminal.height.saturating_sub(rows.len() as u32);
+        for (y, row) in (top..terminal.height).zip(&rows) {
+            for (x, cell) in row.iter().take(terminal.width as usize).enumerate() {
+                self.add_cell_quad(batch, x as u32, y, cell);
+            }
+        }
+    }
+
+    /// Each pane's grid at its place, then the borders between them
+    fn add_panes(&mut self, batch: &mut QuadBatch) {
+        let panes: Vec<_> = self
+            .panes
+            .iter()
+            .map(|pane| (pane.terminal.clone(), pane.column, pane.row, pane.focused))
+            .collect();
+        let mut rects = Vec::new();
+        let mut focused = None;
+        for (index, (grid, column, row, is_focused)) in panes.into_iter().enumerate() {
+            let history = if grid.read().viewport_offset() > 0 {
+                grid.write().viewport_history()
+            } else {
+                Vec::new()
+            };
+            let terminal = grid.read();
+            rects.push(Rect::new(column, row, terminal.width, terminal.height));
+            if is_focused {
+                focused = Some(index);
+            }
+            self.origin = (column, row);
+            self.add_grid(batch, &terminal, &history, is_focused);
+        }
+        self.origin = (0, 0);
+        batch.layer();
 
-        let terminal = self.terminal_state.read();
+        let theme = BorderTheme::default();
+        let thickness = (self.cell_width.min(self.cell_height) * 0.15).max(1.0);
+        for (x, y, vertical, highlighted) in pane_border_cells(&rects, focused, self.pane_area) {
+            let left = x as f32 * self.cell_width;
+            let top = (y + self.grid_top()) as f32 * self.cell_height;
+            let rect = if vertical {
+                (left + (self.cell_width - thickness) / 2.0, top, thickness, self.cell_height)
+            } else {
+                (left, top + (self.cell_height - thickness) / 2.0, self.cell_width, thickness)
+            };
+            let color = if highlighted { theme.focused } else { theme.unfocused };
+            self.add_pixel_quad(batch, rect, color);
+        }
+    }
 
-        // Render terminal cells
+    /// Cell colors pulled most of the way to the background
+    fn add_dimmed_cells(
+        &mut self,
+        batch: &mut QuadBatch,
+        terminal: &TerminalState,
+    ) {
+        let clear = self.clear_rgba();
+        let dim = |color: [f32; 4]| {
+            let mut out = clear;
+            for i in 0..3 {
+                out[i] = clear[i] + (color[i] - clear[i]) * 0.15;
+            }
+            out
+        };
         for y in 0..terminal.height {
             for x in 0..terminal.width {
-                if let Some(cell) = terminal.get_cell(x, y) {
-                    // Only render non-empty cells or cells with non-default background
-                    if cell.character != ' ' || cell.background != [0.0, 0.0, 0.0, 1.0] {
-                        self.add_cell_quad(&mut vertices, &mut indices, &mut vertex_index, x, y, cell);
+                if let Some(cell) = terminal.get_cell(x, y)
+                    && (cell.character != ' ' || cell.background != self.colors.background)
+                {
+                    let mut cell = cell.clone();
+                    cell.foreground = dim(cell.foreground);
+                    if cell.background != self.colors.background {
+                        cell.background = dim(cell.background);
                     }
+                    self.add_cell_quad(batch, x, y, &cell);
                 }
             }
         }
+    }
 
-        // Render cursor
-        if terminal.cursor_visible {
-            self.add_cursor_quad(&mut vertices, &mut indices, &mut vertex_index, terminal.cursor_x, terminal.cursor_y);
+    /// One line of text centered on the middle row, in a color that
+    /// contrasts with the background
+    fn add_notice(
+        &mut self,
+        batch: &mut QuadBatch,
+        terminal: &TerminalState,
+        notice: &str,
+    ) {
+        let clear = self.clear_rgba();
+        let light = 0.2126 * clear[0] + 0.7152 * clear[1] + 0.0722 * clear[2] > 0.5;
+        let foreground = if light { [0.25, 0.25, 0.25, 1.0] } else { [0.75, 0.75, 0.75, 1.0] };
+        let chars: Vec<char> = notice.chars().take(terminal.width as usize).collect();
+        let start = (terminal.width as usize - chars.len()) / 2;
+        let row = terminal.height / 2;
+        for (i, character) in chars.into_iter().enumerate() {
+            let cell = TerminalCell {
+                character,
+                foreground,
+                ..TerminalCell::default()
+            };
+            self.add_cell_quad(batch, (start + i) as u32, row, &cell);
         }
+    }
 
-        (vertices, indices)
+    /// `lines` in a translucent box in the top-right corner of the grid
+    fn add_stats_overlay(&mut self, batch: &mut QuadBatch, lines: &[String]) {
+        let (cols, rows) = self.grid_cells();
+        if cols < 2 {
+            return;
+        }
+        let width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) as u32 + 2;
+        let width = width.min(cols);
+        let height = (lines.len() as u32).min(rows);
+        let left = cols - width;
+        let top = self.grid_top();
+
+        let rect = (
+            left as f32 * self.cell_width,
+            top as f32 * self.cell_height,
+            width as f32 * self.cell_width,
+            height as f32 * self.cell_height,
+        );
+        self.add_pixel_quad(batch, rect, [0.0, 0.0, 0.0, 0.7]);
+        for (y, line) in lines.iter().take(height as usize).enumerate() {
+            for (x, character) in line.chars().take(width as usize - 1).enumerate() {
+                let cell = TerminalCell {
+                    character,
+                    foreground: [0.9, 0.9, 0.9, 1.0],
+                    background: self.colors.background,
+                    ..TerminalCell::default()
+                };
+                let (x, row) = (left + 1 + x as u32, top + y as u32);
+                self.add_window_cell(batch, x, row, &cell);
+            }
+        }
     }
 
+    fn clear_rgba(&self) -> [f32; 4] {
+        let c = self.clear_color;
+        [c.r as f32, c.g as f32, c.b as f32, c.a as f32]
+    }
+
+    /// Where `ch` drawn across `cells` cells sits in the atlas,
+    /// rasterizing it the first time; None for blank glyphs
+    fn glyph_slot(&mut self, ch: char, cells: u32) -> Option<GlyphSlot> {
+        let key = (self.font.face(ch), ch, cells);
+        if let Some(slot) = self.glyph_cache.get(&key) {
+            self.atlas.hits += 1;
+            return *slot;
+        }
+        let (cell_width, height) = self.font.cell_size();
+        let coverage = self.font.rasterize_span(ch, cells);
+        let slot = self.atlas.upload(&self.queue, &coverage, cell_width * cells, height, cell_width);
+        self.glyph_cache.insert(key, slot);
+        slot
+    }
+
+    /// Atlas slot for a shaped span, drawn from its first cell
+    fn span_slot(&mut self, glyphs: &[ShapedCell]) -> Option<GlyphSlot> {
+        if let Some(slot) = self.span_cache.get(glyphs) {
+            self.atlas.hits += 1;
+            return *slot;
+        }
+        let (cell_width, height) = self.font.cell_size();
+        let coverage = self.font.rasterize_shaped(glyphs);
+        let slot = self.atlas.upload(&self.queue, &coverage, cell_width * glyphs.len() as u32, height, cell_width);
+        self.span_cache.insert(glyphs.to_vec(), slot);
+        slot
+    }
+
+    /// Per column of row `y`, the glyph shaping put there, if it changed
+    /// anything. Cells where `breaks` holds are never shaped.
+    fn ligature_row(&mut self, terminal: &TerminalState, y: u32, breaks: impl Fn(u32) -> bool) -> Vec<Option<CellGlyph>> {
+        let mut glyphs = vec![None; terminal.width as usize];
+        if !self.ligatures {
+            return glyphs;
+        }
+        let row: Vec<TerminalCell> = (0..terminal.width)
+            .map(|x| terminal.get_cell(x, y).cloned().unwrap_or_default())
+            .collect();
+        for run in shaping_runs(&row, |x| breaks(x as u32)) {
+            let text: String = row[run.clone()]
+                .iter()
+                .map(|cell| if cell.character == '\0' { ' ' } else { cell.character })
+                .collect();
+            // Only punctuation pairs like `->` or `!=` ligate in coding fonts
+            let chars: Vec<char> = text.chars().collect();
+            if !chars.windows(2).any(|pair| pair[0].is_ascii_punctuation() && pair[1].is_ascii_punctuation()) {
+                continue;
+            }
+            if self.shaped_runs.len() > MAX_SHAPED_RUNS {
+                self.shaped_runs.clear();
+            }
+            let font = &self.font;
+            let shaped = self.shaped_runs.entry(text).or_insert_with_key(|text| font.shape(text));
+            if shaped.len() != run.len() {
+                continue;
+            }
+            for span in ligature_spans(shaped) {
+                glyphs[run.start + span.start] = Some(CellGlyph::Span(shaped[span.clone()].to_vec()));
+                for glyph in &mut glyphs[run.start + span.start + 1..run.start + span.end] {
+                    *glyph = Some(CellGlyph::Hidden);
+                }
+            }
+        }
+        glyphs
+    }
+
+    /// A cell of the grid, below the tab bar
     fn add_cell_quad(
-        &self,
-        vertices: &mut Vec<Vertex>,
-        indices: &mut Vec<u32>,
-        vertex_index: &mut u32,
+        &mut self,
+        batch: &mut QuadBatch,
+        x: u32,
+        y: u32,
+        cell: &TerminalCell,
+    ) {
+        let row = y + self.grid_top() + self.origin.1;
+        self.add_window_cell(batch, x + self.origin.0, row, cell);
+    }
+
+    /// A grid cell whose glyph came from shaping its run
+    #[allow(clippy::too_many_arguments)]
+    fn add_shaped_cell_quad(
+        &mut self,
+        batch: &mut QuadBatch,
         x: u32,
         y: u32,
         cell: &TerminalCell,
+        glyph: &CellGlyph,
+    ) {
+        let row = y + self.grid_top() + self.origin.1;
+        self.add_glyph_cell(batch, x + self.origin.0, row, cell, Some(glyph));
+    }
+
+    /// A cell at a row of the window, counting the tab bar
+    fn add_window_cell(
+        &mut self,
+        batch: &mut QuadBatch,
+        x: u32,
+        row: u32,
+        cell: &TerminalCell,
+    ) {
+        self.add_glyph_cell(batch, x, row, cell, None);
+    }
+
+    /// A window cell drawn with its own glyph, or with `glyph` when shaping
+    /// replaced it
+    #[allow(clippy::too_many_arguments)]
+    fn add_glyph_cell(
+        &mut self,
+        batch: &mut QuadBatch,
+        x: u32,
+        row: u32,
+        cell: &TerminalCell,
+        glyph: Option<&CellGlyph>,
     ) {
         let x_pos = x as f32 * self.cell_width;
-        let y_pos = y as f32 * self.cell_height;
+        let y_pos = row as f32 * self.cell_height;
         let cell_w = self.cell_width;
         let cell_h = self.cell_height;
 
@@ -364,127 +1398,816 @@ impl SimpleRenderer {
         let bottom = 1.0 - ((y_pos + cell_h) / self.config.height as f32) * 2.0;
 
         // Add background quad if background is not default black
-        if cell.background != [0.0, 0.0, 0.0, 1.0] {
-            vertices.extend_from_slice(&[
-                Vertex {
-                    position: [left, top],
-                    tex_coords: [0.0, 0.0],
-                    color: cell.background,
-                },
-                Vertex {
-                    position: [right, top],
-                    tex_coords: [1.0, 0.0],
-                    color: cell.background,
-                },
-                Vertex {
-                    position: [right, bottom],
-                    tex_coords: [1.0, 1.0],
-                    color: cell.background,
-                },
-                Vertex {
-                    position: [left, bottom],
-                    tex_coords: [0.0, 1.0],
-                    color: cell.background,
-                },
-            ]);
-
-            indices.extend_from_slice(&[
-                *vertex_index, *vertex_index + 1, *vertex_index + 2,
-                *vertex_index, *vertex_index + 2, *vertex_index + 3,
-            ]);
-            *vertex_index += 4;
+        if cell.background != self.colors.background {
+            let uv = self.atlas.layout.solid_uv();
+            batch.background([left, top, right, bottom], uv, cell.background);
         }
 
-        // Add character quad (simplified - just render as colored rectangle for now)
+        // One quad textured with the glyph's coverage
         if cell.character != ' ' {
-            // For now, just render characters as small rectangles in the center of the cell
-            let char_size = 0.8; // 80% of cell size
-            let char_offset = (1.0 - char_size) * 0.5;
-            
-            let char_left = left + (right - left) * char_offset;
-            let char_right = left + (right - left) * (1.0 - char_offset);
-            let char_top = top - (top - bottom) * char_offset;
-            let char_bottom = top - (top - bottom) * (1.0 - char_offset);
-
-            vertices.extend_from_slice(&[
-                Vertex {
-                    position: [char_left, char_top],
-                    tex_coords: [0.0, 0.0],
-                    color: cell.foreground,
-                },
-                Vertex {
-                    position: [char_right, char_top],
-                    tex_coords: [1.0, 0.0],
-                    color: cell.foreground,
-                },
-                Vertex {
-                    position: [char_right, char_bottom],
-                    tex_coords: [1.0, 1.0],
-                    color: cell.foreground,
-                },
-                Vertex {
-                    position: [char_left, char_bottom],
-                    tex_coords: [0.0, 1.0],
-                    color: cell.foreground,
-                },
-            ]);
-
-            indices.extend_from_slice(&[
-                *vertex_index, *vertex_index + 1, *vertex_index + 2,
-                *vertex_index, *vertex_index + 2, *vertex_index + 3,
-            ]);
-            *vertex_index += 4;
+            // Wide glyphs draw across their spacer cell too
+            let cells = if cell.wide { 2 } else { 1 };
+            let slot = match glyph {
+                None => self.glyph_slot(cell.character, cells),
+                Some(CellGlyph::Span(glyphs)) => self.span_slot(glyphs),
+                Some(CellGlyph::Hidden) => None,
+            };
+            if let Some(slot) = slot {
+                // Font pixels scale to the cell; spans reach into the
+                // cells after this one
+                let (font_width, font_height) = self.font.cell_size();
+                let glyph_right = left + (right - left) * slot.width as f32 / font_width.max(1) as f32;
+                let glyph_bottom = top + (bottom - top) * slot.height as f32 / font_height.max(1) as f32;
+                batch.glyph([left, top, glyph_right, glyph_bottom], slot.uv, cell.foreground);
+            }
         }
     }
 
+    fn add_pixel_quad(
+        &self,
+        batch: &mut QuadBatch,
+        (x, y, width, height): (f32, f32, f32, f32),
+        color: [f32; 4],
+    ) {
+        let left = (x / self.config.width as f32) * 2.0 - 1.0;
+        let right = ((x + width) / self.config.width as f32) * 2.0 - 1.0;
+        let top = 1.0 - (y / self.config.height as f32) * 2.0;
+        let bottom = 1.0 - ((y + height) / self.config.height as f32) * 2.0;
+        let uv = self.atlas.layout.solid_uv();
+        batch.background([left, top, right, bottom], uv, color);
+    }
+
+    /// Whether a cursor in `style` shows now, or is in the off half of a
+    /// blink
+    fn cursor_shown(&self, style: CursorStyle) -> bool {
+        let blink_off = (self.blink_epoch.elapsed().as_millis() / CURSOR_BLINK.as_millis()) % 2 == 1;
+        !(style.blink && self.window_focused && blink_off)
+    }
+
+    /// The cursor in `style`: a hollow block while the window is not
+    /// focused, and nothing during the off half of a blink
     fn add_cursor_quad(
         &self,
-        vertices: &mut Vec<Vertex>,
-        indices: &mut Vec<u32>,
-        vertex_index: &mut u32,
+        batch: &mut QuadBatch,
         x: u32,
         y: u32,
+        style: CursorStyle,
     ) {
-        let x_pos = x as f32 * self.cell_width;
-        let y_pos = y as f32 * self.cell_height;
-        let cell_w = self.cell_width;
-        let cell_h = self.cell_height;
+        if !self.cursor_shown(style) {
+            return;
+        }
+        let cell = (
+            (x + self.origin.0) as f32 * self.cell_width,
+            (y + self.grid_top() + self.origin.1) as f32 * self.cell_height,
+            self.cell_width,
+            self.cell_height,
+        );
+        let [r, g, b, _] = self.colors.cursor;
+        let cursor_color = [r, g, b, 0.8];
+        for rect in cursor_rects(style.shape, !self.window_focused, cell) {
+            self.add_pixel_quad(batch, rect, cursor_color);
+        }
+    }
+}
 
-        // Convert screen coordinates to normalized device coordinates
-        let left = (x_pos / self.config.width as f32) * 2.0 - 1.0;
-        let right = ((x_pos + cell_w) / self.config.width as f32) * 2.0 - 1.0;
-        let top = 1.0 - (y_pos / self.config.height as f32) * 2.0;
-        let bottom = 1.0 - ((y_pos + cell_h) / self.config.height as f32) * 2.0;
+/// A cached glyph: the font face that drew it, the character and the
+/// cells it spans
+type GlyphKey = (usize, char, u32);
+
+/// Side of the square glyph texture, in pixels
+const ATLAS_SIZE: u32 = 2048;
+/// The texture is one byte of coverage per pixel
+const ATLAS_BYTES: u64 = ATLAS_SIZE as u64 * ATLAS_SIZE as u64;
+/// Side of the opaque patch that solid quads sample
+const SOLID_PATCH: u32 = 4;
+
+/// A glyph's place in the atlas: texture coordinates (left, top, right,
+/// bottom) and its bitmap width in font pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct GlyphSlot {
+    uv: [f32; 4],
+    width: u32,
+    height: u32,
+}
+
+/// Where glyphs go in the atlas texture. Each gets a transparent border
+/// so filtering never picks up its neighbours, and a white patch in the
+/// first slot lets solid quads share the glyph pipeline.
+struct AtlasLayout {
+    packer: AtlasAllocator,
+    solid: [f32; 2],
+    /// Set when a full atlas started over
+    reset: bool,
+}
+
+impl AtlasLayout {
+    fn new(size: u32) -> Self {
+        let mut layout = Self {
+            packer: AtlasAllocator::new(size, 1),
+            solid: [0.0; 2],
+            reset: false,
+        };
+        layout.clear();
+        layout
+    }
+
+    /// Forget every glyph, keeping the white patch where it was
+    fn clear(&mut self) {
+        self.packer.reset();
+        let patch = self
+            .packer
+            .allocate(SOLID_PATCH, SOLID_PATCH)
+            .expect("an empty atlas holds the white patch");
+        let size = self.packer.size() as f32;
+        let center = SOLID_PATCH as f32 / 2.0;
+        self.solid = [(patch.x as f32 + center) / size, (patch.y as f32 + center) / size];
+    }
+
+    /// Top-left pixel for a `width` x `height` glyph. A full atlas is
+    /// cleared first; None when the glyph can never fit.
+    fn place(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let slot = match self.packer.allocate(width + 2, height + 2) {
+            Ok(slot) => slot,
+            Err(GlyphGuardError::AtlasFull) => {
+                self.clear();
+                self.reset = true;
+                self.packer.allocate(width + 2, height + 2).ok()?
+            }
+            Err(_) => return None,
+        };
+        Some((slot.x + 1, slot.y + 1))
+    }
+
+    fn uv(&self, (x, y): (u32, u32), width: u32, height: u32) -> [f32; 4] {
+        let size = self.packer.size() as f32;
+        [x as f32 / size, y as f32 / size, (x + width) as f32 / size, (y + height) as f32 / size]
+    }
+
+    /// Texture coordinates that sample full coverage
+    fn solid_uv(&self) -> [f32; 4] {
+        let [u, v] = self.solid;
+        [u, v, u, v]
+    }
+
+    /// Whether the atlas started over since the last call
+    fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.reset)
+    }
+}
 
-        let cursor_color = [1.0, 1.0, 1.0, 0.8]; // Semi-transparent white
+/// Glyph coverage on the GPU, sampled by the fragment shader
+struct GlyphAtlas {
+    layout: AtlasLayout,
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    /// Glyph lookups answered from the cache, and glyphs rasterized
+    hits: u64,
+    misses: u64,
+    limits: GlyphLimits,
+}
 
-        vertices.extend_from_slice(&[
-            Vertex {
-                position: [left, top],
-                tex_coords: [0.0, 0.0],
-                color: cursor_color,
+impl GlyphAtlas {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue, bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Glyph Atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
             },
-            Vertex {
-                position: [right, top],
-                tex_coords: [1.0, 0.0],
-                color: cursor_color,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Glyph Atlas Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Glyph Atlas"),
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        let atlas = Self {
+            layout: AtlasLayout::new(ATLAS_SIZE),
+            texture,
+            bind_group,
+            hits: 0,
+            misses: 0,
+            limits: GlyphLimits::default(),
+        };
+        // The patch keeps its place across resets, so it is written once
+        let patch = [u8::MAX; (SOLID_PATCH * SOLID_PATCH) as usize];
+        atlas.write(queue, (0, 0), SOLID_PATCH, SOLID_PATCH, &patch);
+        atlas
+    }
+
+    /// Copy a glyph's coverage into the atlas, clipped to the glyph
+    /// limits. Blank glyphs, and bitmaps that don't match their size or
+    /// can never fit, get no slot.
+    fn upload(
+        &mut self,
+        queue: &wgpu::Queue,
+        coverage: &[u8],
+        width: u32,
+        height: u32,
+        cell_width: u32,
+    ) -> Option<GlyphSlot> {
+        self.misses += 1;
+        if coverage.len() != width as usize * height as usize || coverage.iter().all(|&c| c == 0) {
+            return None;
+        }
+        let (coverage, width, height) = clip_coverage(coverage, width, height, cell_width, &self.limits);
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let origin = self.layout.place(width, height)?;
+        self.write(queue, origin, width, height, &coverage);
+        Some(GlyphSlot {
+            uv: self.layout.uv(origin, width, height),
+            width,
+            height,
+        })
+    }
+
+    fn write(&self, queue: &wgpu::Queue, (x, y): (u32, u32), width: u32, height: u32, data: &[u8]) {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
             },
-            Vertex {
-                position: [right, bottom],
-                tex_coords: [1.0, 1.0],
-                color: cursor_color,
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width),
+                rows_per_image: Some(height),
             },
-            Vertex {
-                position: [left, bottom],
-                tex_coords: [0.0, 1.0],
-                color: cursor_color,
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
             },
-        ]);
+        );
+    }
+}
 
-        indices.extend_from_slice(&[
-            *vertex_index, *vertex_index + 1, *vertex_index + 2,
-            *vertex_index, *vertex_index + 2, *vertex_index + 3,
-        ]);
-        *vertex_index += 4;
+/// Crop a glyph's coverage, `cell_width` pixels per cell, to the glyph
+/// limits. Spans over the byte budget lose whole cells from the right so
+/// the glyphs that stay keep their full height.
+fn clip_coverage<'a>(
+    coverage: &'a [u8],
+    width: u32,
+    height: u32,
+    cell_width: u32,
+    limits: &GlyphLimits,
+) -> (Cow<'a, [u8]>, u32, u32) {
+    let cell_width = cell_width.clamp(1, width.max(1));
+    let cell_bytes = (cell_width as usize * height as usize).max(1);
+    let cells = (width / cell_width).clamp(1, (limits.max_glyph_bytes / cell_bytes).max(1) as u32);
+    let clipped = glyph_guard::clip_raster(
+        width.min(cells * cell_width),
+        height,
+        cells.min(u8::MAX as u32) as u8,
+        cell_width as f32,
+        height as f32,
+        1,
+        limits,
+    );
+    if clipped.width == width && clipped.height == height {
+        return (Cow::Borrowed(coverage), width, height);
     }
-}
\ No newline at end of file
+    let rows = coverage
+        .chunks_exact(width as usize)
+        .take(clipped.height as usize)
+        .flat_map(|row| &row[..clipped.width as usize])
+        .copied()
+        .collect();
+    (Cow::Owned(rows), clipped.width, clipped.height)
+}
+
+/// Every quad of a frame, drawn with a single indexed draw. Within a
+/// layer all backgrounds go under all glyphs, so a wide or overhanging
+/// glyph is never cut off by the next cell's background; each layer is
+/// drawn over the ones before it.
+#[derive(Default)]
+struct QuadBatch {
+    vertices: Vec<Vertex>,
+    backgrounds: Vec<Vertex>,
+    glyphs: Vec<Vertex>,
+}
+
+impl QuadBatch {
+    /// A solid quad, or anything else that belongs under the text
+    fn background(&mut self, rect: [f32; 4], uv: [f32; 4], color: [f32; 4]) {
+        push_quad(&mut self.backgrounds, rect, uv, color);
+    }
+
+    fn glyph(&mut self, rect: [f32; 4], uv: [f32; 4], color: [f32; 4]) {
+        push_quad(&mut self.glyphs, rect, uv, color);
+    }
+
+    /// Close the current layer; what comes next is drawn over it
+    fn layer(&mut self) {
+        self.vertices.append(&mut self.backgrounds);
+        self.vertices.append(&mut self.glyphs);
+    }
+
+    /// The vertices in drawing order and the indices for all of them
+    fn finish(mut self) -> (Vec<Vertex>, Vec<u32>) {
+        self.layer();
+        let quads = (self.vertices.len() / 4) as u32;
+        let indices = (0..quads)
+            .flat_map(|quad| {
+                let first = quad * 4;
+                [first, first + 1, first + 2, first, first + 2, first + 3]
+            })
+            .collect();
+        (self.vertices, indices)
+    }
+}
+
+/// Quads of the single grid's cells, one batch per cell, kept between
+/// frames so a frame rebuilds only the cells that changed
+#[derive(Default)]
+struct GridQuads {
+    width: u32,
+    height: u32,
+    cells: Vec<QuadBatch>,
+    /// Every cell needs rebuilding, e.g. after the colors or the
+    /// selection changed
+    stale: bool,
+    /// The frame being built drew the grid from here
+    drawn: bool,
+}
+
+impl GridQuads {
+    /// Cells to rebuild on a `width` x `height` grid, as columns of each
+    /// row: every cell when stale or resized, otherwise the cells in
+    /// `damage`, widened to whole rows when `whole_rows`
+    fn rows_to_rebuild(
+        &mut self,
+        width: u32,
+        height: u32,
+        damage: &[DamageRect],
+        whole_rows: bool,
+    ) -> Vec<(u32, Range<u32>)> {
+        if std::mem::take(&mut self.stale) || (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.cells = (0..width as usize * height as usize).map(|_| QuadBatch::default()).collect();
+            return (0..height).map(|y| (y, 0..width)).collect();
+        }
+        let mut rows: Vec<(u32, Range<u32>)> = damage
+            .iter()
+            .flat_map(|rect| {
+                let columns = if whole_rows {
+                    0..width
+                } else {
+                    rect.x.min(width)..(rect.x + rect.width).min(width)
+                };
+                (rect.y..(rect.y + rect.height).min(height)).map(move |y| (y, columns.clone()))
+            })
+            .collect();
+        if whole_rows {
+            rows.sort_by_key(|(y, _)| *y);
+            rows.dedup_by_key(|(y, _)| *y);
+        }
+        rows
+    }
+
+    fn set(&mut self, x: u32, y: u32, quads: QuadBatch) {
+        self.cells[(y * self.width + x) as usize] = quads;
+    }
+
+    /// Every cell's quads into the grid's layer of `batch`
+    fn append_to(&self, batch: &mut QuadBatch) {
+        for cell in &self.cells {
+            batch.backgrounds.extend_from_slice(&cell.backgrounds);
+            batch.glyphs.extend_from_slice(&cell.glyphs);
+        }
+    }
+}
+
+/// What a frame shows of the grid besides its cells' quads
+#[derive(Debug, PartialEq)]
+struct GridScene {
+    inline_region: Option<(u32, Vec<Vec<TerminalCell>>)>,
+    viewport_offset: usize,
+    cursor_style: Option<CursorStyle>,
+}
+
+impl GridScene {
+    fn of(terminal: &TerminalState) -> Self {
+        Self {
+            inline_region: terminal.inline_region().map(|(top, rows)| (top, rows.to_vec())),
+            viewport_offset: terminal.viewport_offset(),
+            cursor_style: terminal.cursor_style,
+        }
+    }
+}
+
+/// Store `value` in `slot`; true when that changed it
+fn replace<T: PartialEq>(slot: &mut T, value: T) -> bool {
+    let changed = *slot != value;
+    *slot = value;
+    changed
+}
+
+/// A quad from `left, top, right, bottom` in device coordinates, mapped
+/// to `uv` in the atlas
+fn push_quad(
+    vertices: &mut Vec<Vertex>,
+    [left, top, right, bottom]: [f32; 4],
+    [u0, v0, u1, v1]: [f32; 4],
+    color: [f32; 4],
+) {
+    vertices.extend_from_slice(&[
+        Vertex { position: [left, top], tex_coords: [u0, v0], color },
+        Vertex { position: [right, top], tex_coords: [u1, v0], color },
+        Vertex { position: [right, bottom], tex_coords: [u1, v1], color },
+        Vertex { position: [left, bottom], tex_coords: [u0, v1], color },
+    ]);
+}
+
+/// Shaped runs kept before the cache starts over
+const MAX_SHAPED_RUNS: usize = 4096;
+
+/// A cell's glyph when its run was shaped
+#[derive(Debug, Clone, PartialEq)]
+enum CellGlyph {
+    /// Glyphs drawn from this cell across the span's cells
+    Span(Vec<ShapedCell>),
+    /// Covered by a span starting further left
+    Hidden,
+}
+
+/// Column ranges of a row that shape together: at least two one-cell
+/// characters in the same colors and weight, none of them a `breaks` column
+fn shaping_runs(row: &[TerminalCell], breaks: impl Fn(usize) -> bool) -> Vec<Range<usize>> {
+    let style = |cell: &TerminalCell| (cell.foreground, cell.background, cell.bold, cell.italic, cell.dim);
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for x in 0..=row.len() {
+        let joins = x < row.len()
+            && !row[x].wide
+            && !breaks(x)
+            && (x == start || style(&row[x]) == style(&row[start]));
+        if joins {
+            continue;
+        }
+        if x - start >= 2 {
+            runs.push(start..x);
+        }
+        // A cell that can't be shaped at all starts nothing
+        start = if x < row.len() && !row[x].wide && !breaks(x) { x } else { x + 1 };
+    }
+    runs
+}
+
+/// Spans of a shaped run whose glyphs differ from the plain characters
+fn ligature_spans(shaped: &[ShapedCell]) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut x = 0;
+    while x < shaped.len() {
+        if shaped[x] == ShapedCell::Plain {
+            x += 1;
+            continue;
+        }
+        let start = x;
+        x += 1;
+        while x < shaped.len() && shaped[x] == ShapedCell::Covered {
+            x += 1;
+        }
+        spans.push(start..x);
+    }
+    spans
+}
+
+/// Half a blink cycle: the cursor is shown this long, then hidden as long
+const CURSOR_BLINK: Duration = Duration::from_millis(530);
+
+/// Pixel rectangles drawing a cursor over `cell`. Bars and outlines are a
+/// tenth of the cell thick, at least a pixel.
+fn cursor_rects(shape: CursorShape, hollow: bool, (x, y, width, height): (f32, f32, f32, f32)) -> Vec<(f32, f32, f32, f32)> {
+    let stroke = (width.min(height) * 0.1).max(1.0);
+    if hollow {
+        return vec![
+            (x, y, width, stroke),
+            (x, y + height - stroke, width, stroke),
+            (x, y, stroke, height),
+            (x + width - stroke, y, stroke, height),
+        ];
+    }
+    match shape {
+        CursorShape::Block => vec![(x, y, width, height)],
+        CursorShape::Underline => vec![(x, y + height - stroke, width, stroke)],
+        CursorShape::Beam => vec![(x, y, stroke, height)],
+    }
+}
+
+/// A matched cell in reverse video; the current match is amber instead
+fn search_match_cell(cell: &TerminalCell, current: bool) -> TerminalCell {
+    let (foreground, background) = if current {
+        ([0.0, 0.0, 0.0, 1.0], [1.0, 0.75, 0.2, 1.0])
+    } else {
+        (cell.background, cell.foreground)
+    };
+    TerminalCell {
+        foreground,
+        background,
+        ..cell.clone()
+    }
+}
+
+/// Cells of the one-cell borders between split panes, as (column, row,
+/// vertical, highlighted) in row order. The focused pane's edges are
+/// highlighted.
+fn pane_border_cells(panes: &[Rect], focused: Option<usize>, (cols, rows): (u32, u32)) -> Vec<(u32, u32, bool, bool)> {
+    let mut cells = BTreeMap::new();
+    for (index, pane) in panes.iter().enumerate() {
+        let highlighted = focused == Some(index);
+        let mut mark = |x: u32, y: u32, vertical: bool| {
+            let cell = cells.entry((y, x)).or_insert((vertical, false));
+            cell.1 |= highlighted;
+        };
+        let (right, bottom) = (pane.x + pane.width, pane.y + pane.height);
+        for y in pane.y..bottom {
+            if pane.x > 0 {
+                mark(pane.x - 1, y, true);
+            }
+            if right < cols {
+                mark(right, y, true);
+            }
+        }
+        for x in pane.x..right {
+            if pane.y > 0 {
+                mark(x, pane.y - 1, false);
+            }
+            if bottom < rows {
+                mark(x, bottom, false);
+            }
+        }
+    }
+    cells
+        .into_iter()
+        .map(|((y, x), (vertical, highlighted))| (x, y, vertical, highlighted))
+        .collect()
+}
+
+const TAB_BAR_BACKGROUND: [f32; 4] = [0.15, 0.15, 0.15, 1.0];
+const ACTIVE_TAB_BACKGROUND: [f32; 4] = [0.35, 0.35, 0.35, 1.0];
+/// Widest a tab's label gets, in cells
+const MAX_TAB_WIDTH: usize = 24;
+
+/// One row of `width` cells: each tab's number and title, the active tab
+/// highlighted, titles cut short to fit
+fn tab_bar_cells(titles: &[String], active: usize, width: u32) -> Vec<TerminalCell> {
+    let width = width as usize;
+    let blank = TerminalCell {
+        background: TAB_BAR_BACKGROUND,
+        ..TerminalCell::default()
+    };
+    let mut cells = Vec::with_capacity(width);
+    for (i, title) in titles.iter().enumerate() {
+        let background = if i == active { ACTIVE_TAB_BACKGROUND } else { TAB_BAR_BACKGROUND };
+        let label = format!(" {}: {} ", i + 1, title);
+        let mut used = 0;
+        for character in label.chars() {
+            let char_width = crate::glyph_guard::char_width(character) as usize;
+            if used + char_width > MAX_TAB_WIDTH || cells.len() + char_width > width {
+                break;
+            }
+            cells.push(TerminalCell {
+                character,
+                background,
+                wide: char_width > 1,
+                ..TerminalCell::default()
+            });
+            if char_width > 1 {
+                cells.push(TerminalCell {
+                    background,
+                    ..TerminalCell::default()
+                });
+            }
+            used += char_width;
+        }
+        if cells.len() < width {
+            cells.push(blank.clone());
+        }
+    }
+    cells.resize(width, blank);
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_grow_instead_of_truncating() {
+        assert_eq!(grown_buffer_size(VERTEX_BUFFER_SIZE, 1024), None);
+        assert_eq!(grown_buffer_size(VERTEX_BUFFER_SIZE, VERTEX_BUFFER_SIZE), None);
+
+        // A 400x120 grid with backgrounds and glyphs needs more than the
+        // initial buffer; the new size holds it and at least doubles
+        let quads = 400 * 120 * 2;
+        let needed = (quads * 4 * std::mem::size_of::<Vertex>()) as u64;
+        let grown = grown_buffer_size(VERTEX_BUFFER_SIZE, needed).unwrap();
+        assert!(grown >= needed);
+        assert!(grown >= VERTEX_BUFFER_SIZE * 2);
+    }
+
+    #[test]
+    fn test_quad_batch_draws_backgrounds_under_glyphs_and_layers_in_order() {
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let green = [0.0, 1.0, 0.0, 1.0];
+        let blue = [0.0, 0.0, 1.0, 1.0];
+        let rect = [0.0, 0.0, 1.0, 1.0];
+        let uv = [0.0; 4];
+
+        let mut batch = QuadBatch::default();
+        // Two cells, each a background then a glyph
+        batch.background(rect, uv, red);
+        batch.glyph(rect, uv, green);
+        batch.background(rect, uv, red);
+        batch.glyph(rect, uv, green);
+        batch.layer();
+        // The cursor goes over both
+        batch.background(rect, uv, blue);
+        let (vertices, indices) = batch.finish();
+
+        let order: Vec<[f32; 4]> = vertices.chunks(4).map(|quad| quad[0].color).collect();
+        assert_eq!(order, vec![red, red, green, green, blue]);
+        assert!(vertices.chunks(4).all(|quad| quad.iter().all(|v| v.color == quad[0].color)));
+        assert_eq!(indices.len(), 5 * 6);
+        assert_eq!(&indices[6..12], &[4, 5, 6, 4, 6, 7]);
+        assert_eq!(*indices.iter().max().unwrap() as usize, vertices.len() - 1);
+    }
+
+    /// One frame's damage from `terminal`, as the number of grid cells
+    /// `grid` has to rebuild
+    fn cells_to_rebuild(
+        terminal: &mut TerminalState,
+        damage: &mut DamageTracker,
+        grid: &mut GridQuads,
+        whole_rows: bool,
+    ) -> usize {
+        let (width, height) = (terminal.width, terminal.height);
+        damage.collect(&mut terminal.cells, width, |cell| &mut cell.dirty);
+        damage.cursor(Some((terminal.cursor_x, terminal.cursor_y)), true);
+        let rects = damage.take(width, height);
+        grid.rows_to_rebuild(width, height, &rects, whole_rows)
+            .iter()
+            .map(|(_, columns)| columns.len())
+            .sum()
+    }
+
+    #[test]
+    fn test_typing_one_character_rebuilds_few_cells() {
+        let mut terminal = TerminalState::new(80, 24);
+        let mut damage = DamageTracker::new();
+        let mut grid = GridQuads::default();
+
+        assert_eq!(cells_to_rebuild(&mut terminal, &mut damage, &mut grid, false), 80 * 24);
+        assert_eq!(cells_to_rebuild(&mut terminal, &mut damage, &mut grid, false), 0);
+
+        terminal.feed_bytes(b"a");
+        let rebuilt = cells_to_rebuild(&mut terminal, &mut damage, &mut grid, false);
+        assert!(rebuilt <= 2, "{rebuilt} cells rebuilt for one character");
+
+        // Ligatures take the whole row, and a stale grid everything
+        terminal.feed_bytes(b"b");
+        assert_eq!(cells_to_rebuild(&mut terminal, &mut damage, &mut grid, true), 80);
+        grid.stale = true;
+        assert_eq!(cells_to_rebuild(&mut terminal, &mut damage, &mut grid, false), 80 * 24);
+        terminal.resize(100, 30);
+        assert_eq!(cells_to_rebuild(&mut terminal, &mut damage, &mut grid, false), 100 * 30);
+    }
+
+    #[test]
+    fn test_atlas_layout_pads_glyphs_and_starts_over_when_full() {
+        let mut layout = AtlasLayout::new(16);
+        let solid = layout.solid_uv();
+        assert_eq!(solid, [2.0 / 16.0, 2.0 / 16.0, 2.0 / 16.0, 2.0 / 16.0]);
+
+        // Right of the white patch, inside a one-pixel border
+        assert_eq!(layout.place(4, 4), Some((5, 1)));
+        assert_eq!(layout.uv((5, 1), 4, 4), [5.0 / 16.0, 1.0 / 16.0, 9.0 / 16.0, 5.0 / 16.0]);
+        assert_eq!(layout.place(4, 4), Some((11, 1)));
+        assert!(!layout.take_reset());
+
+        // Another shelf fills it; the next glyph clears the atlas and
+        // lands beside the patch again
+        assert_eq!(layout.place(14, 4), Some((1, 7)));
+        assert_eq!(layout.place(4, 4), Some((5, 1)));
+        assert!(layout.take_reset());
+        assert!(!layout.take_reset());
+        assert_eq!(layout.solid_uv(), solid);
+
+        assert_eq!(layout.place(15, 1), None);
+    }
+
+    #[test]
+    fn test_clip_coverage_keeps_glyphs_within_limits() {
+        let limits = GlyphLimits {
+            max_glyph_bytes: 3 * 8 * 16,
+            ..GlyphLimits::default()
+        };
+        let cell: Vec<u8> = (0..8 * 16).map(|i| i as u8).collect();
+        let (coverage, width, height) = clip_coverage(&cell, 8, 16, 8, &limits);
+        assert!(matches!(coverage, Cow::Borrowed(_)));
+        assert_eq!((width, height), (8, 16));
+
+        // A ten-cell ligature span keeps its first three cells at full height
+        let span = vec![7u8; 80 * 16];
+        let (coverage, width, height) = clip_coverage(&span, 80, 16, 8, &limits);
+        assert_eq!((width, height), (24, 16));
+        assert_eq!(coverage.len(), 24 * 16);
+
+        // A single cell over the budget is cut short instead
+        let tiny = GlyphLimits {
+            max_glyph_bytes: 8 * 4,
+            ..GlyphLimits::default()
+        };
+        let (coverage, width, height) = clip_coverage(&cell, 8, 16, 8, &tiny);
+        assert_eq!((width, height), (8, 4));
+        assert_eq!(&coverage[..], &cell[..8 * 4]);
+    }
+
+    #[test]
+    fn test_cursor_shapes() {
+        let cell = (10.0, 20.0, 8.0, 20.0);
+        assert_eq!(cursor_rects(CursorShape::Block, false, cell), vec![cell]);
+        // Thin cells still get a one-pixel bar
+        assert_eq!(cursor_rects(CursorShape::Underline, false, cell), vec![(10.0, 39.0, 8.0, 1.0)]);
+        assert_eq!(cursor_rects(CursorShape::Beam, false, cell), vec![(10.0, 20.0, 1.0, 20.0)]);
+        // Unfocused windows get an outline whatever the shape
+        assert_eq!(cursor_rects(CursorShape::Beam, true, cell).len(), 4);
+    }
+
+    #[test]
+    fn test_tab_bar_labels() {
+        let titles = vec!["zsh".to_string(), "vim a-very-long-file-name.rs".to_string()];
+        let cells = tab_bar_cells(&titles, 1, 40);
+        assert_eq!(cells.len(), 40);
+        let text: String = cells.iter().map(|cell| cell.character).collect();
+        assert_eq!(text.trim_end(), " 1: zsh   2: vim a-very-long-file");
+        assert_eq!(cells[1].background, TAB_BAR_BACKGROUND);
+        assert_eq!(cells[9].background, ACTIVE_TAB_BACKGROUND);
+
+        // A narrow window cuts off the last tabs
+        assert_eq!(tab_bar_cells(&titles, 0, 5).len(), 5);
+    }
+
+    #[test]
+    fn test_shaping_runs_break_on_style_and_cursor() {
+        let plain = |character| TerminalCell { character, ..TerminalCell::default() };
+        let mut row: Vec<TerminalCell> = "a -> b != c".chars().map(plain).collect();
+        assert_eq!(shaping_runs(&row, |_| false), vec![0..11]);
+        // The cursor on `>` splits `->`
+        assert_eq!(shaping_runs(&row, |x| x == 3), vec![0..3, 4..11]);
+        // So does a color change, and a lone cell is no run
+        row[4].foreground = [1.0, 0.0, 0.0, 1.0];
+        assert_eq!(shaping_runs(&row, |_| false), vec![0..4, 5..11]);
+        row[10].wide = true;
+        assert_eq!(shaping_runs(&row, |x| x == 9), vec![0..4, 5..9]);
+    }
+
+    #[test]
+    fn test_ligature_spans() {
+        use ShapedCell::*;
+        let shaped = [Plain, Glyph(7), Covered, Plain, Glyph(9), Glyph(10), Covered];
+        assert_eq!(ligature_spans(&shaped), vec![1..3, 4..5, 5..7]);
+        assert!(ligature_spans(&[Plain, Plain]).is_empty());
+    }
+
+    #[test]
+    fn test_pane_borders() {
+        // One pane on the left, two stacked on the right; the lower one has focus
+        let panes = [Rect::new(0, 0, 40, 24), Rect::new(41, 0, 40, 11), Rect::new(41, 12, 40, 12)];
+        let cells = pane_border_cells(&panes, Some(2), (81, 24));
+        assert_eq!(cells.len(), 24 + 40);
+        assert!(cells.contains(&(40, 0, true, false)));
+        assert!(cells.contains(&(40, 11, true, false)));
+        assert!(cells.contains(&(40, 12, true, true)));
+        assert!(cells.contains(&(60, 11, false, true)));
+        assert!(cells.iter().all(|&(x, y, ..)| !panes.iter().any(|pane| pane.contains(x, y))));
+    }
+}
@@ -0,0 +1,435 @@
+use crate::bitmap_font::BitmapFont;
+use crate::terminal::TerminalState;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StartupError {
+    #[error("Font loading failed: {0}")]
+    FontLoad(String),
+    #[error("Font loader exited without a result")]
+    LoaderDisconnected,
+}
+
+/// What shaping a run did to one of its characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShapedCell {
+    /// The character's own glyph
+    Plain,
+    /// A ligature or contextual alternate drawn in this cell
+    Glyph(u16),
+    /// Part of a ligature whose glyph starts in an earlier cell
+    Covered,
+}
+
+/// A monospace font rendered one cell at a time
+pub trait CellFont: Send + Sync {
+    fn name(&self) -> &str;
+    /// Cell size in pixels
+    fn cell_size(&self) -> (u32, u32);
+    /// Row-major coverage for one cell, `cell_size().0 * cell_size().1` bytes
+    fn rasterize(&self, ch: char) -> Vec<u8>;
+
+    /// Coverage for `ch` drawn across `cells` cells side by side, row-major
+    /// and `cells * cell_size().0` wide. Fonts without wide glyphs draw in
+    /// the first cell.
+    fn rasterize_span(&self, ch: char, cells: u32) -> Vec<u8> {
+        let (width, height) = self.cell_size();
+        let cells = cells.max(1);
+        let glyph = self.rasterize(ch);
+        let mut coverage = vec![0u8; (width * cells * height) as usize];
+        for row in 0..height {
+            let src = (row * width) as usize..((row + 1) * width) as usize;
+            let dest = (row * width * cells) as usize;
+            coverage[dest..dest + width as usize].copy_from_slice(&glyph[src]);
+        }
+        coverage
+    }
+
+    /// Ligatures and contextual alternates for a run of one-cell
+    /// characters, one entry per character. Fonts that don't shape keep
+    /// every character's own glyph.
+    fn shape(&self, text: &str) -> Vec<ShapedCell> {
+        vec![ShapedCell::Plain; text.chars().count()]
+    }
+
+    /// Coverage for shaped glyphs one per cell side by side, row-major and
+    /// `glyphs.len() * cell_size().0` wide. Glyphs may reach into the
+    /// neighbouring cells of the span.
+    fn rasterize_shaped(&self, glyphs: &[ShapedCell]) -> Vec<u8> {
+        let (width, height) = self.cell_size();
+        vec![0; (width * glyphs.len() as u32 * height) as usize]
+    }
+
+    /// Which of the font's faces draws `ch`; glyphs are cached per face
+    fn face(&self, _ch: char) -> usize {
+        0
+    }
+
+    /// Whether the font draws `ch` rather than leaving the cell empty
+    fn covers(&self, ch: char) -> bool {
+        self.rasterize(ch).iter().any(|&coverage| coverage > 0)
+    }
+}
+
+/// Startup milestones, in the order they normally complete
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupStage {
+    WindowCreated,
+    FirstFrame,
+    PtySpawned,
+    RealFontReady,
+    ModelsReady,
+}
+
+#[derive(Debug, Clone)]
+pub struct StartupReport {
+    started: Instant,
+    marks: Vec<(StartupStage, Duration)>,
+}
+
+impl StartupReport {
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            marks: Vec::new(),
+        }
+    }
+
+    /// Record a stage; only the first occurrence counts
+    pub fn mark(&mut self, stage: StartupStage) {
+        if self.elapsed(stage).is_none() {
+            self.marks.push((stage, self.started.elapsed()));
+        }
+    }
+
+    pub fn elapsed(&self, stage: StartupStage) -> Option<Duration> {
+        self.marks
+            .iter()
+            .find(|(s, _)| *s == stage)
+            .map(|(_, elapsed)| *elapsed)
+    }
+
+    pub fn time_to_first_frame(&self) -> Option<Duration> {
+        self.elapsed(StartupStage::FirstFrame)
+    }
+
+    pub fn time_to_real_font(&self) -> Option<Duration> {
+        self.elapsed(StartupStage::RealFontReady)
+    }
+
+    pub fn summary(&self) -> String {
+        let fmt = |d: Option<Duration>| d.map_or("pending".to_string(), |d| format!("{:?}", d));
+        format!(
+            "first frame {}, real font {}, models {}",
+            fmt(self.time_to_first_frame()),
+            fmt(self.time_to_real_font()),
+            fmt(self.elapsed(StartupStage::ModelsReady))
+        )
+    }
+}
+
+pub type FontLoadResult = Result<Arc<dyn CellFont>, StartupError>;
+
+/// Drives the staged startup: the built-in bitmap font is active from the
+/// first frame while the real font loads on a background thread, and work
+/// that must not delay first paint is held until the first frame is shown.
+pub struct StagedStartup {
+    report: StartupReport,
+    font: Arc<dyn CellFont>,
+    pending_font: Option<Receiver<FontLoadResult>>,
+    after_first_paint: Vec<Box<dyn FnOnce() + Send>>,
+}
+
+impl StagedStartup {
+    pub fn new(started: Instant, fallback: BitmapFont) -> Self {
+        Self {
+            report: StartupReport::new(started),
+            font: Arc::new(fallback),
+            pending_font: None,
+            after_first_paint: Vec::new(),
+        }
+    }
+
+    pub fn report(&self) -> &StartupReport {
+        &self.report
+    }
+
+    pub fn mark(&mut self, stage: StartupStage) {
+        self.report.mark(stage);
+    }
+
+    /// The font to render with right now
+    pub fn font(&self) -> Arc<dyn CellFont> {
+        self.font.clone()
+    }
+
+    pub fn has_real_font(&self) -> bool {
+        self.report.time_to_real_font().is_some()
+    }
+
+    /// Start loading the real font in the background
+    pub fn load_font<F>(&mut self, load: F)
+    where
+        F: FnOnce() -> FontLoadResult + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(load());
+        });
+        self.pending_font = Some(rx);
+    }
+
+    /// Queue work (model registration, warmup) to start once the first frame
+    /// has been presented
+    pub fn after_first_paint<F>(&mut self, task: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.report.time_to_first_frame().is_some() {
+            thread::spawn(task);
+        } else {
+            self.after_first_paint.push(Box::new(task));
+        }
+    }
+
+    /// Call after presenting a frame. The first call records time-to-first-frame
+    /// and releases the deferred tasks onto background threads.
+    pub fn frame_presented(&mut self) {
+        if self.report.time_to_first_frame().is_some() {
+            return;
+        }
+        self.report.mark(StartupStage::FirstFrame);
+        for task in self.after_first_paint.drain(..) {
+            thread::spawn(task);
+        }
+    }
+
+    /// Check on the background font load without blocking. Returns the newly
+    /// active font when the swap happens; the caller must mark the whole grid
+    /// dirty so every cell is redrawn with it.
+    pub fn poll_font(&mut self) -> Option<Result<Arc<dyn CellFont>, StartupError>> {
+        let rx = self.pending_font.as_ref()?;
+        let result = match rx.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(StartupError::LoaderDisconnected),
+        };
+        self.pending_font = None;
+        Some(result.inspect(|font| {
+            self.font = font.clone();
+            self.report.mark(StartupStage::RealFontReady);
+        }))
+    }
+}
+
+pub fn mark_all_dirty(terminal: &mut TerminalState) {
+    for cell in &mut terminal.cells {
+        cell.dirty = true;
+    }
+}
+
+/// CPU rasterizer for the terminal grid, used headless and as the reference
+/// for what the GPU path should show. Only dirty cells are redrawn.
+pub struct HeadlessRenderer {
+    font: Arc<dyn CellFont>,
+    cols: u32,
+    rows: u32,
+    /// Coverage per pixel, row-major
+    pixels: Vec<u8>,
+    pub frames: u64,
+}
+
+impl HeadlessRenderer {
+    pub fn new(font: Arc<dyn CellFont>, cols: u32, rows: u32) -> Self {
+        let (cell_w, cell_h) = font.cell_size();
+        Self {
+            font,
+            cols,
+            rows,
+            pixels: vec![0; (cols * cell_w * rows * cell_h) as usize],
+            frames: 0,
+        }
+    }
+
+    pub fn font_name(&self) -> &str {
+        self.font.name()
+    }
+
+    /// Switch fonts; the framebuffer is reallocated for the new cell size
+    pub fn set_font(&mut self, font: Arc<dyn CellFont>) {
+        *self = Self {
+            frames: self.frames,
+            ..Self::new(font, self.cols, self.rows)
+        };
+    }
+
+    pub fn render(&mut self, terminal: &mut TerminalState) {
+        if terminal.width != self.cols || terminal.height != self.rows {
+            self.cols = terminal.width;
+            self.rows = terminal.height;
+            self.set_font(self.font.clone());
+            mark_all_dirty(terminal);
+        }
+
+        let (cell_w, cell_h) = self.font.cell_size();
+        let stride = (self.cols * cell_w) as usize;
+        for y in 0..terminal.height {
+            for x in 0..terminal.width {
+                let index = (y * terminal.width + x) as usize;
+                let cell = &mut terminal.cells[index];
+                if !cell.dirty {
+                    continue;
+                }
+                let coverage = self.font.rasterize(cell.character);
+                for row in 0..cell_h as usize {
+                    let src = &coverage[row * cell_w as usize..(row + 1) * cell_w as usize];
+                    let dst = (y * cell_h) as usize * stride + row * stride + (x * cell_w) as usize;
+                    self.pixels[dst..dst + cell_w as usize].copy_from_slice(src);
+                }
+                cell.dirty = false;
+            }
+        }
+        self.frames += 1;
+    }
+
+    /// Coverage of one rendered cell, for comparison with `CellFont::rasterize`
+    pub fn cell_pixels(&self, x: u32, y: u32) -> Vec<u8> {
+        let (cell_w, cell_h) = self.font.cell_size();
+        let stride = (self.cols * cell_w) as usize;
+        let mut out = Vec::with_capacity((cell_w * cell_h) as usize);
+        for row in 0..cell_h as usize {
+            let start = (y * cell_h) as usize * stride + row * stride + (x * cell_w) as usize;
+            out.extend_from_slice(&self.pixels[start..start + cell_w as usize]);
+        }
+        out
+    }
+
+    /// Whether the framebuffer shows `text` starting at cell (x, y)
+    pub fn shows_text(&self, x: u32, y: u32, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(i, ch)| self.cell_pixels(x + i as u32, y) == self.font.rasterize(ch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    /// Stands in for the real font: a different cell size and a solid block
+    /// for every visible character
+    struct BlockFont;
+
+    impl CellFont for BlockFont {
+        fn name(&self) -> &str {
+            "block"
+        }
+
+        fn cell_size(&self) -> (u32, u32) {
+            (4, 8)
+        }
+
+        fn rasterize(&self, ch: char) -> Vec<u8> {
+            vec![if ch == ' ' { 0 } else { 200 }; 32]
+        }
+    }
+
+    /// PTY stand-in: output arrives over a channel like the reader task's
+    fn pty_output() -> (Sender<Vec<u8>>, Receiver<Vec<u8>>) {
+        mpsc::channel()
+    }
+
+    #[test]
+    fn test_prompt_visible_before_real_font() {
+        let mut startup = StagedStartup::new(Instant::now(), BitmapFont::new(1));
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        startup.load_font(move || {
+            // Artificially delayed until the test releases it
+            release_rx
+                .recv()
+                .map_err(|e| StartupError::FontLoad(e.to_string()))?;
+            Ok(Arc::new(BlockFont) as Arc<dyn CellFont>)
+        });
+
+        let mut terminal = TerminalState::new(20, 4);
+        let mut renderer = HeadlessRenderer::new(startup.font(), 20, 4);
+        renderer.render(&mut terminal);
+        startup.frame_presented();
+        assert!(startup.report().time_to_first_frame().is_some());
+
+        let (pty_tx, pty_rx) = pty_output();
+        pty_tx.send(b"user@host:~$ ".to_vec()).unwrap();
+        for chunk in pty_rx.try_iter() {
+            terminal.feed_bytes(&chunk);
+        }
+        renderer.render(&mut terminal);
+
+        assert!(startup.poll_font().is_none());
+        assert!(!startup.has_real_font());
+        assert_eq!(renderer.font_name(), "builtin-5x7");
+        assert!(renderer.shows_text(0, 0, "user@host:~$"));
+
+        release_tx.send(()).unwrap();
+        let font = loop {
+            if let Some(result) = startup.poll_font() {
+                break result.unwrap();
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert!(startup.report().time_to_real_font() >= startup.report().time_to_first_frame());
+
+        renderer.set_font(font);
+        mark_all_dirty(&mut terminal);
+        renderer.render(&mut terminal);
+        assert_eq!(renderer.font_name(), "block");
+        assert!(renderer.shows_text(0, 0, "user@host:~$"));
+        assert_eq!(terminal.get_cell(5, 0).unwrap().character, 'h');
+    }
+
+    #[test]
+    fn test_deferred_work_runs_after_first_paint() {
+        let mut startup = StagedStartup::new(Instant::now(), BitmapFont::default());
+        let (tx, rx) = mpsc::channel();
+        startup.after_first_paint(move || {
+            tx.send("models registered").unwrap();
+        });
+        assert!(rx.try_recv().is_err());
+
+        startup.frame_presented();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "models registered"
+        );
+    }
+
+    #[test]
+    fn test_failed_font_load_keeps_fallback() {
+        let mut startup = StagedStartup::new(Instant::now(), BitmapFont::default());
+        startup.load_font(|| Err(StartupError::FontLoad("missing".to_string())));
+        let result = loop {
+            if let Some(result) = startup.poll_font() {
+                break result;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert!(result.is_err());
+        assert_eq!(startup.font().name(), "builtin-5x7");
+        assert!(startup.report().summary().contains("real font pending"));
+    }
+
+    #[test]
+    fn test_wide_span_keeps_glyph_in_first_cell() {
+        let span = BlockFont.rasterize_span('中', 2);
+        assert_eq!(span.len(), 64);
+        for row in span.chunks(8) {
+            assert_eq!(row[..4], [200; 4]);
+            assert_eq!(row[4..], [0; 4]);
+        }
+        assert_eq!(BlockFont.rasterize_span('a', 1), BlockFont.rasterize('a'));
+    }
+}
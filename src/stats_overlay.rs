@@ -0,0 +1,294 @@
+//! Frame rate, renderer, output, input and model numbers for the debug overlay
+//! (F12 or `:stats`) and for `:stats --json`
+
+use crate::gpu_timing::GpuStats;
+use crate::input::InputStats;
+use crate::model_host::ModelHostStats;
+use crate::output_scheduler::OutputMetrics;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How often the overlay's numbers change; redrawing them every frame
+/// would make them unreadable
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+/// Frames counted for the rate and the average frame time
+const FRAME_WINDOW: Duration = Duration::from_secs(1);
+
+/// Frames drawn over the last second and how long each took to build and
+/// submit
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    frames: VecDeque<(Instant, Duration)>,
+}
+
+impl FrameStats {
+    pub fn record(&mut self, at: Instant, took: Duration) {
+        self.frames.push_back((at, took));
+        while self.frames.front().is_some_and(|&(start, _)| at.duration_since(start) > FRAME_WINDOW) {
+            self.frames.pop_front();
+        }
+    }
+
+    fn recent(&self, now: Instant) -> impl Iterator<Item = Duration> + '_ {
+        self.frames
+            .iter()
+            .filter(move |&&(at, _)| now.saturating_duration_since(at) <= FRAME_WINDOW)
+            .map(|&(_, took)| took)
+    }
+
+    pub fn fps(&self, now: Instant) -> usize {
+        self.recent(now).count()
+    }
+
+    /// Average over the last second, zero when nothing was drawn
+    pub fn frame_time(&self, now: Instant) -> Duration {
+        let (count, total) = self.recent(now).fold((0u32, Duration::ZERO), |(n, sum), took| (n + 1, sum + took));
+        total.checked_div(count).unwrap_or_default()
+    }
+}
+
+/// One window's renderer numbers
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderStats {
+    pub fps: usize,
+    pub frame_time: Duration,
+    /// Vertex and index buffers and the glyph atlas
+    pub gpu_memory: u64,
+    pub cached_glyphs: usize,
+    /// Glyph lookups the cache answered, and glyphs rasterized into the atlas
+    pub glyph_hits: u64,
+    pub glyph_misses: u64,
+    /// Merged regions of cells changed for the last frame
+    pub dirty_regions: usize,
+    /// Grid cells whose quads were rebuilt, and frames not drawn because
+    /// nothing changed, since startup
+    pub cells_redrawn: u64,
+    pub frames_skipped: u64,
+    pub gpu: GpuStats,
+}
+
+/// Requests one model answered and their average latency
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelStats {
+    pub name: String,
+    pub requests: u64,
+    pub average_latency: Option<Duration>,
+}
+
+/// Registered models in name order, with the ones that have answered
+/// anything since startup
+pub fn model_stats(registered: &[String], host: &ModelHostStats) -> Vec<ModelStats> {
+    let mut names: Vec<&String> = registered.iter().chain(host.per_model.keys()).collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| {
+            let latency = host.per_model.get(name).copied().unwrap_or_default();
+            ModelStats {
+                name: name.clone(),
+                requests: latency.requests,
+                average_latency: latency.average(),
+            }
+        })
+        .collect()
+}
+
+/// Everything `:stats` shows
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsReport {
+    pub render: RenderStats,
+    /// The active tab's PTY output scheduling
+    pub output: OutputMetrics,
+    /// Key binding lookups answered from the cache, unknown before any
+    pub input_cache_hit_rate: Option<f64>,
+    pub models: Vec<ModelStats>,
+}
+
+impl StatsReport {
+    pub fn new(render: RenderStats, output: OutputMetrics, input: &InputStats, models: Vec<ModelStats>) -> Self {
+        let lookups = input.cache_hits + input.cache_misses;
+        Self {
+            render,
+            output,
+            input_cache_hit_rate: (lookups > 0).then(|| input.cache_hits as f64 / lookups as f64),
+            models,
+        }
+    }
+
+    /// Overlay text, one line per row
+    pub fn lines(&self) -> Vec<String> {
+        let render = &self.render;
+        let mut lines = vec![
+            format!("fps {}  frame {:.2} ms", render.fps, millis(render.frame_time)),
+            format!("gpu mem {:.1} MiB", render.gpu_memory as f64 / (1024.0 * 1024.0)),
+            format!(
+                "glyphs {} ({} hit, {} miss)  dirty {}",
+                render.cached_glyphs, render.glyph_hits, render.glyph_misses, render.dirty_regions
+            ),
+            format!("redrawn {} cells  skipped {} frames", render.cells_redrawn, render.frames_skipped),
+            format!(
+                "output backlog {:.1} KiB  slice {:.0}%",
+                self.output.backlog_bytes as f64 / 1024.0,
+                self.output.slice_utilization * 100.0
+            ),
+            match self.input_cache_hit_rate {
+                Some(rate) => format!("input cache {:.0}%", rate * 100.0),
+                None => "input cache -".to_string(),
+            },
+            render.gpu.to_string(),
+        ];
+        for model in &self.models {
+            lines.push(match model.average_latency {
+                Some(latency) => format!("{}: {} req, avg {:.0} ms", model.name, model.requests, millis(latency)),
+                None => format!("{}: {} req", model.name, model.requests),
+            });
+        }
+        lines
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let render = &self.render;
+        json!({
+            "fps": render.fps,
+            "frame_time_ms": millis(render.frame_time),
+            "gpu_memory_bytes": render.gpu_memory,
+            "cached_glyphs": render.cached_glyphs,
+            "glyph_hits": render.glyph_hits,
+            "glyph_misses": render.glyph_misses,
+            "dirty_regions": render.dirty_regions,
+            "cells_redrawn": render.cells_redrawn,
+            "frames_skipped": render.frames_skipped,
+            "output_backlog_bytes": self.output.backlog_bytes,
+            "output_slice_utilization": self.output.slice_utilization,
+            "gpu_pass_us": render.gpu.to_json(),
+            "input_cache_hit_rate": self.input_cache_hit_rate,
+            "models": self.models.iter().map(|model| json!({
+                "name": model.name,
+                "requests": model.requests,
+                "average_latency_ms": model.average_latency.map(millis),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Whether the overlay is shown, and when its text was last refreshed
+#[derive(Debug, Default)]
+pub struct StatsOverlay {
+    visible: bool,
+    refreshed: Option<Instant>,
+}
+
+impl StatsOverlay {
+    /// Show or hide; returns whether it is now shown
+    pub fn toggle(&mut self) -> bool {
+        self.visible = !self.visible;
+        self.refreshed = None;
+        self.visible
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Time to refresh the text: at once when shown, then every
+    /// `REFRESH_INTERVAL`
+    pub fn due(&mut self, now: Instant) -> bool {
+        if !self.visible || self.refreshed.is_some_and(|at| now.duration_since(at) < REFRESH_INTERVAL) {
+            return false;
+        }
+        self.refreshed = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_host::ModelLatency;
+
+    fn render_stats() -> RenderStats {
+        RenderStats {
+            fps: 60,
+            frame_time: Duration::from_micros(1500),
+            gpu_memory: 2 * 1024 * 1024,
+            cached_glyphs: 90,
+            glyph_hits: 4000,
+            glyph_misses: 90,
+            dirty_regions: 3,
+            cells_redrawn: 1920,
+            frames_skipped: 42,
+            gpu: GpuStats::Unavailable,
+        }
+    }
+
+    #[test]
+    fn test_frame_stats_cover_the_last_second() {
+        let start = Instant::now();
+        let mut frames = FrameStats::default();
+        for i in 0..10 {
+            frames.record(start + Duration::from_millis(i * 200), Duration::from_millis(i + 1));
+        }
+        let now = start + Duration::from_millis(1800);
+        // Frames at 800..=1800 ms, taking 5..=10 ms
+        assert_eq!(frames.fps(now), 6);
+        assert_eq!(frames.frame_time(now), Duration::from_micros(7500));
+        assert_eq!(frames.fps(now + Duration::from_secs(5)), 0);
+        assert_eq!(frames.frame_time(now + Duration::from_secs(5)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_report_lines_and_json() {
+        let input = InputStats { cache_hits: 3, cache_misses: 1, ..InputStats::default() };
+        let mut host = ModelHostStats::default();
+        host.per_model.insert(
+            "llama".to_string(),
+            ModelLatency { requests: 2, total_time: Duration::from_millis(900) },
+        );
+        let models = model_stats(&["qwen".to_string(), "llama".to_string()], &host);
+        let output = OutputMetrics {
+            backlog_bytes: 3 * 1024,
+            slice_utilization: 0.5,
+            ..OutputMetrics::default()
+        };
+        let report = StatsReport::new(render_stats(), output, &input, models);
+
+        let lines = report.lines();
+        assert_eq!(lines[0], "fps 60  frame 1.50 ms");
+        assert_eq!(lines[1], "gpu mem 2.0 MiB");
+        assert_eq!(lines[2], "glyphs 90 (4000 hit, 90 miss)  dirty 3");
+        assert_eq!(lines[3], "redrawn 1920 cells  skipped 42 frames");
+        assert_eq!(lines[4], "output backlog 3.0 KiB  slice 50%");
+        assert_eq!(lines[5], "input cache 75%");
+        assert_eq!(&lines[7..], ["llama: 2 req, avg 450 ms", "qwen: 0 req"]);
+
+        let json = report.to_json();
+        assert_eq!(json["fps"], 60);
+        assert_eq!(json["glyph_misses"], 90);
+        assert_eq!(json["frames_skipped"], 42);
+        assert_eq!(json["output_backlog_bytes"], 3072);
+        assert_eq!(json["output_slice_utilization"], 0.5);
+        assert_eq!(json["input_cache_hit_rate"], 0.75);
+        assert_eq!(json["models"][0]["average_latency_ms"], 450.0);
+        assert!(json["models"][1]["average_latency_ms"].is_null());
+        assert!(json["gpu_pass_us"].is_null());
+    }
+
+    #[test]
+    fn test_overlay_refreshes_at_two_hertz() {
+        let start = Instant::now();
+        let mut overlay = StatsOverlay::default();
+        assert!(!overlay.due(start));
+        assert!(overlay.toggle());
+        assert!(overlay.due(start));
+        assert!(!overlay.due(start + Duration::from_millis(100)));
+        assert!(overlay.due(start + REFRESH_INTERVAL));
+        assert!(!overlay.toggle());
+        assert!(!overlay.due(start + REFRESH_INTERVAL * 4));
+    }
+}
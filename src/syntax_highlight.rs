@@ -0,0 +1,354 @@
+use crate::terminal::TerminalCell;
+use std::sync::{Arc, LazyLock};
+use syntect::highlighting::{
+    FontStyle, HighlightIterator, HighlightState, Highlighter, Style, Theme, ThemeSet,
+};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use thiserror::Error;
+use tracing::debug;
+
+#[derive(Error, Debug)]
+pub enum HighlightError {
+    #[error("Unknown code theme: {0}")]
+    UnknownTheme(String),
+}
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// Colors code in agent responses with one of syntect's bundled themes
+#[derive(Debug, Clone)]
+pub struct SyntaxHighlighter {
+    theme: Arc<Theme>,
+}
+
+impl SyntaxHighlighter {
+    /// `theme` is a bundled theme name such as "base16-ocean.dark"
+    pub fn new(theme: &str) -> Result<Self, HighlightError> {
+        let theme = THEMES
+            .themes
+            .get(theme)
+            .ok_or_else(|| HighlightError::UnknownTheme(theme.to_string()))?;
+        Ok(Self {
+            theme: Arc::new(theme.clone()),
+        })
+    }
+
+    pub fn theme_names() -> Vec<&'static str> {
+        THEMES.themes.keys().map(String::as_str).collect()
+    }
+
+    /// A block in `language`, a fence tag such as "rust" or "py"; unknown
+    /// or empty tags come out plain
+    pub fn block(&self, language: &str) -> CodeBlock {
+        let syntax = SYNTAXES
+            .find_syntax_by_token(language)
+            .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+        CodeBlock::new(syntax, self.theme.clone())
+    }
+
+    /// Each line of `code` as cells
+    pub fn highlight(&self, code: &str, language: &str) -> Vec<Vec<TerminalCell>> {
+        let mut block = self.block(language);
+        block.push(code);
+        block.finish();
+        block.rows
+    }
+}
+
+/// A code block highlighted as it arrives. Each line is parsed once, when
+/// its line break comes in, so appending text never reworks earlier lines.
+pub struct CodeBlock {
+    theme: Arc<Theme>,
+    parse: ParseState,
+    highlight: HighlightState,
+    rows: Vec<Vec<TerminalCell>>,
+    /// The line still being written
+    pending: String,
+}
+
+impl CodeBlock {
+    fn new(syntax: &SyntaxReference, theme: Arc<Theme>) -> Self {
+        let highlight = HighlightState::new(&Highlighter::new(&theme), ScopeStack::new());
+        Self {
+            theme,
+            parse: ParseState::new(syntax),
+            highlight,
+            rows: Vec::new(),
+            pending: String::new(),
+        }
+    }
+
+    /// Take in more of the block; returns how many lines it completed
+    pub fn push(&mut self, text: &str) -> usize {
+        let before = self.rows.len();
+        for piece in text.split_inclusive('\n') {
+            self.pending.push_str(piece);
+            if self.pending.ends_with('\n') {
+                let line = std::mem::take(&mut self.pending);
+                let row = self.highlight_line(&line);
+                self.rows.push(row);
+            }
+        }
+        self.rows.len() - before
+    }
+
+    /// Highlight a last line that never got its line break
+    pub fn finish(&mut self) {
+        if !self.pending.is_empty() {
+            let line = format!("{}\n", std::mem::take(&mut self.pending));
+            let row = self.highlight_line(&line);
+            self.rows.push(row);
+        }
+    }
+
+    /// Completed lines, oldest first
+    pub fn rows(&self) -> &[Vec<TerminalCell>] {
+        &self.rows
+    }
+
+    fn highlight_line(&mut self, line: &str) -> Vec<TerminalCell> {
+        let ops = match self.parse.parse_line(line, &SYNTAXES) {
+            Ok(ops) => ops,
+            Err(e) => {
+                debug!("Highlighting stopped: {}", e);
+                return plain_row(line);
+            }
+        };
+        let highlighter = Highlighter::new(&self.theme);
+        HighlightIterator::new(&mut self.highlight, &ops, line, &highlighter)
+            .flat_map(|(style, text)| {
+                text.chars()
+                    .filter(|&c| c != '\n' && c != '\r')
+                    .map(move |character| cell(character, style))
+            })
+            .collect()
+    }
+}
+
+fn plain_row(line: &str) -> Vec<TerminalCell> {
+    line.trim_end_matches(['\n', '\r'])
+        .chars()
+        .map(|character| TerminalCell {
+            character,
+            ..TerminalCell::default()
+        })
+        .collect()
+}
+
+fn cell(character: char, style: Style) -> TerminalCell {
+    let rgba = |c: syntect::highlighting::Color| {
+        [
+            c.r as f32 / 255.0,
+            c.g as f32 / 255.0,
+            c.b as f32 / 255.0,
+            c.a as f32 / 255.0,
+        ]
+    };
+    TerminalCell {
+        character,
+        foreground: rgba(style.foreground),
+        background: rgba(style.background),
+        bold: style.font_style.contains(FontStyle::BOLD),
+        italic: style.font_style.contains(FontStyle::ITALIC),
+        underline: style.font_style.contains(FontStyle::UNDERLINE),
+        ..TerminalCell::default()
+    }
+}
+
+/// A row as text with truecolor SGR sequences for the foreground, bold and
+/// italics, reset at the end. Backgrounds are left to the pane.
+pub fn ansi_row(row: &[TerminalCell]) -> String {
+    let mut text = String::new();
+    let mut last: Option<([u8; 3], bool, bool)> = None;
+    for cell in row {
+        let [r, g, b, _] = cell.foreground.map(|c| (c * 255.0).round() as u8);
+        let style = ([r, g, b], cell.bold, cell.italic);
+        if last != Some(style) {
+            text.push_str(&format!(
+                "\x1b[0;{}{}38;2;{};{};{}m",
+                if cell.bold { "1;" } else { "" },
+                if cell.italic { "3;" } else { "" },
+                r,
+                g,
+                b
+            ));
+            last = Some(style);
+        }
+        text.push(cell.character);
+    }
+    if last.is_some() {
+        text.push_str("\x1b[0m");
+    }
+    text
+}
+
+/// Passes a streamed answer through, coloring fenced code blocks. Plain
+/// text goes out as it comes; code goes out a line at a time, when the
+/// line is complete.
+pub struct StreamHighlighter {
+    highlighter: SyntaxHighlighter,
+    block: Option<(String, CodeBlock)>,
+    /// The start of a line that might still turn out to be a fence
+    line: String,
+    /// The current line is known not to be a fence
+    passthrough: bool,
+}
+
+impl StreamHighlighter {
+    pub fn new(highlighter: SyntaxHighlighter) -> Self {
+        Self {
+            highlighter,
+            block: None,
+            line: String::new(),
+            passthrough: false,
+        }
+    }
+
+    /// The text to show for the next piece of the answer
+    pub fn push(&mut self, token: &str) -> String {
+        let mut out = String::new();
+        for c in token.chars() {
+            if self.passthrough {
+                out.push(c);
+                if c == '\n' {
+                    self.passthrough = false;
+                }
+                continue;
+            }
+            self.line.push(c);
+            if c == '\n' {
+                let line = std::mem::take(&mut self.line);
+                out.push_str(&self.end_line(&line));
+            } else if self.block.is_none() && !could_be_fence(&self.line) {
+                out.push_str(&std::mem::take(&mut self.line));
+                self.passthrough = true;
+            }
+        }
+        out
+    }
+
+    /// Whatever is still held back once the answer ends
+    pub fn finish(&mut self) -> String {
+        self.passthrough = false;
+        let line = std::mem::take(&mut self.line);
+        match self.block.take() {
+            Some((fence, mut block)) if !closes(&fence, &line) => {
+                block.push(&line);
+                block.finish();
+                block
+                    .rows()
+                    .last()
+                    .map(|row| ansi_row(row))
+                    .unwrap_or_default()
+            }
+            _ => line,
+        }
+    }
+
+    fn end_line(&mut self, line: &str) -> String {
+        if let Some((fence, block)) = self.block.as_mut() {
+            if closes(fence, line) {
+                self.block = None;
+                return line.to_string();
+            }
+            block.push(line);
+            let row = block
+                .rows()
+                .last()
+                .map(|row| ansi_row(row))
+                .unwrap_or_default();
+            return format!("{}\n", row);
+        }
+        if let Some(marker) = fence(line) {
+            let language = line.trim()[marker.len()..]
+                .split_whitespace()
+                .next()
+                .unwrap_or("");
+            let block = self.highlighter.block(language);
+            self.block = Some((marker.to_string(), block));
+        }
+        line.to_string()
+    }
+}
+
+/// Whether `line` so far could still become a fence
+fn could_be_fence(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.is_empty()
+        || trimmed.starts_with("```")
+        || trimmed.starts_with("~~~")
+        || "```".starts_with(trimmed)
+        || "~~~".starts_with(trimmed)
+}
+
+/// The run of three or more backticks or tildes opening `line`, if any
+pub(crate) fn fence(line: &str) -> Option<&str> {
+    let trimmed = line.trim();
+    let first = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let run = trimmed.chars().take_while(|&c| c == first).count();
+    (run >= 3).then(|| &trimmed[..run])
+}
+
+/// `line` is nothing but a fence at least as long as `opening`, of the
+/// same character
+pub(crate) fn closes(opening: &str, line: &str) -> bool {
+    fence(line).is_some_and(|marker| {
+        marker.len() == line.trim().len()
+            && marker.len() >= opening.len()
+            && marker.starts_with(&opening[..1])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(row: &[TerminalCell]) -> String {
+        row.iter().map(|cell| cell.character).collect()
+    }
+
+    #[test]
+    fn test_highlight_colors_known_languages() {
+        let highlighter = SyntaxHighlighter::new("base16-ocean.dark").unwrap();
+        let rows = highlighter.highlight("fn main() {\n    let x = 1;\n}", "rust");
+        assert_eq!(rows.len(), 3);
+        assert_eq!(text(&rows[1]), "    let x = 1;");
+        // The keyword and the name around it differ in color
+        assert_ne!(rows[0][0].foreground, rows[0][3].foreground);
+
+        let plain = highlighter.highlight("fn main() {}", "no-such-language");
+        let colors: Vec<_> = plain[0].iter().map(|cell| cell.foreground).collect();
+        assert!(colors.windows(2).all(|pair| pair[0] == pair[1]));
+        assert!(SyntaxHighlighter::new("no-such-theme").is_err());
+    }
+
+    #[test]
+    fn test_block_highlights_each_line_once() {
+        let highlighter = SyntaxHighlighter::new("base16-ocean.dark").unwrap();
+        let mut block = highlighter.block("py");
+        assert_eq!(block.push("def f"), 0);
+        assert_eq!(block.push("():\n    return"), 1);
+        let first = block.rows()[0].clone();
+        assert_eq!(block.push(" 1\n"), 1);
+        assert_eq!(block.rows()[0], first);
+        assert_eq!(text(&block.rows()[1]), "    return 1");
+    }
+
+    #[test]
+    fn test_stream_colors_only_code() {
+        let highlighter = SyntaxHighlighter::new("base16-ocean.dark").unwrap();
+        let mut stream = StreamHighlighter::new(highlighter);
+        let mut out = String::new();
+        for token in ["Run", " this:\n`", "``sh\necho", " hi\n", "```\n", "done"] {
+            out.push_str(&stream.push(token));
+        }
+        out.push_str(&stream.finish());
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[0], "Run this:");
+        assert_eq!(lines[1], "```sh");
+        assert!(lines[2].contains("\x1b[0;38;2;") && lines[2].ends_with("\x1b[0m"));
+        assert_eq!(lines[3], "```");
+        assert_eq!(lines[4], "done");
+    }
+}
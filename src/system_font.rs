@@ -0,0 +1,380 @@
+use crate::glyph_guard::{self, GlyphLimits};
+use crate::startup::{CellFont, ShapedCell, StartupError};
+use font_kit::family_name::FamilyName;
+use font_kit::handle::Handle;
+use font_kit::properties::Properties;
+use font_kit::source::SystemSource;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use swash::FontRef;
+use swash::scale::image::Content;
+use swash::scale::{Render, ScaleContext, Source, StrikeWith};
+use swash::shape::ShapeContext;
+use swash::zeno::Format;
+use tracing::debug;
+use unicode_properties::{EmojiStatus, UnicodeEmoji};
+
+/// Families tried after the configured fallbacks, for emoji, CJK and
+/// symbols the usual monospace fonts leave out
+#[cfg(target_os = "macos")]
+const PLATFORM_FALLBACKS: &[&str] = &[
+    "Apple Color Emoji",
+    "PingFang SC",
+    "Hiragino Sans",
+    "Apple Symbols",
+];
+#[cfg(target_os = "windows")]
+const PLATFORM_FALLBACKS: &[&str] = &["Segoe UI Emoji", "Microsoft YaHei", "Segoe UI Symbol"];
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+const PLATFORM_FALLBACKS: &[&str] = &[
+    "Noto Color Emoji",
+    "Noto Sans CJK SC",
+    "Noto Sans Symbols 2",
+    "DejaVu Sans",
+];
+
+/// A fallback family, read from disk the first time a character needs it
+struct Fallback {
+    name: String,
+    handle: Handle,
+    data: OnceLock<Option<(Vec<u8>, usize)>>,
+}
+
+impl Fallback {
+    fn font(&self) -> Option<FontRef<'_>> {
+        let (data, index) = self
+            .data
+            .get_or_init(|| read_handle(&self.handle).ok())
+            .as_ref()?;
+        FontRef::from_index(data, *index)
+    }
+}
+
+/// A system font rasterized with swash into fixed-size cells. Characters
+/// the font lacks come from the fallback families, first that has them.
+pub struct SystemFont {
+    name: String,
+    data: Vec<u8>,
+    index: usize,
+    fallbacks: Vec<Fallback>,
+    /// Face chosen per character: 0 is the primary font, n the nth
+    /// fallback, `None` no face at all
+    faces: Mutex<HashMap<char, Option<usize>>>,
+    size_px: f32,
+    cell_width: u32,
+    cell_height: u32,
+    ascent: f32,
+    limits: GlyphLimits,
+    context: Mutex<ScaleContext>,
+    shaper: Mutex<ShapeContext>,
+}
+
+impl SystemFont {
+    /// Find `family` (falling back to any monospace font) and size cells for
+    /// `size_px` with the given line height multiplier. `fallbacks` are
+    /// family names in priority order; missing ones are skipped.
+    pub fn load(
+        family: &str,
+        fallbacks: &[String],
+        size_px: f32,
+        line_height: f32,
+    ) -> Result<Self, StartupError> {
+        let source = SystemSource::new();
+        let handle = source
+            .select_best_match(
+                &[FamilyName::Title(family.to_string()), FamilyName::Monospace],
+                &Properties::new(),
+            )
+            .map_err(|e| StartupError::FontLoad(format!("{}: {:?}", family, e)))?;
+        let (data, index) = read_handle(&handle)?;
+
+        let font = FontRef::from_index(&data, index)
+            .ok_or_else(|| StartupError::FontLoad(format!("{}: unreadable font data", family)))?;
+        let metrics = font.metrics(&[]).scale(size_px);
+        let limits = GlyphLimits::default();
+        let advance = font
+            .glyph_metrics(&[])
+            .scale(size_px)
+            .advance_width(font.charmap().map('M'));
+        let cell_width =
+            glyph_guard::sanitize_advance(advance, size_px * 0.6, &limits).ceil() as u32;
+        let cell_height = ((metrics.ascent + metrics.descent) * line_height.max(1.0)).ceil() as u32;
+
+        let fallbacks = fallbacks
+            .iter()
+            .map(String::as_str)
+            .chain(PLATFORM_FALLBACKS.iter().copied())
+            .filter(|name| *name != family)
+            .filter_map(|name| {
+                let handle = source
+                    .select_best_match(&[FamilyName::Title(name.to_string())], &Properties::new())
+                    .ok()?;
+                Some(Fallback {
+                    name: name.to_string(),
+                    handle,
+                    data: OnceLock::new(),
+                })
+            })
+            .collect::<Vec<_>>();
+        debug!(
+            "Font fallbacks for {}: {:?}",
+            family,
+            fallbacks.iter().map(|f| &f.name).collect::<Vec<_>>()
+        );
+
+        Ok(Self {
+            name: family.to_string(),
+            data,
+            index,
+            fallbacks,
+            faces: Mutex::new(HashMap::new()),
+            size_px,
+            cell_width: cell_width.max(1),
+            cell_height: cell_height.max(1),
+            ascent: metrics.ascent,
+            limits,
+            context: Mutex::new(ScaleContext::new()),
+            shaper: Mutex::new(ShapeContext::new()),
+        })
+    }
+
+    fn font(&self, face: usize) -> Option<FontRef<'_>> {
+        match face {
+            0 => FontRef::from_index(&self.data, self.index),
+            n => self.fallbacks.get(n - 1)?.font(),
+        }
+    }
+
+    /// Render glyph `id` into `coverage`, a buffer `width` pixels wide and
+    /// one cell high, with its origin `left` pixels in. Overlapping glyphs
+    /// keep the higher coverage.
+    fn draw_glyph(&self, font: FontRef, id: u16, coverage: &mut [u8], width: u32, left: i32) {
+        let height = self.cell_height;
+        let mut context = self.context.lock();
+        let mut scaler = context.builder(font).size(self.size_px).hint(true).build();
+        let Some(image) = Render::new(&[
+            Source::ColorOutline(0),
+            Source::ColorBitmap(StrikeWith::BestFit),
+            Source::Outline,
+        ])
+        .format(Format::Alpha)
+        .render(&mut scaler, id) else {
+            return;
+        };
+        // Color glyphs come back as RGBA; their alpha is the shape
+        let (stride, channel) = match image.content {
+            Content::Mask => (1, 0),
+            _ => (4, 3),
+        };
+
+        // Blit into the cells, clipped to the cell box and the glyph budget
+        let placement = image.placement;
+        let clipped = glyph_guard::clip_raster(
+            placement.width,
+            placement.height,
+            1,
+            width as f32,
+            height as f32,
+            1,
+            &self.limits,
+        );
+        let baseline = self.ascent.round() as i32;
+        for row in 0..clipped.height.min(placement.height) {
+            let y = baseline - placement.top + row as i32;
+            if y < 0 || y >= height as i32 {
+                continue;
+            }
+            for col in 0..clipped.width.min(placement.width) {
+                let x = left + placement.left + col as i32;
+                if x < 0 || x >= width as i32 {
+                    continue;
+                }
+                let src = (row * placement.width + col) as usize * stride + channel;
+                if let Some(&value) = image.data.get(src) {
+                    let dest = &mut coverage[(y as u32 * width + x as u32) as usize];
+                    *dest = (*dest).max(value);
+                }
+            }
+        }
+    }
+
+    /// The face that has a glyph for `ch`, by the fonts' character maps
+    fn face_for(&self, ch: char) -> Option<usize> {
+        if let Some(&face) = self.faces.lock().get(&ch) {
+            return face;
+        }
+        let face = face_order(ch, self.fallbacks.len()).find(|&face| {
+            self.font(face)
+                .is_some_and(|font| font.charmap().map(ch) != 0)
+        });
+        self.faces.lock().insert(ch, face);
+        face
+    }
+}
+
+fn read_handle(handle: &Handle) -> Result<(Vec<u8>, usize), StartupError> {
+    Ok(match handle {
+        Handle::Path { path, font_index } => (
+            std::fs::read(path)
+                .map_err(|e| StartupError::FontLoad(format!("{}: {}", path.display(), e)))?,
+            *font_index as usize,
+        ),
+        Handle::Memory { bytes, font_index } => (bytes.to_vec(), *font_index as usize),
+    })
+}
+
+/// Emoji that show as pictures by default. Characters like '#' or '©'
+/// are emoji only with a variation selector and stay with the text font.
+pub fn is_emoji_presentation(ch: char) -> bool {
+    matches!(
+        ch.emoji_status(),
+        EmojiStatus::EmojiPresentation
+            | EmojiStatus::EmojiPresentationAndModifierBase
+            | EmojiStatus::EmojiPresentationAndEmojiComponent
+            | EmojiStatus::EmojiPresentationAndModifierAndEmojiComponent
+    )
+}
+
+/// Faces to try for `ch`: the primary font first, except for emoji, where
+/// a monochrome glyph in the text font would win over the emoji font
+fn face_order(ch: char, fallbacks: usize) -> impl Iterator<Item = usize> {
+    let emoji = is_emoji_presentation(ch);
+    let fallback = 1..fallbacks + 1;
+    (!emoji)
+        .then_some(0)
+        .into_iter()
+        .chain(fallback)
+        .chain(emoji.then_some(0))
+}
+
+/// Outline of a box inset in the cells, drawn for characters no font has
+fn replacement_box(width: u32, height: u32) -> Vec<u8> {
+    let mut coverage = vec![0u8; (width * height) as usize];
+    let (left, right) = (width / 8, width - 1 - width / 8);
+    let (top, bottom) = (height / 5, height - 1 - height / 5);
+    for y in top..=bottom {
+        for x in left..=right {
+            if x == left || x == right || y == top || y == bottom {
+                coverage[(y * width + x) as usize] = 255;
+            }
+        }
+    }
+    coverage
+}
+
+impl CellFont for SystemFont {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn cell_size(&self) -> (u32, u32) {
+        (self.cell_width, self.cell_height)
+    }
+
+    fn rasterize(&self, ch: char) -> Vec<u8> {
+        self.rasterize_span(ch, 1)
+    }
+
+    fn rasterize_span(&self, ch: char, cells: u32) -> Vec<u8> {
+        let (cell_width, height) = self.cell_size();
+        let width = cell_width * cells.max(1);
+        let mut coverage = vec![0u8; (width * height) as usize];
+        if ch.is_whitespace() || ch.is_control() {
+            return coverage;
+        }
+        let Some(face) = self.face_for(ch) else {
+            return replacement_box(width, height);
+        };
+        let Some(font) = self.font(face) else {
+            return replacement_box(width, height);
+        };
+
+        self.draw_glyph(font, font.charmap().map(ch), &mut coverage, width, 0);
+        coverage
+    }
+
+    fn shape(&self, text: &str) -> Vec<ShapedCell> {
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+        let mut cells = vec![ShapedCell::Plain; chars.len()];
+        let Some(font) = self.font(0) else {
+            return cells;
+        };
+        let charmap = font.charmap();
+        let mut context = self.shaper.lock();
+        let mut shaper = context.builder(font).size(self.size_px).build();
+        shaper.add_str(text);
+        shaper.shape_with(|cluster| {
+            let first = chars.partition_point(|(byte, _)| *byte < cluster.source.start as usize);
+            let end = chars.partition_point(|(byte, _)| *byte < cluster.source.end as usize);
+            // Clusters that decompose into several glyphs keep the plain ones
+            let ([glyph], Some(&(_, ch))) = (cluster.glyphs, chars.get(first)) else {
+                return;
+            };
+            if end - first == 1 && glyph.id == charmap.map(ch) {
+                return;
+            }
+            cells[first] = ShapedCell::Glyph(glyph.id);
+            for cell in cells.iter_mut().take(end).skip(first + 1) {
+                *cell = ShapedCell::Covered;
+            }
+        });
+        cells
+    }
+
+    fn rasterize_shaped(&self, glyphs: &[ShapedCell]) -> Vec<u8> {
+        let (cell_width, height) = self.cell_size();
+        let width = cell_width * glyphs.len() as u32;
+        let mut coverage = vec![0u8; (width * height) as usize];
+        let Some(font) = self.font(0) else {
+            return coverage;
+        };
+        for (i, glyph) in glyphs.iter().enumerate() {
+            if let ShapedCell::Glyph(id) = *glyph {
+                let left = (i as u32 * cell_width) as i32;
+                self.draw_glyph(font, id, &mut coverage, width, left);
+            }
+        }
+        coverage
+    }
+
+    fn face(&self, ch: char) -> usize {
+        self.face_for(ch).unwrap_or(0)
+    }
+
+    fn covers(&self, ch: char) -> bool {
+        self.face_for(ch).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emoji_go_to_fallbacks_first() {
+        assert!(is_emoji_presentation('🦀'));
+        assert!(is_emoji_presentation('😀'));
+        // Text-default characters that are also emoji stay with the text font
+        assert!(!is_emoji_presentation('#'));
+        assert!(!is_emoji_presentation('©'));
+        assert!(!is_emoji_presentation('中'));
+
+        assert_eq!(face_order('a', 2).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(face_order('中', 2).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(face_order('🦀', 2).collect::<Vec<_>>(), vec![1, 2, 0]);
+        assert_eq!(face_order('🦀', 0).collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[test]
+    fn test_replacement_box_is_an_outline() {
+        let (width, height) = (16, 20);
+        let coverage = replacement_box(width, height);
+        let at = |x: u32, y: u32| coverage[(y * width + x) as usize];
+        assert_eq!(at(2, 4), 255);
+        assert_eq!(at(13, 15), 255);
+        // Hollow inside, clear outside
+        assert_eq!(at(8, 10), 0);
+        assert_eq!(at(0, 0), 0);
+    }
+}
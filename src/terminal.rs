@@ -1,8 +1,16 @@
 use std::cmp;
-use crate::terminal_parser::{TerminalParser, TerminalAction};
+use crate::appearance::{self, Appearance};
+use crate::buffer_search::{self, MatchLocation, SearchError, SearchQuery};
+use crate::fold_map::{CommandRegion, DisplayLine, FoldMap};
+use crate::glyph_guard;
+use crate::hyperlinks::{self, Hyperlinks, ScreenLink};
+use crate::scrollback::Scrollback;
+use crate::terminal_parser::{CursorStyle, PromptMark, TerminalAction, TerminalParser};
+use crate::theme::Theme;
+use std::time::Instant;
 use tracing::debug;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TerminalCell {
     pub character: char,
     pub foreground: [f32; 4],
@@ -16,6 +24,8 @@ pub struct TerminalCell {
     pub blink: bool,
     pub wide: bool,
     pub dirty: bool,
+    /// Id of the OSC 8 link the cell is part of
+    pub link: Option<u32>,
 }
 
 impl Default for TerminalCell {
@@ -33,8 +43,73 @@ impl Default for TerminalCell {
             blink: false,
             wide: false,
             dirty: true,
+            link: None,
+        }
+    }
+}
+
+/// A command line observed through shell integration marks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellEvent {
+    /// Output started (`C`); the text typed since `B`
+    CommandStarted(String),
+    /// `D`, with the exit status if the shell reported one
+    CommandFinished(Option<i32>),
+}
+
+/// Cells from the anchor, where the drag started, to the head, in screen
+/// coordinates. Either end may come first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: (u32, u32),
+    pub head: (u32, u32),
+}
+
+impl Selection {
+    pub fn new(x: u32, y: u32) -> Self {
+        Self {
+            anchor: (x, y),
+            head: (x, y),
+        }
+    }
+
+    /// First and last selected cell in reading order, as (column, row)
+    pub fn ordered(&self) -> ((u32, u32), (u32, u32)) {
+        let key = |(x, y): (u32, u32)| (y, x);
+        if key(self.anchor) <= key(self.head) {
+            (self.anchor, self.head)
+        } else {
+            (self.head, self.anchor)
         }
     }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        let ((start_x, start_y), (end_x, end_y)) = self.ordered();
+        (start_y, start_x) <= (y, x) && (y, x) <= (end_y, end_x)
+    }
+}
+
+/// Cursor position and text attributes kept by DECSC
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SavedCursor {
+    x: u32,
+    y: u32,
+    fg: [f32; 4],
+    bg: [f32; 4],
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+/// The main screen while the alternate one is shown
+#[derive(Debug, Clone)]
+struct MainScreen {
+    cells: Vec<TerminalCell>,
+    cursor_x: u32,
+    cursor_y: u32,
+    saved_cursor: Option<SavedCursor>,
 }
 
 #[derive(Debug, Clone)]
@@ -48,11 +123,15 @@ pub struct TerminalState {
     pub cursor_x: u32,
     pub cursor_y: u32,
     pub cursor_visible: bool,
+    /// Shape and blinking an application asked for; `None` leaves the
+    /// user's setting
+    pub cursor_style: Option<CursorStyle>,
     
     // Current text attributes
     pub current_fg: [f32; 4],
     pub current_bg: [f32; 4],
     pub current_bold: bool,
+    pub current_dim: bool,
     pub current_italic: bool,
     pub current_underline: bool,
     pub current_reverse: bool,
@@ -60,10 +139,55 @@ pub struct TerminalState {
     // Terminal modes
     pub wrap_mode: bool,
     pub application_mode: bool,
+    pub alternate_screen: bool,
+    pub bracketed_paste: bool,
+    /// The application takes mouse input, reported in SGR form if
+    /// `sgr_mouse` is set
+    pub mouse_reporting: bool,
+    pub sgr_mouse: bool,
+    /// Main screen and cursor while the alternate screen is shown
+    saved_screen: Option<MainScreen>,
+    /// DECSC of the screen shown; each screen keeps its own
+    saved_cursor: Option<SavedCursor>,
+
+    // Shell integration
+    pub last_prompt_mark: Option<PromptMark>,
+    /// Cursor position at the last `CommandStart` mark
+    input_start: Option<(u32, u32)>,
+    shell_events: Vec<ShellEvent>,
+    /// Command line, first output line and start of the command running
+    running_command: Option<(usize, usize, Instant)>,
+    /// Finished commands' output, folded or not
+    folds: FoldMap,
+    /// Set by the application through OSC 0 or 2
+    pub title: Option<String>,
+    /// Reported by the shell through OSC 7
+    pub working_directory: Option<String>,
+    /// URLs of the OSC 8 links cells refer to
+    pub links: Hyperlinks,
+    /// The link printed text joins
+    current_link: Option<u32>,
     
     // Scrolling
     pub scroll_top: u32,
     pub scroll_bottom: u32,
+    /// Lines scrolled off the top of the screen
+    pub scrollback: Scrollback,
+    /// History lines the view is scrolled back by; 0 shows the live screen
+    viewport_offset: usize,
+    /// Rows shown under the cursor line without being in the grid, such as
+    /// an answer still streaming in
+    inline_region: Option<Vec<Vec<TerminalCell>>>,
+    
+    /// Colors SGR sequences and blank cells resolve to
+    palette: Theme,
+    // Theme, as reported to applications
+    pub default_background: [f32; 4],
+    pub appearance: Appearance,
+    pub color_scheme_updates: bool,
+    bell_pending: bool,
+    /// Responses to queries, to be written back to the PTY
+    replies: Vec<u8>,
     
     // Parser
     parser: TerminalParser,
@@ -81,16 +205,42 @@ impl TerminalState {
             cursor_x: 0,
             cursor_y: 0,
             cursor_visible: true,
+            cursor_style: None,
             current_fg: [1.0, 1.0, 1.0, 1.0], // White
             current_bg: [0.0, 0.0, 0.0, 1.0], // Black
             current_bold: false,
+            current_dim: false,
             current_italic: false,
             current_underline: false,
             current_reverse: false,
             wrap_mode: true,
             application_mode: false,
+            alternate_screen: false,
+            bracketed_paste: false,
+            mouse_reporting: false,
+            sgr_mouse: false,
+            saved_screen: None,
+            saved_cursor: None,
+            last_prompt_mark: None,
+            input_start: None,
+            shell_events: Vec::new(),
+            running_command: None,
+            folds: FoldMap::default(),
+            title: None,
+            working_directory: None,
+            links: Hyperlinks::default(),
+            current_link: None,
             scroll_top: 0,
             scroll_bottom: height.saturating_sub(1),
+            scrollback: Scrollback::default(),
+            viewport_offset: 0,
+            inline_region: None,
+            palette: Theme::default(),
+            default_background: [0.0, 0.0, 0.0, 1.0],
+            appearance: Appearance::Dark,
+            color_scheme_updates: false,
+            bell_pending: false,
+            replies: Vec::new(),
             parser: TerminalParser::new(),
         }
     }
@@ -100,26 +250,46 @@ impl TerminalState {
             return;
         }
         
-        let old_cells = std::mem::take(&mut self.cells);
-        let old_width = self.width;
-        let old_height = self.height;
-        
+        // Rows above the cursor that no longer fit go to the scrollback
+        // rather than cutting off the cursor line. Behind the alternate
+        // screen that is the main screen's cursor.
+        let (old_width, old_height) = (self.width, self.height);
+        let blank = self.blank();
+        let main_cursor_y = match &self.saved_screen {
+            Some(main) => main.cursor_y,
+            None => self.cursor_y,
+        };
+        let shift = (main_cursor_y + 1).saturating_sub(height);
+        let main_cells = match &self.saved_screen {
+            Some(main) => &main.cells,
+            None => &self.cells,
+        };
+        for y in 0..shift {
+            let start = (y * old_width) as usize;
+            let end = (start + old_width as usize).min(main_cells.len());
+            if let Some(row) = main_cells.get(start..end)
+                && let Err(e) = self.scrollback.push_line(row.to_vec())
+            {
+                debug!("Dropped scrollback line: {}", e);
+            }
+        }
+        self.input_start = self
+            .input_start
+            .and_then(|(x, y)| Some((x, y.checked_sub(shift)?)));
+
         self.width = width;
         self.height = height;
-        self.cells = vec![TerminalCell::default(); (width * height) as usize];
-        
-        // Copy old content to new grid
-        let copy_width = cmp::min(old_width, width);
-        let copy_height = cmp::min(old_height, height);
-        
-        for y in 0..copy_height {
-            for x in 0..copy_width {
-                let old_index = (y * old_width + x) as usize;
-                let new_index = (y * width + x) as usize;
-                
-                if old_index < old_cells.len() && new_index < self.cells.len() {
-                    self.cells[new_index] = old_cells[old_index].clone();
-                }
+        match self.saved_screen.as_mut() {
+            Some(main) => {
+                main.cells = resize_cells(&main.cells, (old_width, old_height), shift, (width, height), &blank);
+                main.cursor_y -= shift;
+                main.cursor_x = main.cursor_x.min(width.saturating_sub(1));
+                main.cursor_y = main.cursor_y.min(height.saturating_sub(1));
+                self.cells = resize_cells(&self.cells, (old_width, old_height), 0, (width, height), &blank);
+            }
+            None => {
+                self.cells = resize_cells(&self.cells, (old_width, old_height), shift, (width, height), &blank);
+                self.cursor_y -= shift;
             }
         }
         
@@ -128,7 +298,9 @@ impl TerminalState {
         self.cursor_y = cmp::min(self.cursor_y, height.saturating_sub(1));
         
         // Adjust scroll region
+        self.scroll_top = 0;
         self.scroll_bottom = height.saturating_sub(1);
+        self.scroll_viewport(0);
     }
     
     pub fn feed_bytes(&mut self, data: &[u8]) {
@@ -137,6 +309,65 @@ impl TerminalState {
         for action in actions {
             self.execute_action(action);
         }
+        // Output moved the cursor line; keep the inline region below it
+        self.make_inline_room();
+    }
+
+    /// Show `rows` under the cursor line, scrolling the screen up to make
+    /// room. Output keeps going to the grid above them.
+    pub fn set_inline_region(&mut self, rows: Vec<Vec<TerminalCell>>) {
+        self.inline_region = Some(rows);
+        self.make_inline_room();
+    }
+
+    pub fn clear_inline_region(&mut self) {
+        self.inline_region = None;
+    }
+
+    /// First screen row of the inline region and the rows that fit on
+    /// screen, the last ones when it is taller
+    pub fn inline_region(&self) -> Option<(u32, &[Vec<TerminalCell>])> {
+        let rows = self.inline_region.as_ref()?;
+        let top = self.cursor_y + 1;
+        let visible = (self.height.saturating_sub(top) as usize).min(rows.len());
+        Some((top, &rows[rows.len() - visible..]))
+    }
+
+    /// Write the inline region into the grid below the cursor line, so it
+    /// scrolls into history like output, and leave the cursor at the start
+    /// of the line after it
+    pub fn commit_inline_region(&mut self) {
+        let Some(rows) = self.inline_region.take() else {
+            return;
+        };
+        for row in rows {
+            self.line_feed();
+            let start = (self.cursor_y * self.width) as usize;
+            for (x, cell) in row.into_iter().take(self.width as usize).enumerate() {
+                if let Some(target) = self.cells.get_mut(start + x) {
+                    *target = TerminalCell { dirty: true, ..cell };
+                }
+            }
+        }
+        self.newline();
+    }
+
+    /// Scroll the screen so the inline region fits under the cursor line,
+    /// keeping that line on screen. Left alone while an application has
+    /// set scroll margins.
+    fn make_inline_room(&mut self) {
+        let Some(rows) = self.inline_region.as_ref() else {
+            return;
+        };
+        if self.scroll_region() != (0, self.height.saturating_sub(1)) {
+            return;
+        }
+        let wanted = (rows.len() as u32).min(self.height.saturating_sub(1));
+        let overflow = (self.cursor_y + 1 + wanted).saturating_sub(self.height);
+        if overflow > 0 {
+            self.scroll_up(overflow);
+            self.cursor_y -= overflow;
+        }
     }
     
     fn execute_action(&mut self, action: TerminalAction) {
@@ -152,13 +383,13 @@ impl TerminalState {
                 self.cursor_y = self.cursor_y.saturating_sub(n);
             }
             TerminalAction::MoveCursorDown(n) => {
-                self.cursor_y = cmp::min(self.cursor_y + n, self.height.saturating_sub(1));
+                self.cursor_y = cmp::min(self.cursor_y.saturating_add(n), self.height.saturating_sub(1));
             }
             TerminalAction::MoveCursorLeft(n) => {
                 self.cursor_x = self.cursor_x.saturating_sub(n);
             }
             TerminalAction::MoveCursorRight(n) => {
-                self.cursor_x = cmp::min(self.cursor_x + n, self.width.saturating_sub(1));
+                self.cursor_x = cmp::min(self.cursor_x.saturating_add(n), self.width.saturating_sub(1));
             }
             TerminalAction::MoveCursorToColumn(col) => {
                 self.cursor_x = cmp::min(col, self.width.saturating_sub(1));
@@ -192,14 +423,17 @@ impl TerminalState {
                 self.insert_chars(n);
             }
             TerminalAction::SetForeground(color) => {
-                self.current_fg = color.to_rgba();
+                self.current_fg = self.palette.color(&color, true);
             }
             TerminalAction::SetBackground(color) => {
-                self.current_bg = color.to_rgba();
+                self.current_bg = self.palette.color(&color, false);
             }
             TerminalAction::SetBold(bold) => {
                 self.current_bold = bold;
             }
+            TerminalAction::SetDim(dim) => {
+                self.current_dim = dim;
+            }
             TerminalAction::SetItalic(italic) => {
                 self.current_italic = italic;
             }
@@ -210,9 +444,10 @@ impl TerminalState {
                 self.current_reverse = reverse;
             }
             TerminalAction::ResetAttributes => {
-                self.current_fg = [1.0, 1.0, 1.0, 1.0]; // White
-                self.current_bg = [0.0, 0.0, 0.0, 1.0]; // Black
+                self.current_fg = self.palette.foreground;
+                self.current_bg = self.palette.background;
                 self.current_bold = false;
+                self.current_dim = false;
                 self.current_italic = false;
                 self.current_underline = false;
                 self.current_reverse = false;
@@ -223,6 +458,26 @@ impl TerminalState {
             TerminalAction::ScrollDown(n) => {
                 self.scroll_down(n);
             }
+            TerminalAction::SetScrollRegion(top, bottom) => {
+                let bottom = bottom.unwrap_or(self.height.saturating_sub(1));
+                // Invalid regions are ignored, as in xterm
+                if top < bottom && bottom < self.height {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.cursor_x = 0;
+                    self.cursor_y = 0;
+                }
+            }
+            TerminalAction::Index => {
+                self.line_feed();
+            }
+            TerminalAction::ReverseIndex => {
+                if self.cursor_y == self.scroll_top {
+                    self.scroll_down(1);
+                } else {
+                    self.cursor_y = self.cursor_y.saturating_sub(1);
+                }
+            }
             TerminalAction::Newline => {
                 self.newline();
             }
@@ -237,6 +492,7 @@ impl TerminalState {
             TerminalAction::Bell => {
                 // Visual bell - could flash the screen
                 debug!("Bell");
+                self.bell_pending = true;
             }
             TerminalAction::Backspace => {
                 if self.cursor_x > 0 {
@@ -255,18 +511,443 @@ impl TerminalState {
             TerminalAction::SetWrapMode(enabled) => {
                 self.wrap_mode = enabled;
             }
+            TerminalAction::QueryBackground => {
+                let report = appearance::background_report(self.default_background);
+                self.replies.extend_from_slice(report.as_bytes());
+            }
+            TerminalAction::QueryColorScheme => {
+                let report = appearance::color_scheme_report(self.appearance);
+                self.replies.extend_from_slice(report.as_bytes());
+            }
+            TerminalAction::SetColorSchemeUpdates(enabled) => {
+                self.color_scheme_updates = enabled;
+            }
+            TerminalAction::SetAlternateScreen(enabled) => {
+                self.set_alternate_screen(enabled);
+            }
+            TerminalAction::SaveCursor => {
+                self.saved_cursor = Some(SavedCursor {
+                    x: self.cursor_x,
+                    y: self.cursor_y,
+                    fg: self.current_fg,
+                    bg: self.current_bg,
+                    bold: self.current_bold,
+                    dim: self.current_dim,
+                    italic: self.current_italic,
+                    underline: self.current_underline,
+                    reverse: self.current_reverse,
+                });
+            }
+            TerminalAction::RestoreCursor => {
+                // With nothing saved, DECRC homes the cursor
+                let saved = self.saved_cursor.unwrap_or(SavedCursor {
+                    x: 0,
+                    y: 0,
+                    fg: self.palette.foreground,
+                    bg: self.palette.background,
+                    bold: false,
+                    dim: false,
+                    italic: false,
+                    underline: false,
+                    reverse: false,
+                });
+                self.cursor_x = saved.x.min(self.width.saturating_sub(1));
+                self.cursor_y = saved.y.min(self.height.saturating_sub(1));
+                self.current_fg = saved.fg;
+                self.current_bg = saved.bg;
+                self.current_bold = saved.bold;
+                self.current_dim = saved.dim;
+                self.current_italic = saved.italic;
+                self.current_underline = saved.underline;
+                self.current_reverse = saved.reverse;
+            }
+            TerminalAction::SetBracketedPaste(enabled) => {
+                self.bracketed_paste = enabled;
+            }
+            TerminalAction::SetMouseReporting(enabled) => {
+                self.mouse_reporting = enabled;
+            }
+            TerminalAction::SetSgrMouse(enabled) => {
+                self.sgr_mouse = enabled;
+            }
+            TerminalAction::SetCursorStyle(style) => {
+                self.cursor_style = style;
+            }
+            TerminalAction::PromptMark(mark) => {
+                self.last_prompt_mark = Some(mark);
+                match mark {
+                    PromptMark::CommandStart => {
+                        self.input_start = Some((self.cursor_x, self.cursor_y));
+                    }
+                    PromptMark::OutputStart if !self.alternate_screen => {
+                        if let Some(command) = self.command_text() {
+                            self.shell_events.push(ShellEvent::CommandStarted(command));
+                        }
+                        let end = self.scrollback.end_line();
+                        let output_start = end + self.cursor_y as usize;
+                        let command_line = self
+                            .input_start
+                            .map_or(output_start.saturating_sub(1), |(_, y)| end + y as usize);
+                        self.running_command = Some((command_line, output_start, Instant::now()));
+                    }
+                    PromptMark::CommandFinished(exit) => {
+                        self.shell_events.push(ShellEvent::CommandFinished(exit));
+                        self.finish_command_region(exit);
+                    }
+                    _ => {}
+                }
+            }
+            TerminalAction::SetTitle(title) => {
+                self.title = Some(title).filter(|title| !title.is_empty());
+            }
+            TerminalAction::SetHyperlink(url) => {
+                self.current_link = url.map(|url| self.links.intern(&url));
+            }
+            TerminalAction::SetWorkingDirectory(dir) => {
+                self.working_directory = Some(dir);
+            }
         }
     }
     
+    fn set_alternate_screen(&mut self, enabled: bool) {
+        if enabled == self.alternate_screen {
+            return;
+        }
+        self.alternate_screen = enabled;
+        self.viewport_offset = 0;
+        if enabled {
+            let blank = vec![self.blank(); self.cells.len()];
+            let main = std::mem::replace(&mut self.cells, blank);
+            self.saved_screen = Some(MainScreen {
+                cells: main,
+                cursor_x: self.cursor_x,
+                cursor_y: self.cursor_y,
+                saved_cursor: self.saved_cursor.take(),
+            });
+            self.cursor_x = 0;
+            self.cursor_y = 0;
+        } else {
+            // Resized along with the alternate screen, so always the same size
+            match self.saved_screen.take() {
+                Some(main) => {
+                    self.cells = main.cells;
+                    self.cursor_x = main.cursor_x;
+                    self.cursor_y = main.cursor_y;
+                    self.saved_cursor = main.saved_cursor;
+                }
+                None => self.cells = vec![self.blank(); self.cells.len()],
+            }
+            for cell in &mut self.cells {
+                cell.dirty = true;
+            }
+        }
+    }
+
+    /// Record the output of the command that just finished for folding
+    fn finish_command_region(&mut self, exit_code: Option<i32>) {
+        let Some((command_line, output_start, started)) = self.running_command.take() else {
+            return;
+        };
+        let end = self.scrollback.end_line() + self.cursor_y as usize + usize::from(self.cursor_x > 0);
+        let first = self.scrollback.first_line();
+        self.folds.remap(|line| (line >= first).then_some(line));
+        self.folds.add_region(CommandRegion {
+            command_line,
+            output_start,
+            output_end: end.max(output_start),
+            exit_code,
+            duration: Some(started.elapsed()),
+            folded: false,
+        });
+        // Output folded as it finishes shortens the history
+        self.scroll_viewport(0);
+    }
+
+    /// Fold command output longer than `lines` as it finishes; 0 never does
+    pub fn set_auto_fold_lines(&mut self, lines: u32) {
+        self.folds.set_auto_fold_lines(Some(lines as usize));
+    }
+
+    /// Fold or unfold a command's output: the last command on the live
+    /// screen, or the one the top row belongs to while scrolled back.
+    /// Returns whether it is folded now, or `None` with no command there.
+    pub fn toggle_fold(&mut self) -> Option<bool> {
+        let index = self.fold_target()?;
+        let mut folded = None;
+        self.change_folds(|folds| folded = folds.toggle(index));
+        folded
+    }
+
+    /// `:fold` and `:unfold`: the command `toggle_fold` would change, or
+    /// every command with `all`. Returns whether there was one to change.
+    pub fn set_folded(&mut self, folded: bool, all: bool) -> bool {
+        let target = if all { None } else { Some(self.fold_target()) };
+        match (target, folded) {
+            (None, true) => self.change_folds(FoldMap::fold_all),
+            (None, false) => self.change_folds(FoldMap::unfold_all),
+            (Some(Some(index)), _) => self.change_folds(|folds| folds.set_folded(index, folded)),
+            (Some(None), _) => return false,
+        }
+        !self.folds.regions().is_empty()
+    }
+
+    /// Change folds, keeping the line on the top row of a scrolled-back
+    /// view there
+    fn change_folds(&mut self, change: impl FnOnce(&mut FoldMap)) {
+        let end = self.scrollback.end_line();
+        let top = (self.viewport_offset > 0)
+            .then(|| self.folds.display_line_count(end) - self.viewport_offset)
+            .and_then(|top| self.folds.display_to_buffer(top, end))
+            .map(|top| match top {
+                DisplayLine::Buffer(line) => line,
+                DisplayLine::FoldSummary(index) => self.folds.regions()[index].output_start,
+            });
+        change(&mut self.folds);
+        if let Some(line) = top {
+            self.viewport_offset = self.folds.display_line_count(end) - self.folds.buffer_to_display(line);
+        }
+        self.scroll_viewport(0);
+    }
+
+    fn fold_target(&self) -> Option<usize> {
+        let end = self.scrollback.end_line();
+        let line = if self.viewport_offset == 0 {
+            end + self.height as usize
+        } else {
+            let top = self.folds.display_line_count(end) - self.viewport_offset;
+            match self.folds.display_to_buffer(top, end)? {
+                DisplayLine::Buffer(line) => line,
+                DisplayLine::FoldSummary(index) => return Some(index),
+            }
+        };
+        self.folds.regions().iter().rposition(|region| region.command_line <= line)
+    }
+
+    /// History rows the view can scroll back through; folded output takes
+    /// one row
+    fn history_rows(&self) -> usize {
+        if self.alternate_screen {
+            return 0;
+        }
+        let end = self.scrollback.end_line();
+        let hidden = end - self.folds.display_line_count(end);
+        self.scrollback.len().saturating_sub(hidden)
+    }
+
+    /// The command line typed after the last `CommandStart` mark. The shell
+    /// has usually echoed the newline by the time output starts, so input
+    /// ends on the row above the cursor.
+    fn command_text(&self) -> Option<String> {
+        let (start_x, start_y) = self.input_start?;
+        let end_y = if self.cursor_x == 0 && self.cursor_y > start_y {
+            self.cursor_y - 1
+        } else {
+            self.cursor_y
+        };
+        let mut text = String::new();
+        for y in start_y..=end_y.min(self.height.saturating_sub(1)) {
+            let from = if y == start_y { start_x } else { 0 };
+            let mut skip_spacer = false;
+            for x in from..self.width {
+                let Some(cell) = self.get_cell(x, y) else { break };
+                if std::mem::take(&mut skip_spacer) {
+                    continue;
+                }
+                skip_spacer = cell.wide;
+                text.push(if cell.character == '\0' { ' ' } else { cell.character });
+            }
+        }
+        let text = text.trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+
+    /// Commands seen through OSC 133 marks since the last call
+    pub fn take_shell_events(&mut self) -> Vec<ShellEvent> {
+        std::mem::take(&mut self.shell_events)
+    }
+
+    /// Whether the cursor sits where the shell's command line begins, i.e.
+    /// nothing has been typed at the prompt yet. `None` when the shell emits
+    /// no OSC 133 marks.
+    pub fn at_input_start(&self) -> Option<bool> {
+        self.last_prompt_mark?;
+        Some(
+            !self.alternate_screen
+                && self.last_prompt_mark == Some(PromptMark::CommandStart)
+                && self.input_start == Some((self.cursor_x, self.cursor_y)),
+        )
+    }
+
+    /// What has been typed on the prompt row, while the cursor sits at its
+    /// end. `None` in full-screen apps, without OSC 133 marks, once the
+    /// command runs, or when the line has wrapped.
+    pub fn prompt_input(&self) -> Option<String> {
+        if self.alternate_screen || self.last_prompt_mark != Some(PromptMark::CommandStart) {
+            return None;
+        }
+        let (start_x, start_y) = self.input_start?;
+        if self.cursor_y != start_y || self.cursor_x < start_x {
+            return None;
+        }
+        let after_cursor_blank = (self.cursor_x..self.width)
+            .filter_map(|x| self.get_cell(x, start_y))
+            .all(|cell| matches!(cell.character, ' ' | '\0'));
+        after_cursor_blank.then(|| self.cells_text(start_y, start_x..self.cursor_x))
+    }
+
+    /// A screen row with trailing blanks removed
+    pub fn row_text(&self, y: u32) -> String {
+        self.cells_text(y, 0..self.width).trim_end().to_string()
+    }
+
+    /// Selected text, one line per row with trailing blanks dropped
+    pub fn extract_text(&self, selection: &Selection) -> String {
+        let ((start_x, start_y), (end_x, end_y)) = selection.ordered();
+        let last_row = end_y.min(self.height.saturating_sub(1));
+        let mut lines = Vec::new();
+        for y in start_y..=last_row {
+            let mut from = if y == start_y { start_x } else { 0 };
+            // Starting on the right half of a wide glyph takes the glyph
+            if from > 0 && self.get_cell(from - 1, y).is_some_and(|cell| cell.wide) {
+                from -= 1;
+            }
+            let to = if y == end_y { (end_x + 1).min(self.width) } else { self.width };
+            lines.push(self.cells_text(y, from..to).trim_end().to_string());
+        }
+        lines.join("\n")
+    }
+
+    /// Links on the live screen in reading order: OSC 8 links, then URLs
+    /// found in the text. A row filled to the last column is taken to
+    /// continue on the next, so wrapped URLs come out whole.
+    pub fn screen_links(&self) -> Vec<ScreenLink> {
+        let mut links: Vec<ScreenLink> = Vec::new();
+        let mut current: Option<u32> = None;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let Some(cell) = self.get_cell(x, y) else {
+                    continue;
+                };
+                // The spacer after a wide glyph belongs to its link
+                if x > 0 && self.get_cell(x - 1, y).is_some_and(|cell| cell.wide) {
+                    continue;
+                }
+                match cell.link.and_then(|id| Some((id, self.links.get(id)?))) {
+                    Some((id, url)) => {
+                        match links.last_mut() {
+                            Some(link) if current == Some(id) => link.cells.push((x, y)),
+                            _ => links.push(ScreenLink {
+                                url: url.to_string(),
+                                cells: vec![(x, y)],
+                            }),
+                        }
+                        current = Some(id);
+                    }
+                    None => current = None,
+                }
+            }
+        }
+
+        let mut line: Vec<(char, (u32, u32))> = Vec::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if let Some(cell) = self.get_cell(x, y)
+                    && cell.link.is_none()
+                {
+                    line.push((cell.character, (x, y)));
+                }
+            }
+            let wraps = self
+                .get_cell(self.width.saturating_sub(1), y)
+                .is_some_and(|cell| !matches!(cell.character, ' ' | '\0'));
+            if !wraps || y + 1 == self.height {
+                links.extend(Self::detected_links(&line));
+                line.clear();
+            }
+        }
+        links.sort_by_key(|link| link.cells.first().map(|&(x, y)| (y, x)));
+        links
+    }
+
+    fn detected_links(line: &[(char, (u32, u32))]) -> Vec<ScreenLink> {
+        let text: String = line.iter().map(|&(c, _)| c).collect();
+        hyperlinks::detect_urls(&text)
+            .into_iter()
+            .map(|range| {
+                let start = text[..range.start].chars().count();
+                let len = text[range.clone()].chars().count();
+                ScreenLink {
+                    url: text[range].to_string(),
+                    cells: line[start..start + len].iter().map(|&(_, cell)| cell).collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// The URL of the link covering a cell of the live screen
+    pub fn link_at(&self, x: u32, y: u32) -> Option<String> {
+        if self.viewport_offset > 0 {
+            return None;
+        }
+        self.screen_links()
+            .into_iter()
+            .find(|link| link.contains(x, y))
+            .map(|link| link.url)
+    }
+
+    fn cells_text(&self, y: u32, columns: std::ops::Range<u32>) -> String {
+        text_of(columns.map_while(|x| self.get_cell(x, y)))
+    }
+
+    /// The last `n` non-blank lines above the cursor, reaching into the
+    /// scrollback when the screen has fewer, oldest first with trailing
+    /// blanks removed
+    pub fn snapshot_lines(&mut self, n: usize) -> Vec<String> {
+        let mut lines: Vec<String> = (0..self.cursor_y)
+            .rev()
+            .map(|y| self.row_text(y))
+            .filter(|line| !line.is_empty())
+            .take(n)
+            .collect();
+        // The alternate screen's lines above are not the shell's history
+        if !self.alternate_screen {
+            let mut index = self.scrollback.end_line();
+            while lines.len() < n && index > self.scrollback.first_line() {
+                index -= 1;
+                match self.scrollback.line(index) {
+                    Ok(Some(cells)) => {
+                        let line = text_of(cells).trim_end().to_string();
+                        if !line.is_empty() {
+                            lines.push(line);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        debug!("Unreadable scrollback line {}: {}", index, e);
+                        break;
+                    }
+                }
+            }
+        }
+        lines.reverse();
+        lines
+    }
+
     fn print_char(&mut self, ch: char) {
+        // Zero-width characters (combining marks, joiners) have no cell of
+        // their own; the grid stores one character per cell so they are dropped
+        let char_width = glyph_guard::char_width(ch) as u32;
+        if char_width == 0 || self.width == 0 {
+            return;
+        }
+        // A wide character that would straddle the right edge wraps first
+        if char_width == 2 && self.cursor_x + 1 == self.width && self.wrap_mode {
+            self.cursor_x = self.width;
+        }
+
         if self.cursor_x >= self.width {
             if self.wrap_mode {
-                self.cursor_x = 0;
-                self.cursor_y += 1;
-                if self.cursor_y >= self.height {
-                    self.scroll_up(1);
-                    self.cursor_y = self.height.saturating_sub(1);
-                }
+                self.newline();
             } else {
                 return; // Don't print if wrapping is disabled
             }
@@ -279,22 +960,43 @@ impl TerminalState {
             cell.foreground = if self.current_reverse { self.current_bg } else { self.current_fg };
             cell.background = if self.current_reverse { self.current_fg } else { self.current_bg };
             cell.bold = self.current_bold;
+            cell.dim = self.current_dim;
             cell.italic = self.current_italic;
             cell.underline = self.current_underline;
             cell.reverse = self.current_reverse;
+            cell.wide = char_width == 2;
             cell.dirty = true;
+            cell.link = self.current_link;
         }
-        
-        self.cursor_x += 1;
+
+        if char_width == 2
+            && self.cursor_x + 1 < self.width
+            && let Some(spacer) = self.cells.get_mut(index + 1)
+        {
+            // Spacer cell covered by the wide glyph
+            *spacer = TerminalCell {
+                character: ' ',
+                background: spacer.background,
+                dirty: true,
+                ..Default::default()
+            };
+        }
+
+        self.cursor_x = cmp::min(self.cursor_x + char_width, self.width);
     }
     
     fn newline(&mut self) {
         self.cursor_x = 0;
-        self.cursor_y += 1;
-        
-        if self.cursor_y >= self.height {
+        self.line_feed();
+    }
+
+    /// Cursor down one row; at the bottom of the scroll region the region
+    /// scrolls instead
+    fn line_feed(&mut self) {
+        if self.cursor_y == self.scroll_bottom {
             self.scroll_up(1);
-            self.cursor_y = self.height.saturating_sub(1);
+        } else if self.cursor_y + 1 < self.height {
+            self.cursor_y += 1;
         }
     }
     
@@ -400,6 +1102,7 @@ impl TerminalState {
         let _line_start = (y * self.width) as usize;
         let line_end = ((y + 1) * self.width) as usize;
         let delete_start = (y * self.width + self.cursor_x) as usize;
+        let n = n.min(self.width.saturating_sub(self.cursor_x));
         
         // Shift characters left
         for i in 0..n {
@@ -434,6 +1137,10 @@ impl TerminalState {
         let _line_start = (y * self.width) as usize;
         let line_end = ((y + 1) * self.width) as usize;
         let insert_start = (y * self.width + self.cursor_x) as usize;
+        if insert_start >= line_end {
+            return;
+        }
+        let n = n.min(self.width - self.cursor_x);
         
         // Shift characters right
         for i in (0..(line_end - insert_start).saturating_sub(n as usize)).rev() {
@@ -459,60 +1166,241 @@ impl TerminalState {
         }
     }
     
+    /// Scroll the scroll region up; rows leaving the top of the screen go to
+    /// the scrollback
     fn scroll_up(&mut self, n: u32) {
-        let scroll_lines = n.min(self.height);
+        let (top, bottom) = self.scroll_region();
+        let scroll_lines = n.min(bottom + 1 - top);
+        if top == 0 && !self.alternate_screen {
+            self.input_start = self
+                .input_start
+                .and_then(|(x, y)| Some((x, y.checked_sub(scroll_lines)?)));
+        }
         
-        // Move lines up
-        for dest_y in 0..self.height.saturating_sub(scroll_lines) {
-            let src_y = dest_y + scroll_lines;
-            
-            let dest_start = (dest_y * self.width) as usize;
-            let src_start = (src_y * self.width) as usize;
-            
-            for x in 0..self.width {
-                let dest_index = dest_start + x as usize;
-                let src_index = src_start + x as usize;
-                
-                if dest_index < self.cells.len() && src_index < self.cells.len() {
-                    self.cells[dest_index] = self.cells[src_index].clone();
-                    self.cells[dest_index].dirty = true;
-                }
+        // Alternate screen content never reaches the scrollback
+        let to_scrollback = if top == 0 && !self.alternate_screen { scroll_lines } else { 0 };
+        for y in 0..to_scrollback {
+            let start = (y * self.width) as usize;
+            let end = (start + self.width as usize).min(self.cells.len());
+            if let Some(row) = self.cells.get(start..end)
+                && let Err(e) = self.scrollback.push_line(row.to_vec())
+            {
+                debug!("Dropped scrollback line: {}", e);
+            }
+            // A view into history stays on the same lines as output arrives
+            if self.viewport_offset > 0 {
+                self.viewport_offset = (self.viewport_offset + 1).min(self.history_rows());
             }
         }
         
+        // Move lines up
+        for dest_y in top..bottom + 1 - scroll_lines {
+            self.copy_row(dest_y + scroll_lines, dest_y);
+        }
+        
         // Clear the bottom lines
-        let clear_start_y = self.height.saturating_sub(scroll_lines);
-        for y in clear_start_y..self.height {
+        for y in bottom + 1 - scroll_lines..=bottom {
             self.clear_line(y);
         }
     }
     
     fn scroll_down(&mut self, n: u32) {
-        let scroll_lines = n.min(self.height);
+        let (top, bottom) = self.scroll_region();
+        let scroll_lines = n.min(bottom + 1 - top);
         
         // Move lines down
-        for dest_y in (scroll_lines..self.height).rev() {
-            let src_y = dest_y - scroll_lines;
-            
-            let dest_start = (dest_y * self.width) as usize;
-            let src_start = (src_y * self.width) as usize;
-            
-            for x in 0..self.width {
-                let dest_index = dest_start + x as usize;
-                let src_index = src_start + x as usize;
-                
-                if dest_index < self.cells.len() && src_index < self.cells.len() {
-                    self.cells[dest_index] = self.cells[src_index].clone();
-                    self.cells[dest_index].dirty = true;
-                }
-            }
+        for dest_y in (top + scroll_lines..=bottom).rev() {
+            self.copy_row(dest_y - scroll_lines, dest_y);
         }
         
         // Clear the top lines
-        for y in 0..scroll_lines {
+        for y in top..top + scroll_lines {
             self.clear_line(y);
         }
     }
+
+    /// The scroll region clamped to the screen, as inclusive rows
+    fn scroll_region(&self) -> (u32, u32) {
+        let bottom = self.scroll_bottom.min(self.height.saturating_sub(1));
+        (self.scroll_top.min(bottom), bottom)
+    }
+
+    fn copy_row(&mut self, src_y: u32, dest_y: u32) {
+        for x in 0..self.width {
+            let src_index = (src_y * self.width + x) as usize;
+            let dest_index = (dest_y * self.width + x) as usize;
+            if dest_index < self.cells.len() && src_index < self.cells.len() {
+                self.cells[dest_index] = self.cells[src_index].clone();
+                self.cells[dest_index].dirty = true;
+            }
+        }
+    }
+    
+    /// Theme switch: update what OSC 11 and color scheme queries report,
+    /// and notify applications that set mode 2031
+    pub fn set_theme(&mut self, appearance: Appearance, background: [f32; 4]) {
+        self.default_background = background;
+        if appearance != self.appearance {
+            self.appearance = appearance;
+            if self.color_scheme_updates {
+                let report = appearance::color_scheme_report(appearance);
+                self.replies.extend_from_slice(report.as_bytes());
+            }
+        }
+    }
+    
+    /// Switch palettes. Cells on both screens and in the scrollback that
+    /// use a default or palette color take the new theme's; true colors
+    /// are kept. A true color equal to a palette entry moves with it.
+    pub fn set_palette(&mut self, theme: Theme) {
+        let old = std::mem::replace(&mut self.palette, theme);
+        if old == theme {
+            return;
+        }
+        let saved = self.saved_screen.iter_mut().flat_map(|main| main.cells.iter_mut());
+        for cell in self.cells.iter_mut().chain(saved) {
+            old.recolor(&theme, cell);
+            cell.dirty = true;
+        }
+        if let Err(e) = self.scrollback.recolor(|cell| old.recolor(&theme, cell)) {
+            debug!("Scrollback kept its old colors: {}", e);
+        }
+        let mut current = TerminalCell {
+            foreground: self.current_fg,
+            background: self.current_bg,
+            ..TerminalCell::default()
+        };
+        old.recolor(&theme, &mut current);
+        self.current_fg = current.foreground;
+        self.current_bg = current.background;
+    }
+
+    /// An empty cell in the palette's default colors
+    fn blank(&self) -> TerminalCell {
+        TerminalCell {
+            foreground: self.palette.foreground,
+            background: self.palette.background,
+            ..TerminalCell::default()
+        }
+    }
+
+    pub fn viewport_offset(&self) -> usize {
+        self.viewport_offset
+    }
+
+    /// Move the view back into history (positive) or toward the screen
+    pub fn scroll_viewport(&mut self, lines: isize) {
+        let history = self.history_rows();
+        self.viewport_offset = self.viewport_offset.saturating_add_signed(lines).min(history);
+    }
+
+    pub fn scroll_viewport_to_top(&mut self) {
+        self.scroll_viewport(isize::MAX);
+    }
+
+    pub fn scroll_viewport_to_bottom(&mut self) {
+        self.viewport_offset = 0;
+    }
+
+    /// History rows shown above the screen while scrolled back, oldest
+    /// first. Screen row `y` is shown `viewport_offset` rows lower. Folded
+    /// output shows as its summary.
+    pub fn viewport_history(&mut self) -> Vec<Vec<TerminalCell>> {
+        let rows = self.viewport_offset.min(self.height as usize);
+        let end = self.scrollback.end_line();
+        let top = self.folds.display_line_count(end) - self.viewport_offset;
+        self.folds
+            .visible_lines(top, rows, end)
+            .into_iter()
+            .map(|line| match line {
+                DisplayLine::Buffer(index) => match self.scrollback.line(index) {
+                    Ok(Some(cells)) => cells.to_vec(),
+                    Ok(None) => Vec::new(),
+                    Err(e) => {
+                        debug!("Unreadable scrollback line {}: {}", index, e);
+                        Vec::new()
+                    }
+                },
+                DisplayLine::FoldSummary(index) => {
+                    let summary = self.folds.regions()[index].summary();
+                    summary
+                        .chars()
+                        .take(self.width as usize)
+                        .map(|character| TerminalCell {
+                            character,
+                            dim: true,
+                            ..self.blank()
+                        })
+                        .collect()
+                }
+            })
+            .collect()
+    }
+
+    /// Find `query` in the history and on screen, oldest line first. Screen
+    /// row `y` is line `scrollback.end_line() + y`; the alternate screen
+    /// has no history to search.
+    pub fn search(&mut self, query: &SearchQuery) -> Result<Vec<MatchLocation>, SearchError> {
+        let regex = query.compile()?;
+        let mut matches = Vec::new();
+        let end = self.scrollback.end_line();
+        if !self.alternate_screen {
+            for index in self.scrollback.first_line()..end {
+                match self.scrollback.line(index) {
+                    Ok(Some(cells)) => buffer_search::find_in_cells(&regex, cells, index, &mut matches),
+                    Ok(None) => {}
+                    Err(e) => debug!("Unreadable scrollback line {}: {}", index, e),
+                }
+            }
+        }
+        let width = self.width as usize;
+        for (y, row) in self.cells.chunks(width.max(1)).enumerate() {
+            buffer_search::find_in_cells(&regex, row, end + y, &mut matches);
+        }
+        Ok(matches)
+    }
+
+    /// Row a buffer line from `search` is drawn on at the current scroll
+    /// position, if it is in view
+    pub fn display_row(&self, line: usize) -> Option<u32> {
+        let end = self.scrollback.end_line();
+        let history = self.folds.display_line_count(end);
+        let display = match line.checked_sub(end) {
+            Some(row) => history + row,
+            None => self.folds.buffer_to_display(line),
+        };
+        (display + self.viewport_offset)
+            .checked_sub(history)
+            .filter(|&row| row < self.height as usize)
+            .map(|row| row as u32)
+    }
+
+    /// Scroll so a buffer line is in view, putting history lines at the
+    /// top. Folded output holding the line is unfolded.
+    pub fn reveal_line(&mut self, line: usize) {
+        if let Some(index) = self.folds.region_at(line)
+            && self.folds.regions()[index].folded
+            && line >= self.folds.regions()[index].output_start
+        {
+            self.folds.set_folded(index, false);
+        }
+        if self.display_row(line).is_some() {
+            return;
+        }
+        let end = self.scrollback.end_line();
+        let lines = self.folds.display_line_count(end).saturating_sub(self.folds.buffer_to_display(line));
+        self.viewport_offset = 0;
+        self.scroll_viewport(lines as isize);
+    }
+
+    /// Whether the bell rang since the last call
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell_pending)
+    }
+
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
     
     pub fn get_cell(&self, x: u32, y: u32) -> Option<&TerminalCell> {
         if x < self.width && y < self.height {
@@ -539,6 +1427,47 @@ impl TerminalState {
     }
 }
 
+/// Text of a row of cells, skipping the spacer after each wide character
+fn text_of<'a>(cells: impl IntoIterator<Item = &'a TerminalCell>) -> String {
+    let mut text = String::new();
+    let mut skip_spacer = false;
+    for cell in cells {
+        if std::mem::take(&mut skip_spacer) {
+            continue;
+        }
+        skip_spacer = cell.wide;
+        text.push(if cell.character == '\0' { ' ' } else { cell.character });
+    }
+    text
+}
+
+/// `cells` of an `old` (width, height) grid copied onto a blank `new` one,
+/// top-left aligned, leaving out the first `skip_rows` rows
+fn resize_cells(
+    cells: &[TerminalCell],
+    old: (u32, u32),
+    skip_rows: u32,
+    new: (u32, u32),
+    blank: &TerminalCell,
+) -> Vec<TerminalCell> {
+    let (old_width, old_height) = old;
+    let (width, height) = new;
+    let mut resized = vec![blank.clone(); (width * height) as usize];
+    let copy_width = cmp::min(old_width, width) as usize;
+    let copy_height = cmp::min(old_height.saturating_sub(skip_rows), height);
+    for y in 0..copy_height {
+        let from = ((y + skip_rows) * old_width) as usize;
+        let to = (y * width) as usize;
+        if let (Some(source), Some(target)) = (cells.get(from..from + copy_width), resized.get_mut(to..to + copy_width)) {
+            target.clone_from_slice(source);
+        }
+    }
+    for cell in &mut resized {
+        cell.dirty = true;
+    }
+    resized
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -581,7 +1510,64 @@ mod tests {
             assert_eq!(cell.character, 'W');
         }
     }
-    
+
+    #[test]
+    fn test_carriage_return_backspace_and_wrap() {
+        let mut terminal = TerminalState::new(5, 3);
+        terminal.feed_bytes(b"abc\rX");
+        assert_eq!(terminal.get_cell(0, 0).unwrap().character, 'X');
+        assert_eq!(terminal.cursor_x, 1);
+
+        terminal.feed_bytes(b"\x08\x08");
+        assert_eq!(terminal.cursor_x, 0);
+
+        // Printing past the last column continues on the next line
+        terminal.feed_bytes(b"\r\n123456");
+        assert_eq!(terminal.get_cell(4, 1).unwrap().character, '5');
+        assert_eq!(terminal.get_cell(0, 2).unwrap().character, '6');
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (1, 2));
+    }
+
+    #[test]
+    fn test_wide_and_combining_chars() {
+        let mut terminal = TerminalState::new(10, 2);
+        terminal.feed_bytes("日a\u{0301}b".as_bytes());
+
+        assert!(terminal.get_cell(0, 0).unwrap().wide);
+        assert_eq!(terminal.get_cell(2, 0).unwrap().character, 'a');
+        // The combining accent does not consume a cell
+        assert_eq!(terminal.get_cell(3, 0).unwrap().character, 'b');
+        assert_eq!(terminal.cursor_x, 4);
+
+        // A wide char at the last column wraps instead of straddling the edge
+        terminal.feed_bytes(b"\x1b[1;10H");
+        terminal.feed_bytes("本".as_bytes());
+        assert_eq!(terminal.get_cell(0, 1).unwrap().character, '本');
+        assert_eq!(terminal.cursor_x, 2);
+    }
+
+    #[test]
+    fn test_hostile_input_regressions() {
+        let mut terminal = TerminalState::new(80, 24);
+        // Cursor moves by u32::MAX used to overflow
+        terminal.feed_bytes(b"\x1b[5;5H\x1b[4294967295B\x1b[4294967295C");
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (79, 23));
+
+        // Huge insert/delete counts are clamped to the line instead of looping
+        terminal.feed_bytes(b"\x1b[1;1Habc\x1b[1;1H\x1b[4294967295@\x1b[4294967295P");
+        assert_eq!(terminal.get_cell(0, 0).unwrap().character, ' ');
+
+        // Zalgo text occupies one cell per base character
+        let mut zalgo = String::from("\x1b[3;1H");
+        for base in ['z', 'a', 'l'] {
+            zalgo.push(base);
+            zalgo.extend(std::iter::repeat_n('\u{0336}', 200));
+        }
+        terminal.feed_bytes(zalgo.as_bytes());
+        assert_eq!(terminal.cursor_x, 3);
+        assert_eq!(terminal.get_cell(2, 2).unwrap().character, 'l');
+    }
+
     #[test]
     fn test_cursor_movement() {
         let mut terminal = TerminalState::new(80, 24);
@@ -601,4 +1587,419 @@ mod tests {
             assert_eq!(cell.character, ' ');
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_full_screen_redraw_with_scroll_region() {
+        let mut terminal = TerminalState::new(10, 5);
+        // Status line on the last row, text area above it
+        terminal.feed_bytes(b"\x1b[?1049h\x1b[2J\x1b[5;1H\x1b[7mstatus\x1b[m\x1b[1;4r");
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (0, 0));
+        terminal.feed_bytes(b"\x1b[1;1Hone\r\ntwo\r\nthree\r\nfour\r\nfive");
+        assert_eq!(terminal.row_text(0), "two");
+        assert_eq!(terminal.row_text(3), "five");
+        assert_eq!(terminal.row_text(4), "status");
+        assert!(terminal.get_cell(0, 4).unwrap().reverse);
+
+        // Scrolling back up at the top of the region
+        terminal.feed_bytes(b"\x1b[1;1H\x1bMzero\x1b[1;31;2m!\x1b[K");
+        assert_eq!(terminal.row_text(0), "zero!");
+        assert_eq!(terminal.row_text(1), "two");
+        assert_eq!(terminal.row_text(4), "status");
+        let cell = terminal.get_cell(4, 0).unwrap();
+        assert!(cell.bold && cell.dim);
+        assert_eq!(cell.foreground, crate::terminal_parser::Color::Red.to_rgba());
+
+        // The alternate screen never fed the scrollback
+        terminal.feed_bytes(b"\x1b[r\x1b[?1049l");
+        assert!(terminal.scrollback.is_empty());
+    }
+
+    #[test]
+    fn test_alternate_screen_restores_main() {
+        let mut terminal = TerminalState::new(10, 3);
+        terminal.feed_bytes(b"$ vim");
+        terminal.feed_bytes(b"\x1b[?1049h");
+        assert!(terminal.alternate_screen);
+        assert_eq!(terminal.get_cell(0, 0).unwrap().character, ' ');
+        terminal.feed_bytes(b"~\r\n~\r\n~\r\n~");
+        assert_eq!(terminal.scrollback.len(), 0);
+
+        terminal.feed_bytes(b"\x1b[?1049l");
+        assert!(!terminal.alternate_screen);
+        assert_eq!(terminal.get_cell(0, 0).unwrap().character, '$');
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (5, 0));
+
+        terminal.feed_bytes(b"\x1b[?2004h");
+        assert!(terminal.bracketed_paste);
+        terminal.feed_bytes(b"\x1b[?2004l");
+        assert!(!terminal.bracketed_paste);
+
+        terminal.feed_bytes(b"\x1b[?1000h\x1b[?1006h");
+        assert!(terminal.mouse_reporting && terminal.sgr_mouse);
+        terminal.feed_bytes(b"\x1b[?1000l");
+        assert!(!terminal.mouse_reporting);
+
+        use crate::terminal_parser::CursorShape;
+        terminal.feed_bytes(b"\x1b[5 q\x1b[?25l");
+        assert_eq!(terminal.cursor_style.map(|style| (style.shape, style.blink)), Some((CursorShape::Beam, true)));
+        assert!(!terminal.cursor_visible);
+        terminal.feed_bytes(b"\x1b[0 q");
+        assert_eq!(terminal.cursor_style, None);
+    }
+
+    #[test]
+    fn test_resize_on_alternate_screen_keeps_main() {
+        let mut terminal = TerminalState::new(10, 5);
+        terminal.feed_bytes(b"one\r\ntwo\r\nthree\r\nfour\r\n$ vim");
+        terminal.feed_bytes(b"\x1b[?1049h~\r\n~");
+        terminal.resize(8, 3);
+        assert_eq!(terminal.cells.len(), 8 * 3);
+        assert_eq!(terminal.scrollback.len(), 2);
+
+        terminal.feed_bytes(b"\x1b[?1049l");
+        let row = |terminal: &TerminalState, y: u32| -> String {
+            (0..terminal.width).map(|x| terminal.get_cell(x, y).unwrap().character).collect()
+        };
+        assert_eq!(row(&terminal, 0).trim_end(), "three");
+        assert_eq!(row(&terminal, 2).trim_end(), "$ vim");
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (5, 2));
+    }
+
+    #[test]
+    fn test_save_and_restore_cursor() {
+        let mut terminal = TerminalState::new(10, 5);
+        terminal.feed_bytes(b"\x1b[2;3H\x1b[1m\x1b7\x1b[5;5H\x1b[0m\x1b8");
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (2, 1));
+        assert!(terminal.current_bold);
+
+        terminal.feed_bytes(b"\x1b[4;4H\x1b[?1048h\x1b[1;1H\x1b[?1048l");
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (3, 3));
+
+        // The alternate screen has a saved cursor of its own
+        terminal.feed_bytes(b"\x1b[?1049h\x1b[3;3H\x1b8");
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (0, 0));
+        terminal.feed_bytes(b"\x1b[?1049l\x1b[1;1H\x1b8");
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (3, 3));
+    }
+
+    #[test]
+    fn test_palette_switch_recolors_screen_and_history() {
+        let dark = Theme::dark();
+        let light = Theme::light();
+        let mut terminal = TerminalState::new(10, 3);
+        terminal.feed_bytes(b"\x1b[31mred\x1b[38;2;1;2;3mtrue\x1b[m\r\n\n\n\nlast");
+        assert_eq!(terminal.scrollback.len(), 2);
+
+        terminal.set_palette(light);
+        let first = terminal.scrollback.line(terminal.scrollback.first_line()).unwrap().unwrap();
+        assert_eq!(first[0].foreground, light.ansi[1]);
+        assert_eq!(first[3].foreground, [1.0 / 255.0, 2.0 / 255.0, 3.0 / 255.0, 1.0]);
+        assert_eq!(first[9].background, light.background);
+        let cell = terminal.get_cell(0, 2).unwrap();
+        assert_eq!((cell.foreground, cell.background), (light.foreground, light.background));
+
+        // New output and blank rows use the new palette
+        terminal.feed_bytes(b"\x1b[34mx\x1b[49m\r\n");
+        assert_eq!(terminal.get_cell(4, 1).unwrap().foreground, light.ansi[4]);
+        assert_eq!(terminal.get_cell(0, 2).unwrap().background, light.background);
+        assert_ne!(light.ansi[4], dark.ansi[4]);
+    }
+
+    #[test]
+    fn test_prompt_input() {
+        let mut terminal = TerminalState::new(40, 5);
+        terminal.feed_bytes(b"git st");
+        assert_eq!(terminal.prompt_input(), None);
+
+        terminal.feed_bytes(b"\r\n\x1b]133;A\x07$ \x1b]133;B\x07");
+        assert_eq!(terminal.prompt_input().as_deref(), Some(""));
+        terminal.feed_bytes(b"git st");
+        assert_eq!(terminal.prompt_input().as_deref(), Some("git st"));
+        assert_eq!(terminal.row_text(1), "$ git st");
+        // Cursor moved back into the line
+        terminal.feed_bytes(b"\x1b[2D");
+        assert_eq!(terminal.prompt_input(), None);
+        terminal.feed_bytes(b"\x1b[2C\x1b[?1049h");
+        assert_eq!(terminal.prompt_input(), None);
+        terminal.feed_bytes(b"\x1b[?1049l");
+        assert_eq!(terminal.prompt_input().as_deref(), Some("git st"));
+        terminal.feed_bytes(b"\r\n\x1b]133;C\x07");
+        assert_eq!(terminal.prompt_input(), None);
+    }
+
+    #[test]
+    fn test_prompt_marks_track_input_start() {
+        let mut terminal = TerminalState::new(40, 5);
+        assert_eq!(terminal.at_input_start(), None);
+
+        // A scripted shell-integration session
+        terminal.feed_bytes(b"\x1b]133;A\x07~/src $ \x1b]133;B\x07");
+        assert_eq!(terminal.at_input_start(), Some(true));
+        terminal.feed_bytes(b"ls");
+        assert_eq!(terminal.at_input_start(), Some(false));
+        terminal.feed_bytes(b"\x08\x08");
+        assert_eq!(terminal.at_input_start(), Some(true));
+
+        terminal.feed_bytes(b"ls\r\n\x1b]133;C\x07file\r\n\x1b]133;D;0\x07");
+        assert_eq!(terminal.at_input_start(), Some(false));
+        assert_eq!(
+            terminal.last_prompt_mark,
+            Some(PromptMark::CommandFinished(Some(0)))
+        );
+
+        // A multi-line prompt starts input on its last line
+        terminal.feed_bytes(b"\x1b]133;A\x07user@host\r\n> \x1b]133;B\x1b\\");
+        assert_eq!(terminal.at_input_start(), Some(true));
+        terminal.feed_bytes(b"\x1b[?1049h\x1b[?1049l");
+        assert_eq!(terminal.at_input_start(), Some(true));
+        terminal.feed_bytes(b"\x1b[?1049h");
+        assert_eq!(terminal.at_input_start(), Some(false));
+    }
+
+    #[test]
+    fn test_shell_events_capture_command_text() {
+        let mut terminal = TerminalState::new(12, 3);
+        terminal.feed_bytes(b"\x1b]133;A\x07$ \x1b]133;B\x07git status -sb\r\n\x1b]133;C\x07");
+        terminal.feed_bytes(b"M  a.rs\r\n\x1b]133;D;1\x07");
+        assert_eq!(
+            terminal.take_shell_events(),
+            vec![
+                ShellEvent::CommandStarted("git status -sb".to_string()),
+                ShellEvent::CommandFinished(Some(1)),
+            ]
+        );
+        assert!(terminal.take_shell_events().is_empty());
+    }
+
+    #[test]
+    fn test_folded_output_takes_one_history_row() {
+        use crate::buffer_search::SearchQuery;
+
+        let mut terminal = TerminalState::new(24, 3);
+        terminal.set_auto_fold_lines(5);
+        terminal.feed_bytes(b"\x1b]133;A\x07$ \x1b]133;B\x07make\r\n\x1b]133;C\x07");
+        for i in 0..10 {
+            terminal.feed_bytes(format!("out {}\r\n", i).as_bytes());
+        }
+        terminal.feed_bytes(b"\x1b]133;D;0\x07\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]133;C\x07");
+        terminal.feed_bytes(b"a\r\nb\r\nc\r\n\x1b]133;D;0\x07");
+        let text = |terminal: &mut TerminalState| -> Vec<String> {
+            terminal
+                .viewport_history()
+                .iter()
+                .map(|row| row.iter().map(|c| c.character).collect::<String>().trim_end().to_string())
+                .collect()
+        };
+
+        // Ten lines of output past the threshold fold into their summary
+        terminal.scroll_viewport_to_top();
+        assert_eq!(terminal.viewport_offset(), 4);
+        let rows = text(&mut terminal);
+        assert_eq!(rows[0], "$ make");
+        assert!(rows[1].starts_with("↳ 10 lines, exit 0"), "{:?}", rows);
+        assert_eq!(rows[2], "$ ls");
+
+        assert_eq!(terminal.toggle_fold(), Some(false));
+        assert_eq!(text(&mut terminal), ["$ make", "out 0", "out 1"]);
+        terminal.scroll_viewport_to_top();
+        assert_eq!(terminal.viewport_offset(), 13);
+
+        // Folded again, a search match inside a fold unfolds it
+        assert!(terminal.set_folded(true, true));
+        assert_eq!(terminal.viewport_offset(), 4);
+        let query = SearchQuery {
+            pattern: "out 4".to_string(),
+            case_sensitive: true,
+            regex: false,
+        };
+        let line = terminal.search(&query).unwrap()[0].line;
+        // Shown on its fold's summary row until revealed
+        assert_eq!(terminal.display_row(line), Some(1));
+        terminal.reveal_line(line);
+        assert_eq!(terminal.display_row(line), Some(0));
+        assert_eq!(text(&mut terminal)[0], "out 4");
+    }
+
+    #[test]
+    fn test_search_finds_history_and_reveals_it() {
+        use crate::buffer_search::SearchQuery;
+        use std::time::{Duration, Instant};
+
+        let mut terminal = TerminalState::new(80, 24);
+        for i in 0..10_000 {
+            terminal.feed_bytes(format!("line {} of the build log\r\n", i).as_bytes());
+        }
+
+        let query = |pattern: &str, regex| SearchQuery {
+            pattern: pattern.to_string(),
+            case_sensitive: false,
+            regex,
+        };
+        let started = Instant::now();
+        let matches = terminal.search(&query("LINE 4321 ", false)).unwrap();
+        let elapsed = started.elapsed();
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].start, matches[0].end), (0, 10));
+        assert!(elapsed < Duration::from_millis(500), "search took {:?}", elapsed);
+
+        // Out of view until revealed, then on the top row
+        let line = matches[0].line;
+        assert_eq!(terminal.display_row(line), None);
+        terminal.reveal_line(line);
+        assert_eq!(terminal.display_row(line), Some(0));
+        let history = terminal.viewport_history();
+        let text: String = history[0].iter().map(|c| c.character).collect();
+        assert!(text.starts_with("line 4321 "));
+
+        // Screen rows come after the history and bring the view back down
+        let last = terminal.search(&query(r"^line 9999\b", true)).unwrap();
+        assert_eq!(last.len(), 1);
+        terminal.reveal_line(last[0].line);
+        assert_eq!(terminal.viewport_offset(), 0);
+        assert!(terminal.search(&query("(", true)).is_err());
+    }
+
+    #[test]
+    fn test_viewport_scrolls_through_bounded_history() {
+        use crate::scrollback::ScrollbackConfig;
+
+        let mut terminal = TerminalState::new(8, 3);
+        terminal.scrollback = Scrollback::new(ScrollbackConfig {
+            live_lines: 4,
+            block_lines: 2,
+            max_lines: 6,
+            cached_blocks: 1,
+        });
+        for i in 0..10 {
+            terminal.feed_bytes(format!("line {}\r\n", i).as_bytes());
+        }
+        // Oldest lines were evicted at the cap
+        assert_eq!(terminal.scrollback.len(), 6);
+        assert_eq!(terminal.row_text(0), "line 8");
+
+        terminal.scroll_viewport(2);
+        let history = terminal.viewport_history();
+        let text = |row: &[TerminalCell]| row.iter().map(|c| c.character).collect::<String>();
+        assert_eq!(history.len(), 2);
+        assert_eq!(text(&history[0]).trim_end(), "line 6");
+        assert_eq!(text(&history[1]).trim_end(), "line 7");
+
+        // New output keeps the view on the same lines
+        terminal.feed_bytes(b"line 10\r\n");
+        assert_eq!(terminal.viewport_offset(), 3);
+        assert_eq!(text(&terminal.viewport_history()[0]).trim_end(), "line 6");
+
+        // Whole compressed blocks are evicted, so the cap is an upper bound
+        terminal.scroll_viewport_to_top();
+        assert!(terminal.scrollback.len() <= 6);
+        assert_eq!(terminal.viewport_offset(), terminal.scrollback.len());
+        terminal.scroll_viewport(-100);
+        assert_eq!(terminal.viewport_offset(), 0);
+    }
+
+    #[test]
+    fn test_snapshot_lines_reaches_into_scrollback() {
+        let mut terminal = TerminalState::new(12, 3);
+        terminal.feed_bytes(b"$ make\r\nerror: one  \r\n\r\nerror: two\r\n$ ");
+        assert_eq!(terminal.scrollback.len(), 2);
+        assert_eq!(terminal.snapshot_lines(2), ["error: one", "error: two"]);
+        assert_eq!(terminal.snapshot_lines(10), ["$ make", "error: one", "error: two"]);
+        assert!(terminal.snapshot_lines(0).is_empty());
+    }
+
+    #[test]
+    fn test_screen_links_follow_wraps_and_osc8() {
+        let mut terminal = TerminalState::new(12, 4);
+        terminal.feed_bytes(b"go https://example.com/docs now\r\n");
+        terminal.feed_bytes(b"\x1b]8;;https://b.example\x1b\\here\x1b]8;;\x1b\\ x");
+        let links = terminal.screen_links();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].url, "https://example.com/docs");
+        assert_eq!(terminal.link_at(1, 1).as_deref(), Some("https://example.com/docs"));
+        assert_eq!(links[1].url, "https://b.example");
+        assert_eq!(links[1].cells, [(0, 3), (1, 3), (2, 3), (3, 3)]);
+        assert!(terminal.get_cell(3, 3).unwrap().link.is_some());
+        assert!(terminal.get_cell(5, 3).unwrap().link.is_none());
+        assert_eq!(terminal.link_at(0, 0), None);
+    }
+
+    #[test]
+    fn test_inline_region_keeps_shell_output() {
+        let row = |text: &str| -> Vec<TerminalCell> {
+            text.chars()
+                .map(|character| TerminalCell { character, ..Default::default() })
+                .collect()
+        };
+        let mut terminal = TerminalState::new(10, 4);
+        terminal.feed_bytes(b"$ f why");
+        terminal.set_inline_region(vec![row("one"), row("two")]);
+        assert_eq!(terminal.inline_region().map(|(top, rows)| (top, rows.len())), Some((1, 2)));
+
+        // Shell output lands above the answer, which moves down, then the
+        // screen scrolls to keep it in view
+        terminal.feed_bytes(b"\r\ndone\r\n$ ");
+        assert_eq!(terminal.row_text(0), "done");
+        assert_eq!(terminal.row_text(1).trim_end(), "$");
+        assert_eq!(terminal.cursor_y, 1);
+        assert_eq!(terminal.scrollback.len(), 1);
+        assert_eq!(terminal.inline_region().map(|(top, _)| top), Some(2));
+
+        terminal.set_inline_region(vec![row("one"), row("two"), row("three")]);
+        terminal.commit_inline_region();
+        assert!(terminal.inline_region().is_none());
+        assert_eq!(terminal.row_text(0), "one");
+        assert_eq!(terminal.row_text(2), "three");
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (0, 3));
+        assert_eq!(terminal.scrollback.len(), 3);
+    }
+
+    #[test]
+    fn test_resize_keeps_history_and_cursor_line() {
+        let mut terminal = TerminalState::new(10, 4);
+        terminal.feed_bytes(b"old\r\none\r\ntwo\r\n$ ls");
+        terminal.scroll_viewport(1);
+        assert_eq!(terminal.viewport_offset(), 0);
+
+        terminal.resize(10, 2);
+        assert_eq!(terminal.row_text(0), "two");
+        assert_eq!(terminal.row_text(1), "$ ls");
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (4, 1));
+        assert_eq!(terminal.scrollback.len(), 2);
+
+        terminal.resize(10, 6);
+        assert_eq!(terminal.scrollback.len(), 2);
+        terminal.scroll_viewport_to_top();
+        assert_eq!(terminal.viewport_offset(), 2);
+        let history = terminal.viewport_history();
+        assert_eq!(history[0][0].character, 'o');
+        assert_eq!(history[1][..3].iter().map(|c| c.character).collect::<String>(), "one");
+    }
+
+    #[test]
+    fn test_extract_selected_text() {
+        let mut terminal = TerminalState::new(10, 3);
+        terminal.feed_bytes("ab 漢字   \r\nsecond\r\nthird".as_bytes());
+
+        // Backwards drags select the same cells; wide glyphs come out once
+        let selection = Selection {
+            anchor: (2, 1),
+            head: (5, 0),
+        };
+        assert_eq!(terminal.extract_text(&selection), "字\nsec");
+        assert!(selection.contains(9, 0) && selection.contains(0, 1));
+        assert!(!selection.contains(4, 0) && !selection.contains(3, 1));
+        // Starting on the right half of a wide glyph takes all of it
+        let selection = Selection {
+            anchor: (4, 0),
+            head: (6, 0),
+        };
+        assert_eq!(terminal.extract_text(&selection), "漢字");
+
+        let everything = Selection {
+            anchor: (0, 0),
+            head: (9, 2),
+        };
+        assert_eq!(terminal.extract_text(&everything), "ab 漢字\nsecond\nthird");
+        assert_eq!(terminal.extract_text(&Selection::new(3, 0)), "漢");
+    }
+}
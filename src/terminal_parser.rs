@@ -1,3 +1,4 @@
+use crate::theme::Theme;
 use std::collections::VecDeque;
 use thiserror::Error;
 use tracing::{debug, warn};
@@ -40,6 +41,7 @@ pub enum TerminalAction {
     SetForeground(Color),
     SetBackground(Color),
     SetBold(bool),
+    SetDim(bool),
     SetItalic(bool),
     SetUnderline(bool),
     SetReverse(bool),
@@ -48,6 +50,12 @@ pub enum TerminalAction {
     // Scrolling
     ScrollUp(u32),
     ScrollDown(u32),
+    /// DECSTBM: top row and, unless it is the last row, bottom row (0-based)
+    SetScrollRegion(u32, Option<u32>),
+    /// `ESC D`: cursor down, scrolling the region at its bottom
+    Index,
+    /// `ESC M`: cursor up, scrolling the region at its top
+    ReverseIndex,
     
     // Special
     Newline,
@@ -63,6 +71,112 @@ pub enum TerminalAction {
     // Terminal modes
     SetApplicationMode(bool),
     SetWrapMode(bool),
+    
+    // Queries and reports
+    QueryBackground,
+    QueryColorScheme,
+    /// Mode 2031: report color scheme changes unprompted
+    SetColorSchemeUpdates(bool),
+    /// Modes 47/1047/1049: full-screen applications draw on a separate screen
+    SetAlternateScreen(bool),
+    /// DECSC (`ESC 7`) or mode 1048 set: remember the cursor position and
+    /// text attributes
+    SaveCursor,
+    /// DECRC (`ESC 8`) or mode 1048 reset
+    RestoreCursor,
+    /// Mode 2004: the application wants pastes wrapped in markers
+    SetBracketedPaste(bool),
+    /// Modes 1000/1002/1003: the application wants mouse reports
+    SetMouseReporting(bool),
+    /// Mode 1006: mouse reports use the SGR encoding
+    SetSgrMouse(bool),
+    /// DECSCUSR (`CSI Ps SP q`); `None` goes back to the user's cursor
+    SetCursorStyle(Option<CursorStyle>),
+    /// Shell integration mark (OSC 133)
+    PromptMark(PromptMark),
+    /// Window or icon title (OSC 0 and 2)
+    SetTitle(String),
+    /// Text that follows links to this URL, until `None` (OSC 8)
+    SetHyperlink(Option<String>),
+    /// The shell's working directory, from a `file://host/path` URL (OSC 7)
+    SetWorkingDirectory(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    /// A bar at the left edge of the cell
+    Beam,
+}
+
+impl CursorShape {
+    /// `ui.cursor_style`; anything unknown is a block
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "beam" | "bar" => CursorShape::Beam,
+            "underline" => CursorShape::Underline,
+            _ => CursorShape::Block,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blink: bool,
+}
+
+/// Shell integration marks, as emitted by shells configured for OSC 133
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptMark {
+    /// `A`: the prompt is about to be drawn
+    PromptStart,
+    /// `B`: the prompt is drawn; user input starts here
+    CommandStart,
+    /// `C`: the command was submitted and its output follows
+    OutputStart,
+    /// `D[;exit]`: the command finished
+    CommandFinished(Option<i32>),
+}
+
+/// Path of a `file://host/path` URL, percent-decoded
+fn file_url_path(url: &[u8]) -> Option<String> {
+    let rest = url.strip_prefix(b"file://")?;
+    let path = &rest[rest.iter().position(|&b| b == b'/')?..];
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let hex = path.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (path[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+impl PromptMark {
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        let data = std::str::from_utf8(data).ok()?;
+        let mut parts = data.split(';');
+        match parts.next()? {
+            "A" => Some(PromptMark::PromptStart),
+            "B" => Some(PromptMark::CommandStart),
+            "C" => Some(PromptMark::OutputStart),
+            "D" => Some(PromptMark::CommandFinished(
+                parts.next().and_then(|code| code.parse().ok()),
+            )),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -89,59 +203,34 @@ pub enum Color {
 }
 
 impl Color {
+    /// RGBA under the built-in dark theme; the grid resolves colors
+    /// through its own palette
     pub fn to_rgba(&self) -> [f32; 4] {
-        match self {
-            Color::Black => [0.0, 0.0, 0.0, 1.0],
-            Color::Red => [1.0, 0.0, 0.0, 1.0],
-            Color::Green => [0.0, 1.0, 0.0, 1.0],
-            Color::Yellow => [1.0, 1.0, 0.0, 1.0],
-            Color::Blue => [0.0, 0.0, 1.0, 1.0],
-            Color::Magenta => [1.0, 0.0, 1.0, 1.0],
-            Color::Cyan => [0.0, 1.0, 1.0, 1.0],
-            Color::White => [1.0, 1.0, 1.0, 1.0],
-            Color::BrightBlack => [0.5, 0.5, 0.5, 1.0],
-            Color::BrightRed => [1.0, 0.5, 0.5, 1.0],
-            Color::BrightGreen => [0.5, 1.0, 0.5, 1.0],
-            Color::BrightYellow => [1.0, 1.0, 0.5, 1.0],
-            Color::BrightBlue => [0.5, 0.5, 1.0, 1.0],
-            Color::BrightMagenta => [1.0, 0.5, 1.0, 1.0],
-            Color::BrightCyan => [0.5, 1.0, 1.0, 1.0],
-            Color::BrightWhite => [1.0, 1.0, 1.0, 1.0],
-            Color::TrueColor(r, g, b) => [*r as f32 / 255.0, *g as f32 / 255.0, *b as f32 / 255.0, 1.0],
-            Color::Color256(n) => {
-                // Convert 256-color palette to RGB (simplified)
-                let n = *n as f32;
-                if n < 16.0 {
-                    // Standard colors
-                    match n as u8 {
-                        0..=7 => Color::Black.to_rgba(),
-                        8..=15 => Color::BrightBlack.to_rgba(),
-                        _ => [1.0, 1.0, 1.0, 1.0],
-                    }
-                } else if n < 232.0 {
-                    // 216 color cube
-                    let n = n - 16.0;
-                    let r = (n / 36.0) as u8;
-                    let g = ((n % 36.0) / 6.0) as u8;
-                    let b = (n % 6.0) as u8;
-                    [r as f32 * 0.2, g as f32 * 0.2, b as f32 * 0.2, 1.0]
-                } else {
-                    // Grayscale
-                    let gray = (n - 232.0) / 24.0;
-                    [gray, gray, gray, 1.0]
-                }
-            }
-            Color::Default => [1.0, 1.0, 1.0, 1.0], // White
-        }
+        Theme::dark().color(self, true)
     }
 }
 
+/// Upper bounds on CSI parameter collection, so a hostile stream of digits
+/// or separators cannot grow parser state without limit
+const MAX_CSI_PARAMS: usize = 32;
+const MAX_PARAM_DIGITS: usize = 10;
+const MAX_OSC_BYTES: usize = 4096;
+
 #[derive(Debug, Clone)]
 pub struct TerminalParser {
     buffer: VecDeque<u8>,
     state: ParserState,
     params: Vec<u32>,
     current_param: String,
+    /// CSI `?` prefix seen
+    private: bool,
+    /// CSI space intermediate seen, as in DECSCUSR
+    space: bool,
+    osc_data: Vec<u8>,
+    utf8_buf: Vec<u8>,
+    utf8_needed: usize,
+    /// Actions beyond the first from a sequence such as `ESC[1;31m`
+    pending: Vec<TerminalAction>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -165,6 +254,12 @@ impl TerminalParser {
             state: ParserState::Normal,
             params: Vec::new(),
             current_param: String::new(),
+            private: false,
+            space: false,
+            osc_data: Vec::new(),
+            utf8_buf: Vec::with_capacity(4),
+            utf8_needed: 0,
+            pending: Vec::new(),
         }
     }
 
@@ -177,7 +272,10 @@ impl TerminalParser {
 
         while let Some(byte) = self.buffer.pop_front() {
             match self.parse_byte(byte) {
-                Ok(Some(action)) => actions.push(action),
+                Ok(Some(action)) => {
+                    actions.push(action);
+                    actions.append(&mut self.pending);
+                }
                 Ok(None) => {}, // Continue parsing
                 Err(e) => {
                     warn!("Parse error: {}", e);
@@ -200,6 +298,10 @@ impl TerminalParser {
     }
 
     fn parse_normal(&mut self, byte: u8) -> Result<Option<TerminalAction>, ParseError> {
+        if self.utf8_needed > 0 {
+            return Ok(self.continue_utf8(byte));
+        }
+
         match byte {
             0x1B => { // ESC
                 self.state = ParserState::Escape;
@@ -214,9 +316,12 @@ impl TerminalParser {
                 // Printable ASCII
                 Ok(Some(TerminalAction::PrintChar(byte as char)))
             }
+            0xC2..=0xDF => Ok(self.start_utf8(byte, 1)),
+            0xE0..=0xEF => Ok(self.start_utf8(byte, 2)),
+            0xF0..=0xF4 => Ok(self.start_utf8(byte, 3)),
             0x80..=0xFF => {
-                // Handle UTF-8 sequences (simplified)
-                Ok(Some(TerminalAction::PrintChar(byte as char)))
+                // Stray continuation or invalid lead byte
+                Ok(Some(TerminalAction::PrintChar(char::REPLACEMENT_CHARACTER)))
             }
             _ => {
                 // Control characters - ignore for now
@@ -232,19 +337,35 @@ impl TerminalParser {
                 self.state = ParserState::CSI;
                 self.params.clear();
                 self.current_param.clear();
+                self.private = false;
+                self.space = false;
                 Ok(None)
             }
             b']' => {
                 self.state = ParserState::OSC;
+                self.osc_data.clear();
+                Ok(None)
+            }
+            b'\\' => {
+                // String terminator after OSC
+                self.state = ParserState::Normal;
                 Ok(None)
             }
             b'M' => {
                 self.state = ParserState::Normal;
-                Ok(Some(TerminalAction::ScrollUp(1)))
+                Ok(Some(TerminalAction::ReverseIndex))
+            }
+            b'7' => {
+                self.state = ParserState::Normal;
+                Ok(Some(TerminalAction::SaveCursor))
+            }
+            b'8' => {
+                self.state = ParserState::Normal;
+                Ok(Some(TerminalAction::RestoreCursor))
             }
             b'D' => {
                 self.state = ParserState::Normal;
-                Ok(Some(TerminalAction::ScrollDown(1)))
+                Ok(Some(TerminalAction::Index))
             }
             b'H' => {
                 self.state = ParserState::Normal;
@@ -269,13 +390,63 @@ impl TerminalParser {
     fn parse_csi(&mut self, byte: u8) -> Result<Option<TerminalAction>, ParseError> {
         match byte {
             b'0'..=b'9' => {
-                self.current_param.push(byte as char);
+                if self.current_param.len() < MAX_PARAM_DIGITS {
+                    self.current_param.push(byte as char);
+                }
                 Ok(None)
             }
             b';' => {
                 self.push_param();
                 Ok(None)
             }
+            b'?' => {
+                self.private = true;
+                Ok(None)
+            }
+            b' ' => {
+                self.push_param();
+                self.space = true;
+                Ok(None)
+            }
+            b'q' if self.space => {
+                let ps = self.params.first().copied().unwrap_or(0);
+                self.reset_state();
+                let style = |shape, blink| Some(CursorStyle { shape, blink });
+                Ok(match ps {
+                    0 => Some(TerminalAction::SetCursorStyle(None)),
+                    1 => Some(TerminalAction::SetCursorStyle(style(CursorShape::Block, true))),
+                    2 => Some(TerminalAction::SetCursorStyle(style(CursorShape::Block, false))),
+                    3 => Some(TerminalAction::SetCursorStyle(style(CursorShape::Underline, true))),
+                    4 => Some(TerminalAction::SetCursorStyle(style(CursorShape::Underline, false))),
+                    5 => Some(TerminalAction::SetCursorStyle(style(CursorShape::Beam, true))),
+                    6 => Some(TerminalAction::SetCursorStyle(style(CursorShape::Beam, false))),
+                    _ => None,
+                })
+            }
+            // Private modes and reports
+            b'h' | b'l' | b'n' if self.private => {
+                self.push_param();
+                let mode = self.params.first().copied();
+                self.reset_state();
+                Ok(match (byte, mode) {
+                    (b'h', Some(25)) => Some(TerminalAction::ShowCursor),
+                    (b'l', Some(25)) => Some(TerminalAction::HideCursor),
+                    (b'h', Some(2031)) => Some(TerminalAction::SetColorSchemeUpdates(true)),
+                    (b'l', Some(2031)) => Some(TerminalAction::SetColorSchemeUpdates(false)),
+                    (b'n', Some(996)) => Some(TerminalAction::QueryColorScheme),
+                    (b'h', Some(47 | 1047 | 1049)) => Some(TerminalAction::SetAlternateScreen(true)),
+                    (b'h', Some(1048)) => Some(TerminalAction::SaveCursor),
+                    (b'l', Some(1048)) => Some(TerminalAction::RestoreCursor),
+                    (b'l', Some(47 | 1047 | 1049)) => Some(TerminalAction::SetAlternateScreen(false)),
+                    (b'h', Some(2004)) => Some(TerminalAction::SetBracketedPaste(true)),
+                    (b'l', Some(2004)) => Some(TerminalAction::SetBracketedPaste(false)),
+                    (b'h', Some(1000 | 1002 | 1003)) => Some(TerminalAction::SetMouseReporting(true)),
+                    (b'l', Some(1000 | 1002 | 1003)) => Some(TerminalAction::SetMouseReporting(false)),
+                    (b'h', Some(1006)) => Some(TerminalAction::SetSgrMouse(true)),
+                    (b'l', Some(1006)) => Some(TerminalAction::SetSgrMouse(false)),
+                    _ => None,
+                })
+            }
             // Cursor movement
             b'A' => {
                 self.push_param();
@@ -350,13 +521,34 @@ impl TerminalParser {
                 self.reset_state();
                 Ok(Some(TerminalAction::InsertChar(n)))
             }
+            // Scrolling
+            b'S' => {
+                self.push_param();
+                let n = self.params.first().copied().unwrap_or(1);
+                self.reset_state();
+                Ok(Some(TerminalAction::ScrollUp(n)))
+            }
+            b'T' => {
+                self.push_param();
+                let n = self.params.first().copied().unwrap_or(1);
+                self.reset_state();
+                Ok(Some(TerminalAction::ScrollDown(n)))
+            }
+            b'r' => {
+                self.push_param();
+                let top = self.params.first().copied().unwrap_or(1).saturating_sub(1);
+                let bottom = self.params.get(1).copied().filter(|&b| b > 0).map(|b| b - 1);
+                self.reset_state();
+                Ok(Some(TerminalAction::SetScrollRegion(top, bottom)))
+            }
             // Text attributes (SGR)
             b'm' => {
                 self.push_param();
-                let actions = self.parse_sgr();
+                let mut actions = self.parse_sgr().into_iter();
                 self.reset_state();
-                // Return the first action for now (simplified)
-                Ok(actions.into_iter().next())
+                let first = actions.next();
+                self.pending.extend(actions);
+                Ok(first)
             }
             // Cursor visibility
             b'l' if self.params.get(0) == Some(&25) => {
@@ -378,20 +570,78 @@ impl TerminalParser {
         // OSC sequences (Operating System Commands) - simplified handling
         match byte {
             0x07 | 0x1B => { // BEL or ESC (terminator)
-                self.state = ParserState::Normal;
-                // For now, ignore OSC sequences
-                Ok(None)
+                // ESC starts the ST terminator; its backslash is consumed
+                // in the escape state
+                self.state = if byte == 0x1B { ParserState::Escape } else { ParserState::Normal };
+                let action = match self.osc_data.as_slice() {
+                    b"11;?" => Some(TerminalAction::QueryBackground),
+                    [b'1', b'3', b'3', b';', mark @ ..] => {
+                        PromptMark::parse(mark).map(TerminalAction::PromptMark)
+                    }
+                    [b'7', b';', url @ ..] => {
+                        file_url_path(url).map(TerminalAction::SetWorkingDirectory)
+                    }
+                    [b'0' | b'2', b';', title @ ..] => Some(TerminalAction::SetTitle(
+                        String::from_utf8_lossy(title).into_owned(),
+                    )),
+                    // `8;params;uri`; an empty URI ends the link
+                    [b'8', b';', rest @ ..] => rest
+                        .iter()
+                        .position(|&b| b == b';')
+                        .map(|split| {
+                            let uri = String::from_utf8_lossy(&rest[split + 1..]).into_owned();
+                            TerminalAction::SetHyperlink(Some(uri).filter(|uri| !uri.is_empty()))
+                        }),
+                    _ => None,
+                };
+                self.osc_data.clear();
+                Ok(action)
             }
             _ => {
                 // Continue collecting OSC data
+                if self.osc_data.len() < MAX_OSC_BYTES {
+                    self.osc_data.push(byte);
+                }
                 Ok(None)
             }
         }
     }
 
+    fn start_utf8(&mut self, byte: u8, needed: usize) -> Option<TerminalAction> {
+        self.utf8_buf.clear();
+        self.utf8_buf.push(byte);
+        self.utf8_needed = needed;
+        None
+    }
+
+    fn continue_utf8(&mut self, byte: u8) -> Option<TerminalAction> {
+        if !(0x80..=0xBF).contains(&byte) {
+            // Truncated sequence: emit a replacement and reprocess this byte
+            self.utf8_needed = 0;
+            self.utf8_buf.clear();
+            self.buffer.push_front(byte);
+            return Some(TerminalAction::PrintChar(char::REPLACEMENT_CHARACTER));
+        }
+
+        self.utf8_buf.push(byte);
+        self.utf8_needed -= 1;
+        if self.utf8_needed > 0 {
+            return None;
+        }
+        // Overlong forms and surrogates fail here
+        let ch = std::str::from_utf8(&self.utf8_buf)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        self.utf8_buf.clear();
+        Some(TerminalAction::PrintChar(ch))
+    }
+
     fn push_param(&mut self) {
         if !self.current_param.is_empty() {
-            if let Ok(param) = self.current_param.parse::<u32>() {
+            if let Ok(param) = self.current_param.parse::<u32>()
+                && self.params.len() < MAX_CSI_PARAMS
+            {
                 self.params.push(param);
             }
             self.current_param.clear();
@@ -402,6 +652,8 @@ impl TerminalParser {
         self.state = ParserState::Normal;
         self.params.clear();
         self.current_param.clear();
+        self.private = false;
+        self.space = false;
     }
 
     fn parse_sgr(&self) -> Vec<TerminalAction> {
@@ -416,10 +668,14 @@ impl TerminalParser {
             match self.params[i] {
                 0 => actions.push(TerminalAction::ResetAttributes),
                 1 => actions.push(TerminalAction::SetBold(true)),
+                2 => actions.push(TerminalAction::SetDim(true)),
                 3 => actions.push(TerminalAction::SetItalic(true)),
                 4 => actions.push(TerminalAction::SetUnderline(true)),
                 7 => actions.push(TerminalAction::SetReverse(true)),
-                22 => actions.push(TerminalAction::SetBold(false)),
+                22 => {
+                    actions.push(TerminalAction::SetBold(false));
+                    actions.push(TerminalAction::SetDim(false));
+                }
                 23 => actions.push(TerminalAction::SetItalic(false)),
                 24 => actions.push(TerminalAction::SetUnderline(false)),
                 27 => actions.push(TerminalAction::SetReverse(false)),
@@ -582,6 +838,89 @@ mod tests {
         assert_eq!(actions[0], TerminalAction::SetForeground(Color::Red));
     }
 
+    #[test]
+    fn test_combined_sgr_and_scroll_region() {
+        let mut parser = TerminalParser::new();
+        // `ls --color` style: every attribute in one sequence applies
+        let actions = parser.feed(b"\x1b[01;34mbin\x1b[0m");
+        assert_eq!(
+            actions,
+            vec![
+                TerminalAction::SetBold(true),
+                TerminalAction::SetForeground(Color::Blue),
+                TerminalAction::PrintChar('b'),
+                TerminalAction::PrintChar('i'),
+                TerminalAction::PrintChar('n'),
+                TerminalAction::ResetAttributes,
+            ]
+        );
+        assert_eq!(
+            parser.feed(b"\x1b[2;38;5;208m"),
+            vec![
+                TerminalAction::SetDim(true),
+                TerminalAction::SetForeground(Color::Color256(208)),
+            ]
+        );
+
+        assert_eq!(
+            parser.feed(b"\x1b[2;10r\x1b[r\x1bM"),
+            vec![
+                TerminalAction::SetScrollRegion(1, Some(9)),
+                TerminalAction::SetScrollRegion(0, None),
+                TerminalAction::ReverseIndex,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_utf8_decoding() {
+        let mut parser = TerminalParser::new();
+        let actions = parser.feed("é日🦀".as_bytes());
+        assert_eq!(
+            actions,
+            vec![
+                TerminalAction::PrintChar('é'),
+                TerminalAction::PrintChar('日'),
+                TerminalAction::PrintChar('🦀'),
+            ]
+        );
+
+        // Sequences split across reads
+        let bytes = "日".as_bytes();
+        assert!(parser.feed(&bytes[..1]).is_empty());
+        assert_eq!(parser.feed(&bytes[1..]), vec![TerminalAction::PrintChar('日')]);
+    }
+
+    #[test]
+    fn test_malformed_utf8_regressions() {
+        let mut parser = TerminalParser::new();
+        // Truncated sequence followed by ASCII keeps the ASCII byte
+        let actions = parser.feed(b"\xE6\x97A");
+        assert_eq!(
+            actions,
+            vec![
+                TerminalAction::PrintChar(char::REPLACEMENT_CHARACTER),
+                TerminalAction::PrintChar('A'),
+            ]
+        );
+        // Overlong encoding, stray continuation and invalid lead bytes
+        let actions = parser.feed(b"\xE0\x80\x80\x80\xFF");
+        assert!(actions.iter().all(|a| *a == TerminalAction::PrintChar(char::REPLACEMENT_CHARACTER)));
+        assert_eq!(actions.len(), 3);
+    }
+
+    #[test]
+    fn test_csi_param_bounds() {
+        let mut parser = TerminalParser::new();
+        let mut input = b"\x1b[".to_vec();
+        input.extend(std::iter::repeat_n(b'9', 10_000));
+        input.extend(std::iter::repeat_n(b';', 10_000));
+        input.push(b'A');
+        let actions = parser.feed(&input);
+        assert_eq!(actions.len(), 1);
+        assert!(parser.params.capacity() <= MAX_CSI_PARAMS * 2);
+    }
+
     #[test]
     fn test_newline() {
         let mut parser = TerminalParser::new();
@@ -590,4 +929,68 @@ mod tests {
         assert_eq!(actions.len(), 1);
         assert_eq!(actions[0], TerminalAction::Newline);
     }
+
+    #[test]
+    fn test_title_sequences() {
+        let mut parser = TerminalParser::new();
+        assert_eq!(
+            parser.feed(b"\x1b]0;vim main.rs\x07"),
+            [TerminalAction::SetTitle("vim main.rs".to_string())]
+        );
+        assert_eq!(
+            parser.feed(b"\x1b]2;~/src\x1b\\"),
+            [TerminalAction::SetTitle("~/src".to_string())]
+        );
+        // OSC 1 sets only the icon name
+        assert!(parser.feed(b"\x1b]1;icon\x07").is_empty());
+    }
+
+    #[test]
+    fn test_hyperlink_sequences() {
+        let mut parser = TerminalParser::new();
+        assert_eq!(
+            parser.feed(b"\x1b]8;id=1;https://example.com\x1b\\"),
+            [TerminalAction::SetHyperlink(Some("https://example.com".to_string()))]
+        );
+        assert_eq!(parser.feed(b"\x1b]8;;\x07"), [TerminalAction::SetHyperlink(None)]);
+    }
+
+    #[test]
+    fn test_cursor_save_sequences() {
+        let mut parser = TerminalParser::new();
+        assert_eq!(parser.feed(b"\x1b7"), [TerminalAction::SaveCursor]);
+        assert_eq!(parser.feed(b"\x1b8"), [TerminalAction::RestoreCursor]);
+        assert_eq!(parser.feed(b"\x1b[?1048h"), [TerminalAction::SaveCursor]);
+        assert_eq!(parser.feed(b"\x1b[?1048l"), [TerminalAction::RestoreCursor]);
+        assert_eq!(parser.feed(b"\x1b[?47h"), [TerminalAction::SetAlternateScreen(true)]);
+        assert_eq!(parser.feed(b"\x1b[?1047l"), [TerminalAction::SetAlternateScreen(false)]);
+    }
+
+    #[test]
+    fn test_working_directory_sequences() {
+        let mut parser = TerminalParser::new();
+        assert_eq!(
+            parser.feed(b"\x1b]7;file://host/home/me/My%20Files\x07"),
+            [TerminalAction::SetWorkingDirectory("/home/me/My Files".to_string())]
+        );
+        assert_eq!(
+            parser.feed(b"\x1b]7;file:///tmp\x1b\\"),
+            [TerminalAction::SetWorkingDirectory("/tmp".to_string())]
+        );
+        assert!(parser.feed(b"\x1b]7;https://example.com/\x07").is_empty());
+    }
+
+    #[test]
+    fn test_cursor_style_sequences() {
+        let mut parser = TerminalParser::new();
+        let style = |shape, blink| TerminalAction::SetCursorStyle(Some(CursorStyle { shape, blink }));
+        assert_eq!(parser.feed(b"\x1b[2 q"), [style(CursorShape::Block, false)]);
+        assert_eq!(parser.feed(b"\x1b[3 q"), [style(CursorShape::Underline, true)]);
+        assert_eq!(parser.feed(b"\x1b[6 q"), [style(CursorShape::Beam, false)]);
+        assert_eq!(parser.feed(b"\x1b[ q"), [TerminalAction::SetCursorStyle(None)]);
+        // Without the space, `q` is not DECSCUSR
+        assert!(!parser.feed(b"\x1b[5q").iter().any(|a| matches!(a, TerminalAction::SetCursorStyle(_))));
+        assert_eq!(parser.feed(b"\x1b[?25l"), [TerminalAction::HideCursor]);
+        assert_eq!(parser.feed(b"\x1b[?25h"), [TerminalAction::ShowCursor]);
+    }
 }
\ No newline at end of file
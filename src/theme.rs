@@ -0,0 +1,317 @@
+use crate::terminal::TerminalCell;
+use crate::terminal_parser::Color;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ThemeError {
+    #[error("{key}: expected a #rrggbb color, got {value:?}")]
+    Color { key: String, value: String },
+    #[error("unknown theme color {0:?}")]
+    UnknownKey(String),
+}
+
+/// Names of the 16 ANSI colors as `[theme]` keys, in palette order
+pub const ANSI_NAMES: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "bright_black",
+    "bright_red",
+    "bright_green",
+    "bright_yellow",
+    "bright_blue",
+    "bright_magenta",
+    "bright_cyan",
+    "bright_white",
+];
+
+/// Colors for rendered answers: headings by level, links, inline code,
+/// and muted text such as table borders and link URLs
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Accents {
+    pub heading1: [f32; 4],
+    pub heading2: [f32; 4],
+    pub heading: [f32; 4],
+    pub link: [f32; 4],
+    pub code: [f32; 4],
+    pub muted: [f32; 4],
+}
+
+/// Every color the grid and the UI draw with
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Theme {
+    pub ansi: [[f32; 4]; 16],
+    pub foreground: [f32; 4],
+    pub background: [f32; 4],
+    pub cursor: [f32; 4],
+    pub selection: [f32; 4],
+    pub accents: Accents,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            ansi: [
+                rgb(0x000000),
+                rgb(0xcd3131),
+                rgb(0x0dbc79),
+                rgb(0xe5e510),
+                rgb(0x2472c8),
+                rgb(0xbc3fbc),
+                rgb(0x11a8cd),
+                rgb(0xe5e5e5),
+                rgb(0x666666),
+                rgb(0xf14c4c),
+                rgb(0x23d18b),
+                rgb(0xf5f543),
+                rgb(0x3b8eea),
+                rgb(0xd670d6),
+                rgb(0x29b8db),
+                rgb(0xffffff),
+            ],
+            foreground: rgb(0xffffff),
+            background: rgb(0x000000),
+            cursor: rgb(0xffffff),
+            selection: rgb(0x264f78),
+            accents: Accents {
+                heading1: rgb(0xffcc33),
+                heading2: rgb(0xccffcc),
+                heading: rgb(0xe6e6ff),
+                link: rgb(0x6699ff),
+                code: rgb(0xffcc99),
+                muted: rgb(0x999999),
+            },
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            ansi: [
+                rgb(0x000000),
+                rgb(0xcd3131),
+                rgb(0x00bc00),
+                rgb(0x949800),
+                rgb(0x0451a5),
+                rgb(0xbc05bc),
+                rgb(0x0598bc),
+                rgb(0x555555),
+                rgb(0x666666),
+                rgb(0xcd3131),
+                rgb(0x14ce14),
+                rgb(0xb5ba00),
+                rgb(0x0451a5),
+                rgb(0xbc05bc),
+                rgb(0x0598bc),
+                rgb(0xa5a5a5),
+            ],
+            foreground: rgb(0x1e1e1e),
+            background: [0.98, 0.98, 0.98, 1.0],
+            cursor: rgb(0x1e1e1e),
+            selection: rgb(0xadd6ff),
+            accents: Accents {
+                heading1: rgb(0xa05a00),
+                heading2: rgb(0x2e7d32),
+                heading: rgb(0x3949ab),
+                link: rgb(0x0451a5),
+                code: rgb(0x9c4221),
+                muted: rgb(0x767676),
+            },
+        }
+    }
+
+    /// Built-in theme by name; anything but "light" is dark
+    pub fn named(name: &str) -> Self {
+        match name {
+            "light" => Self::light(),
+            _ => Self::dark(),
+        }
+    }
+
+    /// Replace colors named in a `[theme]` table
+    pub fn with_overrides(
+        mut self,
+        overrides: &BTreeMap<String, String>,
+    ) -> Result<Self, ThemeError> {
+        for (key, value) in overrides {
+            *self.slot(key)? = parse_hex(value).ok_or_else(|| ThemeError::Color {
+                key: key.clone(),
+                value: value.clone(),
+            })?;
+        }
+        Ok(self)
+    }
+
+    fn slot(&mut self, key: &str) -> Result<&mut [f32; 4], ThemeError> {
+        if let Some(index) = ANSI_NAMES.iter().position(|name| *name == key) {
+            return Ok(&mut self.ansi[index]);
+        }
+        Ok(match key {
+            "foreground" => &mut self.foreground,
+            "background" => &mut self.background,
+            "cursor" => &mut self.cursor,
+            "selection" => &mut self.selection,
+            "heading1" => &mut self.accents.heading1,
+            "heading2" => &mut self.accents.heading2,
+            "heading" => &mut self.accents.heading,
+            "link" => &mut self.accents.link,
+            "code" => &mut self.accents.code,
+            "muted" => &mut self.accents.muted,
+            _ => return Err(ThemeError::UnknownKey(key.to_string())),
+        })
+    }
+
+    /// RGBA for an SGR color. Indexed colors 0-15 come from the palette,
+    /// 16-255 are the standard color cube and gray ramp.
+    pub fn color(&self, color: &Color, foreground: bool) -> [f32; 4] {
+        let index = match color {
+            Color::Black => 0,
+            Color::Red => 1,
+            Color::Green => 2,
+            Color::Yellow => 3,
+            Color::Blue => 4,
+            Color::Magenta => 5,
+            Color::Cyan => 6,
+            Color::White => 7,
+            Color::BrightBlack => 8,
+            Color::BrightRed => 9,
+            Color::BrightGreen => 10,
+            Color::BrightYellow => 11,
+            Color::BrightBlue => 12,
+            Color::BrightMagenta => 13,
+            Color::BrightCyan => 14,
+            Color::BrightWhite => 15,
+            Color::Color256(n) => *n,
+            Color::TrueColor(r, g, b) => {
+                return [*r as f32 / 255.0, *g as f32 / 255.0, *b as f32 / 255.0, 1.0];
+            }
+            Color::Default if foreground => return self.foreground,
+            Color::Default => return self.background,
+        };
+        match index {
+            0..=15 => self.ansi[index as usize],
+            16..=231 => {
+                let n = index - 16;
+                let level = |v: u8| {
+                    if v == 0 {
+                        0.0
+                    } else {
+                        (55.0 + v as f32 * 40.0) / 255.0
+                    }
+                };
+                [level(n / 36), level(n / 6 % 6), level(n % 6), 1.0]
+            }
+            _ => {
+                let gray = (8.0 + (index - 232) as f32 * 10.0) / 255.0;
+                [gray, gray, gray, 1.0]
+            }
+        }
+    }
+
+    /// Cell colors written under `self` as they would have been under
+    /// `theme`. Default and palette colors move; true colors stay.
+    pub fn recolor(&self, theme: &Theme, cell: &mut TerminalCell) {
+        cell.foreground = self.translate(theme, cell.foreground, self.foreground, theme.foreground);
+        cell.background = self.translate(theme, cell.background, self.background, theme.background);
+    }
+
+    fn translate(
+        &self,
+        theme: &Theme,
+        color: [f32; 4],
+        default: [f32; 4],
+        new_default: [f32; 4],
+    ) -> [f32; 4] {
+        if color == default {
+            return new_default;
+        }
+        match self.ansi.iter().position(|ansi| *ansi == color) {
+            Some(index) => theme.ansi[index],
+            None => color,
+        }
+    }
+}
+
+/// `#rrggbb` (the `#` is optional) as opaque RGBA
+pub fn parse_hex(text: &str) -> Option<[f32; 4]> {
+    let hex = text.trim().strip_prefix('#').unwrap_or(text.trim());
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok().map(rgb)
+}
+
+fn rgb(value: u32) -> [f32; 4] {
+    let channel = |shift: u32| ((value >> shift) & 0xff) as f32 / 255.0;
+    [channel(16), channel(8), channel(0), 1.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_and_palette_lookup() {
+        let overrides = BTreeMap::from([
+            ("red".to_string(), "#ff0000".to_string()),
+            ("background".to_string(), "102030".to_string()),
+        ]);
+        let theme = Theme::dark().with_overrides(&overrides).unwrap();
+        assert_eq!(theme.color(&Color::Red, true), [1.0, 0.0, 0.0, 1.0]);
+        // 38;5;1 is the same palette entry
+        assert_eq!(theme.color(&Color::Color256(1), true), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(
+            theme.color(&Color::Default, false),
+            parse_hex("#102030").unwrap()
+        );
+        assert_eq!(
+            theme.color(&Color::Color256(196), true),
+            [1.0, 0.0, 0.0, 1.0]
+        );
+        assert_eq!(theme.color(&Color::Color256(232), true)[0], 8.0 / 255.0);
+
+        let bad = BTreeMap::from([("red".to_string(), "crimson".to_string())]);
+        assert!(matches!(
+            Theme::dark().with_overrides(&bad),
+            Err(ThemeError::Color { .. })
+        ));
+        let unknown = BTreeMap::from([("purple".to_string(), "#800080".to_string())]);
+        assert_eq!(
+            Theme::dark().with_overrides(&unknown),
+            Err(ThemeError::UnknownKey("purple".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_recolor_moves_palette_colors_only() {
+        let dark = Theme::dark();
+        let light = Theme::light();
+        let mut cell = TerminalCell {
+            foreground: dark.ansi[1],
+            background: dark.background,
+            ..TerminalCell::default()
+        };
+        dark.recolor(&light, &mut cell);
+        assert_eq!(cell.foreground, light.ansi[1]);
+        assert_eq!(cell.background, light.background);
+
+        let mut true_color = TerminalCell {
+            foreground: [0.3, 0.2, 0.1, 1.0],
+            ..cell.clone()
+        };
+        light.recolor(&dark, &mut true_color);
+        assert_eq!(true_color.foreground, [0.3, 0.2, 0.1, 1.0]);
+    }
+}
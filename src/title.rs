@@ -0,0 +1,98 @@
+//! Window and tab titles built from what a pane reports: the title its
+//! program set (OSC 0/2), its directory (OSC 7) and the foreground program
+
+use std::path::Path;
+
+/// Longest title shown; anything longer is cut and ends in an ellipsis
+pub const MAX_TITLE_CHARS: usize = 100;
+
+/// What `ui.title_format` can refer to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TitleFields {
+    /// Set by the program, else the foreground program's name
+    pub title: String,
+    /// Working directory, with the home directory as `~`
+    pub cwd: Option<String>,
+    pub process: Option<String>,
+}
+
+/// `format` with `{title}`, `{cwd}` and `{process}` filled in. Brackets
+/// left empty by a missing field are dropped.
+pub fn render(format: &str, fields: &TitleFields) -> String {
+    let text = format
+        .replace("{title}", &fields.title)
+        .replace("{cwd}", fields.cwd.as_deref().unwrap_or_default())
+        .replace("{process}", fields.process.as_deref().unwrap_or_default())
+        .replace("()", "")
+        .replace("[]", "");
+    sanitize(&text, MAX_TITLE_CHARS)
+}
+
+/// Control characters become spaces and runs of whitespace one space, so a
+/// program cannot break the title bar; over `max_chars` the end is cut to
+/// an ellipsis
+pub fn sanitize(title: &str, max_chars: usize) -> String {
+    let clean = title
+        .split(|c: char| c.is_control() || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if clean.chars().count() <= max_chars {
+        return clean;
+    }
+    let mut cut: String = clean.chars().take(max_chars.saturating_sub(1)).collect();
+    cut.truncate(cut.trim_end().len());
+    cut.push('…');
+    cut
+}
+
+/// `path` with a leading `home` shown as `~`
+pub fn abbreviate_home(path: &str, home: Option<&Path>) -> String {
+    let Some(home) = home.and_then(|home| home.to_str()).filter(|home| !home.is_empty() && *home != "/") else {
+        return path.to_string();
+    };
+    match path.strip_prefix(home) {
+        Some("") => "~".to_string(),
+        Some(rest) if rest.starts_with('/') => format!("~{}", rest),
+        _ => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_strips_controls_and_truncates() {
+        assert_eq!(sanitize("vim\x1b[31m main.rs\x07\n", 80), "vim [31m main.rs");
+        assert_eq!(sanitize("  a\t\tb  ", 80), "a b");
+        assert_eq!(sanitize("abcdefghij", 10), "abcdefghij");
+        assert_eq!(sanitize("abcdefghijk", 8), "abcdefg…");
+        assert_eq!(sanitize("abcdef ghij", 8), "abcdef…");
+        assert_eq!(sanitize(&"é".repeat(200), 5), "éééé…");
+    }
+
+    #[test]
+    fn test_render_title_format() {
+        let fields = TitleFields {
+            title: "vim".to_string(),
+            cwd: Some("~/src".to_string()),
+            process: Some("vim".to_string()),
+        };
+        assert_eq!(render("{title} ({cwd})", &fields), "vim (~/src)");
+        assert_eq!(render("{process} [{cwd}]", &fields), "vim [~/src]");
+
+        let no_cwd = TitleFields { cwd: None, ..fields };
+        assert_eq!(render("{title} ({cwd})", &no_cwd), "vim");
+        assert_eq!(render("{title}\x1b", &no_cwd), "vim");
+    }
+
+    #[test]
+    fn test_abbreviate_home() {
+        let home = Some(Path::new("/home/me"));
+        assert_eq!(abbreviate_home("/home/me", home), "~");
+        assert_eq!(abbreviate_home("/home/me/src", home), "~/src");
+        assert_eq!(abbreviate_home("/home/meg", home), "/home/meg");
+        assert_eq!(abbreviate_home("/tmp", None), "/tmp");
+    }
+}
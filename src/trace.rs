@@ -0,0 +1,566 @@
+use crate::output_scheduler::OutputSink;
+use crate::terminal::TerminalState;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TraceError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid trace record on line {line}: {source}")]
+    Record {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error("Not a ferroterm trace")]
+    NotATrace,
+}
+
+const FORMAT: &str = "ferroterm-trace";
+
+/// Bumped when existing events change meaning. New event types and fields
+/// do not need a bump: readers skip what they do not know.
+pub const TRACE_VERSION: u32 = 1;
+
+/// First line of a trace: what the session looked like when it started
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceHeader {
+    pub format: String,
+    pub version: u32,
+    /// Version of ferroterm that recorded it
+    pub recorded_by: String,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub font_family: String,
+    #[serde(default)]
+    pub font_size: u32,
+    #[serde(default)]
+    pub cell_width: u32,
+    #[serde(default)]
+    pub cell_height: u32,
+}
+
+impl TraceHeader {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            format: FORMAT.to_string(),
+            version: TRACE_VERSION,
+            recorded_by: env!("CARGO_PKG_VERSION").to_string(),
+            width,
+            height,
+            font_family: String::new(),
+            font_size: 0,
+            cell_width: 0,
+            cell_height: 0,
+        }
+    }
+}
+
+/// Timestamps are microseconds since recording started. Replay never
+/// sleeps on them; they only order events and show when things happened.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// Bytes read from the PTY, in the chunks the parser saw them
+    Output {
+        t: u64,
+        #[serde(with = "bytes_b64")]
+        data: Vec<u8>,
+    },
+    /// Bytes written to the PTY: keystrokes, pastes and query replies
+    Input {
+        t: u64,
+        #[serde(with = "bytes_b64")]
+        data: Vec<u8>,
+    },
+    Resize {
+        t: u64,
+        width: u32,
+        height: u32,
+    },
+    /// A point to compare at, with the grid as recorded
+    Checkpoint {
+        t: u64,
+        label: String,
+        #[serde(default)]
+        snapshot: Option<GridSnapshot>,
+    },
+    /// An event type added by a later version
+    #[serde(other)]
+    Unknown,
+}
+
+mod bytes_b64 {
+    use base64::{Engine as _, engine::general_purpose};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&general_purpose::STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        general_purpose::STANDARD
+            .decode(text)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Grid text, cursor and a checksum of every cell attribute
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GridSnapshot {
+    pub width: u32,
+    pub height: u32,
+    pub cursor: [u32; 2],
+    /// Trailing blanks trimmed
+    pub rows: Vec<String>,
+    /// CRC32 over characters, colours and attributes, so a change that
+    /// only affects styling is still caught
+    pub cells_crc: u32,
+}
+
+impl GridSnapshot {
+    pub fn capture(terminal: &TerminalState) -> Self {
+        let mut hasher = crc32fast::Hasher::new();
+        for cell in &terminal.cells {
+            hasher.update(&(cell.character as u32).to_le_bytes());
+            for channel in cell.foreground.iter().chain(&cell.background) {
+                hasher.update(&channel.to_bits().to_le_bytes());
+            }
+            let flags = [
+                cell.bold,
+                cell.italic,
+                cell.underline,
+                cell.strikethrough,
+                cell.dim,
+                cell.reverse,
+                cell.blink,
+                cell.wide,
+            ]
+            .iter()
+            .enumerate()
+            .fold(0u8, |flags, (bit, set)| flags | ((*set as u8) << bit));
+            hasher.update(&[flags]);
+        }
+        let rows = terminal
+            .cells
+            .chunks(terminal.width.max(1) as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|cell| cell.character)
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect();
+        Self {
+            width: terminal.width,
+            height: terminal.height,
+            cursor: [terminal.cursor_x, terminal.cursor_y],
+            rows,
+            cells_crc: hasher.finalize(),
+        }
+    }
+
+    /// The first way `actual` differs from this snapshot
+    pub fn diff(&self, actual: &GridSnapshot) -> Option<String> {
+        if (self.width, self.height) != (actual.width, actual.height) {
+            return Some(format!(
+                "grid size {}x{}, expected {}x{}",
+                actual.width, actual.height, self.width, self.height
+            ));
+        }
+        let rows = self.rows.iter().zip(&actual.rows).enumerate();
+        for (index, (expected, actual)) in rows {
+            if expected != actual {
+                return Some(format!(
+                    "row {}: {:?}, expected {:?}",
+                    index, actual, expected
+                ));
+            }
+        }
+        if self.cursor != actual.cursor {
+            return Some(format!(
+                "cursor at {:?}, expected {:?}",
+                actual.cursor, self.cursor
+            ));
+        }
+        if self.cells_crc != actual.cells_crc {
+            return Some("same text, different colours or attributes".to_string());
+        }
+        None
+    }
+}
+
+/// A trace read back from disk
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub header: TraceHeader,
+    pub events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    pub fn load(path: &Path) -> Result<Self, TraceError> {
+        Self::read(io::BufReader::new(File::open(path)?))
+    }
+
+    /// A recording cut off mid-line, e.g. by a crash, keeps every complete
+    /// event before it
+    pub fn read(reader: impl BufRead) -> Result<Self, TraceError> {
+        let mut lines = reader.lines().enumerate().peekable();
+        let (_, first) = lines.next().ok_or(TraceError::NotATrace)?;
+        let header: TraceHeader =
+            serde_json::from_str(&first?).map_err(|_| TraceError::NotATrace)?;
+        if header.format != FORMAT {
+            return Err(TraceError::NotATrace);
+        }
+        if header.version > TRACE_VERSION {
+            tracing::warn!(
+                "Trace version {} is newer than {}; unknown events are skipped",
+                header.version,
+                TRACE_VERSION
+            );
+        }
+
+        let mut events = Vec::new();
+        while let Some((index, line)) = lines.next() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(event) => events.push(event),
+                Err(_) if lines.peek().is_none() => break,
+                Err(source) => {
+                    return Err(TraceError::Record {
+                        line: index + 1,
+                        source,
+                    });
+                }
+            }
+        }
+        Ok(Self { header, events })
+    }
+}
+
+/// Writes a trace as the session runs, one JSON line per event, flushed
+/// as it goes so a crash keeps everything up to it
+pub struct TraceRecorder {
+    out: Box<dyn Write + Send>,
+    start: Instant,
+    outputs: usize,
+    /// Snapshot after this many output chunks, so replay can narrow down
+    /// where things went wrong; 0 only snapshots at explicit checkpoints
+    snapshot_every: usize,
+}
+
+impl TraceRecorder {
+    pub const DEFAULT_SNAPSHOT_EVERY: usize = 64;
+
+    pub fn create(path: &Path, header: &TraceHeader) -> Result<Self, TraceError> {
+        Self::new(Box::new(BufWriter::new(File::create(path)?)), header)
+    }
+
+    pub fn new(out: Box<dyn Write + Send>, header: &TraceHeader) -> Result<Self, TraceError> {
+        let mut recorder = Self {
+            out,
+            start: Instant::now(),
+            outputs: 0,
+            snapshot_every: Self::DEFAULT_SNAPSHOT_EVERY,
+        };
+        recorder.write_line(header)?;
+        Ok(recorder)
+    }
+
+    pub fn with_snapshot_every(mut self, outputs: usize) -> Self {
+        self.snapshot_every = outputs;
+        self
+    }
+
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> Result<(), TraceError> {
+        let line = serde_json::to_string(value)
+            .map_err(|source| TraceError::Record { line: 0, source })?;
+        writeln!(self.out, "{}", line)?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    pub fn input(&mut self, data: &[u8]) -> Result<(), TraceError> {
+        let event = TraceEvent::Input {
+            t: self.now(),
+            data: data.to_vec(),
+        };
+        self.write_line(&event)
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), TraceError> {
+        let event = TraceEvent::Resize {
+            t: self.now(),
+            width,
+            height,
+        };
+        self.write_line(&event)
+    }
+
+    /// Record `data`, feed it to `terminal`, and snapshot when due
+    pub fn output(&mut self, terminal: &mut TerminalState, data: &[u8]) -> Result<(), TraceError> {
+        let event = TraceEvent::Output {
+            t: self.now(),
+            data: data.to_vec(),
+        };
+        self.write_line(&event)?;
+        terminal.feed_bytes(data);
+        self.outputs += 1;
+        if self.snapshot_every > 0 && self.outputs.is_multiple_of(self.snapshot_every) {
+            let label = format!("output {}", self.outputs);
+            self.checkpoint(&label, terminal)?;
+        }
+        Ok(())
+    }
+
+    pub fn checkpoint(&mut self, label: &str, terminal: &TerminalState) -> Result<(), TraceError> {
+        let event = TraceEvent::Checkpoint {
+            t: self.now(),
+            label: label.to_string(),
+            snapshot: Some(GridSnapshot::capture(terminal)),
+        };
+        self.write_line(&event)
+    }
+}
+
+/// Feeds output to the terminal through a recorder. A write failure stops
+/// the recording but never the terminal.
+pub struct RecordingSink<'a> {
+    pub terminal: &'a mut TerminalState,
+    pub recorder: &'a mut Option<TraceRecorder>,
+}
+
+impl OutputSink for RecordingSink<'_> {
+    fn consume(&mut self, bytes: &[u8]) {
+        match self.recorder.as_mut() {
+            Some(recorder) => {
+                if let Err(e) = recorder.output(self.terminal, bytes) {
+                    tracing::error!("Trace recording stopped: {}", e);
+                    *self.recorder = None;
+                }
+            }
+            None => self.terminal.feed_bytes(bytes),
+        }
+    }
+}
+
+/// Grid state after an event during replay
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplaySnapshot {
+    /// Index into the trace's events
+    pub event: usize,
+    pub label: String,
+    pub snapshot: GridSnapshot,
+}
+
+/// First checkpoint whose replayed grid differs from the recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub event: usize,
+    pub label: String,
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub events: usize,
+    /// Recorded checkpoints that matched before any divergence
+    pub matched: usize,
+    pub unknown_events: usize,
+    pub snapshots: Vec<ReplaySnapshot>,
+    pub divergence: Option<Divergence>,
+    pub final_grid: Option<GridSnapshot>,
+}
+
+impl ReplayReport {
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} events replayed, {} checkpoint(s) matched",
+            self.events, self.matched
+        );
+        if self.unknown_events > 0 {
+            summary.push_str(&format!(
+                ", {} unknown event(s) skipped",
+                self.unknown_events
+            ));
+        }
+        if let Some(divergence) = &self.divergence {
+            summary.push_str(&format!(
+                "\nFirst divergence at event {} ({}): {}",
+                divergence.event, divergence.label, divergence.detail
+            ));
+        }
+        summary
+    }
+}
+
+/// Run a trace through a fresh terminal, with no PTY and no real clock.
+/// `feed` parses output into the terminal; tests substitute it to check
+/// that a parser change is caught. Stops at the first divergence.
+pub fn replay_with(
+    trace: &Trace,
+    every_event: bool,
+    mut feed: impl FnMut(&mut TerminalState, &[u8]),
+) -> ReplayReport {
+    let mut terminal = TerminalState::new(trace.header.width, trace.header.height);
+    let mut report = ReplayReport::default();
+    for (index, event) in trace.events.iter().enumerate() {
+        report.events += 1;
+        let label = match event {
+            TraceEvent::Output { data, .. } => {
+                feed(&mut terminal, data);
+                "output".to_string()
+            }
+            // Nothing echoes without a PTY; kept for context in reports
+            TraceEvent::Input { .. } => "input".to_string(),
+            TraceEvent::Resize { width, height, .. } => {
+                terminal.resize(*width, *height);
+                format!("resize {}x{}", width, height)
+            }
+            TraceEvent::Checkpoint {
+                label, snapshot, ..
+            } => {
+                let actual = GridSnapshot::capture(&terminal);
+                if let Some(expected) = snapshot {
+                    match expected.diff(&actual) {
+                        Some(detail) => {
+                            report.divergence = Some(Divergence {
+                                event: index,
+                                label: label.clone(),
+                                detail,
+                            });
+                            report.final_grid = Some(actual);
+                            return report;
+                        }
+                        None => report.matched += 1,
+                    }
+                }
+                if !every_event {
+                    report.snapshots.push(ReplaySnapshot {
+                        event: index,
+                        label: label.clone(),
+                        snapshot: actual,
+                    });
+                }
+                label.clone()
+            }
+            TraceEvent::Unknown => {
+                report.unknown_events += 1;
+                continue;
+            }
+        };
+        if every_event {
+            report.snapshots.push(ReplaySnapshot {
+                event: index,
+                label,
+                snapshot: GridSnapshot::capture(&terminal),
+            });
+        }
+    }
+    report.final_grid = Some(GridSnapshot::capture(&terminal));
+    report
+}
+
+pub fn replay(trace: &Trace, every_event: bool) -> ReplayReport {
+    replay_with(trace, every_event, |terminal, data| {
+        terminal.feed_bytes(data)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A writer the test can read back after the recorder is done
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record_session() -> Vec<u8> {
+        let buffer = SharedBuffer::default();
+        let mut terminal = TerminalState::new(20, 5);
+        let mut recorder = TraceRecorder::new(Box::new(buffer.clone()), &TraceHeader::new(20, 5))
+            .unwrap()
+            .with_snapshot_every(2);
+        for chunk in [
+            &b"$ ls\r\n"[..],
+            b"\x1b[1;32mbin\x1b[0m  src\r\n",
+            b"$ ",
+            b"echo hi\r\nhi\r\n$ ",
+        ] {
+            recorder.output(&mut terminal, chunk).unwrap();
+        }
+        recorder.input(b"clear\r").unwrap();
+        recorder.resize(10, 4).unwrap();
+        terminal.resize(10, 4);
+        recorder.output(&mut terminal, b"\x1b[2J\x1b[H$ ").unwrap();
+        recorder.checkpoint("end", &terminal).unwrap();
+        buffer.0.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_record_and_replay_is_identical() {
+        let bytes = record_session();
+        let trace = Trace::read(&bytes[..]).unwrap();
+        assert_eq!(trace.header.version, TRACE_VERSION);
+
+        let report = replay(&trace, false);
+        assert_eq!(report.divergence, None);
+        assert_eq!(report.matched, 3);
+        assert_eq!(report.final_grid.unwrap().rows[0], "$");
+
+        // Unknown events from a newer version and a torn last line are
+        // skipped rather than failing the whole trace
+        let mut extended = bytes.clone();
+        extended
+            .extend_from_slice(b"{\"type\":\"focus\",\"t\":1,\"focused\":true}\n{\"type\":\"out");
+        let report = replay(&Trace::read(&extended[..]).unwrap(), true);
+        assert_eq!(report.divergence, None);
+        assert_eq!(report.unknown_events, 1);
+        assert_eq!(report.snapshots.len(), trace.events.len());
+    }
+
+    #[test]
+    fn test_parser_change_is_reported_as_divergence() {
+        let trace = Trace::read(&record_session()[..]).unwrap();
+        // A "regression" that drops SGR resets: the text is the same but
+        // the colours are not
+        let report = replay_with(&trace, false, |terminal, data| {
+            let text = String::from_utf8_lossy(data).replace("\x1b[0m", "");
+            terminal.feed_bytes(text.as_bytes());
+        });
+        assert!(report.summary().contains("First divergence at event 2"));
+        let divergence = report.divergence.expect("styling change is detected");
+        assert_eq!(divergence.label, "output 2");
+        assert_eq!(
+            divergence.detail,
+            "same text, different colours or attributes"
+        );
+        assert_eq!(report.matched, 0);
+    }
+}
@@ -2,13 +2,19 @@
 use nix::sys::signal::{self, Signal};
 use nix::sys::wait::{self, WaitStatus};
 use nix::unistd::{self, ForkResult, Pid};
-use std::collections::HashMap;
+use crate::output_records::{OutputRecord, OutputRecords};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::CString;
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::time::{Duration, Instant};
+use bytes::{Bytes, BytesMut};
 use thiserror::Error;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc};
 use tokio::time::timeout;
 use tracing::{debug, error, info, warn};
 
@@ -32,6 +38,42 @@ pub enum TtyError {
     ProcessDied { pid: i32 },
     #[error("Timeout: operation took longer than {timeout_ms}ms")]
     Timeout { timeout_ms: u64 },
+    #[error("Unknown shell exit action: {0}")]
+    UnknownExitAction(String),
+    #[error("{shell}: {reason}")]
+    ShellNotFound { shell: String, reason: String },
+}
+
+/// Lifecycle changes of a PTY's child process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PtyEvent {
+    /// `code` is `None` when a signal killed the child
+    Exited { pty_id: u64, code: Option<i32> },
+}
+
+/// What the window does when its shell exits (`ui.on_shell_exit`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnShellExit {
+    /// Close the tab, and the window with its last tab
+    #[default]
+    Close,
+    /// Start a new shell in the same tab
+    Restart,
+    /// Keep the output on screen with an exit notice
+    Hold,
+}
+
+impl FromStr for OnShellExit {
+    type Err = TtyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "close" => Ok(OnShellExit::Close),
+            "restart" => Ok(OnShellExit::Restart),
+            "hold" => Ok(OnShellExit::Hold),
+            _ => Err(TtyError::UnknownExitAction(s.to_string())),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -41,12 +83,22 @@ pub enum TerminalMode {
     AltScreen,
 }
 
+/// Environment every shell starts with unless `PtyConfig::env` says
+/// otherwise
+pub const DEFAULT_ENV: &[(&str, &str)] = &[("TERM", "xterm-256color"), ("COLORTERM", "truecolor")];
+
 #[derive(Debug, Clone)]
 pub struct PtyConfig {
+    /// Program to run, as a path or a name looked up in `PATH`
     pub shell: String,
     pub args: Vec<String>,
+    /// Set on top of the inherited environment and `DEFAULT_ENV`
     pub env: HashMap<String, String>,
-    pub working_dir: Option<String>,
+    /// Directory to start in; the current one when unset or missing
+    pub cwd: Option<PathBuf>,
+    /// Start a login shell: argv[0] is the shell's name with a leading
+    /// `-`, as login(1) does on every Unix
+    pub login_shell: bool,
     pub rows: u16,
     pub cols: u16,
 }
@@ -56,14 +108,99 @@ impl Default for PtyConfig {
         Self {
             shell: std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string()),
             args: vec![],
-            env: std::env::vars().collect(),
-            working_dir: None,
+            env: HashMap::new(),
+            cwd: None,
+            login_shell: false,
             rows: 24,
             cols: 80,
         }
     }
 }
 
+/// Everything the child needs to exec the shell, prepared before the fork
+/// so a missing shell or a bad argument is reported to the caller
+#[derive(Debug)]
+struct ShellCommand {
+    program: CString,
+    argv: Vec<CString>,
+    env: Vec<(CString, CString)>,
+    cwd: Option<CString>,
+}
+
+impl ShellCommand {
+    fn from_config(config: &PtyConfig) -> Result<Self, TtyError> {
+        let program = find_program(&config.shell)?;
+        let name = Path::new(&config.shell)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| config.shell.clone());
+        let argv0 = if config.login_shell { format!("-{}", name) } else { config.shell.clone() };
+
+        let mut env: Vec<(&str, &str)> = DEFAULT_ENV
+            .iter()
+            .filter(|(key, _)| !config.env.contains_key(*key))
+            .copied()
+            .collect();
+        env.extend(config.env.iter().map(|(key, value)| (key.as_str(), value.as_str())));
+
+        let cwd = match &config.cwd {
+            Some(dir) if dir.is_dir() => Some(c_string(&dir.to_string_lossy())?),
+            Some(dir) => {
+                warn!("Starting shell in the current directory, {} is not a directory", dir.display());
+                None
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            program: c_string(&program.to_string_lossy())?,
+            argv: std::iter::once(argv0.as_str())
+                .chain(config.args.iter().map(String::as_str))
+                .map(c_string)
+                .collect::<Result<_, _>>()?,
+            env: env
+                .into_iter()
+                .map(|(key, value)| Ok((c_string(key)?, c_string(value)?)))
+                .collect::<Result<_, TtyError>>()?,
+            cwd,
+        })
+    }
+}
+
+fn c_string(s: &str) -> Result<CString, TtyError> {
+    CString::new(s).map_err(|_| TtyError::PtyCreation(format!("{:?} contains a NUL byte", s)))
+}
+
+/// `shell` as an executable path, searching `PATH` for a bare name
+fn find_program(shell: &str) -> Result<PathBuf, TtyError> {
+    let not_found = |reason: &str| TtyError::ShellNotFound {
+        shell: shell.to_string(),
+        reason: reason.to_string(),
+    };
+    let executable = |path: &Path| {
+        path.metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    };
+    if shell.is_empty() {
+        return Err(not_found("no shell configured"));
+    }
+    if shell.contains('/') {
+        let path = PathBuf::from(shell);
+        return match path.metadata() {
+            Err(e) => Err(not_found(&e.to_string())),
+            Ok(_) if !executable(&path) => Err(not_found("not an executable file")),
+            Ok(_) => Ok(path),
+        };
+    }
+    std::env::var_os("PATH")
+        .and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(shell))
+                .find(|path| executable(path))
+        })
+        .ok_or_else(|| not_found("not found in PATH"))
+}
+
 #[derive(Debug)]
 pub struct PtySession {
     pub id: u64,
@@ -74,6 +211,80 @@ pub struct PtySession {
     pub bytes_read: AtomicU64,
     pub bytes_written: AtomicU64,
     pub is_alive: AtomicBool,
+    pub outgoing: PtyOutgoing,
+    /// Output split into prompt, echo, output and completion records
+    pub records: Mutex<OutputRecords>,
+    /// Bumped by each `subscribe_output`, retiring the previous reader
+    output_generation: AtomicU64,
+}
+
+/// Most bytes written per batch, so a large paste is fed to the PTY in
+/// pieces and a ^C queued meanwhile goes out before the rest
+const WRITE_CHUNK: usize = 16 * 1024;
+
+/// Bytes waiting to be written to a PTY. Pushing only copies into a buffer
+/// sized up front, so keystrokes can be queued from the UI thread without
+/// allocating or spawning; a persistent writer thread per session drains it.
+#[derive(Debug)]
+pub struct PtyOutgoing {
+    queue: Mutex<VecDeque<u8>>,
+    ready: Condvar,
+    closed: AtomicBool,
+}
+
+impl Default for PtyOutgoing {
+    fn default() -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::with_capacity(4096)),
+            ready: Condvar::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl PtyOutgoing {
+    /// Queue bytes for the writer; false once the session has closed
+    pub fn push(&self, data: &[u8]) -> bool {
+        if self.closed.load(Ordering::Acquire) {
+            return false;
+        }
+        self.queue.lock().unwrap().extend(data);
+        self.ready.notify_one();
+        true
+    }
+
+    /// Queue bytes ahead of anything already waiting, e.g. ^C during a flood
+    pub fn push_front(&self, data: &[u8]) -> bool {
+        if self.closed.load(Ordering::Acquire) {
+            return false;
+        }
+        let mut queue = self.queue.lock().unwrap();
+        for &byte in data.iter().rev() {
+            queue.push_front(byte);
+        }
+        self.ready.notify_one();
+        true
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.ready.notify_all();
+    }
+
+    /// Block until bytes are queued, then move up to `WRITE_CHUNK` of them
+    /// into `batch`. Returns false when closed.
+    fn next_batch(&self, batch: &mut Vec<u8>) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        while queue.is_empty() {
+            if self.closed.load(Ordering::Acquire) {
+                return false;
+            }
+            queue = self.ready.wait(queue).unwrap();
+        }
+        let take = queue.len().min(WRITE_CHUNK);
+        batch.extend(queue.drain(..take));
+        true
+    }
 }
 
 impl PtySession {
@@ -87,6 +298,9 @@ impl PtySession {
             bytes_read: AtomicU64::new(0),
             bytes_written: AtomicU64::new(0),
             is_alive: AtomicBool::new(true),
+            outgoing: PtyOutgoing::default(),
+            records: Mutex::new(OutputRecords::default()),
+            output_generation: AtomicU64::new(0),
         }
     }
 
@@ -131,6 +345,45 @@ impl PtySession {
 
     pub fn mark_dead(&self) {
         self.is_alive.store(false, Ordering::Relaxed);
+        self.outgoing.close();
+    }
+
+    /// True when a process other than the shell owns the terminal's foreground
+    pub fn has_foreground_job(&self) -> bool {
+        let pgrp = unsafe { libc::tcgetpgrp(self.master_fd) };
+        pgrp > 0 && pgrp != self.child_pid.as_raw()
+    }
+
+    /// Name of the program owning the terminal's foreground, e.g. "vim"
+    pub fn foreground_process(&self) -> Option<String> {
+        let pgrp = unsafe { libc::tcgetpgrp(self.master_fd) };
+        if pgrp <= 0 {
+            return None;
+        }
+        #[cfg(target_os = "linux")]
+        let name = std::fs::read_to_string(format!("/proc/{}/comm", pgrp)).ok()?;
+        #[cfg(not(target_os = "linux"))]
+        let name = {
+            let output = std::process::Command::new("ps")
+                .args(["-o", "comm=", "-p", &pgrp.to_string()])
+                .output()
+                .ok()?;
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        };
+        let name = name.trim();
+        let name = name.rsplit('/').next().unwrap_or(name);
+        (!name.is_empty()).then(|| name.to_string())
+    }
+
+    /// The shell's working directory, where the platform exposes it
+    pub fn shell_cwd(&self) -> Option<String> {
+        #[cfg(target_os = "linux")]
+        {
+            let path = std::fs::read_link(format!("/proc/{}/cwd", self.child_pid.as_raw())).ok()?;
+            Some(path.to_string_lossy().into_owned())
+        }
+        #[cfg(not(target_os = "linux"))]
+        None
     }
 
     pub fn get_stats(&self) -> (u64, u64, Duration) {
@@ -150,6 +403,174 @@ impl Drop for PtySession {
     }
 }
 
+/// Runs in the forked child: make the PTY its controlling terminal and
+/// exec the shell. Failures are written to the terminal, where the user
+/// sees them, before exiting with 127 like a shell that cannot find a
+/// command.
+fn exec_shell(master_fd: RawFd, command: &ShellCommand) -> ! {
+    let fail = |what: &[u8]| -> ! {
+        unsafe {
+            libc::write(2, b"ferroterm: ".as_ptr() as *const libc::c_void, 11);
+            libc::write(2, what.as_ptr() as *const libc::c_void, what.len());
+            libc::write(2, b"\r\n".as_ptr() as *const libc::c_void, 2);
+            libc::_exit(127)
+        }
+    };
+    unsafe {
+        if libc::setsid() == -1 {
+            libc::_exit(127);
+        }
+        let slave_name = libc::ptsname(master_fd);
+        if slave_name.is_null() {
+            libc::_exit(127);
+        }
+        let slave_fd = libc::open(slave_name, libc::O_RDWR);
+        if slave_fd == -1 {
+            libc::_exit(127);
+        }
+
+        // Redirect stdin, stdout, stderr to slave PTY
+        libc::dup2(slave_fd, 0);
+        libc::dup2(slave_fd, 1);
+        libc::dup2(slave_fd, 2);
+        libc::close(slave_fd);
+        libc::close(master_fd);
+
+        if let Some(dir) = &command.cwd
+            && libc::chdir(dir.as_ptr()) == -1
+        {
+            fail(b"cannot change to the start directory");
+        }
+        for (key, value) in &command.env {
+            libc::setenv(key.as_ptr(), value.as_ptr(), 1);
+        }
+
+        let mut argv: Vec<*const libc::c_char> = command.argv.iter().map(|arg| arg.as_ptr()).collect();
+        argv.push(std::ptr::null());
+        libc::execv(command.program.as_ptr(), argv.as_ptr());
+    }
+    fail(b"cannot run the shell")
+}
+
+/// Bytes asked of the kernel per read
+const READ_CHUNK: usize = 64 * 1024;
+/// Largest batch sent to an output subscriber. Reads coalesce up to this
+/// while the consumer is behind; past it the reader stops reading, so the
+/// kernel buffer fills and the child blocks on write.
+const OUTPUT_BATCH: usize = 1024 * 1024;
+/// Batches in flight to a subscriber; with `OUTPUT_BATCH` this bounds the
+/// memory a flood can take before the child is paused
+const OUTPUT_DEPTH: usize = 8;
+/// How long the reader waits for output before checking whether it is
+/// still wanted
+const READER_POLL: Duration = Duration::from_millis(100);
+
+/// Reads a PTY continuously and hands its output to one subscriber in
+/// batches. Whatever is readable right away joins the pending batch, and
+/// reads keep piling up there while the channel is full, so a flood reaches
+/// the consumer as a few large batches rather than one per read.
+fn run_output_reader(
+    session: Arc<PtySession>,
+    generation: u64,
+    tx: mpsc::Sender<Bytes>,
+    shutdown: Arc<AtomicBool>,
+    stats: Arc<Mutex<TtyStats>>,
+) {
+    let mut pending = BytesMut::with_capacity(READ_CHUNK);
+    // Whether the last read returned data, so more may be waiting
+    let mut flowing = false;
+    loop {
+        if tx.is_closed()
+            || shutdown.load(Ordering::Relaxed)
+            || session.output_generation.load(Ordering::Acquire) != generation
+        {
+            break;
+        }
+
+        let wait = match (pending.is_empty(), flowing) {
+            (true, _) => READER_POLL,
+            (false, true) => Duration::ZERO,
+            // A batch is held back for a full channel; retry soon
+            (false, false) => Duration::from_millis(1),
+        };
+        let mut pollfd = libc::pollfd {
+            fd: session.master_fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut pollfd, 1, wait.as_millis() as libc::c_int) };
+        if ready < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            error!("PTY {} poll failed: {}", session.id, err);
+            stats.lock().unwrap().errors += 1;
+            break;
+        }
+
+        let mut eof = false;
+        flowing = false;
+        if ready > 0 {
+            pending.reserve(READ_CHUNK);
+            let spare = pending.spare_capacity_mut();
+            let result = unsafe {
+                libc::read(
+                    session.master_fd,
+                    spare.as_mut_ptr() as *mut libc::c_void,
+                    spare.len().min(READ_CHUNK),
+                )
+            };
+            if result > 0 {
+                let start = pending.len();
+                let read = result as usize;
+                unsafe { pending.set_len(start + read) };
+                session.records.lock().unwrap().feed(&pending[start..]);
+                session.bytes_read.fetch_add(read as u64, Ordering::Relaxed);
+                stats.lock().unwrap().total_bytes_read += read as u64;
+                if pending.len() < OUTPUT_BATCH {
+                    flowing = true;
+                    continue;
+                }
+            } else if result < 0
+                && matches!(
+                    std::io::Error::last_os_error().raw_os_error(),
+                    Some(libc::EINTR) | Some(libc::EAGAIN)
+                )
+            {
+                continue;
+            } else {
+                // EIO once the child side has closed and been drained
+                eof = true;
+            }
+        } else if pending.is_empty() && !session.is_alive() {
+            break;
+        }
+
+        if pending.is_empty() {
+            if eof {
+                break;
+            }
+            continue;
+        }
+        match tx.try_reserve() {
+            Ok(permit) => permit.send(pending.split().freeze()),
+            Err(mpsc::error::TrySendError::Full(())) if eof || pending.len() >= OUTPUT_BATCH => {
+                // Backpressure: stop reading until the consumer takes this
+                if tx.blocking_send(pending.split().freeze()).is_err() {
+                    break;
+                }
+            }
+            Err(mpsc::error::TrySendError::Full(())) => {}
+            Err(mpsc::error::TrySendError::Closed(())) => break,
+        }
+        if eof {
+            break;
+        }
+    }
+    debug!("PTY {} output reader stopped", session.id);
+}
+
 // Zero-copy buffer implementation is available for future use
 // Currently using direct libc calls for maximum performance
 
@@ -157,6 +578,9 @@ pub struct TtyEngine {
     sessions: Arc<RwLock<HashMap<u64, Arc<PtySession>>>>,
     next_id: AtomicU64,
     signal_tx: broadcast::Sender<(Signal, Option<u64>)>,
+    event_tx: broadcast::Sender<PtyEvent>,
+    /// Sessions the monitor removed after their child exited
+    exited: Arc<Mutex<HashSet<u64>>>,
     shutdown: Arc<AtomicBool>,
     stats: Arc<Mutex<TtyStats>>,
 }
@@ -174,11 +598,14 @@ pub struct TtyStats {
 impl TtyEngine {
     pub fn new() -> Self {
         let (signal_tx, _) = broadcast::channel(1024);
+        let (event_tx, _) = broadcast::channel(64);
 
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
             next_id: AtomicU64::new(1),
             signal_tx,
+            event_tx,
+            exited: Arc::new(Mutex::new(HashSet::new())),
             shutdown: Arc::new(AtomicBool::new(false)),
             stats: Arc::new(Mutex::new(TtyStats::default())),
         }
@@ -186,6 +613,7 @@ impl TtyEngine {
 
     pub async fn create_pty(&self, config: PtyConfig) -> Result<u64, TtyError> {
         let start = Instant::now();
+        let command = ShellCommand::from_config(&config)?;
 
         // Create PTY using libc directly for better compatibility
         let master_fd = unsafe {
@@ -237,6 +665,7 @@ impl TtyEngine {
 
                 // Start monitoring this session
                 self.start_session_monitor(session.clone()).await;
+                self.start_session_writer(session.clone())?;
 
                 // Update stats
                 {
@@ -249,84 +678,58 @@ impl TtyEngine {
 
                 Ok(session_id)
             }
-            ForkResult::Child => {
-                // Child process - setup and exec shell
-                self.setup_child_process(master_fd, &config).await?;
-                unreachable!("Child process should have exec'd");
-            }
+            ForkResult::Child => exec_shell(master_fd, &command),
         }
     }
 
-    async fn setup_child_process(
-        &self,
-        master_fd: RawFd,
-        config: &PtyConfig,
-    ) -> Result<(), TtyError> {
-        unsafe {
-            // Create new session
-            if libc::setsid() == -1 {
-                return Err(TtyError::Fork("Failed to create new session".to_string()));
-            }
-
-            // Get slave PTY name
-            let slave_name = libc::ptsname(master_fd);
-            if slave_name.is_null() {
-                return Err(TtyError::Fork("Failed to get slave PTY name".to_string()));
-            }
-
-            // Open slave PTY
-            let slave_fd = libc::open(slave_name, libc::O_RDWR);
-            if slave_fd == -1 {
-                return Err(TtyError::Fork("Failed to open slave PTY".to_string()));
-            }
-
-            // Redirect stdin, stdout, stderr to slave PTY
-            libc::dup2(slave_fd, 0); // stdin
-            libc::dup2(slave_fd, 1); // stdout
-            libc::dup2(slave_fd, 2); // stderr
-
-            // Close the slave fd and master fd as they're now duplicated
-            libc::close(slave_fd);
-            libc::close(master_fd);
-
-            // Set working directory
-            if let Some(ref dir) = config.working_dir {
-                let c_dir = std::ffi::CString::new(dir.as_str()).unwrap();
-                libc::chdir(c_dir.as_ptr());
-            }
-
-            // Set environment variables
-            for (key, value) in &config.env {
-                let c_key = std::ffi::CString::new(key.as_str()).unwrap();
-                let c_value = std::ffi::CString::new(value.as_str()).unwrap();
-                libc::setenv(c_key.as_ptr(), c_value.as_ptr(), 1);
-            }
-
-            // Prepare arguments
-            let mut args = vec![config.shell.clone()];
-            args.extend(config.args.iter().cloned());
-
-            let c_args: Vec<std::ffi::CString> = args
-                .iter()
-                .map(|s| std::ffi::CString::new(s.as_str()).unwrap())
-                .collect();
-
-            let mut c_argv: Vec<*const libc::c_char> = c_args.iter().map(|s| s.as_ptr()).collect();
-            c_argv.push(std::ptr::null());
-
-            // Execute shell
-            let c_shell = std::ffi::CString::new(config.shell.as_str()).unwrap();
-            libc::execv(c_shell.as_ptr(), c_argv.as_ptr());
-
-            // If we get here, exec failed
-            libc::exit(1);
-        }
+    /// Persistent writer for `queue_write`: drains the session's outgoing
+    /// queue in batches until the session closes
+    fn start_session_writer(&self, session: Arc<PtySession>) -> Result<(), TtyError> {
+        let stats = Arc::clone(&self.stats);
+        std::thread::Builder::new()
+            .name(format!("pty-writer-{}", session.id))
+            .spawn(move || {
+                let mut batch = Vec::with_capacity(4096);
+                while session.outgoing.next_batch(&mut batch) {
+                    let mut written = 0;
+                    while written < batch.len() {
+                        let result = unsafe {
+                            libc::write(
+                                session.master_fd,
+                                batch[written..].as_ptr() as *const libc::c_void,
+                                batch.len() - written,
+                            )
+                        };
+                        if result < 0 {
+                            let err = std::io::Error::last_os_error();
+                            if err.kind() == std::io::ErrorKind::Interrupted {
+                                continue;
+                            }
+                            if err.kind() == std::io::ErrorKind::WouldBlock {
+                                std::thread::sleep(Duration::from_millis(1));
+                                continue;
+                            }
+                            error!("PTY {} write failed: {}", session.id, err);
+                            stats.lock().unwrap().errors += 1;
+                            break;
+                        }
+                        written += result as usize;
+                    }
+                    session.bytes_written.fetch_add(written as u64, Ordering::Relaxed);
+                    stats.lock().unwrap().total_bytes_written += written as u64;
+                    batch.clear();
+                }
+                debug!("PTY {} writer stopped", session.id);
+            })?;
+        Ok(())
     }
 
     async fn start_session_monitor(&self, session: Arc<PtySession>) {
         let sessions = Arc::clone(&self.sessions);
         let stats = Arc::clone(&self.stats);
         let shutdown = Arc::clone(&self.shutdown);
+        let event_tx = self.event_tx.clone();
+        let exited = Arc::clone(&self.exited);
 
         tokio::spawn(async move {
             let mut check_interval = tokio::time::interval(Duration::from_millis(100));
@@ -337,15 +740,28 @@ impl TtyEngine {
                 // Check if child process is still alive
                 match wait::waitpid(session.child_pid, Some(wait::WaitPidFlag::WNOHANG)) {
                     Ok(WaitStatus::StillAlive) => continue,
-                    Ok(_) | Err(_) => {
+                    status => {
                         // Process has died
+                        let code = match status {
+                            Ok(WaitStatus::Exited(_, code)) => Some(code),
+                            _ => None,
+                        };
                         session.mark_dead();
-                        sessions.write().unwrap().remove(&session.id);
+                        if sessions.write().unwrap().remove(&session.id).is_none() {
+                            // destroy_pty got there first
+                            break;
+                        }
+                        exited.lock().unwrap().insert(session.id);
 
                         let mut stats_guard = stats.lock().unwrap();
                         stats_guard.sessions_destroyed += 1;
+                        drop(stats_guard);
 
-                        info!("PTY session {} terminated", session.id);
+                        info!("PTY session {} terminated ({:?})", session.id, code);
+                        let _ = event_tx.send(PtyEvent::Exited {
+                            pty_id: session.id,
+                            code,
+                        });
                         break;
                     }
                 }
@@ -407,6 +823,62 @@ impl TtyEngine {
         }
     }
 
+    /// Queue bytes for the session's writer without waiting for the write.
+    /// Order is preserved across calls; does not allocate once the queue has
+    /// grown to its working size.
+    pub fn queue_write(&self, pty_id: u64, data: &[u8]) -> Result<(), TtyError> {
+        let sessions = self.sessions.read().unwrap();
+        let session = sessions
+            .get(&pty_id)
+            .ok_or(TtyError::PtyNotFound { id: pty_id })?;
+        if !session.outgoing.push(data) {
+            return Err(TtyError::ProcessDied {
+                pid: session.child_pid.as_raw(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Like `queue_write`, but ahead of bytes that are still queued
+    pub fn queue_priority(&self, pty_id: u64, data: &[u8]) -> Result<(), TtyError> {
+        let sessions = self.sessions.read().unwrap();
+        let session = sessions
+            .get(&pty_id)
+            .ok_or(TtyError::PtyNotFound { id: pty_id })?;
+        if !session.outgoing.push_front(data) {
+            return Err(TtyError::ProcessDied {
+                pid: session.child_pid.as_raw(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Stream the PTY's output. A reader thread drains the PTY into
+    /// batches of at most `OUTPUT_BATCH` bytes, with at most `OUTPUT_DEPTH`
+    /// batches in flight; when the receiver falls behind, reading pauses and
+    /// the child blocks on write instead of memory growing. The channel
+    /// closes once the child has exited and its output has been delivered.
+    /// Subscribing again replaces the previous subscription.
+    pub fn subscribe_output(&self, pty_id: u64) -> Result<mpsc::Receiver<Bytes>, TtyError> {
+        let session = {
+            let sessions = self.sessions.read().unwrap();
+            sessions
+                .get(&pty_id)
+                .ok_or(TtyError::PtyNotFound { id: pty_id })?
+                .clone()
+        };
+
+        let (tx, rx) = mpsc::channel(OUTPUT_DEPTH);
+        let generation = session.output_generation.fetch_add(1, Ordering::AcqRel) + 1;
+        let shutdown = Arc::clone(&self.shutdown);
+        let stats = Arc::clone(&self.stats);
+        std::thread::Builder::new()
+            .name(format!("pty-reader-{}", pty_id))
+            .spawn(move || run_output_reader(session, generation, tx, shutdown, stats))?;
+        Ok(rx)
+    }
+
+    #[deprecated(note = "polls one read at a time and can drop output; use `subscribe_output`")]
     pub async fn read_from_pty(&self, pty_id: u64, buffer: &mut [u8]) -> Result<usize, TtyError> {
         let session = {
             let sessions = self.sessions.read().unwrap();
@@ -457,6 +929,7 @@ impl TtyEngine {
                 } else {
                     let bytes_read = bytes_read as usize;
                     buffer[..bytes_read].copy_from_slice(&temp_buffer[..bytes_read]);
+                    session.records.lock().unwrap().feed(&buffer[..bytes_read]);
 
                     session
                         .bytes_read
@@ -533,6 +1006,53 @@ impl TtyEngine {
         Ok(session.get_stats())
     }
 
+    pub fn has_foreground_job(&self, pty_id: u64) -> Result<bool, TtyError> {
+        let sessions = self.sessions.read().unwrap();
+        let session = sessions
+            .get(&pty_id)
+            .ok_or(TtyError::PtyNotFound { id: pty_id })?;
+
+        Ok(session.is_alive() && session.has_foreground_job())
+    }
+
+    pub fn foreground_process(&self, pty_id: u64) -> Result<Option<String>, TtyError> {
+        let sessions = self.sessions.read().unwrap();
+        let session = sessions
+            .get(&pty_id)
+            .ok_or(TtyError::PtyNotFound { id: pty_id })?;
+
+        Ok(session.foreground_process())
+    }
+
+    pub fn shell_cwd(&self, pty_id: u64) -> Result<Option<String>, TtyError> {
+        let sessions = self.sessions.read().unwrap();
+        let session = sessions
+            .get(&pty_id)
+            .ok_or(TtyError::PtyNotFound { id: pty_id })?;
+
+        Ok(session.shell_cwd())
+    }
+
+    /// Plain-text output of the last command that finished in the session
+    pub fn last_command_output(&self, pty_id: u64) -> Result<Option<String>, TtyError> {
+        let sessions = self.sessions.read().unwrap();
+        let session = sessions
+            .get(&pty_id)
+            .ok_or(TtyError::PtyNotFound { id: pty_id })?;
+
+        Ok(session.records.lock().unwrap().last_output().map(str::to_string))
+    }
+
+    /// Output records closed since the last call, oldest first
+    pub fn take_output_records(&self, pty_id: u64) -> Result<Vec<OutputRecord>, TtyError> {
+        let sessions = self.sessions.read().unwrap();
+        let session = sessions
+            .get(&pty_id)
+            .ok_or(TtyError::PtyNotFound { id: pty_id })?;
+
+        Ok(session.records.lock().unwrap().take_closed())
+    }
+
     pub fn list_sessions(&self) -> Vec<u64> {
         self.sessions.read().unwrap().keys().copied().collect()
     }
@@ -542,11 +1062,13 @@ impl TtyEngine {
     }
 
     pub async fn destroy_pty(&self, pty_id: u64) -> Result<(), TtyError> {
-        let session = {
-            let mut sessions = self.sessions.write().unwrap();
-            sessions
-                .remove(&pty_id)
-                .ok_or(TtyError::PtyNotFound { id: pty_id })?
+        let session = self.sessions.write().unwrap().remove(&pty_id);
+        let Some(session) = session else {
+            // Already reaped by the session monitor
+            if self.exited.lock().unwrap().remove(&pty_id) {
+                return Ok(());
+            }
+            return Err(TtyError::PtyNotFound { id: pty_id });
         };
 
         // Send SIGTERM to child process
@@ -610,6 +1132,10 @@ impl TtyEngine {
     pub fn subscribe_signals(&self) -> broadcast::Receiver<(Signal, Option<u64>)> {
         self.signal_tx.subscribe()
     }
+
+    pub fn subscribe_events(&self) -> broadcast::Receiver<PtyEvent> {
+        self.event_tx.subscribe()
+    }
 }
 
 impl Drop for TtyEngine {
@@ -653,20 +1179,164 @@ mod tests {
 
         let pty_id = engine.create_pty(config).await.unwrap();
 
+        let mut output = engine.subscribe_output(pty_id).unwrap();
+
         // Write to PTY
         let test_data = b"echo hello\n";
         let written = engine.write_to_pty(pty_id, test_data).await.unwrap();
         assert_eq!(written, test_data.len());
 
-        // Give some time for command to execute
+        // Read from PTY
+        let batch = timeout(Duration::from_secs(2), output.recv()).await.unwrap().unwrap();
+        assert!(!batch.is_empty());
+
+        engine.destroy_pty(pty_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_queue_write() {
+        let engine = TtyEngine::new();
+        let pty_id = engine.create_pty(PtyConfig::default()).await.unwrap();
+
+        for &byte in b"echo queued\n" {
+            engine.queue_write(pty_id, &[byte]).unwrap();
+        }
         sleep(Duration::from_millis(100)).await;
+        let (_, written, _) = engine.get_pty_stats(pty_id).unwrap();
+        assert_eq!(written, 12);
 
-        // Read from PTY
-        let mut buffer = [0u8; 1024];
-        let read_bytes = engine.read_from_pty(pty_id, &mut buffer).await.unwrap();
-        assert!(read_bytes > 0);
+        engine.destroy_pty(pty_id).await.unwrap();
+        assert!(matches!(
+            engine.queue_write(pty_id, b"x"),
+            Err(TtyError::PtyNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_output_flood_is_bounded_and_complete() {
+        const FLOOD: usize = 32 * 1024 * 1024;
+        let engine = TtyEngine::new();
+        let config = PtyConfig {
+            shell: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), format!("head -c {} /dev/zero", FLOOD)],
+            ..PtyConfig::default()
+        };
+        let pty_id = engine.create_pty(config).await.unwrap();
+        let mut output = engine.subscribe_output(pty_id).unwrap();
+
+        // Nobody is consuming, so the reader stops and the child blocks
+        sleep(Duration::from_millis(300)).await;
+        let (read, _, _) = engine.get_pty_stats(pty_id).unwrap();
+        assert!(read as usize <= OUTPUT_BATCH * (OUTPUT_DEPTH + 1) + READ_CHUNK, "{read} bytes read ahead");
+
+        let mut total = 0;
+        let mut batches = 0;
+        while let Some(batch) = timeout(Duration::from_secs(10), output.recv()).await.unwrap() {
+            assert!(batch.len() <= OUTPUT_BATCH + READ_CHUNK);
+            total += batch.len();
+            batches += 1;
+        }
+        assert_eq!(total, FLOOD);
+        // Reads were coalesced rather than delivered one by one
+        assert!(batches < FLOOD / READ_CHUNK, "{batches} batches");
+    }
+
+    #[test]
+    fn test_shell_command_from_config() {
+        let config = PtyConfig {
+            shell: "sh".to_string(),
+            args: vec!["-i".to_string()],
+            env: HashMap::from([("TERM".to_string(), "dumb".to_string())]),
+            login_shell: true,
+            ..PtyConfig::default()
+        };
+        let command = ShellCommand::from_config(&config).unwrap();
+        assert!(command.program.to_str().unwrap().ends_with("/sh"));
+        assert_eq!(command.argv, [c_string("-sh").unwrap(), c_string("-i").unwrap()]);
+        let env: Vec<(&str, &str)> = command
+            .env
+            .iter()
+            .map(|(key, value)| (key.to_str().unwrap(), value.to_str().unwrap()))
+            .collect();
+        assert_eq!(env, [("COLORTERM", "truecolor"), ("TERM", "dumb")]);
+
+        assert!(matches!(
+            ShellCommand::from_config(&PtyConfig { shell: "/nonexistent/zsh".to_string(), ..PtyConfig::default() }),
+            Err(TtyError::ShellNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_shell_starts_in_cwd_with_term() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TtyEngine::new();
+        let config = PtyConfig {
+            shell: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), "echo \"$TERM $COLORTERM\"; pwd -P".to_string()],
+            cwd: Some(dir.path().to_path_buf()),
+            ..PtyConfig::default()
+        };
+        let pty_id = engine.create_pty(config).await.unwrap();
+        let mut output = engine.subscribe_output(pty_id).unwrap();
+        let mut text = Vec::new();
+        while let Some(batch) = timeout(Duration::from_secs(5), output.recv()).await.unwrap() {
+            text.extend_from_slice(&batch);
+        }
+        let text = String::from_utf8_lossy(&text);
+        assert!(text.contains("xterm-256color truecolor"), "{text}");
+        let cwd = dir.path().canonicalize().unwrap();
+        assert!(text.contains(cwd.to_str().unwrap()), "{text}");
+
+        let missing = PtyConfig { shell: "/nonexistent/zsh".to_string(), ..PtyConfig::default() };
+        assert!(matches!(engine.create_pty(missing).await, Err(TtyError::ShellNotFound { .. })));
+    }
+
+    #[test]
+    fn test_large_writes_are_chunked() {
+        let outgoing = PtyOutgoing::default();
+        outgoing.push(&vec![b'x'; WRITE_CHUNK * 2 + 10]);
+        let mut batch = Vec::new();
+        assert!(outgoing.next_batch(&mut batch));
+        assert_eq!(batch.len(), WRITE_CHUNK);
+
+        // An interrupt queued mid-paste goes out with the next batch
+        outgoing.push_front(b"\x03");
+        batch.clear();
+        assert!(outgoing.next_batch(&mut batch));
+        assert_eq!(batch[0], 0x03);
+        batch.clear();
+        assert!(outgoing.next_batch(&mut batch));
+        assert_eq!(batch.len(), 11);
+    }
+
+    #[tokio::test]
+    async fn test_child_exit_is_reported() {
+        let engine = TtyEngine::new();
+        let mut events = engine.subscribe_events();
+        let config = PtyConfig {
+            shell: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), "exit 3".to_string()],
+            ..PtyConfig::default()
+        };
+
+        let pty_id = engine.create_pty(config).await.unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event,
+            PtyEvent::Exited {
+                pty_id,
+                code: Some(3)
+            }
+        );
 
+        // Cleaning up after the child already exited is not an error
         engine.destroy_pty(pty_id).await.unwrap();
+        assert!(engine.destroy_pty(pty_id).await.is_err());
+        assert_eq!("Hold".parse::<OnShellExit>().unwrap(), OnShellExit::Hold);
+        assert!("linger".parse::<OnShellExit>().is_err());
     }
 
     #[tokio::test]
@@ -775,8 +1445,7 @@ mod tests {
         let result = engine.write_to_pty(999, b"test").await;
         assert!(matches!(result, Err(TtyError::PtyNotFound { id: 999 })));
 
-        let mut buffer = [0u8; 100];
-        let result = engine.read_from_pty(999, &mut buffer).await;
+        let result = engine.subscribe_output(999);
         assert!(matches!(result, Err(TtyError::PtyNotFound { id: 999 })));
     }
 
@@ -0,0 +1,266 @@
+use crate::config::ConfigManager;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Error, Debug)]
+pub enum UsageError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Encoding error: {0}")]
+    Encode(#[from] serde_json::Error),
+}
+
+/// Requests, tokens and estimated cost for one model, or all of them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelUsage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub errors: u64,
+    pub cost: f64,
+    /// UTC day `cost_today` covers, in days since the epoch
+    pub day: u64,
+    pub cost_today: f64,
+}
+
+impl ModelUsage {
+    fn roll_day(&mut self, today: u64) {
+        if self.day != today {
+            self.day = today;
+            self.cost_today = 0.0;
+        }
+    }
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() / 86_400)
+        .unwrap_or(0)
+}
+
+/// Usage per model name. With a file, the counters start from it and are
+/// written back on `save` and when the tracker is dropped.
+#[derive(Debug, Default)]
+pub struct UsageTracker {
+    models: Mutex<BTreeMap<String, ModelUsage>>,
+    path: Option<PathBuf>,
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counters kept in `path`; a missing or unreadable file starts empty
+    pub fn with_file(path: PathBuf) -> Self {
+        let models = match Self::read(&path) {
+            Ok(models) => models,
+            Err(UsageError::Io(e)) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                warn!("Ignoring usage file {}: {}", path.display(), e);
+                BTreeMap::new()
+            }
+        };
+        Self {
+            models: Mutex::new(models),
+            path: Some(path),
+        }
+    }
+
+    /// `usage.json` beside the config file
+    pub fn default_path() -> Option<PathBuf> {
+        ConfigManager::get_config_path()
+            .ok()
+            .and_then(|path| path.parent().map(|dir| dir.join("usage.json")))
+    }
+
+    pub fn read(path: &Path) -> Result<BTreeMap<String, ModelUsage>, UsageError> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn record(&self, model: &str, prompt_tokens: u64, completion_tokens: u64, cost: f64) {
+        let mut models = self.models.lock().unwrap();
+        let usage = models.entry(model.to_string()).or_default();
+        usage.roll_day(today());
+        usage.requests += 1;
+        usage.prompt_tokens += prompt_tokens;
+        usage.completion_tokens += completion_tokens;
+        usage.cost += cost;
+        usage.cost_today += cost;
+    }
+
+    pub fn record_error(&self, model: &str) {
+        let mut models = self.models.lock().unwrap();
+        let usage = models.entry(model.to_string()).or_default();
+        usage.requests += 1;
+        usage.errors += 1;
+    }
+
+    pub fn get(&self, model: &str) -> Option<ModelUsage> {
+        self.models.lock().unwrap().get(model).cloned()
+    }
+
+    pub fn all(&self) -> BTreeMap<String, ModelUsage> {
+        self.models.lock().unwrap().clone()
+    }
+
+    /// Estimated spend on `model` since midnight UTC
+    pub fn cost_today(&self, model: &str) -> f64 {
+        let today = today();
+        self.models
+            .lock()
+            .unwrap()
+            .get(model)
+            .filter(|usage| usage.day == today)
+            .map_or(0.0, |usage| usage.cost_today)
+    }
+
+    pub fn save(&self) -> Result<(), UsageError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_vec_pretty(&*self.models.lock().unwrap())?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+impl Drop for UsageTracker {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            warn!("Failed to save model usage: {}", e);
+        }
+    }
+}
+
+/// Sum over every model
+pub fn total(models: &BTreeMap<String, ModelUsage>) -> ModelUsage {
+    let today = today();
+    models.values().fold(
+        ModelUsage {
+            day: today,
+            ..Default::default()
+        },
+        |mut sum, usage| {
+            sum.requests += usage.requests;
+            sum.prompt_tokens += usage.prompt_tokens;
+            sum.completion_tokens += usage.completion_tokens;
+            sum.errors += usage.errors;
+            sum.cost += usage.cost;
+            if usage.day == today {
+                sum.cost_today += usage.cost_today;
+            }
+            sum
+        },
+    )
+}
+
+/// One row per model and a total, for `:usage`
+pub fn format_table(models: &BTreeMap<String, ModelUsage>) -> String {
+    if models.is_empty() {
+        return "No model usage recorded yet\n".to_string();
+    }
+    let today = today();
+    let total = total(models);
+    let rows: Vec<(&str, &ModelUsage)> = models
+        .iter()
+        .map(|(name, usage)| (name.as_str(), usage))
+        .chain(std::iter::once(("total", &total)))
+        .collect();
+    let width = rows
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+
+    let mut table = format!(
+        "{:<width$}  {:>8}  {:>10}  {:>10}  {:>6}  {:>9}  {:>9}\n",
+        "model", "requests", "prompt", "completion", "errors", "cost", "today"
+    );
+    for (name, usage) in rows {
+        let cost_today = if usage.day == today {
+            usage.cost_today
+        } else {
+            0.0
+        };
+        table.push_str(&format!(
+            "{:<width$}  {:>8}  {:>10}  {:>10}  {:>6}  {:>9.4}  {:>9.4}\n",
+            name,
+            usage.requests,
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            usage.errors,
+            usage.cost,
+            cost_today
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.json");
+
+        let tracker = UsageTracker::with_file(path.clone());
+        tracker.record("claude", 100, 20, 0.5);
+        tracker.record("claude", 50, 10, 0.25);
+        tracker.record_error("local");
+        drop(tracker);
+
+        let tracker = UsageTracker::with_file(path);
+        let claude = tracker.get("claude").unwrap();
+        assert_eq!(
+            (
+                claude.requests,
+                claude.prompt_tokens,
+                claude.completion_tokens
+            ),
+            (2, 150, 30)
+        );
+        assert_eq!(tracker.cost_today("claude"), 0.75);
+        assert_eq!(tracker.get("local").unwrap().errors, 1);
+
+        let all = tracker.all();
+        assert_eq!(total(&all).requests, 3);
+        let table = format_table(&all);
+        assert!(table.contains("claude") && table.contains("total"));
+        assert!(table.contains("0.7500"));
+    }
+
+    #[test]
+    fn test_cost_today_resets_on_a_new_day() {
+        let tracker = UsageTracker::new();
+        tracker.record("claude", 10, 10, 1.0);
+        tracker
+            .models
+            .lock()
+            .unwrap()
+            .get_mut("claude")
+            .unwrap()
+            .day -= 1;
+        assert_eq!(tracker.cost_today("claude"), 0.0);
+        tracker.record("claude", 10, 10, 0.5);
+        assert_eq!(tracker.cost_today("claude"), 0.5);
+        assert_eq!(tracker.get("claude").unwrap().cost, 1.5);
+    }
+}
@@ -0,0 +1,252 @@
+//! How much GPU memory local models may use, read from the hardware: NVML
+//! on NVIDIA (with the `nvml` feature), Metal's recommended working set on
+//! macOS, the wgpu adapter's limits, and otherwise a share of system RAM
+
+use std::fmt;
+
+const MB: u64 = 1024 * 1024;
+
+/// Share of system RAM models may use when no GPU memory of its own is
+/// found: half, leaving the rest to the OS and the terminal
+const SYSTEM_MEMORY_DIVISOR: u64 = 2;
+
+/// Where a VRAM total came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VramSource {
+    /// Given by the caller
+    Fixed,
+    Nvml,
+    /// `recommendedMaxWorkingSetSize` of the default Metal device
+    Metal,
+    /// The largest buffer a discrete wgpu adapter allows, a floor on its memory
+    Wgpu,
+    /// A share of system RAM, for integrated GPUs and CPU-only machines
+    SystemMemory,
+    /// Nothing could be read, so no model may hold VRAM
+    Unavailable,
+}
+
+impl fmt::Display for VramSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Fixed => "fixed",
+            Self::Nvml => "nvml",
+            Self::Metal => "metal",
+            Self::Wgpu => "wgpu adapter",
+            Self::SystemMemory => "system memory",
+            Self::Unavailable => "unavailable",
+        })
+    }
+}
+
+/// How `ModelHost::new` sizes its VRAM budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VramBudget {
+    /// Probe the hardware once, when the host is created
+    #[default]
+    Detect,
+    /// Exactly this many MB
+    Fixed(u64),
+}
+
+impl VramBudget {
+    pub fn resolve(self) -> DetectedVram {
+        match self {
+            Self::Detect => detect(),
+            Self::Fixed(total_mb) => DetectedVram { total_mb, source: VramSource::Fixed },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectedVram {
+    pub total_mb: u64,
+    pub source: VramSource,
+}
+
+/// What the wgpu adapter says about itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AdapterMemory {
+    discrete: bool,
+    max_buffer_mb: u64,
+}
+
+/// Probe every source and keep the most reliable answer
+pub fn detect() -> DetectedVram {
+    let probed = nvml_total_mb()
+        .map(|mb| (mb, VramSource::Nvml))
+        .or_else(|| metal_total_mb().map(|mb| (mb, VramSource::Metal)));
+    let adapter = if probed.is_some() { None } else { adapter_memory() };
+    choose(probed, adapter, system_memory_mb())
+}
+
+/// A driver's own figure wins; a discrete adapter's buffer limit comes
+/// next, and integrated GPUs and CPUs share system RAM
+fn choose(probed: Option<(u64, VramSource)>, adapter: Option<AdapterMemory>, system_mb: Option<u64>) -> DetectedVram {
+    let (total_mb, source) = match (probed, adapter, system_mb) {
+        (Some(probed), _, _) => probed,
+        (None, Some(adapter), _) if adapter.discrete => (adapter.max_buffer_mb, VramSource::Wgpu),
+        (None, _, Some(system_mb)) => (system_mb / SYSTEM_MEMORY_DIVISOR, VramSource::SystemMemory),
+        (None, Some(adapter), None) => (adapter.max_buffer_mb, VramSource::Wgpu),
+        (None, None, None) => (0, VramSource::Unavailable),
+    };
+    DetectedVram { total_mb, source }
+}
+
+fn adapter_memory() -> Option<AdapterMemory> {
+    let instance = wgpu::Instance::default();
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        force_fallback_adapter: false,
+        compatible_surface: None,
+    }))?;
+    Some(AdapterMemory {
+        discrete: adapter.get_info().device_type == wgpu::DeviceType::DiscreteGpu,
+        max_buffer_mb: adapter.limits().max_buffer_size / MB,
+    })
+}
+
+/// Total memory of the largest NVIDIA GPU, read through `libnvidia-ml`
+#[cfg(all(feature = "nvml", unix))]
+fn nvml_total_mb() -> Option<u64> {
+    use std::ffi::{CStr, c_void};
+
+    #[repr(C)]
+    struct Memory {
+        total: u64,
+        free: u64,
+        used: u64,
+    }
+    type Init = unsafe extern "C" fn() -> i32;
+    type Shutdown = unsafe extern "C" fn() -> i32;
+    type DeviceCount = unsafe extern "C" fn(*mut u32) -> i32;
+    type DeviceByIndex = unsafe extern "C" fn(u32, *mut *mut c_void) -> i32;
+    type MemoryInfo = unsafe extern "C" fn(*mut c_void, *mut Memory) -> i32;
+
+    unsafe {
+        let lib = libc::dlopen(c"libnvidia-ml.so.1".as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if lib.is_null() {
+            return None;
+        }
+        let symbol = |name: &CStr| {
+            let symbol = libc::dlsym(lib, name.as_ptr());
+            (!symbol.is_null()).then_some(symbol)
+        };
+        let total = (|| {
+            let init: Init = std::mem::transmute(symbol(c"nvmlInit_v2")?);
+            let shutdown: Shutdown = std::mem::transmute(symbol(c"nvmlShutdown")?);
+            let count: DeviceCount = std::mem::transmute(symbol(c"nvmlDeviceGetCount_v2")?);
+            let by_index: DeviceByIndex = std::mem::transmute(symbol(c"nvmlDeviceGetHandleByIndex_v2")?);
+            let memory_info: MemoryInfo = std::mem::transmute(symbol(c"nvmlDeviceGetMemoryInfo")?);
+            if init() != 0 {
+                return None;
+            }
+            let mut devices = 0;
+            let mut largest = None;
+            if count(&mut devices) == 0 {
+                for index in 0..devices {
+                    let mut device = std::ptr::null_mut();
+                    let mut memory = Memory { total: 0, free: 0, used: 0 };
+                    if by_index(index, &mut device) == 0 && memory_info(device, &mut memory) == 0 {
+                        largest = largest.max(Some(memory.total / MB));
+                    }
+                }
+            }
+            shutdown();
+            largest
+        })();
+        libc::dlclose(lib);
+        total
+    }
+}
+
+#[cfg(not(all(feature = "nvml", unix)))]
+fn nvml_total_mb() -> Option<u64> {
+    None
+}
+
+/// The memory the default Metal device can use without hurting performance
+#[cfg(target_os = "macos")]
+fn metal_total_mb() -> Option<u64> {
+    use objc::runtime::Object;
+    use objc::{msg_send, sel, sel_impl};
+
+    #[link(name = "Metal", kind = "framework")]
+    unsafe extern "C" {
+        fn MTLCreateSystemDefaultDevice() -> *mut Object;
+    }
+
+    unsafe {
+        let device = MTLCreateSystemDefaultDevice();
+        if device.is_null() {
+            return None;
+        }
+        let bytes: u64 = msg_send![device, recommendedMaxWorkingSetSize];
+        let _: () = msg_send![device, release];
+        Some(bytes / MB)
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+fn metal_total_mb() -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn system_memory_mb() -> Option<u64> {
+    let (pages, page_size) = unsafe { (libc::sysconf(libc::_SC_PHYS_PAGES), libc::sysconf(libc::_SC_PAGESIZE)) };
+    (pages > 0 && page_size > 0).then(|| pages as u64 * page_size as u64 / MB)
+}
+
+#[cfg(not(unix))]
+fn system_memory_mb() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_prefers_driver_then_discrete_adapter() {
+        let discrete = AdapterMemory { discrete: true, max_buffer_mb: 2048 };
+        let integrated = AdapterMemory { discrete: false, max_buffer_mb: 1024 };
+
+        assert_eq!(
+            choose(Some((8192, VramSource::Nvml)), Some(discrete), Some(32768)),
+            DetectedVram { total_mb: 8192, source: VramSource::Nvml }
+        );
+        assert_eq!(
+            choose(None, Some(discrete), Some(32768)),
+            DetectedVram { total_mb: 2048, source: VramSource::Wgpu }
+        );
+        assert_eq!(
+            choose(None, Some(integrated), Some(32768)),
+            DetectedVram { total_mb: 16384, source: VramSource::SystemMemory }
+        );
+        assert_eq!(
+            choose(None, None, Some(16384)),
+            DetectedVram { total_mb: 8192, source: VramSource::SystemMemory }
+        );
+        assert_eq!(
+            choose(None, Some(integrated), None),
+            DetectedVram { total_mb: 1024, source: VramSource::Wgpu }
+        );
+        assert_eq!(choose(None, None, None), DetectedVram { total_mb: 0, source: VramSource::Unavailable });
+    }
+
+    #[test]
+    fn test_fixed_budget_skips_detection() {
+        assert_eq!(
+            VramBudget::Fixed(4096).resolve(),
+            DetectedVram { total_mb: 4096, source: VramSource::Fixed }
+        );
+        assert_eq!(VramSource::SystemMemory.to_string(), "system memory");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_system_memory_is_read() {
+        assert!(system_memory_mb().is_some_and(|mb| mb > 0));
+    }
+}
@@ -0,0 +1,570 @@
+use crate::messages;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber, error};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// A subsystem expected to show signs of life
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Component {
+    RenderLoop,
+    PtyReader(u64),
+    ModelDispatcher,
+    /// A model worker holding a lease
+    Worker(String),
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RenderLoop => write!(f, "render loop"),
+            Self::PtyReader(pty_id) => write!(f, "PTY reader {}", pty_id),
+            Self::ModelDispatcher => write!(f, "model dispatcher"),
+            Self::Worker(id) => write!(f, "worker {}", id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecoveryAction {
+    /// Reconfigure every surface and redraw it in full
+    Redraw,
+    RestartReader(u64),
+    /// The worker's lease was marked failed; replace the worker
+    RestartWorker(String),
+    /// Nothing safe to do beyond the report
+    LogOnly,
+}
+
+impl Component {
+    fn recovery(&self) -> RecoveryAction {
+        match self {
+            Self::RenderLoop => RecoveryAction::Redraw,
+            Self::PtyReader(pty_id) => RecoveryAction::RestartReader(*pty_id),
+            Self::ModelDispatcher => RecoveryAction::LogOnly,
+            Self::Worker(id) => RecoveryAction::RestartWorker(id.clone()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pulse {
+    last: Instant,
+    beats: u64,
+    state: String,
+    retired: bool,
+}
+
+/// Held by a watched component, which beats once per frame, batch or cycle
+#[derive(Debug, Clone)]
+pub struct Heartbeat(Arc<Mutex<Pulse>>);
+
+impl Heartbeat {
+    fn new(now: Instant) -> Self {
+        Self(Arc::new(Mutex::new(Pulse {
+            last: now,
+            beats: 0,
+            state: String::new(),
+            retired: false,
+        })))
+    }
+
+    pub fn beat(&self) {
+        self.beat_at(Instant::now());
+    }
+
+    pub fn beat_at(&self, now: Instant) {
+        let mut pulse = self.0.lock();
+        pulse.last = now;
+        pulse.beats += 1;
+    }
+
+    /// Last-known state, quoted in the report if the component stalls
+    pub fn set_state(&self, state: impl Into<String>) {
+        self.0.lock().state = state.into();
+    }
+
+    /// The component exited on purpose and should no longer be watched
+    pub fn retire(&self) {
+        self.0.lock().retired = true;
+    }
+}
+
+#[derive(Debug, Default)]
+struct LeaseTable {
+    next: u64,
+    held: HashMap<String, (u64, Instant)>,
+}
+
+/// Worker leases, shared between the host that hands them out and the
+/// watchdog that expires them
+#[derive(Debug, Clone, Default)]
+pub struct Leases(Arc<Mutex<LeaseTable>>);
+
+impl Leases {
+    pub fn take(&self, worker: &str) -> Lease {
+        self.take_at(worker, Instant::now())
+    }
+
+    pub fn take_at(&self, worker: &str, now: Instant) -> Lease {
+        let mut table = self.0.lock();
+        table.next += 1;
+        let generation = table.next;
+        table.held.insert(worker.to_string(), (generation, now));
+        Lease {
+            leases: self.clone(),
+            worker: worker.to_string(),
+            generation,
+        }
+    }
+
+    pub fn held(&self) -> usize {
+        self.0.lock().held.len()
+    }
+
+    fn expired(&self, now: Instant, cap: Duration) -> Vec<(String, Duration)> {
+        self.0
+            .lock()
+            .held
+            .iter()
+            .map(|(worker, &(_, taken))| (worker.clone(), now.saturating_duration_since(taken)))
+            .filter(|(_, held)| *held > cap)
+            .collect()
+    }
+
+    fn revoke(&self, worker: &str) {
+        self.0.lock().held.remove(worker);
+    }
+}
+
+/// Released on drop, unless the watchdog already revoked it
+#[derive(Debug)]
+pub struct Lease {
+    leases: Leases,
+    worker: String,
+    generation: u64,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let mut table = self.leases.0.lock();
+        if table
+            .held
+            .get(&self.worker)
+            .is_some_and(|&(g, _)| g == self.generation)
+        {
+            table.held.remove(&self.worker);
+        }
+    }
+}
+
+/// The most recent log lines, kept for stall reports
+#[derive(Debug, Clone)]
+pub struct LogRing {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl LogRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, line: String) {
+        let mut lines = self.lines.lock();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    pub fn recent(&self) -> Vec<String> {
+        self.lines.lock().iter().cloned().collect()
+    }
+}
+
+impl Default for LogRing {
+    fn default() -> Self {
+        Self::new(200)
+    }
+}
+
+struct LineVisitor(String);
+
+impl Visit for LineVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0.push_str(&format!(" {:?}", value));
+        } else {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogRing {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = LineVisitor(format!("{} {}:", metadata.level(), metadata.target()));
+        event.record(&mut visitor);
+        self.push(visitor.0);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+    pub check_interval: Duration,
+    /// A worker lease held longer than this is forcibly failed
+    pub lease_hard_cap: Duration,
+    /// Minimum time between recovery attempts on one component
+    pub cooldown: Duration,
+    /// Attempts allowed per component within `recovery_window`
+    pub max_recoveries: usize,
+    pub recovery_window: Duration,
+    /// `false` only reports stalls
+    pub recover: bool,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_millis(500),
+            lease_hard_cap: Duration::from_secs(120),
+            cooldown: Duration::from_secs(10),
+            max_recoveries: 3,
+            recovery_window: Duration::from_secs(300),
+            recover: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StallReport {
+    pub component: Component,
+    pub silent_for: Duration,
+    pub limit: Duration,
+    pub beats: u64,
+    pub last_state: String,
+    pub recent_log: Vec<String>,
+}
+
+impl StallReport {
+    fn log(&self, action: Option<&RecoveryAction>) {
+        error!(
+            component = %self.component,
+            silent_ms = self.silent_for.as_millis() as u64,
+            limit_ms = self.limit.as_millis() as u64,
+            beats = self.beats,
+            state = %self.last_state,
+            action = ?action,
+            recent_log = ?self.recent_log,
+            "Watchdog: component stopped responding"
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Stall {
+    pub report: StallReport,
+    /// `None` when recovery is off or rate-limited
+    pub action: Option<RecoveryAction>,
+}
+
+impl Stall {
+    /// Error banner telling the user what happened
+    pub fn banner(&self) -> String {
+        let component = self.report.component.to_string();
+        match self.action {
+            Some(_) => messages::current().watchdog_recovered(&component),
+            None => messages::current().watchdog_stalled(&component),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Attempt {
+    Skip,
+    Report,
+    Recover,
+}
+
+#[derive(Debug, Default)]
+struct RateLimit {
+    attempts: VecDeque<Instant>,
+    last: Option<Instant>,
+    gave_up: bool,
+}
+
+impl RateLimit {
+    fn attempt(&mut self, now: Instant, config: &WatchdogConfig) -> Attempt {
+        if self
+            .last
+            .is_some_and(|last| now.saturating_duration_since(last) < config.cooldown)
+        {
+            return Attempt::Skip;
+        }
+        while self
+            .attempts
+            .front()
+            .is_some_and(|&t| now.saturating_duration_since(t) > config.recovery_window)
+        {
+            self.attempts.pop_front();
+        }
+        if config.recover && self.attempts.len() < config.max_recoveries {
+            self.attempts.push_back(now);
+            self.last = Some(now);
+            self.gave_up = false;
+            Attempt::Recover
+        } else if !self.gave_up {
+            self.last = Some(now);
+            self.gave_up = true;
+            Attempt::Report
+        } else {
+            Attempt::Skip
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Watched {
+    heartbeat: Heartbeat,
+    max_interval: Duration,
+}
+
+/// Notices missed heartbeats and expired leases, reports them and picks a
+/// rate-limited recovery. The caller carries out the actions.
+pub struct Watchdog {
+    config: WatchdogConfig,
+    watched: HashMap<Component, Watched>,
+    leases: Leases,
+    limits: HashMap<Component, RateLimit>,
+    log: LogRing,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig, log: LogRing) -> Self {
+        Self {
+            config,
+            watched: HashMap::new(),
+            leases: Leases::default(),
+            limits: HashMap::new(),
+            log,
+        }
+    }
+
+    pub fn config(&self) -> &WatchdogConfig {
+        &self.config
+    }
+
+    /// Replaces any earlier registration, e.g. from a restarted reader
+    pub fn register(&mut self, component: Component, max_interval: Duration) -> Heartbeat {
+        let heartbeat = Heartbeat::new(Instant::now());
+        self.watched.insert(
+            component,
+            Watched {
+                heartbeat: heartbeat.clone(),
+                max_interval,
+            },
+        );
+        heartbeat
+    }
+
+    pub fn unregister(&mut self, component: &Component) {
+        self.watched.remove(component);
+        self.limits.remove(component);
+    }
+
+    pub fn leases(&self) -> Leases {
+        self.leases.clone()
+    }
+
+    pub fn check(&mut self, now: Instant) -> Vec<Stall> {
+        self.watched
+            .retain(|_, watched| !watched.heartbeat.0.lock().retired);
+
+        let mut silent = Vec::new();
+        for (component, watched) in &self.watched {
+            let pulse = watched.heartbeat.0.lock();
+            let silent_for = now.saturating_duration_since(pulse.last);
+            if silent_for > watched.max_interval {
+                silent.push(StallReport {
+                    component: component.clone(),
+                    silent_for,
+                    limit: watched.max_interval,
+                    beats: pulse.beats,
+                    last_state: pulse.state.clone(),
+                    recent_log: Vec::new(),
+                });
+            } else if let Some(limit) = self.limits.get_mut(component) {
+                limit.gave_up = false;
+            }
+        }
+        for (worker, held) in self.leases.expired(now, self.config.lease_hard_cap) {
+            silent.push(StallReport {
+                component: Component::Worker(worker),
+                silent_for: held,
+                limit: self.config.lease_hard_cap,
+                beats: 0,
+                last_state: "lease held".to_string(),
+                recent_log: Vec::new(),
+            });
+        }
+
+        let mut stalls = Vec::new();
+        for mut report in silent {
+            let limit = self.limits.entry(report.component.clone()).or_default();
+            let action = match limit.attempt(now, &self.config) {
+                Attempt::Skip => continue,
+                Attempt::Report => None,
+                Attempt::Recover => Some(report.component.recovery()),
+            };
+            if let Some(RecoveryAction::RestartWorker(worker)) = &action {
+                self.leases.revoke(worker);
+            }
+            report.recent_log = self.log.recent();
+            report.log(action.as_ref());
+            stalls.push(Stall { report, action });
+        }
+        stalls
+    }
+
+    /// Checks every `check_interval` on its own task, so a wedged UI thread
+    /// is still reported; stalls are handed over for recovery
+    pub fn spawn(
+        watchdog: Arc<Mutex<Watchdog>>,
+        stalls: crossbeam_channel::Sender<Stall>,
+    ) -> tokio::task::JoinHandle<()> {
+        let period = watchdog.lock().config.check_interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let found = watchdog.lock().check(Instant::now());
+                for stall in found {
+                    if stalls.send(stall).is_err() {
+                        return;
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchdog(config: WatchdogConfig) -> Watchdog {
+        let log = LogRing::new(4);
+        log.push("INFO ferroterm: frame drawn".to_string());
+        Watchdog::new(config, log)
+    }
+
+    #[test]
+    fn test_missed_heartbeat_detected_and_recovered() {
+        let mut dog = watchdog(WatchdogConfig::default());
+        let render = dog.register(Component::RenderLoop, Duration::from_millis(100));
+        let reader = dog.register(Component::PtyReader(7), Duration::from_millis(100));
+        let t0 = Instant::now();
+        render.beat_at(t0);
+        reader.beat_at(t0);
+        reader.set_state("backlog 4096 bytes");
+
+        // Within the interval nothing is reported
+        assert!(dog.check(t0 + Duration::from_millis(100)).is_empty());
+
+        // One check interval past it, both stalls are caught
+        render.beat_at(t0 + Duration::from_millis(100));
+        let mut stalls = dog.check(t0 + Duration::from_millis(150));
+        assert_eq!(stalls.len(), 1);
+        let stall = stalls.remove(0);
+        assert_eq!(stall.action, Some(RecoveryAction::RestartReader(7)));
+        assert_eq!(stall.report.silent_for, Duration::from_millis(150));
+        assert_eq!(stall.report.last_state, "backlog 4096 bytes");
+        assert_eq!(stall.report.recent_log, ["INFO ferroterm: frame drawn"]);
+        assert!(stall.banner().contains("PTY reader 7"));
+
+        let stalls = dog.check(t0 + Duration::from_millis(250));
+        assert_eq!(stalls.len(), 1);
+        assert_eq!(stalls[0].action, Some(RecoveryAction::Redraw));
+
+        // A reader that exited on purpose is no longer watched
+        reader.retire();
+        assert!(
+            dog.check(t0 + Duration::from_secs(60))
+                .iter()
+                .all(|s| s.report.component == Component::RenderLoop)
+        );
+    }
+
+    #[test]
+    fn test_expired_lease_restarts_worker() {
+        let config = WatchdogConfig {
+            lease_hard_cap: Duration::from_secs(30),
+            ..WatchdogConfig::default()
+        };
+        let mut dog = watchdog(config);
+        let leases = dog.leases();
+        let t0 = Instant::now();
+        let stuck = leases.take_at("gguf-worker-0", t0);
+        let _busy = leases.take_at("gguf-worker-1", t0 + Duration::from_secs(20));
+
+        assert!(dog.check(t0 + Duration::from_secs(30)).is_empty());
+        let stalls = dog.check(t0 + Duration::from_secs(31));
+        assert_eq!(stalls.len(), 1);
+        assert_eq!(
+            stalls[0].action,
+            Some(RecoveryAction::RestartWorker("gguf-worker-0".to_string()))
+        );
+        // Forcibly failed: the lease is gone
+        assert_eq!(leases.held(), 1);
+
+        // The stuck call finishing late must not release the restarted
+        // worker's new lease
+        let _fresh = leases.take_at("gguf-worker-0", t0 + Duration::from_secs(32));
+        drop(stuck);
+        assert_eq!(leases.held(), 2);
+    }
+
+    #[test]
+    fn test_recovery_is_rate_limited() {
+        let config = WatchdogConfig {
+            cooldown: Duration::from_secs(10),
+            max_recoveries: 2,
+            recovery_window: Duration::from_secs(300),
+            ..WatchdogConfig::default()
+        };
+        let mut dog = watchdog(config);
+        let render = dog.register(Component::RenderLoop, Duration::from_secs(1));
+        let t0 = Instant::now();
+        render.beat_at(t0);
+        let at = |secs| t0 + Duration::from_secs(secs);
+
+        assert_eq!(dog.check(at(2))[0].action, Some(RecoveryAction::Redraw));
+        // Still stuck, but within the cooldown
+        assert!(dog.check(at(5)).is_empty());
+        assert_eq!(dog.check(at(12))[0].action, Some(RecoveryAction::Redraw));
+        // Out of attempts: reported once, then left alone
+        let stalls = dog.check(at(30));
+        assert_eq!(stalls[0].action, None);
+        assert!(stalls[0].banner().contains("render loop"));
+        assert!(dog.check(at(60)).is_empty());
+        // Attempts age out of the window
+        assert_eq!(dog.check(at(400))[0].action, Some(RecoveryAction::Redraw));
+
+        let mut quiet = watchdog(WatchdogConfig {
+            recover: false,
+            ..WatchdogConfig::default()
+        });
+        quiet
+            .register(Component::ModelDispatcher, Duration::from_secs(1))
+            .beat_at(t0);
+        assert_eq!(quiet.check(at(2))[0].action, None);
+    }
+}
@@ -0,0 +1,896 @@
+use crate::multiplexer::{self, Pane, PaneDirection, SplitDirection};
+use crate::terminal::TerminalState;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum WindowError {
+    #[error("Window not found")]
+    NotFound,
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Session format error: {0}")]
+    Session(#[from] serde_json::Error),
+}
+
+/// A second close request within this window confirms closing a window that
+/// still has running foreground jobs.
+pub const CLOSE_CONFIRM_WINDOW: Duration = Duration::from_secs(3);
+
+/// Pixel size of one terminal cell at a given scale factor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellMetrics {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl CellMetrics {
+    /// Estimate from the font size alone, for before a font has loaded
+    pub fn from_font(font_size: f32, line_height: f32, scale_factor: f64) -> Self {
+        let scale = scale_factor as f32;
+        Self {
+            width: font_size * 0.6 * scale,
+            height: font_size * 1.2 * line_height * scale,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowGeometry {
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+    pub scale_factor: f64,
+    pub cols: u32,
+    pub rows: u32,
+}
+
+impl WindowGeometry {
+    pub fn compute(
+        pixel_width: u32,
+        pixel_height: u32,
+        scale_factor: f64,
+        metrics: CellMetrics,
+    ) -> Self {
+        Self {
+            pixel_width,
+            pixel_height,
+            scale_factor,
+            // Nudge before truncating so exact multiples don't lose a cell to f32 rounding
+            cols: ((pixel_width as f32 / metrics.width + 1e-3) as u32).max(1),
+            rows: ((pixel_height as f32 / metrics.height + 1e-3) as u32).max(1),
+        }
+    }
+}
+
+/// Per-window state. `resources` holds whatever the frontend owns for the
+/// window (OS window handle, surface, renderer); everything else is shared
+/// bookkeeping that can be tested without a display.
+pub struct ManagedWindow<T> {
+    pub resources: T,
+    /// Grid of the active tab
+    pub terminal: Arc<RwLock<TerminalState>>,
+    pub geometry: WindowGeometry,
+    /// Each tab's PTY; for a split tab, the focused pane's
+    pub tabs: Vec<u64>,
+    pub active_tab: usize,
+    pub position: Option<(i32, i32)>,
+    /// Each tab's and pane's grid, by PTY id
+    grids: HashMap<u64, Arc<RwLock<TerminalState>>>,
+    /// Pane layouts of split tabs, keyed like `tabs`
+    layouts: HashMap<u64, multiplexer::Window>,
+    pending_close: Option<Instant>,
+}
+
+impl<T> ManagedWindow<T> {
+    pub fn active_pty(&self) -> Option<u64> {
+        self.tabs.get(self.active_tab).copied()
+    }
+
+    /// Rows above the grid taken by the tab bar, which shows once there is
+    /// more than one tab
+    pub fn tab_bar_rows(&self) -> u32 {
+        if self.tabs.len() > 1 { 1 } else { 0 }
+    }
+
+    /// Columns and rows of every tab's grid
+    pub fn grid_size(&self) -> (u32, u32) {
+        let rows = self.geometry.rows.saturating_sub(self.tab_bar_rows());
+        (self.geometry.cols, rows.max(1))
+    }
+
+    pub fn grid(&self, pty_id: u64) -> Option<&Arc<RwLock<TerminalState>>> {
+        self.grids.get(&pty_id)
+    }
+
+    /// Add a tab for `pty_id` after the others and switch to it. The first
+    /// tab takes over the grid the window opened with.
+    pub fn open_tab(&mut self, pty_id: u64) -> Arc<RwLock<TerminalState>> {
+        let grid = if self.grids.is_empty() {
+            self.terminal.clone()
+        } else {
+            let (cols, rows) = self.grid_size();
+            Arc::new(RwLock::new(TerminalState::new(cols, rows)))
+        };
+        self.grids.insert(pty_id, grid.clone());
+        self.tabs.push(pty_id);
+        self.select_tab(self.tabs.len() - 1);
+        self.fit_grids();
+        grid
+    }
+
+    /// Drop a tab and the grids of all its panes. Closing the active tab
+    /// activates the one that slides into its place, or the new last tab.
+    pub fn close_tab(&mut self, pty_id: u64) -> bool {
+        let Some(index) = self.tabs.iter().position(|&tab| tab == pty_id) else {
+            return false;
+        };
+        for pane in self.tab_panes(pty_id) {
+            self.grids.remove(&pane);
+        }
+        self.layouts.remove(&pty_id);
+        self.tabs.remove(index);
+        if index < self.active_tab || self.active_tab >= self.tabs.len() {
+            self.active_tab = self.active_tab.saturating_sub(1);
+        }
+        self.select_tab(self.active_tab);
+        self.fit_grids();
+        true
+    }
+
+    /// Give a tab or pane a new PTY, keeping its grid and place
+    pub fn replace_tab(&mut self, old: u64, new: u64) -> bool {
+        let Some(index) = self.tab_index(old) else {
+            return false;
+        };
+        let tab = self.tabs[index];
+        if let Some(mut layout) = self.layouts.remove(&tab) {
+            if let Some(pane_id) = layout.pane_for_pty(old)
+                && let Some(pane) = layout.panes.get_mut(&pane_id)
+            {
+                pane.pty_id = new;
+            }
+            self.layouts.insert(if tab == old { new } else { tab }, layout);
+        }
+        if tab == old {
+            self.tabs[index] = new;
+        }
+        if let Some(grid) = self.grids.remove(&old) {
+            self.grids.insert(new, grid);
+        }
+        true
+    }
+
+    pub fn select_tab(&mut self, index: usize) -> bool {
+        let Some(pty_id) = self.tabs.get(index) else {
+            return false;
+        };
+        self.active_tab = index;
+        if let Some(grid) = self.grids.get(pty_id) {
+            self.terminal = grid.clone();
+        }
+        true
+    }
+
+    /// Switch to the next tab, or the previous one, wrapping around
+    pub fn cycle_tab(&mut self, forward: bool) -> bool {
+        let count = self.tabs.len();
+        if count < 2 {
+            return false;
+        }
+        let index = if forward {
+            (self.active_tab + 1) % count
+        } else {
+            (self.active_tab + count - 1) % count
+        };
+        self.select_tab(index)
+    }
+
+    /// PTYs of every pane of the tab keyed by `tab`, in layout order
+    pub fn tab_panes(&self, tab: u64) -> Vec<u64> {
+        match self.layouts.get(&tab) {
+            Some(layout) => layout.ordered_panes().iter().map(|pane| pane.pty_id).collect(),
+            None => vec![tab],
+        }
+    }
+
+    /// Every PTY in the window, across tabs and panes
+    pub fn ptys(&self) -> Vec<u64> {
+        self.tabs.iter().flat_map(|&tab| self.tab_panes(tab)).collect()
+    }
+
+    /// Index of the tab showing `pty_id`, in any of its panes
+    pub fn tab_index(&self, pty_id: u64) -> Option<usize> {
+        self.tabs
+            .iter()
+            .position(|&tab| self.tab_panes(tab).contains(&pty_id))
+    }
+
+    /// Pane layout of the active tab, if it is split
+    pub fn pane_layout(&self) -> Option<&multiplexer::Window> {
+        self.layouts.get(&self.active_pty()?)
+    }
+
+    /// Pane layout of the tab keyed by `tab`; a tab that is not split is
+    /// one pane filling the grid
+    pub fn tab_layout(&self, tab: u64) -> multiplexer::Window {
+        self.layouts.get(&tab).cloned().unwrap_or_else(|| {
+            let (cols, rows) = self.grid_size();
+            let mut layout = multiplexer::Window::new(0, String::new(), cols, rows);
+            layout.add_pane(Pane::new(1, tab, 0, 0, cols, rows));
+            layout
+        })
+    }
+
+    /// Add a tab laid out as `layout`, whose panes already have PTYs, and
+    /// switch to it with its active pane focused. Returns each pane's PTY
+    /// and new grid.
+    pub fn open_layout_tab(
+        &mut self,
+        layout: multiplexer::Window,
+    ) -> Vec<(u64, Arc<RwLock<TerminalState>>)> {
+        let ptys: Vec<u64> = layout.ordered_panes().iter().map(|pane| pane.pty_id).collect();
+        let Some(focused) = layout
+            .get_active_pane()
+            .map(|pane| pane.pty_id)
+            .or_else(|| ptys.first().copied())
+        else {
+            return Vec::new();
+        };
+        let mut grids = vec![(focused, self.open_tab(focused))];
+        if ptys.len() > 1 {
+            let (cols, rows) = self.grid_size();
+            for &pty_id in ptys.iter().filter(|&&pty_id| pty_id != focused) {
+                let grid = Arc::new(RwLock::new(TerminalState::new(cols, rows)));
+                self.grids.insert(pty_id, grid.clone());
+                grids.push((pty_id, grid));
+            }
+            self.layouts.insert(focused, layout);
+            self.fit_grids();
+        }
+        grids
+    }
+
+    /// Top-left cell of the focused pane, relative to the grid area
+    pub fn pane_origin(&self) -> (u32, u32) {
+        self.pane_layout()
+            .zip(self.active_pty())
+            .and_then(|(layout, pty_id)| layout.panes.get(&layout.pane_for_pty(pty_id)?))
+            .map_or((0, 0), |pane| (pane.layout.x, pane.layout.y))
+    }
+
+    /// Each pane's PTY and size in cells (columns, rows)
+    pub fn pty_sizes(&self) -> Vec<(u64, (u32, u32))> {
+        let (cols, rows) = self.grid_size();
+        self.tabs
+            .iter()
+            .flat_map(|tab| match self.layouts.get(tab) {
+                Some(layout) => layout
+                    .ordered_panes()
+                    .iter()
+                    .map(|pane| (pane.pty_id, (pane.layout.width, pane.layout.height)))
+                    .collect(),
+                None => vec![(*tab, (cols, rows))],
+            })
+            .collect()
+    }
+
+    /// Split the focused pane of the active tab, giving the new half to
+    /// `pty_id` and focusing it. Returns the new pane's grid, or None when
+    /// the pane is too small to split.
+    pub fn split_pane(
+        &mut self,
+        pty_id: u64,
+        direction: SplitDirection,
+    ) -> Option<Arc<RwLock<TerminalState>>> {
+        let focused = self.active_pty()?;
+        let (cols, rows) = self.grid_size();
+        let mut layout = self.layouts.remove(&focused).unwrap_or_else(|| {
+            let mut layout = multiplexer::Window::new(0, String::new(), cols, rows);
+            layout.add_pane(Pane::new(1, focused, 0, 0, cols, rows));
+            layout
+        });
+        let pane_id = layout.pane_for_pty(focused)?;
+        let new_pane = match layout.split_pane(pane_id, direction) {
+            Ok(new_pane) => new_pane,
+            Err(_) => {
+                if layout.panes.len() > 1 {
+                    self.layouts.insert(focused, layout);
+                }
+                return None;
+            }
+        };
+        layout.panes.get_mut(&new_pane)?.pty_id = pty_id;
+        layout.set_active_pane(new_pane).ok()?;
+
+        let grid = Arc::new(RwLock::new(TerminalState::new(cols, rows)));
+        self.grids.insert(pty_id, grid.clone());
+        self.layouts.insert(pty_id, layout);
+        self.tabs[self.active_tab] = pty_id;
+        self.terminal = grid.clone();
+        self.fit_grids();
+        Some(grid)
+    }
+
+    /// Focus the nearest pane in `direction` in the active tab
+    pub fn focus_pane(&mut self, direction: PaneDirection) -> bool {
+        let Some(focused) = self.active_pty() else {
+            return false;
+        };
+        let Some(layout) = self.layouts.get_mut(&focused) else {
+            return false;
+        };
+        let Some(next) = layout
+            .pane_for_pty(focused)
+            .and_then(|pane_id| layout.adjacent_pane(pane_id, direction))
+        else {
+            return false;
+        };
+        if layout.set_active_pane(next).is_err() {
+            return false;
+        }
+        let pty_id = layout.panes[&next].pty_id;
+        if let Some(layout) = self.layouts.remove(&focused) {
+            self.layouts.insert(pty_id, layout);
+        }
+        self.tabs[self.active_tab] = pty_id;
+        self.select_tab(self.active_tab)
+    }
+
+    /// Close one pane of a split tab; its neighbours take the room. A tab
+    /// that is not split closes whole.
+    pub fn close_pane(&mut self, pty_id: u64) -> bool {
+        let Some(index) = self.tab_index(pty_id) else {
+            return false;
+        };
+        let tab = self.tabs[index];
+        let Some(mut layout) = self.layouts.remove(&tab) else {
+            return self.close_tab(pty_id);
+        };
+        if let Some(pane_id) = layout.pane_for_pty(pty_id) {
+            layout.remove_pane(pane_id);
+        }
+        self.grids.remove(&pty_id);
+        let Some(focused) = layout.get_active_pane().map(|pane| pane.pty_id) else {
+            return false;
+        };
+        if layout.panes.len() > 1 {
+            self.layouts.insert(focused, layout);
+        }
+        self.tabs[index] = focused;
+        self.select_tab(self.active_tab);
+        self.fit_grids();
+        true
+    }
+
+    fn fit_grids(&mut self) {
+        let (cols, rows) = self.grid_size();
+        if self.grids.is_empty() {
+            self.terminal.write().resize(cols, rows);
+        }
+        for layout in self.layouts.values_mut() {
+            layout.resize(cols, rows);
+        }
+        for (pty_id, (cols, rows)) in self.pty_sizes() {
+            if let Some(grid) = self.grids.get(&pty_id) {
+                grid.write().resize(cols, rows);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseDecision {
+    /// The close may proceed; the caller tears down resources and calls `remove`
+    Close { last_window: bool },
+    /// Running jobs were found; a second request confirms the close
+    ConfirmationRequired,
+}
+
+/// Routes OS windows to their per-window state. Keyed by the windowing
+/// system's id type so event dispatch is a single map lookup.
+pub struct WindowRegistry<K, T> {
+    windows: HashMap<K, ManagedWindow<T>>,
+    order: Vec<K>,
+    focused: Option<K>,
+    font_size: f32,
+    line_height: f32,
+    /// Cell size measured from the loaded font, in logical pixels
+    cell_size: Option<(f32, f32)>,
+}
+
+impl<K: Copy + Eq + Hash, T> WindowRegistry<K, T> {
+    pub fn new(font_size: f32, line_height: f32) -> Self {
+        Self {
+            windows: HashMap::new(),
+            order: Vec::new(),
+            focused: None,
+            font_size,
+            line_height,
+            cell_size: None,
+        }
+    }
+
+    /// Cell size in physical pixels at `scale_factor`
+    pub fn metrics(&self, scale_factor: f64) -> CellMetrics {
+        match self.cell_size {
+            Some((width, height)) => CellMetrics {
+                width: width * scale_factor as f32,
+                height: height * scale_factor as f32,
+            },
+            None => CellMetrics::from_font(self.font_size, self.line_height, scale_factor),
+        }
+    }
+
+    /// Register a new window with its own terminal grid sized to the window
+    pub fn insert(
+        &mut self,
+        id: K,
+        resources: T,
+        pixel_size: (u32, u32),
+        scale_factor: f64,
+    ) -> &mut ManagedWindow<T> {
+        let geometry = WindowGeometry::compute(
+            pixel_size.0,
+            pixel_size.1,
+            scale_factor,
+            self.metrics(scale_factor),
+        );
+        let terminal = Arc::new(RwLock::new(TerminalState::new(
+            geometry.cols,
+            geometry.rows,
+        )));
+
+        if !self.order.contains(&id) {
+            self.order.push(id);
+        }
+        if self.focused.is_none() {
+            self.focused = Some(id);
+        }
+
+        self.windows.insert(
+            id,
+            ManagedWindow {
+                resources,
+                terminal,
+                geometry,
+                tabs: Vec::new(),
+                active_tab: 0,
+                position: None,
+                grids: HashMap::new(),
+                layouts: HashMap::new(),
+                pending_close: None,
+            },
+        );
+        self.windows.get_mut(&id).unwrap()
+    }
+
+    pub fn get(&self, id: &K) -> Option<&ManagedWindow<T>> {
+        self.windows.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &K) -> Option<&mut ManagedWindow<T>> {
+        self.windows.get_mut(id)
+    }
+
+    pub fn contains(&self, id: &K) -> bool {
+        self.windows.contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.windows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Window ids in creation order
+    pub fn ids(&self) -> &[K] {
+        &self.order
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &ManagedWindow<T>)> {
+        self.order
+            .iter()
+            .filter_map(|id| self.windows.get(id).map(|w| (id, w)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut ManagedWindow<T>)> {
+        self.windows.iter_mut()
+    }
+
+    pub fn focused(&self) -> Option<K> {
+        self.focused
+    }
+
+    pub fn set_focused(&mut self, id: K) {
+        if self.windows.contains_key(&id) {
+            self.focused = Some(id);
+        }
+    }
+
+    /// Recompute grid geometry after a pixel resize; returns the new geometry
+    /// if the cell grid changed size.
+    pub fn resize(
+        &mut self,
+        id: &K,
+        pixel_width: u32,
+        pixel_height: u32,
+    ) -> Option<WindowGeometry> {
+        let scale_factor = self.windows.get(id)?.geometry.scale_factor;
+        self.apply_geometry(id, pixel_width, pixel_height, scale_factor)
+    }
+
+    /// Update a single window's scale factor (e.g. moved to another monitor)
+    pub fn set_scale_factor(&mut self, id: &K, scale_factor: f64) -> Option<WindowGeometry> {
+        let window = self.windows.get(id)?;
+        let (width, height) = (window.geometry.pixel_width, window.geometry.pixel_height);
+        self.apply_geometry(id, width, height, scale_factor)
+    }
+
+    fn apply_geometry(
+        &mut self,
+        id: &K,
+        width: u32,
+        height: u32,
+        scale_factor: f64,
+    ) -> Option<WindowGeometry> {
+        let metrics = self.metrics(scale_factor);
+        let window = self.windows.get_mut(id)?;
+        let geometry = WindowGeometry::compute(width, height, scale_factor, metrics);
+        let grid_changed =
+            geometry.cols != window.geometry.cols || geometry.rows != window.geometry.rows;
+        window.geometry = geometry;
+        if grid_changed {
+            window.fit_grids();
+            Some(geometry)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_position(&mut self, id: &K, x: i32, y: i32) {
+        if let Some(window) = self.windows.get_mut(id) {
+            window.position = Some((x, y));
+        }
+    }
+
+    /// Decide whether a close request may proceed. Windows with running jobs
+    /// need a second request within `CLOSE_CONFIRM_WINDOW`.
+    pub fn request_close(
+        &mut self,
+        id: &K,
+        has_running_jobs: bool,
+    ) -> Result<CloseDecision, WindowError> {
+        let window = self.windows.get_mut(id).ok_or(WindowError::NotFound)?;
+
+        if has_running_jobs {
+            let confirmed = window
+                .pending_close
+                .is_some_and(|requested| requested.elapsed() <= CLOSE_CONFIRM_WINDOW);
+            if !confirmed {
+                window.pending_close = Some(Instant::now());
+                return Ok(CloseDecision::ConfirmationRequired);
+            }
+        }
+
+        window.pending_close = None;
+        Ok(CloseDecision::Close {
+            last_window: self.windows.len() == 1,
+        })
+    }
+
+    pub fn remove(&mut self, id: &K) -> Option<ManagedWindow<T>> {
+        let window = self.windows.remove(id)?;
+        self.order.retain(|k| k != id);
+        if self.focused == Some(*id) {
+            self.focused = self.order.last().copied();
+        }
+        Some(window)
+    }
+
+    /// Apply new font metrics (config reload) to every window. The cell
+    /// size is estimated until the font at this size is measured.
+    pub fn set_font_metrics(&mut self, font_size: f32, line_height: f32) {
+        self.font_size = font_size;
+        self.line_height = line_height;
+        self.cell_size = None;
+        self.refit();
+    }
+
+    /// Lay every window out for the cell size of a loaded font, in logical
+    /// pixels
+    pub fn set_cell_size(&mut self, width: f32, height: f32) {
+        self.cell_size = Some((width, height));
+        self.refit();
+    }
+
+    fn refit(&mut self) {
+        let ids: Vec<K> = self.order.clone();
+        for id in ids {
+            if let Some(window) = self.windows.get(&id) {
+                let g = window.geometry;
+                self.apply_geometry(&id, g.pixel_width, g.pixel_height, g.scale_factor);
+            }
+        }
+    }
+
+    pub fn session_layout(&self) -> SessionLayout {
+        SessionLayout {
+            windows: self
+                .iter()
+                .map(|(_, window)| WindowRecord {
+                    position: window.position,
+                    pixel_width: window.geometry.pixel_width,
+                    pixel_height: window.geometry.pixel_height,
+                    scale_factor: window.geometry.scale_factor,
+                    tabs: window.tabs.clone(),
+                    active_tab: window.active_tab,
+                    read_only_tabs: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowRecord {
+    pub position: Option<(i32, i32)>,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+    pub scale_factor: f64,
+    /// Tabs that belonged to this window, in order
+    pub tabs: Vec<u64>,
+    pub active_tab: usize,
+    /// Indices into `tabs` of read-only panes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub read_only_tabs: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionLayout {
+    pub windows: Vec<WindowRecord>,
+}
+
+impl SessionLayout {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_local_dir().map(|dir| dir.join("ferroterm").join("session.json"))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), WindowError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, WindowError> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn registry() -> WindowRegistry<u32, &'static str> {
+        WindowRegistry::new(14.0, 1.0)
+    }
+
+    #[test]
+    fn test_routing_table() {
+        let mut windows = registry();
+        windows.insert(1, "first", (800, 600), 1.0);
+        windows.insert(2, "second", (800, 600), 1.0);
+
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows.get(&1).unwrap().resources, "first");
+        assert_eq!(windows.get(&2).unwrap().resources, "second");
+        assert!(windows.get(&3).is_none());
+        assert_eq!(windows.ids(), &[1, 2]);
+        assert_eq!(windows.focused(), Some(1));
+
+        windows.set_focused(2);
+        windows.remove(&2);
+        assert_eq!(windows.focused(), Some(1));
+    }
+
+    #[test]
+    fn test_per_window_state_isolation() {
+        let mut windows = registry();
+        windows.insert(1, "a", (800, 600), 1.0);
+        windows.insert(2, "b", (800, 600), 1.0);
+
+        windows
+            .get(&1)
+            .unwrap()
+            .terminal
+            .write()
+            .feed_bytes(b"hello");
+        let other = windows.get(&2).unwrap().terminal.read();
+        assert_eq!(other.get_cell(0, 0).unwrap().character, ' ');
+
+        drop(other);
+        windows.get_mut(&1).unwrap().tabs.push(7);
+        assert!(windows.get(&2).unwrap().tabs.is_empty());
+        assert_eq!(windows.get(&1).unwrap().active_pty(), Some(7));
+    }
+
+    #[test]
+    fn test_tabs_keep_their_own_grids() {
+        let mut windows = registry();
+        let window = windows.insert(1, "a", (840, 336), 1.0);
+        let rows = window.geometry.rows;
+        let first = window.open_tab(10);
+        assert!(Arc::ptr_eq(&first, &window.terminal));
+        assert_eq!(window.grid_size().1, rows);
+
+        // A second tab brings up the tab bar, which takes a row from every grid
+        let second = window.open_tab(11);
+        assert_eq!(window.active_pty(), Some(11));
+        assert!(Arc::ptr_eq(&second, &window.terminal));
+        assert_eq!(first.read().height, rows - 1);
+        second.write().feed_bytes(b"second");
+        assert_eq!(first.read().get_cell(0, 0).unwrap().character, ' ');
+
+        window.open_tab(12);
+        assert!(window.cycle_tab(true));
+        assert_eq!(window.active_pty(), Some(10));
+        assert!(window.cycle_tab(false));
+        assert_eq!(window.active_pty(), Some(12));
+
+        // Closing the last, active tab activates its neighbour; a lone tab
+        // gets its row back
+        assert!(window.close_tab(12));
+        assert_eq!(window.active_pty(), Some(11));
+        assert!(window.replace_tab(11, 21));
+        assert_eq!(window.grid(21).unwrap().read().get_cell(0, 0).unwrap().character, 's');
+        assert!(window.close_tab(10));
+        assert_eq!((window.tabs.clone(), window.active_tab), (vec![21], 0));
+        assert_eq!(window.terminal.read().height, rows);
+        assert!(!window.close_tab(10));
+    }
+
+    #[test]
+    fn test_split_panes_route_to_focus() {
+        let mut windows = registry();
+        let window = windows.insert(1, "a", (840, 336), 1.0);
+        let (cols, rows) = (window.geometry.cols, window.geometry.rows);
+        let first = window.open_tab(10);
+
+        let right = window.split_pane(11, SplitDirection::Vertical).unwrap();
+        assert_eq!(window.active_pty(), Some(11));
+        assert!(Arc::ptr_eq(&right, &window.terminal));
+        assert_eq!(window.tab_panes(11), vec![10, 11]);
+        let widths = (first.read().width, right.read().width);
+        assert_eq!(widths.0 + widths.1 + 1, cols);
+        assert_eq!(right.read().height, rows);
+
+        // Focus moves between panes and input follows it
+        assert!(window.focus_pane(PaneDirection::Left));
+        assert_eq!(window.active_pty(), Some(10));
+        assert!(!window.focus_pane(PaneDirection::Left));
+        assert!(window.focus_pane(PaneDirection::Right));
+        assert_eq!(window.tab_index(10), Some(0));
+
+        // The survivor of a closed pane fills the tab again
+        assert!(window.close_pane(11));
+        assert_eq!(window.active_pty(), Some(10));
+        assert!(window.pane_layout().is_none());
+        assert_eq!(first.read().width, cols);
+    }
+
+    #[test]
+    fn test_layout_tab_reopens_split() {
+        let mut windows = registry();
+        let window = windows.insert(1, "a", (840, 336), 1.0);
+        window.open_tab(10);
+        window.split_pane(11, SplitDirection::Vertical).unwrap();
+        assert_eq!(window.tab_layout(10).panes.len(), 1);
+
+        // A saved layout comes back with new PTYs in the same places
+        let mut layout = window.tab_layout(11);
+        for pane in layout.panes.values_mut() {
+            pane.pty_id += 10;
+        }
+        let grids = window.open_layout_tab(layout);
+        assert_eq!(grids.iter().map(|(pty_id, _)| *pty_id).collect::<Vec<_>>(), vec![21, 20]);
+        assert_eq!(window.active_pty(), Some(21));
+        assert_eq!(window.tab_panes(21), vec![20, 21]);
+        assert!(Arc::ptr_eq(&grids[0].1, &window.terminal));
+        assert_eq!(window.ptys(), vec![10, 11, 20, 21]);
+    }
+
+    #[test]
+    fn test_two_windows_independent_geometry() {
+        let mut windows = registry();
+        windows.insert(1, "a", (840, 336), 1.0);
+        windows.insert(2, "b", (1680, 672), 2.0);
+
+        let g1 = windows.get(&1).unwrap().geometry;
+        let g2 = windows.get(&2).unwrap().geometry;
+        // Same logical size on different scale factors yields the same grid
+        assert_eq!((g1.cols, g1.rows), (g2.cols, g2.rows));
+
+        let resized = windows.resize(&1, 1680, 336).unwrap();
+        assert_eq!(resized.cols, g1.cols * 2);
+        assert_eq!(windows.get(&1).unwrap().terminal.read().width, resized.cols);
+        assert_eq!(windows.get(&2).unwrap().geometry, g2);
+
+        let rescaled = windows.set_scale_factor(&2, 1.0).unwrap();
+        assert_eq!(rescaled.cols, g2.cols * 2);
+        assert_eq!(windows.get(&1).unwrap().geometry.scale_factor, 1.0);
+    }
+
+    #[test]
+    fn test_measured_cell_size_replaces_estimate() {
+        let mut windows = registry();
+        windows.insert(1, "a", (800, 600), 1.0);
+        windows.insert(2, "b", (1600, 1200), 2.0);
+
+        windows.set_cell_size(10.0, 20.0);
+        let (g1, g2) = (windows.get(&1).unwrap().geometry, windows.get(&2).unwrap().geometry);
+        assert_eq!((g1.cols, g1.rows), (80, 30));
+        // Twice the pixels at twice the scale is the same grid
+        assert_eq!((g2.cols, g2.rows), (80, 30));
+        assert_eq!(windows.get(&2).unwrap().terminal.read().width, 80);
+
+        // A new font size goes back to estimating until it is measured
+        windows.set_font_metrics(28.0, 1.0);
+        assert_eq!(windows.metrics(1.0), CellMetrics::from_font(28.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_close_flow() {
+        let mut windows = registry();
+        windows.insert(1, "a", (800, 600), 1.0);
+        windows.insert(2, "b", (800, 600), 1.0);
+
+        assert_eq!(
+            windows.request_close(&1, true).unwrap(),
+            CloseDecision::ConfirmationRequired
+        );
+        assert!(windows.contains(&1));
+        assert_eq!(
+            windows.request_close(&1, true).unwrap(),
+            CloseDecision::Close { last_window: false }
+        );
+        assert_eq!(windows.remove(&1).unwrap().resources, "a");
+        assert_eq!(
+            windows.request_close(&2, false).unwrap(),
+            CloseDecision::Close { last_window: true }
+        );
+        windows.remove(&2);
+        assert!(matches!(
+            windows.request_close(&2, false),
+            Err(WindowError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_session_layout_roundtrip() {
+        let mut windows = registry();
+        windows.insert(1, "a", (800, 600), 1.0).tabs = vec![1, 2];
+        windows.insert(2, "b", (1600, 1200), 2.0).tabs = vec![3];
+        windows.set_position(&2, 1920, 0);
+
+        let layout = windows.session_layout();
+        assert_eq!(layout.windows.len(), 2);
+        assert_eq!(layout.windows[0].tabs, vec![1, 2]);
+        assert_eq!(layout.windows[1].position, Some((1920, 0)));
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("session.json");
+        layout.save(&path).unwrap();
+        assert_eq!(SessionLayout::load(&path).unwrap(), layout);
+    }
+}
@@ -0,0 +1,41 @@
+use ferroterm::trace::*;
+use std::path::Path;
+
+/// Recorded sessions in tests/traces must replay to the grids they were
+/// recorded with. A failure here means the parser or grid changed
+/// behaviour; if the change is intended, re-record the trace.
+#[test]
+fn test_recorded_traces_replay_identically() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/traces");
+    let mut replayed = 0;
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("trace") {
+            continue;
+        }
+        let trace = Trace::load(&path).unwrap();
+        let report = replay(&trace, false);
+        assert!(
+            report.divergence.is_none(),
+            "{}: {}",
+            path.display(),
+            report.summary()
+        );
+        assert!(report.matched > 0, "{} has no snapshots", path.display());
+        replayed += 1;
+    }
+    assert!(replayed >= 3);
+}
+
+#[test]
+fn test_traces_survive_format_additions() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/traces/shell_prompt.trace");
+    let mut text = std::fs::read_to_string(path).unwrap();
+    // A newer recorder may add header fields and event types
+    text = text.replacen("\"version\":1", "\"version\":2,\"dpi\":2.0", 1);
+    text.push_str("{\"type\":\"focus\",\"t\":999,\"focused\":false}\n");
+    let trace = Trace::read(text.as_bytes()).unwrap();
+    let report = replay(&trace, false);
+    assert!(report.divergence.is_none());
+    assert_eq!(report.unknown_events, 1);
+}